@@ -1,3 +0,0 @@
-//! Benchmark utilities.
-
-pub use rainybook::generators::OrderGenerator;
@@ -2,22 +2,111 @@
 
 use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
 use std::hint::black_box;
+use time::{Duration, OffsetDateTime};
 
-use rainybook::{Order, OrderBook};
+use rainybook::generators::OrderGenerator;
+use rainybook::{
+    Action, DenseOrderBook, IncrementalMbp, MarketByOrderMessage, MarketByPrice, MboObserver,
+    MboProcessor, Order, OrderBook, Side,
+};
 
-mod data;
+/// The operations benchmarked on both book implementations.
+trait Book {
+    /// Benchmark name prefix.
+    const NAME: &'static str;
 
-use data::OrderGenerator;
+    fn empty() -> Self;
+    fn add(&mut self, order: Order);
+    fn remove(&mut self, order_id: u64);
+    fn modify(&mut self, order: Order);
+    fn best_bid(&self) -> Option<(i64, u64)>;
+    fn best_ask(&self) -> Option<(i64, u64)>;
+    fn top_n_bids(&self, n: usize) -> Vec<(i64, u64)>;
+}
+
+impl Book for OrderBook {
+    const NAME: &'static str = "orderbook";
+
+    fn empty() -> Self {
+        OrderBook::new()
+    }
+
+    fn add(&mut self, order: Order) {
+        self.add_order(order);
+    }
+
+    fn remove(&mut self, order_id: u64) {
+        self.remove_order(order_id);
+    }
+
+    fn modify(&mut self, order: Order) {
+        self.modify_order(order);
+    }
+
+    fn best_bid(&self) -> Option<(i64, u64)> {
+        OrderBook::best_bid(self)
+    }
+
+    fn best_ask(&self) -> Option<(i64, u64)> {
+        OrderBook::best_ask(self)
+    }
+
+    fn top_n_bids(&self, n: usize) -> Vec<(i64, u64)> {
+        OrderBook::top_n_bids(self, n)
+    }
+}
+
+impl Book for DenseOrderBook {
+    const NAME: &'static str = "dense_orderbook";
+
+    /// Covers three standard deviations of the generated prices around 10000.
+    fn empty() -> Self {
+        DenseOrderBook::new(9_700, 10_300, 1).expect("valid dense range")
+    }
+
+    fn add(&mut self, order: Order) {
+        self.add_order(order);
+    }
+
+    fn remove(&mut self, order_id: u64) {
+        self.remove_order(order_id);
+    }
+
+    fn modify(&mut self, order: Order) {
+        self.modify_order(order);
+    }
+
+    fn best_bid(&self) -> Option<(i64, u64)> {
+        DenseOrderBook::best_bid(self)
+    }
+
+    fn best_ask(&self) -> Option<(i64, u64)> {
+        DenseOrderBook::best_ask(self)
+    }
+
+    fn top_n_bids(&self, n: usize) -> Vec<(i64, u64)> {
+        DenseOrderBook::top_n_bids(self, n)
+    }
+}
+
+/// A book holding 1000 generated orders.
+fn populated<B: Book>(orders: &[Order]) -> B {
+    let mut book = B::empty();
+    for order in orders {
+        book.add(*order);
+    }
+    book
+}
 
 /// Benchmark adding a single order to an empty book.
-fn bench_add_order_empty(c: &mut Criterion) {
-    c.bench_function("orderbook/add_order_empty", |b| {
+fn bench_add_order_empty<B: Book>(c: &mut Criterion) {
+    c.bench_function(&format!("{}/add_order_empty", B::NAME), |b| {
         let mut generator = OrderGenerator::default_seeded(42);
 
         b.iter_batched(
-            || (OrderBook::new(), generator.next_order()),
+            || (B::empty(), generator.next_order()),
             |(mut book, order)| {
-                book.add_order(black_box(order));
+                book.add(black_box(order));
                 black_box(book)
             },
             BatchSize::SmallInput,
@@ -26,22 +115,19 @@ fn bench_add_order_empty(c: &mut Criterion) {
 }
 
 /// Benchmark adding orders to a book with existing orders.
-fn bench_add_order_populated(c: &mut Criterion) {
-    c.bench_function("orderbook/add_order_populated", |b| {
+fn bench_add_order_populated<B: Book>(c: &mut Criterion) {
+    c.bench_function(&format!("{}/add_order_populated", B::NAME), |b| {
         let mut generator = OrderGenerator::default_seeded(42);
 
         b.iter_batched(
             || {
                 // Setup: create book with 1000 orders
-                let mut book = OrderBook::new();
-                for order in generator.make_orders(1000) {
-                    book.add_order(order);
-                }
+                let book: B = populated(&generator.make_orders(1000));
                 let new_order = generator.next_order();
                 (book, new_order)
             },
             |(mut book, order)| {
-                book.add_order(black_box(order));
+                book.add(black_box(order));
                 black_box(book)
             },
             BatchSize::LargeInput,
@@ -49,24 +135,45 @@ fn bench_add_order_populated(c: &mut Criterion) {
     });
 }
 
+/// Benchmark building a 100k-order book one `add_order` at a time versus with
+/// `add_orders`.
+fn bench_build_book(c: &mut Criterion) {
+    let orders = OrderGenerator::default_seeded(42).make_orders(100_000);
+    let mut group = c.benchmark_group("build_100k_orders");
+    group.sample_size(10);
+    group.bench_function("add_order_loop", |b| {
+        b.iter(|| {
+            let mut book = OrderBook::new();
+            for order in &orders {
+                book.add_order(*order);
+            }
+            book
+        })
+    });
+    group.bench_function("add_orders", |b| {
+        b.iter(|| {
+            let mut book = OrderBook::new();
+            book.add_orders(orders.iter().copied());
+            book
+        })
+    });
+    group.finish();
+}
+
 /// Benchmark removing an order from a populated book.
-fn bench_remove_order(c: &mut Criterion) {
-    c.bench_function("orderbook/remove_order", |b| {
+fn bench_remove_order<B: Book>(c: &mut Criterion) {
+    c.bench_function(&format!("{}/remove_order", B::NAME), |b| {
         let mut generator = OrderGenerator::default_seeded(42);
 
         b.iter_batched(
             || {
-                let mut book = OrderBook::new();
                 let orders = generator.make_orders(1000);
-                for order in &orders {
-                    book.add_order(*order);
-                }
                 // Pick an order to remove (middle of the batch)
                 let order_to_remove = orders[500].order_id;
-                (book, order_to_remove)
+                (populated::<B>(&orders), order_to_remove)
             },
             |(mut book, order_id)| {
-                book.remove_order(black_box(order_id));
+                book.remove(black_box(order_id));
                 black_box(book)
             },
             BatchSize::LargeInput,
@@ -75,65 +182,133 @@ fn bench_remove_order(c: &mut Criterion) {
 }
 
 /// Benchmark getting best bid from a populated book.
-fn bench_best_bid(c: &mut Criterion) {
+fn bench_best_bid<B: Book>(c: &mut Criterion) {
     let mut generator = OrderGenerator::default_seeded(42);
+    let book: B = populated(&generator.make_orders(1000));
+
+    c.bench_function(&format!("{}/best_bid", B::NAME), |b| {
+        b.iter(|| black_box(book.best_bid()))
+    });
+}
+
+/// Benchmark getting best bid from a book 10,000 levels deep on each side, with
+/// several orders per level.
+fn bench_best_bid_deep(c: &mut Criterion) {
     let mut book = OrderBook::new();
-    for order in generator.make_orders(1000) {
-        book.add_order(order);
+    for level in 0..10_000 {
+        for (side, price) in [(Side::Bid, 100_000 - level), (Side::Ask, 100_001 + level)] {
+            for order in 0..4 {
+                book.add_order(Order {
+                    order_id: (price as u64) * 10 + order,
+                    side,
+                    price,
+                    size: 10,
+                    sequence: order as u32,
+                    owner: None,
+                    expires_at: None,
+                });
+            }
+        }
     }
 
-    c.bench_function("orderbook/best_bid", |b| {
-        b.iter(|| black_box(book.best_bid()))
+    c.bench_function("orderbook/best_bid_deep", |b| {
+        b.iter(|| black_box(black_box(&book).best_bid()))
     });
 }
 
+/// Benchmark cancelling an order at the front or in the middle of a single level's
+/// queue of increasing depth, each cancel followed by an add at the back so the depth
+/// holds. Orders leave a middle position by shifting the orders on one side of it.
+fn bench_cancel_in_queue(c: &mut Criterion) {
+    let order = |order_id: u64| Order {
+        order_id,
+        side: Side::Bid,
+        price: 100,
+        size: 10,
+        sequence: order_id as u32,
+        owner: None,
+        expires_at: None,
+    };
+    let mut group = c.benchmark_group("cancel_in_queue");
+    for depth in [100u64, 1_000, 10_000] {
+        for (position, offset) in [("front", 0), ("middle", depth / 2)] {
+            let mut book = OrderBook::new();
+            for order_id in 0..depth {
+                book.add_order(order(order_id));
+            }
+            // Order offset + k is always offset orders from the front: the orders ahead
+            // of it are never cancelled, those behind it shift up one per round
+            let mut k = 0;
+            group.bench_function(format!("{position}/{depth}_orders"), |b| {
+                b.iter(|| {
+                    black_box(book.remove_order(black_box(k + offset)));
+                    book.add_order(order(k + depth));
+                    k += 1;
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
 /// Benchmark getting best ask from a populated book.
-fn bench_best_ask(c: &mut Criterion) {
+fn bench_best_ask<B: Book>(c: &mut Criterion) {
     let mut generator = OrderGenerator::default_seeded(42);
-    let mut book = OrderBook::new();
-    for order in generator.make_orders(1000) {
-        book.add_order(order);
-    }
+    let book: B = populated(&generator.make_orders(1000));
 
-    c.bench_function("orderbook/best_ask", |b| {
+    c.bench_function(&format!("{}/best_ask", B::NAME), |b| {
         b.iter(|| black_box(book.best_ask()))
     });
 }
 
 /// Benchmark getting top N bids.
-fn bench_top_n_bids(c: &mut Criterion) {
+fn bench_top_n_bids<B: Book>(c: &mut Criterion) {
     let mut generator = OrderGenerator::default_seeded(42);
-    let mut book = OrderBook::new();
-    for order in generator.make_orders(1000) {
-        book.add_order(order);
-    }
+    let book: B = populated(&generator.make_orders(1000));
 
-    c.bench_function("orderbook/top_10_bids", |b| {
+    c.bench_function(&format!("{}/top_10_bids", B::NAME), |b| {
         b.iter(|| black_box(book.top_n_bids(10)))
     });
 }
 
+/// Benchmark the allocating, buffer-reusing and array forms of the top 10 bids, as
+/// polled after every message.
+fn bench_top_n_buffers(c: &mut Criterion) {
+    let mut generator = OrderGenerator::default_seeded(42);
+    let book: OrderBook = populated(&generator.make_orders(1000));
+
+    let mut group = c.benchmark_group("top_10_bids_buffer");
+    group.bench_function("vec", |b| b.iter(|| black_box(book.top_n_bids(10))));
+    group.bench_function("into", |b| {
+        let mut levels = Vec::with_capacity(10);
+        b.iter(|| {
+            book.top_n_bids_into(10, &mut levels);
+            black_box(levels.len())
+        })
+    });
+    group.bench_function("array", |b| {
+        b.iter(|| black_box(book.top_n_bids_array::<10>()))
+    });
+    group.finish();
+}
+
 /// Benchmark modifying an order's size in a populated book.
-fn bench_modify_order(c: &mut Criterion) {
-    c.bench_function("orderbook/modify_order", |b| {
+fn bench_modify_order<B: Book>(c: &mut Criterion) {
+    c.bench_function(&format!("{}/modify_order", B::NAME), |b| {
         let mut generator = OrderGenerator::default_seeded(42);
 
         b.iter_batched(
             || {
-                let mut book = OrderBook::new();
                 let orders = generator.make_orders(1000);
-                for order in &orders {
-                    book.add_order(*order);
-                }
                 let order_to_modify = orders[500];
-                (book, order_to_modify)
+                (populated::<B>(&orders), order_to_modify)
             },
             |(mut book, order)| {
                 let modified = Order {
                     size: black_box(999),
                     ..order
                 };
-                let _ = book.modify_order(black_box(modified));
+                book.modify(black_box(modified));
                 black_box(book)
             },
             BatchSize::LargeInput,
@@ -141,14 +316,165 @@ fn bench_modify_order(c: &mut Criterion) {
     });
 }
 
+/// Benchmark replaying generated messages into each book, the differential workload
+/// of both implementations.
+fn bench_replay(c: &mut Criterion) {
+    let messages = OrderGenerator::default_seeded(42).make_mbo_messages(10_000);
+    let mut group = c.benchmark_group("replay_10k_messages");
+    group.bench_function("orderbook", |b| {
+        b.iter(|| black_box(replay::<OrderBook>(&messages)))
+    });
+    group.bench_function("dense_orderbook", |b| {
+        b.iter(|| black_box(replay::<DenseOrderBook>(&messages)))
+    });
+    group.finish();
+}
+
+/// Applies the book-changing messages of `messages` to a new book.
+fn replay<B: Book>(messages: &[MarketByOrderMessage]) -> B {
+    let mut book = B::empty();
+    for message in messages {
+        let order = Order::from(message);
+        match message.action {
+            Action::Add => book.add(order),
+            Action::Cancel => book.remove(order.order_id),
+            Action::Modify => book.modify(order),
+            _ => {}
+        }
+    }
+    book
+}
+
+/// A message for instrument 1 with the LAST flag.
+fn message(action: Action, side: Side, price: i64, order_id: u64) -> MarketByOrderMessage {
+    MarketByOrderMessage {
+        instrument_id: 1,
+        action,
+        side,
+        no_side: false,
+        price,
+        order_id,
+        size: 10,
+        is_last: true,
+        flags: dbn::flags::LAST,
+        sequence: 0,
+        event_time: OffsetDateTime::UNIX_EPOCH,
+        recv_time: OffsetDateTime::UNIX_EPOCH,
+        ts_in_delta: Duration::ZERO,
+        owner: None,
+        publisher_id: 0,
+        channel_id: 0,
+    }
+}
+
+/// A processor whose book has 500 levels per side, of two orders each.
+fn thousand_level_processor<O: MboObserver>(observer: O) -> MboProcessor<O> {
+    let mut processor = MboProcessor::with_observer(observer);
+    for level in 0..500 {
+        for (side, price) in [(Side::Bid, 10_000 - level), (Side::Ask, 10_001 + level)] {
+            for order in 0..2 {
+                let order_id = (price as u64) * 10 + order;
+                let add = message(Action::Add, side, price, order_id);
+                processor.process_message(&add).unwrap();
+            }
+        }
+    }
+    processor
+}
+
+/// Adds and cancels an order at levels across both sides, leaving the book as it was.
+fn churn() -> Vec<MarketByOrderMessage> {
+    (0..100)
+        .flat_map(|i| {
+            let (side, price) = match i % 2 {
+                0 => (Side::Bid, 10_000 - i * 5),
+                _ => (Side::Ask, 10_001 + i * 5),
+            };
+            let order_id = 1_000_000 + i as u64;
+            [
+                message(Action::Add, side, price, order_id),
+                message(Action::Cancel, side, price, order_id),
+            ]
+        })
+        .collect()
+}
+
+/// Benchmark an up-to-date MBP view after every message on a 1000-level book,
+/// maintained incrementally versus rebuilt from the book.
+fn bench_mbp_per_message(c: &mut Criterion) {
+    let churn = churn();
+    let mut group = c.benchmark_group("mbp_per_message");
+
+    let mut incremental = thousand_level_processor(IncrementalMbp::new());
+    group.bench_function("incremental", |b| {
+        b.iter(|| {
+            for message in &churn {
+                incremental.process_message(black_box(message)).unwrap();
+                black_box(incremental.observer().mbp(1));
+            }
+        })
+    });
+
+    let mut rebuild = thousand_level_processor(());
+    group.bench_function("rebuild", |b| {
+        b.iter(|| {
+            for message in &churn {
+                rebuild.process_message(black_box(message)).unwrap();
+                black_box(MarketByPrice::from(rebuild.book(1).unwrap()));
+            }
+        })
+    });
+    group.finish();
+}
+
+/// Benchmark an MBP-10 snapshot of books of increasing depth, which should take the
+/// same time whatever the depth.
+fn bench_mbp_top_10(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mbp_top_10");
+    for levels in [100i64, 1_000, 10_000] {
+        let mut book = OrderBook::new();
+        for level in 0..levels {
+            for (side, price) in [(Side::Bid, 100_000 - level), (Side::Ask, 100_001 + level)] {
+                book.add_order(Order {
+                    order_id: price as u64,
+                    side,
+                    price,
+                    size: 10,
+                    sequence: 0,
+                    owner: None,
+                    expires_at: None,
+                });
+            }
+        }
+        group.bench_function(format!("{levels}_levels"), |b| {
+            b.iter(|| black_box(MarketByPrice::from_top_n(black_box(&book), 10)))
+        });
+    }
+    group.finish();
+}
+
 criterion_group!(
     benches,
-    bench_add_order_empty,
-    bench_add_order_populated,
-    bench_remove_order,
-    bench_best_bid,
-    bench_best_ask,
-    bench_top_n_bids,
-    bench_modify_order,
+    bench_add_order_empty::<OrderBook>,
+    bench_add_order_populated::<OrderBook>,
+    bench_build_book,
+    bench_remove_order::<OrderBook>,
+    bench_best_bid::<OrderBook>,
+    bench_best_bid_deep,
+    bench_cancel_in_queue,
+    bench_best_ask::<OrderBook>,
+    bench_top_n_bids::<OrderBook>,
+    bench_top_n_buffers,
+    bench_modify_order::<OrderBook>,
+    bench_add_order_empty::<DenseOrderBook>,
+    bench_add_order_populated::<DenseOrderBook>,
+    bench_remove_order::<DenseOrderBook>,
+    bench_best_bid::<DenseOrderBook>,
+    bench_best_ask::<DenseOrderBook>,
+    bench_top_n_bids::<DenseOrderBook>,
+    bench_modify_order::<DenseOrderBook>,
+    bench_replay,
+    bench_mbp_per_message,
+    bench_mbp_top_10,
 );
 criterion_main!(benches);
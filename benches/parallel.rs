@@ -0,0 +1,56 @@
+//! Sequential against `MboProcessor::process_parallel` on a generated multi-instrument
+//! stream, for the wall-clock gain of spreading instruments across threads.
+
+use criterion::{BatchSize, BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use std::hint::black_box;
+
+use rainybook::MboProcessor;
+use rainybook::generators::{MessageMix, make_multi_instrument_messages};
+
+/// Messages in the stream, spread evenly over `INSTRUMENTS`.
+const MESSAGES: usize = 1_000_000;
+const INSTRUMENTS: u32 = 8;
+
+fn bench_process_parallel(c: &mut Criterion) {
+    let messages = make_multi_instrument_messages(42, INSTRUMENTS, MESSAGES, MessageMix::default());
+    let mut group = c.benchmark_group("process_parallel");
+    group.throughput(Throughput::Elements(MESSAGES as u64));
+    group.sample_size(10);
+    group.bench_function("sequential", |b| {
+        b.iter_batched(
+            MboProcessor::new,
+            |mut processor| {
+                assert!(
+                    processor
+                        .process_messages(black_box(&messages))
+                        .is_success()
+                );
+                processor
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    for threads in [1, 2, 4, 8] {
+        group.bench_with_input(
+            BenchmarkId::new("threads", threads),
+            &threads,
+            |b, &threads| {
+                b.iter_batched(
+                    MboProcessor::new,
+                    |mut processor| {
+                        let summary = processor
+                            .process_parallel(black_box(&messages), threads)
+                            .unwrap();
+                        assert!(summary.is_success());
+                        processor
+                    },
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_process_parallel);
+criterion_main!(benches);
@@ -0,0 +1,71 @@
+//! End-to-end `MboProcessor` throughput over generated venue-like message streams.
+//!
+//! This is the headline number for hot-path changes: report `process_messages/book`
+//! before and after, alongside the observer variants it affects.
+
+use criterion::{BatchSize, BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use std::hint::black_box;
+
+use rainybook::generators::{MarketModel, MessageMix, OrderGenerator};
+use rainybook::{BboRecorder, IncrementalMbp, MarketByOrderMessage, MboObserver, MboProcessor};
+
+/// Stream lengths, in messages.
+const SIZES: [usize; 2] = [100_000, 1_000_000];
+
+/// Measures replaying `messages` into a new processor observed by `observer()`.
+fn bench_observer<O: MboObserver>(
+    group: &mut criterion::BenchmarkGroup<criterion::measurement::WallTime>,
+    name: &str,
+    messages: &[MarketByOrderMessage],
+    observer: impl Fn() -> O,
+) {
+    group.bench_with_input(
+        BenchmarkId::new(name, messages.len()),
+        messages,
+        |b, messages| {
+            b.iter_batched(
+                || MboProcessor::with_observer(observer()),
+                |mut processor| {
+                    let summary = processor.process_messages(black_box(messages));
+                    assert!(summary.is_success());
+                    processor
+                },
+                BatchSize::LargeInput,
+            )
+        },
+    );
+}
+
+/// Benchmark messages per second through `MboProcessor::process_messages`, with no
+/// observer, an `IncrementalMbp`, a `BboRecorder` and both, and with no observer over
+/// a stream around a moving mid (`realistic_flow`), whose activity concentrates at the
+/// touch.
+fn bench_process_messages(c: &mut Criterion) {
+    let mut group = c.benchmark_group("process_messages");
+    for n in SIZES {
+        let messages =
+            OrderGenerator::default_seeded(42).make_lifecycle_messages(n, MessageMix::default());
+        group.throughput(Throughput::Elements(n as u64));
+        if n >= 1_000_000 {
+            group.sample_size(10);
+        }
+        bench_observer(&mut group, "book", &messages, || ());
+        bench_observer(
+            &mut group,
+            "incremental_mbp",
+            &messages,
+            IncrementalMbp::new,
+        );
+        bench_observer(&mut group, "bbo_recorder", &messages, BboRecorder::new);
+        bench_observer(&mut group, "mbp_and_bbo", &messages, || {
+            (IncrementalMbp::new(), BboRecorder::new())
+        });
+        let realistic =
+            OrderGenerator::default_seeded(42).make_realistic_messages(n, &MarketModel::default());
+        bench_observer(&mut group, "realistic_flow", &realistic, || ());
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_process_messages);
+criterion_main!(benches);
@@ -5,7 +5,7 @@
 //!
 //! Run with: `cargo run --example simple_orderbook`
 
-use rainybook::{MarketByPrice, Order, OrderBook, Side};
+use rainybook::{MarketByPrice, Order, OrderBook, PriceScale, Side};
 
 fn main() {
     // Create a new order book
@@ -19,6 +19,8 @@ fn main() {
         price: 10050,
         size: 100,
         sequence: 1,
+        owner: None,
+        expires_at: None,
     }); // Order 1: 100 units @ 100.50
     book.add_order(Order {
         order_id: 2,
@@ -26,6 +28,8 @@ fn main() {
         price: 10050,
         size: 250,
         sequence: 2,
+        owner: None,
+        expires_at: None,
     }); // Order 2: 250 units @ 100.50
     book.add_order(Order {
         order_id: 3,
@@ -33,6 +37,8 @@ fn main() {
         price: 10045,
         size: 500,
         sequence: 3,
+        owner: None,
+        expires_at: None,
     }); // Order 3: 500 units @ 100.45
     book.add_order(Order {
         order_id: 4,
@@ -40,6 +46,8 @@ fn main() {
         price: 10040,
         size: 300,
         sequence: 4,
+        owner: None,
+        expires_at: None,
     }); // Order 4: 300 units @ 100.40
     book.add_order(Order {
         order_id: 5,
@@ -47,6 +55,8 @@ fn main() {
         price: 10040,
         size: 150,
         sequence: 5,
+        owner: None,
+        expires_at: None,
     }); // Order 5: 150 units @ 100.40
 
     // Add some ask orders at various price levels
@@ -56,6 +66,8 @@ fn main() {
         price: 10055,
         size: 200,
         sequence: 6,
+        owner: None,
+        expires_at: None,
     }); // Order 6: 200 units @ 100.55
     book.add_order(Order {
         order_id: 7,
@@ -63,6 +75,8 @@ fn main() {
         price: 10055,
         size: 100,
         sequence: 7,
+        owner: None,
+        expires_at: None,
     }); // Order 7: 100 units @ 100.55
     book.add_order(Order {
         order_id: 8,
@@ -70,6 +84,8 @@ fn main() {
         price: 10060,
         size: 400,
         sequence: 8,
+        owner: None,
+        expires_at: None,
     }); // Order 8: 400 units @ 100.60
     book.add_order(Order {
         order_id: 9,
@@ -77,6 +93,8 @@ fn main() {
         price: 10065,
         size: 600,
         sequence: 9,
+        owner: None,
+        expires_at: None,
     }); // Order 9: 600 units @ 100.65
 
     // Get best bid and ask
@@ -102,7 +120,7 @@ fn main() {
 
     // Print the order book view
     println!("=== Market-By-Price View ===\n");
-    print_orderbook_view(&mbp);
+    print!("{}", mbp.render(10, cents()));
 
     // Demonstrate some order operations
     println!("\n=== Order Operations ===\n");
@@ -133,57 +151,15 @@ fn main() {
     // Show updated view
     let mbp_updated = MarketByPrice::from(&book);
     println!("\n=== Updated Market-By-Price View ===\n");
-    print_orderbook_view(&mbp_updated);
+    print!("{}", mbp_updated.render(10, cents()));
 }
 
-/// Formats an integer price as a decimal string (assuming 2 decimal places).
-fn format_price(price: i64) -> String {
-    format!("{:.2}", price as f64 / 100.0)
+/// Prices in cents, two implied decimal places.
+fn cents() -> PriceScale {
+    PriceScale::from_decimals(2).expect("two decimals fit in an i64 divisor")
 }
 
-/// Prints a formatted order book view showing bids and asks.
-fn print_orderbook_view(mbp: &MarketByPrice) {
-    // Collect and sort asks (ascending for display, but we'll reverse for top-of-book first)
-    let mut asks: Vec<_> = mbp.asks.values().collect();
-    asks.sort_by(|a, b| b.price.cmp(&a.price)); // Descending (highest ask first)
-
-    // Collect and sort bids (descending - highest bid first)
-    let mut bids: Vec<_> = mbp.bids.values().collect();
-    bids.sort_by(|a, b| b.price.cmp(&a.price)); // Descending (highest bid first)
-
-    // Print header
-    println!(
-        "{:>10} {:>12} {:>8}  |  {:>10} {:>12} {:>8}",
-        "Ask Qty", "Ask Price", "Orders", "Bid Price", "Bid Qty", "Orders"
-    );
-    println!("{}", "-".repeat(70));
-
-    // Determine max rows
-    let max_rows = asks.len().max(bids.len());
-
-    for i in 0..max_rows {
-        let ask_str = asks
-            .get(i)
-            .map_or(format!("{:>10} {:>12} {:>8}", "", "", ""), |a| {
-                format!(
-                    "{:>10} {:>12} {:>8}",
-                    a.total_quantity,
-                    format_price(a.price),
-                    a.order_count
-                )
-            });
-
-        let bid_str = bids
-            .get(i)
-            .map_or(format!("{:>10} {:>12} {:>8}", "", "", ""), |b| {
-                format!(
-                    "{:>10} {:>12} {:>8}",
-                    format_price(b.price),
-                    b.total_quantity,
-                    b.order_count
-                )
-            });
-
-        println!("{}  |  {}", ask_str, bid_str);
-    }
+/// Formats an integer price in cents as a decimal string.
+fn format_price(price: i64) -> String {
+    cents().format(price)
 }
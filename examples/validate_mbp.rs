@@ -562,6 +562,17 @@ fn main() -> Result<(), Box<dyn Error>> {
     print!("{mbo_stats}");
     println!();
 
+    println!("--- Final Top of Book ---");
+    let format_level = |level: Option<(i64, u64)>| {
+        level.map_or_else(
+            || "-".to_string(),
+            |(price, qty)| format!("{} x {}", format_price(price), qty),
+        )
+    };
+    println!("  Best bid: {}", format_level(processor.best_bid()));
+    println!("  Best ask: {}", format_level(processor.best_ask()));
+    println!();
+
     println!("--- MBP-10 Record Statistics ---");
     println!("  Total snapshots checked: {}", val_stats.snapshots_checked);
     println!(
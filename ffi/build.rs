@@ -0,0 +1,17 @@
+use std::env;
+use std::path::PathBuf;
+
+fn main() {
+    let dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let source = dir.join("../src/ffi.rs");
+    let config = dir.join("cbindgen.toml");
+    println!("cargo:rerun-if-changed={}", source.display());
+    println!("cargo:rerun-if-changed={}", config.display());
+
+    cbindgen::Builder::new()
+        .with_config(cbindgen::Config::from_file(&config).unwrap())
+        .with_src(&source)
+        .generate()
+        .expect("Unable to generate rainybook.h")
+        .write_to_file(dir.join("include/rainybook.h"));
+}
@@ -0,0 +1,214 @@
+/*
+ * rainybook C API, generated by cbindgen from src/ffi.rs; do not edit.
+ *
+ * Handles are not synchronized: one may be passed between threads, but must not be
+ * used from two threads at once.
+ */
+
+#ifndef RAINYBOOK_H
+#define RAINYBOOK_H
+
+
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Outcome of a call.
+enum RbStatus
+#if defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+  : int32_t
+#endif // defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+ {
+  RB_STATUS_OK = 0,
+  // A handle or required pointer argument was null.
+  RB_STATUS_NULL_POINTER = 1,
+  // A side was neither `'B'` nor `'A'`, or a size does not fit the book.
+  RB_STATUS_INVALID_ARGUMENT = 2,
+  // The book holds no order with the id.
+  RB_STATUS_ORDER_NOT_FOUND = 3,
+  // A fill was larger than the order.
+  RB_STATUS_SIZE_UNDERFLOW = 4,
+  // The order's price is beyond the book's depth limit.
+  RB_STATUS_BEYOND_DEPTH_LIMIT = 5,
+  // Some messages of a batch failed to apply or could not be converted.
+  RB_STATUS_PROCESS_FAILED = 6,
+  // The call panicked; the handle should not be used further.
+  RB_STATUS_PANIC = 7,
+};
+#ifndef __cplusplus
+#if __STDC_VERSION__ >= 202311L
+typedef enum RbStatus RbStatus;
+#else
+typedef int32_t RbStatus;
+#endif // __STDC_VERSION__ >= 202311L
+#endif // __cplusplus
+
+// An L3 order book.
+typedef struct RbBook RbBook;
+
+// A processor keeping one book per instrument.
+typedef struct RbProcessor RbProcessor;
+
+// A market-by-order message in dbn terms, laid out largest field first so the struct
+// has no padding between fields.
+typedef struct RbMboMessage {
+  uint64_t ts_event;
+  // 0 for the event time.
+  uint64_t ts_recv;
+  uint64_t order_id;
+  int64_t price;
+  uint32_t size;
+  uint32_t instrument_id;
+  uint32_t sequence;
+  int32_t ts_in_delta;
+  // dbn action character, e.g. `'A'`.
+  int8_t action;
+  // dbn side character, e.g. `'B'`.
+  int8_t side;
+  uint8_t flags;
+} RbMboMessage;
+
+// Counts of one `rb_processor_process` call.
+typedef struct RbProcessSummary {
+  // Messages applied.
+  uint64_t processed;
+  // Messages that failed to apply.
+  uint64_t failed;
+  // Messages that could not be converted, e.g. for an unknown action.
+  uint64_t rejected;
+} RbProcessSummary;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Creates an empty book, or returns null if that fails.
+struct RbBook *rb_book_new(void);
+
+// Releases a book. Null is ignored.
+//
+// # Safety
+// `book` must be null or a live handle from `rb_book_new`, not used afterwards.
+void rb_book_free(struct RbBook *book);
+
+// Adds a resting order of side `'B'` or `'A'`.
+//
+// # Safety
+// `book` must be null or a live handle.
+RbStatus rb_book_add(struct RbBook *book,
+                     uint64_t order_id,
+                     int8_t side,
+                     int64_t price,
+                     uint64_t size,
+                     uint32_t sequence);
+
+// Removes an order, writing its size into `size` unless that is null.
+//
+// # Safety
+// `book` must be null or a live handle, `size` null or valid for writes.
+RbStatus rb_book_cancel(struct RbBook *book, uint64_t order_id, uint64_t *size);
+
+// Moves an order to `price` with `size`, keeping its side and sequence.
+//
+// # Safety
+// `book` must be null or a live handle.
+RbStatus rb_book_modify(struct RbBook *book, uint64_t order_id, int64_t price, uint64_t size);
+
+// Executes `size` of a resting order, removing it when nothing is left, and writes
+// what is left into `remaining` unless that is null.
+//
+// # Safety
+// `book` must be null or a live handle, `remaining` null or valid for writes.
+RbStatus rb_book_fill(struct RbBook *book, uint64_t order_id, uint64_t size, uint64_t *remaining);
+
+// Writes the price and quantity of the best bid level, returning false if the book
+// has none.
+//
+// # Safety
+// `book` must be null or a live handle, `price` and `quantity` null or valid for
+// writes.
+bool rb_book_best_bid(const struct RbBook *book, int64_t *price, uint64_t *quantity);
+
+// Writes the price and quantity of the best ask level, returning false if the book
+// has none.
+//
+// # Safety
+// As for `rb_book_best_bid`.
+bool rb_book_best_ask(const struct RbBook *book, int64_t *price, uint64_t *quantity);
+
+// Fills `prices` and `quantities` with up to `capacity` levels of `side`, best first,
+// and writes how many into `len`.
+//
+// # Safety
+// `book` must be null or a live handle, `prices` and `quantities` valid for
+// `capacity` writes and `len` for one.
+RbStatus rb_book_top_n(const struct RbBook *book,
+                       int8_t side,
+                       int64_t *prices,
+                       uint64_t *quantities,
+                       size_t capacity,
+                       size_t *len);
+
+// Creates a processor, or returns null if that fails. With `fail_fast` a batch stops
+// at its first message that cannot be applied; otherwise such messages are skipped.
+struct RbProcessor *rb_processor_new(bool fail_fast);
+
+// Releases a processor. Null is ignored.
+//
+// # Safety
+// `processor` must be null or a live handle from `rb_processor_new`, not used
+// afterwards.
+void rb_processor_free(struct RbProcessor *processor);
+
+// Applies `len` messages in order, converting them with the rules of tabular input,
+// and writes the counts into `summary` unless that is null. Returns
+// `RB_STATUS_PROCESS_FAILED` if any message failed or could not be converted.
+//
+// # Safety
+// `processor` must be null or a live handle, `messages` valid for `len` reads and
+// `summary` null or valid for writes.
+RbStatus rb_processor_process(struct RbProcessor *processor,
+                              const struct RbMboMessage *messages,
+                              size_t len,
+                              struct RbProcessSummary *summary);
+
+// Writes the best bid level of an instrument, returning false if it has none.
+//
+// # Safety
+// `processor` must be null or a live handle, `price` and `quantity` null or valid
+// for writes.
+bool rb_processor_best_bid(const struct RbProcessor *processor,
+                           uint32_t instrument_id,
+                           int64_t *price,
+                           uint64_t *quantity);
+
+// Writes the best ask level of an instrument, returning false if it has none.
+//
+// # Safety
+// As for `rb_processor_best_bid`.
+bool rb_processor_best_ask(const struct RbProcessor *processor,
+                           uint32_t instrument_id,
+                           int64_t *price,
+                           uint64_t *quantity);
+
+// `rb_book_top_n` for the book of an instrument; an instrument without a book has
+// no levels.
+//
+// # Safety
+// As for `rb_book_top_n`.
+RbStatus rb_processor_top_n(const struct RbProcessor *processor,
+                            uint32_t instrument_id,
+                            int8_t side,
+                            int64_t *prices,
+                            uint64_t *quantities,
+                            size_t capacity,
+                            size_t *len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RAINYBOOK_H */
@@ -0,0 +1,4 @@
+//! Static and shared C libraries of rainybook. The functions are those of
+//! `rainybook::ffi`, declared in include/rainybook.h, which the build regenerates.
+
+pub use rainybook::ffi::*;
@@ -0,0 +1,111 @@
+/* Drives the C API through both handles; exits non-zero on the first failed check. */
+
+#include <stdio.h>
+
+#include "rainybook.h"
+
+#define CHECK(cond)                                                    \
+    do {                                                               \
+        if (!(cond)) {                                                 \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__,   \
+                    __LINE__, #cond);                                  \
+            return 1;                                                  \
+        }                                                              \
+    } while (0)
+
+static int check_book(void) {
+    RbBook *book = rb_book_new();
+    CHECK(book != NULL);
+    CHECK(rb_book_add(book, 1, 'B', 100, 10, 0) == RB_STATUS_OK);
+    CHECK(rb_book_add(book, 2, 'B', 99, 5, 1) == RB_STATUS_OK);
+    CHECK(rb_book_add(book, 3, 'A', 102, 7, 2) == RB_STATUS_OK);
+    CHECK(rb_book_add(book, 4, 'Z', 102, 7, 3) == RB_STATUS_INVALID_ARGUMENT);
+
+    uint64_t remaining = 0;
+    CHECK(rb_book_fill(book, 1, 4, &remaining) == RB_STATUS_OK);
+    CHECK(remaining == 6);
+    CHECK(rb_book_fill(book, 1, 7, NULL) == RB_STATUS_SIZE_UNDERFLOW);
+    CHECK(rb_book_modify(book, 3, 103, 2) == RB_STATUS_OK);
+    CHECK(rb_book_modify(book, 42, 103, 2) == RB_STATUS_ORDER_NOT_FOUND);
+
+    int64_t price = 0;
+    uint64_t quantity = 0;
+    CHECK(rb_book_best_bid(book, &price, &quantity));
+    CHECK(price == 100 && quantity == 6);
+    CHECK(rb_book_best_ask(book, &price, &quantity));
+    CHECK(price == 103 && quantity == 2);
+
+    int64_t prices[4];
+    uint64_t quantities[4];
+    size_t len = 0;
+    CHECK(rb_book_top_n(book, 'B', prices, quantities, 4, &len) == RB_STATUS_OK);
+    CHECK(len == 2 && prices[1] == 99 && quantities[1] == 5);
+    CHECK(rb_book_top_n(book, 'B', prices, quantities, 1, &len) == RB_STATUS_OK);
+    CHECK(len == 1);
+
+    uint64_t size = 0;
+    CHECK(rb_book_cancel(book, 3, &size) == RB_STATUS_OK);
+    CHECK(size == 2);
+    CHECK(rb_book_cancel(book, 3, &size) == RB_STATUS_ORDER_NOT_FOUND);
+    CHECK(!rb_book_best_ask(book, &price, &quantity));
+    CHECK(rb_book_add(NULL, 1, 'B', 100, 10, 0) == RB_STATUS_NULL_POINTER);
+    rb_book_free(book);
+    return 0;
+}
+
+static RbMboMessage message(char action, char side, uint64_t order_id, int64_t price,
+                            uint32_t size) {
+    RbMboMessage message = {0};
+    message.ts_event = 1000 + order_id;
+    message.order_id = order_id;
+    message.price = price;
+    message.size = size;
+    message.instrument_id = 7;
+    message.action = action;
+    message.side = side;
+    message.flags = 128;
+    return message;
+}
+
+static int check_processor(void) {
+    RbProcessor *processor = rb_processor_new(false);
+    CHECK(processor != NULL);
+    RbMboMessage messages[] = {
+        message('A', 'B', 1, 100, 10),
+        message('A', 'B', 2, 99, 4),
+        message('A', 'A', 3, 102, 8),
+        message('C', 'B', 2, 99, 4),
+        message('?', 'B', 4, 100, 1),
+    };
+    RbProcessSummary summary = {0};
+    RbStatus status = rb_processor_process(processor, messages, 5, &summary);
+    CHECK(status == RB_STATUS_PROCESS_FAILED);
+    CHECK(summary.processed == 4 && summary.failed == 0 && summary.rejected == 1);
+    CHECK(rb_processor_process(processor, messages, 0, NULL) == RB_STATUS_OK);
+
+    int64_t price = 0;
+    uint64_t quantity = 0;
+    CHECK(rb_processor_best_bid(processor, 7, &price, &quantity));
+    CHECK(price == 100 && quantity == 10);
+    CHECK(rb_processor_best_ask(processor, 7, &price, &quantity));
+    CHECK(price == 102 && quantity == 8);
+    CHECK(!rb_processor_best_bid(processor, 8, &price, &quantity));
+
+    int64_t prices[4];
+    uint64_t quantities[4];
+    size_t len = 99;
+    CHECK(rb_processor_top_n(processor, 7, 'B', prices, quantities, 4, &len) == RB_STATUS_OK);
+    CHECK(len == 1);
+    CHECK(rb_processor_top_n(processor, 8, 'A', prices, quantities, 4, &len) == RB_STATUS_OK);
+    CHECK(len == 0);
+    rb_processor_free(processor);
+    return 0;
+}
+
+int main(void) {
+    if (check_book() != 0 || check_processor() != 0) {
+        return 1;
+    }
+    printf("ok\n");
+    return 0;
+}
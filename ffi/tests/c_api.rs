@@ -0,0 +1,38 @@
+//! Compiles tests/book.c against the static library and the generated header, and runs it.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Directory the test binary was built into, which also holds the crate's libraries.
+fn library_dir() -> PathBuf {
+    let exe = env::current_exe().unwrap();
+    exe.parent().unwrap().to_path_buf()
+}
+
+#[test]
+fn test_c_program() {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let program = Path::new(env!("CARGO_TARGET_TMPDIR")).join("book");
+    let compiler = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = Command::new(compiler)
+        .args(["-std=c99", "-Wall", "-Wextra", "-Werror"])
+        .arg("-I")
+        .arg(manifest.join("include"))
+        .arg(manifest.join("tests/book.c"))
+        .arg(library_dir().join("librainybook_ffi.a"))
+        .args(["-lpthread", "-ldl", "-lm"])
+        .arg("-o")
+        .arg(&program)
+        .status()
+        .expect("Unable to run the C compiler");
+    assert!(status.success(), "Compiling tests/book.c failed");
+
+    let output = Command::new(&program).output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+}
@@ -0,0 +1,25 @@
+//! Feeds arbitrary MBO records to the `TryFrom<&MboMsg>` conversion. It must either
+//! fail with an error or produce a message that converts back to an equal one.
+
+#![no_main]
+
+use dbn::MboMsg;
+use libfuzzer_sys::fuzz_target;
+use rainybook::{MarketByOrderMessage, validate};
+use rainybook_fuzz::RawMbo;
+
+fuzz_target!(|raw: RawMbo| {
+    let record = raw.to_mbo();
+    let Ok(message) = MarketByOrderMessage::try_from(&record) else {
+        return;
+    };
+    let _ = validate(&message);
+    let _ = (
+        message.action_char(),
+        message.defined_price(),
+        message.is_snapshot(),
+    );
+    let round_trip = MarketByOrderMessage::try_from(&MboMsg::from(&message))
+        .expect("a converted message converts back");
+    assert_eq!(round_trip, message);
+});
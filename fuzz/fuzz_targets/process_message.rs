@@ -0,0 +1,49 @@
+//! Drives `MboProcessor::process_message` with arbitrary records under the lenient
+//! validation policy: a message may fail, but must never panic or leave the books
+//! inconsistent.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use rainybook::{
+    CrossingPolicy, ErrorPolicy, IncrementalMbp, MarketByOrderMessage, MboProcessor,
+    ModifyPriorityPolicy, ValidationPolicy,
+};
+use rainybook_fuzz::{RawMbo, check_book};
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    match_crossing: bool,
+    always_preserve: bool,
+    records: Vec<RawMbo>,
+}
+
+fuzz_target!(|input: Input| {
+    let mut processor = MboProcessor::with_observer(IncrementalMbp::new())
+        .with_validation_policy(ValidationPolicy::PassThrough)
+        .with_error_policy(ErrorPolicy::Collect)
+        .with_crossing_policy(match input.match_crossing {
+            true => CrossingPolicy::Match,
+            false => CrossingPolicy::Rest,
+        })
+        .with_modify_priority_policy(match input.always_preserve {
+            true => ModifyPriorityPolicy::AlwaysPreserve,
+            false => ModifyPriorityPolicy::ExchangeStyle,
+        });
+    for raw in &input.records {
+        let mut record = raw.to_mbo();
+        // A few instruments and order ids, so records meet the same books and orders
+        record.hd.instrument_id %= 3;
+        record.order_id %= 16;
+        let Ok(message) = MarketByOrderMessage::try_from(&record) else {
+            continue;
+        };
+        let _ = processor.process_message(&message);
+        let book = processor
+            .book(message.instrument_id)
+            .expect("book exists once processed");
+        check_book(book, processor.observer().mbp(message.instrument_id));
+    }
+    let _ = processor.stats();
+});
@@ -0,0 +1,135 @@
+//! Input types and checks shared by the fuzz targets.
+//!
+//! `RawMbo` is built by `arbitrary` from the fuzzer's bytes and turned into the
+//! `MboMsg` a decoder would yield, so every target goes through the same conversion as
+//! DBN input. Its enums steer the fuzzer towards the values that matter (known action
+//! and side characters, extreme prices and sizes) while still allowing any raw value.
+
+use std::ffi::c_char;
+
+use arbitrary::Arbitrary;
+use dbn::{FlagSet, MboMsg, RecordHeader};
+use rainybook::{MarketByPrice, OrderBook, Side};
+
+#[derive(Debug, Arbitrary)]
+pub enum Price {
+    Min,
+    Max,
+    Zero,
+    /// Near a typical fixed-point price, so orders share levels and cross.
+    Near(i8),
+    Any(i64),
+}
+
+impl Price {
+    fn raw(&self) -> i64 {
+        match *self {
+            Price::Min => i64::MIN,
+            Price::Max => i64::MAX,
+            Price::Zero => 0,
+            Price::Near(ticks) => 100_000_000_000 + i64::from(ticks),
+            Price::Any(price) => price,
+        }
+    }
+}
+
+#[derive(Debug, Arbitrary)]
+pub enum Size {
+    Small(u8),
+    Max,
+    Any(u32),
+}
+
+/// A character field that is usually one of `known`.
+#[derive(Debug, Arbitrary)]
+pub enum Char {
+    Known(u8),
+    Any(u8),
+}
+
+impl Char {
+    fn raw(&self, known: &[u8]) -> c_char {
+        match *self {
+            Char::Known(i) => known[usize::from(i) % known.len()] as c_char,
+            Char::Any(c) => c as c_char,
+        }
+    }
+}
+
+/// The fields of one MBO record.
+#[derive(Debug, Arbitrary)]
+pub struct RawMbo {
+    pub instrument_id: u32,
+    pub order_id: u64,
+    pub price: Price,
+    pub size: Size,
+    pub flags: u8,
+    pub action: Char,
+    pub side: Char,
+    pub ts_event: u64,
+    pub ts_recv: u64,
+    pub ts_in_delta: i32,
+    pub sequence: u32,
+}
+
+impl RawMbo {
+    pub fn to_mbo(&self) -> MboMsg {
+        MboMsg {
+            hd: RecordHeader::new::<MboMsg>(
+                dbn::enums::rtype::MBO,
+                1,
+                self.instrument_id,
+                self.ts_event,
+            ),
+            order_id: self.order_id,
+            price: self.price.raw(),
+            size: match self.size {
+                Size::Small(size) => u32::from(size),
+                Size::Max => u32::MAX,
+                Size::Any(size) => size,
+            },
+            flags: FlagSet::new(self.flags),
+            channel_id: 0,
+            action: self.action.raw(b"ACMFRTN"),
+            side: self.side.raw(b"BAN"),
+            ts_recv: self.ts_recv,
+            ts_in_delta: self.ts_in_delta,
+            sequence: self.sequence,
+        }
+    }
+}
+
+/// Checks the book's levels against each other and against `mbp`, the view an
+/// `IncrementalMbp` kept of it, and exercises the derived metrics.
+pub fn check_book(book: &OrderBook, mbp: Option<&MarketByPrice>) {
+    let full = MarketByPrice::from(book);
+    for (side, levels) in [(Side::Bid, &full.bids), (Side::Ask, &full.asks)] {
+        assert!(
+            levels
+                .values()
+                .all(|level| level.order_count > 0 && level.total_quantity > 0),
+            "empty {side:?} level"
+        );
+        assert_eq!(book.level_count(side), levels.len());
+        let total: u64 = levels.values().map(|level| level.total_quantity).sum();
+        assert_eq!(book.total_qty(side), total);
+    }
+    let top = |level: Option<(&i64, &rainybook::OrderLevelSummary)>| {
+        level.map(|(&price, level)| (price, level.total_quantity))
+    };
+    assert_eq!(book.best_bid(), top(full.bids.last_key_value()));
+    assert_eq!(book.best_ask(), top(full.asks.first_key_value()));
+    if let Some(mbp) = mbp {
+        assert_eq!(mbp.bids, full.bids);
+        assert_eq!(mbp.asks, full.asks);
+    }
+
+    let _ = (book.spread(), book.mid());
+    let _ = (book.imbalance(5), book.weighted_mid(5));
+    let _ = (
+        full.spread(),
+        full.mid(),
+        full.imbalance(5),
+        full.weighted_mid(5),
+    );
+}
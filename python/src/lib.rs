@@ -0,0 +1,10 @@
+//! The `rainybook` Python extension module, built with maturin (see pyproject.toml).
+//! The classes and functions live in `rainybook::python`.
+
+use pyo3::prelude::*;
+
+#[pymodule]
+#[pyo3(name = "rainybook")]
+fn init(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    rainybook::python::register(module)
+}
@@ -0,0 +1,129 @@
+"""End-to-end checks of the rainybook extension module.
+
+Build and install it first, e.g. `maturin develop --release` in python/, then run
+`pytest python/tests`.
+"""
+
+import threading
+from pathlib import Path
+
+import numpy as np
+import pyarrow.csv
+import pytest
+
+import rainybook
+
+FIXTURE = Path(__file__).parents[2] / "tests" / "fixtures" / "mbo.csv"
+
+
+def test_order_book_operations():
+    book = rainybook.OrderBook()
+    book.add(1, "bid", 100, 10)
+    book.add(2, "B", 100, 5, sequence=3)
+    book.add(3, "ask", 102, 7)
+    assert book.best_bid() == (100, 15)
+    assert book.best_ask() == (102, 7)
+
+    assert book.fill(1, 4) == 6
+    assert book.sweep("ask", 100, 8) == [(1, 100, 6), (2, 100, 2)]
+    assert book.top_n("bid", 5) == [(100, 3)]
+
+    assert book.modify(3, 103, 2)
+    assert not book.modify(42, 103, 2)
+    assert book.top_n("ask", 5) == [(103, 2)]
+    assert book.cancel(3) == 2
+    assert book.cancel(3) is None
+    assert book.best_ask() is None
+
+    with pytest.raises(ValueError):
+        book.fill(2, 100)
+    with pytest.raises(ValueError):
+        book.add(9, "sideways", 100, 1)
+
+
+def test_batch_matches_file_replay():
+    processor, summary = rainybook.process_file(str(FIXTURE))
+    assert summary == {"processed": 8, "failed": [], "rejected": []}
+    expected = processor.snapshot(depth=5)
+
+    # The same rows as an Arrow table, with action and side as strings
+    table = pyarrow.csv.read_csv(FIXTURE)
+    from_table = rainybook.MboProcessor()
+    assert from_table.process(table)["processed"] == 8
+    assert from_table.snapshot(depth=5).equals(expected)
+
+    # And as numpy arrays, with action and side as character codes
+    columns = {name: table.column(name).to_numpy() for name in table.column_names}
+    for name in ("action", "side"):
+        columns[name] = np.frombuffer("".join(columns[name]).encode(), dtype=np.int8)
+    from_arrays = rainybook.MboProcessor()
+    assert from_arrays.process(columns)["processed"] == 8
+    assert from_arrays.snapshot(depth=5).equals(expected)
+
+    assert from_arrays.instruments() == [7, 8]
+    assert from_arrays.best_bid(7) == (100_000_000_000, 10)
+    assert from_arrays.best_ask(7) == (101_000_000_000, 8)
+    assert from_arrays.best_bid(99) is None
+
+
+def test_snapshot_to_pandas():
+    processor, _ = rainybook.process_file(str(FIXTURE))
+    frame = processor.snapshot(depth=1, instrument_id=7).to_pandas()
+    assert list(frame.columns) == ["instrument_id", "side", "level", "price", "size", "count"]
+    assert frame.to_dict("records") == [
+        {"instrument_id": 7, "side": "B", "level": 0, "price": 100_000_000_000, "size": 10, "count": 1},
+        {"instrument_id": 7, "side": "A", "level": 0, "price": 101_000_000_000, "size": 8, "count": 1},
+    ]
+
+
+def test_failures_and_rejected_rows_reported():
+    processor = rainybook.MboProcessor()
+    summary = processor.process(
+        {
+            "action": np.array(["A", "C", "X", "A"]),
+            "side": np.array(["B", "B", "B", "A"]),
+            "price": np.array([100, 100, 100, 101]),
+            "order_id": np.array([1, 2, 3, 4]),
+            "size": np.array([10, 10, 10, 10]),
+        }
+    )
+    # Row 2 has no such action; the cancel of an unknown order counts as an anomaly
+    assert summary["processed"] == 3
+    assert [row for row, _ in summary["rejected"]] == [2]
+
+    with pytest.raises(ValueError, match="Missing column 'size'"):
+        processor.process({"action": [65], "side": [66], "price": [1], "order_id": [1]})
+    with pytest.raises(ValueError, match="different lengths"):
+        processor.process(
+            {"action": [65], "side": [66], "price": [1, 2], "order_id": [1], "size": [1]}
+        )
+
+
+def test_large_batches_process_on_threads():
+    rows = 200_000
+    rng = np.random.default_rng(7)
+    bids = rng.random(rows) < 0.5
+    batch = {
+        "action": np.full(rows, ord("A"), dtype=np.int8),
+        "side": np.where(bids, ord("B"), ord("A")).astype(np.int8),
+        # Bids below asks, so no add crosses the book
+        "price": np.where(bids, rng.integers(90, 100, rows), rng.integers(101, 111, rows)),
+        "order_id": np.arange(1, rows + 1, dtype=np.uint64),
+        "size": rng.integers(1, 100, rows),
+        "instrument_id": np.ones(rows, dtype=np.uint32),
+    }
+    processors = [rainybook.MboProcessor() for _ in range(4)]
+    summaries = [None] * len(processors)
+
+    def run(i):
+        summaries[i] = processors[i].process(batch)
+
+    # Processing releases the GIL, so the threads are not serialized on it
+    threads = [threading.Thread(target=run, args=(i,)) for i in range(len(processors))]
+    for thread in threads:
+        thread.start()
+    for thread in threads:
+        thread.join()
+    assert all(summary["processed"] == rows for summary in summaries)
+    snapshots = [processor.snapshot(depth=3) for processor in processors]
+    assert all(snapshot.equals(snapshots[0]) for snapshot in snapshots)
@@ -0,0 +1,606 @@
+//! C API, with the `ffi` feature.
+//!
+//! `OrderBook` and `MboProcessor` are exposed to C as opaque handles created by
+//! `rb_book_new`/`rb_processor_new` and released with the matching `_free`. Calls take
+//! and return plain integers: sides and actions as dbn characters (`'B'`, `'A'`, ...),
+//! prices in the book's fixed-point units, and an `RbStatus` code for anything that can
+//! fail. No panic crosses the boundary: one is caught and reported as
+//! `RB_STATUS_PANIC`, or as a null handle or an empty level.
+//!
+//! A handle may move between threads but is not synchronized: it must not be used from
+//! two threads at once. The crate in `ffi/` builds the static and shared libraries and
+//! generates `rainybook.h` with cbindgen.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::slice;
+
+use crate::orderbook::conversion::MboRow;
+use crate::orderbook::{
+    ConversionReport, ErrorPolicy, MboProcessor, Order, OrderBook, OrderBookError, Side,
+};
+
+/// Outcome of a call.
+#[repr(i32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RbStatus {
+    Ok = 0,
+    /// A handle or required pointer argument was null.
+    NullPointer = 1,
+    /// A side was neither `'B'` nor `'A'`, or a size does not fit the book.
+    InvalidArgument = 2,
+    /// The book holds no order with the id.
+    OrderNotFound = 3,
+    /// A fill was larger than the order.
+    SizeUnderflow = 4,
+    /// The order's price is beyond the book's depth limit.
+    BeyondDepthLimit = 5,
+    /// Some messages of a batch failed to apply or could not be converted.
+    ProcessFailed = 6,
+    /// The call panicked; the handle should not be used further.
+    Panic = 7,
+}
+
+impl From<OrderBookError> for RbStatus {
+    fn from(error: OrderBookError) -> Self {
+        match error {
+            OrderBookError::OrderNotFound(_) => RbStatus::OrderNotFound,
+            OrderBookError::SizeUnderflow { .. } => RbStatus::SizeUnderflow,
+            OrderBookError::BeyondDepthLimit { .. } => RbStatus::BeyondDepthLimit,
+        }
+    }
+}
+
+/// An L3 order book.
+pub struct RbBook {
+    book: OrderBook,
+}
+
+/// A processor keeping one book per instrument.
+pub struct RbProcessor {
+    processor: MboProcessor,
+}
+
+/// A market-by-order message in dbn terms, laid out largest field first so the struct
+/// has no padding between fields.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct RbMboMessage {
+    pub ts_event: u64,
+    /// 0 for the event time.
+    pub ts_recv: u64,
+    pub order_id: u64,
+    pub price: i64,
+    pub size: u32,
+    pub instrument_id: u32,
+    pub sequence: u32,
+    pub ts_in_delta: i32,
+    /// dbn action character, e.g. `'A'`.
+    pub action: i8,
+    /// dbn side character, e.g. `'B'`.
+    pub side: i8,
+    pub flags: u8,
+}
+
+/// Counts of one `rb_processor_process` call.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct RbProcessSummary {
+    /// Messages applied.
+    pub processed: u64,
+    /// Messages that failed to apply.
+    pub failed: u64,
+    /// Messages that could not be converted, e.g. for an unknown action.
+    pub rejected: u64,
+}
+
+/// Runs `f`, turning a panic into `fallback`.
+fn guard<T>(fallback: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(fallback)
+}
+
+/// Runs a call returning a status, turning a panic into `RbStatus::Panic`.
+fn guard_status(f: impl FnOnce() -> Result<(), RbStatus>) -> RbStatus {
+    guard(RbStatus::Panic, || match f() {
+        Ok(()) => RbStatus::Ok,
+        Err(status) => status,
+    })
+}
+
+fn parse_side(side: i8) -> Result<Side, RbStatus> {
+    match side as u8 {
+        b'B' => Ok(Side::Bid),
+        b'A' => Ok(Side::Ask),
+        _ => Err(RbStatus::InvalidArgument),
+    }
+}
+
+/// Writes `value` through `out` unless it is null.
+///
+/// # Safety
+/// `out` must be null or valid for writes.
+unsafe fn write_out<T>(out: *mut T, value: T) {
+    if let Some(out) = unsafe { out.as_mut() } {
+        *out = value;
+    }
+}
+
+/// Writes a level into the out-params, returning whether there was one.
+///
+/// # Safety
+/// `price` and `quantity` must be null or valid for writes.
+unsafe fn write_level(level: Option<(i64, u64)>, price: *mut i64, quantity: *mut u64) -> bool {
+    let Some((level_price, level_quantity)) = level else {
+        return false;
+    };
+    unsafe {
+        write_out(price, level_price);
+        write_out(quantity, level_quantity);
+    }
+    true
+}
+
+/// Copies up to `capacity` levels into the caller's arrays and their count into `len`.
+///
+/// # Safety
+/// `prices` and `quantities` must be valid for `capacity` writes, `len` for one.
+unsafe fn write_levels(
+    levels: &[(i64, u64)],
+    prices: *mut i64,
+    quantities: *mut u64,
+    capacity: usize,
+    len: *mut usize,
+) -> Result<(), RbStatus> {
+    if len.is_null() || (capacity > 0 && (prices.is_null() || quantities.is_null())) {
+        return Err(RbStatus::NullPointer);
+    }
+    for (i, &(price, quantity)) in levels.iter().take(capacity).enumerate() {
+        unsafe {
+            *prices.add(i) = price;
+            *quantities.add(i) = quantity;
+        }
+    }
+    unsafe { *len = levels.len().min(capacity) };
+    Ok(())
+}
+
+/// # Safety
+/// `book` must be null or a live handle.
+unsafe fn book_mut<'a>(book: *mut RbBook) -> Result<&'a mut OrderBook, RbStatus> {
+    match unsafe { book.as_mut() } {
+        Some(handle) => Ok(&mut handle.book),
+        None => Err(RbStatus::NullPointer),
+    }
+}
+
+/// Creates an empty book, or returns null if that fails.
+#[unsafe(no_mangle)]
+pub extern "C" fn rb_book_new() -> *mut RbBook {
+    guard(std::ptr::null_mut(), || {
+        Box::into_raw(Box::new(RbBook {
+            book: OrderBook::new(),
+        }))
+    })
+}
+
+/// Releases a book. Null is ignored.
+///
+/// # Safety
+/// `book` must be null or a live handle from `rb_book_new`, not used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rb_book_free(book: *mut RbBook) {
+    if !book.is_null() {
+        guard((), || drop(unsafe { Box::from_raw(book) }));
+    }
+}
+
+/// Adds a resting order of side `'B'` or `'A'`.
+///
+/// # Safety
+/// `book` must be null or a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rb_book_add(
+    book: *mut RbBook,
+    order_id: u64,
+    side: i8,
+    price: i64,
+    size: u64,
+    sequence: u32,
+) -> RbStatus {
+    guard_status(|| {
+        let book = unsafe { book_mut(book) }?;
+        let order = Order {
+            order_id,
+            side: parse_side(side)?,
+            price,
+            size,
+            sequence,
+            owner: None,
+            expires_at: None,
+        };
+        book.try_add_order(order)?;
+        Ok(())
+    })
+}
+
+/// Removes an order, writing its size into `size` unless that is null.
+///
+/// # Safety
+/// `book` must be null or a live handle, `size` null or valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rb_book_cancel(
+    book: *mut RbBook,
+    order_id: u64,
+    size: *mut u64,
+) -> RbStatus {
+    guard_status(|| {
+        let book = unsafe { book_mut(book) }?;
+        let info = book.remove_order(order_id).ok_or(RbStatus::OrderNotFound)?;
+        unsafe { write_out(size, info.order.size) };
+        Ok(())
+    })
+}
+
+/// Moves an order to `price` with `size`, keeping its side and sequence.
+///
+/// # Safety
+/// `book` must be null or a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rb_book_modify(
+    book: *mut RbBook,
+    order_id: u64,
+    price: i64,
+    size: u64,
+) -> RbStatus {
+    guard_status(|| {
+        let book = unsafe { book_mut(book) }?;
+        let &old = book.get_order(order_id).ok_or(RbStatus::OrderNotFound)?;
+        book.modify_order(Order { price, size, ..old })
+            .ok_or(RbStatus::OrderNotFound)?;
+        Ok(())
+    })
+}
+
+/// Executes `size` of a resting order, removing it when nothing is left, and writes
+/// what is left into `remaining` unless that is null.
+///
+/// # Safety
+/// `book` must be null or a live handle, `remaining` null or valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rb_book_fill(
+    book: *mut RbBook,
+    order_id: u64,
+    size: u64,
+    remaining: *mut u64,
+) -> RbStatus {
+    guard_status(|| {
+        let book = unsafe { book_mut(book) }?;
+        let delta = i64::try_from(size).map_err(|_| RbStatus::InvalidArgument)?;
+        let left = book.adjust_order_size(order_id, -delta)?;
+        unsafe { write_out(remaining, left) };
+        Ok(())
+    })
+}
+
+/// Writes the price and quantity of the best bid level, returning false if the book
+/// has none.
+///
+/// # Safety
+/// `book` must be null or a live handle, `price` and `quantity` null or valid for
+/// writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rb_book_best_bid(
+    book: *const RbBook,
+    price: *mut i64,
+    quantity: *mut u64,
+) -> bool {
+    guard(false, || {
+        let level = unsafe { book.as_ref() }.and_then(|handle| handle.book.best_bid());
+        unsafe { write_level(level, price, quantity) }
+    })
+}
+
+/// Writes the price and quantity of the best ask level, returning false if the book
+/// has none.
+///
+/// # Safety
+/// As for `rb_book_best_bid`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rb_book_best_ask(
+    book: *const RbBook,
+    price: *mut i64,
+    quantity: *mut u64,
+) -> bool {
+    guard(false, || {
+        let level = unsafe { book.as_ref() }.and_then(|handle| handle.book.best_ask());
+        unsafe { write_level(level, price, quantity) }
+    })
+}
+
+/// Fills `prices` and `quantities` with up to `capacity` levels of `side`, best first,
+/// and writes how many into `len`.
+///
+/// # Safety
+/// `book` must be null or a live handle, `prices` and `quantities` valid for
+/// `capacity` writes and `len` for one.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rb_book_top_n(
+    book: *const RbBook,
+    side: i8,
+    prices: *mut i64,
+    quantities: *mut u64,
+    capacity: usize,
+    len: *mut usize,
+) -> RbStatus {
+    guard_status(|| {
+        let book = &unsafe { book.as_ref() }.ok_or(RbStatus::NullPointer)?.book;
+        let levels = match parse_side(side)? {
+            Side::Bid => book.top_n_bids(capacity),
+            Side::Ask => book.top_n_asks(capacity),
+        };
+        unsafe { write_levels(&levels, prices, quantities, capacity, len) }
+    })
+}
+
+/// Creates a processor, or returns null if that fails. With `fail_fast` a batch stops
+/// at its first message that cannot be applied; otherwise such messages are skipped.
+#[unsafe(no_mangle)]
+pub extern "C" fn rb_processor_new(fail_fast: bool) -> *mut RbProcessor {
+    guard(std::ptr::null_mut(), || {
+        let policy = match fail_fast {
+            true => ErrorPolicy::FailFast,
+            false => ErrorPolicy::Collect,
+        };
+        Box::into_raw(Box::new(RbProcessor {
+            processor: MboProcessor::new().with_error_policy(policy),
+        }))
+    })
+}
+
+/// Releases a processor. Null is ignored.
+///
+/// # Safety
+/// `processor` must be null or a live handle from `rb_processor_new`, not used
+/// afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rb_processor_free(processor: *mut RbProcessor) {
+    if !processor.is_null() {
+        guard((), || drop(unsafe { Box::from_raw(processor) }));
+    }
+}
+
+/// Applies `len` messages in order, converting them with the rules of tabular input,
+/// and writes the counts into `summary` unless that is null. Returns
+/// `RB_STATUS_PROCESS_FAILED` if any message failed or could not be converted.
+///
+/// # Safety
+/// `processor` must be null or a live handle, `messages` valid for `len` reads and
+/// `summary` null or valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rb_processor_process(
+    processor: *mut RbProcessor,
+    messages: *const RbMboMessage,
+    len: usize,
+    summary: *mut RbProcessSummary,
+) -> RbStatus {
+    guard_status(|| {
+        let processor = &mut unsafe { processor.as_mut() }
+            .ok_or(RbStatus::NullPointer)?
+            .processor;
+        let messages = match len {
+            0 => &[],
+            _ if messages.is_null() => return Err(RbStatus::NullPointer),
+            _ => unsafe { slice::from_raw_parts(messages, len) },
+        };
+        let mut report = ConversionReport::default();
+        for (index, message) in messages.iter().enumerate() {
+            let row = MboRow {
+                action: message.action,
+                side: message.side,
+                price: message.price,
+                order_id: message.order_id,
+                size: message.size,
+                instrument_id: message.instrument_id,
+                ts_event: message.ts_event,
+                ts_recv: match message.ts_recv {
+                    0 => message.ts_event,
+                    ts_recv => ts_recv,
+                },
+                ts_in_delta: message.ts_in_delta,
+                sequence: message.sequence,
+                flags: message.flags,
+                publisher_id: 0,
+                channel_id: 0,
+            };
+            report.push(index, row.into_message());
+        }
+        let processed = processor.process_messages(&report.messages);
+        let counts = RbProcessSummary {
+            processed: processed.processed,
+            failed: processed.failures.len() as u64,
+            rejected: report.rejected.len() as u64,
+        };
+        unsafe { write_out(summary, counts) };
+        match counts.failed + counts.rejected {
+            0 => Ok(()),
+            _ => Err(RbStatus::ProcessFailed),
+        }
+    })
+}
+
+/// Writes the best bid level of an instrument, returning false if it has none.
+///
+/// # Safety
+/// `processor` must be null or a live handle, `price` and `quantity` null or valid
+/// for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rb_processor_best_bid(
+    processor: *const RbProcessor,
+    instrument_id: u32,
+    price: *mut i64,
+    quantity: *mut u64,
+) -> bool {
+    guard(false, || {
+        let level = unsafe { processor.as_ref() }
+            .and_then(|handle| handle.processor.book(instrument_id)?.best_bid());
+        unsafe { write_level(level, price, quantity) }
+    })
+}
+
+/// Writes the best ask level of an instrument, returning false if it has none.
+///
+/// # Safety
+/// As for `rb_processor_best_bid`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rb_processor_best_ask(
+    processor: *const RbProcessor,
+    instrument_id: u32,
+    price: *mut i64,
+    quantity: *mut u64,
+) -> bool {
+    guard(false, || {
+        let level = unsafe { processor.as_ref() }
+            .and_then(|handle| handle.processor.book(instrument_id)?.best_ask());
+        unsafe { write_level(level, price, quantity) }
+    })
+}
+
+/// `rb_book_top_n` for the book of an instrument; an instrument without a book has
+/// no levels.
+///
+/// # Safety
+/// As for `rb_book_top_n`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rb_processor_top_n(
+    processor: *const RbProcessor,
+    instrument_id: u32,
+    side: i8,
+    prices: *mut i64,
+    quantities: *mut u64,
+    capacity: usize,
+    len: *mut usize,
+) -> RbStatus {
+    guard_status(|| {
+        let processor = &unsafe { processor.as_ref() }
+            .ok_or(RbStatus::NullPointer)?
+            .processor;
+        let side = parse_side(side)?;
+        let levels = match processor.book(instrument_id) {
+            Some(book) if side == Side::Bid => book.top_n_bids(capacity),
+            Some(book) => book.top_n_asks(capacity),
+            None => Vec::new(),
+        };
+        unsafe { write_levels(&levels, prices, quantities, capacity, len) }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+
+    fn message(action: u8, side: u8, order_id: u64, price: i64, size: u32) -> RbMboMessage {
+        RbMboMessage {
+            order_id,
+            price,
+            size,
+            instrument_id: 7,
+            action: action as i8,
+            side: side as i8,
+            flags: dbn::flags::LAST,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_book_status_codes() {
+        let book = rb_book_new();
+        unsafe {
+            assert_eq!(rb_book_add(book, 1, b'B' as i8, 100, 10, 0), RbStatus::Ok);
+            assert_eq!(rb_book_add(book, 2, b'A' as i8, 101, 5, 1), RbStatus::Ok);
+            assert_eq!(
+                rb_book_add(book, 3, b'X' as i8, 101, 5, 2),
+                RbStatus::InvalidArgument
+            );
+            assert_eq!(
+                rb_book_fill(book, 1, 11, ptr::null_mut()),
+                RbStatus::SizeUnderflow
+            );
+            assert_eq!(rb_book_modify(book, 9, 100, 1), RbStatus::OrderNotFound);
+
+            let mut remaining = 0;
+            assert_eq!(rb_book_fill(book, 1, 4, &mut remaining), RbStatus::Ok);
+            assert_eq!(remaining, 6);
+            let (mut price, mut quantity) = (0, 0);
+            assert!(rb_book_best_bid(book, &mut price, &mut quantity));
+            assert_eq!((price, quantity), (100, 6));
+
+            let mut size = 0;
+            assert_eq!(rb_book_cancel(book, 2, &mut size), RbStatus::Ok);
+            assert_eq!(size, 5);
+            assert_eq!(rb_book_cancel(book, 2, &mut size), RbStatus::OrderNotFound);
+            assert!(!rb_book_best_ask(book, &mut price, &mut quantity));
+            rb_book_free(book);
+
+            assert_eq!(
+                rb_book_add(ptr::null_mut(), 1, b'B' as i8, 100, 10, 0),
+                RbStatus::NullPointer
+            );
+            assert!(!rb_book_best_bid(ptr::null(), &mut price, &mut quantity));
+            rb_book_free(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_processor_batches() {
+        let processor = rb_processor_new(false);
+        let messages = [
+            message(b'A', b'B', 1, 100, 10),
+            message(b'A', b'B', 2, 99, 4),
+            message(b'A', b'A', 3, 102, 8),
+            message(b'X', b'B', 4, 100, 1),
+            message(b'F', b'B', 1, 100, u32::MAX),
+        ];
+        let mut summary = RbProcessSummary::default();
+        unsafe {
+            let status =
+                rb_processor_process(processor, messages.as_ptr(), messages.len(), &mut summary);
+            assert_eq!(status, RbStatus::ProcessFailed);
+            assert_eq!(summary.rejected, 1);
+            assert_eq!(summary.processed + summary.failed, 4);
+
+            let (mut prices, mut quantities, mut len) = ([0; 4], [0; 4], 0);
+            let status = rb_processor_top_n(
+                processor,
+                7,
+                b'B' as i8,
+                prices.as_mut_ptr(),
+                quantities.as_mut_ptr(),
+                prices.len(),
+                &mut len,
+            );
+            assert_eq!(status, RbStatus::Ok);
+            assert_eq!(len, 2);
+            assert_eq!((prices[1], quantities[1]), (99, 4));
+
+            let (mut price, mut quantity) = (0, 0);
+            assert!(rb_processor_best_ask(
+                processor,
+                7,
+                &mut price,
+                &mut quantity
+            ));
+            assert_eq!((price, quantity), (102, 8));
+            assert!(!rb_processor_best_bid(
+                processor,
+                8,
+                &mut price,
+                &mut quantity
+            ));
+            assert_eq!(
+                rb_processor_process(processor, ptr::null(), 1, ptr::null_mut()),
+                RbStatus::NullPointer
+            );
+            rb_processor_free(processor);
+        }
+    }
+}
@@ -3,12 +3,18 @@
 //! Provides randomized order generation with configurable distributions
 //! for price and quantity, while ensuring the order book never crosses.
 
+use std::collections::BTreeMap;
+
 use rand::Rng;
 use rand::SeedableRng;
 use rand_chacha::ChaCha8Rng;
-use rand_distr::{Distribution, Exp, Normal};
+use rand_distr::{Distribution, Exp, Normal, StandardNormal};
+use time::{Duration, OffsetDateTime};
+
+use crate::orderbook::{Action, MarketByOrderMessage, Order, Side};
 
-use crate::orderbook::{Order, Side};
+/// Orders `make_mbo_messages` keeps resting, at most.
+const MAX_RESTING_ORDERS: usize = 1_000;
 
 /// Stateful order generator that tracks market state to prevent crossed books.
 ///
@@ -107,6 +113,8 @@ where
             price,
             size,
             sequence: order_id as u32,
+            owner: None,
+            expires_at: None,
         }
     }
 
@@ -114,6 +122,518 @@ where
     pub fn make_orders(&mut self, n: usize) -> Vec<Order> {
         (0..n).map(|_| self.next_order()).collect()
     }
+
+    /// Generate `n` MBO messages for instrument 1, one microsecond apart, that apply
+    /// cleanly to an empty book.
+    ///
+    /// Orders from `next_order` are added until half of `MAX_RESTING_ORDERS` rest;
+    /// from then on about 40% of messages are adds, 40% cancels, 10% size modifies of
+    /// resting orders and 10% trades at their prices, keeping the book bounded.
+    pub fn make_mbo_messages(&mut self, n: usize) -> Vec<MarketByOrderMessage> {
+        let mut resting: Vec<Order> = Vec::new();
+        (0..n)
+            .map(|index| {
+                let roll: f64 = self.rng.random();
+                let (action, order) = if resting.len() < MAX_RESTING_ORDERS / 2
+                    || (roll < 0.4 && resting.len() < MAX_RESTING_ORDERS)
+                {
+                    let order = self.next_order();
+                    resting.push(order);
+                    (Action::Add, order)
+                } else {
+                    let slot = self.rng.random_range(0..resting.len());
+                    if roll < 0.8 {
+                        (Action::Cancel, resting.swap_remove(slot))
+                    } else if roll < 0.9 {
+                        resting[slot].size = self.sample_qty();
+                        (Action::Modify, resting[slot])
+                    } else {
+                        let trade = Order {
+                            order_id: 0,
+                            size: 1,
+                            ..resting[slot]
+                        };
+                        (Action::Trade, trade)
+                    }
+                };
+                mbo_message(action, &order, index, index, true)
+            })
+            .collect()
+    }
+
+    /// Generate `n` MBO messages for instrument 1 whose order lifecycles are consistent:
+    /// every cancel, modify and fill refers to a resting order, no fill exceeds the
+    /// order's resting size, and a fill takes its size off the book as a venue reports it.
+    ///
+    /// Like `make_mbo_messages`, orders are added until half of `MAX_RESTING_ORDERS`
+    /// rest, then events are drawn with the frequencies of `mix`. A modify changes the
+    /// size, or half the time moves the order away from the touch by up to 3 ticks. A
+    /// fill is one event of three messages: the aggressor's Trade, the resting order's
+    /// Fill, then the Cancel or size-reducing Modify of that order. A trade is a lone
+    /// Trade at a resting order's price, as against hidden liquidity. Sequences count
+    /// messages and events are one microsecond apart, each ending with the LAST flag.
+    pub fn make_lifecycle_messages(
+        &mut self,
+        n: usize,
+        mix: MessageMix,
+    ) -> Vec<MarketByOrderMessage> {
+        let total = mix.add + mix.cancel + mix.modify + mix.fill + mix.trade;
+        let mut resting: Vec<Order> = Vec::new();
+        let mut messages = Vec::with_capacity(n);
+        let mut event = 0;
+        while messages.len() < n {
+            let roll = self.rng.random::<f64>() * total;
+            let sequence = messages.len();
+            let room = n - messages.len();
+            if resting.len() < MAX_RESTING_ORDERS / 2
+                || (roll < mix.add && resting.len() < MAX_RESTING_ORDERS)
+            {
+                let order = self.next_order();
+                resting.push(order);
+                messages.push(mbo_message(Action::Add, &order, sequence, event, true));
+            } else if roll < mix.add + mix.cancel {
+                let slot = self.rng.random_range(0..resting.len());
+                let order = resting.swap_remove(slot);
+                messages.push(mbo_message(Action::Cancel, &order, sequence, event, true));
+            } else if roll >= mix.add + mix.cancel + mix.modify + mix.fill {
+                let order = resting[self.rng.random_range(0..resting.len())];
+                let trade = Order {
+                    order_id: 0,
+                    side: opposite(order.side),
+                    size: self.sample_qty(),
+                    ..order
+                };
+                messages.push(mbo_message(Action::Trade, &trade, sequence, event, true));
+            } else if roll < mix.add + mix.cancel + mix.modify || room < 3 {
+                // A fill that would not fit in `n` becomes a modify
+                let slot = self.rng.random_range(0..resting.len());
+                let order = &mut resting[slot];
+                if self.rng.random_bool(0.5) {
+                    let ticks = self.rng.random_range(1..=3);
+                    order.price += match order.side {
+                        Side::Bid => -ticks,
+                        Side::Ask => ticks,
+                    };
+                } else {
+                    order.size = self.sample_qty();
+                }
+                messages.push(mbo_message(Action::Modify, order, sequence, event, true));
+            } else {
+                let slot = self.rng.random_range(0..resting.len());
+                let order = resting[slot];
+                let size = self.rng.random_range(1..=order.size);
+                let aggressor = Order {
+                    order_id: 0,
+                    side: opposite(order.side),
+                    size,
+                    ..order
+                };
+                let fill = Order { size, ..order };
+                messages.push(mbo_message(
+                    Action::Trade,
+                    &aggressor,
+                    sequence,
+                    event,
+                    false,
+                ));
+                messages.push(mbo_message(Action::Fill, &fill, sequence + 1, event, false));
+                let update = if size == order.size {
+                    (Action::Cancel, resting.swap_remove(slot))
+                } else {
+                    resting[slot].size -= size;
+                    (Action::Modify, resting[slot])
+                };
+                messages.push(mbo_message(update.0, &update.1, sequence + 2, event, true));
+            }
+            event += 1;
+        }
+        messages
+    }
+
+    /// Generate `n` lifecycle-consistent messages for instrument 1 around a moving mid
+    /// price, as described by `model`.
+    ///
+    /// Orders are added until half of `MAX_RESTING_ORDERS` rest, then the mid moves
+    /// once per event. Bids are added `k` ticks below the tick under the mid and asks
+    /// `k` ticks above the tick over it, `k` drawn with weights decaying by
+    /// `depth_decay` per tick. Executions and lone trades hit the oldest order at the
+    /// touch; modifies and cancels pick any resting order, so they cluster where the
+    /// orders do, near the touch. When the mid moves through orders, each is executed
+    /// in full by an aggressor from the other side before the next event, so the book
+    /// stays uncrossed; the mid never moves past the last order of either side, so it
+    /// stays two-sided. Executions have the three messages of `make_lifecycle_messages`,
+    /// and events are one microsecond apart.
+    pub fn make_realistic_messages(
+        &mut self,
+        n: usize,
+        model: &MarketModel,
+    ) -> Vec<MarketByOrderMessage> {
+        let mix = model.mix;
+        let total = mix.add + mix.cancel + mix.modify + mix.fill + mix.trade;
+        let mut mid = model.initial_mid;
+        let mut book = RestingOrders::default();
+        let mut messages = Vec::with_capacity(n);
+        let mut event = 0;
+        while messages.len() < n {
+            let sequence = messages.len();
+            let room = n - messages.len();
+            let populated = book.len() >= MAX_RESTING_ORDERS / 2;
+            if populated {
+                let shock = model.volatility * self.rng.sample::<f64, _>(StandardNormal);
+                let pull = match model.mid_process {
+                    MidProcess::RandomWalk => 0.0,
+                    MidProcess::OrnsteinUhlenbeck { reversion } => {
+                        reversion * (model.initial_mid - mid)
+                    }
+                };
+                // Strictly inside the far ends of both sides
+                let (low, high) = (book.worst(Side::Bid), book.worst(Side::Ask));
+                mid = (mid + pull + shock).clamp(low as f64 + 0.5, high as f64 - 0.5);
+            }
+
+            // Orders the mid moved through are taken out, one event each
+            let crossed = [Side::Bid, Side::Ask].into_iter().find_map(|side| {
+                let best = book.best(side)?;
+                let through = match side {
+                    Side::Bid => best as f64 >= mid,
+                    Side::Ask => best as f64 <= mid,
+                };
+                through.then(|| book.first_at(side, best))
+            });
+            let roll = self.rng.random::<f64>() * total;
+            if let Some(slot) = crossed {
+                let order = book.remove(slot);
+                if room < 3 {
+                    messages.push(mbo_message(Action::Cancel, &order, sequence, event, true));
+                } else {
+                    messages.extend(execution(&order, order.size, sequence, event));
+                }
+            } else if !populated
+                || book.count(Side::Bid) == 0
+                || book.count(Side::Ask) == 0
+                || (roll < mix.add && book.len() < MAX_RESTING_ORDERS)
+            {
+                let side = match (book.count(Side::Bid), book.count(Side::Ask)) {
+                    (0, _) => Side::Bid,
+                    (_, 0) => Side::Ask,
+                    _ => self.sample_side(),
+                };
+                let mut ticks = 0;
+                while ticks < model.max_depth && self.rng.random_bool(model.continuation()) {
+                    ticks += 1;
+                }
+                let price = match side {
+                    Side::Bid => mid.ceil() as i64 - 1 - ticks,
+                    Side::Ask => mid.floor() as i64 + 1 + ticks,
+                };
+                let order = self.new_order(side, price);
+                book.insert(order);
+                messages.push(mbo_message(Action::Add, &order, sequence, event, true));
+            } else if roll < mix.add + mix.cancel {
+                let slot = self.rng.random_range(0..book.len());
+                // The last order of a side stays, resized
+                if book.count(book.orders[slot].side) == 1 {
+                    book.orders[slot].size = self.sample_qty();
+                    let order = book.orders[slot];
+                    messages.push(mbo_message(Action::Modify, &order, sequence, event, true));
+                } else {
+                    let order = book.remove(slot);
+                    messages.push(mbo_message(Action::Cancel, &order, sequence, event, true));
+                }
+            } else if roll < mix.add + mix.cancel + mix.modify || room < 3 {
+                let slot = self.rng.random_range(0..book.len());
+                let mut order = book.remove(slot);
+                if self.rng.random_bool(0.5) {
+                    let ticks = self.rng.random_range(1..=3);
+                    order.price += match order.side {
+                        Side::Bid => -ticks,
+                        Side::Ask => ticks,
+                    };
+                } else {
+                    order.size = self.sample_qty();
+                }
+                book.insert(order);
+                messages.push(mbo_message(Action::Modify, &order, sequence, event, true));
+            } else {
+                // An aggressor from either side, trading at the opposite touch
+                let side = self.sample_side();
+                let best = book.best(side).expect("both sides rest orders");
+                let slot = book.first_at(side, best);
+                let order = book.orders[slot];
+                if roll < mix.add + mix.cancel + mix.modify + mix.fill {
+                    // Against the last order of a side, never its whole size
+                    let most = match book.count(side) {
+                        1 => order.size - 1,
+                        _ => order.size,
+                    };
+                    if most == 0 {
+                        let trade = Order {
+                            order_id: 0,
+                            side: opposite(side),
+                            size: 1,
+                            ..order
+                        };
+                        messages.push(mbo_message(Action::Trade, &trade, sequence, event, true));
+                    } else {
+                        let size = self.rng.random_range(1..=most);
+                        let filled = match size == order.size {
+                            true => book.remove(slot),
+                            false => {
+                                book.orders[slot].size -= size;
+                                order
+                            }
+                        };
+                        messages.extend(execution(&filled, size, sequence, event));
+                    }
+                } else {
+                    let trade = Order {
+                        order_id: 0,
+                        side: opposite(side),
+                        size: self.sample_qty(),
+                        ..order
+                    };
+                    messages.push(mbo_message(Action::Trade, &trade, sequence, event, true));
+                }
+            }
+            event += 1;
+        }
+        messages
+    }
+
+    /// A new order with the next id and a sampled size.
+    fn new_order(&mut self, side: Side, price: i64) -> Order {
+        let order_id = self.next_order_id;
+        self.next_order_id += 1;
+        Order {
+            order_id,
+            side,
+            price,
+            size: self.sample_qty(),
+            sequence: order_id as u32,
+            owner: None,
+            expires_at: None,
+        }
+    }
+}
+
+/// The three messages of an execution of `size` against the resting `order`, as it
+/// was before it: the aggressor's Trade, the order's Fill, then its Cancel if filled
+/// in full or else the Modify to what remains.
+fn execution(order: &Order, size: u64, sequence: usize, event: usize) -> [MarketByOrderMessage; 3] {
+    let aggressor = Order {
+        order_id: 0,
+        side: opposite(order.side),
+        size,
+        ..*order
+    };
+    let fill = Order { size, ..*order };
+    let update = match size == order.size {
+        true => mbo_message(Action::Cancel, order, sequence + 2, event, true),
+        false => mbo_message(
+            Action::Modify,
+            &Order {
+                size: order.size - size,
+                ..*order
+            },
+            sequence + 2,
+            event,
+            true,
+        ),
+    };
+    [
+        mbo_message(Action::Trade, &aggressor, sequence, event, false),
+        mbo_message(Action::Fill, &fill, sequence + 1, event, false),
+        update,
+    ]
+}
+
+/// How the reference mid of `MarketModel` moves from one event to the next.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MidProcess {
+    /// Each step adds a normal move of `MarketModel::volatility` ticks.
+    RandomWalk,
+    /// Each step also pulls the mid back by `reversion` of its distance from
+    /// `MarketModel::initial_mid`, so it wanders but does not drift away.
+    OrnsteinUhlenbeck { reversion: f64 },
+}
+
+/// Price-path and order-flow model of `OrderGenerator::make_realistic_messages`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MarketModel {
+    /// Mid price at the start, in ticks.
+    pub initial_mid: f64,
+    pub mid_process: MidProcess,
+    /// Standard deviation of the mid's move per event, in ticks.
+    pub volatility: f64,
+    /// Decay of the arrival intensity of adds per tick away from the touch: an add
+    /// `k + 1` ticks out is `exp(-depth_decay)` times as likely as one `k` ticks out.
+    pub depth_decay: f64,
+    /// Farthest an add rests from the touch, in ticks.
+    pub max_depth: i64,
+    /// Relative frequencies of the events once the book is populated; `cancel` is the
+    /// cancel rate and `fill` the rate of executions at the touch.
+    pub mix: MessageMix,
+}
+
+impl MarketModel {
+    /// Chance of an add going one tick further out.
+    fn continuation(&self) -> f64 {
+        (-self.depth_decay).exp().clamp(0.0, 1.0)
+    }
+}
+
+/// A slowly wandering mid around 10000 with most adds within a few ticks of the touch,
+/// and the events of `MessageMix::default`.
+impl Default for MarketModel {
+    fn default() -> Self {
+        Self {
+            initial_mid: 10_000.0,
+            mid_process: MidProcess::RandomWalk,
+            volatility: 0.05,
+            depth_decay: 0.3,
+            max_depth: 20,
+            mix: MessageMix::default(),
+        }
+    }
+}
+
+/// The resting orders of `make_realistic_messages`, with the count of orders at each
+/// price per side to find the touch.
+#[derive(Debug, Default)]
+struct RestingOrders {
+    orders: Vec<Order>,
+    bids: BTreeMap<i64, usize>,
+    asks: BTreeMap<i64, usize>,
+    bid_count: usize,
+    ask_count: usize,
+}
+
+impl RestingOrders {
+    fn len(&self) -> usize {
+        self.orders.len()
+    }
+
+    /// The levels and order count of `side`.
+    fn side_mut(&mut self, side: Side) -> (&mut BTreeMap<i64, usize>, &mut usize) {
+        match side {
+            Side::Bid => (&mut self.bids, &mut self.bid_count),
+            Side::Ask => (&mut self.asks, &mut self.ask_count),
+        }
+    }
+
+    fn count(&self, side: Side) -> usize {
+        match side {
+            Side::Bid => self.bid_count,
+            Side::Ask => self.ask_count,
+        }
+    }
+
+    fn best(&self, side: Side) -> Option<i64> {
+        match side {
+            Side::Bid => self.bids.keys().next_back().copied(),
+            Side::Ask => self.asks.keys().next().copied(),
+        }
+    }
+
+    /// The price farthest from the touch on `side`.
+    fn worst(&self, side: Side) -> i64 {
+        match side {
+            Side::Bid => self.bids.keys().next().copied(),
+            Side::Ask => self.asks.keys().next_back().copied(),
+        }
+        .expect("both sides rest orders")
+    }
+
+    /// Slot of the order with the lowest id at `price` on `side`.
+    fn first_at(&self, side: Side, price: i64) -> usize {
+        (0..self.orders.len())
+            .filter(|&slot| self.orders[slot].side == side && self.orders[slot].price == price)
+            .min_by_key(|&slot| self.orders[slot].order_id)
+            .expect("a level holds orders")
+    }
+
+    fn insert(&mut self, order: Order) {
+        let (levels, count) = self.side_mut(order.side);
+        *levels.entry(order.price).or_default() += 1;
+        *count += 1;
+        self.orders.push(order);
+    }
+
+    fn remove(&mut self, slot: usize) -> Order {
+        let order = self.orders.swap_remove(slot);
+        let (levels, count) = self.side_mut(order.side);
+        *count -= 1;
+        let level = levels.get_mut(&order.price).expect("a level holds orders");
+        *level -= 1;
+        if *level == 0 {
+            levels.remove(&order.price);
+        }
+        order
+    }
+}
+
+/// Relative frequencies of the events `OrderGenerator::make_lifecycle_messages` draws
+/// once the book is populated. They need not sum to 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MessageMix {
+    pub add: f64,
+    pub cancel: f64,
+    pub modify: f64,
+    /// Executions against a resting order, three messages each.
+    pub fill: f64,
+    /// Trades that leave the book untouched.
+    pub trade: f64,
+}
+
+/// Roughly the mix of a liquid futures feed: adds and cancels dominate, with few
+/// modifies and fewer executions.
+impl Default for MessageMix {
+    fn default() -> Self {
+        Self {
+            add: 0.47,
+            cancel: 0.44,
+            modify: 0.06,
+            fill: 0.025,
+            trade: 0.005,
+        }
+    }
+}
+
+fn opposite(side: Side) -> Side {
+    match side {
+        Side::Bid => Side::Ask,
+        Side::Ask => Side::Bid,
+    }
+}
+
+/// A message for instrument 1 with the given sequence, timestamped `event`
+/// microseconds after the epoch.
+fn mbo_message(
+    action: Action,
+    order: &Order,
+    sequence: usize,
+    event: usize,
+    is_last: bool,
+) -> MarketByOrderMessage {
+    let event_time = OffsetDateTime::UNIX_EPOCH + Duration::microseconds(event as i64);
+    MarketByOrderMessage {
+        instrument_id: 1,
+        action,
+        side: order.side,
+        no_side: false,
+        price: order.price,
+        order_id: order.order_id,
+        size: order.size as u32,
+        is_last,
+        flags: if is_last { dbn::flags::LAST } else { 0 },
+        sequence: sequence as u32,
+        event_time,
+        recv_time: event_time,
+        ts_in_delta: Duration::ZERO,
+        owner: None,
+        publisher_id: 0,
+        channel_id: 0,
+    }
 }
 
 /// Convenience constructor for testing with sensible defaults.
@@ -133,6 +653,38 @@ impl OrderGenerator<Normal<f64>, Exp<f64>, ChaCha8Rng> {
     }
 }
 
+/// Generate `n` lifecycle-consistent messages over instruments `1..=instruments`, each
+/// drawn by `make_lifecycle_messages` from its own generator seeded with `seed` plus its
+/// id. The streams are merged by event time, each keeping its own order, and sequences
+/// are renumbered to count messages of the merged stream.
+pub fn make_multi_instrument_messages(
+    seed: u64,
+    instruments: u32,
+    n: usize,
+    mix: MessageMix,
+) -> Vec<MarketByOrderMessage> {
+    let instruments = instruments.max(1);
+    let mut messages: Vec<MarketByOrderMessage> = (1..=instruments)
+        .flat_map(|instrument_id| {
+            let share = n / instruments as usize
+                + usize::from((instrument_id as usize) <= n % instruments as usize);
+            OrderGenerator::default_seeded(seed + u64::from(instrument_id))
+                .make_lifecycle_messages(share, mix)
+                .into_iter()
+                .map(move |message| MarketByOrderMessage {
+                    instrument_id,
+                    ..message
+                })
+        })
+        .collect();
+    // Stable, so each instrument's messages stay in order
+    messages.sort_by_key(|message| message.event_time);
+    for (sequence, message) in messages.iter_mut().enumerate() {
+        message.sequence = sequence as u32;
+    }
+    messages
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -154,6 +706,149 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_mbo_messages_apply_cleanly() {
+        let messages = OrderGenerator::default_seeded(7).make_mbo_messages(5_000);
+        assert_eq!(
+            messages,
+            OrderGenerator::default_seeded(7).make_mbo_messages(5_000)
+        );
+
+        let mut processor = crate::orderbook::MboProcessor::new();
+        let summary = processor.process_messages(&messages);
+        assert!(summary.is_success());
+        let counts = summary.action_counts;
+        assert_eq!(
+            counts.add + counts.cancel + counts.modify + counts.trade,
+            5_000
+        );
+        assert!(counts.cancel > 1_000 && counts.modify > 100 && counts.trade > 100);
+        let stats = processor.stats();
+        assert_eq!(stats.unknown_cancels + stats.unknown_modifies, 0);
+        assert!(counts.add - counts.cancel <= MAX_RESTING_ORDERS as u64);
+    }
+
+    #[test]
+    fn test_lifecycle_messages_apply_cleanly() {
+        let mix = MessageMix::default();
+        let messages = OrderGenerator::default_seeded(7).make_lifecycle_messages(20_000, mix);
+        assert_eq!(messages.len(), 20_000);
+        assert!(messages.last().unwrap().is_last);
+
+        let mut processor = crate::orderbook::MboProcessor::new();
+        let summary = processor.process_messages(&messages);
+        assert!(summary.is_success());
+        let stats = processor.stats();
+        assert_eq!(stats.warnings(), 0);
+        let counts = summary.action_counts;
+        assert!(counts.add > 9_000 && counts.cancel > 8_000);
+        assert!(counts.modify > 1_000 && counts.fill > 300);
+        assert!(counts.trade > counts.fill);
+    }
+
+    #[test]
+    fn test_lifecycle_messages_pass_strict_processing() {
+        let mix = MessageMix {
+            fill: 0.1,
+            trade: 0.05,
+            ..MessageMix::default()
+        };
+        let messages = OrderGenerator::default_seeded(21).make_lifecycle_messages(10_000, mix);
+        for pair in messages.windows(2) {
+            assert_eq!(pair[1].sequence, pair[0].sequence + 1);
+            assert!(pair[1].event_time >= pair[0].event_time);
+        }
+
+        // Fills only ever take part of what rests
+        let mut resting = std::collections::HashMap::new();
+        for message in &messages {
+            match message.action {
+                Action::Add | Action::Modify => {
+                    resting.insert(message.order_id, message.size);
+                }
+                Action::Cancel => assert!(resting.remove(&message.order_id).is_some()),
+                Action::Fill => assert!(message.size <= resting[&message.order_id]),
+                _ => {}
+            }
+        }
+
+        let mut processor = crate::orderbook::MboProcessor::new()
+            .with_validation_policy(crate::orderbook::ValidationPolicy::Reject)
+            .with_error_policy(crate::orderbook::ErrorPolicy::FailFast);
+        let summary = processor.process_messages(&messages);
+        assert!(summary.is_success(), "{:?}", summary.failures.first());
+        assert_eq!(summary.processed, 10_000);
+        assert_eq!(processor.stats().warnings(), 0);
+    }
+
+    /// Replays `messages` strictly, checking that every Fill is at the touch and every
+    /// event ends with a two-sided, uncrossed book, and returns the mids.
+    fn replay_realistic(messages: &[MarketByOrderMessage]) -> Vec<f64> {
+        let mut processor = crate::orderbook::MboProcessor::new()
+            .with_validation_policy(crate::orderbook::ValidationPolicy::Reject)
+            .with_error_policy(crate::orderbook::ErrorPolicy::FailFast);
+        let mut mids = Vec::new();
+        for (index, message) in messages.iter().enumerate() {
+            if message.action == Action::Fill {
+                let touch = match message.side {
+                    Side::Bid => processor.best_bid(),
+                    Side::Ask => processor.best_ask(),
+                };
+                assert_eq!(touch.map(|(price, _)| price), Some(message.price));
+            }
+            processor.process_message(message).unwrap();
+            // The first message adds a bid, the second an ask
+            if message.is_last && index >= 1 {
+                let (bid, _) = processor.best_bid().expect("a bid rests");
+                let (ask, _) = processor.best_ask().expect("an ask rests");
+                assert!(bid < ask, "crossed at message {index}: {bid} >= {ask}");
+                mids.push(processor.order_book().mid().unwrap());
+            }
+        }
+        assert_eq!(processor.stats().warnings(), 0);
+        let counts = processor.stats().action_counts;
+        assert!(counts.fill > 100 && counts.cancel > 1_000 && counts.modify > 100);
+        mids
+    }
+
+    #[test]
+    fn test_realistic_messages_stay_two_sided_and_uncrossed() {
+        let model = MarketModel {
+            volatility: 0.2,
+            ..MarketModel::default()
+        };
+        let messages = OrderGenerator::default_seeded(11).make_realistic_messages(50_000, &model);
+        assert_eq!(messages.len(), 50_000);
+        assert_eq!(
+            messages,
+            OrderGenerator::default_seeded(11).make_realistic_messages(50_000, &model)
+        );
+        for pair in messages.windows(2) {
+            assert_eq!(pair[1].sequence, pair[0].sequence + 1);
+        }
+
+        let mids = replay_realistic(&messages);
+        // The walk drifts, unlike the static distribution of `next_order`
+        let low = mids.iter().copied().fold(f64::INFINITY, f64::min);
+        let high = mids.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        assert!(high - low > 10.0, "mid stayed within {low}..{high}");
+    }
+
+    #[test]
+    fn test_mean_reverting_mid_stays_near_start() {
+        let model = MarketModel {
+            mid_process: MidProcess::OrnsteinUhlenbeck { reversion: 0.01 },
+            volatility: 0.5,
+            ..MarketModel::default()
+        };
+        let messages = OrderGenerator::default_seeded(3).make_realistic_messages(50_000, &model);
+        let mids = replay_realistic(&messages);
+        assert!(
+            mids.iter()
+                .all(|mid| (mid - model.initial_mid).abs() < 30.0)
+        );
+    }
+
     #[test]
     fn test_no_crossed_book() {
         let mut generator = OrderGenerator::default_seeded(123);
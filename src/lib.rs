@@ -1,9 +1,54 @@
+#[cfg(feature = "ffi")]
+pub mod ffi;
 pub mod generators;
 pub mod orderbook;
+#[cfg(feature = "python")]
+pub mod python;
 
+#[cfg(feature = "async")]
+pub use orderbook::BboUpdate;
+#[cfg(feature = "parallel")]
+pub use orderbook::ParallelError;
+#[cfg(feature = "databento")]
+pub use orderbook::{API_KEY_ENV, FetchError, FetchRequest};
 pub use orderbook::{
-    Action, AddOrderInfo, MarketByOrderMessage, MarketByPrice, MboObserver, MboProcessError,
-    MboProcessor, ModifyOrderInfo, Order, OrderAddedEvent, OrderBook, OrderBookError,
-    OrderCancelledEvent, OrderLevelSummary, OrderModifiedEvent, RemoveOrderInfo, Side,
-    TradeCollector, TradeEvent,
+    Action, ActionCounts, AddOrderInfo, Anomalies, AnomalyKind, AnomalyRecord, Bar, BarBuilder,
+    BarError, Bbo, BboHistory, BboRecorder, BboSample, BookChange, BookEvent, BookHandler, BookKey,
+    BytesRead, CharEncoding, CheckpointError, Compression, ConsistencyError, ConversionError,
+    ConversionReport, CountingReader, CrossingPolicy, CsvOptions, CsvReadError, CsvRows,
+    DEFAULT_PROGRESS_INTERVAL, DEPTH_BUCKETS, DataFormat, DbnStreamError, DbnWriteError, DbnWriter,
+    DenseBookError, DenseOrderBook, DepthBucketSummary, DepthJsonError, DepthLimitPolicy,
+    DepthStats, DepthSummary, DepthTracker, ErrorPolicy, EventRetention, Execution,
+    ExecutionReport, FlowStats, FormatError, IcebergDetector, IcebergSignal, IdRemapper,
+    IncrementalMbp, InputError, InputFormat, InputSource, IntervalError, L2Action, L2Batch,
+    L2Publisher, L2Sink, L2Update, MAX_DENSE_LEVELS, MAX_PLAYBACK_SPEED, MIN_PLAYBACK_SPEED,
+    MarketByOrderMessage, MarketByPrice, MatchOutcome, MboMessages, MboObserver, MboProcessError,
+    MboProcessor, MboStats, Mbp1Row, Mbp1Tracker, ModifyOrderInfo, ModifyPriorityPolicy,
+    NdjsonLines, NewOrder, Order, OrderAddedEvent, OrderBook, OrderBookError, OrderCancelledEvent,
+    OrderLevelSummary, OrderModifiedEvent, ParseNameError, ParticipantSide, ParticipantStats,
+    PassiveFillSimulator, PassiveOrder, PassiveOrderState, PipelineError, PipelineOptions,
+    Playback, PlaybackCommand, PlaybackStep, PriceScale, PriceScaleError, ProcessEvent,
+    ProcessFailure, ProcessSummary, ProgressSink, QueueModel, QuoteStats, QuoteSummary,
+    ReaderProcessSummary, RecordSourceError, RemoveOrderInfo, ReplayError, Replayer, RollbackError,
+    SYNTHETIC_ORDER_ID_MIN, SeedMode, SelfMatchPolicy, SelfMatchReport, Side, SnapshotInterval,
+    SpreadSummary, SymbolMap, SymbolMapError, TimeInForce, TimeRange, TimeRangeError,
+    TopOfBookError, TopOfBookRecorder, TopOfBookWriter, TradeClass, TradeCollector, TradeEvent,
+    TradeQuality, TradeQualityCounts, TradeRecord, ValidationError, ValidationPolicy, WindowCounts,
+    WithProgress, create_output, decompress, depth_levels, detect_format, expand_paths,
+    filter_time_range, first_event_time, format_from_suffix, is_synthetic_order_id,
+    iter_mbo_csv_from, iter_mbo_ndjson_from, mbo_messages, mbo_metadata, open_input, parse_time_ns,
+    process_reader, process_reader_with_progress, read_mbo_csv, read_mbo_csv_from, read_mbo_ndjson,
+    read_mbo_ndjson_from, resolve_format, sniff_compression, sort_by_first_event, validate,
+    write_dbn, write_mbo_csv, write_mbo_ndjson,
 };
+#[cfg(feature = "server")]
+pub use orderbook::{BookFeed, BookServer, ServerOptions};
+#[cfg(feature = "polars")]
+pub use orderbook::{
+    ColumnMapping, ColumnMappingError, MboRows, MbpFrameOptions, ParquetBatches,
+    ParquetProcessSummary, SnapshotWriter, TimeSampler, filter_instrument, into_mbo_messages,
+    into_mbo_messages_with, iter_mbo_messages, iter_mbo_messages_with, mbo_messages_to_dataframe,
+    process_parquet_streaming,
+};
+#[cfg(feature = "itch")]
+pub use orderbook::{ItchError, ItchMessages, itch_messages};
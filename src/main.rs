@@ -1,14 +1,174 @@
+use std::cell::RefCell;
 use std::error::Error;
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
 
-use clap::Parser;
+use clap::{ArgAction, ArgGroup, Args, Parser, Subcommand, ValueEnum};
 use dbn::{
-    MboMsg,
-    decode::{DecodeRecord, DynReader, dbn::Decoder},
+    Mbp1Msg, Mbp10Msg, Metadata, Schema,
+    decode::{DbnMetadata, DecodeRecord, DynReader, dbn::Decoder},
 };
-use tracing::{debug, info};
+use glob::Pattern;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use serde_json::{Value, json};
+use time::OffsetDateTime;
+use tracing::{Level, debug, info, warn};
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::filter::LevelFilter;
 
-use rainybook::{MarketByOrderMessage, MboProcessor};
+use rainybook::generators::OrderGenerator;
+#[cfg(feature = "itch")]
+use rainybook::itch_messages;
+use rainybook::{
+    Action, BboRecorder, CharEncoding, Compression, ConversionError, CountingReader, CsvOptions,
+    DEFAULT_PROGRESS_INTERVAL, DataFormat, DbnStreamError, DbnWriter, DepthSummary, DepthTracker,
+    ErrorPolicy, EventRetention, IdRemapper, InputError, InputFormat, InputSource,
+    MarketByOrderMessage, MarketByPrice, MboObserver, MboProcessError, MboProcessor,
+    PipelineOptions, PriceScale, ProcessSummary, ProgressSink, QuoteSummary, Replayer, SeedMode,
+    Side, SnapshotInterval, SymbolMap, TimeRange, TimeRangeError, TopOfBookRecorder,
+    TopOfBookWriter, TradeCollector, WindowCounts, WithProgress, create_output, depth_levels,
+    expand_paths, filter_time_range, first_event_time, format_from_suffix, iter_mbo_csv_from,
+    iter_mbo_ndjson_from, mbo_messages, mbo_metadata, open_input, parse_time_ns, resolve_format,
+    write_mbo_csv, write_mbo_ndjson,
+};
+#[cfg(feature = "server")]
+use rainybook::{BookServer, ServerOptions};
+#[cfg(feature = "polars")]
+use rainybook::{ColumnMapping, ParquetBatches, SnapshotWriter, mbo_messages_to_dataframe};
+#[cfg(feature = "databento")]
+use rainybook::{FetchError, FetchRequest};
+
+#[cfg(feature = "tui")]
+mod view;
+
+/// Rows of --tob-out output buffered before they are appended to the file.
+const TOB_CHUNK_ROWS: usize = 65_536;
+
+/// Messages between progress lines when the size of the input is unknown.
+const PROGRESS_LINE_MESSAGES: u64 = 1_000_000;
+
+/// A failure of the command, with the exit status it ends the process with:
+///
+/// | Status | Failure                                                     |
+/// |--------|-------------------------------------------------------------|
+/// | 1      | anything else, e.g. invalid options or unwritable output    |
+/// | 2      | invalid arguments, reported by clap                         |
+/// | 3      | an input cannot be opened                                   |
+/// | 4      | an input is not in its format, or is corrupt                |
+/// | 5      | a record cannot be converted to an MBO message              |
+/// | 6      | a message failed to apply and processing stopped            |
+/// | 7      | processing finished, but with failed messages under collect |
+#[derive(Debug, thiserror::Error)]
+enum CliError {
+    #[error("Cannot open {input}: {source}")]
+    Open {
+        input: String,
+        source: Box<dyn Error>,
+    },
+
+    #[error("Cannot read {input}: {source}")]
+    Decode {
+        input: String,
+        source: Box<dyn Error>,
+    },
+
+    #[error("Cannot decode record {record} of {input}: {source}")]
+    DecodeRecord {
+        input: String,
+        record: usize,
+        source: dbn::Error,
+    },
+
+    #[error("Cannot convert record {record} of {input}: {source}")]
+    Convert {
+        input: String,
+        record: usize,
+        source: MboProcessError,
+    },
+
+    #[error("Message #{index} failed to process: {source}")]
+    Process {
+        index: usize,
+        source: MboProcessError,
+    },
+
+    #[error("Completed with {failed} failed messages, the first was message #{index}: {source}")]
+    CompletedWithErrors {
+        failed: usize,
+        index: usize,
+        source: MboProcessError,
+    },
+
+    #[error("The books diverge from message #{index}")]
+    Diverged { index: u64 },
+
+    #[cfg(feature = "databento")]
+    #[error(transparent)]
+    Fetch(FetchError),
+
+    #[error(transparent)]
+    Other(Box<dyn Error>),
+}
+
+impl CliError {
+    fn from_dbn_stream(input: &str, error: DbnStreamError) -> Self {
+        let input = input.to_owned();
+        match error {
+            DbnStreamError::Decode { index, source } => Self::DecodeRecord {
+                input,
+                record: index,
+                source,
+            },
+            DbnStreamError::Conversion { index, source } => Self::Convert {
+                input,
+                record: index,
+                source,
+            },
+        }
+    }
+
+    fn exit_code(&self) -> u8 {
+        match self {
+            Self::Other(_) => 1,
+            Self::Open { .. } => 3,
+            #[cfg(feature = "databento")]
+            Self::Fetch(FetchError::Request(_)) => 3,
+            #[cfg(feature = "databento")]
+            Self::Fetch(FetchError::Stream(_)) => 4,
+            Self::Decode { .. } | Self::DecodeRecord { .. } => 4,
+            Self::Convert { .. } => 5,
+            Self::Process { .. } => 6,
+            Self::CompletedWithErrors { .. } => 7,
+            Self::Diverged { .. } => 8,
+        }
+    }
+}
+
+/// Keeps a `CliError` passed through a boxed error; anything else is `Other`.
+impl From<Box<dyn Error>> for CliError {
+    fn from(error: Box<dyn Error>) -> Self {
+        match error.downcast::<CliError>() {
+            Ok(error) => *error,
+            Err(error) => Self::Other(error),
+        }
+    }
+}
+
+impl From<String> for CliError {
+    fn from(message: String) -> Self {
+        Self::Other(message.into())
+    }
+}
+
+impl From<&str> for CliError {
+    fn from(message: &str) -> Self {
+        Self::Other(message.into())
+    }
+}
 
 #[derive(Parser)]
 #[command(name = "rainybook")]
@@ -16,43 +176,2322 @@ use rainybook::{MarketByOrderMessage, MboProcessor};
 #[command(
     long_about = "Process market data and maintain an in-memory orderbook.\n\n\
     Supported data formats:\n  \
-    - Databento Binary Encoding (DBN): .dbn, .dbn.zst"
+    - Databento Binary Encoding (DBN): .dbn, .dbn.zst\n  \
+    - CSV with MBO columns: .csv, .csv.gz\n  \
+    - Newline-delimited JSON, one MBO object per line: .ndjson, .jsonl\n  \
+    - Parquet with MBO columns (requires the polars feature): .parquet\n  \
+    - NASDAQ TotalView-ITCH 5.0 (requires the itch feature): .itch, .nq\n\n\
+    Files without a recognized suffix are identified by their magic bytes.\n\n\
+    Exit status:\n  \
+    0  success\n  \
+    1  any other failure, such as invalid options or an unwritable output\n  \
+    2  invalid arguments\n  \
+    3  an input cannot be opened\n  \
+    4  an input is not in its format, or is corrupt\n  \
+    5  a record cannot be converted to an MBO message\n  \
+    6  a message failed to apply, which stopped processing\n  \
+    7  processing finished, but with failed messages (--on-error collect)\n  \
+    8  compare found the two inputs' books differ"
 )]
 struct Cli {
-    /// Path to the market data file
-    #[arg(short, long, value_name = "FILE")]
-    #[arg(help = "Input data file (supports .dbn, .dbn.zst formats)")]
+    #[command(subcommand)]
+    command: Command,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Replay the input through the order books and print summary stats and the top
+    /// of every book
+    Process(Box<ProcessArgs>),
+    /// Replay the input and print the market-by-price book of every instrument at the
+    /// end, or at --as-of
+    Snapshot(SnapshotArgs),
+    /// Replay the input and print action counts, message rates and anomaly counts
+    Stats(StatsArgs),
+    /// Replay two inputs independently and report whether, and from which message,
+    /// their books differ
+    Compare(CompareArgs),
+    /// Write the input's MBO messages, after filtering, as DBN, CSV, NDJSON or parquet
+    Convert(ConvertArgs),
+    /// Time how many messages per second the input, or synthetic messages, replay at
+    Bench(BenchArgs),
+    /// Replay the input in an interactive terminal view of one book at a time, with
+    /// pause, step, speed and jump controls
+    #[cfg(feature = "tui")]
+    View(ViewArgs),
+    /// Replay the input to WebSocket clients: a snapshot of every book on connect,
+    /// then L2 deltas or periodic snapshots
+    #[cfg(feature = "server")]
+    Serve(ServeArgs),
+    /// Request a time range of MBO data from the Databento historical API, with the API
+    /// key in DATABENTO_API_KEY, and process it as it arrives
+    #[cfg(feature = "databento")]
+    Fetch(FetchArgs),
+}
+
+/// Logging options, accepted before or after the subcommand.
+#[derive(Args)]
+struct LogArgs {
+    /// Log progress to standard error: -v for info, -vv for debug, -vvv for trace.
+    /// Only warnings and errors are logged by default
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Format of the log output
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        default_value_t = LogFormat::Pretty,
+        global = true
+    )]
+    log_format: LogFormat,
+
+    /// Log filter directives on top of the --verbose level, e.g.
+    /// 'rainybook::orderbook=debug'
+    #[arg(long, value_name = "FILTER", global = true)]
+    log_filter: Option<String>,
+}
+
+/// File format of the tables written by `process`, which have the same schema in
+/// either.
+#[cfg(feature = "polars")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum TableFormat {
+    Parquet,
+    /// Arrow IPC (Feather v2)
+    Ipc,
+}
+
+#[cfg(feature = "polars")]
+impl TableFormat {
+    /// `format` if given, otherwise the one the extension of `path` names.
+    fn of(path: &Path, format: Option<Self>) -> Self {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        match (format, extension.as_deref()) {
+            (Some(format), _) => format,
+            (None, Some("arrow" | "feather" | "ipc")) => TableFormat::Ipc,
+            (None, _) => TableFormat::Parquet,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LogFormat {
+    /// Human-readable lines
+    Pretty,
+    /// One JSON object per event
+    Json,
+}
+
+/// The market data to read and the filters applied to its messages, shared by every
+/// subcommand.
+#[derive(Args, Clone)]
+struct InputArgs {
+    /// Path to a market data file or a directory of them
+    #[arg(short, long, value_name = "PATH")]
+    #[arg(
+        help = "Input data file or directory (supports .dbn, .dbn.zst, .csv, .csv.gz, .ndjson, .jsonl, .parquet, .itch and .nq formats). Repeat to process several files as one session, in order of their first event"
+    )]
     #[arg(value_parser = clap::value_parser!(PathBuf))]
-    data_path: PathBuf,
+    data_path: Vec<PathBuf>,
+
+    /// Only take the files in --data-path directories whose names match this pattern,
+    /// e.g. '*.dbn.zst'
+    #[arg(long, value_name = "PATTERN")]
+    glob: Option<Pattern>,
+
+    /// Process the files in the order given, with directories in name order, instead
+    /// of by the event time of their first message
+    #[arg(long)]
+    no_sort: bool,
+
+    /// Read the market data from standard input instead of a file, in the format of
+    /// --format
+    #[arg(long, conflicts_with = "data_path", requires = "format")]
+    stdin: bool,
+
+    /// Read the input as this format instead of detecting it from file names and
+    /// contents: dbn, csv, ndjson, parquet or itch, optionally with its compression,
+    /// e.g. dbn-zst or csv-gz. Without one, gzip and zstd are detected from the data
+    #[arg(long, value_name = "FORMAT")]
+    format: Option<InputFormat>,
+
+    /// Only process the messages of this instrument. DBN records and parquet rows of
+    /// other instruments are skipped before conversion, and parquet input without an
+    /// instrument_id column is an error; CSV and NDJSON rows without one are instrument 0
+    #[arg(long, value_name = "ID")]
+    instrument_id: Option<u32>,
+
+    /// Stop after the last message with event timestamp at or before this time
+    /// (nanoseconds since the UNIX epoch) and report the book as of then
+    #[arg(long, value_name = "NS")]
+    as_of: Option<u64>,
+
+    /// Skip messages with event timestamp before this time, given as nanoseconds since
+    /// the UNIX epoch or as an RFC 3339 timestamp such as 2024-03-01T14:30:00Z
+    #[arg(long, value_name = "TIME", value_parser = parse_time_ns)]
+    start: Option<u64>,
+
+    /// Skip messages with event timestamp at or after this time, given like --start
+    #[arg(long, value_name = "TIME", value_parser = parse_time_ns)]
+    end: Option<u64>,
+
+    /// Only process instruments mapped to this symbol, e.g. ES.FUT or ESZ4, by the
+    /// symbology in the DBN metadata. Repeat for several symbols; the summary is given
+    /// per symbol
+    #[arg(long = "symbol", value_name = "SYMBOL")]
+    symbols: Vec<String>,
+
+    /// Rows of parquet input read and converted at a time
+    #[cfg(feature = "polars")]
+    #[arg(long, value_name = "ROWS", default_value_t = 100_000)]
+    batch_size: usize,
 
-    /// Enable verbose output
+    /// Columns of parquet input holding the MBO fields, for files that name them
+    /// differently, e.g. action=act,side=bs,price=px,order_id=oid,size=qty
+    #[cfg(feature = "polars")]
+    #[arg(long, value_name = "FIELD=COLUMN,...")]
+    col_map: Option<ColumnMapping>,
+
+    /// Field delimiter of CSV input
+    #[arg(long, value_name = "CHAR", default_value_t = ',')]
+    csv_delimiter: char,
+
+    /// CSV input has no header row; columns are read in the order action, side,
+    /// price, order_id, size and optionally ts_event
+    #[arg(long)]
+    csv_no_header: bool,
+
+    /// CSV input encodes action and side as character codes (65) rather than
+    /// characters (A)
+    #[arg(long)]
+    csv_numeric_chars: bool,
+
+    /// Show prices in units of this tick size, e.g. 1e-9 or 0.01, or of this divisor,
+    /// e.g. 100, or as raw integers with 'raw'. DBN and ITCH prices are in units of 1e-9
+    /// and shown so by default; the prices of other inputs are shown as they are
+    #[arg(long, value_name = "SCALE")]
+    price_scale: Option<PriceScale>,
+
+    /// Do not report progress through the input on standard error, nor print the
+    /// ladders of --print-every
     #[arg(short, long)]
-    verbose: bool,
+    quiet: bool,
+}
+
+impl InputArgs {
+    fn time_range(&self) -> Result<TimeRange, TimeRangeError> {
+        TimeRange::new(self.start, self.end)
+    }
+
+    /// The scale prices are shown at: --price-scale, or the scale every input's format
+    /// fixes, or raw integers if they fix none or differ.
+    fn price_scale(&self, inputs: &Inputs) -> PriceScale {
+        if let Some(price_scale) = self.price_scale {
+            return price_scale;
+        }
+        let mut scales = inputs.iter().map(|(_, input)| input.format.price_scale());
+        let first = scales.next().flatten();
+        match scales.all(|scale| scale == first) {
+            true => first.unwrap_or_default(),
+            false => PriceScale::RAW,
+        }
+    }
+}
+
+/// How the order books are built before the input is applied.
+#[derive(Args)]
+struct BookArgs {
+    /// Apply the messages before --start to build the book, without any output for them,
+    /// so the window starts from the right book rather than an empty one
+    #[arg(long, alias = "book-warmup", requires = "start")]
+    warmup: bool,
+
+    /// Seed the book from the first record of this DBN MBP-1 or MBP-10 file before
+    /// processing, for MBO data that starts mid-session
+    #[arg(long, value_name = "FILE")]
+    seed_depth: Option<PathBuf>,
+
+    /// Seed each level with as many orders as its order count instead of one
+    /// aggregate order
+    #[arg(long, requires = "seed_depth")]
+    seed_per_order: bool,
+
+    /// What to do with a message that fails to apply. Either way the run ends with a
+    /// non-zero exit status if any failed
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = OnError::FailFast)]
+    on_error: OnError,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OnError {
+    /// Stop at the first failing message
+    FailFast,
+    /// Skip failing messages, count them in the summary and carry on
+    Collect,
+}
+
+impl From<OnError> for ErrorPolicy {
+    fn from(on_error: OnError) -> Self {
+        match on_error {
+            OnError::FailFast => ErrorPolicy::FailFast,
+            OnError::Collect => ErrorPolicy::Collect,
+        }
+    }
+}
+
+#[derive(Args)]
+#[command(group(ArgGroup::new("outputs").multiple(true)))]
+struct ProcessArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    #[command(flatten)]
+    book: BookArgs,
+
+    /// Pace messages by their event timestamps: 1.0 replays in real time, 2.0 twice
+    /// as fast, 0 as fast as possible
+    #[arg(long, value_name = "FACTOR", default_value_t = 0.0)]
+    replay_speed: f64,
+
+    /// Also print the best N price levels per side of every book
+    #[arg(long, value_name = "LEVELS")]
+    depth: Option<usize>,
+
+    /// Print the end-of-run summary as one JSON object
+    #[arg(long)]
+    json: bool,
+
+    /// Print the best --print-depth levels of every book during the replay, after every
+    /// N messages or every S seconds of event time given as e.g. 5s. With --replay-speed
+    /// this is a simple live view. --quiet turns it off
+    #[arg(long, value_name = "N|Ss")]
+    print_every: Option<SnapshotInterval>,
+
+    /// Price levels shown per side by --print-every
+    #[arg(
+        long,
+        value_name = "LEVELS",
+        default_value_t = 5,
+        requires = "print_every"
+    )]
+    print_depth: usize,
+
+    /// Write every top-of-book change to this parquet or Arrow IPC file
+    #[cfg(feature = "polars")]
+    #[arg(long, value_name = "FILE", group = "outputs")]
+    bbo_out: Option<PathBuf>,
+
+    /// Write every Trade and Fill to this parquet or Arrow IPC file
+    #[cfg(feature = "polars")]
+    #[arg(long, value_name = "FILE", group = "outputs")]
+    trades_out: Option<PathBuf>,
+
+    /// Write every anomaly (duplicate add, unknown cancel or modify, missing level,
+    /// order dropped by the depth limit) with the message that caused it to this parquet
+    /// or Arrow IPC file
+    #[cfg(feature = "polars")]
+    #[arg(long, value_name = "FILE", group = "outputs")]
+    anomalies_out: Option<PathBuf>,
+
+    /// Write the top of book after every message to this .csv, .parquet or Arrow IPC
+    /// (.arrow, .feather, .ipc) file (parquet and IPC require the polars feature)
+    #[arg(long, value_name = "FILE", group = "outputs")]
+    tob_out: Option<PathBuf>,
+
+    /// Only write a top-of-book row when the best bid or ask changed, turning
+    /// --tob-out into an MBP-1 feed
+    #[arg(long, requires = "tob_out")]
+    tob_dedup: bool,
+
+    /// Write market-by-price snapshots of every book to this parquet or Arrow IPC file:
+    /// one after each --snapshot-every messages, or only the final books without it.
+    /// IPC snapshots are written as they are taken rather than at the end
+    #[cfg(feature = "polars")]
+    #[arg(
+        short,
+        long,
+        value_name = "FILE",
+        alias = "snapshot-out",
+        group = "outputs"
+    )]
+    output: Option<PathBuf>,
+
+    /// Format of --output, --bbo-out, --trades-out and --anomalies-out. By default .arrow, .feather and
+    /// .ipc files are written as Arrow IPC and any other as parquet
+    #[cfg(feature = "polars")]
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    output_format: Option<TableFormat>,
+
+    /// Snapshot every book after each N messages
+    #[cfg(feature = "polars")]
+    #[arg(long, value_name = "N", requires = "output")]
+    snapshot_every: Option<usize>,
+
+    /// Keep at most this many price levels per side in each snapshot
+    #[cfg(feature = "polars")]
+    #[arg(long, value_name = "LEVELS", requires = "output")]
+    snapshot_depth: Option<usize>,
+
+    /// Replace the output files (--tob-out, --bbo-out, --trades-out, --anomalies-out,
+    /// --output) if they already exist
+    #[arg(long, requires = "outputs")]
+    force: bool,
+
+    /// Decode the input on one thread while another applies it to the books. Not with
+    /// --print-every, --snapshot-every or --threads, which process the messages their
+    /// own way
+    #[arg(long, conflicts_with = "print_every")]
+    #[cfg_attr(feature = "polars", arg(conflicts_with = "snapshot_every"))]
+    #[cfg_attr(feature = "parallel", arg(conflicts_with = "threads"))]
+    pipeline: bool,
+
+    /// Messages handed from decoding to processing at a time with --pipeline
+    #[arg(long, value_name = "N", requires = "pipeline", value_parser = parse_count)]
+    #[arg(default_value_t = PipelineOptions::default().batch_size())]
+    pipeline_batch: usize,
+
+    /// Batches decoded ahead of processing with --pipeline
+    #[arg(long, value_name = "BATCHES", requires = "pipeline")]
+    #[arg(default_value_t = PipelineOptions::default().channel_depth())]
+    pipeline_depth: usize,
+
+    /// Apply the messages on N threads with the instruments spread across them. Output
+    /// written during the replay (--tob-out, --bbo-out, --trades-out, --snapshot-every,
+    /// --print-every) needs a single thread
+    #[cfg(feature = "parallel")]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    threads: Option<u32>,
+}
+
+#[derive(Args)]
+struct SnapshotArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    #[command(flatten)]
+    book: BookArgs,
+
+    /// Price levels shown per side
+    #[arg(long, value_name = "LEVELS", default_value_t = 10)]
+    depth: usize,
+
+    /// Print one JSON object of bids and asks per instrument instead of a price ladder
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct StatsArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    #[command(flatten)]
+    book: BookArgs,
+}
+
+#[derive(Args)]
+struct CompareArgs {
+    /// The first input: a market data file or a directory of them
+    #[arg(long, value_name = "PATH")]
+    a: PathBuf,
+
+    /// The input compared with --a
+    #[arg(long, value_name = "PATH")]
+    b: PathBuf,
+
+    /// Filters and options applied to both inputs
+    #[command(flatten)]
+    input: InputArgs,
+
+    #[command(flatten)]
+    book: BookArgs,
+
+    /// Compare the state hashes of the books after every N messages to find the first
+    /// message after which they differ
+    #[arg(long, value_name = "N", default_value = "1000", value_parser = parse_count)]
+    hash_every: usize,
+
+    /// Print the report as one JSON object
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct ConvertArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    /// File to write, in the format of its suffix: .dbn, .dbn.zst, .csv, .ndjson,
+    /// .jsonl or .parquet (requires the polars feature). DBN output keeps the first DBN
+    /// input's metadata, or generates an MBO header
+    #[arg(short, long, value_name = "FILE")]
+    output: PathBuf,
+
+    /// Replace order ids with new ones, 1, 2, 3, ... in order of first sight, so the
+    /// output can be shared without the venue's ids
+    #[arg(long)]
+    remap_ids: bool,
+
+    /// Draw the new order ids at random from this seed instead of sequentially
+    #[arg(long, value_name = "SEED", requires = "remap_ids")]
+    remap_seed: Option<u64>,
+
+    /// Replace --output if it already exists
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Args)]
+struct BenchArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    /// Times to replay the messages
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    #[arg(value_parser = clap::value_parser!(u32).range(1..))]
+    iterations: u32,
+
+    /// Replay this many messages from the order generator instead of an input, e.g.
+    /// 10_000_000
+    #[arg(long, value_name = "MESSAGES", value_parser = parse_count)]
+    #[arg(conflicts_with_all = ["data_path", "stdin"])]
+    synthetic: Option<usize>,
+
+    /// Seed of the order generator for --synthetic
+    #[arg(long, default_value_t = 42, requires = "synthetic")]
+    seed: u64,
+
+    /// Print the results as one JSON object
+    #[arg(long)]
+    json: bool,
+}
+
+#[cfg(feature = "tui")]
+#[derive(Args)]
+struct ViewArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    /// Price levels shown per side
+    #[arg(long, value_name = "LEVELS", default_value_t = 10)]
+    depth: usize,
+
+    /// Speed to start at: 1.0 replays in real time, 2.0 twice as fast, 0 as fast as
+    /// possible. + and - change it while viewing
+    #[arg(long, value_name = "FACTOR", default_value_t = 1.0)]
+    replay_speed: f64,
+}
+
+#[cfg(feature = "server")]
+#[derive(Args)]
+struct ServeArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    #[command(flatten)]
+    book: BookArgs,
+
+    /// Pace messages by their event timestamps: 1.0 replays in real time, 2.0 twice
+    /// as fast, 0 as fast as possible
+    #[arg(long, value_name = "FACTOR", default_value_t = 1.0)]
+    replay_speed: f64,
+
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+
+    /// Port to listen on
+    #[arg(long, default_value_t = 9001)]
+    port: u16,
+
+    /// Price levels per side of the snapshots and deltas
+    #[arg(long, value_name = "LEVELS", default_value_t = 10)]
+    depth: usize,
+
+    /// Send a snapshot of every book after every N messages or every S seconds of event
+    /// time, given as e.g. 5s, instead of deltas
+    #[arg(long, value_name = "N|Ss")]
+    snapshot_every: Option<SnapshotInterval>,
+
+    /// Frames a client may fall behind before it skips to a fresh snapshot
+    #[arg(long, value_name = "FRAMES", default_value_t = 1024)]
+    client_buffer: usize,
+}
+
+#[cfg(feature = "databento")]
+#[derive(Args)]
+struct FetchArgs {
+    /// Dataset to request, e.g. GLBX.MDP3 or XNAS.ITCH
+    #[arg(long)]
+    dataset: String,
+
+    /// Raw symbol to request, e.g. ESZ4. Repeat for several symbols
+    #[arg(long = "symbol", value_name = "SYMBOL", required = true)]
+    symbols: Vec<String>,
+
+    /// Start of the range, inclusive, given as nanoseconds since the UNIX epoch or as
+    /// an RFC 3339 timestamp such as 2024-03-01T14:30:00Z
+    #[arg(long, value_name = "TIME", value_parser = parse_time_ns)]
+    start: u64,
+
+    /// End of the range, exclusive, given like --start
+    #[arg(long, value_name = "TIME", value_parser = parse_time_ns)]
+    end: u64,
+
+    /// What to do with a message that fails to apply. Either way the run ends with a
+    /// non-zero exit status if any failed
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = OnError::FailFast)]
+    on_error: OnError,
+
+    /// Also print the best N price levels per side of every book
+    #[arg(long, value_name = "LEVELS")]
+    depth: Option<usize>,
+
+    /// Print the end-of-run summary as one JSON object
+    #[arg(long)]
+    json: bool,
+}
+
+/// Parses a count that may group its digits with underscores, e.g. 10_000_000.
+fn parse_count(s: &str) -> Result<usize, String> {
+    s.replace('_', "")
+        .parse()
+        .map_err(|_| format!("Invalid count '{s}', expected a number such as 10_000_000"))
+}
+
+/// Logs to standard error at the level of `args.verbose`, with `args.log_filter`
+/// directives applied on top.
+fn init_logging(args: &LogArgs) -> Result<(), Box<dyn Error>> {
+    let level = match args.verbose {
+        0 => Level::WARN,
+        1 => Level::INFO,
+        2 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::from_level(level).into())
+        .parse(args.log_filter.as_deref().unwrap_or_default())?;
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(io::stderr().is_terminal())
+        .with_writer(io::stderr);
+    match args.log_format {
+        LogFormat::Pretty => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+    Ok(())
+}
+
+/// Opens the input file at `path`, or standard input, as `input` with its compression
+/// removed. The bytes read from a file are counted on `bar`, if given.
+fn open_reader(
+    path: Option<&Path>,
+    input: InputFormat,
+    bar: Option<&ProgressBar>,
+) -> Result<Box<dyn BufRead>, InputError> {
+    let reader: Box<dyn BufRead> = match (path, bar) {
+        (Some(path), Some(bar)) => Box::new(BufReader::new(bar.wrap_read(File::open(path)?))),
+        (Some(path), None) => Box::new(BufReader::new(File::open(path)?)),
+        (None, _) => Box::new(io::stdin().lock()),
+    };
+    let (_, reader) = open_input(InputSource::Reader(reader), Some(input))?;
+    Ok(reader)
+}
+
+/// Progress through the input, reported on standard error: a bar over the bytes read
+/// when the size of the input is known, otherwise a line every
+/// `PROGRESS_LINE_MESSAGES` messages. Parquet files are read by polars, so their size
+/// does not count as known.
+struct InputProgress {
+    bar: ProgressBar,
+    lines: bool,
+    started: Instant,
+}
+
+impl InputProgress {
+    fn new(args: &InputArgs, inputs: &Inputs) -> Self {
+        let size = inputs.iter().try_fold(0, |size, (path, input)| match path {
+            Some(path) if input.format != DataFormat::Parquet => {
+                Some(size + fs::metadata(path).ok()?.len())
+            }
+            _ => None,
+        });
+        let bar = match size {
+            Some(size) if !args.quiet => {
+                ProgressBar::with_draw_target(Some(size), ProgressDrawTarget::stderr()).with_style(
+                    ProgressStyle::with_template("{wide_bar} {percent:>3}% {msg}")
+                        .expect("progress template is valid"),
+                )
+            }
+            _ => ProgressBar::hidden(),
+        };
+        Self {
+            bar,
+            lines: size.is_none() && !args.quiet,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl ProgressSink for &InputProgress {
+    fn interval(&self) -> u64 {
+        match self.lines {
+            true => PROGRESS_LINE_MESSAGES,
+            false => DEFAULT_PROGRESS_INTERVAL,
+        }
+    }
+
+    fn progress(&mut self, messages: u64) {
+        let rate = rate(messages, self.started.elapsed()).unwrap_or_default();
+        if self.lines {
+            eprintln!("Processed {messages} messages ({rate:.0} messages/s)");
+        } else {
+            self.bar
+                .set_message(format!("{messages} messages, {rate:.0} messages/s"));
+        }
+    }
+
+    fn finish(&mut self, _messages: u64) {
+        self.bar.finish_and_clear();
+    }
+}
+
+/// The CLI's options for CSV input.
+fn csv_options(args: &InputArgs) -> Result<CsvOptions, Box<dyn Error>> {
+    let delimiter = u8::try_from(args.csv_delimiter).map_err(|_| {
+        format!(
+            "CSV delimiter must be a single-byte character, got {:?}",
+            args.csv_delimiter
+        )
+    })?;
+    let char_encoding = if args.csv_numeric_chars {
+        CharEncoding::Numeric
+    } else {
+        CharEncoding::Character
+    };
+    Ok(CsvOptions::new()
+        .with_delimiter(delimiter)
+        .with_header(!args.csv_no_header)
+        .with_char_encoding(char_encoding))
+}
+
+/// Input files, or standard input, in the order they are processed.
+type Inputs = Vec<(Option<PathBuf>, InputFormat)>;
+
+/// Resolves --data-path into files in processing order with their formats, or
+/// standard input with the compression sniffed from it.
+fn resolve_inputs(args: &InputArgs) -> Result<Inputs, CliError> {
+    if args.stdin {
+        info!("Reading standard input");
+        // Any compression is sniffed once the stream is opened
+        let input = args.format.ok_or("--stdin needs --format")?;
+        return Ok(vec![(None, input)]);
+    }
+    if args.data_path.is_empty() {
+        return Err("No input given: pass --data-path, or --stdin with --format".into());
+    }
+    let paths = expand_paths(&args.data_path, args.glob.as_ref()).map_err(|e| CliError::Open {
+        input: "--data-path".into(),
+        source: e.into(),
+    })?;
+    if paths.is_empty() {
+        return Err("No input files found".into());
+    }
+    info!("Using {} data file(s)", paths.len());
+    // Every file is checked before any is read, so a bad one fails the run early
+    let inputs = paths
+        .into_iter()
+        .map(|path| input_format(&path, args.format).map(|input| (path, input)))
+        .collect::<Result<Vec<_>, _>>()?;
+    if args.no_sort || inputs.len() == 1 {
+        return Ok(inputs
+            .into_iter()
+            .map(|(path, input)| (Some(path), input))
+            .collect());
+    }
+    let csv_options = csv_options(args)?;
+    let mut keyed = inputs
+        .into_iter()
+        .map(|(path, input)| {
+            let first =
+                first_event_time(&path, input, csv_options).map_err(|e| CliError::Decode {
+                    input: path.display().to_string(),
+                    source: e.into(),
+                })?;
+            Ok((first, path, input))
+        })
+        .collect::<Result<Vec<_>, CliError>>()?;
+    keyed.sort_by_key(|(first, ..)| *first);
+    Ok(keyed
+        .into_iter()
+        .map(|(_, path, input)| (Some(path), input))
+        .collect())
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    tracing_subscriber::fmt::init();
+/// The format to read the input file at `path` as, once it is known to open: `format`
+/// if given, otherwise the one detected.
+fn input_format(path: &Path, format: Option<InputFormat>) -> Result<InputFormat, CliError> {
+    let input = path.display().to_string();
+    if let Err(e) = File::open(path) {
+        return Err(CliError::Open {
+            input,
+            source: e.into(),
+        });
+    }
+    resolve_format(path, format).map_err(|e| CliError::Decode {
+        input,
+        source: e.into(),
+    })
+}
+
+/// Messages of one input, or of all inputs chained together.
+type Messages<'a> = Box<dyn Iterator<Item = MarketByOrderMessage> + 'a>;
+
+/// Opens one input as a stream of messages.
+///
+/// DBN and ITCH records and parquet batches are read as the stream is consumed; the
+/// first read failure ends the stream and is stored in `read_error`. DBN symbol mappings
+/// are added to `symbols` when the input is opened, ITCH symbols once its stream ends.
+fn open_messages<'a>(
+    args: &'a InputArgs,
+    path: Option<&'a Path>,
+    input: InputFormat,
+    read_error: &'a RefCell<Option<CliError>>,
+    symbols: &'a RefCell<SymbolMap>,
+    bar: &ProgressBar,
+) -> Result<Messages<'a>, CliError> {
+    let name = match path {
+        Some(path) => path.display().to_string(),
+        None => "standard input".to_string(),
+    };
+    info!("Processing {input} input from {name}");
+    let check_mappings = |has_mappings: bool| match args.symbols.first() {
+        Some(symbol) if !has_mappings => Err(format!(
+            "Cannot filter by symbol {symbol}: the {input} input {name} has no symbol \
+             mappings, select its instruments with --instrument-id instead"
+        )),
+        _ => Ok(()),
+    };
+    check_mappings(input.format == DataFormat::Dbn)?;
+    let open = || {
+        open_reader(path, input, Some(bar)).map_err(|e| CliError::Open {
+            input: name.clone(),
+            source: e.into(),
+        })
+    };
+    let decode_failed = |e: Box<dyn Error>| CliError::Decode {
+        input: name.clone(),
+        source: e,
+    };
+    let fail = move |e: CliError| {
+        read_error.borrow_mut().get_or_insert(e);
+    };
+    let messages: Messages = match input.format {
+        DataFormat::Dbn => {
+            let reader = DynReader::new_inferred(open()?).map_err(|e| decode_failed(e.into()))?;
+            let (reader, bytes_read) = CountingReader::new(reader);
+            let decoder = Decoder::new(reader).map_err(|e| decode_failed(e.into()))?;
+            let mappings = SymbolMap::from_metadata(decoder.metadata())
+                .map_err(|e| decode_failed(e.into()))?;
+            check_mappings(!mappings.is_empty())?;
+            symbols.borrow_mut().merge(mappings);
+            for symbol in &args.symbols {
+                symbols
+                    .borrow()
+                    .resolve(symbol)
+                    .map_err(|e| CliError::Other(e.into()))?;
+            }
+            let name = name.clone();
+            Box::new(
+                mbo_messages(decoder)
+                    .with_instrument_id(args.instrument_id)
+                    .with_bytes_read(bytes_read)
+                    .map_while(move |result| {
+                        result
+                            .map_err(|e| fail(CliError::from_dbn_stream(&name, e)))
+                            .ok()
+                    }),
+            )
+        }
+        // Rows are converted as they are processed, like DBN records
+        DataFormat::Csv => {
+            let rows = iter_mbo_csv_from(open()?, csv_options(args)?)
+                .map_err(|e| decode_failed(e.into()))?;
+            let name = name.clone();
+            Box::new(
+                rows.map_while(move |row| {
+                    row.map_err(|e| {
+                        fail(CliError::Decode {
+                            input: name.clone(),
+                            source: e.into(),
+                        })
+                    })
+                    .ok()
+                })
+                .filter_map(skip_rejected),
+            )
+        }
+        DataFormat::Ndjson => {
+            let name = name.clone();
+            Box::new(
+                iter_mbo_ndjson_from(open()?)
+                    .map_while(move |line| {
+                        line.map_err(|e| {
+                            fail(CliError::Decode {
+                                input: name.clone(),
+                                source: e.into(),
+                            })
+                        })
+                        .ok()
+                    })
+                    .filter_map(skip_rejected),
+            )
+        }
+        // Parquet is read by seeking to the footer, so it needs an uncompressed file
+        #[cfg(feature = "polars")]
+        DataFormat::Parquet => match (path, input.compression) {
+            (Some(path), Compression::None) => {
+                let mut batches = ParquetBatches::open(path, args.batch_size)
+                    .map_err(|e| decode_failed(e.into()))?
+                    .with_column_mapping(args.col_map.clone().unwrap_or_default())
+                    .with_instrument_id(args.instrument_id);
+                let decode_failed = move |e: Box<dyn Error>| CliError::Decode {
+                    input: path.display().to_string(),
+                    source: e,
+                };
+                // Rows are converted as they are processed, one batch in memory at a time
+                Box::new(
+                    iter::from_fn(move || batches.next_rows())
+                        .map_while(move |rows| rows.map_err(|e| fail(decode_failed(e.into()))).ok())
+                        .flatten()
+                        .enumerate()
+                        .filter_map(skip_rejected),
+                )
+            }
+            (Some(_), _) => return Err(format!("{input} input is not supported").into()),
+            (None, _) => {
+                return Err("Parquet input cannot be read from stdin, it needs a file".into());
+            }
+        },
+        #[cfg(feature = "itch")]
+        DataFormat::Itch => {
+            let mut stream = itch_messages(open()?);
+            let name = name.clone();
+            // ITCH symbols arrive in the stream, so they are only known once it ends
+            let messages = iter::from_fn(move || {
+                match stream.next() {
+                    Some(Ok(message)) => return Some(message),
+                    Some(Err(e)) => fail(CliError::Decode {
+                        input: name.clone(),
+                        source: e.into(),
+                    }),
+                    None => {}
+                }
+                symbols.borrow_mut().merge(stream.symbols().clone());
+                None
+            });
+            Box::new(messages.fuse())
+        }
+        #[cfg(not(feature = "polars"))]
+        DataFormat::Parquet => {
+            return Err(format!("{input} input requires the polars feature").into());
+        }
+        #[cfg(not(feature = "itch"))]
+        DataFormat::Itch => return Err(format!("{input} input requires the itch feature").into()),
+    };
+    Ok(match (input.format, args.instrument_id) {
+        (DataFormat::Dbn | DataFormat::Parquet, _) | (_, None) => messages,
+        (_, Some(instrument_id)) => {
+            Box::new(messages.filter(move |message| message.instrument_id == instrument_id))
+        }
+    })
+}
+
+/// Streams the messages of `inputs` into `consume`, filtered by --symbol, --as-of and
+/// the time range, and returns its result with the symbology of the inputs and the
+/// messages counted against the time range.
+///
+/// The inputs are opened one after another as `consume` reads the stream, so a single
+/// pass spans all of them. The first failure to open or read an input ends the stream
+/// and is returned once `consume` is done. With `warm_up` the messages before --start
+/// are kept, for `MboProcessor::warm_up` to apply. Progress through the stream is
+/// reported on standard error unless --quiet.
+fn with_messages<T>(
+    args: &InputArgs,
+    inputs: &Inputs,
+    warm_up: bool,
+    consume: impl FnOnce(Messages<'_>) -> Result<T, Box<dyn Error>>,
+) -> Result<(T, SymbolMap, WindowCounts), Box<dyn Error>> {
+    let progress = InputProgress::new(args, inputs);
+    let read_error: RefCell<Option<CliError>> = RefCell::new(None);
+    let symbols = RefCell::new(SymbolMap::new());
+    let messages = inputs.iter().flat_map(|(path, input)| -> Messages {
+        if read_error.borrow().is_some() {
+            return Box::new(iter::empty());
+        }
+        open_messages(
+            args,
+            path.as_deref(),
+            *input,
+            &read_error,
+            &symbols,
+            &progress.bar,
+        )
+        .unwrap_or_else(|e| {
+            *read_error.borrow_mut() = Some(e);
+            Box::new(iter::empty())
+        })
+    });
+    let messages: Messages = match args.symbols.is_empty() {
+        true => Box::new(messages),
+        false => {
+            info!(
+                "Filtering to instruments mapped to {}",
+                args.symbols.join(", ")
+            );
+            Box::new(messages.filter(|message| {
+                let symbols = symbols.borrow();
+                let date = message.event_time.date();
+                args.symbols
+                    .iter()
+                    .any(|symbol| symbols.matches(symbol, message.instrument_id, date))
+            }))
+        }
+    };
+    let messages = messages.inspect(|message| {
+        debug!(
+            instrument_id = message.instrument_id,
+            order_id = message.order_id,
+            action = %message.action,
+            side = ?message.side,
+            price = message.price,
+            size = message.size,
+            sequence = message.sequence,
+            "Processing MBO message"
+        )
+    });
+    let messages: Messages = match args.as_of {
+        Some(ts_event) => {
+            info!("Replaying up to ts_event {ts_event}");
+            let target = i128::from(ts_event);
+            Box::new(
+                messages
+                    .take_while(move |message| message.event_time.unix_timestamp_nanos() <= target),
+            )
+        }
+        None => Box::new(messages),
+    };
+    let range = args.time_range()?;
+    if range != TimeRange::default() {
+        info!(
+            "Processing events from {:?} to {:?}",
+            range.start_ns, range.end_ns
+        );
+    }
+    let mut window = WindowCounts::default();
+    let messages = messages.inspect(|message| window.record(&range, message));
+    let kept = match warm_up {
+        true => TimeRange {
+            start_ns: None,
+            ..range
+        },
+        false => range,
+    };
+    let mut messages = WithProgress::new(filter_time_range(messages, kept), &progress);
+    let consumed = consume(Box::new(messages.by_ref()));
+    messages.finish();
+    if let Some(e) = read_error.take() {
+        return Err(e.into());
+    }
+    Ok((consumed?, symbols.into_inner(), window))
+}
+
+/// A processor after replaying the input.
+struct Replay<O: MboObserver> {
+    processor: MboProcessor<O>,
+    /// Outcome of the warm-up and the processing after it, with failure indices counted
+    /// from the first message of the warm-up.
+    summary: ProcessSummary,
+    symbols: SymbolMap,
+    /// The symbols of --symbol, which the summary is given per.
+    requested: Vec<String>,
+    /// Messages inside and outside --start and --end, if either is given.
+    window: Option<WindowCounts>,
+    /// Scale the prices of the input are shown at.
+    price_scale: PriceScale,
+}
+
+impl<O: MboObserver> Replay<O> {
+    /// Prints the end-of-run summary: the stats and the time spent, then the best bid
+    /// and ask of every book, labelled with its symbol if known, with its spread, levels
+    /// and resting quantity per side and, given `depth`, its price ladder. With
+    /// --symbol the books are listed under each requested symbol.
+    fn print_summary(&self, elapsed: Duration, depth: Option<usize>) {
+        let stats = self.processor.stats();
+        println!("{stats}");
+        println!("{}", self.processor.flow_stats());
+        println!("Failed messages:    {}", self.summary.failures.len());
+        if let Some(window) = &self.window {
+            println!("Inside window:      {}", window.inside);
+            println!(
+                "Outside window:     {} ({} before start, {} after end)",
+                window.outside(),
+                window.before,
+                window.after
+            );
+        }
+        println!("Elapsed:            {:.3} s", elapsed.as_secs_f64());
+        if let Some(rate) = rate(stats.messages, elapsed) {
+            println!("Processing rate:    {rate:.1} messages/s");
+        }
+        if self.requested.is_empty() {
+            for instrument_id in self.processor.instruments() {
+                self.print_book(instrument_id, depth);
+            }
+        }
+        for symbol in &self.requested {
+            let books = self.symbol_books(symbol);
+            match books.is_empty() {
+                true => println!("Symbol {symbol}: no books"),
+                false => println!("Symbol {symbol}:"),
+            }
+            for instrument_id in books {
+                self.print_book(instrument_id, depth);
+            }
+        }
+    }
+
+    fn print_book(&self, instrument_id: u32, depth: Option<usize>) {
+        if let Some(book) = self.processor.book(instrument_id) {
+            println!(
+                "{}: bid {} | ask {}",
+                self.label(instrument_id),
+                format_level(book.best_bid(), self.price_scale),
+                format_level(book.best_ask(), self.price_scale)
+            );
+            let spread = book
+                .spread()
+                .map(|spread| self.price_scale.to_f64(spread).to_string());
+            println!("  Spread:           {}", spread.as_deref().unwrap_or("-"));
+            println!("  Bid levels:       {}", book.level_count(Side::Bid));
+            println!("  Ask levels:       {}", book.level_count(Side::Ask));
+            println!("  Bid quantity:     {}", book.total_qty(Side::Bid));
+            println!("  Ask quantity:     {}", book.total_qty(Side::Ask));
+            if let Some(depth) = depth {
+                print!(
+                    "{}",
+                    MarketByPrice::from(book).render(depth, self.price_scale)
+                );
+            }
+        }
+    }
+
+    /// The instruments with a book that `symbol` mapped to.
+    fn symbol_books(&self, symbol: &str) -> Vec<u32> {
+        let ids = self.symbols.resolve(symbol).unwrap_or_default();
+        ids.into_iter()
+            .filter(|&id| self.processor.book(id).is_some())
+            .collect()
+    }
+
+    /// The summary `print_summary` prints, as JSON with prices at the price scale. With --symbol
+    /// the instrument ids of each requested symbol are listed under "symbols".
+    fn summary_json(&self, elapsed: Duration, depth: Option<usize>) -> Value {
+        let stats = self.processor.stats();
+        let scale = self.price_scale;
+        let price = |price: i64| match scale.is_raw() {
+            true => json!(price),
+            false => json!(scale.to_f64(price)),
+        };
+        let level = |level: Option<(i64, u64)>| level.map(|(px, qty)| json!([price(px), qty]));
+        let instruments: Vec<Value> = self
+            .processor
+            .instruments()
+            .into_iter()
+            .filter_map(|instrument_id| {
+                let book = self.processor.book(instrument_id)?;
+                let mut summary = json!({
+                    "instrument_id": instrument_id,
+                    "symbol": self.symbols.latest_symbol(instrument_id),
+                    "best_bid": level(book.best_bid()),
+                    "best_ask": level(book.best_ask()),
+                    "spread": book.spread().map(price),
+                    "bid_levels": book.level_count(Side::Bid),
+                    "ask_levels": book.level_count(Side::Ask),
+                    "bid_qty": book.total_qty(Side::Bid),
+                    "ask_qty": book.total_qty(Side::Ask),
+                });
+                if let Some(depth) = depth {
+                    summary["depth"] = MarketByPrice::from(book).to_json_depth(depth, scale);
+                }
+                Some(summary)
+            })
+            .collect();
+        let mut summary = json!({
+            "stats": stats,
+            "flow": self.processor.flow_stats(),
+            "failed_messages": self.summary.failures.len(),
+            "elapsed_s": elapsed.as_secs_f64(),
+            "messages_per_s": rate(stats.messages, elapsed),
+            "instruments": instruments,
+        });
+        if let Some(window) = &self.window {
+            summary["window"] = json!({
+                "inside": window.inside,
+                "outside": window.outside(),
+                "before_start": window.before,
+                "after_end": window.after,
+            });
+        }
+        if !self.requested.is_empty() {
+            let symbols: Vec<Value> = self
+                .requested
+                .iter()
+                .map(
+                    |symbol| json!({"symbol": symbol, "instrument_ids": self.symbol_books(symbol)}),
+                )
+                .collect();
+            summary["symbols"] = symbols.into();
+        }
+        summary
+    }
+
+    fn label(&self, instrument_id: u32) -> String {
+        match self.symbols.latest_symbol(instrument_id) {
+            Some(symbol) => format!("{symbol} ({instrument_id})"),
+            None => format!("Instrument {instrument_id}"),
+        }
+    }
+
+    /// The first message that failed to process, as an error: the one that stopped
+    /// processing under fail-fast, or the first of those skipped under collect.
+    fn check(&self) -> Result<(), CliError> {
+        let Some(failure) = self.summary.first_failure() else {
+            return Ok(());
+        };
+        let (index, source) = (failure.index, failure.error.clone());
+        Err(match self.processor.error_policy() {
+            ErrorPolicy::FailFast => CliError::Process { index, source },
+            ErrorPolicy::Collect => CliError::CompletedWithErrors {
+                failed: self.summary.failures.len(),
+                index,
+                source,
+            },
+        })
+    }
+}
+
+/// Builds the books as `book` asks, then applies `inputs` with `observer` attached,
+/// passing the processor and the stream of messages to `process`.
+///
+/// The observer is attached after any warm-up, so that the warm-up produces no output;
+/// until then the processor holds `O::default()`. A failed warm-up ends processing, as
+/// the first failure does under fail-fast.
+fn replay<O: MboObserver + Default>(
+    input: &InputArgs,
+    inputs: &Inputs,
+    book: &BookArgs,
+    observer: O,
+    quote_stats: bool,
+    anomaly_log: bool,
+    process: impl FnOnce(&mut MboProcessor<O>, Messages<'_>) -> Result<ProcessSummary, Box<dyn Error>>,
+) -> Result<Replay<O>, Box<dyn Error>> {
+    let mut processor = MboProcessor::with_observer(O::default())
+        .with_error_policy(book.on_error.into())
+        .with_quote_stats(quote_stats)
+        .with_anomaly_log(match anomaly_log {
+            true => EventRetention::All,
+            false => EventRetention::Off,
+        });
+    if let Some(path) = &book.seed_depth {
+        let (instrument_id, levels) = read_seed_depth(path)?;
+        let mode = if book.seed_per_order {
+            SeedMode::PerOrder
+        } else {
+            SeedMode::Aggregate
+        };
+        let orders = processor.seed_book(instrument_id, &levels, mode);
+        info!(
+            "Seeded instrument {instrument_id} with {} levels ({orders} orders) from {}",
+            levels.len(),
+            path.display()
+        );
+    }
+
+    let range = input.time_range()?;
+    let (summary, symbols, window) = with_messages(input, inputs, book.warmup, |messages| {
+        let mut messages = messages.peekable();
+        let mut summary = match book.warmup {
+            true => processor.warm_up(&mut messages, range),
+            false => ProcessSummary::default(),
+        };
+        if book.warmup {
+            info!("Warmed up the book with {} messages", summary.processed);
+        }
+        *processor.observer_mut() = observer;
+        let warmed_up = summary.processed as usize + summary.failures.len();
+        let proceed = summary.is_success() || processor.error_policy() == ErrorPolicy::Collect;
+        let processed = process(
+            &mut processor,
+            Box::new(messages.take_while(move |_| proceed)),
+        )?;
+        summary.append(processed, warmed_up);
+        Ok(summary)
+    })?;
+    Ok(Replay {
+        processor,
+        summary,
+        symbols,
+        requested: input.symbols.clone(),
+        window: (range != TimeRange::default()).then_some(window),
+        price_scale: input.price_scale(inputs),
+    })
+}
+
+/// Depth levels of one instrument, as taken by `MboProcessor::seed_book`.
+type SeedLevels = (u32, Vec<(Side, i64, u64, u32)>);
 
+/// Reads the first record of a DBN MBP-1 or MBP-10 file as seed levels.
+fn read_seed_depth(path: &Path) -> Result<SeedLevels, Box<dyn Error>> {
+    let mut decoder = Decoder::new(DynReader::from_file(path)?)?;
+    let seed = match decoder.metadata().schema {
+        Some(Schema::Mbp10) => decoder
+            .decode_record::<Mbp10Msg>()?
+            .map(|record| (record.hd.instrument_id, depth_levels(&record.levels))),
+        Some(Schema::Mbp1) => decoder
+            .decode_record::<Mbp1Msg>()?
+            .map(|record| (record.hd.instrument_id, depth_levels(&record.levels))),
+        schema => {
+            return Err(format!(
+                "Cannot seed from {}: expected an MBP-1 or MBP-10 file, got schema {schema:?}",
+                path.display()
+            )
+            .into());
+        }
+    };
+    seed.ok_or_else(|| {
+        format!(
+            "Cannot seed from {}: the file has no records",
+            path.display()
+        )
+        .into()
+    })
+}
+
+/// Header for DBN output: the metadata of the first input if it is a DBN file, so its
+/// dataset and symbol mappings carry over, otherwise a generated MBO header starting at
+/// the first input's first event.
+fn dbn_output_metadata(args: &InputArgs, inputs: &Inputs) -> Result<Metadata, Box<dyn Error>> {
+    let start = match inputs.first() {
+        Some((Some(path), input)) if input.format == DataFormat::Dbn => {
+            let decoder = Decoder::new(DynReader::new_inferred(open_reader(
+                Some(path),
+                *input,
+                None,
+            )?)?)?;
+            return Ok(decoder.metadata().clone());
+        }
+        Some((Some(path), input)) => first_event_time(path, *input, csv_options(args)?)?,
+        _ => None,
+    };
+    Ok(mbo_metadata(
+        "",
+        start.unwrap_or(OffsetDateTime::UNIX_EPOCH),
+    ))
+}
+
+/// The message of a converted row, or `None` after warning that the row is skipped.
+fn skip_rejected(
+    (row, converted): (usize, Result<MarketByOrderMessage, ConversionError>),
+) -> Option<MarketByOrderMessage> {
+    converted.map_err(|e| warn!("Skipping row {row}: {e}")).ok()
+}
+
+fn main() -> ExitCode {
     let cli = Cli::parse();
-    info!("Using data file: {}", cli.data_path.display());
+    match run(&cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), CliError> {
+    init_logging(&cli.log)?;
+    let result = match &cli.command {
+        Command::Process(args) => process(args),
+        Command::Snapshot(args) => snapshot(args),
+        Command::Stats(args) => stats(args),
+        Command::Compare(args) => compare(args),
+        Command::Convert(args) => convert(args),
+        Command::Bench(args) => bench(args),
+        #[cfg(feature = "tui")]
+        Command::View(args) => view_input(args),
+        #[cfg(feature = "server")]
+        Command::Serve(args) => serve(args),
+        #[cfg(feature = "databento")]
+        Command::Fetch(args) => fetch(args),
+    };
+    Ok(result?)
+}
+
+fn process(args: &ProcessArgs) -> Result<(), Box<dyn Error>> {
+    #[cfg(feature = "polars")]
+    let (bbo_recorder, trade_collector) = (
+        args.bbo_out.as_ref().map(|_| BboRecorder::new()),
+        args.trades_out.as_ref().map(|_| TradeCollector::new()),
+    );
+    #[cfg(not(feature = "polars"))]
+    let (bbo_recorder, trade_collector): (Option<BboRecorder>, Option<TradeCollector>) =
+        (None, None);
+    let outputs = [
+        &args.tob_out,
+        #[cfg(feature = "polars")]
+        &args.bbo_out,
+        #[cfg(feature = "polars")]
+        &args.trades_out,
+        #[cfg(feature = "polars")]
+        &args.anomalies_out,
+        #[cfg(feature = "polars")]
+        &args.output,
+    ];
+    refuse_existing(outputs.into_iter().flatten(), args.force)?;
+    let tob_recorder = match &args.tob_out {
+        Some(path) => Some(
+            TopOfBookRecorder::new()
+                .with_dedup(args.tob_dedup)
+                .with_writer(TopOfBookWriter::create(path)?, TOB_CHUNK_ROWS),
+        ),
+        None => None,
+    };
+    let observers = ((bbo_recorder, trade_collector), tob_recorder);
+    let replayer = Replayer::new(args.replay_speed)?;
+    #[cfg(feature = "polars")]
+    let mut snapshots = match &args.output {
+        Some(path) => {
+            let writer = SnapshotWriter::new()
+                .with_depth(args.snapshot_depth)
+                .with_overwrite(args.force);
+            match TableFormat::of(path, args.output_format) {
+                TableFormat::Parquet => Some(writer),
+                TableFormat::Ipc => Some(
+                    writer
+                        .stream_ipc(path)
+                        .map_err(|e| format!("Cannot create {}: {e}", path.display()))?,
+                ),
+            }
+        }
+        None => None,
+    };
+
+    let print_every = args.print_every.filter(|_| !args.input.quiet);
+    #[cfg(feature = "polars")]
+    if print_every.is_some() && args.snapshot_every.is_some() {
+        return Err("--print-every cannot be combined with --snapshot-every".into());
+    }
+
+    #[cfg(feature = "parallel")]
+    if args.threads.is_some() {
+        let mut single_threaded = vec![("--tob-out", args.tob_out.is_some())];
+        single_threaded.push(("--print-every", print_every.is_some()));
+        #[cfg(feature = "polars")]
+        single_threaded.extend([
+            ("--bbo-out", args.bbo_out.is_some()),
+            ("--trades-out", args.trades_out.is_some()),
+            ("--anomalies-out", args.anomalies_out.is_some()),
+            ("--snapshot-every", args.snapshot_every.is_some()),
+        ]);
+        if let Some((option, _)) = single_threaded.iter().find(|(_, given)| *given) {
+            return Err(format!("--threads cannot be combined with {option}").into());
+        }
+    }
+
+    #[cfg(feature = "polars")]
+    let anomaly_log = args.anomalies_out.is_some();
+    #[cfg(not(feature = "polars"))]
+    let anomaly_log = false;
+
+    let started = Instant::now();
+    let inputs = resolve_inputs(&args.input)?;
+    let price_scale = args.input.price_scale(&inputs);
+    let mut replay = replay(
+        &args.input,
+        &inputs,
+        &args.book,
+        observers,
+        false,
+        anomaly_log,
+        |processor, messages| {
+            let messages = replayer.pace(messages);
+            #[cfg(feature = "parallel")]
+            if let Some(threads) = args.threads {
+                return Ok(processor.process_parallel(messages, threads as usize)?);
+            }
+            #[cfg(feature = "polars")]
+            if let (Some(every), Some(writer)) = (args.snapshot_every, &mut snapshots) {
+                return Ok(processor.process_with_snapshots(messages, every, writer)?);
+            }
+            if let Some(interval) = print_every {
+                let print = |processor: &MboProcessor<_>, index| {
+                    print_ladders(processor, index, args.print_depth, price_scale)
+                };
+                return Ok(processor.process_with_interval(messages, interval, print)?);
+            }
+            if args.pipeline {
+                let options = PipelineOptions::new()
+                    .with_batch_size(args.pipeline_batch)
+                    .with_channel_depth(args.pipeline_depth);
+                return Ok(processor.process_pipelined(messages, options));
+            }
+            Ok(processor.process_messages(messages))
+        },
+    )?;
+    let elapsed = started.elapsed();
+
+    #[cfg(feature = "polars")]
+    if let (Some(path), ((Some(recorder), _), _)) = (&args.bbo_out, replay.processor.observer()) {
+        match TableFormat::of(path, args.output_format) {
+            TableFormat::Parquet => recorder.write_parquet(path)?,
+            TableFormat::Ipc => recorder.write_ipc(path)?,
+        }
+        info!("Wrote {} BBO changes to {}", recorder.len(), path.display());
+    }
+    #[cfg(feature = "polars")]
+    if let (Some(path), ((_, Some(collector)), _)) = (&args.trades_out, replay.processor.observer())
+    {
+        match TableFormat::of(path, args.output_format) {
+            TableFormat::Parquet => collector.write_parquet(path)?,
+            TableFormat::Ipc => collector.write_ipc(path)?,
+        }
+        info!(
+            "Wrote {} trades to {}",
+            collector.trades().len(),
+            path.display()
+        );
+    }
+
+    #[cfg(feature = "polars")]
+    if let Some(path) = &args.anomalies_out {
+        use polars::prelude::{IpcWriter, ParquetWriter, SerWriter};
+
+        let mut df = replay.processor.anomalies_to_dataframe()?;
+        let file = create_output(path, args.force)
+            .map_err(|e| format!("Cannot write anomalies to {}: {e}", path.display()))?;
+        match TableFormat::of(path, args.output_format) {
+            TableFormat::Parquet => {
+                ParquetWriter::new(file).finish(&mut df)?;
+            }
+            TableFormat::Ipc => IpcWriter::new(file).finish(&mut df)?,
+        }
+        info!("Wrote {} anomalies to {}", df.height(), path.display());
+    }
 
-    match cli.data_path.extension() {
-        Some(ext) if ext == "dbn" || ext == "zst" => {
-            info!("Processing Databento Binary Encoding (DBN) file...");
+    if let (Some(path), (_, Some(recorder))) = (&args.tob_out, replay.processor.observer_mut()) {
+        let rows = recorder.finish()?;
+        info!("Wrote {rows} top-of-book rows to {}", path.display());
+    }
+    #[cfg(feature = "polars")]
+    if let (Some(path), Some(writer)) = (&args.output, &mut snapshots) {
+        if args.snapshot_every.is_none() {
+            let messages = replay.summary.processed + replay.summary.failures.len() as u64;
+            writer.record(&replay.processor, messages.saturating_sub(1))?;
         }
-        _ => {
-            return Err("Data file must have extension .dbn or .dbn.zst".into());
+        let rows = match TableFormat::of(path, args.output_format) {
+            TableFormat::Parquet => writer.write_parquet(path),
+            TableFormat::Ipc => writer.finish(),
         }
+        .map_err(|e| format!("Cannot write snapshots to {}: {e}", path.display()))?;
+        info!(
+            "Wrote {} snapshots ({rows} rows) to {}",
+            writer.len(),
+            path.display()
+        );
     }
 
-    let mut decoder = Decoder::new(DynReader::from_file(&cli.data_path)?)?;
-    let mut processor = MboProcessor::new();
+    if args.json {
+        println!("{}", replay.summary_json(elapsed, args.depth));
+    } else {
+        replay.print_summary(elapsed, args.depth);
+    }
+    Ok(replay.check()?)
+}
 
-    while let Some(record) = decoder.decode_record::<MboMsg>()? {
-        let message = MarketByOrderMessage::try_from(record)?;
-        debug!("Processing MBO message: {:?}", debug(&message));
-        processor.process_message(&message)?;
+/// Fails on the first of `paths` that already exists unless `force`, so that no output
+/// is replaced by accident and the check comes before the input is read.
+fn refuse_existing<'a>(
+    paths: impl IntoIterator<Item = &'a PathBuf>,
+    force: bool,
+) -> Result<(), Box<dyn Error>> {
+    match paths.into_iter().find(|path| path.exists()) {
+        Some(path) if !force => Err(format!(
+            "{} already exists, pass --force to overwrite it",
+            path.display()
+        )
+        .into()),
+        _ => Ok(()),
     }
+}
 
+/// Prints the best `depth` levels of every book, headed by the index and event time of
+/// the last message applied.
+fn print_ladders<O: MboObserver>(
+    processor: &MboProcessor<O>,
+    message_index: u64,
+    depth: usize,
+    price_scale: PriceScale,
+) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    writeln!(
+        out,
+        "Message {message_index} at ts_event {}",
+        processor.last_event_time().unix_timestamp_nanos()
+    )?;
+    for instrument_id in processor.instruments() {
+        if let Some(book) = processor.book(instrument_id) {
+            let ladder = MarketByPrice::from_top_n(book, depth).render(depth, price_scale);
+            write!(out, "Instrument {instrument_id}\n{ladder}")?;
+        }
+    }
+    out.flush()
+}
+
+fn snapshot(args: &SnapshotArgs) -> Result<(), Box<dyn Error>> {
+    let inputs = resolve_inputs(&args.input)?;
+    let replay = replay(
+        &args.input,
+        &inputs,
+        &args.book,
+        (),
+        false,
+        false,
+        |processor, messages| Ok(processor.process_messages(messages)),
+    )?;
+    for instrument_id in replay.processor.instruments() {
+        let Some(book) = replay.processor.book(instrument_id) else {
+            continue;
+        };
+        let mbp = MarketByPrice::from(book);
+        if args.json {
+            let mut depth = mbp.to_json_depth(args.depth, replay.price_scale);
+            depth["instrument_id"] = instrument_id.into();
+            if let Some(symbol) = replay.symbols.latest_symbol(instrument_id) {
+                depth["symbol"] = symbol.into();
+            }
+            println!("{depth}");
+        } else {
+            println!("{}", replay.label(instrument_id));
+            print!("{}", mbp.render(args.depth, replay.price_scale));
+        }
+    }
+    Ok(replay.check()?)
+}
+
+fn stats(args: &StatsArgs) -> Result<(), Box<dyn Error>> {
+    let mut first_event = None;
+    let mut last_event = None;
+    let started = Instant::now();
+    let tracker = DepthTracker::new();
+    let inputs = resolve_inputs(&args.input)?;
+    let replay = replay(
+        &args.input,
+        &inputs,
+        &args.book,
+        tracker,
+        true,
+        false,
+        |processor, messages| {
+            let messages = messages.inspect(|message| {
+                first_event.get_or_insert(message.event_time);
+                last_event = Some(message.event_time);
+            });
+            Ok(processor.process_messages(messages))
+        },
+    )?;
+    let elapsed = started.elapsed();
+
+    let stats = replay.processor.stats();
+    println!("{stats}");
+    println!("Failed messages:    {}", replay.summary.failures.len());
+    println!(
+        "Instruments:        {}",
+        replay.processor.instruments().len()
+    );
+    if let (Some(first), Some(last)) = (first_event, last_event) {
+        let span = (last - first).as_seconds_f64();
+        println!("Event time:         {first} to {last} ({span:.3} s)");
+        if span > 0.0 {
+            println!(
+                "Event rate:         {:.1} messages/s",
+                stats.messages as f64 / span
+            );
+        }
+    }
+    if let Some(rate) = rate(stats.messages, elapsed) {
+        println!("Processing rate:    {rate:.1} messages/s");
+    }
+    println!("State hash:         {:016x}", replay.processor.state_hash());
+    if let Some(last) = last_event {
+        for instrument_id in replay.processor.instruments() {
+            if let Some(quotes) = replay.processor.quote_stats(instrument_id) {
+                println!("{} quotes:", replay.label(instrument_id));
+                print_quote_summary(&quotes.summary(last), replay.price_scale);
+            }
+            if let Some(depth) = replay.processor.observer().stats(instrument_id) {
+                println!("{} depth:", replay.label(instrument_id));
+                print_depth_summary(&depth.summary(last));
+            }
+        }
+    }
+    Ok(replay.check()?)
+}
+
+/// Prints the time-weighted quantity of the top levels of one instrument, one line per
+/// side and bucket.
+fn print_depth_summary(summary: &DepthSummary) {
+    let sides = [("Bid", &summary.bids), ("Ask", &summary.asks)];
+    for (side, buckets) in sides {
+        for bucket in buckets {
+            let label = format!("{side} top {}:", bucket.levels);
+            match (bucket.avg_qty, bucket.peak, bucket.trough) {
+                (Some(avg), Some(peak), Some(trough)) => {
+                    println!("  {label:<18}avg {avg:.1}, peak {peak}, trough {trough}")
+                }
+                _ => println!("  {label:<18}-"),
+            }
+        }
+    }
+}
+
+/// Prints the time-weighted BBO statistics of one instrument, prices at `price_scale`.
+fn print_quote_summary(summary: &QuoteSummary, price_scale: PriceScale) {
+    let units = |price: Option<f64>| match price {
+        Some(price) => (price / price_scale.divisor() as f64).to_string(),
+        None => "-".to_string(),
+    };
+    let pct = |pct: Option<f64>| pct.map_or("-".to_string(), |pct| format!("{pct:.2}%"));
+    println!("  BBO updates:      {}", summary.updates);
+    println!(
+        "  Observed:         {:.3} s",
+        summary.observed.as_seconds_f64()
+    );
+    println!("  Avg spread:       {}", units(summary.avg_spread));
+    println!("  Avg mid:          {}", units(summary.avg_mid));
+    println!("  Locked/crossed:   {}", pct(summary.locked_or_crossed_pct));
+    println!("  One-sided:        {}", pct(summary.one_sided_pct));
+    if let (Some(min), Some(max)) = (summary.min_spread, summary.max_spread) {
+        println!(
+            "  Spread range:     {} to {}",
+            price_scale.to_f64(min),
+            price_scale.to_f64(max)
+        );
+    }
+}
+
+/// The state of a `compare` run after one message.
+#[derive(Debug, Clone, Copy)]
+struct StateTick {
+    /// Index of the message among those processed, from 0.
+    index: u64,
+    hash: u64,
+    ts_event: OffsetDateTime,
+    sequence: u32,
+}
+
+impl StateTick {
+    fn to_json(self) -> Value {
+        json!({
+            "ts_event": self.ts_event.unix_timestamp_nanos() as u64,
+            "sequence": self.sequence,
+        })
+    }
+}
+
+/// Replays `path` with the options of `args`, recording the state after every `every`
+/// messages from message `from` on, and stopping after message `until` if given.
+fn compare_run(
+    args: &CompareArgs,
+    path: &Path,
+    every: usize,
+    from: u64,
+    until: Option<u64>,
+) -> Result<(Replay<()>, Vec<StateTick>), Box<dyn Error>> {
+    let input = InputArgs {
+        data_path: vec![path.to_owned()],
+        ..args.input.clone()
+    };
+    let inputs = resolve_inputs(&input)?;
+    let mut ticks = Vec::new();
+    let replay = replay(
+        &input,
+        &inputs,
+        &args.book,
+        (),
+        false,
+        false,
+        |processor, messages| {
+            let messages = messages.take(until.map_or(usize::MAX, |until| until as usize + 1));
+            let interval = SnapshotInterval::Messages(every);
+            processor.process_with_interval(messages, interval, |processor, index| {
+                if index >= from {
+                    ticks.push(StateTick {
+                        index,
+                        hash: processor.state_hash(),
+                        ts_event: processor.last_event_time(),
+                        sequence: processor.last_sequence_number(),
+                    });
+                }
+                Ok(())
+            })
+        },
+    )?;
+    Ok((replay, ticks))
+}
+
+/// The messages processed by a run, failed ones included.
+fn message_count(replay: &Replay<()>) -> u64 {
+    replay.summary.processed + replay.summary.failures.len() as u64
+}
+
+/// Replays both inputs of `args`, hashing their books every --hash-every messages; if
+/// the hashes part, replays both again up to the first differing hash, hashing after
+/// every message since the last equal one, to name the first message after which the
+/// books differ.
+fn compare(args: &CompareArgs) -> Result<(), Box<dyn Error>> {
+    if !args.input.data_path.is_empty() || args.input.stdin {
+        return Err("compare reads --a and --b, not --data-path or --stdin".into());
+    }
+    if args.hash_every == 0 {
+        return Err("--hash-every must be at least 1".into());
+    }
+    let (a, ticks_a) = compare_run(args, &args.a, args.hash_every, 0, None)?;
+    let (b, ticks_b) = compare_run(args, &args.b, args.hash_every, 0, None)?;
+    let (messages_a, messages_b) = (message_count(&a), message_count(&b));
+
+    // The window of messages holding the first divergence: after the last checkpoint
+    // both agree on, up to the first they do not, or else to the end of the longer run
+    let agreed = ticks_a
+        .iter()
+        .zip(&ticks_b)
+        .take_while(|(a, b)| a.hash == b.hash)
+        .count();
+    let end = match (ticks_a.get(agreed), ticks_b.get(agreed)) {
+        (Some(a), Some(b)) => Some(a.index.min(b.index)),
+        _ if a.processor.state_hash() != b.processor.state_hash() || messages_a != messages_b => {
+            Some(messages_a.max(messages_b).saturating_sub(1))
+        }
+        _ => None,
+    };
+    let divergence = match end {
+        Some(end) => {
+            let start = agreed
+                .checked_sub(1)
+                .map_or(0, |last| ticks_a[last].index + 1);
+            let (_, ticks_a) = compare_run(args, &args.a, 1, start, Some(end))?;
+            let (_, ticks_b) = compare_run(args, &args.b, 1, start, Some(end))?;
+            let first = (0..ticks_a.len().max(ticks_b.len()))
+                .find(|&i| ticks_a.get(i).map(|t| t.hash) != ticks_b.get(i).map(|t| t.hash))
+                .unwrap_or(0);
+            let index = start + first as u64;
+            Some((
+                index,
+                ticks_a.get(first).copied(),
+                ticks_b.get(first).copied(),
+            ))
+        }
+        None => None,
+    };
+
+    let price_scale = a.price_scale;
+    let mut instruments = a.processor.instruments();
+    instruments.extend(b.processor.instruments());
+    instruments.sort_unstable();
+    instruments.dedup();
+    let mbp = |replay: &Replay<()>, id| {
+        replay
+            .processor
+            .book(id)
+            .map(MarketByPrice::from)
+            .unwrap_or_default()
+    };
+    let mut levels = Vec::new();
+    for id in instruments {
+        let (book_a, book_b) = (mbp(&a, id), mbp(&b, id));
+        for update in book_a.diff(&book_b) {
+            let level = |book: &MarketByPrice| {
+                let levels = match update.side {
+                    Side::Bid => &book.bids,
+                    Side::Ask => &book.asks,
+                };
+                levels
+                    .get(&update.price)
+                    .map(|level| (level.total_quantity, level.order_count))
+            };
+            levels.push((
+                id,
+                update.side,
+                update.price,
+                level(&book_a),
+                level(&book_b),
+            ));
+        }
+    }
+
+    let stats = |replay: &Replay<()>| {
+        let mut stats = json!(replay.processor.stats());
+        stats["failed_messages"] = json!(replay.summary.failures.len());
+        stats
+    };
+    let mut stat_differences = Vec::new();
+    diff_json("", &stats(&a), &stats(&b), &mut stat_differences);
+
+    if args.json {
+        let price = |price: i64| match price_scale.is_raw() {
+            true => json!(price),
+            false => json!(price_scale.to_f64(price)),
+        };
+        let level = |level: Option<(u64, usize)>| level.map(|(qty, count)| json!([qty, count]));
+        let report = json!({
+            "identical": divergence.is_none(),
+            "messages": {"a": messages_a, "b": messages_b},
+            "state_hash": {
+                "a": format!("{:016x}", a.processor.state_hash()),
+                "b": format!("{:016x}", b.processor.state_hash()),
+            },
+            "divergence": divergence.map(|(index, tick_a, tick_b)| json!({
+                "index": index,
+                "a": tick_a.map(StateTick::to_json),
+                "b": tick_b.map(StateTick::to_json),
+            })),
+            "levels": levels.iter().map(|&(id, side, px, level_a, level_b)| json!({
+                "instrument_id": id,
+                "side": side_name(side),
+                "price": price(px),
+                "a": level(level_a),
+                "b": level(level_b),
+            })).collect::<Vec<_>>(),
+            "stats": stat_differences.iter().map(|(field, a, b)| json!({
+                "field": field,
+                "a": a,
+                "b": b,
+            })).collect::<Vec<_>>(),
+        });
+        println!("{report}");
+    } else {
+        let matching = if divergence.is_none() {
+            "match"
+        } else {
+            "differ"
+        };
+        println!("Books:              {matching}");
+        println!("Messages:           {messages_a} vs {messages_b}");
+        println!(
+            "State hash:         {:016x} vs {:016x}",
+            a.processor.state_hash(),
+            b.processor.state_hash()
+        );
+        if let Some((index, tick_a, tick_b)) = divergence {
+            let at = |tick: Option<StateTick>| match tick {
+                Some(tick) => format!("ts_event {}, sequence {}", tick.ts_event, tick.sequence),
+                None => "not reached".to_string(),
+            };
+            println!("First divergence:   message #{index}");
+            println!("  A:                {}", at(tick_a));
+            println!("  B:                {}", at(tick_b));
+        }
+        println!("Level differences:  {}", levels.len());
+        let level = |level: Option<(u64, usize)>| match level {
+            Some((qty, count)) => format!("{qty} in {count} orders"),
+            None => "none".to_string(),
+        };
+        for &(id, side, px, level_a, level_b) in levels.iter().take(COMPARE_LEVELS_SHOWN) {
+            println!(
+                "  {} {} {}: {} vs {}",
+                a.label(id),
+                side_name(side),
+                price_scale.format(px),
+                level(level_a),
+                level(level_b)
+            );
+        }
+        if levels.len() > COMPARE_LEVELS_SHOWN {
+            println!("  ... and {} more", levels.len() - COMPARE_LEVELS_SHOWN);
+        }
+        println!("Stat differences:   {}", stat_differences.len());
+        for (field, a, b) in &stat_differences {
+            println!("  {field}: {a} vs {b}");
+        }
+    }
+    match divergence {
+        Some((index, _, _)) => Err(CliError::Diverged { index }.into()),
+        None => Ok(()),
+    }
+}
+
+/// Differing levels `compare` prints; --json reports all of them.
+const COMPARE_LEVELS_SHOWN: usize = 20;
+
+fn side_name(side: Side) -> &'static str {
+    match side {
+        Side::Bid => "bid",
+        Side::Ask => "ask",
+    }
+}
+
+/// Appends the dotted path and both values of every leaf where `a` and `b` differ.
+fn diff_json(path: &str, a: &Value, b: &Value, out: &mut Vec<(String, Value, Value)>) {
+    match (a, b) {
+        (Value::Object(a), Value::Object(b)) => {
+            for (key, value) in a {
+                let path = match path {
+                    "" => key.clone(),
+                    _ => format!("{path}.{key}"),
+                };
+                diff_json(&path, value, b.get(key).unwrap_or(&Value::Null), out);
+            }
+        }
+        _ if a != b => out.push((path.to_owned(), a.clone(), b.clone())),
+        _ => {}
+    }
+}
+
+fn convert(args: &ConvertArgs) -> Result<(), Box<dyn Error>> {
+    let path = &args.output;
+    let output = format_from_suffix(path).ok_or_else(|| {
+        format!(
+            "Cannot tell the output format of {}: expected a .dbn, .dbn.zst, .csv, .ndjson, \
+             .jsonl or .parquet file",
+            path.display()
+        )
+    })?;
+    refuse_existing([path], args.force)?;
+    let inputs = resolve_inputs(&args.input)?;
+    let mut remapper = args.remap_ids.then(|| {
+        args.remap_seed
+            .map_or_else(IdRemapper::new, IdRemapper::with_seed)
+    });
+    let (written, _, _) = match (output.format, output.compression) {
+        (DataFormat::Dbn, Compression::None | Compression::Zstd) => {
+            let metadata = dbn_output_metadata(&args.input, &inputs)?;
+            let mut writer = DbnWriter::create(path, &metadata)?;
+            with_messages(&args.input, &inputs, false, |messages| {
+                let messages = remap_ids(messages, remapper.as_mut());
+                for message in messages {
+                    writer.write(&message)?;
+                }
+                Ok(writer.finish()?)
+            })?
+        }
+        (DataFormat::Csv | DataFormat::Ndjson, Compression::None) => {
+            let file = BufWriter::new(create_output(path, args.force)?);
+            with_messages(&args.input, &inputs, false, |messages| {
+                let messages = remap_ids(messages, remapper.as_mut());
+                let mut written = 0;
+                let messages = messages.inspect(|_| written += 1);
+                match output.format {
+                    DataFormat::Csv => write_mbo_csv(file, messages)?,
+                    _ => write_mbo_ndjson(file, messages)?,
+                }
+                Ok(written)
+            })?
+        }
+        #[cfg(feature = "polars")]
+        (DataFormat::Parquet, Compression::None) => {
+            with_messages(&args.input, &inputs, false, |messages| {
+                let messages: Vec<MarketByOrderMessage> =
+                    remap_ids(messages, remapper.as_mut()).collect();
+                let mut df = mbo_messages_to_dataframe(&messages)?;
+                let file = create_output(path, args.force)?;
+                polars::prelude::ParquetWriter::new(file).finish(&mut df)?;
+                Ok(messages.len() as u64)
+            })?
+        }
+        _ => return Err(format!("Cannot write {output} output").into()),
+    };
+    info!("Wrote {written} messages to {}", path.display());
+    Ok(())
+}
+
+/// `messages` with their order ids replaced by `remapper`, if there is one.
+fn remap_ids<'a>(messages: Messages<'a>, remapper: Option<&'a mut IdRemapper>) -> Messages<'a> {
+    match remapper {
+        Some(remapper) => Box::new(remapper.remap(messages)),
+        None => messages,
+    }
+}
+
+/// Replays the messages of the input, or of the order generator, `--iterations` times
+/// through `process_messages` and prints the rates reached.
+///
+/// The messages are decoded into memory once, so that the processing rate leaves
+/// decoding out. Files are also replayed from disk, as `process` would, for a rate that
+/// includes decoding; standard input can only be read once, so it gets none.
+fn bench(args: &BenchArgs) -> Result<(), Box<dyn Error>> {
+    let (source, messages, inputs) = match args.synthetic {
+        Some(count) => {
+            info!("Generating {count} synthetic messages");
+            let generator = &mut OrderGenerator::default_seeded(args.seed);
+            let messages = generator.make_mbo_messages(count);
+            (format!("synthetic, seed {}", args.seed), messages, None)
+        }
+        None => {
+            let inputs = resolve_inputs(&args.input)?;
+            let (messages, _, _) = with_messages(&args.input, &inputs, false, |messages| {
+                Ok(messages.collect::<Vec<_>>())
+            })?;
+            let source = match inputs.len() {
+                1 => inputs[0]
+                    .0
+                    .as_ref()
+                    .map_or("standard input".into(), |path| path.display().to_string()),
+                files => format!("{files} files"),
+            };
+            let from_files = inputs.iter().all(|(path, _)| path.is_some());
+            (source, messages, from_files.then_some(inputs))
+        }
+    };
+    let count = messages.len() as u64;
+    let processor = || MboProcessor::new().with_error_policy(ErrorPolicy::Collect);
+
+    let mut summary = ProcessSummary::default();
+    let mut processing = Vec::new();
+    for iteration in 1..=args.iterations {
+        let mut processor = processor();
+        let started = Instant::now();
+        summary = processor.process_messages(&messages);
+        processing.push(started.elapsed());
+        debug!(iteration, elapsed = ?started.elapsed(), "Processed in memory");
+    }
+    let mut decoding = Vec::new();
+    if let Some(inputs) = &inputs {
+        for iteration in 1..=args.iterations {
+            let mut processor = processor();
+            let started = Instant::now();
+            with_messages(&args.input, inputs, false, |messages| {
+                Ok(processor.process_messages(messages))
+            })?;
+            decoding.push(started.elapsed());
+            debug!(iteration, elapsed = ?started.elapsed(), "Processed with decoding");
+        }
+    }
+    let mut pipelined = Vec::new();
+    if let Some(inputs) = &inputs {
+        for iteration in 1..=args.iterations {
+            let mut processor = processor();
+            let started = Instant::now();
+            with_messages(&args.input, inputs, false, |messages| {
+                Ok(processor.process_pipelined(messages, PipelineOptions::default()))
+            })?;
+            pipelined.push(started.elapsed());
+            debug!(iteration, elapsed = ?started.elapsed(), "Processed pipelined");
+        }
+    }
+    let processing = RateSpread::new(count, &processing);
+    let decoding = RateSpread::new(count, &decoding);
+    let pipelined = RateSpread::new(count, &pipelined);
+    // Median pipelined rate over the median single-threaded rate with decoding
+    let speedup = decoding
+        .zip(pipelined)
+        .map(|(decoding, pipelined)| pipelined.median / decoding.median);
+
+    let actions = summary.action_counts;
+    if args.json {
+        let rates =
+            |rates: RateSpread| json!({"median": rates.median, "min": rates.min, "max": rates.max});
+        let summary = json!({
+            "source": source,
+            "messages": count,
+            "failed_messages": summary.failures.len(),
+            "iterations": args.iterations,
+            "processing": processing.map(rates),
+            "with_decoding": decoding.map(rates),
+            "pipelined": pipelined.map(rates),
+            "pipeline_speedup": speedup,
+            "action_counts": actions,
+        });
+        println!("{summary}");
+        return Ok(());
+    }
+    println!("Source:             {source}");
+    println!("Messages:           {count}");
+    println!("Failed messages:    {}", summary.failures.len());
+    println!("Iterations:         {}", args.iterations);
+    for (name, rates) in [
+        ("Processing:", processing),
+        ("With decoding:", decoding),
+        ("Pipelined:", pipelined),
+    ] {
+        if let Some(rates) = rates {
+            println!(
+                "{name:<20}median {:.1}, min {:.1}, max {:.1} messages/s",
+                rates.median, rates.min, rates.max
+            );
+        }
+    }
+    if let Some(speedup) = speedup {
+        println!("Pipeline speedup:   {speedup:.2}x");
+    }
+    println!("Actions:");
+    for action in [
+        Action::Add,
+        Action::Cancel,
+        Action::Modify,
+        Action::Fill,
+        Action::Clear,
+        Action::Trade,
+    ] {
+        let n = actions.get(action);
+        let share = 100.0 * n as f64 / actions.total().max(1) as f64;
+        println!("  {:>8}: {n:>12} ({share:.1}%)", action.to_string());
+    }
+    Ok(())
+}
+
+/// Median, lowest and highest messages per second over repeated runs.
+#[derive(Clone, Copy)]
+struct RateSpread {
+    median: f64,
+    min: f64,
+    max: f64,
+}
+
+impl RateSpread {
+    /// The spread of rates of `messages` processed in each of `times`, or `None` if
+    /// there are no times or one took no time at all.
+    fn new(messages: u64, times: &[Duration]) -> Option<Self> {
+        let mut rates: Vec<f64> = times
+            .iter()
+            .map(|&elapsed| rate(messages, elapsed))
+            .collect::<Option<_>>()?;
+        rates.sort_by(f64::total_cmp);
+        let (&min, &max) = (rates.first()?, rates.last()?);
+        let middle = rates.len() / 2;
+        let median = match rates.len() % 2 {
+            0 => (rates[middle - 1] + rates[middle]) / 2.0,
+            _ => rates[middle],
+        };
+        Some(Self { median, min, max })
+    }
+}
+
+/// Runs the interactive viewer over the input, without the progress bar, which would
+/// draw over it.
+#[cfg(feature = "tui")]
+fn view_input(args: &ViewArgs) -> Result<(), Box<dyn Error>> {
+    let input = InputArgs {
+        quiet: true,
+        ..args.input.clone()
+    };
+    let inputs = resolve_inputs(&input)?;
+    let price_scale = input.price_scale(&inputs);
+    with_messages(&input, &inputs, false, |messages| {
+        view::view(
+            messages,
+            args.replay_speed,
+            args.depth,
+            input.instrument_id,
+            price_scale,
+        )
+    })?;
     Ok(())
 }
+
+/// Replays the input to the clients of a WebSocket server, then keeps serving the final
+/// books until interrupted.
+#[cfg(feature = "server")]
+fn serve(args: &ServeArgs) -> Result<(), Box<dyn Error>> {
+    // The feed only sees books built through its callbacks
+    if args.book.warmup || args.book.seed_depth.is_some() {
+        return Err("serve cannot be combined with --warmup or --seed-depth".into());
+    }
+    let replayer = Replayer::new(args.replay_speed)?;
+    let inputs = resolve_inputs(&args.input)?;
+    let server = BookServer::new(
+        ServerOptions::new()
+            .with_depth(args.depth)
+            .with_snapshot_every(args.snapshot_every)
+            .with_price_scale(args.input.price_scale(&inputs))
+            .with_buffer(args.client_buffer),
+    );
+    let runtime = tokio::runtime::Runtime::new()?;
+    let listener = runtime
+        .block_on(tokio::net::TcpListener::bind((
+            args.host.as_str(),
+            args.port,
+        )))
+        .map_err(|e| format!("Cannot listen on {}:{}: {e}", args.host, args.port))?;
+    info!("Serving books on ws://{}", listener.local_addr()?);
+    let serving = runtime.spawn(server.clone().serve(listener));
+
+    let replay = replay(
+        &args.input,
+        &inputs,
+        &args.book,
+        Some(server.feed()),
+        false,
+        false,
+        |processor, messages| Ok(processor.process_messages(replayer.pace(messages))),
+    )?;
+    if let Err(e) = replay.check() {
+        warn!("{e}");
+    }
+    // Dropping the feed tells the clients the replay ended
+    drop(replay);
+    info!("Replay finished, serving the final books until interrupted");
+    runtime.block_on(serving)??;
+    Ok(())
+}
+
+/// Processes the records of a Databento historical request as they are received, then
+/// prints the summary `process` does.
+#[cfg(feature = "databento")]
+fn fetch(args: &FetchArgs) -> Result<(), Box<dyn Error>> {
+    TimeRange::new(Some(args.start), Some(args.end))?;
+    let request = FetchRequest {
+        dataset: args.dataset.clone(),
+        symbols: args.symbols.clone(),
+        start: args.start,
+        end: args.end,
+    };
+    let mut processor = MboProcessor::new().with_error_policy(args.on_error.into());
+    let started = Instant::now();
+    let summary = processor.fetch(&request).map_err(CliError::Fetch)?;
+    let elapsed = started.elapsed();
+    let replay = Replay {
+        processor,
+        summary,
+        symbols: SymbolMap::default(),
+        requested: Vec::new(),
+        window: None,
+        price_scale: PriceScale::DBN,
+    };
+    if args.json {
+        println!("{}", replay.summary_json(elapsed, args.depth));
+    } else {
+        replay.print_summary(elapsed, args.depth);
+    }
+    Ok(replay.check()?)
+}
+
+/// Messages per second of wall time, or `None` if no time has passed.
+fn rate(messages: u64, elapsed: Duration) -> Option<f64> {
+    let seconds = elapsed.as_secs_f64();
+    (seconds > 0.0).then(|| messages as f64 / seconds)
+}
+
+/// Formats a `(price, qty)` top-of-book level, with the price in units of `price_scale`.
+fn format_level(level: Option<(i64, u64)>, price_scale: PriceScale) -> String {
+    match level {
+        Some((price, qty)) => format!("{qty} @ {}", price_scale.to_f64(price)),
+        None => "-".to_string(),
+    }
+}
@@ -0,0 +1,225 @@
+//! Detailed log of the anomalies behind the `Anomalies` counters.
+//!
+//! The counters say how many duplicate adds or unknown cancels a feed had; the log
+//! says which messages they were, with enough of each message to take back to the
+//! vendor. `MboProcessor::with_anomaly_log` turns it on. It is off by default and costs
+//! nothing then.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "polars")]
+use polars::prelude::{DataFrame, PolarsResult, df};
+
+use crate::orderbook::{Anomalies, EventRetention, MarketByOrderMessage, Side};
+
+/// Kind of anomaly, one per `Anomalies` counter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AnomalyKind {
+    /// An Add for an order id already in the book.
+    DuplicateAdd,
+    /// A Cancel of an order id not in the book.
+    UnknownCancel,
+    /// A Modify of an order id not in the book.
+    UnknownModify,
+    /// A removal of an indexed order whose price level did not hold it.
+    LevelMissing,
+    /// An order left out or trimmed away by the depth limit.
+    DepthDropped,
+}
+
+impl AnomalyKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnomalyKind::DuplicateAdd => "duplicate_add",
+            AnomalyKind::UnknownCancel => "unknown_cancel",
+            AnomalyKind::UnknownModify => "unknown_modify",
+            AnomalyKind::LevelMissing => "level_missing",
+            AnomalyKind::DepthDropped => "depth_dropped",
+        }
+    }
+}
+
+/// An anomaly and the message that caused it.
+///
+/// The order fields are the message's: for `DepthDropped` the order trimmed away may
+/// be another one, further from the touch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AnomalyRecord {
+    pub kind: AnomalyKind,
+    /// Position of the message among all messages the processor was given since the
+    /// log was enabled, from 0.
+    pub message_index: u64,
+    pub instrument_id: u32,
+    /// Event time of the message in nanoseconds since the UNIX epoch.
+    pub ts_event: i64,
+    pub sequence: u32,
+    /// The dbn character of the message's action, e.g. `'C'` for Cancel.
+    pub action: char,
+    pub side: Side,
+    pub order_id: u64,
+    pub price: i64,
+    pub size: u32,
+}
+
+/// The anomalies of a processor, bounded by an `EventRetention`.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub(crate) struct AnomalyLog {
+    retention: EventRetention,
+    records: VecDeque<AnomalyRecord>,
+    /// Index of the next message.
+    next_index: u64,
+}
+
+impl AnomalyLog {
+    pub fn new(retention: EventRetention) -> Self {
+        Self {
+            retention,
+            ..Self::default()
+        }
+    }
+
+    pub fn records(&self) -> impl ExactSizeIterator<Item = &AnomalyRecord> {
+        self.records.iter()
+    }
+
+    /// An empty log with the same retention.
+    #[cfg(feature = "parallel")]
+    pub fn emptied(&self) -> Self {
+        Self::new(self.retention)
+    }
+
+    /// Index the next message's records are logged at.
+    #[cfg(feature = "parallel")]
+    pub fn next_index(&self) -> u64 {
+        self.next_index
+    }
+
+    /// Logs the next message at `index`, for a log of part of the input whose messages
+    /// are numbered in the whole of it.
+    #[cfg(feature = "parallel")]
+    pub fn seek(&mut self, index: u64) {
+        self.next_index = index;
+    }
+
+    /// Appends `records` logged elsewhere, in message order and after those here, and
+    /// moves on to message `next_index`.
+    #[cfg(feature = "parallel")]
+    pub fn append(&mut self, records: impl IntoIterator<Item = AnomalyRecord>, next_index: u64) {
+        for record in records {
+            self.push(record);
+        }
+        self.next_index = next_index;
+    }
+
+    #[cfg(feature = "parallel")]
+    pub fn into_records(self) -> VecDeque<AnomalyRecord> {
+        self.records
+    }
+
+    /// Logs one record per count by which `after` exceeds `before`, all for `message`,
+    /// and moves on to the next message index.
+    pub fn record(&mut self, message: &MarketByOrderMessage, before: Anomalies, after: Anomalies) {
+        let counts = [
+            (
+                AnomalyKind::DuplicateAdd,
+                before.duplicate_add,
+                after.duplicate_add,
+            ),
+            (
+                AnomalyKind::UnknownCancel,
+                before.unknown_cancel,
+                after.unknown_cancel,
+            ),
+            (
+                AnomalyKind::UnknownModify,
+                before.unknown_modify,
+                after.unknown_modify,
+            ),
+            (
+                AnomalyKind::LevelMissing,
+                before.level_missing,
+                after.level_missing,
+            ),
+            (
+                AnomalyKind::DepthDropped,
+                before.depth_dropped,
+                after.depth_dropped,
+            ),
+        ];
+        for (kind, before, after) in counts {
+            for _ in before..after {
+                self.push(AnomalyRecord {
+                    kind,
+                    message_index: self.next_index,
+                    instrument_id: message.instrument_id,
+                    ts_event: message.event_time.unix_timestamp_nanos() as i64,
+                    sequence: message.sequence,
+                    action: message.action_char(),
+                    side: message.side,
+                    order_id: message.order_id,
+                    price: message.price,
+                    size: message.size,
+                });
+            }
+        }
+        self.next_index += 1;
+    }
+
+    fn push(&mut self, record: AnomalyRecord) {
+        match self.retention {
+            EventRetention::Off => return,
+            EventRetention::All => {}
+            EventRetention::Last(0) => return,
+            EventRetention::Last(limit) => {
+                if self.records.len() == limit {
+                    self.records.pop_front();
+                }
+            }
+        }
+        self.records.push_back(record);
+    }
+
+    /// The records as a DataFrame: `kind` (as `AnomalyKind::as_str`), `message_index`,
+    /// `instrument_id`, `ts_event` (nanoseconds), `sequence`, `action` (the dbn
+    /// character), `side` (`"B"` or `"A"`), `order_id`, `price` and `size`.
+    #[cfg(feature = "polars")]
+    pub fn to_dataframe(&self) -> PolarsResult<DataFrame> {
+        let records = &self.records;
+        df!(
+            "kind" => records.iter().map(|r| r.kind.as_str()).collect::<Vec<_>>(),
+            "message_index" => records.iter().map(|r| r.message_index).collect::<Vec<_>>(),
+            "instrument_id" => records.iter().map(|r| r.instrument_id).collect::<Vec<_>>(),
+            "ts_event" => records.iter().map(|r| r.ts_event).collect::<Vec<_>>(),
+            "sequence" => records.iter().map(|r| r.sequence).collect::<Vec<_>>(),
+            "action" => records.iter().map(|r| r.action.to_string()).collect::<Vec<_>>(),
+            "side" => records.iter().map(|r| match r.side {
+                Side::Bid => "B",
+                Side::Ask => "A",
+            }).collect::<Vec<_>>(),
+            "order_id" => records.iter().map(|r| r.order_id).collect::<Vec<_>>(),
+            "price" => records.iter().map(|r| r.price).collect::<Vec<_>>(),
+            "size" => records.iter().map(|r| r.size).collect::<Vec<_>>(),
+        )
+    }
+}
+
+/// Counts the records per kind, equal to the processor's counters when every record
+/// since the start is kept.
+impl<'a> FromIterator<&'a AnomalyRecord> for Anomalies {
+    fn from_iter<I: IntoIterator<Item = &'a AnomalyRecord>>(records: I) -> Self {
+        let mut anomalies = Anomalies::default();
+        for record in records {
+            let count = match record.kind {
+                AnomalyKind::DuplicateAdd => &mut anomalies.duplicate_add,
+                AnomalyKind::UnknownCancel => &mut anomalies.unknown_cancel,
+                AnomalyKind::UnknownModify => &mut anomalies.unknown_modify,
+                AnomalyKind::LevelMissing => &mut anomalies.level_missing,
+                AnomalyKind::DepthDropped => &mut anomalies.depth_dropped,
+            };
+            *count += 1;
+        }
+        anomalies
+    }
+}
@@ -0,0 +1,148 @@
+//! Opt-in audit journal of the changes made to an `OrderBook`.
+//!
+//! For working out how a book got into its state: with `OrderBook::with_events`, every
+//! successful mutation appends a `BookEvent`, and `OrderBook::replay_events` applies
+//! them to a fresh book to rebuild it, queue positions included. Operations that fail
+//! or change nothing, such as a Cancel of an unknown id, are not journaled.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::orderbook::{Order, Side};
+
+/// How many events an `OrderBook` journals.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EventRetention {
+    /// Nothing is journaled and nothing allocated.
+    #[default]
+    Off,
+    /// Every event until drained.
+    All,
+    /// The last N events, dropping the oldest for each new one once full.
+    Last(usize),
+}
+
+/// A change made to the book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BookChange {
+    /// `order` was added, replacing any order with its id.
+    Added(Order),
+    /// The order was removed with `size` left, by a cancel or a Modify to a zero size.
+    Removed {
+        order_id: u64,
+        side: Side,
+        price: i64,
+        size: u64,
+    },
+    /// The order was modified from `old_price` and `old_size` into `order`, whose
+    /// sequence fixes its queue position.
+    Modified {
+        order: Order,
+        old_price: i64,
+        old_size: u64,
+    },
+    /// The resting order traded against an incoming one in `match_order`, leaving
+    /// `new_size` (removed at 0).
+    Filled {
+        order_id: u64,
+        side: Side,
+        price: i64,
+        old_size: u64,
+        new_size: u64,
+    },
+    /// Every order was removed.
+    Cleared,
+}
+
+/// One entry of the audit journal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BookEvent {
+    /// Position among all events the book has journaled, from 0; drained and dropped
+    /// events keep their indices counted.
+    pub index: u64,
+    pub change: BookChange,
+}
+
+/// The events of a book, bounded by its `EventRetention`.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct EventJournal {
+    retention: EventRetention,
+    events: VecDeque<BookEvent>,
+    next_index: u64,
+}
+
+impl EventJournal {
+    pub fn new(retention: EventRetention) -> Self {
+        Self {
+            retention,
+            ..Self::default()
+        }
+    }
+
+    pub fn retention(&self) -> EventRetention {
+        self.retention
+    }
+
+    #[inline]
+    pub fn record(&mut self, change: BookChange) {
+        match self.retention {
+            EventRetention::Off => return,
+            EventRetention::All => {}
+            EventRetention::Last(limit) => {
+                if limit == 0 {
+                    self.next_index += 1;
+                    return;
+                }
+                if self.events.len() == limit {
+                    self.events.pop_front();
+                }
+            }
+        }
+        self.events.push_back(BookEvent {
+            index: self.next_index,
+            change,
+        });
+        self.next_index += 1;
+    }
+
+    pub fn events(&self) -> impl Iterator<Item = &BookEvent> {
+        self.events.iter()
+    }
+
+    pub fn drain(&mut self) -> Vec<BookEvent> {
+        self.events.drain(..).collect()
+    }
+}
+
+/// The journal records how a book got to its state rather than being part of it, so
+/// books are equal whatever they have journaled.
+impl PartialEq for EventJournal {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for EventJournal {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bounded_journal_keeps_last_events() {
+        let mut journal = EventJournal::new(EventRetention::Last(2));
+        (0..5).for_each(|_| journal.record(BookChange::Cleared));
+        let indices: Vec<u64> = journal.events().map(|event| event.index).collect();
+        assert_eq!(indices, vec![3, 4]);
+
+        assert_eq!(journal.drain().len(), 2);
+        journal.record(BookChange::Cleared);
+        assert_eq!(journal.events().next().unwrap().index, 5);
+
+        let mut off = EventJournal::default();
+        off.record(BookChange::Cleared);
+        assert_eq!(off.events().count(), 0);
+        assert_eq!(off.events.capacity(), 0);
+    }
+}
@@ -0,0 +1,360 @@
+//! OHLCV time bars built from trade prints.
+//!
+//! [`BarBuilder`] is an [`MboObserver`] that buckets trades by `event_time` into
+//! fixed-interval bars. Completed bars accumulate in a drainable `Vec`.
+
+use std::mem;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use time::{Duration, OffsetDateTime};
+use tracing::warn;
+
+#[cfg(feature = "polars")]
+use polars::prelude::{DataFrame, PolarsResult, df};
+
+use crate::orderbook::{MboObserver, TradeEvent};
+
+#[derive(Debug, Error, Clone)]
+pub enum BarError {
+    #[error("Bar interval must be positive, got {0}")]
+    InvalidInterval(Duration),
+}
+
+/// A single OHLCV bar. Prices are in the same integer units as the order book.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bar {
+    /// Inclusive start of the interval covered by this bar.
+    pub start: OffsetDateTime,
+    pub open: i64,
+    pub high: i64,
+    pub low: i64,
+    pub close: i64,
+    /// Total traded size.
+    pub volume: u64,
+    /// Number of trades aggregated into this bar.
+    pub trade_count: u64,
+    /// Volume-weighted average price. Equals `close` for an empty bar.
+    pub vwap: f64,
+}
+
+impl Bar {
+    fn empty(start: OffsetDateTime, price: i64) -> Self {
+        Self {
+            start,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume: 0,
+            trade_count: 0,
+            vwap: price as f64,
+        }
+    }
+}
+
+/// Observer that aggregates trades into fixed-interval OHLCV bars.
+///
+/// By default only aggressor-side prints (Trade actions) are counted, since each
+/// execution is also reported as one or more passive Fills; use `with_fills` to
+/// count Fills as well for feeds that only publish the passive side.
+///
+/// Intervals without trades are skipped unless `with_empty_bars(true)` is set, in
+/// which case they are emitted flat at the previous close with zero volume.
+#[derive(Debug)]
+pub struct BarBuilder {
+    interval: Duration,
+    emit_empty: bool,
+    include_fills: bool,
+    /// The bar currently being built, if any trade has been seen.
+    current: Option<Bar>,
+    /// Sum of price * size over the current bar, for VWAP.
+    current_notional: i128,
+    completed: Vec<Bar>,
+}
+
+impl BarBuilder {
+    pub fn new(interval: Duration) -> Result<Self, BarError> {
+        if !interval.is_positive() {
+            return Err(BarError::InvalidInterval(interval));
+        }
+        Ok(Self {
+            interval,
+            emit_empty: false,
+            include_fills: false,
+            current: None,
+            current_notional: 0,
+            completed: Vec::new(),
+        })
+    }
+
+    /// Emits flat, zero-volume bars for intervals without trades.
+    pub fn with_empty_bars(mut self, emit_empty: bool) -> Self {
+        self.emit_empty = emit_empty;
+        self
+    }
+
+    /// Counts passive Fill prints in addition to aggressor Trades.
+    pub fn with_fills(mut self, include_fills: bool) -> Self {
+        self.include_fills = include_fills;
+        self
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// The bar currently being built.
+    pub fn current_bar(&self) -> Option<&Bar> {
+        self.current.as_ref()
+    }
+
+    /// Bars completed so far and not yet drained.
+    pub fn completed_bars(&self) -> &[Bar] {
+        &self.completed
+    }
+
+    /// Takes all completed bars, leaving the builder's output empty.
+    pub fn drain_bars(&mut self) -> Vec<Bar> {
+        mem::take(&mut self.completed)
+    }
+
+    /// Completes the bar currently being built, e.g. at the end of a replay.
+    pub fn flush(&mut self) {
+        if let Some(bar) = self.current.take() {
+            self.completed.push(bar);
+            self.current_notional = 0;
+        }
+    }
+
+    /// Start of the interval containing `time`, aligned to the UNIX epoch.
+    fn bucket_start(&self, time: OffsetDateTime) -> OffsetDateTime {
+        let offset = (time - OffsetDateTime::UNIX_EPOCH).whole_nanoseconds();
+        let interval = self.interval.whole_nanoseconds();
+        let aligned = offset.div_euclid(interval) * interval;
+        OffsetDateTime::UNIX_EPOCH + Duration::nanoseconds_i128(aligned)
+    }
+
+    fn start_bar(&mut self, start: OffsetDateTime, price: i64) {
+        self.current = Some(Bar::empty(start, price));
+        self.current_notional = 0;
+    }
+
+    fn record(&mut self, price: i64, size: u32) {
+        if let Some(bar) = self.current.as_mut() {
+            if bar.trade_count == 0 {
+                bar.open = price;
+                bar.high = price;
+                bar.low = price;
+            }
+            bar.high = bar.high.max(price);
+            bar.low = bar.low.min(price);
+            bar.close = price;
+            bar.volume += u64::from(size);
+            bar.trade_count += 1;
+            self.current_notional += i128::from(price) * i128::from(size);
+            if bar.volume > 0 {
+                bar.vwap = self.current_notional as f64 / bar.volume as f64;
+            }
+        }
+    }
+}
+
+impl MboObserver for BarBuilder {
+    fn on_trade(&mut self, event: &TradeEvent) {
+        if !event.aggressor && !self.include_fills {
+            return;
+        }
+
+        let start = self.bucket_start(event.event_time);
+        match self.current {
+            None => self.start_bar(start, event.price),
+            Some(bar) if start > bar.start => {
+                self.completed.push(bar);
+                if self.emit_empty {
+                    let gaps = ((start - bar.start).whole_nanoseconds()
+                        / self.interval.whole_nanoseconds()
+                        - 1) as i32;
+                    let interval = self.interval;
+                    self.completed.extend(
+                        (1..=gaps).map(|i| Bar::empty(bar.start + interval * i, bar.close)),
+                    );
+                }
+                self.start_bar(start, event.price);
+            }
+            Some(bar) if start < bar.start => warn!(
+                "Trade at {} precedes current bar starting {}, adding to current bar",
+                event.event_time, bar.start
+            ),
+            Some(_) => {}
+        }
+        self.record(event.price, event.size);
+    }
+}
+
+/// Converts bars into a DataFrame with one row per bar.
+///
+/// `start` is exported as nanoseconds since the UNIX epoch.
+#[cfg(feature = "polars")]
+pub fn bars_to_dataframe(bars: &[Bar]) -> PolarsResult<DataFrame> {
+    df!(
+        "start" => bars.iter().map(|b| (b.start.unix_timestamp_nanos()) as i64).collect::<Vec<_>>(),
+        "open" => bars.iter().map(|b| b.open).collect::<Vec<_>>(),
+        "high" => bars.iter().map(|b| b.high).collect::<Vec<_>>(),
+        "low" => bars.iter().map(|b| b.low).collect::<Vec<_>>(),
+        "close" => bars.iter().map(|b| b.close).collect::<Vec<_>>(),
+        "volume" => bars.iter().map(|b| b.volume).collect::<Vec<_>>(),
+        "trade_count" => bars.iter().map(|b| b.trade_count).collect::<Vec<_>>(),
+        "vwap" => bars.iter().map(|b| b.vwap).collect::<Vec<_>>(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::{Action, MarketByOrderMessage, MboProcessor, Side};
+
+    fn trade(seconds: i64, price: i64, size: u32) -> MarketByOrderMessage {
+        let event_time = OffsetDateTime::UNIX_EPOCH + Duration::seconds(seconds);
+        MarketByOrderMessage::test(Action::Trade, Side::Ask, price, 0, size).at(event_time)
+    }
+
+    fn fill(seconds: i64, price: i64, size: u32) -> MarketByOrderMessage {
+        MarketByOrderMessage {
+            action: Action::Fill,
+            ..trade(seconds, price, size)
+        }
+    }
+
+    fn minute(n: i64) -> OffsetDateTime {
+        OffsetDateTime::UNIX_EPOCH + Duration::minutes(n)
+    }
+
+    fn run(builder: BarBuilder, messages: &[MarketByOrderMessage]) -> Vec<Bar> {
+        let mut proc = MboProcessor::with_observer(builder);
+        assert!(proc.process_messages(messages).is_success());
+        let mut builder = proc.into_observer();
+        builder.flush();
+        builder.drain_bars()
+    }
+
+    #[test]
+    fn test_three_intervals() {
+        let builder = BarBuilder::new(Duration::minutes(1)).unwrap();
+        let messages = [
+            trade(5, 100, 10),
+            fill(5, 100, 10), // passive side of the same execution, ignored
+            trade(30, 103, 5),
+            trade(59, 99, 5),
+            trade(60, 101, 20),
+            trade(150, 104, 1),
+            trade(170, 102, 3),
+        ];
+
+        let bars = run(builder, &messages);
+
+        assert_eq!(
+            bars,
+            vec![
+                Bar {
+                    start: minute(0),
+                    open: 100,
+                    high: 103,
+                    low: 99,
+                    close: 99,
+                    volume: 20,
+                    trade_count: 3,
+                    vwap: (100.0 * 10.0 + 103.0 * 5.0 + 99.0 * 5.0) / 20.0,
+                },
+                Bar {
+                    start: minute(1),
+                    open: 101,
+                    high: 101,
+                    low: 101,
+                    close: 101,
+                    volume: 20,
+                    trade_count: 1,
+                    vwap: 101.0,
+                },
+                Bar {
+                    start: minute(2),
+                    open: 104,
+                    high: 104,
+                    low: 102,
+                    close: 102,
+                    volume: 4,
+                    trade_count: 2,
+                    vwap: (104.0 + 102.0 * 3.0) / 4.0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gaps_skipped_or_emitted() {
+        let messages = [trade(10, 100, 1), trade(200, 105, 2)];
+
+        let skipped = run(BarBuilder::new(Duration::minutes(1)).unwrap(), &messages);
+        assert_eq!(
+            skipped.iter().map(|b| b.start).collect::<Vec<_>>(),
+            vec![minute(0), minute(3)]
+        );
+
+        let filled = run(
+            BarBuilder::new(Duration::minutes(1))
+                .unwrap()
+                .with_empty_bars(true),
+            &messages,
+        );
+        assert_eq!(
+            filled.iter().map(|b| b.start).collect::<Vec<_>>(),
+            vec![minute(0), minute(1), minute(2), minute(3)]
+        );
+        assert_eq!(filled[1], Bar::empty(minute(1), 100));
+        assert_eq!(filled[2].volume, 0);
+        assert_eq!(filled[3].close, 105);
+    }
+
+    #[test]
+    fn test_fills_counted_when_enabled() {
+        let builder = BarBuilder::new(Duration::minutes(1))
+            .unwrap()
+            .with_fills(true);
+        let bars = run(builder, &[trade(1, 100, 10), fill(1, 100, 10)]);
+        assert_eq!(bars.len(), 1);
+        assert_eq!(bars[0].volume, 20);
+        assert_eq!(bars[0].trade_count, 2);
+    }
+
+    #[test]
+    fn test_drain_leaves_current_bar_open() {
+        let mut proc = MboProcessor::with_observer(BarBuilder::new(Duration::minutes(1)).unwrap());
+        proc.process_messages([trade(1, 100, 1), trade(61, 101, 1)]);
+
+        let drained = proc.observer_mut().drain_bars();
+        assert_eq!(drained.len(), 1);
+        assert!(proc.observer().completed_bars().is_empty());
+        assert_eq!(proc.observer().current_bar().unwrap().start, minute(1));
+    }
+
+    #[test]
+    fn test_invalid_interval() {
+        assert!(matches!(
+            BarBuilder::new(Duration::ZERO),
+            Err(BarError::InvalidInterval(_))
+        ));
+    }
+
+    #[cfg(feature = "polars")]
+    #[test]
+    fn test_bars_to_dataframe() {
+        let bars = run(
+            BarBuilder::new(Duration::minutes(1)).unwrap(),
+            &[trade(1, 100, 2), trade(61, 104, 2)],
+        );
+        let df = bars_to_dataframe(&bars).unwrap();
+        assert_eq!(df.shape(), (2, 8));
+        let close = df.column("close").unwrap().i64().unwrap();
+        assert_eq!(close.get(1), Some(104));
+    }
+}
@@ -0,0 +1,349 @@
+//! Top-of-book time series recording.
+
+use time::OffsetDateTime;
+
+#[cfg(feature = "polars")]
+use std::path::Path;
+
+#[cfg(feature = "polars")]
+use polars::prelude::{DataFrame, IpcWriter, ParquetWriter, PolarsResult, SerWriter, df};
+
+#[cfg(feature = "polars")]
+use crate::orderbook::create_output;
+use crate::orderbook::events::{
+    OrderAddedEvent, OrderCancelledEvent, OrderModifiedEvent, TradeEvent,
+};
+use crate::orderbook::{Bbo, MboObserver, OrderBook};
+
+/// A top-of-book (MBP-1) row. A missing side has no price or size.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Mbp1Row {
+    pub bid_px: Option<i64>,
+    pub bid_sz: Option<u64>,
+    pub ask_px: Option<i64>,
+    pub ask_sz: Option<u64>,
+}
+
+impl From<Bbo> for Mbp1Row {
+    fn from(bbo: Bbo) -> Self {
+        Self {
+            bid_px: bbo.bid.map(|(price, _)| price),
+            bid_sz: bbo.bid.map(|(_, size)| size),
+            ask_px: bbo.ask.map(|(price, _)| price),
+            ask_sz: bbo.ask.map(|(_, size)| size),
+        }
+    }
+}
+
+/// Deduplicates top-of-book output: yields a row only when the price or size on
+/// either side differs from the last row yielded.
+///
+/// Starts from an empty book, so an empty top of book is never reported first.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Mbp1Tracker {
+    last: Bbo,
+}
+
+impl Mbp1Tracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the book's top of book if it changed since the last emitted row.
+    pub fn update(&mut self, book: &OrderBook) -> Option<Mbp1Row> {
+        self.update_bbo(book.bbo())
+    }
+
+    /// Returns `bbo` as a row if it differs from the last emitted row.
+    pub fn update_bbo(&mut self, bbo: Bbo) -> Option<Mbp1Row> {
+        (bbo != self.last).then(|| {
+            self.last = bbo;
+            Mbp1Row::from(bbo)
+        })
+    }
+
+    /// The top of book as of the last emitted row.
+    pub fn last_bbo(&self) -> Bbo {
+        self.last
+    }
+}
+
+/// Observer that records a row every time the top of book changes.
+///
+/// The book is sampled at event boundaries (`on_event_complete`), so mid-event states
+/// are never recorded. Rows are stored column-wise, so memory grows with the number of
+/// BBO changes rather than the number of messages. Intended for a single instrument.
+#[derive(Debug, Default, Clone)]
+pub struct BboRecorder {
+    /// Top of book as of the last recorded row.
+    tracker: Mbp1Tracker,
+    /// Sequence of the most recent order or trade event.
+    last_sequence: u32,
+    ts_event: Vec<i64>,
+    sequence: Vec<u32>,
+    bid_px: Vec<Option<i64>>,
+    bid_sz: Vec<Option<u64>>,
+    ask_px: Vec<Option<i64>>,
+    ask_sz: Vec<Option<u64>>,
+}
+
+impl BboRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of recorded BBO changes.
+    pub fn len(&self) -> usize {
+        self.ts_event.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ts_event.is_empty()
+    }
+
+    /// The most recently recorded top of book.
+    pub fn last_bbo(&self) -> Bbo {
+        self.tracker.last_bbo()
+    }
+
+    /// Converts the recorded changes into a DataFrame with columns `ts_event`
+    /// (nanoseconds since the UNIX epoch), `sequence`, `bid_px`, `bid_sz`, `ask_px`,
+    /// `ask_sz`, `mid` and `spread`. Missing sides are null, as are `mid` and `spread`
+    /// unless both sides are present.
+    #[cfg(feature = "polars")]
+    pub fn to_dataframe(&self) -> PolarsResult<DataFrame> {
+        let (mid, spread): (Vec<Option<f64>>, Vec<Option<i64>>) = self
+            .bid_px
+            .iter()
+            .zip(&self.ask_px)
+            .map(|(bid, ask)| match (bid, ask) {
+                (Some(bid), Some(ask)) => (
+                    Some((*bid as f64 + *ask as f64) / 2.0),
+                    Some(ask.saturating_sub(*bid)),
+                ),
+                _ => (None, None),
+            })
+            .unzip();
+
+        df!(
+            "ts_event" => &self.ts_event,
+            "sequence" => &self.sequence,
+            "bid_px" => &self.bid_px,
+            "bid_sz" => &self.bid_sz,
+            "ask_px" => &self.ask_px,
+            "ask_sz" => &self.ask_sz,
+            "mid" => mid,
+            "spread" => spread,
+        )
+    }
+
+    /// Writes the DataFrame from `to_dataframe` to a parquet file.
+    #[cfg(feature = "polars")]
+    pub fn write_parquet(&self, path: impl AsRef<Path>) -> PolarsResult<()> {
+        let mut df = self.to_dataframe()?;
+        ParquetWriter::new(create_output(path, true)?).finish(&mut df)?;
+        Ok(())
+    }
+
+    /// Writes the same DataFrame to an Arrow IPC (Feather v2) file.
+    #[cfg(feature = "polars")]
+    pub fn write_ipc(&self, path: impl AsRef<Path>) -> PolarsResult<()> {
+        let mut df = self.to_dataframe()?;
+        IpcWriter::new(create_output(path, true)?).finish(&mut df)?;
+        Ok(())
+    }
+}
+
+impl MboObserver for BboRecorder {
+    fn on_order_added(&mut self, event: &OrderAddedEvent) {
+        self.last_sequence = event.sequence;
+    }
+
+    fn on_order_cancelled(&mut self, event: &OrderCancelledEvent) {
+        self.last_sequence = event.sequence;
+    }
+
+    fn on_order_modified(&mut self, event: &OrderModifiedEvent) {
+        self.last_sequence = event.sequence;
+    }
+
+    fn on_trade(&mut self, event: &TradeEvent) {
+        self.last_sequence = event.sequence;
+    }
+
+    fn on_event_complete(
+        &mut self,
+        book: &OrderBook,
+        event_time: OffsetDateTime,
+        _recv_time: OffsetDateTime,
+    ) {
+        let Some(row) = self.tracker.update(book) else {
+            return;
+        };
+        self.ts_event.push(event_time.unix_timestamp_nanos() as i64);
+        self.sequence.push(self.last_sequence);
+        self.bid_px.push(row.bid_px);
+        self.bid_sz.push(row.bid_sz);
+        self.ask_px.push(row.ask_px);
+        self.ask_sz.push(row.ask_sz);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Duration;
+
+    use crate::orderbook::{Action, MarketByOrderMessage, MboProcessor, Side};
+
+    fn msg(
+        seq: u32,
+        action: Action,
+        order_id: u64,
+        side: Side,
+        price: i64,
+        size: u32,
+        is_last: bool,
+    ) -> MarketByOrderMessage {
+        MarketByOrderMessage {
+            sequence: seq,
+            ..MarketByOrderMessage::test(action, side, price, order_id, size)
+                .at(OffsetDateTime::UNIX_EPOCH + Duration::nanoseconds(seq.into()))
+                .with_last(is_last)
+        }
+    }
+
+    /// Scripted session producing four BBO changes out of seven messages.
+    fn recorded() -> BboRecorder {
+        let messages = [
+            msg(1, Action::Add, 1, Side::Bid, 100, 10, true), // change: bid only
+            msg(2, Action::Add, 2, Side::Ask, 104, 5, true),  // change: two-sided
+            msg(3, Action::Add, 3, Side::Bid, 99, 7, true),   // behind the top
+            msg(4, Action::Trade, 0, Side::Bid, 104, 5, false),
+            msg(5, Action::Fill, 2, Side::Ask, 104, 5, false),
+            msg(6, Action::Cancel, 2, Side::Ask, 0, 0, true), // change: ask gone
+            msg(7, Action::Add, 4, Side::Bid, 100, 5, true),  // change: bid size
+        ];
+        let mut proc = MboProcessor::with_observer(BboRecorder::new());
+        assert!(proc.process_messages(messages).is_success());
+        proc.into_observer()
+    }
+
+    #[test]
+    fn test_tracker_emits_only_on_change() {
+        let mut proc = MboProcessor::new();
+        proc.process_messages([
+            msg(1, Action::Add, 1, Side::Bid, 100, 10, true),
+            msg(2, Action::Add, 2, Side::Ask, 105, 10, true),
+        ]);
+        let mut tracker = Mbp1Tracker::new();
+        assert!(tracker.update(proc.order_book()).is_some());
+
+        let emitted: Vec<Option<Mbp1Row>> = [
+            msg(3, Action::Add, 3, Side::Bid, 98, 5, true), // deep bid
+            msg(4, Action::Add, 4, Side::Ask, 107, 5, true), // deep ask
+            msg(5, Action::Add, 5, Side::Bid, 100, 5, true), // best bid size only
+            msg(6, Action::Cancel, 3, Side::Bid, 0, 0, true), // deep bid gone
+            msg(7, Action::Modify, 4, Side::Ask, 106, 5, true), // deep ask moves
+        ]
+        .iter()
+        .map(|message| {
+            proc.process_message(message).unwrap();
+            tracker.update(proc.order_book())
+        })
+        .collect();
+
+        assert_eq!(
+            emitted,
+            vec![
+                None,
+                None,
+                Some(Mbp1Row {
+                    bid_px: Some(100),
+                    bid_sz: Some(15),
+                    ask_px: Some(105),
+                    ask_sz: Some(10),
+                }),
+                None,
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_records_only_changes_at_event_boundaries() {
+        let recorder = recorded();
+        assert_eq!(recorder.len(), 4);
+        assert_eq!(recorder.sequence, vec![1, 2, 6, 7]);
+        assert_eq!(recorder.ts_event, vec![1, 2, 6, 7]);
+        assert_eq!(
+            recorder.bid_sz,
+            vec![Some(10), Some(10), Some(10), Some(15)]
+        );
+        assert_eq!(recorder.ask_px, vec![None, Some(104), None, None]);
+        assert_eq!(
+            recorder.last_bbo(),
+            Bbo {
+                bid: Some((100, 15)),
+                ask: None
+            }
+        );
+    }
+
+    #[cfg(feature = "polars")]
+    #[test]
+    fn test_to_dataframe() {
+        let df = recorded().to_dataframe().unwrap();
+        assert_eq!(df.shape(), (4, 8));
+
+        let mid: Vec<Option<f64>> = df
+            .column("mid")
+            .unwrap()
+            .f64()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(mid, vec![None, Some(102.0), None, None]);
+        let spread: Vec<Option<i64>> = df
+            .column("spread")
+            .unwrap()
+            .i64()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(spread, vec![None, Some(4), None, None]);
+        let bid_px: Vec<Option<i64>> = df
+            .column("bid_px")
+            .unwrap()
+            .i64()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(bid_px, vec![Some(100); 4]);
+        let sequence: Vec<Option<u32>> = df
+            .column("sequence")
+            .unwrap()
+            .u32()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(sequence, vec![Some(1), Some(2), Some(6), Some(7)]);
+    }
+
+    #[cfg(feature = "polars")]
+    #[test]
+    fn test_ipc_round_trip() {
+        use polars::prelude::{IpcReader, SerReader};
+        use std::fs::File;
+        use std::{env, fs, process};
+
+        let recorder = recorded();
+        let path = env::temp_dir().join(format!("rainybook-{}-bbo.arrow", process::id()));
+        recorder.write_ipc(&path).unwrap();
+        let df = IpcReader::new(File::open(&path).unwrap()).finish().unwrap();
+        fs::remove_file(&path).unwrap();
+        let expected = recorder.to_dataframe().unwrap();
+        assert_eq!(df.schema(), expected.schema());
+        assert!(df.equals_missing(&expected));
+    }
+}
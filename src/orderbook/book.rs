@@ -1,9 +1,30 @@
 use std::collections::hash_map::Entry;
 use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "fast-hash")]
+use std::hash::BuildHasherDefault;
+use std::mem;
 
 use num_enum::{IntoPrimitive, TryFromPrimitive};
+use serde::{Deserialize, Serialize};
+use strum::Display;
 use thiserror::Error;
-use tracing::warn;
+use tracing::debug;
+
+use crate::orderbook::MarketByPrice;
+use crate::orderbook::audit::{BookChange, BookEvent, EventJournal, EventRetention};
+use crate::orderbook::expiry::ExpiryQueue;
+use crate::orderbook::queue::{LevelPool, OrderQueue};
+
+/// Map keyed by order id, the lookup behind every cancel, modify and fill.
+///
+/// With the `fast-hash` feature it uses FxHash, which is much cheaper than std's SipHash
+/// on `u64` keys but has no random seed. Order ids come from the feed, so data crafted
+/// with colliding ids could make lookups linear (HashDoS); enable it only for venue or
+/// otherwise trusted data.
+#[cfg(feature = "fast-hash")]
+pub(crate) type OrderMap<V> = HashMap<u64, V, BuildHasherDefault<rustc_hash::FxHasher>>;
+#[cfg(not(feature = "fast-hash"))]
+pub(crate) type OrderMap<V> = HashMap<u64, V>;
 
 /// Information returned by `OrderBook::add_order`.
 #[derive(Debug, Clone, Copy)]
@@ -16,6 +37,13 @@ pub struct AddOrderInfo {
     pub level_order_count: usize,
     /// True if this order created a new price level.
     pub new_level: bool,
+    /// True if an order with the same id already existed and was replaced.
+    pub replaced: bool,
+    /// Side and price of the replaced order, whose level it was removed from.
+    pub replaced_level: Option<(Side, i64)>,
+    /// True if the order would have opened a level beyond the book's depth limit and
+    /// was not added; the level fields are then 0.
+    pub dropped: bool,
 }
 
 /// Information returned by `OrderBook::remove_order`.
@@ -57,26 +85,201 @@ pub struct ModifyOrderInfo {
     pub level_qty: u64,
     /// Number of orders at the (new) price level after modification.
     pub level_order_count: usize,
-    /// True if the order kept its queue position (same price + size decrease).
-    /// False if queue position was reset (price change or size increase).
+    /// True if the order kept its queue position (same price, and a size decrease
+    /// under `ModifyPriorityPolicy::ExchangeStyle`). False if it was re-queued.
     pub retained_queue_position: bool,
 }
 
-#[derive(Debug, Error, Clone)]
+/// A resting order (partially) executed by `OrderBook::match_order`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Execution {
+    /// The resting order as it was before the execution.
+    pub resting: Order,
+    /// Quantity executed against the resting order.
+    pub size: u64,
+}
+
+/// What `OrderBook::match_order_with_policy` did besides executing resting orders.
+#[derive(Debug, Default, Clone)]
+pub struct MatchOutcome {
+    pub executions: Vec<Execution>,
+    /// Resting orders of the incoming order's owner removed under
+    /// `SelfMatchPolicy::CancelResting`, in the order they were reached.
+    pub cancelled: Vec<RemoveOrderInfo>,
+    /// True if matching stopped at a resting order of the incoming order's owner under
+    /// `SelfMatchPolicy::CancelIncoming`; the unfilled residual must not rest.
+    pub incoming_cancelled: bool,
+}
+
+/// Resting orders an aggressive order would trade against although they have its owner,
+/// from `OrderBook::would_self_match`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SelfMatchReport {
+    /// Each such order as it rests, with the quantity that would trade against it, in
+    /// match order.
+    pub matches: Vec<Execution>,
+}
+
+impl SelfMatchReport {
+    pub fn is_empty(&self) -> bool {
+        self.matches.is_empty()
+    }
+
+    /// Quantity that would trade between the owner's orders.
+    pub fn qty(&self) -> u64 {
+        self.matches.iter().map(|execution| execution.size).sum()
+    }
+}
+
+/// What matching does when an incoming order reaches a resting order of the same owner.
+/// Orders without an owner never self-match.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SelfMatchPolicy {
+    /// Trade as with any other resting order.
+    #[default]
+    Allow,
+    /// Cancel the resting order and keep matching past it.
+    CancelResting,
+    /// Stop matching and cancel the rest of the incoming order; executions before the
+    /// resting order stand.
+    CancelIncoming,
+}
+
+/// Whether a Modify at an unchanged price keeps the order's queue position.
+///
+/// A price change always moves the order to the back of the new level's queue.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ModifyPriorityPolicy {
+    /// A size decrease keeps queue position, a size increase loses it. This is the
+    /// rule used by most exchanges, including CME.
+    #[default]
+    ExchangeStyle,
+    /// Any size change keeps queue position.
+    AlwaysPreserve,
+}
+
+/// Lowest order id reserved for the synthetic orders created by
+/// `OrderBook::seed_from_depth` and `OrderBook::from_mbp`, which take ids counting up from here. Venue order ids
+/// never reach this range, so seeded liquidity cannot collide with real orders.
+pub const SYNTHETIC_ORDER_ID_MIN: u64 = u64::MAX - u32::MAX as u64;
+
+/// True if `order_id` lies in the range reserved for synthetic orders.
+pub fn is_synthetic_order_id(order_id: u64) -> bool {
+    order_id >= SYNTHETIC_ORDER_ID_MIN
+}
+
+/// How `OrderBook::seed_from_depth` represents a price level with synthetic orders.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SeedMode {
+    /// One order holding the whole level size.
+    #[default]
+    Aggregate,
+    /// `count` orders of equal size, with any remainder on the first. Keeps the
+    /// level's order count in line with the depth feed.
+    PerOrder,
+}
+
+/// What happens to an Add that would open a level beyond the depth limit set with
+/// `OrderBook::with_max_levels_per_side`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DepthLimitPolicy {
+    /// The order is left out of the book and counted in `Anomalies::depth_dropped`.
+    #[default]
+    Drop,
+    /// `OrderBook::try_add_order` fails with `OrderBookError::BeyondDepthLimit`,
+    /// leaving the book untouched. `add_order` leaves the order out without counting it.
+    Reject,
+}
+
+/// Counts of inconsistencies between the book and the operations applied to it.
+///
+/// Each anomaly is tolerated (the operation is ignored or overwrites) and logged at
+/// debug level; these counters make data quality measurable after the fact.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Anomalies {
+    /// Adds for an order id already in the book (the old order is replaced).
+    pub duplicate_add: u64,
+    /// Removals of an order id not in the book (ignored).
+    pub unknown_cancel: u64,
+    /// Modifies of an order id not in the book (ignored).
+    pub unknown_modify: u64,
+    /// Removals of an indexed order whose price level did not hold it (ignored).
+    pub level_missing: u64,
+    /// Orders left out or trimmed away by the depth limit under `DepthLimitPolicy::Drop`.
+    #[serde(default)]
+    pub depth_dropped: u64,
+}
+
+impl Anomalies {
+    pub fn total(&self) -> u64 {
+        self.duplicate_add
+            + self.unknown_cancel
+            + self.unknown_modify
+            + self.level_missing
+            + self.depth_dropped
+    }
+}
+
+/// One participant's resting orders on one side of the book.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ParticipantSide {
+    pub qty: u64,
+    pub order_count: usize,
+    /// Fraction of the side's resting quantity, from 0 to 1.
+    pub share: f64,
+}
+
+/// What one participant has resting and executed, from `participant_summary`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ParticipantStats {
+    pub bid: ParticipantSide,
+    pub ask: ParticipantSide,
+    /// Quantity of the participant's orders filled so far. Only
+    /// `MboProcessor::participant_summary` counts fills; the book's own summary has 0.
+    pub executed_qty: u64,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
 pub enum OrderBookError {
     #[error("Order {0} not found at price level")]
     OrderNotFound(u64),
+
+    #[error("Cannot reduce order {order_id} of size {size} by {}", delta.unsigned_abs())]
+    SizeUnderflow {
+        order_id: u64,
+        size: u64,
+        delta: i64,
+    },
+
+    #[error(
+        "Order {order_id} at {side:?} price {price} is beyond the {max_levels}-level depth limit"
+    )]
+    BeyondDepthLimit {
+        order_id: u64,
+        side: Side,
+        price: i64,
+        max_levels: usize,
+    },
 }
 
+/// Side of an order. Serde writes it as `"Bid"` or `"Ask"` to human-readable formats,
+/// see `names`.
 #[repr(i8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, TryFromPrimitive, IntoPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Display, TryFromPrimitive, IntoPrimitive)]
 pub enum Side {
     Bid = 1,
     Ask = 2,
 }
 
+/// Best bid and offer: `(price, total_qty)` of the top level on each side.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Bbo {
+    pub bid: Option<(i64, u64)>,
+    pub ask: Option<(i64, u64)>,
+}
+
 /// A single order in the order book.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Order {
     pub order_id: u64,
     pub side: Side,
@@ -87,63 +290,128 @@ pub struct Order {
     /// For a size-decrease modify, this stays as the original Add's sequence.
     /// For a size-increase or price change, it becomes the Modify's sequence.
     pub sequence: u32,
+    /// Participant the order is attributed to, such as an ITCH MPID, if the feed says.
+    pub owner: Option<u32>,
+    /// Time in nanoseconds since the UNIX epoch from which `OrderBook::purge_expired`
+    /// removes the order; it never expires if `None`.
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+}
+
+impl Order {
+    /// True for the orders `OrderBook::seed_from_depth` and `OrderBook::from_mbp` create,
+    /// whose ids lie in the reserved range from `SYNTHETIC_ORDER_ID_MIN`.
+    pub fn is_synthetic(&self) -> bool {
+        is_synthetic_order_id(self.order_id)
+    }
 }
 
 /// Price level tracking individual orders (Market-By-Order).
 ///
-/// Orders are maintained in price-time (FIFO) order using the exchange sequence number
-/// as the BTreeMap key. A lower sequence means an earlier (better) queue position.
-#[derive(Debug)]
+/// Orders are maintained in price-time (FIFO) order using the exchange sequence number.
+/// A lower sequence means an earlier (better) queue position.
+///
+/// Two levels are equal if they queue the same orders at the same price.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "LevelRecord", into = "LevelRecord")]
 pub struct OrderLevel {
     pub price: i64,
-    /// BTreeMap from (sequence, order_id) → Order. The composite key ensures uniqueness
-    /// even when multiple orders share the same sequence (e.g. SNAPSHOT-phase Add records).
-    /// Natural iteration order equals queue order (sequence-primary, order_id tiebreaker).
-    queue: BTreeMap<(u32, u64), Order>,
-    /// Index from order_id → sequence for O(1) lookup.
-    order_index: HashMap<u64, u32>,
+    /// Orders sorted by (sequence, order_id). The composite key ensures uniqueness even
+    /// when multiple orders share the same sequence (e.g. SNAPSHOT-phase Add records).
+    pub(crate) queue: OrderQueue,
+    /// Index from order_id → the queue slot holding the order.
+    pub(crate) order_index: OrderMap<u32>,
+}
+
+impl PartialEq for OrderLevel {
+    fn eq(&self, other: &Self) -> bool {
+        self.price == other.price && self.queue == other.queue
+    }
+}
+
+impl Eq for OrderLevel {}
+
+/// The serialized form of an `OrderLevel`. Slots are not state, so the index is written
+/// by sequence, as it was before levels had slots, and rebuilt when read.
+#[derive(Serialize, Deserialize)]
+struct LevelRecord {
+    price: i64,
+    queue: OrderQueue,
+    order_index: OrderMap<u32>,
+}
+
+impl From<LevelRecord> for OrderLevel {
+    fn from(record: LevelRecord) -> Self {
+        let order_index = record
+            .queue
+            .entries()
+            .map(|(slot, order)| (order.order_id, slot))
+            .collect();
+        Self {
+            price: record.price,
+            queue: record.queue,
+            order_index,
+        }
+    }
+}
+
+impl From<OrderLevel> for LevelRecord {
+    fn from(level: OrderLevel) -> Self {
+        let order_index = level
+            .queue
+            .values()
+            .map(|order| (order.order_id, order.sequence))
+            .collect();
+        Self {
+            price: level.price,
+            queue: level.queue,
+            order_index,
+        }
+    }
 }
 
 impl OrderLevel {
     pub fn new(price: i64) -> Self {
         Self {
             price,
-            queue: BTreeMap::new(),
-            order_index: HashMap::new(),
+            queue: OrderQueue::default(),
+            order_index: OrderMap::default(),
         }
     }
 
-    /// Iterates over orders and sums size.
+    /// Total size of the orders at this level.
     pub fn total_qty(&self) -> u64 {
-        self.queue.values().map(|o| o.size).sum()
+        self.queue.qty()
     }
 
     /// Add order. If an order with the same `order_id` already exists it is removed first
     /// (idempotent overwrite with a warning).
     pub fn add_order(&mut self, order: Order) {
-        let key = (order.sequence, order.order_id);
         // Remove any existing entry for this order_id before inserting
-        if let Some(old_seq) = self.order_index.get(&order.order_id).copied() {
-            warn!(
+        let old = self
+            .order_index
+            .get(&order.order_id)
+            .and_then(|&slot| self.queue.remove(slot, order.order_id));
+        if let Some(old) = old {
+            debug!(
                 "Order {} already exists at sequence {}, overwriting at sequence {}",
-                order.order_id, old_seq, order.sequence
+                order.order_id, old.sequence, order.sequence
             );
-            self.queue.remove(&(old_seq, order.order_id));
         }
-        self.order_index.insert(order.order_id, order.sequence);
-        self.queue.insert(key, order);
+        let slot = self.queue.insert(order);
+        self.order_index.insert(order.order_id, slot);
     }
 
     /// Remove order entirely (idempotent — no-op with warning if not found).
     pub fn remove_order(&mut self, order_id: u64) -> Option<Order> {
-        let seq = match self.order_index.entry(order_id) {
+        let slot = match self.order_index.entry(order_id) {
             Entry::Vacant(_) => {
-                warn!("Order {} not found in level, ignoring removal", order_id);
+                debug!("Order {} not found in level, ignoring removal", order_id);
                 return None;
             }
             Entry::Occupied(e) => e.remove(),
         };
-        self.queue.remove(&(seq, order_id))
+        self.queue.remove(slot, order_id)
     }
 
     /// Update order size in place without changing queue position.
@@ -152,23 +420,31 @@ impl OrderLevel {
         order_id: u64,
         new_size: u64,
     ) -> Result<(), OrderBookError> {
-        let seq = self
+        let slot = self
             .order_index
             .get(&order_id)
             .copied()
             .ok_or(OrderBookError::OrderNotFound(order_id))?;
-        let order = self
-            .queue
-            .get_mut(&(seq, order_id))
+        self.queue
+            .set_size(slot, order_id, new_size)
             .ok_or(OrderBookError::OrderNotFound(order_id))?;
-        order.size = new_size;
         Ok(())
     }
 
     /// Gets an order by id.
     pub fn get_order(&self, order_id: u64) -> Option<&Order> {
-        let seq = *self.order_index.get(&order_id)?;
-        self.queue.get(&(seq, order_id))
+        let slot = *self.order_index.get(&order_id)?;
+        self.queue.get(slot, order_id)
+    }
+
+    /// The order at the head of the queue.
+    pub fn front(&self) -> Option<&Order> {
+        self.queue.front()
+    }
+
+    /// The order at the back of the queue.
+    pub fn back(&self) -> Option<&Order> {
+        self.queue.back()
     }
 
     pub fn is_empty(&self) -> bool {
@@ -180,33 +456,61 @@ impl OrderLevel {
         self.queue.len()
     }
 
-    /// Returns the 0-indexed queue position of the given order (0 = first/best). O(n).
-    /// Returns `None` if the order is not in this level.
+    /// Returns the 0-indexed queue position of the given order (0 = first/best).
+    /// O(n). Returns `None` if the order is not in this level.
     pub fn queue_position(&self, order_id: u64) -> Option<usize> {
-        let seq = *self.order_index.get(&order_id)?;
-        let key = (seq, order_id);
-        Some(self.queue.range(..key).count())
+        let slot = *self.order_index.get(&order_id)?;
+        self.queue.position(slot, order_id)
     }
 
     /// Returns the total quantity of all orders ahead of this order in the queue. O(n).
     /// Returns `None` if the order is not in this level.
     pub fn queue_depth_ahead(&self, order_id: u64) -> Option<u64> {
-        let seq = *self.order_index.get(&order_id)?;
-        let key = (seq, order_id);
-        Some(self.queue.range(..key).map(|(_, o)| o.size).sum())
+        let position = self.queue_position(order_id)?;
+        Some(self.queue.ahead(position).map(|o| o.size).sum())
     }
 }
 
 /// Market-By-Order orderbook tracking individual orders.
 /// Prices are integers (cents, ticks, etc.)
-#[derive(Debug, Default)]
+///
+/// The best level of each side is cached, so the levels are read through `bids` and
+/// `asks` and only changed by the book's methods.
+///
+/// A clone has the same orders in the same queue positions, the same anomaly counters,
+/// depth limit and audit journal, and evolves independently from there.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct OrderBook {
-    pub bids: BTreeMap<i64, OrderLevel>,
-    pub asks: BTreeMap<i64, OrderLevel>,
+    bids: BTreeMap<i64, OrderLevel>,
+    asks: BTreeMap<i64, OrderLevel>,
 
     /// Mapping from order_id -> price for fast order lookup.
     /// Side is stored in the Order itself.
-    order_index: HashMap<u64, i64>,
+    pub(crate) order_index: OrderMap<i64>,
+
+    /// Price and quantity of the best level per side, kept up to date by every change
+    /// to the levels so queries need no tree traversal.
+    pub(crate) bbo: Bbo,
+
+    anomalies: Anomalies,
+
+    /// Most levels kept per side, unlimited if `None`; see `with_max_levels_per_side`.
+    #[serde(default)]
+    max_levels: Option<usize>,
+    #[serde(default)]
+    depth_policy: DepthLimitPolicy,
+
+    /// Orders with an expiry, earliest first, for `purge_expired`.
+    #[serde(default)]
+    pub(crate) expiries: ExpiryQueue,
+
+    /// Emptied levels kept to be reused by new ones.
+    #[serde(skip)]
+    pub(crate) spare_levels: LevelPool,
+
+    /// Audit journal, off unless enabled with `with_events`.
+    #[serde(skip)]
+    events: EventJournal,
 }
 
 impl OrderBook {
@@ -214,8 +518,319 @@ impl OrderBook {
         Self::default()
     }
 
+    /// Anomalies encountered so far.
+    pub fn anomalies(&self) -> Anomalies {
+        self.anomalies
+    }
+
+    pub(crate) fn restore_anomalies(&mut self, anomalies: Anomalies) {
+        self.anomalies = anomalies;
+    }
+
+    /// Journals every change to the book from now on, keeping as many events as
+    /// `retention` allows. Events journaled so far are dropped.
+    pub fn with_events(mut self, retention: EventRetention) -> Self {
+        self.events = EventJournal::new(retention);
+        self
+    }
+
+    pub fn event_retention(&self) -> EventRetention {
+        self.events.retention()
+    }
+
+    /// Keeps at most `max_levels` price levels per side, for books whose far levels are
+    /// never queried. This makes the book a lossy view: liquidity beyond the limit is
+    /// not tracked, so when the book shrinks back from the touch the levels that were
+    /// left out are simply absent rather than reappearing.
+    ///
+    /// An Add that would open a level worse than the worst of `max_levels` full levels
+    /// is handled as set with `with_depth_limit_policy`. An Add or Modify that opens a
+    /// level better than that, such as a new best price, pushes the worst level out: its
+    /// orders are removed as if cancelled and, under `DepthLimitPolicy::Drop`, counted in
+    /// `Anomalies::depth_dropped`. Levels beyond the limit already in the book are
+    /// trimmed the same way. Replayed events are applied as journaled, without the limit.
+    pub fn with_max_levels_per_side(mut self, max_levels: usize) -> Self {
+        self.max_levels = Some(max_levels);
+        self.trim_to_depth(Side::Bid);
+        self.trim_to_depth(Side::Ask);
+        self
+    }
+
+    /// Sets what happens to an Add beyond the depth limit; `DepthLimitPolicy::Drop` by
+    /// default.
+    pub fn with_depth_limit_policy(mut self, policy: DepthLimitPolicy) -> Self {
+        self.depth_policy = policy;
+        self
+    }
+
+    pub fn max_levels_per_side(&self) -> Option<usize> {
+        self.max_levels
+    }
+
+    pub fn depth_limit_policy(&self) -> DepthLimitPolicy {
+        self.depth_policy
+    }
+
+    /// Fails with `OrderBookError::BeyondDepthLimit` if `order` would open a level
+    /// worse than the worst level of a side already holding as many levels as the
+    /// depth limit allows. Always succeeds for an unlimited book.
+    pub fn check_depth_limit(&self, order: &Order) -> Result<(), OrderBookError> {
+        let Some(max_levels) = self.max_levels else {
+            return Ok(());
+        };
+        let levels = self.levels(order.side);
+        if levels.len() < max_levels || levels.contains_key(&order.price) {
+            return Ok(());
+        }
+        let worst = match order.side {
+            Side::Bid => levels.first_key_value(),
+            Side::Ask => levels.last_key_value(),
+        };
+        match worst.is_none_or(|(&worst, _)| is_better(order.side, worst, order.price)) {
+            true => Err(OrderBookError::BeyondDepthLimit {
+                order_id: order.order_id,
+                side: order.side,
+                price: order.price,
+                max_levels,
+            }),
+            false => Ok(()),
+        }
+    }
+
+    /// Removes the worst levels of `side` until it is within the depth limit, counting
+    /// their orders as dropped under `DepthLimitPolicy::Drop`.
+    fn trim_to_depth(&mut self, side: Side) {
+        let Some(max_levels) = self.max_levels else {
+            return;
+        };
+        while self.levels(side).len() > max_levels {
+            let levels = self.levels(side);
+            let worst = match side {
+                Side::Bid => levels.first_key_value(),
+                Side::Ask => levels.last_key_value(),
+            };
+            let Some((&price, level)) = worst else {
+                break;
+            };
+            debug!("Trimming {side:?} level {price} beyond the {max_levels}-level depth limit");
+            let order_ids: Vec<u64> = level.queue.values().map(|order| order.order_id).collect();
+            for order_id in order_ids {
+                self.remove_order(order_id);
+                if self.depth_policy == DepthLimitPolicy::Drop {
+                    self.anomalies.depth_dropped += 1;
+                }
+            }
+        }
+    }
+
+    /// The journaled events, oldest first; none unless enabled with `with_events`.
+    pub fn events(&self) -> impl Iterator<Item = &BookEvent> {
+        self.events.events()
+    }
+
+    /// Takes the journaled events, oldest first. Journaling carries on.
+    pub fn drain_events(&mut self) -> Vec<BookEvent> {
+        self.events.drain()
+    }
+
+    /// Rebuilds a book by applying `events` to an empty one, which gives the book that
+    /// journaled them, queue positions included, if they start from its creation or a
+    /// `Cleared`. Anomaly counters only count the replaced orders of Adds.
+    pub fn replay_events(events: &[BookEvent]) -> OrderBook {
+        let mut book = OrderBook::new();
+        events.iter().for_each(|event| book.apply_event(event));
+        book
+    }
+
+    /// Makes the change journaled as `event`, journaling it again if this book has
+    /// journaling enabled.
+    pub fn apply_event(&mut self, event: &BookEvent) {
+        match event.change {
+            BookChange::Added(order) => {
+                self.place_order(order);
+            }
+            BookChange::Removed { order_id, .. }
+            | BookChange::Filled {
+                order_id,
+                new_size: 0,
+                ..
+            } => {
+                self.take_order(order_id);
+            }
+            BookChange::Modified { order, .. } => {
+                // The order's sequence keeps its place in the queue when re-added
+                self.take_order(order.order_id);
+                if order.size > 0 {
+                    self.place_order(order);
+                }
+            }
+            BookChange::Filled {
+                order_id, new_size, ..
+            } => {
+                self.update_order_size(order_id, new_size);
+            }
+            BookChange::Cleared => {
+                self.take_orders_unjournaled();
+            }
+        }
+        self.events.record(event.change);
+        self.debug_validate();
+    }
+
+    /// Removes all orders and returns the previous book. The anomaly counters and the
+    /// audit journal carry over, so they stay cumulative across clears.
+    pub fn take_orders(&mut self) -> OrderBook {
+        let old = self.take_orders_unjournaled();
+        self.events.record(BookChange::Cleared);
+        old
+    }
+
+    /// Removes every order on `side`, best level first and in queue order within a
+    /// level, as if each were cancelled, and returns them with what each removal left.
+    /// The other side is untouched.
+    pub fn clear_side(&mut self, side: Side) -> Vec<RemoveOrderInfo> {
+        let mut levels: Vec<&OrderLevel> = self.levels(side).values().collect();
+        if side == Side::Bid {
+            levels.reverse();
+        }
+        let order_ids: Vec<u64> = levels
+            .into_iter()
+            .flat_map(|level| level.queue.values().map(|order| order.order_id))
+            .collect();
+        order_ids
+            .into_iter()
+            .filter_map(|order_id| self.remove_order(order_id))
+            .collect()
+    }
+
+    fn take_orders_unjournaled(&mut self) -> OrderBook {
+        let anomalies = self.anomalies;
+        let events = mem::take(&mut self.events);
+        let mut old = mem::take(self);
+        self.anomalies = anomalies;
+        self.spare_levels = mem::take(&mut old.spare_levels);
+        self.max_levels = old.max_levels;
+        self.depth_policy = old.depth_policy;
+        self.events = events;
+        old
+    }
+
+    /// Puts back the orders and anomaly counters of `old`, a book `take_orders` took,
+    /// journaling a clear and the orders as added. The depth limit stays this book's.
+    pub(crate) fn restore_orders(&mut self, old: OrderBook) {
+        let events = mem::take(&mut self.events);
+        let (max_levels, depth_policy) = (self.max_levels, self.depth_policy);
+        *self = old;
+        self.max_levels = max_levels;
+        self.depth_policy = depth_policy;
+        self.events = events;
+        self.events.record(BookChange::Cleared);
+        for order in self
+            .bids
+            .values()
+            .chain(self.asks.values())
+            .flat_map(|level| level.queue.values())
+        {
+            self.events.record(BookChange::Added(*order));
+        }
+        self.debug_validate();
+    }
+
+    /// Seeds the book with aggregated depth, such as the levels of an MBP-10 record, so
+    /// that MBO messages can be applied to a book that was not built from the start of
+    /// the session.
+    ///
+    /// Each `(side, price, size, count)` level becomes synthetic orders as chosen by
+    /// `mode`, with ids from the reserved range (see `is_synthetic_order_id`) and
+    /// sequence 0, so they stay ahead of real orders at the same price. A count of 0 is
+    /// treated as 1 and levels with no size are skipped. Synthetic orders from an
+    /// earlier seed are removed first; real orders are left in place.
+    ///
+    /// Returns the number of synthetic orders added.
+    pub fn seed_from_depth(&mut self, levels: &[(Side, i64, u64, u32)], mode: SeedMode) -> usize {
+        let synthetic: Vec<u64> = self
+            .order_index
+            .keys()
+            .copied()
+            .filter(|&id| is_synthetic_order_id(id))
+            .collect();
+        for order_id in synthetic {
+            self.remove_order(order_id);
+        }
+
+        let mut order_id = SYNTHETIC_ORDER_ID_MIN;
+        let mut added = 0;
+        for &(side, price, size, count) in levels {
+            if size == 0 {
+                continue;
+            }
+            let count = match mode {
+                SeedMode::Aggregate => 1,
+                // Never more orders than units of size, so none is empty
+                SeedMode::PerOrder => u64::from(count.max(1)).min(size),
+            };
+            let (each, remainder) = (size / count, size % count);
+            for i in 0..count {
+                self.add_order(Order {
+                    order_id,
+                    side,
+                    price,
+                    size: if i == 0 { each + remainder } else { each },
+                    sequence: 0,
+                    owner: None,
+                    expires_at: None,
+                });
+                order_id += 1;
+                added += 1;
+            }
+        }
+        added
+    }
+
+    /// A book holding the levels of a market-by-price snapshot, such as one read back
+    /// from this crate's parquet exports, as synthetic orders (see `seed_from_depth`),
+    /// ready for MBO messages or matching.
+    ///
+    /// `MarketByPrice::from` the book gives back `mbp` without its metadata under
+    /// `SeedMode::PerOrder`, as long as no level has more orders than units of size;
+    /// under `SeedMode::Aggregate` every level counts one order. Levels with no
+    /// quantity are left out.
+    pub fn from_mbp(mbp: &MarketByPrice, mode: SeedMode) -> OrderBook {
+        let sides = [(Side::Bid, &mbp.bids), (Side::Ask, &mbp.asks)];
+        let levels: Vec<_> = sides
+            .into_iter()
+            .flat_map(|(side, levels)| {
+                levels.values().map(move |level| {
+                    let count = u32::try_from(level.order_count).unwrap_or(u32::MAX);
+                    (side, level.price, level.total_quantity, count)
+                })
+            })
+            .collect();
+        let mut book = OrderBook::new();
+        book.seed_from_depth(&levels, mode);
+        book
+    }
+
+    /// The bid levels by price, best (highest) last.
+    pub fn bids(&self) -> &BTreeMap<i64, OrderLevel> {
+        &self.bids
+    }
+
+    /// The ask levels by price, best (lowest) first.
+    pub fn asks(&self) -> &BTreeMap<i64, OrderLevel> {
+        &self.asks
+    }
+
+    /// Gets the side of the book (bids or asks) for the given side.
+    pub(crate) fn levels(&self, side: Side) -> &BTreeMap<i64, OrderLevel> {
+        match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        }
+    }
+
     /// Gets the side of the book (bids or asks) for the given side.
-    fn levels_mut(&mut self, side: Side) -> &mut BTreeMap<i64, OrderLevel> {
+    pub(crate) fn levels_mut(&mut self, side: Side) -> &mut BTreeMap<i64, OrderLevel> {
         match side {
             Side::Bid => &mut self.bids,
             Side::Ask => &mut self.asks,
@@ -226,8 +841,122 @@ impl OrderBook {
     /// possibly with changed price and size.
     ///
     /// Returns information about the added order and its price level.
+    ///
+    /// With a depth limit, an order beyond it is left out, with any order of the same
+    /// id removed, and reported as `dropped`; see `with_max_levels_per_side`.
     pub fn add_order(&mut self, order: Order) -> AddOrderInfo {
+        if self.check_depth_limit(&order).is_err() {
+            return self.drop_beyond_depth(order);
+        }
+        let info = self.place_order(order);
+        self.events.record(BookChange::Added(order));
+        self.trim_to_depth(order.side);
+        self.debug_validate();
+        info
+    }
+
+    /// Adds an order as `add_order` does, but under `DepthLimitPolicy::Reject` fails
+    /// with `OrderBookError::BeyondDepthLimit` for an order beyond the depth limit,
+    /// leaving the book untouched.
+    pub fn try_add_order(&mut self, order: Order) -> Result<AddOrderInfo, OrderBookError> {
+        if self.depth_policy == DepthLimitPolicy::Reject {
+            self.check_depth_limit(&order)?;
+        }
+        Ok(self.add_order(order))
+    }
+
+    /// Leaves out an Add beyond the depth limit. An order with the same id would have
+    /// been replaced, so it is removed.
+    fn drop_beyond_depth(&mut self, order: Order) -> AddOrderInfo {
+        debug!(
+            "Order {} at {:?} price {} is beyond the depth limit, dropping",
+            order.order_id, order.side, order.price
+        );
+        let replaced = match self.order_index.contains_key(&order.order_id) {
+            true => {
+                self.anomalies.duplicate_add += 1;
+                self.remove_order(order.order_id).map(|info| info.order)
+            }
+            false => None,
+        };
+        if self.depth_policy == DepthLimitPolicy::Drop {
+            self.anomalies.depth_dropped += 1;
+        }
+        AddOrderInfo {
+            order,
+            level_qty: 0,
+            level_order_count: 0,
+            new_level: false,
+            replaced: replaced.is_some(),
+            replaced_level: replaced.map(|old| (old.side, old.price)),
+            dropped: true,
+        }
+    }
+
+    /// `add_order` without journaling.
+    fn place_order(&mut self, order: Order) -> AddOrderInfo {
+        let (replaced, replaced_level) = self.insert_order(order);
+        let level = self
+            .levels(order.side)
+            .get(&order.price)
+            .expect("level must exist after add");
+        let info = AddOrderInfo {
+            order,
+            level_qty: level.total_qty(),
+            level_order_count: level.order_count(),
+            new_level: level.order_count() == 1,
+            replaced,
+            replaced_level,
+            dropped: false,
+        };
+        self.update_best(order.side, order.price, info.level_qty);
+        info
+    }
+
+    /// Adds orders as if by `add_order` one at a time, including for repeated ids, but
+    /// without working out an `AddOrderInfo` per order: the quickest way to seed a book.
+    /// Reserves index space for the iterator's lower size bound first.
+    ///
+    /// With a depth limit, the levels beyond it are trimmed once all orders are in rather
+    /// than as each is added, so the orders left out are journaled as added and removed.
+    pub fn add_orders(&mut self, orders: impl IntoIterator<Item = Order>) {
+        let orders = orders.into_iter();
+        self.reserve(orders.size_hint().0);
+        for order in orders {
+            self.insert_order(order);
+            self.events.record(BookChange::Added(order));
+        }
+        self.bbo = Bbo {
+            bid: self
+                .bids
+                .last_key_value()
+                .map(|(&price, level)| (price, level.total_qty())),
+            ask: self
+                .asks
+                .first_key_value()
+                .map(|(&price, level)| (price, level.total_qty())),
+        };
+        self.trim_to_depth(Side::Bid);
+        self.trim_to_depth(Side::Ask);
+        self.debug_validate();
+    }
+
+    /// Reserves order index space for at least `additional_orders` more orders. Price
+    /// levels live in a `BTreeMap`, which has no capacity to reserve.
+    pub fn reserve(&mut self, additional_orders: usize) {
+        self.order_index.reserve(additional_orders);
+    }
+
+    /// Inserts `order` after removing any order with the same id, returning whether one
+    /// was replaced and where it was. The cached best level of the order's side is left
+    /// to the caller.
+    fn insert_order(&mut self, order: Order) -> (bool, Option<(Side, i64)>) {
         // If order exists, remove it from old location first (handles price changes)
+        let replaced = self.order_index.contains_key(&order.order_id);
+        if replaced {
+            self.anomalies.duplicate_add += 1;
+        }
+        let mut replaced_level = None;
         if let Some(&old_price) = self.order_index.get(&order.order_id) {
             // Look up the old order to get its side
             let old_side = self
@@ -243,53 +972,89 @@ impl OrderBook {
                 });
 
             if let Some(old_side) = old_side {
-                warn!(
+                debug!(
                     "Order {} already exists at {:?} price {}, moving to {:?} price {}",
                     order.order_id, old_side, old_price, order.side, order.price
                 );
 
-                let old_levels = self.levels_mut(old_side);
-                if let Some(level) = old_levels.get_mut(&old_price) {
+                replaced_level = Some((old_side, old_price));
+                if let Some(level) = self.levels_mut(old_side).get_mut(&old_price) {
                     level.remove_order(order.order_id);
-                    if level.is_empty() {
-                        old_levels.remove(&old_price);
+                    match level.is_empty() {
+                        true => self.remove_level(old_side, old_price),
+                        false => {
+                            let level_qty = level.total_qty();
+                            self.update_best(old_side, old_price, level_qty);
+                        }
                     }
                 }
             }
         }
 
         let price = order.price;
-        let side = order.side;
-        let order_id = order.order_id;
-        let order_copy = order;
-        let levels = self.levels_mut(side);
-
+        let levels = match order.side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        };
         levels
             .entry(price)
-            .or_insert_with(|| OrderLevel::new(price))
+            .or_insert_with(|| self.spare_levels.take(price))
             .add_order(order);
+        self.order_index.insert(order.order_id, price);
+        self.schedule_expiry(&order);
+        (replaced, replaced_level)
+    }
 
-        self.order_index.insert(order_id, price);
+    /// Records that the non-empty level at `price` on `side` now holds `level_qty`,
+    /// caching it if it is the best level.
+    fn update_best(&mut self, side: Side, price: i64, level_qty: u64) {
+        let best = match side {
+            Side::Bid => &mut self.bbo.bid,
+            Side::Ask => &mut self.bbo.ask,
+        };
+        if best.is_none_or(|(best, _)| price == best || is_better(side, price, best)) {
+            *best = Some((price, level_qty));
+        }
+    }
 
-        // Read level info after insertion (mutable borrow has ended)
-        let levels = match side {
-            Side::Bid => &self.bids,
-            Side::Ask => &self.asks,
+    /// Drops the level at `price` on `side`, caching the next level if it was the best.
+    fn remove_level(&mut self, side: Side, price: i64) {
+        let (levels, best) = match side {
+            Side::Bid => (&mut self.bids, &mut self.bbo.bid),
+            Side::Ask => (&mut self.asks, &mut self.bbo.ask),
         };
-        let level = levels.get(&price).expect("level must exist after add");
-        AddOrderInfo {
-            order: order_copy,
-            level_qty: level.total_qty(),
-            level_order_count: level.order_count(),
-            new_level: level.order_count() == 1,
+        if let Some(level) = levels.remove(&price) {
+            self.spare_levels.give(level);
+        }
+        if best.is_some_and(|(best, _)| best == price) {
+            *best = match side {
+                Side::Bid => levels.last_key_value(),
+                Side::Ask => levels.first_key_value(),
+            }
+            .map(|(&price, level)| (price, level.total_qty()));
         }
     }
 
     /// Removes an order from the order book. If it is not found, no operation is performed.
     /// Returns information about the removed order and the remaining level state.
     pub fn remove_order(&mut self, order_id: u64) -> Option<RemoveOrderInfo> {
+        let info = self.take_order(order_id)?;
+        let order = info.order;
+        self.events.record(BookChange::Removed {
+            order_id,
+            side: order.side,
+            price: order.price,
+            size: order.size,
+        });
+        self.debug_validate();
+        Some(info)
+    }
+
+    /// `remove_order` without journaling.
+    fn take_order(&mut self, order_id: u64) -> Option<RemoveOrderInfo> {
         let Some(price) = self.order_index.remove(&order_id) else {
-            warn!("Order {} not found in index, ignoring removal", order_id);
+            self.anomalies.unknown_cancel += 1;
+            debug!("Order {} not found in index, ignoring removal", order_id);
             return None;
         };
 
@@ -306,15 +1071,20 @@ impl OrderBook {
 
         // Capture level info and clean up empty levels
         if let Some(order) = removed {
-            let levels = self.levels_mut(order.side);
-            let (remaining_qty, remaining_count, level_removed) = match levels.get(&price) {
-                Some(level) if level.is_empty() => {
-                    levels.remove(&price);
-                    (0, 0, true)
-                }
-                Some(level) => (level.total_qty(), level.order_count(), false),
-                None => (0, 0, true),
-            };
+            let (remaining_qty, remaining_count, level_removed) =
+                match self.levels(order.side).get(&price) {
+                    Some(level) if level.is_empty() => {
+                        self.remove_level(order.side, price);
+                        (0, 0, true)
+                    }
+                    Some(level) => {
+                        let level_qty = level.total_qty();
+                        let level_count = level.order_count();
+                        self.update_best(order.side, price, level_qty);
+                        (level_qty, level_count, false)
+                    }
+                    None => (0, 0, true),
+                };
 
             Some(RemoveOrderInfo {
                 order,
@@ -323,7 +1093,8 @@ impl OrderBook {
                 level_removed,
             })
         } else {
-            warn!("Price level {} not found for order {}", price, order_id);
+            self.anomalies.level_missing += 1;
+            debug!("Price level {} not found for order {}", price, order_id);
             None
         }
     }
@@ -346,33 +1117,127 @@ impl OrderBook {
     fn update_order_size(&mut self, order_id: u64, new_size: u64) -> Option<UpdateSizeInfo> {
         let &price = self.order_index.get(&order_id)?;
 
-        if let Some(level) = self.bids.get_mut(&price)
-            && level.get_order(order_id).is_some()
-        {
-            level.update_size_in_place(order_id, new_size).ok()?;
-            let queue_position = level.queue_position(order_id).unwrap_or(0);
-            let order = *level.get_order(order_id)?;
-            return Some(UpdateSizeInfo {
-                order,
-                level_qty: level.total_qty(),
-                level_order_count: level.order_count(),
-                queue_position,
+        let side = [Side::Bid, Side::Ask].into_iter().find(|&side| {
+            self.levels(side)
+                .get(&price)
+                .is_some_and(|level| level.get_order(order_id).is_some())
+        })?;
+        let level = self.levels_mut(side).get_mut(&price)?;
+        level.update_size_in_place(order_id, new_size).ok()?;
+        let info = UpdateSizeInfo {
+            order: *level.get_order(order_id)?,
+            level_qty: level.total_qty(),
+            level_order_count: level.order_count(),
+            queue_position: level.queue_position(order_id).unwrap_or(0),
+        };
+        self.update_best(side, price, info.level_qty);
+        Some(info)
+    }
+
+    /// Matches an incoming order against the opposite side, best price first and in
+    /// queue order within a level, until it is filled or no longer crosses.
+    ///
+    /// Executed resting orders are depleted in place (keeping their queue position) or
+    /// removed when fully filled. The incoming order itself is never added; the caller
+    /// decides what to do with the unfilled residual (`order.size` minus the executed sizes).
+    pub fn match_order(&mut self, order: &Order) -> Vec<Execution> {
+        self.match_order_with_policy(order, SelfMatchPolicy::Allow)
+            .executions
+    }
+
+    /// Matches as `match_order` does, applying `policy` whenever the incoming order
+    /// reaches a resting order of its own owner.
+    pub fn match_order_with_policy(
+        &mut self,
+        order: &Order,
+        policy: SelfMatchPolicy,
+    ) -> MatchOutcome {
+        let mut outcome = MatchOutcome::default();
+        let mut remaining = order.size;
+        while remaining > 0 {
+            let Some(resting) = self.crossing_front(order.side, order.price) else {
+                break;
+            };
+            if order.owner.is_some() && resting.owner == order.owner {
+                match policy {
+                    SelfMatchPolicy::Allow => {}
+                    SelfMatchPolicy::CancelResting => {
+                        let info = self
+                            .remove_order(resting.order_id)
+                            .expect("front order must be in the book");
+                        outcome.cancelled.push(info);
+                        continue;
+                    }
+                    SelfMatchPolicy::CancelIncoming => {
+                        outcome.incoming_cancelled = true;
+                        break;
+                    }
+                }
+            }
+
+            let size = remaining.min(resting.size);
+            remaining -= size;
+            if size == resting.size {
+                self.take_order(resting.order_id);
+            } else {
+                self.update_order_size(resting.order_id, resting.size - size);
+            }
+            self.events.record(BookChange::Filled {
+                order_id: resting.order_id,
+                side: resting.side,
+                price: resting.price,
+                old_size: resting.size,
+                new_size: resting.size - size,
             });
+            outcome.executions.push(Execution { resting, size });
         }
-        if let Some(level) = self.asks.get_mut(&price)
-            && level.get_order(order_id).is_some()
-        {
-            level.update_size_in_place(order_id, new_size).ok()?;
-            let queue_position = level.queue_position(order_id).unwrap_or(0);
-            let order = *level.get_order(order_id)?;
-            return Some(UpdateSizeInfo {
-                order,
-                level_qty: level.total_qty(),
-                level_order_count: level.order_count(),
-                queue_position,
-            });
+        self.debug_validate();
+        outcome
+    }
+
+    /// The first order in the queue of the best level opposite `side`, if that level
+    /// crosses `price`.
+    fn crossing_front(&self, side: Side, price: i64) -> Option<Order> {
+        match side {
+            Side::Bid => self.asks.first_key_value(),
+            Side::Ask => self.bids.last_key_value(),
         }
-        None
+        .filter(|&(&level_price, _)| match side {
+            Side::Bid => level_price <= price,
+            Side::Ask => level_price >= price,
+        })
+        .and_then(|(_, level)| level.front())
+        .copied()
+    }
+
+    /// The resting orders of `owner` that an order to trade `qty` on `side` up to
+    /// `price` would reach, matching best price first and in queue order against
+    /// everything in its way. The book is left unchanged.
+    pub fn would_self_match(
+        &self,
+        side: Side,
+        price: i64,
+        qty: u64,
+        owner: u32,
+    ) -> SelfMatchReport {
+        let levels: Box<dyn Iterator<Item = &OrderLevel>> = match side {
+            Side::Bid => Box::new(self.asks.range(..=price).map(|(_, level)| level)),
+            Side::Ask => Box::new(self.bids.range(price..).rev().map(|(_, level)| level)),
+        };
+        let mut remaining = qty;
+        let matches = levels
+            .flat_map(|level| level.queue.values())
+            .map_while(|resting| {
+                let size = remaining.min(resting.size);
+                remaining -= size;
+                (size > 0).then_some(Execution {
+                    resting: *resting,
+                    size,
+                })
+            })
+            .filter(|execution| execution.resting.owner == Some(owner))
+            .collect();
+        SelfMatchReport { matches }
     }
 
     /// Modifies an order's price and/or size.
@@ -384,62 +1249,290 @@ impl OrderBook {
     ///
     /// Returns `None` if the order is not found in the book.
     pub fn modify_order(&mut self, new_order: Order) -> Option<ModifyOrderInfo> {
-        let old = self.get_order(new_order.order_id).copied()?;
+        self.modify_order_with_policy(new_order, ModifyPriorityPolicy::ExchangeStyle)
+    }
+
+    /// Modifies an order's price and/or size, keeping or resetting its queue position
+    /// according to `policy`.
+    ///
+    /// A Modify to the other side never keeps the queue position, and one to a zero
+    /// size removes the order. A Modify without an owner or expiry keeps the order's.
+    ///
+    /// Returns `None` if the order is not found in the book.
+    pub fn modify_order_with_policy(
+        &mut self,
+        new_order: Order,
+        policy: ModifyPriorityPolicy,
+    ) -> Option<ModifyOrderInfo> {
+        let Some(old) = self.get_order(new_order.order_id).copied() else {
+            self.anomalies.unknown_modify += 1;
+            debug!("Order {} not found, ignoring modify", new_order.order_id);
+            return None;
+        };
+        let new_order = Order {
+            owner: new_order.owner.or(old.owner),
+            expires_at: new_order.expires_at.or(old.expires_at),
+            ..new_order
+        };
         let old_price = old.price;
         let old_size = old.size;
 
-        let retained = old_price == new_order.price && new_order.size <= old_size;
+        let retained = new_order.size > 0
+            && old.side == new_order.side
+            && old_price == new_order.price
+            && match policy {
+                ModifyPriorityPolicy::ExchangeStyle => new_order.size <= old_size,
+                ModifyPriorityPolicy::AlwaysPreserve => true,
+            };
+
+        let (order, level_qty, level_order_count) = if new_order.size == 0 {
+            // Nothing is left to rest, so the order leaves the book as if cancelled
+            let info = self
+                .take_order(new_order.order_id)
+                .expect("order must exist after get_order succeeded");
+            (
+                new_order,
+                info.remaining_level_qty,
+                info.remaining_level_count,
+            )
+        } else if retained {
+            let info = self
+                .update_order_size(new_order.order_id, new_order.size)
+                .expect("order must exist after get_order succeeded");
+            (info.order, info.level_qty, info.level_order_count)
+        } else {
+            self.take_order(new_order.order_id);
+            let info = self.place_order(new_order);
+            (info.order, info.level_qty, info.level_order_count)
+        };
+        self.events.record(BookChange::Modified {
+            order,
+            old_price,
+            old_size,
+        });
+        // A move to a new level may push the worst one out, which can be the order's own
+        self.trim_to_depth(order.side);
+        self.debug_validate();
+
+        Some(ModifyOrderInfo {
+            order,
+            old_price,
+            old_size,
+            level_qty,
+            level_order_count,
+            retained_queue_position: retained,
+        })
+    }
+
+    /// Changes the size of an order by `delta`, as a Modify under
+    /// `ModifyPriorityPolicy::ExchangeStyle`, and returns its new size.
+    pub fn adjust_order_size(&mut self, order_id: u64, delta: i64) -> Result<u64, OrderBookError> {
+        self.adjust_order_size_with_policy(order_id, delta, ModifyPriorityPolicy::ExchangeStyle)
+    }
+
+    /// Changes the size of an order by `delta` and returns its new size, keeping or
+    /// losing its queue position according to `policy`. An order that loses it goes
+    /// behind the last order of its level.
+    ///
+    /// A reduction to exactly zero removes the order, as a full fill does. Fails with
+    /// `OrderNotFound` for an order not in the book and `SizeUnderflow` for a reduction
+    /// below zero, leaving the book untouched; an increase saturates at `u64::MAX`.
+    pub fn adjust_order_size_with_policy(
+        &mut self,
+        order_id: u64,
+        delta: i64,
+        policy: ModifyPriorityPolicy,
+    ) -> Result<u64, OrderBookError> {
+        let old = self
+            .get_order(order_id)
+            .copied()
+            .ok_or(OrderBookError::OrderNotFound(order_id))?;
+        let size =
+            match delta < 0 {
+                true => old.size.checked_sub(delta.unsigned_abs()).ok_or(
+                    OrderBookError::SizeUnderflow {
+                        order_id,
+                        size: old.size,
+                        delta,
+                    },
+                )?,
+                false => old.size.saturating_add(delta.unsigned_abs()),
+            };
+        let sequence = match self
+            .levels(old.side)
+            .get(&old.price)
+            .and_then(OrderLevel::back)
+        {
+            Some(back) if back.order_id != order_id => back.sequence.saturating_add(1),
+            _ => old.sequence,
+        };
+        self.modify_order_with_policy(
+            Order {
+                size,
+                sequence,
+                ..old
+            },
+            policy,
+        );
+        Ok(size)
+    }
+
+    /// Price and quantity of the best bid level, from the cache.
+    pub fn best_bid(&self) -> Option<(i64, u64)> {
+        self.bbo.bid
+    }
+
+    /// Price and quantity of the best ask level, from the cache.
+    pub fn best_ask(&self) -> Option<(i64, u64)> {
+        self.bbo.ask
+    }
+
+    /// Best bid and ask together.
+    pub fn bbo(&self) -> Bbo {
+        self.bbo
+    }
+
+    /// Best ask minus best bid, or `None` if either side is empty. Saturates at the
+    /// bounds of `i64` for prices far enough apart.
+    pub fn spread(&self) -> Option<i64> {
+        spread(self.best_bid(), self.best_ask())
+    }
+
+    /// Midpoint of the best bid and ask, or `None` if either side is empty.
+    pub fn mid(&self) -> Option<f64> {
+        mid(self.best_bid(), self.best_ask())
+    }
+
+    /// `(bid_qty - ask_qty) / (bid_qty + ask_qty)` over the best `depth` levels per
+    /// side, from -1 (all asks) to 1 (all bids), or `None` if either side is empty.
+    pub fn imbalance(&self, depth: usize) -> Option<f64> {
+        imbalance(self.depth_bids(depth), self.depth_asks(depth))
+    }
+
+    /// Volume-weighted price of the best `depth` bid levels and of the best `depth` ask
+    /// levels, each weighted by the other side's quantity, so the mid leans towards the
+    /// thinner side. At depth 1 this is the microprice. `None` if either side is empty.
+    pub fn weighted_mid(&self, depth: usize) -> Option<f64> {
+        weighted_mid(self.depth_bids(depth), self.depth_asks(depth))
+    }
+
+    /// Cumulative liquidity curve of `side`: each level from the touch outward, with the
+    /// quantity of that level and all better ones, thinned to at most `max_points`
+    /// points. Thinning keeps the touch and the last level and spreads the rest evenly
+    /// between them, so the curve still spans the whole side.
+    pub fn depth_curve(&self, side: Side, max_points: usize) -> Vec<(i64, u64)> {
+        match side {
+            Side::Bid => depth_curve(self.depth_bids(usize::MAX), max_points),
+            Side::Ask => depth_curve(self.depth_asks(usize::MAX), max_points),
+        }
+    }
+
+    /// Like `depth_curve`, with cumulative notional in currency for prices in
+    /// fixed-point units of `1 / price_scale`.
+    pub fn notional_curve(
+        &self,
+        side: Side,
+        max_points: usize,
+        price_scale: i64,
+    ) -> Vec<(i64, f64)> {
+        match side {
+            Side::Bid => notional_curve(self.depth_bids(usize::MAX), max_points, price_scale),
+            Side::Ask => notional_curve(self.depth_asks(usize::MAX), max_points, price_scale),
+        }
+    }
 
-        let (order, level_qty, level_order_count) = if retained {
-            let info = self
-                .update_order_size(new_order.order_id, new_order.size)
-                .expect("order must exist after get_order succeeded");
-            (info.order, info.level_qty, info.level_order_count)
-        } else {
-            self.remove_order(new_order.order_id);
-            let info = self.add_order(new_order);
-            (info.order, info.level_qty, info.level_order_count)
-        };
+    /// Number of price levels on `side`.
+    pub fn level_count(&self, side: Side) -> usize {
+        self.levels(side).len()
+    }
 
-        Some(ModifyOrderInfo {
-            order,
-            old_price,
-            old_size,
-            level_qty,
-            level_order_count,
-            retained_queue_position: retained,
-        })
+    /// Quantity resting on `side`, summed over all its levels.
+    pub fn total_qty(&self, side: Side) -> u64 {
+        self.levels(side).values().map(OrderLevel::total_qty).sum()
     }
 
-    pub fn best_bid(&self) -> Option<(i64, u64)> {
+    /// Resting quantity, order count and share of each side per owner. Orders without
+    /// an owner are left out, so a book built from a feed without attribution gives an
+    /// empty map. Walks every order on each call.
+    pub fn participant_summary(&self) -> HashMap<u32, ParticipantStats> {
+        let mut summary: HashMap<u32, ParticipantStats> = HashMap::new();
+        for side in [Side::Bid, Side::Ask] {
+            let orders = self
+                .levels(side)
+                .values()
+                .flat_map(|level| level.queue.values());
+            for order in orders {
+                let Some(owner) = order.owner else {
+                    continue;
+                };
+                let stats = summary.entry(owner).or_default();
+                let stats = match side {
+                    Side::Bid => &mut stats.bid,
+                    Side::Ask => &mut stats.ask,
+                };
+                stats.qty += order.size;
+                stats.order_count += 1;
+            }
+        }
+        let (bid_qty, ask_qty) = (self.total_qty(Side::Bid), self.total_qty(Side::Ask));
+        for stats in summary.values_mut() {
+            stats.bid.share = share(stats.bid.qty, bid_qty);
+            stats.ask.share = share(stats.ask.qty, ask_qty);
+        }
+        summary
+    }
+
+    fn depth_bids(&self, depth: usize) -> impl Iterator<Item = (i64, u64)> {
         self.bids
             .iter()
-            .next_back()
+            .rev()
+            .take(depth)
             .map(|(&price, level)| (price, level.total_qty()))
     }
 
-    pub fn best_ask(&self) -> Option<(i64, u64)> {
+    fn depth_asks(&self, depth: usize) -> impl Iterator<Item = (i64, u64)> {
         self.asks
             .iter()
-            .next()
+            .take(depth)
             .map(|(&price, level)| (price, level.total_qty()))
     }
 
+    /// The best `n` bid levels as `(price, total_qty)`, highest price first.
     pub fn top_n_bids(&self, n: usize) -> Vec<(i64, u64)> {
-        self.bids
-            .iter()
-            .rev() // Highest to lowest
-            .take(n)
-            .map(|(&price, level)| (price, level.total_qty()))
-            .collect()
+        let mut levels = Vec::with_capacity(n.min(self.bids.len()));
+        self.top_n_bids_into(n, &mut levels);
+        levels
     }
 
+    /// The best `n` ask levels as `(price, total_qty)`, lowest price first.
     pub fn top_n_asks(&self, n: usize) -> Vec<(i64, u64)> {
-        self.asks
-            .iter()
-            .take(n)
-            .map(|(&price, level)| (price, level.total_qty()))
-            .collect()
+        let mut levels = Vec::with_capacity(n.min(self.asks.len()));
+        self.top_n_asks_into(n, &mut levels);
+        levels
+    }
+
+    /// Like `top_n_bids`, but clears `out` and writes into it, so a caller polling
+    /// every message can reuse one buffer instead of allocating.
+    pub fn top_n_bids_into(&self, n: usize, out: &mut Vec<(i64, u64)>) {
+        out.clear();
+        out.extend(self.depth_bids(n));
+    }
+
+    /// Like `top_n_asks`, but clears `out` and writes into it.
+    pub fn top_n_asks_into(&self, n: usize, out: &mut Vec<(i64, u64)>) {
+        out.clear();
+        out.extend(self.depth_asks(n));
+    }
+
+    /// The best `N` bid levels in a fixed-size array, with the number filled. Slots
+    /// past the count, when the book has fewer levels, are `(0, 0)`.
+    pub fn top_n_bids_array<const N: usize>(&self) -> ([(i64, u64); N], usize) {
+        fill_array(self.depth_bids(N))
+    }
+
+    /// The best `N` ask levels in a fixed-size array, with the number filled.
+    pub fn top_n_asks_array<const N: usize>(&self) -> ([(i64, u64); N], usize) {
+        fill_array(self.depth_asks(N))
     }
 
     /// Returns the 0-indexed queue position of the order within its price level (0 = best). O(n).
@@ -469,11 +1562,174 @@ impl OrderBook {
                     .and_then(|l| l.queue_depth_ahead(order_id))
             })
     }
+
+    /// Deterministic 64-bit hash of the resting orders, equal for books holding the same
+    /// orders in the same queue positions, across runs and platforms.
+    ///
+    /// Covers the order id, side, price and size of every order, visited bids then asks,
+    /// price ascending, each level in queue (FIFO) order; caches, limits and counters
+    /// are not included. Meant to compare replays, not as a cryptographic digest.
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = StateHasher::new();
+        for (side, levels) in [(Side::Bid, &self.bids), (Side::Ask, &self.asks)] {
+            for level in levels.values() {
+                for order in level.queue.values() {
+                    hasher.write_u64(order.order_id);
+                    hasher.write_u8(i8::from(side) as u8);
+                    hasher.write_u64(order.price as u64);
+                    hasher.write_u64(order.size);
+                }
+            }
+        }
+        hasher.finish()
+    }
+}
+
+/// FNV-1a, fixed by its specification so `state_hash` values can be compared between
+/// builds, unlike those of std's randomly seeded or unspecified hashers.
+pub(crate) struct StateHasher(u64);
+
+impl StateHasher {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    pub(crate) fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+
+    pub(crate) fn write_u8(&mut self, byte: u8) {
+        self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(Self::PRIME);
+    }
+
+    pub(crate) fn write_u64(&mut self, value: u64) {
+        for byte in value.to_le_bytes() {
+            self.write_u8(byte);
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Whether `a` is a better price than `b` on `side`.
+fn is_better(side: Side, a: i64, b: i64) -> bool {
+    match side {
+        Side::Bid => a > b,
+        Side::Ask => a < b,
+    }
+}
+
+/// Copies up to `N` levels into an array, returning it with the number copied.
+fn fill_array<const N: usize>(
+    levels: impl Iterator<Item = (i64, u64)>,
+) -> ([(i64, u64); N], usize) {
+    let mut out = [(0, 0); N];
+    let mut count = 0;
+    for (slot, level) in out.iter_mut().zip(levels) {
+        *slot = level;
+        count += 1;
+    }
+    (out, count)
+}
+
+// Book metrics over `(price, quantity)` levels, shared with `MarketByPrice` so both
+// give identical results.
+
+pub(crate) fn spread(bid: Option<(i64, u64)>, ask: Option<(i64, u64)>) -> Option<i64> {
+    Some(ask?.0.saturating_sub(bid?.0))
+}
+
+pub(crate) fn mid(bid: Option<(i64, u64)>, ask: Option<(i64, u64)>) -> Option<f64> {
+    Some((bid?.0 as f64 + ask?.0 as f64) / 2.0)
+}
+
+/// `qty` as a fraction of `total`, or 0 without a total.
+fn share(qty: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        qty as f64 / total as f64
+    }
+}
+
+/// Total quantity and volume-weighted price of `levels`, or `None` without quantity.
+fn depth_totals(levels: impl Iterator<Item = (i64, u64)>) -> Option<(u64, f64)> {
+    let (quantity, notional) = levels.fold((0u64, 0i128), |(quantity, notional), (price, qty)| {
+        (
+            quantity + qty,
+            notional + i128::from(price) * i128::from(qty),
+        )
+    });
+    (quantity > 0).then(|| (quantity, notional as f64 / quantity as f64))
+}
+
+pub(crate) fn imbalance(
+    bids: impl Iterator<Item = (i64, u64)>,
+    asks: impl Iterator<Item = (i64, u64)>,
+) -> Option<f64> {
+    let (bid_qty, _) = depth_totals(bids)?;
+    let (ask_qty, _) = depth_totals(asks)?;
+    let (bid_qty, ask_qty) = (bid_qty as f64, ask_qty as f64);
+    Some((bid_qty - ask_qty) / (bid_qty + ask_qty))
+}
+
+pub(crate) fn weighted_mid(
+    bids: impl Iterator<Item = (i64, u64)>,
+    asks: impl Iterator<Item = (i64, u64)>,
+) -> Option<f64> {
+    let (bid_qty, bid_price) = depth_totals(bids)?;
+    let (ask_qty, ask_price) = depth_totals(asks)?;
+    let (bid_qty, ask_qty) = (bid_qty as f64, ask_qty as f64);
+    Some((bid_price * ask_qty + ask_price * bid_qty) / (bid_qty + ask_qty))
+}
+
+pub(crate) fn depth_curve(
+    levels: impl Iterator<Item = (i64, u64)>,
+    max_points: usize,
+) -> Vec<(i64, u64)> {
+    let curve = levels
+        .scan(0u64, |total, (price, qty)| {
+            *total = total.saturating_add(qty);
+            Some((price, *total))
+        })
+        .collect();
+    downsample(curve, max_points)
+}
+
+pub(crate) fn notional_curve(
+    levels: impl Iterator<Item = (i64, u64)>,
+    max_points: usize,
+    price_scale: i64,
+) -> Vec<(i64, f64)> {
+    let curve = levels
+        .scan(0i128, |total, (price, qty)| {
+            *total += i128::from(price) * i128::from(qty);
+            Some((price, *total as f64 / price_scale as f64))
+        })
+        .collect();
+    downsample(curve, max_points)
+}
+
+/// At most `max_points` of `points`: the first, the last and the rest evenly spaced
+/// between them.
+fn downsample<T: Copy>(points: Vec<T>, max_points: usize) -> Vec<T> {
+    let len = points.len();
+    match max_points {
+        _ if len <= max_points => points,
+        0 => Vec::new(),
+        1 => vec![points[0]],
+        _ => (0..max_points)
+            .map(|i| points[i * (len - 1) / (max_points - 1)])
+            .collect(),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::generators::{MessageMix, OrderGenerator};
+    use crate::orderbook::{Action, MarketByPrice};
 
     /// Helper to create an Order for tests.
     /// Uses `order_id as u32` for the sequence so each order gets a distinct,
@@ -485,7 +1741,165 @@ mod tests {
             price,
             size,
             sequence: order_id as u32,
+            owner: None,
+            expires_at: None,
+        }
+    }
+
+    /// The best levels found by walking `bids` and `asks`, ignoring the cache.
+    fn naive_bbo(book: &OrderBook) -> Bbo {
+        Bbo {
+            bid: book
+                .bids
+                .iter()
+                .next_back()
+                .map(|(&price, level)| (price, level.total_qty())),
+            ask: book
+                .asks
+                .iter()
+                .next()
+                .map(|(&price, level)| (price, level.total_qty())),
+        }
+    }
+
+    #[test]
+    fn test_cached_best_matches_levels() {
+        let messages = OrderGenerator::default_seeded(5)
+            .make_lifecycle_messages(20_000, MessageMix::default());
+        let mut book = OrderBook::new();
+        for (index, message) in messages.iter().enumerate() {
+            let order = Order::from(message);
+            match message.action {
+                Action::Add => {
+                    book.add_order(order);
+                    // Now and then move it a tick inside with a duplicate add
+                    if index % 7 == 0 {
+                        let inside = match order.side {
+                            Side::Bid => order.price + 1,
+                            Side::Ask => order.price - 1,
+                        };
+                        book.add_order(Order {
+                            price: inside,
+                            ..order
+                        });
+                    }
+                }
+                Action::Cancel => {
+                    book.remove_order(order.order_id);
+                }
+                Action::Modify => {
+                    book.modify_order(order);
+                }
+                // Fill through every level up to the filled order's price
+                Action::Fill => {
+                    let side = match order.side {
+                        Side::Bid => Side::Ask,
+                        Side::Ask => Side::Bid,
+                    };
+                    book.match_order(&Order { side, ..order });
+                }
+                _ => {}
+            }
+            if index == messages.len() / 2 {
+                book.take_orders();
+            }
+            assert_eq!(book.bbo(), naive_bbo(&book), "message {index}");
+        }
+        assert!(book.best_bid().is_some() && book.best_ask().is_some());
+    }
+
+    #[test]
+    fn test_add_orders_matches_add_order() {
+        let mut orders = OrderGenerator::default_seeded(3).make_orders(2_000);
+        // Repeated ids move orders, across sides and within a level, and empty levels
+        let repeats: Vec<Order> = orders
+            .iter()
+            .step_by(9)
+            .enumerate()
+            .map(|(i, order)| Order {
+                side: if i % 3 == 0 { Side::Ask } else { order.side },
+                price: order.price + 20_000 * (i % 3 == 0) as i64 + (i % 2) as i64,
+                sequence: 5_000 + i as u32,
+                ..*order
+            })
+            .collect();
+        orders.extend(repeats);
+
+        let mut sequential = OrderBook::new();
+        sequential.add_order(order(1, Side::Bid, 1, 1));
+        let mut bulk = OrderBook::new();
+        bulk.add_order(order(1, Side::Bid, 1, 1));
+        for order in &orders {
+            sequential.add_order(*order);
+        }
+        bulk.add_orders(orders.iter().copied());
+
+        assert_eq!(bulk, sequential);
+        assert_eq!(bulk.bbo(), naive_bbo(&bulk));
+        assert!(bulk.anomalies().duplicate_add > 200);
+
+        let mut empty = OrderBook::new();
+        empty.add_orders([]);
+        assert_eq!(empty, OrderBook::new());
+    }
+
+    #[test]
+    fn test_top_n_variants_agree() {
+        let mut book = OrderBook::new();
+        for (id, price) in [(1, 9800), (2, 10000), (3, 9900), (4, 9900)] {
+            book.add_order(order(id, Side::Bid, price, 10 * id));
+        }
+        for (id, price) in [(5, 10200), (6, 10100)] {
+            book.add_order(order(id, Side::Ask, price, 10 * id));
+        }
+        assert_eq!(book.top_n_bids(2), vec![(10000, 20), (9900, 70)]);
+        assert_eq!(book.top_n_asks(5), vec![(10100, 60), (10200, 50)]);
+
+        // A dirty buffer is cleared; short books and zero depth give fewer levels
+        let mut out = vec![(1, 1); 8];
+        for n in [0, 1, 3, 5] {
+            book.top_n_bids_into(n, &mut out);
+            assert_eq!(out, book.top_n_bids(n), "bids, n = {n}");
+            book.top_n_asks_into(n, &mut out);
+            assert_eq!(out, book.top_n_asks(n), "asks, n = {n}");
         }
+
+        let (bids, count) = book.top_n_bids_array::<4>();
+        assert_eq!(&bids[..count], book.top_n_bids(4));
+        assert_eq!(bids[3], (0, 0));
+        let (asks, count) = book.top_n_asks_array::<1>();
+        assert_eq!(&asks[..count], book.top_n_asks(1));
+        assert_eq!(OrderBook::new().top_n_asks_array::<3>(), ([(0, 0); 3], 0));
+    }
+
+    #[test]
+    fn test_state_hash_covers_orders_not_insertion_order() {
+        let orders = [
+            order(1, Side::Bid, 100, 10),
+            order(2, Side::Bid, 100, 20),
+            order(3, Side::Bid, 99, 5),
+            order(4, Side::Ask, 101, 7),
+        ];
+        let mut book = OrderBook::new();
+        book.add_orders(orders);
+        let mut reversed = OrderBook::new();
+        reversed.add_orders(orders.into_iter().rev());
+        assert_eq!(book.state_hash(), reversed.state_hash());
+        assert_ne!(book.state_hash(), OrderBook::new().state_hash());
+
+        let hash = book.state_hash();
+        book.bids
+            .get_mut(&100)
+            .unwrap()
+            .update_size_in_place(2, 21)
+            .unwrap();
+        assert_ne!(book.state_hash(), hash);
+        book.bids
+            .get_mut(&100)
+            .unwrap()
+            .update_size_in_place(2, 20)
+            .unwrap();
+        assert_eq!(book.state_hash(), hash);
     }
 
     #[test]
@@ -553,11 +1967,13 @@ mod tests {
     fn test_add_duplicate_order_id_overwrites() {
         let mut book = OrderBook::new();
 
-        book.add_order(order(123, Side::Bid, 10050, 100));
+        assert!(!book.add_order(order(123, Side::Bid, 10050, 100)).replaced);
         assert_eq!(book.best_bid(), Some((10050, 100)));
 
         // Adding same order_id at different price should move it
-        book.add_order(order(123, Side::Bid, 10051, 150));
+        let info = book.add_order(order(123, Side::Bid, 10051, 150));
+        assert!(info.replaced);
+        assert_eq!(info.replaced_level, Some((Side::Bid, 10050)));
         assert_eq!(book.best_bid(), Some((10051, 150)));
 
         // Old price level should be empty
@@ -607,6 +2023,53 @@ mod tests {
         assert_eq!(book.best_ask(), Some((10054, 80)));
     }
 
+    #[test]
+    fn test_level_count_and_total_qty() {
+        let mut book = OrderBook::new();
+        book.add_order(order(1, Side::Bid, 10050, 100));
+        book.add_order(order(2, Side::Bid, 10050, 20));
+        book.add_order(order(3, Side::Bid, 10048, 50));
+        book.add_order(order(4, Side::Ask, 10052, 75));
+
+        assert_eq!(book.level_count(Side::Bid), 2);
+        assert_eq!(book.total_qty(Side::Bid), 170);
+        assert_eq!(book.level_count(Side::Ask), 1);
+        assert_eq!(book.total_qty(Side::Ask), 75);
+
+        book.remove_order(4);
+        assert_eq!(book.level_count(Side::Ask), 0);
+        assert_eq!(book.total_qty(Side::Ask), 0);
+    }
+
+    #[test]
+    fn test_depth_curve() {
+        let mut book = OrderBook::new();
+        for (order_id, (price, size)) in [(105, 10), (104, 20), (103, 5), (101, 15), (100, 50)]
+            .into_iter()
+            .enumerate()
+        {
+            book.add_order(order(order_id as u64 + 1, Side::Bid, price, size));
+        }
+        book.add_order(order(6, Side::Ask, 106, 8));
+
+        assert_eq!(
+            book.depth_curve(Side::Bid, 10),
+            vec![(105, 10), (104, 30), (103, 35), (101, 50), (100, 100)]
+        );
+        // Touch, middle and tail
+        assert_eq!(
+            book.depth_curve(Side::Bid, 3),
+            vec![(105, 10), (103, 35), (100, 100)]
+        );
+        assert_eq!(book.depth_curve(Side::Bid, 1), vec![(105, 10)]);
+        assert!(book.depth_curve(Side::Bid, 0).is_empty());
+        assert_eq!(book.depth_curve(Side::Ask, 3), vec![(106, 8)]);
+        assert_eq!(
+            book.notional_curve(Side::Bid, 2, 100),
+            vec![(105, 10.5), (100, 101.6)]
+        );
+    }
+
     #[test]
     fn test_multiple_orders_at_same_price() {
         let mut book = OrderBook::new();
@@ -651,6 +2114,25 @@ mod tests {
         assert_eq!(book.best_ask(), None);
     }
 
+    #[test]
+    fn test_clear_side() {
+        let mut book = OrderBook::new();
+        book.add_order(order(1, Side::Ask, 10052, 50));
+        book.add_order(order(2, Side::Ask, 10051, 20));
+        book.add_order(order(3, Side::Ask, 10052, 30));
+        book.add_order(order(4, Side::Bid, 10050, 100));
+
+        let removed = book.clear_side(Side::Ask);
+        let ids: Vec<u64> = removed.iter().map(|info| info.order.order_id).collect();
+        assert_eq!(ids, vec![2, 1, 3]);
+        assert!(removed[2].level_removed);
+        assert_eq!(book.best_ask(), None);
+        assert_eq!(book.level_count(Side::Ask), 0);
+        assert_eq!(book.get_order(1), None);
+        assert_eq!(book.best_bid(), Some((10050, 100)));
+        assert!(book.clear_side(Side::Ask).is_empty());
+    }
+
     // --- Queue position tests ---
 
     #[test]
@@ -718,6 +2200,8 @@ mod tests {
             price: 10050,
             size: 80,
             sequence: 100, // higher than 3 → end of queue
+            owner: None,
+            expires_at: None,
         });
 
         let level = book.bids.get(&10050).unwrap();
@@ -743,8 +2227,166 @@ mod tests {
         assert_eq!(level_10051.queue_position(2), Some(0));
     }
 
+    // --- anomaly tests ---
+
+    #[test]
+    fn test_anomalies_counted_once_each() {
+        let mut book = OrderBook::new();
+        book.add_order(order(1, Side::Bid, 100, 10));
+        book.add_order(order(2, Side::Ask, 105, 10));
+        assert_eq!(book.anomalies(), Anomalies::default());
+
+        book.add_order(order(1, Side::Bid, 101, 10)); // duplicate add
+        assert!(book.remove_order(99).is_none()); // unknown cancel
+        assert!(book.modify_order(order(98, Side::Bid, 100, 5)).is_none()); // unknown modify
+        book.asks.clear(); // corrupt the book behind the index
+        assert!(book.remove_order(2).is_none()); // level missing
+
+        assert_eq!(
+            book.anomalies(),
+            Anomalies {
+                duplicate_add: 1,
+                unknown_cancel: 1,
+                unknown_modify: 1,
+                level_missing: 1,
+                depth_dropped: 0,
+            }
+        );
+        assert_eq!(book.anomalies().total(), 4);
+
+        // Clearing the orders keeps the counters
+        let old = book.take_orders();
+        assert_eq!(old.anomalies().total(), 4);
+        assert_eq!(book.best_bid(), None);
+        assert_eq!(book.anomalies().total(), 4);
+    }
+
+    #[test]
+    fn test_participant_summary() {
+        let owned = |order_id, side, price, size, owner| Order {
+            owner: Some(owner),
+            ..order(order_id, side, price, size)
+        };
+        let mut book = OrderBook::new();
+        book.add_order(owned(1, Side::Bid, 100, 30, 7));
+        book.add_order(owned(2, Side::Bid, 100, 10, 8));
+        book.add_order(owned(3, Side::Ask, 105, 20, 8));
+        // Unattributed, but part of the side's quantity
+        book.add_order(order(4, Side::Bid, 99, 10));
+
+        let summary = book.participant_summary();
+        assert_eq!(summary.len(), 2);
+        let side = |qty, order_count, share| ParticipantSide {
+            qty,
+            order_count,
+            share,
+        };
+        assert_eq!(summary[&7].bid, side(30, 1, 0.6));
+        assert_eq!(summary[&7].ask, ParticipantSide::default());
+        assert_eq!(summary[&8].bid, side(10, 1, 0.2));
+        assert_eq!(summary[&8].ask, side(20, 1, 1.0));
+        assert_eq!(summary[&8].executed_qty, 0);
+
+        // Participant 7 cancels its share of the level
+        book.remove_order(1);
+        let summary = book.participant_summary();
+        assert!(!summary.contains_key(&7));
+        assert_eq!(summary[&8].bid, side(10, 1, 0.5));
+
+        // A Modify without an owner keeps the order's
+        book.modify_order(order(2, Side::Bid, 101, 10));
+        assert_eq!(book.get_order(2).unwrap().owner, Some(8));
+
+        assert!(three_order_level().participant_summary().is_empty());
+    }
+
     // --- modify_order tests ---
 
+    fn three_order_level() -> OrderBook {
+        let mut book = OrderBook::new();
+        book.add_order(order(1, Side::Ask, 10100, 10));
+        book.add_order(order(2, Side::Ask, 10100, 20));
+        book.add_order(order(3, Side::Ask, 10100, 30));
+        book
+    }
+
+    fn queue(book: &OrderBook) -> Vec<Option<usize>> {
+        [1, 2, 3]
+            .iter()
+            .map(|&id| book.queue_position(id))
+            .collect()
+    }
+
+    #[test]
+    fn test_modify_policy_exchange_style() {
+        let mut book = three_order_level();
+        let mut modified = order(2, Side::Ask, 10100, 5);
+        modified.sequence = 100;
+
+        let info = book
+            .modify_order_with_policy(modified, ModifyPriorityPolicy::ExchangeStyle)
+            .unwrap();
+        assert!(info.retained_queue_position);
+        assert_eq!(queue(&book), vec![Some(0), Some(1), Some(2)]);
+
+        modified.size = 25;
+        let info = book
+            .modify_order_with_policy(modified, ModifyPriorityPolicy::ExchangeStyle)
+            .unwrap();
+        assert!(!info.retained_queue_position);
+        assert_eq!(queue(&book), vec![Some(0), Some(2), Some(1)]);
+        assert_eq!(book.best_ask(), Some((10100, 65)));
+    }
+
+    #[test]
+    fn test_modify_policy_always_preserve() {
+        let mut book = three_order_level();
+        let mut modified = order(2, Side::Ask, 10100, 50);
+        modified.sequence = 100;
+
+        let info = book
+            .modify_order_with_policy(modified, ModifyPriorityPolicy::AlwaysPreserve)
+            .unwrap();
+        assert!(info.retained_queue_position);
+        assert_eq!(info.order.sequence, 2);
+        assert_eq!(queue(&book), vec![Some(0), Some(1), Some(2)]);
+        assert_eq!(book.queue_depth_ahead(3), Some(60));
+
+        // A price change re-queues regardless of policy
+        modified.price = 10101;
+        let info = book
+            .modify_order_with_policy(modified, ModifyPriorityPolicy::AlwaysPreserve)
+            .unwrap();
+        assert!(!info.retained_queue_position);
+        assert_eq!(book.queue_position(3), Some(1));
+    }
+
+    #[test]
+    fn test_modify_to_other_side_or_zero_size() {
+        let mut book = three_order_level();
+        let info = book
+            .modify_order_with_policy(
+                order(2, Side::Bid, 10100, 20),
+                ModifyPriorityPolicy::AlwaysPreserve,
+            )
+            .unwrap();
+        assert!(!info.retained_queue_position);
+        assert_eq!(book.get_order(2).unwrap().side, Side::Bid);
+        assert_eq!(book.best_bid(), Some((10100, 20)));
+        assert_eq!(book.best_ask(), Some((10100, 40)));
+
+        let info = book
+            .modify_order_with_policy(
+                order(2, Side::Bid, 10100, 0),
+                ModifyPriorityPolicy::AlwaysPreserve,
+            )
+            .unwrap();
+        assert!(!info.retained_queue_position);
+        assert_eq!(info.order.size, 0);
+        assert_eq!(book.get_order(2), None);
+        assert_eq!(book.level_count(Side::Bid), 0);
+    }
+
     #[test]
     fn test_modify_order_size_decrease_retains_queue_position() {
         let mut book = OrderBook::new();
@@ -781,6 +2423,8 @@ mod tests {
             price: 10050,
             size: 80,
             sequence: 100,
+            owner: None,
+            expires_at: None,
         };
         let info = book.modify_order(new).unwrap();
         assert!(!info.retained_queue_position);
@@ -808,6 +2452,8 @@ mod tests {
             price: 10051,
             size: 50,
             sequence: 100,
+            owner: None,
+            expires_at: None,
         };
         let info = book.modify_order(new).unwrap();
         assert!(!info.retained_queue_position);
@@ -851,4 +2497,456 @@ mod tests {
         assert_eq!(level.queue_position(1), Some(0));
         assert_eq!(level.queue_position(2), Some(1));
     }
+
+    // --- adjust_order_size tests ---
+
+    #[test]
+    fn test_adjust_order_size_reduce_to_zero_removes() {
+        let mut book = three_order_level();
+        assert_eq!(book.adjust_order_size(2, -5), Ok(15));
+        assert_eq!(queue(&book), vec![Some(0), Some(1), Some(2)]);
+
+        assert_eq!(book.adjust_order_size(2, -15), Ok(0));
+        assert_eq!(book.get_order(2), None);
+        assert_eq!(book.best_ask(), Some((10100, 40)));
+        assert_eq!(book.adjust_order_size(1, -10), Ok(0));
+        assert_eq!(book.adjust_order_size(3, -30), Ok(0));
+        assert_eq!(book.level_count(Side::Ask), 0);
+        assert_eq!(book.anomalies().total(), 0);
+    }
+
+    #[test]
+    fn test_adjust_order_size_underflow() {
+        let mut book = three_order_level();
+        assert_eq!(
+            book.adjust_order_size(2, -21),
+            Err(OrderBookError::SizeUnderflow {
+                order_id: 2,
+                size: 20,
+                delta: -21
+            })
+        );
+        assert_eq!(book.get_order(2).unwrap().size, 20);
+        assert_eq!(book.best_ask(), Some((10100, 60)));
+    }
+
+    #[test]
+    fn test_adjust_order_size_increase() {
+        let mut book = three_order_level();
+        // Loses its place to order 3, behind which it goes
+        assert_eq!(book.adjust_order_size(2, 5), Ok(25));
+        assert_eq!(queue(&book), vec![Some(0), Some(2), Some(1)]);
+        assert_eq!(book.best_ask(), Some((10100, 65)));
+        // Already last, it stays last
+        assert_eq!(book.adjust_order_size(2, 5), Ok(30));
+        assert_eq!(queue(&book), vec![Some(0), Some(2), Some(1)]);
+
+        assert_eq!(
+            book.adjust_order_size_with_policy(1, 10, ModifyPriorityPolicy::AlwaysPreserve),
+            Ok(20)
+        );
+        assert_eq!(queue(&book), vec![Some(0), Some(2), Some(1)]);
+        assert_eq!(book.best_ask(), Some((10100, 80)));
+    }
+
+    #[test]
+    fn test_adjust_order_size_nonexistent() {
+        let mut book = three_order_level();
+        assert_eq!(
+            book.adjust_order_size(99, -1),
+            Err(OrderBookError::OrderNotFound(99))
+        );
+        assert_eq!(
+            book.adjust_order_size(99, 1),
+            Err(OrderBookError::OrderNotFound(99))
+        );
+        assert_eq!(book.best_ask(), Some((10100, 60)));
+    }
+
+    #[test]
+    fn test_match_order_sweeps_levels_in_price_time_order() {
+        let mut book = OrderBook::new();
+        book.add_order(order(1, Side::Ask, 101, 5));
+        book.add_order(order(2, Side::Ask, 101, 5));
+        book.add_order(order(3, Side::Ask, 102, 10));
+        book.add_order(order(4, Side::Ask, 103, 10));
+
+        let executions = book.match_order(&order(9, Side::Bid, 102, 14));
+
+        assert_eq!(
+            executions
+                .iter()
+                .map(|e| (e.resting.order_id, e.size))
+                .collect::<Vec<_>>(),
+            vec![(1, 5), (2, 5), (3, 4)]
+        );
+        // Partially filled order 3 keeps its remaining size; order 4 is beyond the limit
+        assert_eq!(book.top_n_asks(5), vec![(102, 6), (103, 10)]);
+        assert!(book.get_order(9).is_none());
+    }
+
+    #[test]
+    fn test_match_order_without_cross_is_noop() {
+        let mut book = OrderBook::new();
+        book.add_order(order(1, Side::Bid, 100, 5));
+
+        assert!(book.match_order(&order(2, Side::Ask, 101, 5)).is_empty());
+        assert_eq!(book.best_bid(), Some((100, 5)));
+    }
+
+    #[test]
+    fn test_self_match_report_and_prevention() {
+        let owned = |order_id, side, price, size, owner| Order {
+            owner: Some(owner),
+            ..order(order_id, side, price, size)
+        };
+        let two_levels = || {
+            let mut book = OrderBook::new();
+            book.add_order(owned(1, Side::Ask, 101, 5, 3));
+            book.add_order(owned(2, Side::Ask, 102, 5, 7));
+            book.add_order(owned(3, Side::Ask, 102, 5, 3));
+            book
+        };
+        let buy = owned(9, Side::Bid, 102, 12, 7);
+
+        let book = two_levels();
+        let report = book.would_self_match(Side::Bid, 102, 12, 7);
+        assert_eq!(
+            report.matches,
+            vec![Execution {
+                resting: owned(2, Side::Ask, 102, 5, 7),
+                size: 5
+            }]
+        );
+        assert_eq!(report.qty(), 5);
+        assert_eq!(book.would_self_match(Side::Bid, 102, 12, 3).qty(), 7);
+        assert!(book.would_self_match(Side::Bid, 101, 12, 7).is_empty());
+        assert_eq!(book.top_n_asks(5), vec![(101, 5), (102, 10)]);
+
+        let traded = |outcome: &MatchOutcome| -> Vec<(u64, u64)> {
+            outcome
+                .executions
+                .iter()
+                .map(|e| (e.resting.order_id, e.size))
+                .collect()
+        };
+
+        // Allow trades against the owner's own order
+        let mut book = two_levels();
+        let outcome = book.match_order_with_policy(&buy, SelfMatchPolicy::Allow);
+        assert_eq!(traded(&outcome), vec![(1, 5), (2, 5), (3, 2)]);
+        assert!(outcome.cancelled.is_empty() && !outcome.incoming_cancelled);
+        assert_eq!(book.top_n_asks(5), vec![(102, 3)]);
+
+        // CancelResting removes it and trades on behind it
+        let mut book = two_levels();
+        let outcome = book.match_order_with_policy(&buy, SelfMatchPolicy::CancelResting);
+        assert_eq!(traded(&outcome), vec![(1, 5), (3, 5)]);
+        assert_eq!(outcome.cancelled.len(), 1);
+        assert_eq!(outcome.cancelled[0].order.order_id, 2);
+        assert!(!outcome.incoming_cancelled);
+        assert!(book.top_n_asks(5).is_empty());
+
+        // CancelIncoming stops at it, leaving it and everything behind it resting
+        let mut book = two_levels();
+        let outcome = book.match_order_with_policy(&buy, SelfMatchPolicy::CancelIncoming);
+        assert_eq!(traded(&outcome), vec![(1, 5)]);
+        assert!(outcome.cancelled.is_empty() && outcome.incoming_cancelled);
+        assert_eq!(book.top_n_asks(5), vec![(102, 10)]);
+        assert_eq!(book.queue_position(2), Some(0));
+    }
+
+    #[test]
+    fn test_replayed_events_rebuild_book() {
+        let mut book = OrderBook::new().with_events(EventRetention::All);
+        book.add_orders([order(1, Side::Bid, 100, 10), order(2, Side::Bid, 100, 10)]);
+        book.add_order(order(3, Side::Bid, 99, 10));
+        book.add_order(order(4, Side::Ask, 101, 10));
+        book.add_order(order(5, Side::Ask, 102, 10));
+        // Replaced, then modified in place, to the back of the queue and away
+        book.add_order(order(3, Side::Bid, 100, 5));
+        book.modify_order(order(1, Side::Bid, 100, 8));
+        book.modify_order(Order {
+            sequence: 9,
+            ..order(2, Side::Bid, 100, 12)
+        });
+        book.modify_order(order(3, Side::Bid, 100, 0));
+        book.match_order(&order(6, Side::Bid, 102, 14));
+        book.remove_order(5);
+        book.seed_from_depth(&[(Side::Ask, 103, 20, 2)], SeedMode::PerOrder);
+
+        let events: Vec<BookEvent> = book.events().copied().collect();
+        assert_eq!(events.len(), 14);
+        assert!(events.iter().zip(0..).all(|(event, i)| event.index == i));
+        assert_eq!(
+            events[9].change,
+            BookChange::Filled {
+                order_id: 4,
+                side: Side::Ask,
+                price: 101,
+                old_size: 10,
+                new_size: 0
+            }
+        );
+        let replayed = OrderBook::replay_events(&events);
+        assert_eq!(replayed, book);
+        assert_eq!(replayed.queue_position(2), Some(1));
+        assert!(replayed.events().next().is_none());
+
+        // After a clear, the drained tail rebuilds the book on its own
+        book.take_orders();
+        book.add_order(order(7, Side::Ask, 105, 1));
+        book.drain_events();
+        book.add_order(order(8, Side::Bid, 95, 1));
+        let tail = book.drain_events();
+        assert_eq!(tail[0].index, 16);
+        let mut rebuilt = OrderBook::new();
+        rebuilt.add_order(order(7, Side::Ask, 105, 1));
+        tail.iter().for_each(|event| rebuilt.apply_event(event));
+        rebuilt.restore_anomalies(book.anomalies());
+        assert_eq!(rebuilt, book);
+
+        // Off by default, with nothing allocated
+        let mut plain = three_order_level();
+        plain.remove_order(1);
+        assert_eq!(plain.event_retention(), EventRetention::Off);
+        assert!(plain.drain_events().is_empty());
+    }
+
+    /// Ten bid levels below 100 and ten ask levels from 101, two orders each.
+    fn depth() -> Vec<(Side, i64, u64, u32)> {
+        (0..10)
+            .flat_map(|i| {
+                [
+                    (Side::Bid, 100 - i, 10 + i as u64, 2),
+                    (Side::Ask, 101 + i, 20 + i as u64, 2),
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_seed_from_depth_matches_market_by_price() {
+        let mut book = OrderBook::new();
+        assert_eq!(book.seed_from_depth(&depth(), SeedMode::PerOrder), 40);
+
+        let mbp = MarketByPrice::from(&book);
+        assert_eq!(mbp.bids.len(), 10);
+        assert_eq!(mbp.asks.len(), 10);
+        for (side, price, size, count) in depth() {
+            let levels = match side {
+                Side::Bid => &mbp.bids,
+                Side::Ask => &mbp.asks,
+            };
+            let level = levels[&price];
+            assert_eq!(level.total_quantity, size);
+            assert_eq!(level.order_count, count as usize);
+        }
+        // Odd sizes put the remainder on the first order
+        let level = &book.bids[&99];
+        assert_eq!(
+            level.queue.values().map(|o| o.size).collect::<Vec<_>>(),
+            vec![6, 5]
+        );
+        assert!(
+            level
+                .queue
+                .values()
+                .all(|o| is_synthetic_order_id(o.order_id))
+        );
+    }
+
+    #[test]
+    fn test_seeded_book_takes_real_orders() {
+        let mut book = OrderBook::new();
+        assert_eq!(book.seed_from_depth(&depth(), SeedMode::Aggregate), 20);
+
+        // A real order joins the back of a seeded level and is then cancelled
+        book.add_order(order(7, Side::Bid, 100, 5));
+        assert_eq!(book.best_bid(), Some((100, 15)));
+        assert_eq!(book.bids[&100].order_count(), 2);
+        assert_eq!(book.queue_depth_ahead(7), Some(10));
+
+        book.add_order(order(8, Side::Ask, 101, 3));
+        book.remove_order(7).unwrap();
+        assert_eq!(book.best_bid(), Some((100, 10)));
+        assert_eq!(book.best_ask(), Some((101, 23)));
+        assert_eq!(book.anomalies().total(), 0);
+
+        // Re-seeding replaces the synthetic orders but keeps the real one
+        book.seed_from_depth(&[(Side::Ask, 101, 4, 1)], SeedMode::Aggregate);
+        assert!(book.bids.is_empty());
+        assert_eq!(book.top_n_asks(5), vec![(101, 7)]);
+        assert!(book.get_order(8).is_some());
+    }
+
+    #[test]
+    fn test_seed_from_depth_skips_empty_levels() {
+        let mut book = OrderBook::new();
+        let levels = [(Side::Bid, 100, 0, 3), (Side::Ask, 101, 2, 5)];
+        assert_eq!(book.seed_from_depth(&levels, SeedMode::PerOrder), 2);
+        assert!(book.bids.is_empty());
+        assert_eq!(book.asks[&101].order_count(), 2);
+    }
+
+    #[test]
+    fn test_from_mbp_round_trips_and_takes_real_orders() {
+        let mut source = OrderBook::new();
+        source.add_orders([
+            order(1, Side::Bid, 100, 10),
+            order(2, Side::Bid, 100, 7),
+            order(3, Side::Bid, 99, 1),
+            order(4, Side::Ask, 101, 4),
+            order(5, Side::Ask, 101, 4),
+            order(6, Side::Ask, 101, 5),
+            order(7, Side::Ask, 103, 2),
+        ]);
+        let mbp = MarketByPrice::from(&source);
+
+        let mut book = OrderBook::from_mbp(&mbp, SeedMode::PerOrder);
+        assert_eq!(MarketByPrice::from(&book), mbp);
+        assert!(
+            book.bids
+                .values()
+                .chain(book.asks.values())
+                .all(|level| { level.queue.values().all(Order::is_synthetic) })
+        );
+        // One order per level holds the same quantities
+        let aggregate = OrderBook::from_mbp(&mbp, SeedMode::Aggregate);
+        assert_eq!(aggregate.bids[&100].order_count(), 1);
+        assert_eq!(aggregate.top_n_bids(5), source.top_n_bids(5));
+        assert_eq!(aggregate.top_n_asks(5), source.top_n_asks(5));
+
+        // A real bid queues behind the synthetic ones, and a buy takes the synthetic
+        // asks first
+        book.add_order(order(8, Side::Bid, 100, 3));
+        assert!(!book.get_order(8).unwrap().is_synthetic());
+        assert_eq!(book.queue_depth_ahead(8), Some(17));
+        let buy = order(9, Side::Bid, 101, 6);
+        let executions = book.match_order(&buy);
+        assert!(
+            executions
+                .iter()
+                .all(|execution| execution.resting.is_synthetic())
+        );
+        assert_eq!(executions.iter().map(|e| e.size).sum::<u64>(), 6);
+        assert_eq!(book.best_ask(), Some((101, 7)));
+
+        // The synthetic orders cancel by their ids like any other
+        let synthetic = book.bids[&99].front().unwrap().order_id;
+        assert!(book.remove_order(synthetic).is_some());
+        assert_eq!(book.remove_order(8).unwrap().order.size, 3);
+        assert_eq!(book.top_n_bids(5), vec![(100, 17)]);
+        assert_eq!(book.anomalies().total(), 0);
+    }
+
+    /// Bids at 100, 99 and 98 and asks at 101, 102 and 103, one order each.
+    fn three_levels(book: OrderBook) -> OrderBook {
+        let mut book = book;
+        for (i, price) in (98..=100).enumerate() {
+            book.add_order(order(1 + i as u64, Side::Bid, price, 10));
+            book.add_order(order(11 + i as u64, Side::Ask, price + 3, 10));
+        }
+        book
+    }
+
+    #[test]
+    fn test_unlimited_book_keeps_every_level() {
+        let mut book = three_levels(OrderBook::new());
+        assert_eq!(book.max_levels_per_side(), None);
+        let info = book.try_add_order(order(4, Side::Bid, 50, 10)).unwrap();
+        assert!(!info.dropped);
+        assert!(info.new_level);
+        assert_eq!(book.level_count(Side::Bid), 4);
+        assert_eq!(book.anomalies().total(), 0);
+    }
+
+    #[test]
+    fn test_depth_limit_drops_orders_beyond() {
+        let mut book = three_levels(OrderBook::new().with_max_levels_per_side(3));
+
+        // Beyond the worst level of a full side: left out and counted
+        let info = book.add_order(order(4, Side::Bid, 97, 10));
+        assert!(info.dropped);
+        assert_eq!(info.level_qty, 0);
+        assert!(book.get_order(4).is_none());
+        assert_eq!(book.anomalies().depth_dropped, 1);
+
+        // Joining an existing level is always kept, including the worst one
+        let info = book.try_add_order(order(5, Side::Ask, 103, 5)).unwrap();
+        assert!(!info.dropped);
+        assert_eq!(info.level_qty, 15);
+
+        // An order moved beyond the limit is removed along with the dropped add
+        let info = book.add_order(order(11, Side::Ask, 110, 10));
+        assert!(info.dropped);
+        assert_eq!(info.replaced_level, Some((Side::Ask, 101)));
+        assert!(book.get_order(11).is_none());
+        assert_eq!(book.best_ask(), Some((102, 10)));
+        assert_eq!(book.anomalies().depth_dropped, 2);
+
+        // With room left, the side accepts a worse level again
+        assert!(!book.add_order(order(6, Side::Ask, 110, 10)).dropped);
+        assert_eq!(book.top_n_asks(5), vec![(102, 10), (103, 15), (110, 10)]);
+        assert_eq!(book.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_depth_limit_rejects_orders_beyond() {
+        let mut book = three_levels(
+            OrderBook::new()
+                .with_max_levels_per_side(3)
+                .with_depth_limit_policy(DepthLimitPolicy::Reject),
+        );
+        let before = book.top_n_bids(5);
+        assert_eq!(
+            book.try_add_order(order(4, Side::Bid, 97, 10)).unwrap_err(),
+            OrderBookError::BeyondDepthLimit {
+                order_id: 4,
+                side: Side::Bid,
+                price: 97,
+                max_levels: 3
+            }
+        );
+        // Rejecting an existing id's move leaves the order where it was
+        assert!(book.try_add_order(order(1, Side::Bid, 97, 10)).is_err());
+        assert_eq!(book.get_order(1).map(|o| o.price), Some(98));
+        assert_eq!(book.top_n_bids(5), before);
+        assert_eq!(book.anomalies().total(), 0);
+
+        // `add_order` leaves the order out without counting it
+        assert!(book.add_order(order(4, Side::Bid, 97, 10)).dropped);
+        assert_eq!(book.anomalies().total(), 0);
+    }
+
+    #[test]
+    fn test_depth_limit_trims_on_new_best() {
+        let mut book = three_levels(OrderBook::new().with_max_levels_per_side(3));
+
+        // A new best bid pushes the worst level out
+        let info = book.add_order(order(4, Side::Bid, 101, 10));
+        assert!(!info.dropped);
+        assert_eq!(book.top_n_bids(5), vec![(101, 10), (100, 10), (99, 10)]);
+        assert!(book.get_order(1).is_none());
+        assert_eq!(book.anomalies().depth_dropped, 1);
+
+        // Once the best level goes, the trimmed level does not come back
+        book.remove_order(4);
+        assert_eq!(book.top_n_bids(5), vec![(100, 10), (99, 10)]);
+
+        // A Modify to a new best price trims as an Add does
+        book.modify_order(order(13, Side::Ask, 100, 10)).unwrap();
+        book.modify_order(order(12, Side::Ask, 99, 10)).unwrap();
+        assert_eq!(book.top_n_asks(5), vec![(99, 10), (100, 10), (101, 10)]);
+        book.add_order(order(5, Side::Ask, 98, 10));
+        assert_eq!(book.top_n_asks(5), vec![(98, 10), (99, 10), (100, 10)]);
+        assert_eq!(book.anomalies().depth_dropped, 2);
+
+        // Limiting a full book trims it straight away
+        let book = three_levels(OrderBook::new()).with_max_levels_per_side(1);
+        assert_eq!(book.top_n_bids(5), vec![(100, 10)]);
+        assert_eq!(book.top_n_asks(5), vec![(101, 10)]);
+        assert_eq!(book.anomalies().depth_dropped, 4);
+        assert_eq!(book.validate(), Ok(()));
+    }
 }
@@ -0,0 +1,205 @@
+//! Processing messages received over a tokio channel.
+//!
+//! The processor stays synchronous; `run` only awaits the next message (and, when
+//! forwarding, room on the outbound channel), so back-pressure comes from the bounded
+//! channels themselves.
+
+use std::collections::HashMap;
+
+use time::OffsetDateTime;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tracing::warn;
+
+use crate::orderbook::{
+    Bbo, ErrorPolicy, MarketByOrderMessage, MboObserver, MboProcessor, Mbp1Tracker, ProcessFailure,
+    ProcessSummary,
+};
+
+/// A change in an instrument's top of book, sent by `MboProcessor::run_with_bbo`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BboUpdate {
+    pub instrument_id: u32,
+    /// Exchange event timestamp of the message that completed the event.
+    pub event_time: OffsetDateTime,
+    /// Exchange sequence of the message that completed the event.
+    pub sequence: u32,
+    pub bbo: Bbo,
+}
+
+impl<O: MboObserver> MboProcessor<O> {
+    /// Applies messages from `rx` until the channel is closed and summarizes the outcome.
+    ///
+    /// Failures are reported with their 0-based index in the received stream. Under
+    /// `ErrorPolicy::FailFast` the receiver is dropped at the first failure, which the
+    /// producer observes as a closed channel.
+    pub async fn run(&mut self, rx: Receiver<MarketByOrderMessage>) -> ProcessSummary {
+        self.run_inner(rx, None).await
+    }
+
+    /// Like `run`, additionally sending a `BboUpdate` on `bbo_tx` whenever a completed
+    /// event leaves an instrument's top of book different from the last one sent
+    /// (see `Mbp1Tracker`).
+    ///
+    /// If the receiving side of `bbo_tx` is dropped, forwarding stops and processing
+    /// continues.
+    pub async fn run_with_bbo(
+        &mut self,
+        rx: Receiver<MarketByOrderMessage>,
+        bbo_tx: Sender<BboUpdate>,
+    ) -> ProcessSummary {
+        self.run_inner(rx, Some(bbo_tx)).await
+    }
+
+    async fn run_inner(
+        &mut self,
+        mut rx: Receiver<MarketByOrderMessage>,
+        mut bbo_tx: Option<Sender<BboUpdate>>,
+    ) -> ProcessSummary {
+        let mut summary = ProcessSummary::default();
+        let mut trackers: HashMap<u32, Mbp1Tracker> = HashMap::new();
+        let mut index = 0;
+        while let Some(message) = rx.recv().await {
+            let event = self.step(message);
+            match event.outcome {
+                Ok(()) => {
+                    summary.processed += 1;
+                    summary.action_counts.record(event.message.action);
+                }
+                Err(error) => {
+                    summary.failures.push(ProcessFailure { index, error });
+                    if self.error_policy() == ErrorPolicy::FailFast {
+                        break;
+                    }
+                }
+            }
+            index += 1;
+
+            let instrument_id = event.message.instrument_id;
+            if let Some(tx) = bbo_tx.as_ref()
+                && self.is_event_complete()
+                && trackers
+                    .entry(instrument_id)
+                    .or_default()
+                    .update_bbo(event.bbo)
+                    .is_some()
+            {
+                let update = BboUpdate {
+                    instrument_id,
+                    event_time: event.message.event_time,
+                    sequence: event.message.sequence,
+                    bbo: event.bbo,
+                };
+                if tx.send(update).await.is_err() {
+                    warn!("BBO receiver dropped, no longer forwarding updates");
+                    bbo_tx = None;
+                }
+            }
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::iter;
+
+    use time::Duration;
+    use tokio::sync::mpsc;
+
+    use crate::generators::OrderGenerator;
+    use crate::orderbook::{Action, Order, Side};
+
+    fn message(action: Action, order: &Order, sequence: u32) -> MarketByOrderMessage {
+        let event_time = OffsetDateTime::UNIX_EPOCH + Duration::microseconds(sequence.into());
+        MarketByOrderMessage {
+            sequence,
+            ..MarketByOrderMessage::test(
+                action,
+                order.side,
+                order.price,
+                order.order_id,
+                order.size as u32,
+            )
+            .at(event_time)
+        }
+    }
+
+    /// Generated Adds, every other one followed by a Cancel of an earlier order.
+    fn generated_messages() -> Vec<MarketByOrderMessage> {
+        let orders = OrderGenerator::default_seeded(11).make_orders(200);
+        orders
+            .iter()
+            .enumerate()
+            .flat_map(|(i, order)| {
+                let cancel = (i % 2 == 1).then(|| (Action::Cancel, orders[i / 2]));
+                iter::once((Action::Add, *order)).chain(cancel)
+            })
+            .enumerate()
+            .map(|(seq, (action, order))| message(action, &order, seq as u32 + 1))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_run_matches_synchronous_replay() {
+        let messages = generated_messages();
+        assert_eq!(messages.len(), 300);
+
+        let mut sync = MboProcessor::new();
+        assert!(sync.process_messages(&messages).is_success());
+
+        let (tx, rx) = mpsc::channel(16);
+        let producer = tokio::spawn({
+            let messages = messages.clone();
+            async move {
+                for message in messages {
+                    tx.send(message).await.unwrap();
+                }
+            }
+        });
+
+        let mut proc = MboProcessor::new();
+        let summary = proc.run(rx).await;
+        producer.await.unwrap();
+
+        assert!(summary.is_success());
+        assert_eq!(summary.processed, 300);
+        assert_eq!(proc.book(1), sync.book(1));
+        assert_eq!(proc.stats(), sync.stats());
+    }
+
+    #[tokio::test]
+    async fn test_run_with_bbo_forwards_changes() {
+        let order = |order_id, side, price| Order {
+            order_id,
+            side,
+            price,
+            size: 10,
+            sequence: 0,
+            owner: None,
+            expires_at: None,
+        };
+        let messages = [
+            message(Action::Add, &order(1, Side::Bid, 100), 1),
+            message(Action::Add, &order(2, Side::Bid, 99), 2), // behind the best bid
+            message(Action::Add, &order(3, Side::Ask, 101), 3),
+            message(Action::Cancel, &order(1, Side::Bid, 100), 4),
+        ];
+
+        let (tx, rx) = mpsc::channel(messages.len());
+        messages
+            .iter()
+            .for_each(|message| tx.try_send(*message).unwrap());
+        drop(tx);
+        let (bbo_tx, mut bbo_rx) = mpsc::channel(messages.len());
+
+        let summary = MboProcessor::new().run_with_bbo(rx, bbo_tx).await;
+        assert!(summary.is_success());
+
+        let mut sequences = Vec::new();
+        while let Some(update) = bbo_rx.recv().await {
+            sequences.push(update.sequence);
+        }
+        assert_eq!(sequences, vec![1, 3, 4]);
+    }
+}
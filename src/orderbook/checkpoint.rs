@@ -0,0 +1,142 @@
+//! Saving and restoring `MboProcessor` state.
+//!
+//! A checkpoint holds every instrument's book and statistics, the observer, and the
+//! last sequence number and timestamps, encoded with bincode. Restoring a checkpoint and
+//! continuing processing yields the same state as an uninterrupted replay.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use thiserror::Error;
+
+use crate::orderbook::{MboObserver, MboProcessor};
+
+#[derive(Debug, Error)]
+pub enum CheckpointError {
+    #[error("Checkpoint I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Checkpoint encoding error: {0}")]
+    Encoding(#[from] bincode::Error),
+}
+
+impl<O: MboObserver + Serialize> MboProcessor<O> {
+    /// Writes the full processor state to `path`, replacing any existing file.
+    pub fn save_checkpoint(&self, path: impl AsRef<Path>) -> Result<(), CheckpointError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        bincode::serialize_into(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<O: MboObserver + DeserializeOwned> MboProcessor<O> {
+    /// Restores a processor previously written by `save_checkpoint`.
+    pub fn load_checkpoint(path: impl AsRef<Path>) -> Result<Self, CheckpointError> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(bincode::deserialize_from(reader)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::{env, fs, iter, process};
+
+    use time::{Duration, OffsetDateTime};
+
+    use crate::generators::OrderGenerator;
+    use crate::orderbook::{Action, ErrorPolicy, MarketByOrderMessage, Order, TradeCollector};
+
+    fn checkpoint_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("rainybook-{}-{name}.ckpt", process::id()))
+    }
+
+    fn message(action: Action, order: &Order, sequence: u32) -> MarketByOrderMessage {
+        let event_time = OffsetDateTime::UNIX_EPOCH + Duration::microseconds(sequence.into());
+        MarketByOrderMessage {
+            instrument_id: 1 + order.order_id as u32 % 2,
+            sequence,
+            ..MarketByOrderMessage::test(
+                action,
+                order.side,
+                order.price,
+                order.order_id,
+                order.size as u32,
+            )
+            .at(event_time)
+        }
+    }
+
+    /// 1000 messages: generated Adds, with every third message cancelling, trading
+    /// against, or shrinking an earlier order.
+    fn generated_messages() -> Vec<MarketByOrderMessage> {
+        let orders = OrderGenerator::default_seeded(7).make_orders(667);
+        orders
+            .iter()
+            .enumerate()
+            .flat_map(|(i, order)| {
+                let earlier = &orders[i / 2];
+                let follow_up = (i % 2 == 1).then(|| match i % 3 {
+                    0 => (Action::Cancel, *earlier),
+                    1 => (Action::Trade, *earlier),
+                    _ => (
+                        Action::Modify,
+                        Order {
+                            size: (earlier.size / 2).max(1),
+                            ..*earlier
+                        },
+                    ),
+                });
+                iter::once((Action::Add, *order)).chain(follow_up)
+            })
+            .take(1000)
+            .enumerate()
+            .map(|(seq, (action, order))| message(action, &order, seq as u32 + 1))
+            .collect()
+    }
+
+    #[test]
+    fn test_checkpoint_restore_matches_uninterrupted_run() {
+        let messages = generated_messages();
+        assert_eq!(messages.len(), 1000);
+
+        let mut uninterrupted = MboProcessor::with_observer(TradeCollector::new())
+            .with_error_policy(ErrorPolicy::Collect);
+        uninterrupted.process_messages(&messages);
+
+        let path = checkpoint_path("roundtrip");
+        let mut first_half = MboProcessor::with_observer(TradeCollector::new())
+            .with_error_policy(ErrorPolicy::Collect);
+        first_half.process_messages(&messages[..500]);
+        first_half.save_checkpoint(&path).unwrap();
+
+        let mut restored = MboProcessor::<TradeCollector>::load_checkpoint(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(restored.last_sequence_number(), 500);
+        restored.process_messages(&messages[500..]);
+
+        assert_eq!(restored.instruments(), uninterrupted.instruments());
+        restored.instruments().into_iter().for_each(|id| {
+            assert_eq!(restored.book(id), uninterrupted.book(id));
+        });
+        assert_eq!(restored.stats(), uninterrupted.stats());
+        assert_eq!(restored.stats().messages, 1000);
+        assert_eq!(
+            restored.observer().trades(),
+            uninterrupted.observer().trades()
+        );
+        assert_eq!(restored.last_timestamps(), uninterrupted.last_timestamps());
+        assert_eq!(restored.error_policy(), uninterrupted.error_policy());
+    }
+
+    #[test]
+    fn test_load_missing_checkpoint_fails() {
+        let result = MboProcessor::<()>::load_checkpoint(checkpoint_path("missing"));
+        assert!(matches!(result, Err(CheckpointError::Io(_))));
+    }
+}
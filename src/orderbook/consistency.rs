@@ -0,0 +1,411 @@
+//! Checking an `OrderBook`'s redundant state against itself.
+//!
+//! The book keeps each order in three places: its level's queue, that level's index
+//! from order id to queue slot, and the book's index from order id to price. It also
+//! caches each level's quantity and the best level of each side. `OrderBook::validate` walks all of them and
+//! reports every disagreement, for debugging and tests; with the `debug-validate`
+//! feature the book runs it after every change and panics on the first inconsistency.
+
+use thiserror::Error;
+
+use crate::orderbook::{OrderBook, Side};
+
+/// One inconsistency found by `OrderBook::validate`.
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ConsistencyError {
+    #[error("Order {order_id} is indexed at price {price} but no level there holds it")]
+    MissingOrder { order_id: u64, price: i64 },
+
+    #[error("Order {order_id} is held by the levels at price {price} on both sides")]
+    DuplicateOrder { order_id: u64, price: i64 },
+
+    #[error("Order {order_id} at price {price} is not indexed there (indexed at {indexed:?})")]
+    Unindexed {
+        order_id: u64,
+        price: i64,
+        indexed: Option<i64>,
+    },
+
+    #[error("Order {order_id} of side {order_side:?} rests on the {level_side:?} side")]
+    WrongSide {
+        order_id: u64,
+        order_side: Side,
+        level_side: Side,
+    },
+
+    #[error("Order {order_id} of price {order_price} rests at level {level_price}")]
+    WrongPrice {
+        order_id: u64,
+        order_price: i64,
+        level_price: i64,
+    },
+
+    #[error("Order {order_id} at {side:?} price {price} has no size")]
+    ZeroSize {
+        order_id: u64,
+        side: Side,
+        price: i64,
+    },
+
+    #[error(
+        "Order {order_id} at {side:?} price {price} is queued in slot {queued} but indexed at {indexed:?}"
+    )]
+    QueueMismatch {
+        order_id: u64,
+        side: Side,
+        price: i64,
+        queued: u32,
+        indexed: Option<u32>,
+    },
+
+    #[error("Order {order_id} at {side:?} price {price} is queued behind a later order")]
+    QueueOutOfOrder {
+        order_id: u64,
+        side: Side,
+        price: i64,
+    },
+
+    #[error("Level at {side:?} price {price} indexes {indexed} orders but queues {queued}")]
+    LevelCountMismatch {
+        side: Side,
+        price: i64,
+        indexed: usize,
+        queued: usize,
+    },
+
+    #[error("Level at {side:?} price {price} caches quantity {cached} but queues {queued}")]
+    LevelQtyMismatch {
+        side: Side,
+        price: i64,
+        cached: u64,
+        queued: u64,
+    },
+
+    #[error("Level keyed at {side:?} price {key} has price {price}")]
+    LevelPriceMismatch { side: Side, key: i64, price: i64 },
+
+    #[error("Level at {side:?} price {price} is empty")]
+    EmptyLevel { side: Side, price: i64 },
+
+    #[error("Cached best {side:?} level {cached:?} differs from the levels' {actual:?}")]
+    StaleBest {
+        side: Side,
+        cached: Option<(i64, u64)>,
+        actual: Option<(i64, u64)>,
+    },
+}
+
+impl OrderBook {
+    /// Checks that the book agrees with itself: every indexed order is held by exactly
+    /// one level, on its own side at the indexed price, and is queued in sequence order
+    /// in the slot its level indexes; every resting order is indexed and has a size; no
+    /// level is empty or keyed at another price, or caches a quantity other than its
+    /// orders' total; and the cached best levels match the levels.
+    ///
+    /// Walks every order, so it is for debugging and tests rather than the hot path.
+    /// Returns every inconsistency found.
+    pub fn validate(&self) -> Result<(), Vec<ConsistencyError>> {
+        let mut errors = Vec::new();
+
+        for (&order_id, &price) in &self.order_index {
+            let holders = [Side::Bid, Side::Ask]
+                .into_iter()
+                .filter(|&side| {
+                    self.levels(side)
+                        .get(&price)
+                        .is_some_and(|level| level.get_order(order_id).is_some())
+                })
+                .count();
+            match holders {
+                0 => errors.push(ConsistencyError::MissingOrder { order_id, price }),
+                1 => {}
+                _ => errors.push(ConsistencyError::DuplicateOrder { order_id, price }),
+            }
+        }
+
+        for side in [Side::Bid, Side::Ask] {
+            for (&key, level) in self.levels(side) {
+                if level.price != key {
+                    errors.push(ConsistencyError::LevelPriceMismatch {
+                        side,
+                        key,
+                        price: level.price,
+                    });
+                }
+                if level.is_empty() {
+                    errors.push(ConsistencyError::EmptyLevel { side, price: key });
+                }
+                if level.order_index.len() != level.queue.len() {
+                    errors.push(ConsistencyError::LevelCountMismatch {
+                        side,
+                        price: key,
+                        indexed: level.order_index.len(),
+                        queued: level.queue.len(),
+                    });
+                }
+                let queued = level.queue.values().map(|order| order.size).sum();
+                if level.total_qty() != queued {
+                    errors.push(ConsistencyError::LevelQtyMismatch {
+                        side,
+                        price: key,
+                        cached: level.total_qty(),
+                        queued,
+                    });
+                }
+                let mut previous = None;
+                for (slot, order) in level.queue.entries() {
+                    let (sequence, order_id) = (order.sequence, order.order_id);
+                    if previous.is_some_and(|previous| previous >= (sequence, order_id)) {
+                        errors.push(ConsistencyError::QueueOutOfOrder {
+                            order_id,
+                            side,
+                            price: key,
+                        });
+                    }
+                    previous = Some((sequence, order_id));
+                    let indexed = level.order_index.get(&order_id).copied();
+                    if indexed != Some(slot) {
+                        errors.push(ConsistencyError::QueueMismatch {
+                            order_id,
+                            side,
+                            price: key,
+                            queued: slot,
+                            indexed,
+                        });
+                    }
+                    if order.side != side {
+                        errors.push(ConsistencyError::WrongSide {
+                            order_id,
+                            order_side: order.side,
+                            level_side: side,
+                        });
+                    }
+                    if order.price != key {
+                        errors.push(ConsistencyError::WrongPrice {
+                            order_id,
+                            order_price: order.price,
+                            level_price: key,
+                        });
+                    }
+                    if order.size == 0 {
+                        errors.push(ConsistencyError::ZeroSize {
+                            order_id,
+                            side,
+                            price: key,
+                        });
+                    }
+                    let indexed = self.order_index.get(&order_id).copied();
+                    if indexed != Some(key) {
+                        errors.push(ConsistencyError::Unindexed {
+                            order_id,
+                            price: key,
+                            indexed,
+                        });
+                    }
+                }
+            }
+        }
+
+        let actual = |side| {
+            let mut levels = self.levels(side).iter();
+            match side {
+                Side::Bid => levels.next_back(),
+                Side::Ask => levels.next(),
+            }
+            .map(|(&price, level)| (price, level.total_qty()))
+        };
+        let bbo = self.bbo();
+        for (side, cached) in [(Side::Bid, bbo.bid), (Side::Ask, bbo.ask)] {
+            let actual = actual(side);
+            if cached != actual {
+                errors.push(ConsistencyError::StaleBest {
+                    side,
+                    cached,
+                    actual,
+                });
+            }
+        }
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors),
+        }
+    }
+
+    /// Panics with every inconsistency of the book, with the `debug-validate` feature.
+    /// Does nothing without it.
+    #[inline]
+    pub(crate) fn debug_validate(&self) {
+        #[cfg(feature = "debug-validate")]
+        if let Err(errors) = self.validate() {
+            panic!("Order book is inconsistent: {errors:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::Order;
+    use crate::orderbook::book::OrderLevel;
+
+    fn order(order_id: u64, side: Side, price: i64, size: u64) -> Order {
+        Order {
+            order_id,
+            side,
+            price,
+            size,
+            sequence: order_id as u32,
+            owner: None,
+            expires_at: None,
+        }
+    }
+
+    fn two_sided() -> OrderBook {
+        let mut book = OrderBook::new();
+        book.add_order(order(1, Side::Bid, 100, 10));
+        book.add_order(order(2, Side::Bid, 100, 20));
+        book.add_order(order(3, Side::Bid, 99, 5));
+        book.add_order(order(4, Side::Ask, 101, 7));
+        book
+    }
+
+    #[test]
+    fn test_consistent_book_validates() {
+        let mut book = two_sided();
+        assert_eq!(book.validate(), Ok(()));
+        book.modify_order(order(2, Side::Bid, 101, 30));
+        book.match_order(&order(5, Side::Ask, 99, 12));
+        book.remove_order(3);
+        assert_eq!(book.validate(), Ok(()));
+        assert_eq!(OrderBook::new().validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_corrupted_indexes_reported() {
+        let mut book = two_sided();
+        // Indexed at a price with no level, and resting without an index entry
+        book.order_index.insert(1, 98);
+        book.order_index.remove(&4);
+        assert_eq!(
+            book.validate(),
+            Err(vec![
+                ConsistencyError::MissingOrder {
+                    order_id: 1,
+                    price: 98
+                },
+                ConsistencyError::Unindexed {
+                    order_id: 1,
+                    price: 100,
+                    indexed: Some(98)
+                },
+                ConsistencyError::Unindexed {
+                    order_id: 4,
+                    price: 101,
+                    indexed: None
+                },
+            ])
+        );
+
+        let mut book = two_sided();
+        // Lookups go through the level's index, so the order cannot be found either
+        let level = book.levels_mut(Side::Bid).get_mut(&100).unwrap();
+        level.order_index.insert(2, 7);
+        assert_eq!(
+            book.validate(),
+            Err(vec![
+                ConsistencyError::MissingOrder {
+                    order_id: 2,
+                    price: 100
+                },
+                ConsistencyError::QueueMismatch {
+                    order_id: 2,
+                    side: Side::Bid,
+                    price: 100,
+                    queued: 1,
+                    indexed: Some(7)
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_corrupted_levels_reported() {
+        let mut book = two_sided();
+        let level = book.levels_mut(Side::Bid).get_mut(&99).unwrap();
+        let (_, resting) = level.queue.iter_mut().next().unwrap();
+        resting.size = 0;
+        resting.side = Side::Ask;
+        book.levels_mut(Side::Ask).insert(105, OrderLevel::new(105));
+        assert_eq!(
+            book.validate(),
+            Err(vec![
+                ConsistencyError::LevelQtyMismatch {
+                    side: Side::Bid,
+                    price: 99,
+                    cached: 5,
+                    queued: 0
+                },
+                ConsistencyError::WrongSide {
+                    order_id: 3,
+                    order_side: Side::Ask,
+                    level_side: Side::Bid
+                },
+                ConsistencyError::ZeroSize {
+                    order_id: 3,
+                    side: Side::Bid,
+                    price: 99
+                },
+                ConsistencyError::EmptyLevel {
+                    side: Side::Ask,
+                    price: 105
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_out_of_order_queue_reported() {
+        let mut book = two_sided();
+        let level = book.levels_mut(Side::Bid).get_mut(&100).unwrap();
+        // The second order claims a sequence ahead of the first
+        let (_, resting) = level.queue.iter_mut().nth(1).unwrap();
+        resting.sequence = 0;
+        let errors = book.validate().unwrap_err();
+        assert!(errors.contains(&ConsistencyError::QueueOutOfOrder {
+            order_id: 2,
+            side: Side::Bid,
+            price: 100
+        }));
+    }
+
+    #[test]
+    fn test_stale_best_reported() {
+        let mut book = two_sided();
+        book.bbo.bid = Some((100, 25));
+        book.bbo.ask = None;
+        assert_eq!(
+            book.validate(),
+            Err(vec![
+                ConsistencyError::StaleBest {
+                    side: Side::Bid,
+                    cached: Some((100, 25)),
+                    actual: Some((100, 30))
+                },
+                ConsistencyError::StaleBest {
+                    side: Side::Ask,
+                    cached: None,
+                    actual: Some((101, 7))
+                },
+            ])
+        );
+        assert!(
+            ConsistencyError::EmptyLevel {
+                side: Side::Ask,
+                price: 105
+            }
+            .to_string()
+            .contains("is empty")
+        );
+    }
+}
@@ -0,0 +1,117 @@
+//! Row-by-row conversion of tabular MBO data (CSV files, DataFrames) into messages.
+//!
+//! Tabular exports routinely contain rows that cannot be converted. Ingest functions
+//! return a `ConversionReport` listing those rows by index alongside the messages that
+//! could be converted, so that bad input is visible rather than silently dropped.
+
+use std::ffi::c_char;
+
+use dbn::MboMsg;
+use thiserror::Error;
+
+use crate::orderbook::{MarketByOrderMessage, MboProcessError};
+
+/// Why a row was left out of a `ConversionReport`.
+#[derive(Debug, Error, Clone)]
+pub enum ConversionError {
+    #[error("Column '{0}' is null")]
+    Null(&'static str),
+
+    #[error("Column '{column}' holds {value:?}, expected a single character")]
+    InvalidCharacter { column: &'static str, value: String },
+
+    #[error("Column '{column}' holds {value:?}, which is not a valid {expected}")]
+    InvalidValue {
+        column: &'static str,
+        value: String,
+        expected: &'static str,
+    },
+
+    #[error("Malformed row: {0}")]
+    Malformed(String),
+
+    #[error(transparent)]
+    Message(#[from] MboProcessError),
+}
+
+/// Result of a tabular ingest: the converted messages, in row order, and the rows that
+/// could not be converted.
+#[derive(Debug, Default, Clone)]
+pub struct ConversionReport {
+    pub messages: Vec<MarketByOrderMessage>,
+    /// 0-based row index and reason of every rejected row, in row order.
+    pub rejected: Vec<(usize, ConversionError)>,
+}
+
+impl ConversionReport {
+    pub fn converted(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.rejected.len()
+    }
+
+    /// The messages, or the first rejected row if any row was rejected.
+    pub fn into_strict(self) -> Result<Vec<MarketByOrderMessage>, (usize, ConversionError)> {
+        match self.rejected.into_iter().next() {
+            Some(rejected) => Err(rejected),
+            None => Ok(self.messages),
+        }
+    }
+
+    pub(crate) fn push(
+        &mut self,
+        row: usize,
+        result: Result<MarketByOrderMessage, ConversionError>,
+    ) {
+        match result {
+            Ok(message) => self.messages.push(message),
+            Err(error) => self.rejected.push((row, error)),
+        }
+    }
+}
+
+/// Field values of one row, with optional columns already defaulted.
+pub(crate) struct MboRow {
+    /// dbn action character, e.g. `b'A'`.
+    pub action: i8,
+    /// dbn side character, e.g. `b'B'`.
+    pub side: i8,
+    pub price: i64,
+    pub order_id: u64,
+    pub size: u32,
+    pub instrument_id: u32,
+    pub ts_event: u64,
+    pub ts_recv: u64,
+    pub ts_in_delta: i32,
+    pub sequence: u32,
+    pub flags: u8,
+    pub publisher_id: u16,
+    pub channel_id: u8,
+}
+
+impl MboRow {
+    /// Converts the row with the same rules as a decoded dbn record.
+    pub fn into_message(self) -> Result<MarketByOrderMessage, ConversionError> {
+        let record = MboMsg {
+            hd: dbn::RecordHeader::new::<MboMsg>(
+                dbn::enums::rtype::MBO,
+                self.publisher_id,
+                self.instrument_id,
+                self.ts_event,
+            ),
+            order_id: self.order_id,
+            price: self.price,
+            size: self.size,
+            flags: dbn::FlagSet::new(self.flags),
+            channel_id: self.channel_id,
+            action: self.action as c_char,
+            side: self.side as c_char,
+            ts_recv: self.ts_recv,
+            ts_in_delta: self.ts_in_delta,
+            sequence: self.sequence,
+        };
+        Ok(MarketByOrderMessage::try_from(&record)?)
+    }
+}
@@ -0,0 +1,517 @@
+//! Reading MBO messages from CSV files, optionally gzip-compressed, and writing them.
+//!
+//! Columns follow `into_mbo_messages`: `action`, `side`, `price`, `order_id` and `size`
+//! are required, `instrument_id`, `ts_event`, `ts_recv`, `ts_in_delta`, `sequence` and
+//! `flags` are optional. Files without a header must list the required columns in that
+//! order, optionally followed by `ts_event`. `write_mbo_csv` writes every column.
+
+use std::any;
+use std::borrow::Borrow;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use csv::{ReaderBuilder, StringRecord, StringRecordsIntoIter, Writer};
+use flate2::read::MultiGzDecoder;
+use thiserror::Error;
+
+use crate::orderbook::conversion::MboRow;
+use crate::orderbook::{ConversionError, ConversionReport, MarketByOrderMessage};
+
+/// Columns written by `write_mbo_csv`.
+const COLUMNS: [&str; 11] = [
+    "action",
+    "side",
+    "price",
+    "order_id",
+    "size",
+    "instrument_id",
+    "ts_event",
+    "ts_recv",
+    "ts_in_delta",
+    "sequence",
+    "flags",
+];
+
+#[derive(Debug, Error)]
+pub enum CsvReadError {
+    #[error("Failed to read CSV: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Failed to read CSV: {0}")]
+    Csv(#[from] csv::Error),
+
+    #[error("CSV header has no '{0}' column")]
+    MissingColumn(&'static str),
+}
+
+/// How the `action` and `side` columns are encoded.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum CharEncoding {
+    /// The dbn character itself, e.g. `A` for Add and `B` for Bid.
+    #[default]
+    Character,
+    /// The character's code, e.g. `65` for Add and `66` for Bid.
+    Numeric,
+}
+
+/// Options for `read_mbo_csv`. Defaults to comma-separated with a header row and
+/// character-encoded action and side.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CsvOptions {
+    delimiter: u8,
+    has_header: bool,
+    char_encoding: CharEncoding,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            has_header: true,
+            char_encoding: CharEncoding::Character,
+        }
+    }
+}
+
+impl CsvOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Whether the first row names the columns. Without a header, columns are read by
+    /// position.
+    pub fn with_header(mut self, has_header: bool) -> Self {
+        self.has_header = has_header;
+        self
+    }
+
+    pub fn with_char_encoding(mut self, char_encoding: CharEncoding) -> Self {
+        self.char_encoding = char_encoding;
+        self
+    }
+
+    pub fn delimiter(&self) -> u8 {
+        self.delimiter
+    }
+
+    pub fn has_header(&self) -> bool {
+        self.has_header
+    }
+
+    pub fn char_encoding(&self) -> CharEncoding {
+        self.char_encoding
+    }
+}
+
+/// Reads a CSV file of MBO rows. Files ending in `.gz` are decompressed.
+///
+/// Rows that are malformed, have an empty or unparsable required field or fail
+/// conversion are reported in `ConversionReport::rejected` with their 0-based index
+/// (not counting the header). Empty optional fields take their defaults.
+pub fn read_mbo_csv(
+    path: impl AsRef<Path>,
+    options: CsvOptions,
+) -> Result<ConversionReport, CsvReadError> {
+    let path = path.as_ref();
+    let file = BufReader::new(File::open(path)?);
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        read_mbo_csv_from(MultiGzDecoder::new(file), options)
+    } else {
+        read_mbo_csv_from(file, options)
+    }
+}
+
+/// Like `read_mbo_csv`, reading uncompressed CSV from `reader`.
+pub fn read_mbo_csv_from<R: Read>(
+    reader: R,
+    options: CsvOptions,
+) -> Result<ConversionReport, CsvReadError> {
+    let mut report = ConversionReport::default();
+    for row in iter_mbo_csv_from(reader, options)? {
+        let (row, converted) = row?;
+        report.push(row, converted);
+    }
+    Ok(report)
+}
+
+/// Like `read_mbo_csv_from`, converting one row at a time as the returned iterator is
+/// advanced instead of collecting the messages.
+///
+/// The header is read up front. Each item is a row's 0-based index with its message,
+/// or why it was rejected; an error reading `reader` is yielded as an `Err` item, after
+/// which the rows are not to be relied on.
+pub fn iter_mbo_csv_from<R: Read>(
+    reader: R,
+    options: CsvOptions,
+) -> Result<CsvRows<R>, CsvReadError> {
+    let mut reader = ReaderBuilder::new()
+        .delimiter(options.delimiter)
+        .has_headers(options.has_header)
+        .from_reader(reader);
+    let layout = if options.has_header {
+        Layout::from_header(reader.headers()?)?
+    } else {
+        Layout::POSITIONAL
+    };
+    Ok(CsvRows {
+        records: reader.into_records(),
+        layout,
+        char_encoding: options.char_encoding,
+        row: 0,
+    })
+}
+
+/// Rows of CSV input converted as they are read, returned by `iter_mbo_csv_from`.
+pub struct CsvRows<R> {
+    records: StringRecordsIntoIter<R>,
+    layout: Layout,
+    char_encoding: CharEncoding,
+    row: usize,
+}
+
+impl<R: Read> Iterator for CsvRows<R> {
+    type Item = Result<(usize, Result<MarketByOrderMessage, ConversionError>), CsvReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let converted = match self.records.next()? {
+            Ok(record) => self.layout.row(&record, self.char_encoding),
+            Err(e) if e.is_io_error() => return Some(Err(e.into())),
+            Err(e) => Err(ConversionError::Malformed(e.to_string())),
+        };
+        let row = self.row;
+        self.row += 1;
+        Some(Ok((row, converted)))
+    }
+}
+
+/// Writes `messages` as comma-separated CSV with a header row, every column and
+/// character-encoded action and side, as read back with the default `CsvOptions`.
+pub fn write_mbo_csv<W, I>(writer: W, messages: I) -> io::Result<()>
+where
+    W: Write,
+    I: IntoIterator,
+    I::Item: Borrow<MarketByOrderMessage>,
+{
+    let mut writer = Writer::from_writer(writer);
+    writer.write_record(COLUMNS)?;
+    for message in messages {
+        let message = message.borrow();
+        writer.write_record([
+            message.action_char().to_string(),
+            message.side_char().to_string(),
+            message.price.to_string(),
+            message.order_id.to_string(),
+            message.size.to_string(),
+            message.instrument_id.to_string(),
+            message.event_time.unix_timestamp_nanos().to_string(),
+            message.recv_time.unix_timestamp_nanos().to_string(),
+            message.ts_in_delta.whole_nanoseconds().to_string(),
+            message.sequence.to_string(),
+            message.flags.to_string(),
+        ])?;
+    }
+    writer.flush()
+}
+
+/// Field index of every column; `None` for an absent optional column.
+struct Layout {
+    action: usize,
+    side: usize,
+    price: usize,
+    order_id: usize,
+    size: usize,
+    instrument_id: Option<usize>,
+    ts_event: Option<usize>,
+    ts_recv: Option<usize>,
+    ts_in_delta: Option<usize>,
+    sequence: Option<usize>,
+    flags: Option<usize>,
+}
+
+impl Layout {
+    const POSITIONAL: Layout = Layout {
+        action: 0,
+        side: 1,
+        price: 2,
+        order_id: 3,
+        size: 4,
+        instrument_id: None,
+        ts_event: Some(5),
+        ts_recv: None,
+        ts_in_delta: None,
+        sequence: None,
+        flags: None,
+    };
+
+    fn from_header(header: &StringRecord) -> Result<Self, CsvReadError> {
+        let find = |name| header.iter().position(|column| column.trim() == name);
+        let require = |name| find(name).ok_or(CsvReadError::MissingColumn(name));
+        Ok(Layout {
+            action: require("action")?,
+            side: require("side")?,
+            price: require("price")?,
+            order_id: require("order_id")?,
+            size: require("size")?,
+            instrument_id: find("instrument_id"),
+            ts_event: find("ts_event"),
+            ts_recv: find("ts_recv"),
+            ts_in_delta: find("ts_in_delta"),
+            sequence: find("sequence"),
+            flags: find("flags"),
+        })
+    }
+
+    fn row(
+        &self,
+        record: &StringRecord,
+        encoding: CharEncoding,
+    ) -> Result<MarketByOrderMessage, ConversionError> {
+        let ts_event = optional(record, "ts_event", self.ts_event)?.unwrap_or_default();
+        MboRow {
+            action: char_field(record, "action", self.action, encoding)?,
+            side: char_field(record, "side", self.side, encoding)?,
+            price: required(record, "price", self.price)?,
+            order_id: required(record, "order_id", self.order_id)?,
+            size: required(record, "size", self.size)?,
+            instrument_id: optional(record, "instrument_id", self.instrument_id)?
+                .unwrap_or_default(),
+            ts_event,
+            ts_recv: optional(record, "ts_recv", self.ts_recv)?.unwrap_or(ts_event),
+            ts_in_delta: optional(record, "ts_in_delta", self.ts_in_delta)?.unwrap_or_default(),
+            sequence: optional(record, "sequence", self.sequence)?.unwrap_or_default(),
+            flags: optional(record, "flags", self.flags)?.unwrap_or(dbn::flags::LAST),
+            publisher_id: 0,
+            channel_id: 0,
+        }
+        .into_message()
+    }
+}
+
+/// The trimmed field at `index`, or `None` if it is absent or empty.
+fn field(record: &StringRecord, index: Option<usize>) -> Option<&str> {
+    index
+        .and_then(|index| record.get(index))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+fn required<T: FromStr>(
+    record: &StringRecord,
+    column: &'static str,
+    index: usize,
+) -> Result<T, ConversionError> {
+    let value = field(record, Some(index)).ok_or(ConversionError::Null(column))?;
+    parse(column, value)
+}
+
+fn optional<T: FromStr>(
+    record: &StringRecord,
+    column: &'static str,
+    index: Option<usize>,
+) -> Result<Option<T>, ConversionError> {
+    field(record, index)
+        .map(|value| parse(column, value))
+        .transpose()
+}
+
+fn char_field(
+    record: &StringRecord,
+    column: &'static str,
+    index: usize,
+    encoding: CharEncoding,
+) -> Result<i8, ConversionError> {
+    let value = field(record, Some(index)).ok_or(ConversionError::Null(column))?;
+    match encoding {
+        CharEncoding::Character => match value.as_bytes() {
+            &[c] if c.is_ascii() => Ok(c as i8),
+            _ => Err(ConversionError::InvalidCharacter {
+                column,
+                value: value.to_owned(),
+            }),
+        },
+        CharEncoding::Numeric => parse(column, value),
+    }
+}
+
+fn parse<T: FromStr>(column: &'static str, value: &str) -> Result<T, ConversionError> {
+    value.parse().map_err(|_| ConversionError::InvalidValue {
+        column,
+        value: value.to_owned(),
+        expected: any::type_name::<T>(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::{env, fs, process};
+
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+
+    use crate::orderbook::{Action, MboProcessError, MboProcessor, Side};
+
+    const FIXTURE: &str = "\
+action,side,price,order_id,size,ts_event
+A,B,100,1,10,1000
+A,A,101,2,5,2000
+M,B,99,1,20,3000
+C,A,101,2,5,4000
+";
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        env::temp_dir().join(format!("rainybook-{}-{name}", process::id()))
+    }
+
+    #[test]
+    fn test_read_fixture_file() {
+        let path = temp_path("fixture.csv");
+        fs::write(&path, FIXTURE).unwrap();
+        let report = read_mbo_csv(&path, CsvOptions::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(report.skipped(), 0);
+        let messages = report.messages;
+        let summary: Vec<(Action, Side, i64, u64, u32)> = messages
+            .iter()
+            .map(|m| (m.action, m.side, m.price, m.order_id, m.size))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (Action::Add, Side::Bid, 100, 1, 10),
+                (Action::Add, Side::Ask, 101, 2, 5),
+                (Action::Modify, Side::Bid, 99, 1, 20),
+                (Action::Cancel, Side::Ask, 101, 2, 5),
+            ]
+        );
+        assert_eq!(messages[2].event_time.unix_timestamp_nanos(), 3_000);
+        assert!(messages.iter().all(|m| m.is_last));
+
+        let mut proc = MboProcessor::new();
+        assert!(proc.process_messages(&messages).is_success());
+        assert_eq!(proc.best_bid(), Some((99, 20)));
+        assert_eq!(proc.best_ask(), None);
+    }
+
+    #[test]
+    fn test_read_gzip_file() {
+        let path = temp_path("fixture.csv.gz");
+        let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        encoder.write_all(FIXTURE.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+        let report = read_mbo_csv(&path, CsvOptions::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(report.converted(), 4);
+        assert_eq!(report.skipped(), 0);
+    }
+
+    #[test]
+    fn test_numeric_chars_without_header() {
+        let csv = "65;66;100;1;10\n65;65;101;2;5;2000\n";
+        let options = CsvOptions::new()
+            .with_delimiter(b';')
+            .with_header(false)
+            .with_char_encoding(CharEncoding::Numeric);
+        let report = read_mbo_csv_from(csv.as_bytes(), options).unwrap();
+
+        // The second row has an extra field, which the csv reader rejects
+        assert_eq!(report.converted(), 1);
+        assert_eq!(report.messages[0].side, Side::Bid);
+        assert!(matches!(
+            report.rejected[..],
+            [(1, ConversionError::Malformed(_))]
+        ));
+    }
+
+    #[test]
+    fn test_bad_rows_reported_with_index() {
+        let csv = "\
+action,side,price,order_id,size
+A,B,100,1,10
+A,B,,2,10
+A,B,abc,3,10
+X,B,100,4,10
+Add,B,100,5,10
+A,B,100,6,10
+";
+        let report = read_mbo_csv_from(csv.as_bytes(), CsvOptions::default()).unwrap();
+        assert_eq!(report.converted(), 2);
+        let rejected: Vec<usize> = report.rejected.iter().map(|(row, _)| *row).collect();
+        assert_eq!(rejected, vec![1, 2, 3, 4]);
+        assert!(matches!(
+            report.rejected[0].1,
+            ConversionError::Null("price")
+        ));
+        assert!(matches!(
+            report.rejected[1].1,
+            ConversionError::InvalidValue {
+                column: "price",
+                ..
+            }
+        ));
+        assert!(matches!(
+            report.rejected[2].1,
+            ConversionError::Message(MboProcessError::UnknownAction(_))
+        ));
+        assert!(matches!(
+            report.rejected[3].1,
+            ConversionError::InvalidCharacter {
+                column: "action",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        let mut messages = read_mbo_csv_from(FIXTURE.as_bytes(), CsvOptions::default())
+            .unwrap()
+            .messages;
+        messages[0].instrument_id = 7;
+        messages[1].flags = dbn::flags::SNAPSHOT;
+        messages[1].is_last = false;
+
+        let mut csv = Vec::new();
+        write_mbo_csv(&mut csv, &messages).unwrap();
+        let report = read_mbo_csv_from(&csv[..], CsvOptions::default()).unwrap();
+        assert_eq!(report.skipped(), 0);
+        assert_eq!(report.messages, messages);
+    }
+
+    #[test]
+    fn test_missing_column() {
+        let csv = "action,side,price,size\nA,B,100,10\n";
+        assert!(matches!(
+            read_mbo_csv_from(csv.as_bytes(), CsvOptions::default()),
+            Err(CsvReadError::MissingColumn("order_id"))
+        ));
+    }
+
+    #[test]
+    fn test_iter_converts_rows_as_read() {
+        let csv = "action,side,price,order_id,size\nA,B,100,1,10\nA,B,abc,2,10\n";
+        let mut rows = iter_mbo_csv_from(csv.as_bytes(), CsvOptions::default()).unwrap();
+        let (row, converted) = rows.next().unwrap().unwrap();
+        assert_eq!((row, converted.unwrap().order_id), (0, 1));
+        let (row, converted) = rows.next().unwrap().unwrap();
+        assert_eq!(row, 1);
+        assert!(matches!(
+            converted,
+            Err(ConversionError::InvalidValue {
+                column: "price",
+                ..
+            })
+        ));
+        assert!(rows.next().is_none());
+    }
+}
@@ -0,0 +1,700 @@
+//! Conversion of MBO rows held in a Polars DataFrame, e.g. read from a parquet export.
+//!
+//! Exports disagree on column types: action and side may be stored as `i8` character
+//! codes or as one-character strings, and integer columns are often wider or of the
+//! other signedness than the dbn fields they came from. Integer columns are cast to
+//! the dbn field type when every value fits; anything else is a schema error naming
+//! the column. Rows that cannot be converted are reported individually rather than
+//! failing the whole frame. Exports with other column names are read through a
+//! `ColumnMapping`.
+//!
+//! `into_mbo_messages` collects every message; `iter_mbo_messages` converts a row at a
+//! time for frames too large to hold twice. `filter_instrument` narrows a frame to one
+//! instrument before either. `mbo_messages_to_dataframe` goes the other way.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::str::FromStr;
+
+use polars::prelude::{
+    ChunkCompareEq, DataFrame, DataType, Int8Chunked, Int32Chunked, Int64Chunked, PolarsResult,
+    Series, StringChunked, UInt8Chunked, UInt16Chunked, UInt32Chunked, UInt64Chunked, df,
+    polars_bail, polars_err,
+};
+use thiserror::Error;
+
+use crate::orderbook::conversion::MboRow;
+use crate::orderbook::{ConversionError, ConversionReport, MarketByOrderMessage};
+
+/// The MBO fields read from DataFrame columns, in `into_mbo_messages` order.
+const FIELDS: [&str; 13] = [
+    "action",
+    "side",
+    "price",
+    "order_id",
+    "size",
+    "instrument_id",
+    "ts_event",
+    "ts_recv",
+    "ts_in_delta",
+    "sequence",
+    "flags",
+    "publisher_id",
+    "channel_id",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ColumnMappingError {
+    #[error("Unknown MBO field '{0}', expected one of {fields}", fields = FIELDS.join(", "))]
+    UnknownField(String),
+
+    #[error("Invalid column mapping '{0}', expected field=column")]
+    InvalidEntry(String),
+}
+
+/// Names of the DataFrame columns holding the MBO fields.
+///
+/// The default reads every field from the column of the same name. Parses from a list
+/// of renamed fields such as `action=act,side=bs,price=px`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ColumnMapping {
+    renamed: BTreeMap<&'static str, String>,
+}
+
+impl ColumnMapping {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read `field` from `column`. Fails if `field` is not an MBO field.
+    pub fn with_column(
+        mut self,
+        field: &str,
+        column: impl Into<String>,
+    ) -> Result<Self, ColumnMappingError> {
+        let field = FIELDS
+            .into_iter()
+            .find(|known| *known == field)
+            .ok_or_else(|| ColumnMappingError::UnknownField(field.to_owned()))?;
+        self.renamed.insert(field, column.into());
+        Ok(self)
+    }
+
+    /// Name of the column holding `field`.
+    pub fn column<'a>(&'a self, field: &'a str) -> &'a str {
+        self.renamed.get(field).map_or(field, String::as_str)
+    }
+
+    /// Whether `field` was given a column, in which case it must exist even if the
+    /// field is optional.
+    pub fn is_renamed(&self, field: &str) -> bool {
+        self.renamed.contains_key(field)
+    }
+}
+
+impl FromStr for ColumnMapping {
+    type Err = ColumnMappingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .try_fold(Self::new(), |mapping, entry| match entry.split_once('=') {
+                Some((field, column)) if !column.trim().is_empty() => {
+                    mapping.with_column(field.trim(), column.trim())
+                }
+                _ => Err(ColumnMappingError::InvalidEntry(entry.to_owned())),
+            })
+    }
+}
+
+/// Converts a DataFrame of MBO rows into messages.
+///
+/// Required columns: `action` and `side` (`i8` codes or one-character strings such as
+/// `"A"`, `"B"`), `price` (`i64`, fixed-point), `order_id` (`u64`) and `size` (`u32`).
+/// Optional columns, defaulted when absent or null: `instrument_id` (`u32`, 0),
+/// `ts_event` and `ts_recv` (`u64` nanoseconds, 0 and `ts_event`), `ts_in_delta`
+/// (`i32`, 0), `sequence` (`u32`, 0), `flags` (`u8`, `F_LAST`), `publisher_id` (`u16`,
+/// 0) and `channel_id` (`u8`, 0).
+///
+/// Columns that already have the listed type are used as-is. Other integer columns are
+/// cast, failing if a value does not fit the target type. A row with a null in a
+/// required column or that fails conversion is reported in
+/// `ConversionReport::rejected` with its index.
+pub fn into_mbo_messages(df: &DataFrame) -> PolarsResult<ConversionReport> {
+    into_mbo_messages_with(df, &ColumnMapping::default())
+}
+
+/// Like `into_mbo_messages`, reading each field from the column `mapping` names.
+///
+/// A missing required column, or a missing optional column that `mapping` renames, is
+/// an error naming both the field and the column.
+pub fn into_mbo_messages_with(
+    df: &DataFrame,
+    mapping: &ColumnMapping,
+) -> PolarsResult<ConversionReport> {
+    let mut report = ConversionReport::default();
+    for (row, converted) in iter_mbo_messages_with(df, mapping)?.enumerate() {
+        report.push(row, converted);
+    }
+    Ok(report)
+}
+
+/// Converts the rows of `df` one at a time as the returned iterator is advanced, by the
+/// rules of `into_mbo_messages`, without collecting the messages.
+///
+/// Missing columns and columns of the wrong type fail up front. A row that cannot be
+/// converted yields its error in place of the message.
+pub fn iter_mbo_messages(df: &DataFrame) -> PolarsResult<MboRows> {
+    iter_mbo_messages_with(df, &ColumnMapping::default())
+}
+
+/// Like `iter_mbo_messages`, reading each field from the column `mapping` names.
+pub fn iter_mbo_messages_with(df: &DataFrame, mapping: &ColumnMapping) -> PolarsResult<MboRows> {
+    let integer = |field, target| integer_column(mapped_column(df, mapping, field)?, target);
+    let optional = |field, target| optional_integer_column(df, mapping, field, target);
+    Ok(MboRows {
+        action: char_column(mapped_column(df, mapping, "action")?)?,
+        side: char_column(mapped_column(df, mapping, "side")?)?,
+        price: integer("price", &DataType::Int64)?.i64()?.clone(),
+        order_id: integer("order_id", &DataType::UInt64)?.u64()?.clone(),
+        size: integer("size", &DataType::UInt32)?.u32()?.clone(),
+        instrument_id: optional("instrument_id", &DataType::UInt32)?.u32()?.clone(),
+        ts_event: optional("ts_event", &DataType::UInt64)?.u64()?.clone(),
+        ts_recv: optional("ts_recv", &DataType::UInt64)?.u64()?.clone(),
+        ts_in_delta: optional("ts_in_delta", &DataType::Int32)?.i32()?.clone(),
+        sequence: optional("sequence", &DataType::UInt32)?.u32()?.clone(),
+        flags: optional("flags", &DataType::UInt8)?.u8()?.clone(),
+        publisher_id: optional("publisher_id", &DataType::UInt16)?.u16()?.clone(),
+        channel_id: optional("channel_id", &DataType::UInt8)?.u8()?.clone(),
+        rows: 0..df.height(),
+    })
+}
+
+/// Messages converted from the rows of a DataFrame as they are iterated, returned by
+/// `iter_mbo_messages`.
+///
+/// The columns share the DataFrame's buffers, apart from those that had to be cast, so
+/// the iterator does not borrow the DataFrame and adds no copy of its rows.
+pub struct MboRows {
+    action: CharColumn,
+    side: CharColumn,
+    price: Int64Chunked,
+    order_id: UInt64Chunked,
+    size: UInt32Chunked,
+    instrument_id: UInt32Chunked,
+    ts_event: UInt64Chunked,
+    ts_recv: UInt64Chunked,
+    ts_in_delta: Int32Chunked,
+    sequence: UInt32Chunked,
+    flags: UInt8Chunked,
+    publisher_id: UInt16Chunked,
+    channel_id: UInt8Chunked,
+    rows: Range<usize>,
+}
+
+impl MboRows {
+    fn convert(&self, row: usize) -> Result<MarketByOrderMessage, ConversionError> {
+        let ts_event = self.ts_event.get(row).unwrap_or_default();
+        MboRow {
+            action: char_value("action", self.action.get(row))?,
+            side: char_value("side", self.side.get(row))?,
+            price: self.price.get(row).ok_or(ConversionError::Null("price"))?,
+            order_id: self
+                .order_id
+                .get(row)
+                .ok_or(ConversionError::Null("order_id"))?,
+            size: self.size.get(row).ok_or(ConversionError::Null("size"))?,
+            instrument_id: self.instrument_id.get(row).unwrap_or_default(),
+            ts_event,
+            ts_recv: self.ts_recv.get(row).unwrap_or(ts_event),
+            ts_in_delta: self.ts_in_delta.get(row).unwrap_or_default(),
+            sequence: self.sequence.get(row).unwrap_or_default(),
+            flags: self.flags.get(row).unwrap_or(dbn::flags::LAST),
+            publisher_id: self.publisher_id.get(row).unwrap_or_default(),
+            channel_id: self.channel_id.get(row).unwrap_or_default(),
+        }
+        .into_message()
+    }
+}
+
+impl Iterator for MboRows {
+    type Item = Result<MarketByOrderMessage, ConversionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let row = self.rows.next()?;
+        Some(self.convert(row))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.rows.size_hint()
+    }
+}
+
+impl ExactSizeIterator for MboRows {}
+
+fn char_value(
+    column: &'static str,
+    value: Option<Result<i8, &str>>,
+) -> Result<i8, ConversionError> {
+    match value {
+        Some(Ok(code)) => Ok(code),
+        Some(Err(value)) => Err(ConversionError::InvalidCharacter {
+            column,
+            value: value.to_owned(),
+        }),
+        None => Err(ConversionError::Null(column)),
+    }
+}
+
+/// The rows of `df` whose `instrument_id` column, or the column `mapping` names for
+/// it, equals `instrument_id`.
+///
+/// Unlike conversion, which defaults a missing `instrument_id` column to 0, a missing
+/// column is an error: every row of such a frame would pass or fail the filter alike,
+/// hiding that the frame may mix instruments.
+pub fn filter_instrument(
+    df: &DataFrame,
+    mapping: &ColumnMapping,
+    instrument_id: u32,
+) -> PolarsResult<DataFrame> {
+    let column = integer_column(
+        mapped_column(df, mapping, "instrument_id")?,
+        &DataType::UInt32,
+    )?;
+    df.filter(&column.u32()?.equal(instrument_id))
+}
+
+/// A DataFrame of `messages` with every column `into_mbo_messages` reads, each of its
+/// listed type, with action and side as `i8` character codes.
+pub fn mbo_messages_to_dataframe(messages: &[MarketByOrderMessage]) -> PolarsResult<DataFrame> {
+    fn column<T>(
+        messages: &[MarketByOrderMessage],
+        field: impl Fn(&MarketByOrderMessage) -> T,
+    ) -> Vec<T> {
+        messages.iter().map(field).collect()
+    }
+
+    df!(
+        "action" => column(messages, |message| message.action_char() as i8),
+        "side" => column(messages, |message| message.side_char() as i8),
+        "price" => column(messages, |message| message.price),
+        "order_id" => column(messages, |message| message.order_id),
+        "size" => column(messages, |message| message.size),
+        "instrument_id" => column(messages, |message| message.instrument_id),
+        "ts_event" => column(messages, |message| message.event_time.unix_timestamp_nanos() as u64),
+        "ts_recv" => column(messages, |message| message.recv_time.unix_timestamp_nanos() as u64),
+        "ts_in_delta" => column(messages, |message| message.ts_in_delta.whole_nanoseconds() as i32),
+        "sequence" => column(messages, |message| message.sequence),
+        "flags" => column(messages, |message| message.flags),
+        "publisher_id" => column(messages, |message| message.publisher_id),
+        "channel_id" => column(messages, |message| message.channel_id),
+    )
+}
+
+/// The column `mapping` names for `field`.
+fn mapped_column<'a>(
+    df: &'a DataFrame,
+    mapping: &ColumnMapping,
+    field: &str,
+) -> PolarsResult<&'a Series> {
+    let name = mapping.column(field);
+    df.column(name)
+        .map(|column| column.as_materialized_series())
+        .map_err(
+            |_| polars_err!(ColumnNotFound: "column '{}' for field '{}' not found", name, field),
+        )
+}
+
+/// Reads an integer column as `target`, casting it if its type differs.
+pub(crate) fn integer_column(series: &Series, target: &DataType) -> PolarsResult<Series> {
+    let name = series.name();
+    let dtype = series.dtype();
+    if dtype == target {
+        return Ok(series.clone());
+    }
+    if !dtype.is_integer() {
+        polars_bail!(
+            SchemaMismatch: "column '{}' has type {}, expected an integer type castable to {}",
+            name, dtype, target
+        );
+    }
+    series.strict_cast(target).map_err(|_| {
+        polars_err!(
+            SchemaMismatch: "column '{}' of type {} has values that do not fit {}",
+            name, dtype, target
+        )
+    })
+}
+
+/// Like `integer_column`, returning an all-null column if `field` is absent and not
+/// renamed by `mapping`.
+fn optional_integer_column(
+    df: &DataFrame,
+    mapping: &ColumnMapping,
+    field: &str,
+    target: &DataType,
+) -> PolarsResult<Series> {
+    if mapping.is_renamed(field) || df.get_column_index(field).is_some() {
+        integer_column(mapped_column(df, mapping, field)?, target)
+    } else {
+        Ok(Series::full_null(field.into(), df.height(), target))
+    }
+}
+
+/// A dbn character column stored either as `i8` codes or as strings.
+enum CharColumn {
+    Codes(Int8Chunked),
+    Text(StringChunked),
+}
+
+impl CharColumn {
+    /// The value at `row` as a character code. A string that is not a single ASCII
+    /// character is returned as the error.
+    fn get(&self, row: usize) -> Option<Result<i8, &str>> {
+        match self {
+            CharColumn::Codes(codes) => codes.get(row).map(Ok),
+            CharColumn::Text(text) => text.get(row).map(|value| match value.as_bytes() {
+                &[c] if c.is_ascii() => Ok(c as i8),
+                _ => Err(value),
+            }),
+        }
+    }
+}
+
+fn char_column(series: &Series) -> PolarsResult<CharColumn> {
+    if series.dtype() == &DataType::String {
+        return Ok(CharColumn::Text(series.str()?.clone()));
+    }
+    Ok(CharColumn::Codes(
+        integer_column(series, &DataType::Int8)?.i8()?.clone(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use polars::prelude::df;
+
+    use crate::orderbook::{Action, MboProcessError, MboProcessor, Side};
+
+    #[test]
+    fn test_native_dtypes() {
+        let df = df!(
+            "action" => [b'A' as i8, b'A' as i8, b'C' as i8],
+            "side" => [b'B' as i8, b'A' as i8, b'B' as i8],
+            "price" => [100i64, 101, 100],
+            "order_id" => [1u64, 2, 1],
+            "size" => [10u32, 5, 10],
+            "ts_event" => [1_000u64, 2_000, 3_000],
+        )
+        .unwrap();
+
+        let messages = into_mbo_messages(&df).unwrap().messages;
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1].action, Action::Add);
+        assert_eq!(messages[1].side, Side::Ask);
+        assert_eq!(messages[2].action, Action::Cancel);
+        assert!(messages.iter().all(|message| message.is_last));
+        assert_eq!(messages[2].recv_time.unix_timestamp_nanos(), 3_000);
+        assert_eq!((messages[0].publisher_id, messages[0].channel_id), (0, 0));
+
+        let mut proc = MboProcessor::new();
+        assert!(proc.process_messages(&messages).is_success());
+        assert_eq!(proc.best_bid(), None);
+        assert_eq!(proc.best_ask(), Some((101, 5)));
+    }
+
+    #[test]
+    fn test_string_action_and_side() {
+        let df = df!(
+            "action" => ["A", "A", "M"],
+            "side" => ["B", "A", "B"],
+            "price" => [100i64, 101, 99],
+            "order_id" => [1u64, 2, 1],
+            "size" => [10u32, 5, 20],
+        )
+        .unwrap();
+
+        let messages = into_mbo_messages(&df).unwrap().messages;
+        let actions: Vec<Action> = messages.iter().map(|message| message.action).collect();
+        assert_eq!(actions, vec![Action::Add, Action::Add, Action::Modify]);
+        assert_eq!(messages[0].side, Side::Bid);
+        assert_eq!(messages[2].price, 99);
+        assert_eq!(messages[2].size, 20);
+    }
+
+    #[test]
+    fn test_i64_order_id_and_size_cast() {
+        let df = df!(
+            "action" => ["A", "A"],
+            "side" => ["B", "B"],
+            "price" => [100i64, 99],
+            "order_id" => [1i64, 2],
+            "size" => [10i64, 20],
+        )
+        .unwrap();
+
+        let messages = into_mbo_messages(&df).unwrap().messages;
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].order_id, 2);
+        assert_eq!(messages[1].size, 20);
+    }
+
+    #[test]
+    fn test_incompatible_dtype_errors() {
+        let df = df!(
+            "action" => ["A"],
+            "side" => ["B"],
+            "price" => [100.25f64],
+            "order_id" => [1u64],
+            "size" => [10u32],
+        )
+        .unwrap();
+
+        let message = into_mbo_messages(&df).unwrap_err().to_string();
+        assert!(message.contains("'price'"), "{message}");
+        assert!(message.contains("f64"), "{message}");
+    }
+
+    #[test]
+    fn test_out_of_range_cast_errors() {
+        let df = df!(
+            "action" => ["A"],
+            "side" => ["B"],
+            "price" => [100i64],
+            "order_id" => [-1i64],
+            "size" => [10u32],
+        )
+        .unwrap();
+
+        let message = into_mbo_messages(&df).unwrap_err().to_string();
+        assert!(message.contains("'order_id'"), "{message}");
+    }
+
+    #[test]
+    fn test_rejected_rows_reported_with_index() {
+        let price: Vec<Option<i64>> = (0..10).map(|row| (row != 3).then_some(100 + row)).collect();
+        let action: Vec<&str> = (0..10)
+            .map(|row| if row == 7 { "X" } else { "A" })
+            .collect();
+        let df = df!(
+            "action" => action,
+            "side" => ["B"; 10],
+            "price" => price,
+            "order_id" => (1..=10u64).collect::<Vec<_>>(),
+            "size" => [10u32; 10],
+        )
+        .unwrap();
+
+        let report = into_mbo_messages(&df).unwrap();
+        assert_eq!(report.converted(), 8);
+        assert_eq!(report.skipped(), 2);
+        assert!(matches!(
+            report.rejected[0],
+            (3, ConversionError::Null("price"))
+        ));
+        assert!(matches!(
+            report.rejected[1],
+            (
+                7,
+                ConversionError::Message(MboProcessError::UnknownAction(a))
+            ) if a == b'X' as i8
+        ));
+        assert!(matches!(
+            report.into_strict(),
+            Err((3, ConversionError::Null("price")))
+        ));
+    }
+
+    #[test]
+    fn test_iterator_matches_report() {
+        let df = df!(
+            "action" => [b'A' as i8, b'A' as i8, b'X' as i8, b'C' as i8],
+            "side" => ["B", "A", "B", "B"],
+            "price" => [Some(100i64), Some(101), Some(99), None],
+            "order_id" => [1u64, 2, 3, 1],
+            "size" => [10i64, 5, 10, 10],
+            "ts_event" => [1_000u64, 2_000, 3_000, 4_000],
+        )
+        .unwrap();
+
+        let rows = iter_mbo_messages(&df).unwrap();
+        assert_eq!(rows.len(), 4);
+        let converted: Vec<_> = rows.collect();
+        let report = into_mbo_messages(&df).unwrap();
+        let messages: Vec<_> = converted.iter().flatten().copied().collect();
+        assert_eq!(messages, report.messages);
+
+        // Rows that fail are yielded in place, in the same order as the report
+        let failed: Vec<usize> = converted
+            .iter()
+            .enumerate()
+            .filter_map(|(row, converted)| converted.is_err().then_some(row))
+            .collect();
+        let rejected: Vec<usize> = report.rejected.iter().map(|(row, _)| *row).collect();
+        assert_eq!(failed, vec![2, 3]);
+        assert_eq!(failed, rejected);
+        assert!(matches!(converted[3], Err(ConversionError::Null("price"))));
+    }
+
+    #[test]
+    fn test_renamed_columns_match_canonical() {
+        let canonical = df!(
+            "action" => ["A", "A", "C"],
+            "side" => ["B", "A", "B"],
+            "price" => [100i64, 101, 100],
+            "order_id" => [1u64, 2, 1],
+            "size" => [10u32, 5, 10],
+            "ts_event" => [1_000u64, 2_000, 3_000],
+            "sequence" => [7u32, 8, 9],
+            "instrument_id" => [3u32, 3, 3],
+        )
+        .unwrap();
+        let mut renamed = canonical.clone();
+        let columns = "action=act, side=bs, price=px, order_id=oid, size=qty, \
+            ts_event=ts, sequence=seq, instrument_id=iid";
+        for entry in columns.split(',') {
+            let (field, column) = entry.trim().split_once('=').unwrap();
+            renamed.rename(field, column.into()).unwrap();
+        }
+
+        let mapping: ColumnMapping = columns.parse().unwrap();
+        assert_eq!(mapping.column("price"), "px");
+        assert_eq!(mapping.column("flags"), "flags");
+        let expected = into_mbo_messages(&canonical).unwrap().messages;
+        assert_eq!(
+            into_mbo_messages_with(&renamed, &mapping).unwrap().messages,
+            expected
+        );
+        assert_eq!(expected[2].sequence, 9);
+        assert_eq!(expected[2].instrument_id, 3);
+    }
+
+    #[test]
+    fn test_missing_mapped_column_names_field() {
+        let df = df!(
+            "act" => ["A"],
+            "side" => ["B"],
+            "px" => [100i64],
+            "order_id" => [1u64],
+            "size" => [10u32],
+        )
+        .unwrap();
+
+        let mapping = ColumnMapping::new()
+            .with_column("action", "act")
+            .unwrap()
+            .with_column("price", "price_px")
+            .unwrap();
+        let message = into_mbo_messages_with(&df, &mapping)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            message.contains("'price_px' for field 'price'"),
+            "{message}"
+        );
+
+        // A renamed optional column must exist too
+        let mapping = "action=act,price=px,ts_event=ts".parse().unwrap();
+        let message = into_mbo_messages_with(&df, &mapping)
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("'ts' for field 'ts_event'"), "{message}");
+
+        assert_eq!(
+            "px=price".parse::<ColumnMapping>(),
+            Err(ColumnMappingError::UnknownField("px".to_owned()))
+        );
+        assert_eq!(
+            "price".parse::<ColumnMapping>(),
+            Err(ColumnMappingError::InvalidEntry("price".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_filter_instrument() {
+        let df = df!(
+            "action" => ["A", "A", "A", "C"],
+            "side" => ["B", "B", "A", "B"],
+            "price" => [100i64, 200, 101, 100],
+            "order_id" => [1u64, 1, 2, 1],
+            "size" => [10u32, 20, 5, 10],
+            "iid" => [3i64, 4, 3, 3],
+        )
+        .unwrap();
+        let mapping: ColumnMapping = "instrument_id=iid".parse().unwrap();
+
+        let messages =
+            into_mbo_messages_with(&filter_instrument(&df, &mapping, 3).unwrap(), &mapping)
+                .unwrap()
+                .messages;
+        assert_eq!(
+            messages.iter().map(|m| m.order_id).collect::<Vec<_>>(),
+            vec![1, 2, 1]
+        );
+        assert!(
+            messages
+                .iter()
+                .all(|m| m.instrument_id == 3 && m.price != 200)
+        );
+        assert_eq!(filter_instrument(&df, &mapping, 5).unwrap().height(), 0);
+
+        // Without the column there is nothing to filter on
+        let message = filter_instrument(&df, &ColumnMapping::default(), 3)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            message.contains("'instrument_id' for field 'instrument_id'"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn test_messages_to_dataframe_round_trip() {
+        let df = df!(
+            "action" => ["A", "A", "T", "C"],
+            "side" => ["B", "A", "A", "B"],
+            "price" => [100i64, 101, 101, 100],
+            "order_id" => [1u64, 2, 0, 1],
+            "size" => [10u32, 5, 2, 10],
+            "instrument_id" => [7u32, 7, 7, 8],
+            "ts_event" => [1_000u64, 2_000, 3_000, 4_000],
+            "ts_recv" => [1_050u64, 2_050, 3_050, 4_050],
+            "ts_in_delta" => [-5i32, 0, 5, 10],
+            "sequence" => [1u32, 2, 3, 4],
+            "flags" => [0u8, dbn::flags::LAST, 0, dbn::flags::LAST],
+            "publisher_id" => [1i64, 1, 2, 2],
+            "channel_id" => [0u8, 0, 3, 3],
+        )
+        .unwrap();
+        let messages = into_mbo_messages(&df).unwrap().messages;
+        assert_eq!(messages.len(), 4);
+        assert_eq!((messages[2].publisher_id, messages[2].channel_id), (2, 3));
+
+        let written = mbo_messages_to_dataframe(&messages).unwrap();
+        assert_eq!(written.get_column_names_str(), FIELDS);
+        assert_eq!(written.column("side").unwrap().dtype(), &DataType::Int8);
+        assert_eq!(into_mbo_messages(&written).unwrap().messages, messages);
+    }
+
+    #[test]
+    fn test_multi_character_string_rejected() {
+        let df = df!(
+            "action" => ["Add", "A"],
+            "side" => ["B", "B"],
+            "price" => [100i64, 100],
+            "order_id" => [1u64, 2],
+            "size" => [10u32, 10],
+        )
+        .unwrap();
+
+        let report = into_mbo_messages(&df).unwrap();
+        assert_eq!(report.converted(), 1);
+        assert!(matches!(
+            &report.rejected[0],
+            (0, ConversionError::InvalidCharacter { column: "action", value }) if value == "Add"
+        ));
+    }
+}
@@ -0,0 +1,396 @@
+//! Incremental conversion of decoded DBN records into `MarketByOrderMessage`s.
+//!
+//! Records are pulled from the decoder one at a time, so memory use does not grow with
+//! the size of the file. Compressed input needs no special handling: `DynReader`
+//! detects zstd and decompresses as it reads.
+//!
+//! The decoder reports input cut short within a record the same as input ending
+//! between records, so a reader wrapped in `CountingReader` lets `MboMessages` tell
+//! the two apart by the bytes left over.
+
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use dbn::decode::DecodeRecord;
+use dbn::{MboMsg, Record};
+use thiserror::Error;
+
+use crate::orderbook::{MarketByOrderMessage, MboProcessError};
+
+#[derive(Debug, Error)]
+pub enum DbnStreamError {
+    #[error("Failed to decode record {index}: {source}")]
+    Decode { index: usize, source: dbn::Error },
+
+    #[error("Failed to convert record {index}: {source}")]
+    Conversion {
+        index: usize,
+        source: MboProcessError,
+    },
+}
+
+impl DbnStreamError {
+    /// 0-based index of the record that failed.
+    pub fn index(&self) -> usize {
+        match self {
+            DbnStreamError::Decode { index, .. } | DbnStreamError::Conversion { index, .. } => {
+                *index
+            }
+        }
+    }
+}
+
+/// Iterator over the MBO records of a decoder, created by `mbo_messages`.
+///
+/// A record that fails conversion is yielded as an error and iteration continues with
+/// the next record. A decode error is yielded once and ends the iteration, since the
+/// position in the underlying stream is no longer reliable.
+pub struct MboMessages<D> {
+    decoder: D,
+    index: usize,
+    done: bool,
+    instrument_id: Option<u32>,
+    /// Bytes read by the decoder, if counted.
+    bytes_read: Option<BytesRead>,
+    /// Bytes of the records decoded so far.
+    record_bytes: u64,
+}
+
+/// Lengths read through a `CountingReader`, shared with the `MboMessages` decoding
+/// from it.
+#[derive(Debug, Clone, Default)]
+pub struct BytesRead(Arc<ReadCounts>);
+
+#[derive(Debug, Default)]
+struct ReadCounts {
+    total: AtomicU64,
+    /// Length of the DBN prefix and metadata, 0 until read.
+    metadata: AtomicU64,
+}
+
+impl BytesRead {
+    /// Bytes read past the metadata, or `None` before it has been read.
+    fn after_metadata(&self) -> Option<u64> {
+        let metadata = self.0.metadata.load(Ordering::Relaxed);
+        (metadata > 0).then(|| {
+            self.0
+                .total
+                .load(Ordering::Relaxed)
+                .saturating_sub(metadata)
+        })
+    }
+}
+
+/// Counts the bytes of decompressed DBN read through it, reading the metadata length
+/// from the prefix: `DBN`, a version byte and a little-endian `u32`.
+pub struct CountingReader<R> {
+    inner: R,
+    read: u64,
+    prefix: [u8; 8],
+    counts: BytesRead,
+}
+
+impl<R> CountingReader<R> {
+    /// Wraps `inner`, returning the counts to pass to `MboMessages::with_bytes_read`.
+    pub fn new(inner: R) -> (Self, BytesRead) {
+        let counts = BytesRead::default();
+        let reader = Self {
+            inner,
+            read: 0,
+            prefix: [0; 8],
+            counts: counts.clone(),
+        };
+        (reader, counts)
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        let start = self.read as usize;
+        self.read += n as u64;
+        if start < self.prefix.len() {
+            let end = (start + n).min(self.prefix.len());
+            self.prefix[start..end].copy_from_slice(&buf[..end - start]);
+            if end == self.prefix.len() {
+                let length = u32::from_le_bytes([
+                    self.prefix[4],
+                    self.prefix[5],
+                    self.prefix[6],
+                    self.prefix[7],
+                ]);
+                let metadata = self.prefix.len() as u64 + u64::from(length);
+                self.counts.0.metadata.store(metadata, Ordering::Relaxed);
+            }
+        }
+        self.counts.0.total.store(self.read, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// Streams the MBO records of `decoder` as `MarketByOrderMessage`s.
+pub fn mbo_messages<D: DecodeRecord>(decoder: D) -> MboMessages<D> {
+    MboMessages {
+        decoder,
+        index: 0,
+        done: false,
+        instrument_id: None,
+        bytes_read: None,
+        record_bytes: 0,
+    }
+}
+
+impl<D> MboMessages<D> {
+    /// Only yield the records of `instrument_id`. Records of other instruments are
+    /// skipped by their header, without being converted.
+    pub fn with_instrument_id(mut self, instrument_id: Option<u32>) -> Self {
+        self.instrument_id = instrument_id;
+        self
+    }
+
+    /// Fails with a `Decode` error at the end of the records if the decoder, reading
+    /// through the `CountingReader` that returned `bytes_read`, was left with part of a
+    /// record.
+    pub fn with_bytes_read(mut self, bytes_read: BytesRead) -> Self {
+        self.bytes_read = Some(bytes_read);
+        self
+    }
+
+    /// Number of records decoded so far.
+    pub fn records_read(&self) -> usize {
+        self.index
+    }
+}
+
+impl<D: DecodeRecord> Iterator for MboMessages<D> {
+    type Item = Result<MarketByOrderMessage, DbnStreamError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        loop {
+            let index = self.index;
+            let record = match self.decoder.decode_record::<MboMsg>() {
+                Ok(Some(record)) => record,
+                Ok(None) => {
+                    self.done = true;
+                    let left = self
+                        .bytes_read
+                        .as_ref()
+                        .and_then(BytesRead::after_metadata)
+                        .map_or(0, |read| read.saturating_sub(self.record_bytes));
+                    if left == 0 {
+                        return None;
+                    }
+                    let source =
+                        dbn::Error::decode(format!("input ends {left} bytes into the record"));
+                    return Some(Err(DbnStreamError::Decode { index, source }));
+                }
+                Err(source) => {
+                    self.done = true;
+                    return Some(Err(DbnStreamError::Decode { index, source }));
+                }
+            };
+            self.index += 1;
+            self.record_bytes += record.record_size() as u64;
+            if self
+                .instrument_id
+                .is_none_or(|instrument_id| instrument_id == record.hd.instrument_id)
+            {
+                return Some(
+                    MarketByOrderMessage::try_from(record)
+                        .map_err(|source| DbnStreamError::Conversion { index, source }),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::c_char;
+
+    use dbn::decode::{DynReader, dbn::Decoder};
+    use dbn::encode::{DynWriter, EncodeRecord, dbn::Encoder};
+    use dbn::{Compression, MetadataBuilder, SType, Schema};
+
+    use crate::orderbook::MboProcessor;
+
+    fn record(sequence: u32, action: u8, side: u8, order_id: u64, price: i64) -> MboMsg {
+        instrument_record(7, sequence, action, side, order_id, price)
+    }
+
+    fn instrument_record(
+        instrument_id: u32,
+        sequence: u32,
+        action: u8,
+        side: u8,
+        order_id: u64,
+        price: i64,
+    ) -> MboMsg {
+        let ts_event = 1_000 + u64::from(sequence);
+        MboMsg {
+            hd: dbn::RecordHeader::new::<MboMsg>(
+                dbn::enums::rtype::MBO,
+                1,
+                instrument_id,
+                ts_event,
+            ),
+            order_id,
+            price,
+            size: 10,
+            flags: dbn::FlagSet::new(dbn::flags::LAST),
+            channel_id: 0,
+            action: action as c_char,
+            side: side as c_char,
+            ts_recv: ts_event + 50,
+            ts_in_delta: 0,
+            sequence,
+        }
+    }
+
+    fn records() -> Vec<MboMsg> {
+        vec![
+            record(1, b'A', b'B', 1, 100),
+            record(2, b'A', b'B', 2, 99),
+            record(3, b'A', b'A', 3, 101),
+            record(4, b'A', b'A', 4, 102),
+            record(5, b'M', b'B', 2, 100),
+            record(6, b'C', b'A', 3, 101),
+            record(7, b'T', b'A', 0, 102),
+            record(8, b'F', b'A', 4, 102),
+        ]
+    }
+
+    fn encode(records: &[MboMsg], compression: Compression) -> Vec<u8> {
+        let metadata = MetadataBuilder::new()
+            .dataset("TEST".to_owned())
+            .schema(Some(Schema::Mbo))
+            .start(0)
+            .stype_in(Some(SType::InstrumentId))
+            .stype_out(SType::InstrumentId)
+            .build();
+        let mut buf = Vec::new();
+        {
+            let writer = DynWriter::new(&mut buf, compression).unwrap();
+            let mut encoder = Encoder::new(writer, &metadata).unwrap();
+            records
+                .iter()
+                .for_each(|record| encoder.encode_record(record).unwrap());
+            encoder.flush().unwrap();
+        }
+        buf
+    }
+
+    fn decoder(buf: &[u8]) -> Decoder<DynReader<'_, &[u8]>> {
+        Decoder::new(DynReader::inferred_with_buffer(buf).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_streamed_matches_collected() {
+        [Compression::None, Compression::Zstd]
+            .into_iter()
+            .for_each(|compression| {
+                let buf = encode(&records(), compression);
+
+                let collected: Vec<MarketByOrderMessage> = mbo_messages(decoder(&buf))
+                    .collect::<Result<_, _>>()
+                    .unwrap();
+                assert_eq!(collected.len(), 8);
+                let mut expected = MboProcessor::new();
+                assert!(expected.process_messages(&collected).is_success());
+
+                let mut streamed = MboProcessor::new();
+                let mut stream = mbo_messages(decoder(&buf));
+                let summary = streamed.process_messages(stream.by_ref().map_while(Result::ok));
+                assert!(summary.is_success());
+                assert_eq!(stream.records_read(), 8);
+
+                assert_eq!(streamed.book(7), expected.book(7));
+                assert_eq!(streamed.stats(), expected.stats());
+                assert_eq!(streamed.best_bid(), Some((100, 20)));
+            });
+    }
+
+    #[test]
+    fn test_conversion_error_reports_index() {
+        let mut records = records();
+        records[5].action = b'?' as c_char;
+        let buf = encode(&records, Compression::None);
+
+        let results: Vec<_> = mbo_messages(decoder(&buf)).collect();
+        assert_eq!(results.len(), 8);
+        let failed: Vec<usize> = results
+            .iter()
+            .filter_map(|result| result.as_ref().err().map(DbnStreamError::index))
+            .collect();
+        assert_eq!(failed, vec![5]);
+        assert!(matches!(
+            results[5],
+            Err(DbnStreamError::Conversion { index: 5, .. })
+        ));
+    }
+
+    #[test]
+    fn test_truncated_record_is_a_decode_error() {
+        [Compression::None, Compression::Zstd]
+            .into_iter()
+            .for_each(|compression| {
+                let plain = encode(&records(), Compression::None);
+                let cut = &plain[..plain.len() - 3];
+                let buf = match compression {
+                    Compression::None => cut.to_vec(),
+                    Compression::Zstd => zstd::encode_all(cut, 0).unwrap(),
+                };
+                let (reader, bytes_read) =
+                    CountingReader::new(DynReader::inferred_with_buffer(&buf[..]).unwrap());
+                let results: Vec<_> = mbo_messages(Decoder::new(reader).unwrap())
+                    .with_bytes_read(bytes_read)
+                    .collect();
+                assert_eq!(results.len(), 8);
+                assert!(results[..7].iter().all(Result::is_ok));
+                let error = results[7].as_ref().unwrap_err();
+                assert!(matches!(error, DbnStreamError::Decode { index: 7, .. }));
+                assert!(
+                    error.to_string().contains("53 bytes into the record"),
+                    "{error}"
+                );
+            });
+
+        // Whole records end cleanly
+        let buf = encode(&records(), Compression::Zstd);
+        let (reader, bytes_read) =
+            CountingReader::new(DynReader::inferred_with_buffer(&buf[..]).unwrap());
+        let stream = mbo_messages(Decoder::new(reader).unwrap()).with_bytes_read(bytes_read);
+        assert_eq!(stream.filter(Result::is_ok).count(), 8);
+    }
+
+    #[test]
+    fn test_instrument_filter_skips_other_records() {
+        let mut records = records();
+        // Instrument 8 has an unconvertible record, which the filter never converts
+        records.insert(2, instrument_record(8, 9, b'A', b'B', 1, 50));
+        records.insert(5, instrument_record(8, 10, b'?', b'B', 2, 50));
+        let buf = encode(&records, Compression::None);
+
+        let mut stream = mbo_messages(decoder(&buf)).with_instrument_id(Some(7));
+        let messages: Vec<MarketByOrderMessage> =
+            stream.by_ref().collect::<Result<_, _>>().unwrap();
+        assert_eq!(messages.len(), 8);
+        assert!(messages.iter().all(|message| message.instrument_id == 7));
+        assert_eq!(stream.records_read(), 10);
+
+        let results: Vec<_> = mbo_messages(decoder(&buf))
+            .with_instrument_id(Some(8))
+            .collect();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_ref().unwrap().price, 50);
+        assert!(matches!(
+            results[1],
+            Err(DbnStreamError::Conversion { index: 5, .. })
+        ));
+    }
+}
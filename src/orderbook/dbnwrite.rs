@@ -0,0 +1,191 @@
+//! Writing MBO messages back to DBN, e.g. after filtering a capture down to one
+//! instrument or a time window, so that other DBN tooling can read the result.
+
+use std::borrow::Borrow;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use dbn::encode::{DynWriter, EncodeRecord, dbn::Encoder};
+use dbn::{Compression, MboMsg, Metadata, MetadataBuilder, SType, Schema};
+use thiserror::Error;
+use time::OffsetDateTime;
+
+use crate::orderbook::{ConversionError, MarketByOrderMessage, create_output};
+
+#[derive(Debug, Error)]
+pub enum DbnWriteError {
+    #[error("Failed to write DBN: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Failed to write DBN: {0}")]
+    Dbn(#[from] dbn::Error),
+
+    #[error("Cannot encode message as DBN: {0}")]
+    Conversion(#[from] ConversionError),
+}
+
+/// A minimal header for MBO records identified by instrument id, without symbol
+/// mappings.
+pub fn mbo_metadata(dataset: &str, start: OffsetDateTime) -> Metadata {
+    MetadataBuilder::new()
+        .dataset(dataset.to_owned())
+        .schema(Some(Schema::Mbo))
+        .start(start.unix_timestamp_nanos() as u64)
+        .stype_in(Some(SType::InstrumentId))
+        .stype_out(SType::InstrumentId)
+        .build()
+}
+
+/// Encodes messages as DBN MBO records, one at a time.
+pub struct DbnWriter<'a, W: Write> {
+    encoder: Encoder<DynWriter<'a, W>>,
+    records: u64,
+}
+
+impl DbnWriter<'_, File> {
+    /// Creates the file at `path` and any missing parent directories, zstd-compressed
+    /// if its name ends in `.zst`, and writes `metadata` as its header.
+    pub fn create(path: impl AsRef<Path>, metadata: &Metadata) -> Result<Self, DbnWriteError> {
+        let path = path.as_ref();
+        let compression = match path.extension() {
+            Some(extension) if extension == "zst" => Compression::Zstd,
+            _ => Compression::None,
+        };
+        Self::new(create_output(path, true)?, compression, metadata)
+    }
+}
+
+impl<W: Write> DbnWriter<'_, W> {
+    /// Writes `metadata` to `writer` as the header of a DBN stream.
+    pub fn new(
+        writer: W,
+        compression: Compression,
+        metadata: &Metadata,
+    ) -> Result<Self, DbnWriteError> {
+        let encoder = Encoder::new(DynWriter::new(writer, compression)?, metadata)?;
+        Ok(Self {
+            encoder,
+            records: 0,
+        })
+    }
+
+    /// Writes one record; a message DBN cannot hold fails without writing anything.
+    pub fn write(&mut self, message: &MarketByOrderMessage) -> Result<(), DbnWriteError> {
+        self.encoder.encode_record(&MboMsg::try_from(message)?)?;
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Number of records written.
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    /// Flushes the records and ends the stream, returning the number written.
+    pub fn finish(mut self) -> Result<u64, DbnWriteError> {
+        self.encoder.flush()?;
+        Ok(self.records)
+    }
+}
+
+/// Writes `messages` to a DBN file at `path` with `metadata` as its header,
+/// zstd-compressed if the name ends in `.zst`. Returns the number of records written.
+pub fn write_dbn<I>(
+    path: impl AsRef<Path>,
+    metadata: &Metadata,
+    messages: I,
+) -> Result<u64, DbnWriteError>
+where
+    I: IntoIterator,
+    I::Item: Borrow<MarketByOrderMessage>,
+{
+    let mut writer = DbnWriter::create(path, metadata)?;
+    for message in messages {
+        writer.write(message.borrow())?;
+    }
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    use dbn::decode::{DynReader, dbn::Decoder};
+    use time::Duration;
+
+    use crate::orderbook::{Action, Side, mbo_messages};
+
+    fn message(
+        action: Action,
+        side: Side,
+        order_id: u64,
+        sequence: u32,
+        is_last: bool,
+    ) -> MarketByOrderMessage {
+        let event_time = OffsetDateTime::UNIX_EPOCH + Duration::microseconds(sequence.into());
+        MarketByOrderMessage {
+            instrument_id: 42,
+            sequence,
+            recv_time: event_time + Duration::nanoseconds(250),
+            ts_in_delta: Duration::nanoseconds(-15),
+            ..MarketByOrderMessage::test(
+                action,
+                side,
+                100_000_000_000 + order_id as i64,
+                order_id,
+                10 * sequence,
+            )
+            .at(event_time)
+            .with_last(is_last)
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut messages = vec![
+            message(Action::Add, Side::Bid, 1, 1, true),
+            message(Action::Add, Side::Ask, 2, 2, false),
+            message(Action::Modify, Side::Ask, 2, 3, true),
+            message(Action::Trade, Side::Bid, 0, 4, false),
+            message(Action::Fill, Side::Ask, 2, 5, false),
+            message(Action::Cancel, Side::Ask, 2, 6, true),
+        ];
+        messages[0].flags |= dbn::flags::SNAPSHOT;
+
+        let path = env::temp_dir().join(format!("rainybook-{}-roundtrip.dbn", process::id()));
+        let metadata = mbo_metadata("TEST", messages[0].event_time);
+        assert_eq!(write_dbn(&path, &metadata, &messages).unwrap(), 6);
+
+        let decoder = Decoder::new(DynReader::from_file(&path).unwrap()).unwrap();
+        let decoded: Vec<MarketByOrderMessage> =
+            mbo_messages(decoder).collect::<Result<_, _>>().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(decoded, messages);
+    }
+
+    #[test]
+    fn test_unrepresentable_message_is_refused() {
+        let metadata = mbo_metadata("TEST", OffsetDateTime::UNIX_EPOCH);
+        let mut writer = DbnWriter::new(Vec::new(), Compression::None, &metadata).unwrap();
+        writer
+            .write(&message(Action::Add, Side::Bid, 1, 1, true))
+            .unwrap();
+
+        let early = message(Action::Add, Side::Bid, 2, 2, true)
+            .at(OffsetDateTime::UNIX_EPOCH - Duration::seconds(1));
+        let slow = MarketByOrderMessage {
+            ts_in_delta: Duration::seconds(3),
+            ..message(Action::Add, Side::Bid, 3, 3, true)
+        };
+        for (message, field) in [(early, "ts_event"), (slow, "ts_in_delta")] {
+            assert!(matches!(
+                writer.write(&message),
+                Err(DbnWriteError::Conversion(ConversionError::InvalidValue { column, .. }))
+                    if column == field
+            ));
+        }
+        assert_eq!(writer.finish().unwrap(), 1);
+    }
+}
@@ -0,0 +1,1025 @@
+//! An order book storing the levels of a preallocated price range contiguously.
+//!
+//! `DenseOrderBook` offers the operations of `OrderBook` on a `Vec` of levels indexed by
+//! tick offset from the bottom of the range, so adds, cancels and best-price lookups
+//! near the touch avoid tree traversal. Prices outside the range, or off its tick grid,
+//! are kept in an ordered overflow map per side, so any price is accepted and the book
+//! behaves exactly like `OrderBook`, only slower for those levels.
+
+use std::collections::BTreeMap;
+use std::{iter, mem};
+
+use thiserror::Error;
+use tracing::debug;
+
+use crate::orderbook::book::{self, Bbo, OrderMap, StateHasher};
+use crate::orderbook::expiry::ExpiryQueue;
+use crate::orderbook::{
+    AddOrderInfo, Anomalies, DepthLimitPolicy, Execution, MarketByPrice, MatchOutcome,
+    ModifyOrderInfo, ModifyPriorityPolicy, Order, OrderBookError, OrderLevelSummary,
+    RemoveOrderInfo, SelfMatchPolicy, Side,
+};
+
+/// Most levels `DenseOrderBook::new` preallocates per side.
+pub const MAX_DENSE_LEVELS: usize = 1 << 24;
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum DenseBookError {
+    #[error("Tick size must be positive, got {0}")]
+    InvalidTickSize(i64),
+
+    #[error(
+        "Invalid dense price range {low}..={high}, expected low <= high spanning at most \
+         {MAX_DENSE_LEVELS} ticks"
+    )]
+    InvalidRange { low: i64, high: i64 },
+}
+
+/// Orders at one price, in queue order, with their total size.
+#[derive(Debug, Default, Clone)]
+struct DenseLevel {
+    /// Sorted by `(sequence, order_id)`, as `OrderLevel` orders its queue.
+    orders: Vec<Order>,
+    total_qty: u64,
+}
+
+impl DenseLevel {
+    fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
+    fn position(&self, order: &Order) -> Option<usize> {
+        self.orders
+            .binary_search_by_key(&(order.sequence, order.order_id), |o| {
+                (o.sequence, o.order_id)
+            })
+            .ok()
+    }
+
+    fn insert(&mut self, order: Order) {
+        let key = (order.sequence, order.order_id);
+        // Orders nearly always arrive in sequence order, so this is usually a push
+        let at = self
+            .orders
+            .partition_point(|o| (o.sequence, o.order_id) < key);
+        self.orders.insert(at, order);
+        self.total_qty += order.size;
+    }
+
+    fn remove(&mut self, order: &Order) -> Option<Order> {
+        let removed = self.orders.remove(self.position(order)?);
+        self.total_qty -= removed.size;
+        Some(removed)
+    }
+
+    fn set_size(&mut self, order: &Order, size: u64) -> Option<Order> {
+        let at = self.position(order)?;
+        let resting = &mut self.orders[at];
+        self.total_qty = self.total_qty - resting.size + size;
+        resting.size = size;
+        Some(*resting)
+    }
+
+    fn summary(&self, price: i64) -> OrderLevelSummary {
+        OrderLevelSummary {
+            price,
+            total_quantity: self.total_qty,
+            order_count: self.orders.len(),
+        }
+    }
+}
+
+/// One side of a `DenseOrderBook`.
+#[derive(Debug, Clone)]
+struct DenseSide {
+    side: Side,
+    /// Price of `levels[0]`.
+    low: i64,
+    tick_size: i64,
+    levels: Vec<DenseLevel>,
+    /// Index of the best non-empty level in `levels`.
+    best: Option<usize>,
+    /// Index of the worst non-empty level in `levels`.
+    worst: Option<usize>,
+    /// Non-empty levels in `levels`.
+    occupied: usize,
+    /// Levels at prices `levels` cannot hold.
+    overflow: BTreeMap<i64, DenseLevel>,
+}
+
+impl DenseSide {
+    fn new(side: Side, low: i64, tick_size: i64, len: usize) -> Self {
+        Self {
+            side,
+            low,
+            tick_size,
+            levels: vec![DenseLevel::default(); len],
+            best: None,
+            worst: None,
+            occupied: 0,
+            overflow: BTreeMap::new(),
+        }
+    }
+
+    /// An empty side over the same range.
+    fn emptied(&self) -> Self {
+        Self::new(self.side, self.low, self.tick_size, self.levels.len())
+    }
+
+    /// Whether `a` is a better price than `b` on this side.
+    fn better(&self, a: i64, b: i64) -> bool {
+        match self.side {
+            Side::Bid => a > b,
+            Side::Ask => a < b,
+        }
+    }
+
+    fn slot(&self, price: i64) -> Option<usize> {
+        let offset = price.checked_sub(self.low)?;
+        if offset < 0 || offset % self.tick_size != 0 {
+            return None;
+        }
+        usize::try_from(offset / self.tick_size)
+            .ok()
+            .filter(|&index| index < self.levels.len())
+    }
+
+    fn price(&self, index: usize) -> i64 {
+        self.low + index as i64 * self.tick_size
+    }
+
+    fn level(&self, price: i64) -> Option<&DenseLevel> {
+        match self.slot(price) {
+            Some(index) => Some(&self.levels[index]),
+            None => self.overflow.get(&price),
+        }
+    }
+
+    fn level_mut(&mut self, price: i64) -> Option<&mut DenseLevel> {
+        match self.slot(price) {
+            Some(index) => Some(&mut self.levels[index]),
+            None => self.overflow.get_mut(&price),
+        }
+    }
+
+    fn level_count(&self) -> usize {
+        self.occupied + self.overflow.len()
+    }
+
+    /// Adds `order`, returning its level after the add.
+    fn insert(&mut self, order: Order) -> &DenseLevel {
+        let level = match self.slot(order.price) {
+            Some(index) => {
+                if self.levels[index].is_empty() {
+                    self.occupied += 1;
+                    if self
+                        .best
+                        .is_none_or(|best| self.better(order.price, self.price(best)))
+                    {
+                        self.best = Some(index);
+                    }
+                    if self
+                        .worst
+                        .is_none_or(|worst| self.better(self.price(worst), order.price))
+                    {
+                        self.worst = Some(index);
+                    }
+                }
+                &mut self.levels[index]
+            }
+            None => self.overflow.entry(order.price).or_default(),
+        };
+        level.insert(order);
+        level
+    }
+
+    /// Removes `order`, returning it with the quantity and order count left at its level.
+    fn remove(&mut self, order: &Order) -> Option<(Order, u64, usize)> {
+        let price = order.price;
+        let (removed, (qty, count)) = match self.slot(price) {
+            Some(index) => {
+                let level = &mut self.levels[index];
+                let removed = level.remove(order)?;
+                let remaining = (level.total_qty, level.orders.len());
+                if level.is_empty() {
+                    self.occupied -= 1;
+                    if self.best == Some(index) {
+                        self.best = self.next_best(index);
+                    }
+                    if self.worst == Some(index) {
+                        self.worst = self.next_worst(index);
+                    }
+                }
+                (removed, remaining)
+            }
+            None => {
+                let level = self.overflow.get_mut(&price)?;
+                let removed = level.remove(order)?;
+                let remaining = (level.total_qty, level.orders.len());
+                if level.is_empty() {
+                    self.overflow.remove(&price);
+                }
+                (removed, remaining)
+            }
+        };
+        Some((removed, qty, count))
+    }
+
+    /// The best non-empty level in `levels` worse than `index`.
+    fn next_best(&self, index: usize) -> Option<usize> {
+        if self.occupied == 0 {
+            return None;
+        }
+        match self.side {
+            Side::Bid => (0..index).rev().find(|&i| !self.levels[i].is_empty()),
+            Side::Ask => (index + 1..self.levels.len()).find(|&i| !self.levels[i].is_empty()),
+        }
+    }
+
+    /// The worst non-empty level in `levels` better than `index`.
+    fn next_worst(&self, index: usize) -> Option<usize> {
+        if self.occupied == 0 {
+            return None;
+        }
+        match self.side {
+            Side::Bid => (index + 1..self.levels.len()).find(|&i| !self.levels[i].is_empty()),
+            Side::Ask => (0..index).rev().find(|&i| !self.levels[i].is_empty()),
+        }
+    }
+
+    /// Non-empty levels from best to worst.
+    fn iter(&self) -> impl Iterator<Item = (i64, &DenseLevel)> {
+        // Walks away from the best index without boxing, so iterating never allocates
+        let bid = self.side == Side::Bid;
+        let span = match self.best {
+            None => 0,
+            Some(best) if bid => best + 1,
+            Some(best) => self.levels.len() - best,
+        };
+        let best = self.best.unwrap_or(0);
+        let mut dense = (0..span)
+            .map(move |step| if bid { best - step } else { best + step })
+            .filter(|&index| !self.levels[index].is_empty())
+            .map(|index| (self.price(index), &self.levels[index]))
+            .peekable();
+        let mut levels = self.overflow.iter();
+        let mut overflow = iter::from_fn(move || match bid {
+            true => levels.next_back(),
+            false => levels.next(),
+        })
+        .map(|(&price, level)| (price, level))
+        .peekable();
+        iter::from_fn(move || match (dense.peek(), overflow.peek()) {
+            (Some(&(a, _)), Some(&(b, _))) if self.better(b, a) => overflow.next(),
+            (Some(_), _) => dense.next(),
+            (None, _) => overflow.next(),
+        })
+    }
+
+    fn best(&self) -> Option<(i64, &DenseLevel)> {
+        let dense = self
+            .best
+            .map(|index| (self.price(index), &self.levels[index]));
+        let overflow = match self.side {
+            Side::Bid => self.overflow.last_key_value(),
+            Side::Ask => self.overflow.first_key_value(),
+        }
+        .map(|(&price, level)| (price, level));
+        match (dense, overflow) {
+            (Some(dense), Some(overflow)) if self.better(overflow.0, dense.0) => Some(overflow),
+            (Some(dense), _) => Some(dense),
+            (None, overflow) => overflow,
+        }
+    }
+
+    fn worst(&self) -> Option<(i64, &DenseLevel)> {
+        let dense = self
+            .worst
+            .map(|index| (self.price(index), &self.levels[index]));
+        let overflow = match self.side {
+            Side::Bid => self.overflow.first_key_value(),
+            Side::Ask => self.overflow.last_key_value(),
+        }
+        .map(|(&price, level)| (price, level));
+        match (dense, overflow) {
+            (Some(dense), Some(overflow)) if self.better(dense.0, overflow.0) => Some(overflow),
+            (Some(dense), _) => Some(dense),
+            (None, overflow) => overflow,
+        }
+    }
+}
+
+/// Market-By-Order order book with contiguous level storage over a preallocated price
+/// range, giving the same results as `OrderBook`.
+///
+/// Levels for prices from `low` to `high` in steps of `tick_size` live in a `Vec` per
+/// side, found by index. Any other price is kept in an ordered overflow map instead of
+/// growing the range, so a stray price costs a tree level rather than a reallocation.
+///
+/// Anomaly counters, the depth limit, order expiry and self-match prevention work as
+/// on `OrderBook`; only the audit journal (`OrderBook::with_events`) is not kept.
+#[derive(Debug, Clone)]
+pub struct DenseOrderBook {
+    bids: DenseSide,
+    asks: DenseSide,
+    /// Every resting order by id, as held at its level.
+    orders: OrderMap<Order>,
+    anomalies: Anomalies,
+    /// Most levels kept per side, unlimited if `None`; see `with_max_levels_per_side`.
+    max_levels: Option<usize>,
+    depth_policy: DepthLimitPolicy,
+    /// Orders with an expiry, earliest first, for `purge_expired`.
+    expiries: ExpiryQueue,
+}
+
+impl DenseOrderBook {
+    /// A book preallocating one level per tick from `low` to `high` inclusive on each
+    /// side.
+    pub fn new(low: i64, high: i64, tick_size: i64) -> Result<Self, DenseBookError> {
+        if tick_size <= 0 {
+            return Err(DenseBookError::InvalidTickSize(tick_size));
+        }
+        let len = high
+            .checked_sub(low)
+            .filter(|&span| span >= 0)
+            .and_then(|span| usize::try_from(span / tick_size + 1).ok())
+            .filter(|&len| len <= MAX_DENSE_LEVELS)
+            .ok_or(DenseBookError::InvalidRange { low, high })?;
+        Ok(Self {
+            bids: DenseSide::new(Side::Bid, low, tick_size, len),
+            asks: DenseSide::new(Side::Ask, low, tick_size, len),
+            orders: OrderMap::default(),
+            anomalies: Anomalies::default(),
+            max_levels: None,
+            depth_policy: DepthLimitPolicy::default(),
+            expiries: ExpiryQueue::default(),
+        })
+    }
+
+    /// Keeps at most `max_levels` price levels per side, as
+    /// `OrderBook::with_max_levels_per_side`.
+    pub fn with_max_levels_per_side(mut self, max_levels: usize) -> Self {
+        self.max_levels = Some(max_levels);
+        self
+    }
+
+    /// Sets what happens to an Add beyond the depth limit, as
+    /// `OrderBook::with_depth_limit_policy`.
+    pub fn with_depth_limit_policy(mut self, policy: DepthLimitPolicy) -> Self {
+        self.depth_policy = policy;
+        self
+    }
+
+    pub fn max_levels_per_side(&self) -> Option<usize> {
+        self.max_levels
+    }
+
+    pub fn depth_limit_policy(&self) -> DepthLimitPolicy {
+        self.depth_policy
+    }
+
+    /// Anomalies encountered so far, counted as `OrderBook` counts them.
+    pub fn anomalies(&self) -> Anomalies {
+        self.anomalies
+    }
+
+    pub(crate) fn restore_anomalies(&mut self, anomalies: Anomalies) {
+        self.anomalies = anomalies;
+    }
+
+    /// Fails with `OrderBookError::BeyondDepthLimit` if `order` would open a level
+    /// worse than the worst of a full side, as `OrderBook::check_depth_limit`.
+    pub fn check_depth_limit(&self, order: &Order) -> Result<(), OrderBookError> {
+        let Some(max_levels) = self.max_levels else {
+            return Ok(());
+        };
+        let side = self.side(order.side);
+        if side.level_count() < max_levels || side.level(order.price).is_some_and(|l| !l.is_empty())
+        {
+            return Ok(());
+        }
+        match side
+            .worst()
+            .is_none_or(|(worst, _)| side.better(worst, order.price))
+        {
+            true => Err(OrderBookError::BeyondDepthLimit {
+                order_id: order.order_id,
+                side: order.side,
+                price: order.price,
+                max_levels,
+            }),
+            false => Ok(()),
+        }
+    }
+
+    /// Removes the worst levels of `side` until it is within the depth limit, counting
+    /// their orders as dropped under `DepthLimitPolicy::Drop`.
+    fn trim_to_depth(&mut self, side: Side) {
+        let Some(max_levels) = self.max_levels else {
+            return;
+        };
+        while self.side(side).level_count() > max_levels {
+            let Some((price, level)) = self.side(side).worst() else {
+                break;
+            };
+            debug!("Trimming {side:?} level {price} beyond the {max_levels}-level depth limit");
+            let order_ids: Vec<u64> = level.orders.iter().map(|order| order.order_id).collect();
+            for order_id in order_ids {
+                self.remove_order(order_id);
+                if self.depth_policy == DepthLimitPolicy::Drop {
+                    self.anomalies.depth_dropped += 1;
+                }
+            }
+        }
+    }
+
+    /// Leaves out an Add beyond the depth limit, removing any order with the same id.
+    fn drop_beyond_depth(&mut self, order: Order) -> AddOrderInfo {
+        debug!(
+            "Order {} at {:?} price {} is beyond the depth limit, dropping",
+            order.order_id, order.side, order.price
+        );
+        let replaced = match self.orders.contains_key(&order.order_id) {
+            true => {
+                self.anomalies.duplicate_add += 1;
+                self.remove_order(order.order_id).map(|info| info.order)
+            }
+            false => None,
+        };
+        if self.depth_policy == DepthLimitPolicy::Drop {
+            self.anomalies.depth_dropped += 1;
+        }
+        AddOrderInfo {
+            order,
+            level_qty: 0,
+            level_order_count: 0,
+            new_level: false,
+            replaced: replaced.is_some(),
+            replaced_level: replaced.map(|old| (old.side, old.price)),
+            dropped: true,
+        }
+    }
+
+    /// Removes the orders whose `Order::expires_at` is at or before `now_ns`, as
+    /// `OrderBook::purge_expired`.
+    pub fn purge_expired(&mut self, now_ns: u64) -> Vec<Order> {
+        self.purge_expired_infos(now_ns)
+            .into_iter()
+            .map(|info| info.order)
+            .collect()
+    }
+
+    /// `purge_expired` with the level state left by each removal.
+    pub(crate) fn purge_expired_infos(&mut self, now_ns: u64) -> Vec<RemoveOrderInfo> {
+        let mut purged = Vec::new();
+        while let Some((expires_at, order_id)) = self.expiries.pop_due(now_ns) {
+            let live = self
+                .get_order(order_id)
+                .is_some_and(|order| order.expires_at == Some(expires_at));
+            if live {
+                purged.extend(self.remove_order(order_id));
+            }
+        }
+        purged
+    }
+
+    /// Queues `order` for `purge_expired`, rebuilding the queue from the resting orders
+    /// once it holds too many stale entries.
+    fn schedule_expiry(&mut self, order: &Order) {
+        self.expiries.push(order);
+        if self.expiries.is_bloated(self.orders.len()) {
+            self.expiries = self.orders.values().collect();
+        }
+    }
+
+    fn side(&self, side: Side) -> &DenseSide {
+        match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        }
+    }
+
+    fn side_mut(&mut self, side: Side) -> &mut DenseSide {
+        match side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        }
+    }
+
+    /// Removes all orders and returns the previous book; this one keeps its range,
+    /// depth limit and anomaly counters.
+    pub fn take_orders(&mut self) -> DenseOrderBook {
+        let empty = Self {
+            bids: self.bids.emptied(),
+            asks: self.asks.emptied(),
+            orders: OrderMap::default(),
+            anomalies: self.anomalies,
+            max_levels: self.max_levels,
+            depth_policy: self.depth_policy,
+            expiries: ExpiryQueue::default(),
+        };
+        mem::replace(self, empty)
+    }
+
+    /// The hash `OrderBook::state_hash` gives a book holding the same orders.
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = StateHasher::new();
+        // `OrderBook` visits prices ascending, bids first
+        let mut bids: Vec<_> = self.bids.iter().collect();
+        bids.reverse();
+        let levels = bids
+            .into_iter()
+            .map(|level| (Side::Bid, level))
+            .chain(self.asks.iter().map(|level| (Side::Ask, level)));
+        for (side, (_, level)) in levels {
+            for order in &level.orders {
+                hasher.write_u64(order.order_id);
+                hasher.write_u8(i8::from(side) as u8);
+                hasher.write_u64(order.price as u64);
+                hasher.write_u64(order.size);
+            }
+        }
+        hasher.finish()
+    }
+
+    /// Number of levels on `side` held in the overflow map rather than the range.
+    pub fn overflow_level_count(&self, side: Side) -> usize {
+        self.side(side).overflow.len()
+    }
+
+    /// Adds an order, replacing any order with the same id, as `OrderBook::add_order`.
+    /// With a depth limit, an order beyond it is left out and reported as `dropped`.
+    pub fn add_order(&mut self, order: Order) -> AddOrderInfo {
+        if self.check_depth_limit(&order).is_err() {
+            return self.drop_beyond_depth(order);
+        }
+        let info = self.place_order(order);
+        self.trim_to_depth(order.side);
+        info
+    }
+
+    /// `add_order` without the depth limit.
+    fn place_order(&mut self, order: Order) -> AddOrderInfo {
+        let replaced = self.orders.insert(order.order_id, order);
+        self.schedule_expiry(&order);
+        if let Some(old) = replaced {
+            self.anomalies.duplicate_add += 1;
+            debug!(
+                "Order {} already exists at {:?} price {}, moving to {:?} price {}",
+                order.order_id, old.side, old.price, order.side, order.price
+            );
+            self.side_mut(old.side).remove(&old);
+        }
+        let level = self.side_mut(order.side).insert(order);
+        AddOrderInfo {
+            order,
+            level_qty: level.total_qty,
+            level_order_count: level.orders.len(),
+            new_level: level.orders.len() == 1,
+            replaced: replaced.is_some(),
+            replaced_level: replaced.map(|old| (old.side, old.price)),
+            dropped: false,
+        }
+    }
+
+    /// Removes an order, as `OrderBook::remove_order`. Returns `None` if it is not found.
+    pub fn remove_order(&mut self, order_id: u64) -> Option<RemoveOrderInfo> {
+        let Some(order) = self.orders.remove(&order_id) else {
+            self.anomalies.unknown_cancel += 1;
+            debug!("Order {} not found in index, ignoring removal", order_id);
+            return None;
+        };
+        let Some((order, remaining_level_qty, remaining_level_count)) =
+            self.side_mut(order.side).remove(&order)
+        else {
+            self.anomalies.level_missing += 1;
+            debug!(
+                "Price level {} not found for order {}",
+                order.price, order_id
+            );
+            return None;
+        };
+        Some(RemoveOrderInfo {
+            order,
+            remaining_level_qty,
+            remaining_level_count,
+            level_removed: remaining_level_count == 0,
+        })
+    }
+
+    /// Removes every order on `side`, as `OrderBook::clear_side`.
+    pub fn clear_side(&mut self, side: Side) -> Vec<RemoveOrderInfo> {
+        let order_ids: Vec<u64> = self
+            .side(side)
+            .iter()
+            .flat_map(|(_, level)| level.orders.iter().map(|order| order.order_id))
+            .collect();
+        order_ids
+            .into_iter()
+            .filter_map(|order_id| self.remove_order(order_id))
+            .collect()
+    }
+
+    pub fn get_order(&self, order_id: u64) -> Option<&Order> {
+        self.orders.get(&order_id)
+    }
+
+    /// Sets an order's size in place, keeping its queue position. Returns the order and
+    /// the quantity and order count of its level.
+    fn update_order_size(&mut self, order_id: u64, size: u64) -> Option<(Order, u64, usize)> {
+        let order = *self.orders.get(&order_id)?;
+        let level = self.side_mut(order.side).level_mut(order.price)?;
+        let updated = level.set_size(&order, size)?;
+        let info = (updated, level.total_qty, level.orders.len());
+        self.orders.insert(order_id, updated);
+        Some(info)
+    }
+
+    /// Fills resting orders against an incoming order, as `OrderBook::match_order`.
+    pub fn match_order(&mut self, order: &Order) -> Vec<Execution> {
+        self.match_order_with_policy(order, SelfMatchPolicy::Allow)
+            .executions
+    }
+
+    /// Fills resting orders against an incoming order, preventing self-matches as
+    /// `policy` says, as `OrderBook::match_order_with_policy`.
+    pub fn match_order_with_policy(
+        &mut self,
+        order: &Order,
+        policy: SelfMatchPolicy,
+    ) -> MatchOutcome {
+        let mut outcome = MatchOutcome::default();
+        let mut remaining = order.size;
+        while remaining > 0 {
+            let Some(resting) = self.crossing_front(order.side, order.price) else {
+                break;
+            };
+            if order.owner.is_some() && resting.owner == order.owner {
+                match policy {
+                    SelfMatchPolicy::Allow => {}
+                    SelfMatchPolicy::CancelResting => {
+                        let info = self
+                            .remove_order(resting.order_id)
+                            .expect("front order must be in the book");
+                        outcome.cancelled.push(info);
+                        continue;
+                    }
+                    SelfMatchPolicy::CancelIncoming => {
+                        outcome.incoming_cancelled = true;
+                        break;
+                    }
+                }
+            }
+
+            let size = remaining.min(resting.size);
+            remaining -= size;
+            if size == resting.size {
+                self.remove_order(resting.order_id);
+            } else {
+                self.update_order_size(resting.order_id, resting.size - size);
+            }
+            outcome.executions.push(Execution { resting, size });
+        }
+        outcome
+    }
+
+    /// The first order in the queue of the best level opposite `side`, if that level
+    /// crosses `price`.
+    fn crossing_front(&self, side: Side, price: i64) -> Option<Order> {
+        self.side(match side {
+            Side::Bid => Side::Ask,
+            Side::Ask => Side::Bid,
+        })
+        .best()
+        .filter(|&(level_price, _)| match side {
+            Side::Bid => level_price <= price,
+            Side::Ask => level_price >= price,
+        })
+        .and_then(|(_, level)| level.orders.first())
+        .copied()
+    }
+
+    /// Modifies an order's price and/or size under `ModifyPriorityPolicy::ExchangeStyle`,
+    /// as `OrderBook::modify_order`.
+    pub fn modify_order(&mut self, new_order: Order) -> Option<ModifyOrderInfo> {
+        self.modify_order_with_policy(new_order, ModifyPriorityPolicy::ExchangeStyle)
+    }
+
+    /// Modifies an order's price and/or size, as `OrderBook::modify_order_with_policy`.
+    pub fn modify_order_with_policy(
+        &mut self,
+        new_order: Order,
+        policy: ModifyPriorityPolicy,
+    ) -> Option<ModifyOrderInfo> {
+        let Some(old) = self.get_order(new_order.order_id).copied() else {
+            self.anomalies.unknown_modify += 1;
+            debug!("Order {} not found, ignoring modify", new_order.order_id);
+            return None;
+        };
+        let new_order = Order {
+            owner: new_order.owner.or(old.owner),
+            expires_at: new_order.expires_at.or(old.expires_at),
+            ..new_order
+        };
+        let retained = new_order.size > 0
+            && old.side == new_order.side
+            && old.price == new_order.price
+            && match policy {
+                ModifyPriorityPolicy::ExchangeStyle => new_order.size <= old.size,
+                ModifyPriorityPolicy::AlwaysPreserve => true,
+            };
+
+        let (order, level_qty, level_order_count) = if new_order.size == 0 {
+            // Nothing is left to rest, so the order leaves the book as if cancelled
+            let info = self
+                .remove_order(new_order.order_id)
+                .expect("order must exist after get_order succeeded");
+            (
+                new_order,
+                info.remaining_level_qty,
+                info.remaining_level_count,
+            )
+        } else if retained {
+            self.update_order_size(new_order.order_id, new_order.size)
+                .expect("order must exist after get_order succeeded")
+        } else {
+            self.remove_order(new_order.order_id);
+            let info = self.place_order(new_order);
+            (info.order, info.level_qty, info.level_order_count)
+        };
+        // A move to a new level may push the worst one out, which can be the order's own
+        self.trim_to_depth(order.side);
+
+        Some(ModifyOrderInfo {
+            order,
+            old_price: old.price,
+            old_size: old.size,
+            level_qty,
+            level_order_count,
+            retained_queue_position: retained,
+        })
+    }
+
+    pub fn best_bid(&self) -> Option<(i64, u64)> {
+        self.bids
+            .best()
+            .map(|(price, level)| (price, level.total_qty))
+    }
+
+    pub fn best_ask(&self) -> Option<(i64, u64)> {
+        self.asks
+            .best()
+            .map(|(price, level)| (price, level.total_qty))
+    }
+
+    /// Best bid and ask together.
+    pub fn bbo(&self) -> Bbo {
+        Bbo {
+            bid: self.best_bid(),
+            ask: self.best_ask(),
+        }
+    }
+
+    /// Best ask minus best bid, or `None` if either side is empty.
+    pub fn spread(&self) -> Option<i64> {
+        book::spread(self.best_bid(), self.best_ask())
+    }
+
+    /// Midpoint of the best bid and ask, or `None` if either side is empty.
+    pub fn mid(&self) -> Option<f64> {
+        book::mid(self.best_bid(), self.best_ask())
+    }
+
+    /// Number of price levels on `side`.
+    pub fn level_count(&self, side: Side) -> usize {
+        self.side(side).level_count()
+    }
+
+    /// Quantity resting on `side`, summed over all its levels.
+    pub fn total_qty(&self, side: Side) -> u64 {
+        self.side(side)
+            .iter()
+            .map(|(_, level)| level.total_qty)
+            .sum()
+    }
+
+    pub fn top_n_bids(&self, n: usize) -> Vec<(i64, u64)> {
+        let mut levels = Vec::with_capacity(n.min(self.bids.level_count()));
+        self.top_n_bids_into(n, &mut levels);
+        levels
+    }
+
+    pub fn top_n_asks(&self, n: usize) -> Vec<(i64, u64)> {
+        let mut levels = Vec::with_capacity(n.min(self.asks.level_count()));
+        self.top_n_asks_into(n, &mut levels);
+        levels
+    }
+
+    /// As `OrderBook::top_n_bids_into`.
+    pub fn top_n_bids_into(&self, n: usize, out: &mut Vec<(i64, u64)>) {
+        self.top_n_into(Side::Bid, n, out);
+    }
+
+    /// As `OrderBook::top_n_asks_into`.
+    pub fn top_n_asks_into(&self, n: usize, out: &mut Vec<(i64, u64)>) {
+        self.top_n_into(Side::Ask, n, out);
+    }
+
+    fn top_n_into(&self, side: Side, n: usize, out: &mut Vec<(i64, u64)>) {
+        out.clear();
+        out.extend(
+            self.side(side)
+                .iter()
+                .take(n)
+                .map(|(price, level)| (price, level.total_qty)),
+        );
+    }
+
+    /// Returns the 0-indexed queue position of the order within its price level
+    /// (0 = best). Returns `None` if the order is not in the book.
+    pub fn queue_position(&self, order_id: u64) -> Option<usize> {
+        let order = self.orders.get(&order_id)?;
+        self.side(order.side).level(order.price)?.position(order)
+    }
+}
+
+impl From<&DenseOrderBook> for MarketByPrice {
+    fn from(book: &DenseOrderBook) -> Self {
+        let summaries = |side: &DenseSide| {
+            side.iter()
+                .map(|(price, level)| (price, level.summary(price)))
+                .collect()
+        };
+        Self {
+            bids: summaries(&book.bids),
+            asks: summaries(&book.asks),
+            event_time: None,
+            recv_time: None,
+            sequence: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generators::OrderGenerator;
+    use crate::orderbook::{Action, MarketByOrderMessage, OrderBook};
+
+    fn order(order_id: u64, side: Side, price: i64, size: u64) -> Order {
+        Order {
+            order_id,
+            side,
+            price,
+            size,
+            sequence: order_id as u32,
+            owner: None,
+            expires_at: None,
+        }
+    }
+
+    /// Applies `message` to both books as `MboProcessor` would to one.
+    fn apply(book: &mut OrderBook, dense: &mut DenseOrderBook, message: &MarketByOrderMessage) {
+        let order = Order::from(message);
+        match message.action {
+            Action::Add => {
+                book.add_order(order);
+                dense.add_order(order);
+            }
+            Action::Cancel => {
+                let info = book.remove_order(order.order_id).map(|info| info.order);
+                assert_eq!(
+                    dense.remove_order(order.order_id).map(|info| info.order),
+                    info
+                );
+            }
+            Action::Modify => {
+                let info = book.modify_order(order).map(|info| info.order);
+                assert_eq!(dense.modify_order(order).map(|info| info.order), info);
+            }
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_matches_order_book() {
+        let messages = OrderGenerator::default_seeded(11).make_mbo_messages(20_000);
+        // A range inside the price distribution, so both the dense levels and the
+        // overflow map are used, and a tick grid half the prices fall off
+        for (low, high, tick_size) in [(9_900, 10_100, 1), (9_800, 10_200, 2)] {
+            let mut book = OrderBook::new();
+            let mut dense = DenseOrderBook::new(low, high, tick_size).unwrap();
+            for (index, message) in messages.iter().enumerate() {
+                apply(&mut book, &mut dense, message);
+                assert_eq!(dense.bbo(), book.bbo(), "message {index}");
+                if index % 500 == 0 {
+                    assert_eq!(MarketByPrice::from(&dense), MarketByPrice::from(&book));
+                }
+            }
+            assert!(dense.overflow_level_count(Side::Bid) > 0);
+            assert!(dense.overflow_level_count(Side::Ask) > 0);
+            assert_eq!(MarketByPrice::from(&dense), MarketByPrice::from(&book));
+            for side in [Side::Bid, Side::Ask] {
+                assert_eq!(dense.level_count(side), book.level_count(side));
+                assert_eq!(dense.total_qty(side), book.total_qty(side));
+            }
+            assert_eq!(dense.top_n_bids(10), book.top_n_bids(10));
+            assert_eq!(dense.top_n_asks(10), book.top_n_asks(10));
+
+            // Sweep both sides through the overflow and dense levels alike
+            let sweeps = [
+                order(u64::MAX, Side::Bid, 10_150, 2_000),
+                order(u64::MAX - 1, Side::Ask, 9_850, 2_000),
+            ];
+            for sweep in sweeps {
+                assert_eq!(dense.match_order(&sweep), book.match_order(&sweep));
+                assert_eq!(MarketByPrice::from(&dense), MarketByPrice::from(&book));
+            }
+            assert_eq!(dense.state_hash(), book.state_hash());
+
+            // Clearing a side removes the same orders in the same order
+            let (mut dense_asks, mut book_asks) = (dense.clone(), book.clone());
+            let removed = dense_asks.clear_side(Side::Ask);
+            let expected = book_asks.clear_side(Side::Ask);
+            assert!(
+                removed
+                    .iter()
+                    .map(|info| info.order)
+                    .eq(expected.iter().map(|info| info.order))
+            );
+            assert_eq!(dense_asks.state_hash(), book_asks.state_hash());
+            assert_eq!(dense_asks.best_bid(), book.best_bid());
+
+            let taken = dense.take_orders();
+            assert_eq!(taken.state_hash(), book.state_hash());
+            assert_eq!(
+                (dense.bbo(), dense.level_count(Side::Bid)),
+                (Bbo::default(), 0)
+            );
+        }
+    }
+
+    #[test]
+    fn test_queue_order_and_modify() {
+        let mut dense = DenseOrderBook::new(100, 110, 1).unwrap();
+        let info = dense.add_order(order(1, Side::Bid, 105, 10));
+        assert!(info.new_level);
+        dense.add_order(order(2, Side::Bid, 105, 20));
+        dense.add_order(order(3, Side::Bid, 105, 30));
+        assert_eq!(dense.queue_position(3), Some(2));
+
+        // A size decrease keeps the queue position, an increase loses it
+        let info = dense.modify_order(order(1, Side::Bid, 105, 5)).unwrap();
+        assert!(info.retained_queue_position);
+        assert_eq!((info.level_qty, info.level_order_count), (55, 3));
+        assert_eq!(dense.queue_position(1), Some(0));
+        let info = dense
+            .modify_order(Order {
+                sequence: 4,
+                ..order(2, Side::Bid, 105, 25)
+            })
+            .unwrap();
+        assert!(!info.retained_queue_position);
+        assert_eq!(dense.queue_position(2), Some(2));
+
+        let executions = dense.match_order(&order(9, Side::Ask, 105, 10));
+        let filled: Vec<_> = executions
+            .iter()
+            .map(|e| (e.resting.order_id, e.size))
+            .collect();
+        assert_eq!(filled, vec![(1, 5), (3, 5)]);
+        assert_eq!(dense.best_bid(), Some((105, 50)));
+
+        assert!(dense.remove_order(42).is_none());
+        let info = dense.remove_order(3).unwrap();
+        assert!(!info.level_removed);
+        let info = dense.remove_order(2).unwrap();
+        assert!(info.level_removed);
+        assert_eq!(dense.best_bid(), None);
+    }
+
+    #[test]
+    fn test_out_of_range_prices() {
+        let mut dense = DenseOrderBook::new(100, 110, 2).unwrap();
+        dense.add_order(order(1, Side::Ask, 104, 1));
+        dense.add_order(order(2, Side::Ask, 103, 1));
+        dense.add_order(order(3, Side::Ask, 99, 1));
+        dense.add_order(order(4, Side::Ask, 112, 1));
+        assert_eq!(dense.overflow_level_count(Side::Ask), 3);
+        assert_eq!(
+            dense.top_n_asks(4),
+            vec![(99, 1), (103, 1), (104, 1), (112, 1)]
+        );
+        dense.remove_order(3);
+        assert_eq!(dense.best_ask(), Some((103, 1)));
+        dense.remove_order(2);
+        assert_eq!(dense.best_ask(), Some((104, 1)));
+
+        assert_eq!(
+            DenseOrderBook::new(0, 10, 0).unwrap_err(),
+            DenseBookError::InvalidTickSize(0)
+        );
+        assert!(DenseOrderBook::new(10, 0, 1).is_err());
+        assert!(DenseOrderBook::new(i64::MIN, i64::MAX, 1).is_err());
+    }
+}
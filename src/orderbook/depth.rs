@@ -0,0 +1,365 @@
+//! Time-weighted average resting quantity near the touch over a session.
+//!
+//! `DepthStats` integrates the quantity of the top levels of each side over time, for
+//! buckets of the best 1, 5 and 10 levels. It is fed only when the book changes, from
+//! snapshots with `update` or by a `DepthTracker` observing an `MboProcessor`, so its
+//! cost follows the book's changes rather than its messages.
+
+use std::collections::HashMap;
+use std::{array, mem};
+
+#[cfg(feature = "polars")]
+use polars::prelude::{DataFrame, PolarsResult, df};
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+
+use crate::orderbook::events::{
+    OrderAddedEvent, OrderCancelledEvent, OrderModifiedEvent, TradeEvent,
+};
+use crate::orderbook::{
+    BookKey, MarketByOrderMessage, MarketByPrice, MboObserver, OrderBook, Side,
+};
+
+/// Number of best levels summed in each bucket: the touch, the best 5 and the best 10.
+pub const DEPTH_BUCKETS: [usize; 3] = [1, 5, 10];
+
+/// Levels in the deepest bucket.
+const DEEPEST: usize = DEPTH_BUCKETS[2];
+
+/// Quantity per side (bids, asks) and bucket of `DEPTH_BUCKETS`.
+type BucketQty = [[u64; 3]; 2];
+
+/// Accumulates the time-weighted quantity of the top levels of one book.
+///
+/// Each state recorded is in force from its timestamp until the next, and the last
+/// until the session end given to `summary`; time before the first is not observed.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DepthStats {
+    /// The quantities in force and since when.
+    current: Option<(OffsetDateTime, BucketQty)>,
+    /// Nanoseconds over the intervals that have ended.
+    observed: i128,
+    /// Quantity times nanoseconds over the intervals that have ended.
+    integrals: [[i128; 3]; 2],
+    /// Largest and smallest quantities in force for some time, once any was.
+    extremes: Option<(BucketQty, BucketQty)>,
+}
+
+/// Statistics of one side and bucket of a `DepthStats`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DepthBucketSummary {
+    /// Number of best levels summed.
+    pub levels: usize,
+    /// Time-weighted average quantity, or `None` without observed time.
+    pub avg_qty: Option<f64>,
+    /// Largest quantity in force for some time, so not one replaced at its own
+    /// timestamp, or `None` without observed time.
+    pub peak: Option<u64>,
+    /// Smallest quantity in force for some time.
+    pub trough: Option<u64>,
+}
+
+/// Statistics of a `DepthStats` up to a session end, per bucket of `DEPTH_BUCKETS`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DepthSummary {
+    /// Time from the first recorded state to the session end.
+    pub observed: Duration,
+    pub bids: [DepthBucketSummary; 3],
+    pub asks: [DepthBucketSummary; 3],
+}
+
+impl DepthStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the top levels of `mbp`, a snapshot taken at `ts_event`.
+    pub fn update(&mut self, ts_event: OffsetDateTime, mbp: &MarketByPrice) {
+        let qty = |side| {
+            let levels = match side {
+                Side::Bid => mbp.top_n_bids(DEEPEST),
+                Side::Ask => mbp.top_n_asks(DEEPEST),
+            };
+            bucket_qty(levels.iter().map(|level| level.total_quantity))
+        };
+        self.record(ts_event, [qty(Side::Bid), qty(Side::Ask)]);
+    }
+
+    /// Records the top levels of `book` at `ts_event`.
+    pub fn update_from_book(&mut self, ts_event: OffsetDateTime, book: &OrderBook) {
+        let (bids, _) = book.top_n_bids_array::<DEEPEST>();
+        let (asks, _) = book.top_n_asks_array::<DEEPEST>();
+        let qty =
+            |levels: [(i64, u64); DEEPEST]| bucket_qty(levels.into_iter().map(|(_, qty)| qty));
+        self.record(ts_event, [qty(bids), qty(asks)]);
+    }
+
+    /// Ends the state in force and starts `qty`, unless it is the same. A timestamp
+    /// before the previous one gives that state no time.
+    fn record(&mut self, ts_event: OffsetDateTime, qty: BucketQty) {
+        match self.current {
+            Some((_, current)) if current == qty => {}
+            Some((since, current)) => {
+                self.add_interval(current, (ts_event - since).whole_nanoseconds().max(0));
+                self.current = Some((since.max(ts_event), qty));
+            }
+            None => self.current = Some((ts_event, qty)),
+        }
+    }
+
+    fn add_interval(&mut self, qty: BucketQty, nanos: i128) {
+        if nanos == 0 {
+            return;
+        }
+        let (peaks, troughs) = self.extremes.get_or_insert((qty, qty));
+        for side in 0..2 {
+            for bucket in 0..3 {
+                peaks[side][bucket] = peaks[side][bucket].max(qty[side][bucket]);
+                troughs[side][bucket] = troughs[side][bucket].min(qty[side][bucket]);
+            }
+        }
+        self.observed += nanos;
+        for (integrals, qty) in self.integrals.iter_mut().zip(qty) {
+            for (integral, qty) in integrals.iter_mut().zip(qty) {
+                *integral += i128::from(qty) * nanos;
+            }
+        }
+    }
+
+    /// The statistics with the state in force lasting until `session_end`.
+    pub fn summary(&self, session_end: OffsetDateTime) -> DepthSummary {
+        let mut stats = self.clone();
+        if let Some((since, current)) = self.current {
+            stats.add_interval(current, (session_end - since).whole_nanoseconds().max(0));
+        }
+        let side = |side: usize| {
+            array::from_fn(|bucket| DepthBucketSummary {
+                levels: DEPTH_BUCKETS[bucket],
+                avg_qty: (stats.observed > 0)
+                    .then(|| stats.integrals[side][bucket] as f64 / stats.observed as f64),
+                peak: stats.extremes.map(|(peaks, _)| peaks[side][bucket]),
+                trough: stats.extremes.map(|(_, troughs)| troughs[side][bucket]),
+            })
+        };
+        DepthSummary {
+            observed: Duration::nanoseconds(stats.observed as i64),
+            bids: side(0),
+            asks: side(1),
+        }
+    }
+}
+
+/// Cumulative quantity of `levels`, best first, at each bucket's depth.
+fn bucket_qty(levels: impl Iterator<Item = u64>) -> [u64; 3] {
+    let mut qty = [0; 3];
+    let mut total = 0u64;
+    for (index, level_qty) in levels.take(DEEPEST).enumerate() {
+        total = total.saturating_add(level_qty);
+        for (bucket, &levels) in DEPTH_BUCKETS.iter().enumerate() {
+            if index < levels {
+                qty[bucket] = total;
+            }
+        }
+    }
+    qty
+}
+
+impl DepthSummary {
+    /// The summary as a DataFrame of one row per side and bucket, with columns `side`
+    /// (`"B"` or `"A"`), `levels`, `avg_qty`, `peak` and `trough`.
+    #[cfg(feature = "polars")]
+    pub fn to_dataframe(&self) -> PolarsResult<DataFrame> {
+        let rows: Vec<(&str, &DepthBucketSummary)> = self
+            .bids
+            .iter()
+            .map(|bucket| ("B", bucket))
+            .chain(self.asks.iter().map(|bucket| ("A", bucket)))
+            .collect();
+        df!(
+            "side" => rows.iter().map(|&(side, _)| side).collect::<Vec<_>>(),
+            "levels" => rows.iter().map(|(_, b)| b.levels as u32).collect::<Vec<_>>(),
+            "avg_qty" => rows.iter().map(|(_, b)| b.avg_qty).collect::<Vec<_>>(),
+            "peak" => rows.iter().map(|(_, b)| b.peak).collect::<Vec<_>>(),
+            "trough" => rows.iter().map(|(_, b)| b.trough).collect::<Vec<_>>(),
+        )
+    }
+}
+
+/// Observer keeping a `DepthStats` per book, recorded at the end of each event
+/// that added, removed, modified or matched an order. Events that change nothing,
+/// such as Trades and Fills, cost nothing.
+#[derive(Debug, Default)]
+pub struct DepthTracker {
+    books: HashMap<BookKey, DepthStats>,
+    /// True if the message being applied changed the book.
+    changed: bool,
+    /// Books changed since their last event ended.
+    pending: Vec<BookKey>,
+}
+
+impl DepthTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The statistics of an instrument, once an event has changed its book.
+    pub fn stats(&self, instrument_id: u32) -> Option<&DepthStats> {
+        self.stats_for(instrument_id.into())
+    }
+
+    /// The statistics of the book under `key`, once an event has changed it.
+    pub fn stats_for(&self, key: BookKey) -> Option<&DepthStats> {
+        self.books.get(&key)
+    }
+}
+
+impl MboObserver for DepthTracker {
+    fn on_order_added(&mut self, _event: &OrderAddedEvent) {
+        self.changed = true;
+    }
+
+    fn on_order_cancelled(&mut self, _event: &OrderCancelledEvent) {
+        self.changed = true;
+    }
+
+    fn on_order_modified(&mut self, _event: &OrderModifiedEvent) {
+        self.changed = true;
+    }
+
+    fn on_trade(&mut self, event: &TradeEvent) {
+        // Resting orders executed by a crossing Add
+        self.changed |= !event.aggressor;
+    }
+
+    fn on_clear(&mut self) {
+        self.changed = true;
+    }
+
+    fn on_message_applied(
+        &mut self,
+        message: &MarketByOrderMessage,
+        key: BookKey,
+        book: &OrderBook,
+    ) {
+        if mem::take(&mut self.changed) && !self.pending.contains(&key) {
+            self.pending.push(key);
+        }
+        if message.is_last && self.pending.contains(&key) {
+            self.pending.retain(|&pending| pending != key);
+            self.books
+                .entry(key)
+                .or_default()
+                .update_from_book(message.event_time, book);
+        }
+    }
+
+    fn on_message_rejected(
+        &mut self,
+        message: &MarketByOrderMessage,
+        key: BookKey,
+        book: &OrderBook,
+    ) {
+        self.on_message_applied(message, key, book);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::{Action, MboProcessor};
+
+    fn at(seconds: i64) -> OffsetDateTime {
+        OffsetDateTime::UNIX_EPOCH + Duration::seconds(seconds)
+    }
+
+    fn msg(
+        seconds: i64,
+        action: Action,
+        order_id: u64,
+        side: Side,
+        price: i64,
+        size: u32,
+    ) -> MarketByOrderMessage {
+        MarketByOrderMessage {
+            sequence: order_id as u32,
+            ..MarketByOrderMessage::test(action, side, price, order_id, size).at(at(seconds))
+        }
+    }
+
+    #[test]
+    fn test_three_change_timeline() {
+        let mut processor = MboProcessor::with_observer(DepthTracker::new());
+        processor.process_messages(
+            (0..6).map(|level| msg(0, Action::Add, level + 1, Side::Bid, 100 - level as i64, 10)),
+        );
+        // Six bid levels of 10 from 0 s, an ask of 5 at 10 s and the touch bid gone at
+        // 15 s; the Trade changes nothing
+        processor.process_messages([
+            msg(10, Action::Add, 7, Side::Ask, 101, 5),
+            msg(12, Action::Trade, 0, Side::Bid, 100, 1),
+            msg(15, Action::Cancel, 1, Side::Bid, 100, 10),
+        ]);
+        let stats = processor.observer().stats(1).unwrap();
+        let summary = stats.summary(at(20));
+
+        assert_eq!(summary.observed, Duration::seconds(20));
+        let avg = |buckets: &[DepthBucketSummary; 3]| buckets.map(|bucket| bucket.avg_qty.unwrap());
+        // Bids: 10 and 50 throughout as the sixth level moves up, 60 for 15 s then 50
+        assert_eq!(avg(&summary.bids), [10.0, 50.0, 57.5]);
+        // Asks: 0 for 10 s, then 5
+        assert_eq!(avg(&summary.asks), [2.5, 2.5, 2.5]);
+        assert_eq!(summary.bids[2].levels, 10);
+        assert_eq!(
+            (summary.bids[2].peak, summary.bids[2].trough),
+            (Some(60), Some(50))
+        );
+        assert_eq!(
+            (summary.asks[0].peak, summary.asks[0].trough),
+            (Some(5), Some(0))
+        );
+    }
+
+    #[test]
+    fn test_snapshots_and_empty_session() {
+        let mut stats = DepthStats::new();
+        let empty = stats.summary(at(0));
+        assert_eq!((empty.bids[0].avg_qty, empty.bids[0].peak), (None, None));
+
+        let mut book = OrderBook::new();
+        stats.update(at(0), &MarketByPrice::from(&book));
+        book.add_order(crate::orderbook::Order {
+            order_id: 1,
+            side: Side::Ask,
+            price: 101,
+            size: 8,
+            sequence: 1,
+            owner: None,
+            expires_at: None,
+        });
+        // Zero-length: no time to weigh by
+        stats.update(at(0), &MarketByPrice::from(&book));
+        assert_eq!(stats.summary(at(0)).asks[0].avg_qty, None);
+        assert_eq!(stats.summary(at(0)).asks[0].peak, None);
+        let summary = stats.summary(at(4));
+        assert_eq!(summary.asks[1].avg_qty, Some(8.0));
+        assert_eq!(
+            (summary.asks[1].peak, summary.asks[1].trough),
+            (Some(8), Some(8))
+        );
+        assert_eq!(summary.bids[0].avg_qty, Some(0.0));
+    }
+
+    #[cfg(feature = "polars")]
+    #[test]
+    fn test_summary_dataframe() {
+        let mut stats = DepthStats::new();
+        stats.update(at(0), &MarketByPrice::new());
+        let df = stats.summary(at(1)).to_dataframe().unwrap();
+        assert_eq!(df.shape(), (6, 5));
+        let levels = df.column("levels").unwrap().u32().unwrap();
+        assert_eq!(
+            levels.into_no_null_iter().collect::<Vec<_>>(),
+            [1, 5, 10, 1, 5, 10]
+        );
+    }
+}
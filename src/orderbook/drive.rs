@@ -0,0 +1,151 @@
+//! Lazy, pull-based processing of message streams.
+
+use crate::orderbook::{
+    Bbo, BookKey, MarketByOrderMessage, MboObserver, MboProcessError, MboProcessor,
+};
+
+/// Outcome of applying a single message via `MboProcessor::drive`.
+#[derive(Debug, Clone)]
+pub struct ProcessEvent {
+    /// The message that was applied.
+    pub message: MarketByOrderMessage,
+    /// Result of `MboProcessor::process_message` for this message.
+    pub outcome: Result<(), MboProcessError>,
+    /// Top of book of the message's book (see `MboProcessor::book_key`) after the
+    /// message was applied.
+    pub bbo: Bbo,
+    /// True if the message changed the top of book of its book.
+    pub bbo_changed: bool,
+}
+
+impl<O: MboObserver> MboProcessor<O> {
+    /// Adapts a stream of decoded messages into a stream of `ProcessEvent`s.
+    ///
+    /// Messages are pulled and applied one at a time as the returned iterator is
+    /// advanced, so it can be chained with `filter`, `take_while` and friends over an
+    /// unbounded source such as a DBN decoder without materializing a `Vec`. Input
+    /// errors are passed through untouched and leave the book unchanged.
+    pub fn drive<I, E>(&mut self, messages: I) -> impl Iterator<Item = Result<ProcessEvent, E>>
+    where
+        I: IntoIterator<Item = Result<MarketByOrderMessage, E>>,
+    {
+        messages
+            .into_iter()
+            .map(move |input| input.map(|message| self.step(message)))
+    }
+
+    /// Top of book for the given instrument; empty if it has not been seen.
+    pub fn bbo(&self, instrument_id: u32) -> Bbo {
+        self.bbo_for(instrument_id.into())
+    }
+
+    /// Top of book of the book under `key`; empty if no message has been routed to it.
+    pub fn bbo_for(&self, key: BookKey) -> Bbo {
+        self.book_for(key)
+            .map(|book| book.bbo())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn step(&mut self, message: MarketByOrderMessage) -> ProcessEvent {
+        let key = self.book_key(&message);
+        let before = self.bbo_for(key);
+        let outcome = self.process_message(&message);
+        let bbo = self.bbo_for(key);
+        ProcessEvent {
+            message,
+            outcome,
+            bbo,
+            bbo_changed: bbo != before,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use std::iter;
+
+    use time::{Duration, OffsetDateTime};
+
+    use crate::orderbook::{Action, Side};
+
+    fn add(n: u64) -> MarketByOrderMessage {
+        // Alternate sides; only every fourth message improves the bid, and every ask
+        // rests behind the first one.
+        let side = if n.is_multiple_of(2) {
+            Side::Bid
+        } else {
+            Side::Ask
+        };
+        let price = match n {
+            n if n.is_multiple_of(4) => 100 + (n / 4) as i64,
+            n if n.is_multiple_of(2) => 50,
+            n => 1000 + n as i64,
+        };
+        MarketByOrderMessage {
+            sequence: n as u32,
+            ..MarketByOrderMessage::test(Action::Add, side, price, n, 1)
+                .at(OffsetDateTime::UNIX_EPOCH + Duration::seconds(n as i64))
+        }
+    }
+
+    #[test]
+    fn test_drive_unbounded_lazy_source() {
+        let mut proc = MboProcessor::new();
+        // An endless generator: only finishes because the consumer stops pulling.
+        let source = (0..).map(|n| Ok::<_, Infallible>(add(n)));
+        let cutoff = OffsetDateTime::UNIX_EPOCH + Duration::seconds(9);
+
+        let changes: Vec<u64> = proc
+            .drive(source)
+            .map_while(Result::ok)
+            .take_while(|event| event.message.event_time <= cutoff)
+            .filter(|event| event.bbo_changed)
+            .map(|event| event.message.order_id)
+            .collect();
+
+        // First bid, first ask, then bid improvements at 4 and 8
+        assert_eq!(changes, vec![0, 1, 4, 8]);
+        // Exactly one message past the cutoff was pulled and applied
+        assert_eq!(proc.stats().messages, 11);
+        assert_eq!(proc.bbo(1).bid, Some((102, 1)));
+    }
+
+    #[test]
+    fn test_drive_passes_input_errors_through() {
+        let mut proc = MboProcessor::new();
+        let source = iter::once(Ok(add(0)))
+            .chain(iter::once(Err("corrupt record")))
+            .chain(iter::once(Ok(add(1))));
+
+        let events: Vec<_> = proc.drive(source).collect();
+        assert_eq!(events.len(), 3);
+        assert!(events[0].as_ref().is_ok_and(|e| e.bbo_changed));
+        assert_eq!(events[1].as_ref().err(), Some(&"corrupt record"));
+        let last = events[2].as_ref().unwrap();
+        assert_eq!(
+            last.bbo,
+            Bbo {
+                bid: Some((100, 1)),
+                ask: Some((1001, 1))
+            }
+        );
+    }
+
+    #[test]
+    fn test_drive_reports_failed_messages() {
+        let mut proc = MboProcessor::new();
+        let modify_unknown = MarketByOrderMessage {
+            action: Action::Modify,
+            ..add(0)
+        };
+        let event = proc
+            .drive(iter::once(Ok::<_, Infallible>(modify_unknown)))
+            .next()
+            .unwrap()
+            .unwrap();
+        assert!(event.outcome.is_err());
+        assert!(!event.bbo_changed);
+    }
+}
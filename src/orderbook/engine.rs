@@ -0,0 +1,244 @@
+//! A toy matching engine on top of `OrderBook`, for backtesting strategies against a
+//! book rather than only replaying one.
+
+use serde::{Deserialize, Serialize};
+
+use crate::orderbook::book::OrderLevel;
+use crate::orderbook::{Execution, Order, OrderBook, Side};
+
+/// How long the unfilled part of a `NewOrder` lives.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeInForce {
+    /// Good till cancelled: the remainder rests at the limit price.
+    #[default]
+    Gtc,
+    /// Immediate or cancel: the remainder is cancelled.
+    Ioc,
+    /// Fill or kill: the order fills completely at once or not at all.
+    Fok,
+}
+
+/// An order submitted to `OrderBook::execute`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NewOrder {
+    /// Id the remainder rests under. An order resting with the same id is replaced.
+    pub order_id: u64,
+    pub side: Side,
+    /// Limit price, or `None` for a market order, whose remainder never rests.
+    pub price: Option<i64>,
+    pub qty: u64,
+    pub time_in_force: TimeInForce,
+    pub owner: Option<u32>,
+}
+
+impl NewOrder {
+    /// A good-till-cancelled limit order.
+    pub fn limit(order_id: u64, side: Side, price: i64, qty: u64) -> Self {
+        Self {
+            order_id,
+            side,
+            price: Some(price),
+            qty,
+            time_in_force: TimeInForce::Gtc,
+            owner: None,
+        }
+    }
+
+    /// A market order, trading at any price.
+    pub fn market(order_id: u64, side: Side, qty: u64) -> Self {
+        Self {
+            price: None,
+            ..Self::limit(order_id, side, 0, qty)
+        }
+    }
+
+    pub fn with_time_in_force(mut self, time_in_force: TimeInForce) -> Self {
+        self.time_in_force = time_in_force;
+        self
+    }
+
+    pub fn with_owner(mut self, owner: u32) -> Self {
+        self.owner = Some(owner);
+        self
+    }
+
+    /// The price the order trades up (buying) or down (selling) to.
+    fn limit_price(&self) -> i64 {
+        match (self.price, self.side) {
+            (Some(price), _) => price,
+            (None, Side::Bid) => i64::MAX,
+            (None, Side::Ask) => i64::MIN,
+        }
+    }
+}
+
+/// Result of `OrderBook::execute`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ExecutionReport {
+    /// Each resting order traded against, in match order, at its own price.
+    pub fills: Vec<Execution>,
+    /// Id of the remainder left resting, if any.
+    pub resting_order_id: Option<u64>,
+    /// Quantity neither filled nor left resting.
+    pub cancelled_qty: u64,
+}
+
+impl ExecutionReport {
+    pub fn filled_qty(&self) -> u64 {
+        self.fills.iter().map(|fill| fill.size).sum()
+    }
+}
+
+impl OrderBook {
+    /// Matches `order` against the book in price-time priority, then rests or cancels
+    /// the remainder as its time in force says. A remainder rests behind every order
+    /// already at its price.
+    pub fn execute(&mut self, order: NewOrder) -> ExecutionReport {
+        let price = order.limit_price();
+        if order.time_in_force == TimeInForce::Fok
+            && self.crossing_qty(order.side, price) < order.qty
+        {
+            return ExecutionReport {
+                cancelled_qty: order.qty,
+                ..ExecutionReport::default()
+            };
+        }
+
+        let fills = self.match_order(&Order {
+            order_id: order.order_id,
+            side: order.side,
+            price,
+            size: order.qty,
+            sequence: 0,
+            owner: order.owner,
+            expires_at: None,
+        });
+        let remaining = order.qty - fills.iter().map(|fill| fill.size).sum::<u64>();
+        let rests =
+            remaining > 0 && order.price.is_some() && order.time_in_force == TimeInForce::Gtc;
+        if !rests {
+            return ExecutionReport {
+                fills,
+                resting_order_id: None,
+                cancelled_qty: remaining,
+            };
+        }
+
+        let sequence = self
+            .levels(order.side)
+            .get(&price)
+            .and_then(OrderLevel::back)
+            .map_or(0, |back| back.sequence.saturating_add(1));
+        self.add_order(Order {
+            order_id: order.order_id,
+            side: order.side,
+            price,
+            size: remaining,
+            sequence,
+            owner: order.owner,
+            expires_at: None,
+        });
+        ExecutionReport {
+            fills,
+            resting_order_id: Some(order.order_id),
+            cancelled_qty: 0,
+        }
+    }
+
+    /// Quantity resting opposite `side` at prices an order limited to `price` reaches.
+    fn crossing_qty(&self, side: Side, price: i64) -> u64 {
+        let levels: Box<dyn Iterator<Item = &OrderLevel>> = match side {
+            Side::Bid => Box::new(self.asks().range(..=price).map(|(_, level)| level)),
+            Side::Ask => Box::new(self.bids().range(price..).map(|(_, level)| level)),
+        };
+        levels.map(OrderLevel::total_qty).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::MarketByPrice;
+
+    /// Asks of 5 and 5 at 101, 10 at 102 and 10 at 103, and a bid of 10 at 99.
+    fn book() -> OrderBook {
+        let mut book = OrderBook::new();
+        for (order_id, side, price, size) in [
+            (1, Side::Ask, 101, 5),
+            (2, Side::Ask, 101, 5),
+            (3, Side::Ask, 102, 10),
+            (4, Side::Ask, 103, 10),
+            (5, Side::Bid, 99, 10),
+        ] {
+            book.add_order(Order {
+                order_id,
+                side,
+                price,
+                size,
+                sequence: order_id as u32,
+                owner: None,
+                expires_at: None,
+            });
+        }
+        book
+    }
+
+    fn fills(report: &ExecutionReport) -> Vec<(u64, i64, u64)> {
+        report
+            .fills
+            .iter()
+            .map(|fill| (fill.resting.order_id, fill.resting.price, fill.size))
+            .collect()
+    }
+
+    #[test]
+    fn test_marketable_limit_sweeps_two_levels_and_rests() {
+        let mut book = book();
+        let report = book.execute(NewOrder::limit(10, Side::Bid, 102, 25));
+
+        assert_eq!(fills(&report), vec![(1, 101, 5), (2, 101, 5), (3, 102, 10)]);
+        assert_eq!(report.filled_qty(), 20);
+        assert_eq!(report.resting_order_id, Some(10));
+        assert_eq!(report.cancelled_qty, 0);
+        assert_eq!(book.top_n_bids(5), vec![(102, 5), (99, 10)]);
+        assert_eq!(book.top_n_asks(5), vec![(103, 10)]);
+
+        // A later order at the same price queues behind it
+        book.execute(NewOrder::limit(11, Side::Bid, 102, 3));
+        assert_eq!(book.queue_position(10), Some(0));
+        assert_eq!(book.queue_position(11), Some(1));
+        let report = book.execute(NewOrder::market(12, Side::Ask, 6));
+        assert_eq!(fills(&report), vec![(10, 102, 5), (11, 102, 1)]);
+    }
+
+    #[test]
+    fn test_ioc_partially_fills_and_cancels_the_rest() {
+        let mut book = book();
+        let order = NewOrder::limit(10, Side::Bid, 101, 12).with_time_in_force(TimeInForce::Ioc);
+        let report = book.execute(order);
+
+        assert_eq!(fills(&report), vec![(1, 101, 5), (2, 101, 5)]);
+        assert_eq!(report.resting_order_id, None);
+        assert_eq!(report.cancelled_qty, 2);
+        assert!(book.get_order(10).is_none());
+        assert_eq!(book.best_bid(), Some((99, 10)));
+        assert_eq!(book.best_ask(), Some((102, 10)));
+    }
+
+    #[test]
+    fn test_fok_that_cannot_fill_leaves_book_untouched() {
+        let mut book = book();
+        let before = MarketByPrice::from(&book);
+        let order = NewOrder::limit(10, Side::Bid, 102, 21).with_time_in_force(TimeInForce::Fok);
+        let report = book.execute(order);
+
+        assert!(report.fills.is_empty());
+        assert_eq!((report.resting_order_id, report.cancelled_qty), (None, 21));
+        assert_eq!(MarketByPrice::from(&book), before);
+
+        // Enough within the limit fills completely
+        let order = NewOrder::limit(10, Side::Bid, 102, 20).with_time_in_force(TimeInForce::Fok);
+        assert_eq!(book.execute(order).filled_qty(), 20);
+        assert_eq!(book.best_ask(), Some((103, 10)));
+    }
+}
@@ -1,3 +1,4 @@
+use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 
 use crate::orderbook::{Order, Side};
@@ -13,6 +14,8 @@ pub struct OrderAddedEvent {
     pub level_order_count: usize,
     /// True if this order created a new price level.
     pub new_level: bool,
+    /// Side and price of the order with the same id that this add replaced, if any.
+    pub replaced_level: Option<(Side, i64)>,
     /// Exchange event timestamp.
     pub event_time: OffsetDateTime,
     /// Server receive timestamp.
@@ -69,7 +72,7 @@ pub struct OrderModifiedEvent {
 /// A single exchange trade produces two MBO messages: a Trade (aggressor)
 /// and a Fill (resting side). Both are emitted as `TradeEvent` with the
 /// `aggressor` flag distinguishing them.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct TradeEvent {
     /// Trade price.
     pub price: i64,
@@ -77,6 +80,9 @@ pub struct TradeEvent {
     pub size: u32,
     /// Side of the order involved in the trade.
     pub side: Side,
+    /// Id of the order involved in the trade, as reported by the venue (often 0 for
+    /// aggressor Trade records).
+    pub order_id: u64,
     /// True if this was the aggressor (incoming order), false if resting (passive fill).
     pub aggressor: bool,
     /// Exchange event timestamp.
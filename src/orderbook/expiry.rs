@@ -0,0 +1,199 @@
+//! Removing orders whose expiry time has passed.
+//!
+//! Every order placed with an `expires_at` is pushed onto a min-heap of
+//! `(expires_at, order_id)`, so `OrderBook::purge_expired` (and the same on
+//! `DenseOrderBook`) only looks at the orders due, however large the book. Cancels, fills and replacements leave their entries
+//! behind; a popped entry is acted on only if the book still holds the order with that
+//! expiry, and the heap is rebuilt from the live orders once stale entries outnumber
+//! them.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::orderbook::{Order, OrderBook, RemoveOrderInfo};
+
+/// Stale entries tolerated beyond twice the book's order count before a rebuild.
+const STALE_SLACK: usize = 64;
+
+/// Min-heap of `(expires_at, order_id)`, possibly holding stale entries.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub(crate) struct ExpiryQueue {
+    heap: BinaryHeap<Reverse<(u64, u64)>>,
+}
+
+impl ExpiryQueue {
+    /// Takes the earliest entry if it is due at `now_ns`. It may be stale: the caller
+    /// checks that the book still holds the order with that expiry.
+    pub(crate) fn pop_due(&mut self, now_ns: u64) -> Option<(u64, u64)> {
+        let &Reverse(entry) = self.heap.peek()?;
+        if entry.0 > now_ns {
+            return None;
+        }
+        self.heap.pop();
+        Some(entry)
+    }
+
+    /// Queues `order` if it has an expiry.
+    pub(crate) fn push(&mut self, order: &Order) {
+        if let Some(expires_at) = order.expires_at {
+            self.heap.push(Reverse((expires_at, order.order_id)));
+        }
+    }
+
+    /// Whether stale entries have grown too many for a book of `live_count` orders, so
+    /// the queue should be rebuilt from them.
+    pub(crate) fn is_bloated(&self, live_count: usize) -> bool {
+        self.heap.len() > 2 * live_count + STALE_SLACK
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.heap.len()
+    }
+}
+
+/// A queue of the orders given, without stale entries.
+impl<'a> FromIterator<&'a Order> for ExpiryQueue {
+    fn from_iter<I: IntoIterator<Item = &'a Order>>(orders: I) -> Self {
+        let heap = orders
+            .into_iter()
+            .filter_map(|order| Some(Reverse((order.expires_at?, order.order_id))))
+            .collect();
+        Self { heap }
+    }
+}
+
+/// Which orders a book holds is compared through its levels, so the queue is ignored.
+impl PartialEq for ExpiryQueue {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for ExpiryQueue {}
+
+impl OrderBook {
+    /// Removes and returns every order whose `expires_at` is at or before `now_ns`, in
+    /// the order they expired, ties by order id. Each is removed as if cancelled,
+    /// dropping emptied levels and journaling the removal. Orders without an expiry
+    /// never leave.
+    ///
+    /// Costs a heap pop per order removed or stale entry skipped, so it can be called
+    /// for every message.
+    pub fn purge_expired(&mut self, now_ns: u64) -> Vec<Order> {
+        self.purge_expired_infos(now_ns)
+            .into_iter()
+            .map(|info| info.order)
+            .collect()
+    }
+
+    /// `purge_expired` with the level state left by each removal.
+    pub(crate) fn purge_expired_infos(&mut self, now_ns: u64) -> Vec<RemoveOrderInfo> {
+        let mut purged = Vec::new();
+        while let Some((expires_at, order_id)) = self.expiries.pop_due(now_ns) {
+            let live = self
+                .get_order(order_id)
+                .is_some_and(|order| order.expires_at == Some(expires_at));
+            if live {
+                purged.extend(self.remove_order(order_id));
+            }
+        }
+        purged
+    }
+
+    /// Queues `order` for `purge_expired` if it has an expiry, rebuilding the queue
+    /// from the book's orders once it holds too many stale entries.
+    pub(crate) fn schedule_expiry(&mut self, order: &Order) {
+        self.expiries.push(order);
+        if self.expiries.is_bloated(self.order_index.len()) {
+            self.expiries = self
+                .bids()
+                .values()
+                .chain(self.asks().values())
+                .flat_map(|level| level.queue.values())
+                .collect();
+        }
+    }
+
+    /// Entries in the expiry queue, stale ones included.
+    #[cfg(test)]
+    pub(crate) fn expiry_queue_len(&self) -> usize {
+        self.expiries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::Side;
+
+    fn order(order_id: u64, price: i64, expires_at: Option<u64>) -> Order {
+        Order {
+            order_id,
+            side: Side::Bid,
+            price,
+            size: 10,
+            sequence: order_id as u32,
+            owner: None,
+            expires_at,
+        }
+    }
+
+    #[test]
+    fn test_purge_interleaved_expiring_orders() {
+        let mut book = OrderBook::new();
+        book.add_order(order(1, 100, Some(3_000)));
+        book.add_order(order(2, 100, None));
+        book.add_order(order(3, 99, Some(1_000)));
+        book.add_order(order(4, 98, Some(2_000)));
+        book.add_order(order(5, 98, None));
+
+        assert!(book.purge_expired(999).is_empty());
+        let purged = book.purge_expired(2_500);
+        assert_eq!(
+            purged.iter().map(|o| o.order_id).collect::<Vec<_>>(),
+            vec![3, 4]
+        );
+        // The emptied level is gone, the shared one keeps its other order
+        assert_eq!(book.top_n_bids(5), vec![(100, 20), (98, 10)]);
+        assert_eq!(book.get_order(3), None);
+
+        assert_eq!(book.purge_expired(u64::MAX).len(), 1);
+        assert_eq!(book.top_n_bids(5), vec![(100, 10), (98, 10)]);
+        assert_eq!(book.validate(), Ok(()));
+        assert_eq!(book.anomalies().total(), 0);
+    }
+
+    #[test]
+    fn test_purge_expiry_equal_to_now() {
+        let mut book = OrderBook::new();
+        book.add_order(order(1, 100, Some(5_000)));
+        book.add_order(order(2, 100, Some(5_001)));
+        let purged = book.purge_expired(5_000);
+        assert_eq!(purged, vec![order(1, 100, Some(5_000))]);
+        assert_eq!(book.best_bid(), Some((100, 10)));
+    }
+
+    #[test]
+    fn test_purge_skips_stale_entries() {
+        let mut book = OrderBook::new();
+        book.add_order(order(1, 100, Some(1_000)));
+        book.add_order(order(2, 100, Some(1_000)));
+        // Cancelled, and re-added with a later expiry
+        book.remove_order(1);
+        book.add_order(order(2, 101, Some(9_000)));
+        assert!(book.purge_expired(1_000).is_empty());
+        assert_eq!(book.best_bid(), Some((101, 10)));
+        assert_eq!(book.anomalies().unknown_cancel, 0);
+
+        // Churn through cancelled orders rebuilds the queue rather than growing it
+        for order_id in 10..1_000 {
+            book.add_order(order(order_id, 90, Some(order_id)));
+            book.remove_order(order_id);
+        }
+        assert!(book.expiry_queue_len() <= 2 * 2 + STALE_SLACK);
+        assert_eq!(book.purge_expired(9_000), vec![order(2, 101, Some(9_000))]);
+    }
+}
@@ -0,0 +1,122 @@
+//! Processing MBO records requested from the Databento historical API.
+//!
+//! The `databento` client decodes with the same `dbn` release as this crate, so its
+//! records go straight to `process_records`. Records are applied as they arrive; the
+//! response is never held whole.
+
+use std::iter;
+
+use databento::HistoricalClient;
+use databento::historical::DateTimeRange;
+use databento::historical::timeseries::GetRangeParams;
+use dbn::{MboMsg, Schema};
+use thiserror::Error;
+
+use crate::orderbook::{MboObserver, MboProcessor, ProcessSummary, RecordSourceError};
+
+/// Environment variable the API key is read from.
+pub const API_KEY_ENV: &str = "DATABENTO_API_KEY";
+
+/// A historical request for the MBO records of `symbols` in `dataset`, with event
+/// times in `[start, end)` nanoseconds since the UNIX epoch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchRequest {
+    pub dataset: String,
+    pub symbols: Vec<String>,
+    pub start: u64,
+    pub end: u64,
+}
+
+/// Errors fetching records from the Databento API.
+#[derive(Debug, Error)]
+pub enum FetchError {
+    /// The request failed before any record arrived, e.g. the API key is missing or
+    /// rejected, or the API could not be reached.
+    #[error("Databento request failed: {0}")]
+    Request(#[from] databento::Error),
+
+    /// The response failed part-way; the records before it stay applied.
+    #[error(transparent)]
+    Stream(#[from] RecordSourceError<dbn::Error>),
+}
+
+impl FetchError {
+    /// Records received before the failure.
+    pub fn processed(&self) -> usize {
+        match self {
+            Self::Request(_) => 0,
+            Self::Stream(error) => error.processed,
+        }
+    }
+}
+
+impl<O: MboObserver> MboProcessor<O> {
+    /// Requests `request` from the Databento historical API, with the API key in
+    /// `API_KEY_ENV`, and applies its records as they are received.
+    ///
+    /// Blocks the calling thread on a runtime of its own, so it must not be called
+    /// from within an async runtime. Records that fail conversion or processing are
+    /// handled as in `process_records`.
+    pub fn fetch(&mut self, request: &FetchRequest) -> Result<ProcessSummary, FetchError> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(databento::Error::Io)?;
+        let mut client = HistoricalClient::builder().key_from_env()?.build()?;
+        let params = GetRangeParams::builder()
+            .dataset(&request.dataset)
+            .symbols(request.symbols.clone())
+            .schema(Schema::Mbo)
+            .date_time_range(DateTimeRange::try_from((request.start, request.end))?)
+            .build();
+        let mut decoder = runtime.block_on(client.timeseries().get_range(&params))?;
+        let records = iter::from_fn(|| {
+            runtime
+                .block_on(decoder.decode_record::<MboMsg>())
+                .map(|record| record.cloned())
+                .transpose()
+        });
+        Ok(self.process_records(records)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::c_char;
+    use std::io;
+
+    fn record(action: u8, side: u8, order_id: u64, price: i64) -> MboMsg {
+        MboMsg {
+            hd: dbn::RecordHeader::new::<MboMsg>(dbn::enums::rtype::MBO, 1, 7, 1_000),
+            order_id,
+            price,
+            size: 10,
+            flags: dbn::FlagSet::new(dbn::flags::LAST),
+            channel_id: 0,
+            action: action as c_char,
+            side: side as c_char,
+            ts_recv: 1_050,
+            ts_in_delta: 0,
+            sequence: 1,
+        }
+    }
+
+    #[test]
+    fn test_stream_failure_keeps_applied_records() {
+        let records = [
+            Ok(record(b'A', b'B', 1, 100)),
+            Ok(record(b'A', b'A', 2, 101)),
+            Err(dbn::Error::io(
+                io::Error::from(io::ErrorKind::ConnectionReset),
+                "reading the response",
+            )),
+        ];
+        let mut proc = MboProcessor::new();
+        let error = FetchError::from(proc.process_records(records).unwrap_err());
+        assert_eq!(error.processed(), 2);
+        assert!(error.to_string().contains("after 2 records"));
+        assert_eq!(proc.best_bid(), Some((100, 10)));
+        assert_eq!(proc.best_ask(), Some((101, 10)));
+    }
+}
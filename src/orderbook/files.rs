@@ -0,0 +1,256 @@
+//! Collecting and ordering the files of a multi-file replay.
+//!
+//! Market data is usually delivered as one file per day or hour. To replay several of
+//! them as one session they are put in chronological order by the event time of their
+//! first message, which only needs the start of each file to be read. Output files are
+//! created through `create_output`.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use dbn::decode::{DynReader, dbn::Decoder};
+use glob::Pattern;
+use time::OffsetDateTime;
+
+#[cfg(feature = "polars")]
+use crate::orderbook::ParquetBatches;
+#[cfg(feature = "itch")]
+use crate::orderbook::itch_messages;
+use crate::orderbook::{
+    CsvOptions, DataFormat, InputError, InputFormat, MarketByOrderMessage, decompress,
+    detect_format, mbo_messages, read_mbo_csv_from, read_mbo_ndjson_from,
+};
+
+/// Creates the output file at `path` and any missing parent directories, failing if it
+/// exists unless `overwrite`.
+pub fn create_output(path: impl AsRef<Path>, overwrite: bool) -> io::Result<File> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        fs::create_dir_all(parent)?;
+    }
+    if overwrite {
+        File::create(path)
+    } else {
+        File::create_new(path)
+    }
+}
+
+/// Replaces each directory in `paths` by the files directly inside it whose names match
+/// `pattern`, in name order. Files named explicitly are kept whether or not they match.
+pub fn expand_paths(paths: &[PathBuf], pattern: Option<&Pattern>) -> io::Result<Vec<PathBuf>> {
+    let mut expanded = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            expanded.push(path.clone());
+            continue;
+        }
+        let mut files = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let matches = pattern.is_none_or(|pattern| {
+                entry
+                    .file_name()
+                    .to_str()
+                    .is_some_and(|name| pattern.matches(name))
+            });
+            if matches && entry.file_type()?.is_file() {
+                files.push(entry.path());
+            }
+        }
+        files.sort();
+        expanded.extend(files);
+    }
+    Ok(expanded)
+}
+
+/// Event time of the first message in the file at `path`, or `None` if it has none.
+///
+/// Only the first record is decoded. For CSV and NDJSON that is the first row, which
+/// yields `None` if it cannot be converted; CSV without a `ts_event` column has every
+/// event at the UNIX epoch.
+pub fn first_event_time(
+    path: impl AsRef<Path>,
+    input: InputFormat,
+    csv_options: CsvOptions,
+) -> Result<Option<OffsetDateTime>, InputError> {
+    let path = path.as_ref();
+    let open = || decompress(BufReader::new(File::open(path)?), input.compression);
+    let first: Option<MarketByOrderMessage> = match input.format {
+        DataFormat::Dbn => {
+            let decoder = Decoder::new(DynReader::new_inferred(open()?)?)?;
+            mbo_messages(decoder).next().transpose()?
+        }
+        DataFormat::Csv => {
+            let rows = if csv_options.has_header() { 2 } else { 1 };
+            let head = read_lines(open()?, rows, false)?;
+            read_mbo_csv_from(head.as_bytes(), csv_options)?
+                .messages
+                .into_iter()
+                .next()
+        }
+        DataFormat::Ndjson => {
+            let head = read_lines(open()?, 1, true)?;
+            read_mbo_ndjson_from(head.as_bytes())?
+                .messages
+                .into_iter()
+                .next()
+        }
+        #[cfg(feature = "polars")]
+        DataFormat::Parquet => ParquetBatches::open(path, 1)?
+            .next()
+            .transpose()?
+            .and_then(|report| report.messages.into_iter().next()),
+        #[cfg(feature = "itch")]
+        DataFormat::Itch => itch_messages(open()?).next().transpose()?,
+        #[cfg(not(feature = "polars"))]
+        DataFormat::Parquet => return Err(InputError::Unsupported(input)),
+        #[cfg(not(feature = "itch"))]
+        DataFormat::Itch => return Err(InputError::Unsupported(input)),
+    };
+    Ok(first.map(|message| message.event_time))
+}
+
+/// Reads the first `n` lines of `reader`, not counting blank lines if `skip_blank`.
+fn read_lines(mut reader: impl BufRead, n: usize, skip_blank: bool) -> io::Result<String> {
+    let mut head = String::new();
+    let mut lines = 0;
+    while lines < n {
+        let start = head.len();
+        if reader.read_line(&mut head)? == 0 {
+            break;
+        }
+        if !(skip_blank && head[start..].trim().is_empty()) {
+            lines += 1;
+        }
+    }
+    Ok(head)
+}
+
+/// Sorts `paths` by the event time of their first message, as found by
+/// `first_event_time` after detecting each file's format. The sort is stable, so files
+/// starting at the same time keep their order; files without messages come first.
+pub fn sort_by_first_event(
+    paths: Vec<PathBuf>,
+    csv_options: CsvOptions,
+) -> Result<Vec<PathBuf>, InputError> {
+    let mut keyed = paths
+        .into_iter()
+        .map(|path| {
+            let input = detect_format(&path)?;
+            Ok((first_event_time(&path, input, csv_options)?, path))
+        })
+        .collect::<Result<Vec<_>, InputError>>()?;
+    keyed.sort_by_key(|(first, _)| *first);
+    Ok(keyed.into_iter().map(|(_, path)| path).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::c_char;
+    use std::{env, process, slice};
+
+    use dbn::encode::{DynWriter, EncodeRecord, dbn::Encoder};
+    use dbn::{MboMsg, MetadataBuilder, SType, Schema};
+
+    use crate::orderbook::{MboProcessor, process_reader};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rainybook-{}-{name}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A DBN file adding order 1 at `ts_event`.
+    fn write_dbn(path: &Path, ts_event: u64) {
+        let metadata = MetadataBuilder::new()
+            .dataset("TEST".to_owned())
+            .schema(Some(Schema::Mbo))
+            .start(0)
+            .stype_in(Some(SType::InstrumentId))
+            .stype_out(SType::InstrumentId)
+            .build();
+        let record = MboMsg {
+            hd: dbn::RecordHeader::new::<MboMsg>(dbn::enums::rtype::MBO, 1, 7, ts_event),
+            order_id: 1,
+            price: 100,
+            size: 10,
+            flags: dbn::FlagSet::new(dbn::flags::LAST),
+            channel_id: 0,
+            action: b'A' as c_char,
+            side: b'B' as c_char,
+            ts_recv: ts_event,
+            ts_in_delta: 0,
+            sequence: 1,
+        };
+        let writer = DynWriter::new(File::create(path).unwrap(), dbn::Compression::None).unwrap();
+        let mut encoder = Encoder::new(writer, &metadata).unwrap();
+        encoder.encode_record(&record).unwrap();
+        encoder.flush().unwrap();
+    }
+
+    #[test]
+    fn test_sorted_by_first_event_not_name() {
+        let dir = temp_dir("sorted");
+        // "a" sorts first by name but modifies the order "b" adds
+        let later = dir.join("a.ndjson");
+        fs::write(
+            &later,
+            "\n{\"action\":\"M\",\"side\":\"B\",\"price\":101,\"order_id\":1,\"size\":5,\"instrument_id\":7,\"ts_event\":2000}\n",
+        )
+        .unwrap();
+        let earlier = dir.join("b.dbn");
+        write_dbn(&earlier, 1_000);
+
+        let paths = expand_paths(slice::from_ref(&dir), None).unwrap();
+        assert_eq!(paths, vec![later.clone(), earlier.clone()]);
+        let sorted = sort_by_first_event(paths, CsvOptions::new()).unwrap();
+        assert_eq!(sorted, vec![earlier.clone(), later.clone()]);
+
+        // Replaying in that order applies the Modify to the order the Add created
+        let mut proc = MboProcessor::new();
+        for path in &sorted {
+            let input = detect_format(path).unwrap();
+            let result = process_reader(File::open(path).unwrap(), input, &mut proc).unwrap();
+            assert!(result.summary.is_success());
+        }
+        assert_eq!(proc.book(7).unwrap().best_bid(), Some((101, 5)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_expand_paths_filters_directories() {
+        let dir = temp_dir("expand");
+        for name in ["day2.dbn", "day1.dbn", "notes.txt"] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+        fs::create_dir_all(dir.join("nested.dbn")).unwrap();
+        let explicit = PathBuf::from("explicit.csv");
+
+        let pattern = Pattern::new("*.dbn").unwrap();
+        let paths = expand_paths(&[dir.clone(), explicit.clone()], Some(&pattern)).unwrap();
+        assert_eq!(
+            paths,
+            vec![dir.join("day1.dbn"), dir.join("day2.dbn"), explicit]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_first_event_time_of_csv() {
+        let dir = temp_dir("first-csv");
+        let path = dir.join("mbo.csv");
+        fs::write(
+            &path,
+            "action,side,price,order_id,size,ts_event\nA,B,100,1,5,1500\nA,B,100,2,5,1400\n",
+        )
+        .unwrap();
+        let input = detect_format(&path).unwrap();
+        let first = first_event_time(&path, input, CsvOptions::new()).unwrap();
+        assert_eq!(first.unwrap().unix_timestamp_nanos(), 1_500);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,288 @@
+//! Executed volume by aggressor side, the input of order-flow imbalance signals.
+//!
+//! Every print the processor counts in `MboStats::traded_volume` (Trade messages and,
+//! under `CrossingPolicy::Match`, the executions of crossing Adds) is attributed to a
+//! buyer or a seller taking liquidity:
+//!
+//! 1. The side the print carries wins. A Trade's side is its aggressor's; a matched
+//!    Add is the aggressor.
+//! 2. A Trade without a side (dbn side `N`) is compared with the mid of the book it
+//!    printed into: above the mid it was a buy, below it a sell.
+//! 3. At the mid, or without a two-sided book, the tick rule compares it with the
+//!    instrument's previous print: an uptick is a buy, a downtick a sell and an
+//!    unchanged price repeats the previous print's side.
+//! 4. A print none of these decide, such as the first one at an unknown side and
+//!    without a quote, or one without a price, is counted as unclassified.
+//!
+//! Fills are the resting halves of the same executions and are not counted.
+
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::fmt;
+use std::iter::Sum;
+use std::ops::Add;
+
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+
+use crate::orderbook::Side;
+
+/// Buy- and sell-initiated executed volume and trade counts.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlowStats {
+    pub buy_volume: u64,
+    pub sell_volume: u64,
+    pub buy_trades: u64,
+    pub sell_trades: u64,
+    /// Trades no rule could attribute to a side.
+    pub unclassified: u64,
+}
+
+impl FlowStats {
+    /// Buy minus sell volume over their sum, from -1 (all sells) to 1 (all buys), or
+    /// `None` without classified volume.
+    pub fn imbalance(&self) -> Option<f64> {
+        let total = self.buy_volume + self.sell_volume;
+        (total > 0).then(|| (self.buy_volume as f64 - self.sell_volume as f64) / total as f64)
+    }
+
+    fn record(&mut self, aggressor: Option<Side>, size: u64) {
+        match aggressor {
+            Some(Side::Bid) => {
+                self.buy_volume += size;
+                self.buy_trades += 1;
+            }
+            Some(Side::Ask) => {
+                self.sell_volume += size;
+                self.sell_trades += 1;
+            }
+            None => self.unclassified += 1,
+        }
+    }
+}
+
+impl Add for FlowStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            buy_volume: self.buy_volume + other.buy_volume,
+            sell_volume: self.sell_volume + other.sell_volume,
+            buy_trades: self.buy_trades + other.buy_trades,
+            sell_trades: self.sell_trades + other.sell_trades,
+            unclassified: self.unclassified + other.unclassified,
+        }
+    }
+}
+
+impl Sum for FlowStats {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), Add::add)
+    }
+}
+
+impl fmt::Display for FlowStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Aggressor flow:")?;
+        writeln!(
+            f,
+            "  Buy volume:       {} in {} trades",
+            self.buy_volume, self.buy_trades
+        )?;
+        writeln!(
+            f,
+            "  Sell volume:      {} in {} trades",
+            self.sell_volume, self.sell_trades
+        )?;
+        write!(f, "  Unclassified:     {} trades", self.unclassified)
+    }
+}
+
+/// A print kept for the rolling window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct FlowPrint {
+    event_time: OffsetDateTime,
+    aggressor: Option<Side>,
+    size: u64,
+}
+
+/// The state of a `FlowTracker` before a message, restored by a rollback.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) struct FlowMark {
+    totals: FlowStats,
+    last_print: Option<(i64, Option<Side>)>,
+    recorded: u64,
+}
+
+/// Classifies the prints of one instrument and sums them, in total and, given a
+/// window, over the prints of the last `window` of event time.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct FlowTracker {
+    totals: FlowStats,
+    /// Price of the previous print and the side it was attributed to, for the tick rule.
+    last_print: Option<(i64, Option<Side>)>,
+    window: Option<Duration>,
+    /// Prints within `window` of the latest, oldest first.
+    recent: VecDeque<FlowPrint>,
+    /// Prints recorded into `recent`, so a rollback knows how many to take back.
+    recorded: u64,
+}
+
+impl FlowTracker {
+    pub fn new(window: Option<Duration>) -> Self {
+        Self {
+            window,
+            ..Self::default()
+        }
+    }
+
+    pub fn totals(&self) -> FlowStats {
+        self.totals
+    }
+
+    /// The prints of the window ending at `now`, excluding its start, or `None`
+    /// without a window.
+    pub fn recent(&self, now: OffsetDateTime) -> Option<FlowStats> {
+        let start = now - self.window?;
+        let mut stats = FlowStats::default();
+        self.recent
+            .iter()
+            .filter(|print| print.event_time > start && print.event_time <= now)
+            .for_each(|print| stats.record(print.aggressor, print.size));
+        Some(stats)
+    }
+
+    /// Attributes a print of `size` at `price` to a side by the rules of the module
+    /// documentation: `side` if the print carried one, else by `price` against `mid`,
+    /// the mid of the book before it, else by the tick rule.
+    pub fn record(
+        &mut self,
+        event_time: OffsetDateTime,
+        side: Option<Side>,
+        price: Option<i64>,
+        size: u64,
+        mid: Option<f64>,
+    ) {
+        let aggressor = side.or_else(|| {
+            let price = price?;
+            let by_quote = mid.and_then(|mid| match (price as f64).total_cmp(&mid) {
+                Ordering::Greater => Some(Side::Bid),
+                Ordering::Less => Some(Side::Ask),
+                Ordering::Equal => None,
+            });
+            by_quote.or_else(|| {
+                let (last_price, last_side) = self.last_print?;
+                match price.cmp(&last_price) {
+                    Ordering::Greater => Some(Side::Bid),
+                    Ordering::Less => Some(Side::Ask),
+                    Ordering::Equal => last_side,
+                }
+            })
+        });
+        if let Some(price) = price {
+            self.last_print = Some((price, aggressor));
+        }
+        self.totals.record(aggressor, size);
+        if let Some(window) = self.window {
+            while self
+                .recent
+                .front()
+                .is_some_and(|print| print.event_time <= event_time - window)
+            {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(FlowPrint {
+                event_time,
+                aggressor,
+                size,
+            });
+            self.recorded += 1;
+        }
+    }
+
+    pub fn mark(&self) -> FlowMark {
+        FlowMark {
+            totals: self.totals,
+            last_print: self.last_print,
+            recorded: self.recorded,
+        }
+    }
+
+    /// Returns to the state of `mark`. Prints the window dropped since as too old are
+    /// not brought back.
+    pub fn restore(&mut self, mark: FlowMark) {
+        for _ in mark.recorded..self.recorded {
+            self.recent.pop_back();
+        }
+        self.totals = mark.totals;
+        self.last_print = mark.last_print;
+        self.recorded = mark.recorded;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: i64) -> OffsetDateTime {
+        OffsetDateTime::UNIX_EPOCH + Duration::seconds(seconds)
+    }
+
+    #[test]
+    fn test_scripted_classification() {
+        let mut flow = FlowTracker::new(None);
+        // Neither a side, a quote nor a previous print
+        flow.record(at(0), None, Some(100), 1, None);
+        // Explicit sell, though above the mid and an uptick
+        flow.record(at(1), Some(Side::Ask), Some(102), 2, Some(101.0));
+        // Above the mid, a buy, though a downtick
+        flow.record(at(2), None, Some(101), 3, Some(100.5));
+        // At the mid: the tick rule, a downtick from 101
+        flow.record(at(3), None, Some(100), 4, Some(100.0));
+        // No quote and an unchanged price: the previous print's side
+        flow.record(at(4), None, Some(100), 5, None);
+        // An uptick with the book one-sided
+        flow.record(at(5), None, Some(103), 6, None);
+        // Explicit buy, though below the mid
+        flow.record(at(6), Some(Side::Bid), Some(99), 7, Some(101.0));
+        // Below the mid, a sell, though an uptick
+        flow.record(at(7), None, Some(100), 8, Some(100.5));
+        // Without a price or a side
+        flow.record(at(8), None, None, 9, Some(100.0));
+
+        assert_eq!(
+            flow.totals(),
+            FlowStats {
+                buy_volume: 3 + 6 + 7,
+                sell_volume: 2 + 4 + 5 + 8,
+                buy_trades: 3,
+                sell_trades: 4,
+                unclassified: 2,
+            }
+        );
+        assert_eq!(flow.totals().imbalance(), Some(-3.0 / 35.0));
+    }
+
+    #[test]
+    fn test_window_and_restore() {
+        let mut flow = FlowTracker::new(Some(Duration::seconds(10)));
+        flow.record(at(0), Some(Side::Bid), Some(100), 1, None);
+        flow.record(at(5), Some(Side::Ask), Some(100), 2, None);
+        let mark = flow.mark();
+        flow.record(at(12), Some(Side::Bid), Some(100), 4, None);
+
+        // The print at 0 s is out of the window (2 s, 12 s]
+        let recent = flow.recent(at(12)).unwrap();
+        assert_eq!((recent.buy_volume, recent.sell_volume), (4, 2));
+        assert_eq!(flow.recent(at(20)).unwrap().sell_volume, 0);
+        assert_eq!(FlowTracker::new(None).recent(at(0)), None);
+
+        flow.restore(mark);
+        assert_eq!(
+            (flow.totals().buy_volume, flow.totals().sell_volume),
+            (1, 2)
+        );
+        assert_eq!(flow.recent(at(5)).unwrap().buy_volume, 0);
+        assert_eq!(flow.recent(at(5)).unwrap().sell_volume, 2);
+    }
+}
@@ -0,0 +1,462 @@
+//! Input format detection from file names and, failing that, file contents.
+//!
+//! A bare `extension()` check is not enough: `foo.dbn.zst` has extension `zst`, as does
+//! a zstd-compressed parquet file. `detect_format` matches the full multi-part suffix
+//! first and only sniffs magic bytes, looking inside a zstd or gzip stream if needed,
+//! when the suffix is ambiguous or missing.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use flate2::read::MultiGzDecoder;
+use thiserror::Error;
+
+use crate::orderbook::PriceScale;
+
+const DBN_MAGIC: &[u8] = b"DBN";
+const PARQUET_MAGIC: &[u8] = b"PAR1";
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// Number of leading (decompressed) bytes inspected when sniffing.
+const SNIFF_LEN: usize = 64;
+
+#[derive(Debug, Error)]
+pub enum FormatError {
+    #[error("Failed to read {path}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+
+    #[error("Could not determine the format of {0}")]
+    Unrecognized(PathBuf),
+
+    #[error(
+        "Unknown data format '{0}', expected dbn, csv, ndjson, parquet or itch, optionally \
+         followed by -zst or -gz, e.g. dbn-zst"
+    )]
+    UnknownName(String),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DataFormat {
+    Dbn,
+    Csv,
+    Ndjson,
+    Parquet,
+    /// NASDAQ TotalView-ITCH 5.0
+    Itch,
+}
+
+impl fmt::Display for DataFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DataFormat::Dbn => "DBN",
+            DataFormat::Csv => "CSV",
+            DataFormat::Ndjson => "NDJSON",
+            DataFormat::Parquet => "parquet",
+            DataFormat::Itch => "ITCH",
+        })
+    }
+}
+
+impl DataFormat {
+    /// The scale of the prices this format carries, if it fixes one. DBN metadata has no
+    /// price scale field, as every DBN version stores prices in units of 1e-9, and ITCH
+    /// prices are converted to the same units; CSV, NDJSON and parquet prices are
+    /// whatever integers the file holds.
+    pub fn price_scale(&self) -> Option<PriceScale> {
+        match self {
+            DataFormat::Dbn | DataFormat::Itch => Some(PriceScale::DBN),
+            DataFormat::Csv | DataFormat::Ndjson | DataFormat::Parquet => None,
+        }
+    }
+}
+
+impl FromStr for DataFormat {
+    type Err = FormatError;
+
+    /// Parses a format name as given on the command line, e.g. `dbn` or `jsonl`.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "dbn" => Ok(DataFormat::Dbn),
+            "csv" => Ok(DataFormat::Csv),
+            "ndjson" | "jsonl" => Ok(DataFormat::Ndjson),
+            "parquet" => Ok(DataFormat::Parquet),
+            "itch" => Ok(DataFormat::Itch),
+            _ => Err(FormatError::UnknownName(name.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+/// A data format and the compression wrapped around it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct InputFormat {
+    pub format: DataFormat,
+    pub compression: Compression,
+}
+
+impl InputFormat {
+    pub fn new(format: DataFormat, compression: Compression) -> Self {
+        Self {
+            format,
+            compression,
+        }
+    }
+}
+
+/// Parses a format name as given on the command line, optionally followed by a
+/// compression, e.g. `dbn`, `dbn-zst`, `csv.gz` or `jsonl`. Without one the compression
+/// is `Compression::None`, which `resolve_format` and `open_input` take as unknown.
+impl FromStr for InputFormat {
+    type Err = FormatError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let unknown = || FormatError::UnknownName(name.to_owned());
+        let (format, compression) = match name.rsplit_once(['-', '.']) {
+            Some((format, compression)) => {
+                let compression = match compression.to_ascii_lowercase().as_str() {
+                    "zst" | "zstd" => Compression::Zstd,
+                    "gz" | "gzip" => Compression::Gzip,
+                    _ => return Err(unknown()),
+                };
+                (format, compression)
+            }
+            None => (name, Compression::None),
+        };
+        let format = format.parse().map_err(|_| unknown())?;
+        Ok(Self::new(format, compression))
+    }
+}
+
+impl fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.compression {
+            Compression::None => write!(f, "{}", self.format),
+            Compression::Gzip => write!(f, "gzip-compressed {}", self.format),
+            Compression::Zstd => write!(f, "zstd-compressed {}", self.format),
+        }
+    }
+}
+
+/// Suffixes that identify a format without looking at the file.
+const SUFFIXES: &[(&str, DataFormat, Compression)] = &[
+    (".dbn.zst", DataFormat::Dbn, Compression::Zstd),
+    (".dbn", DataFormat::Dbn, Compression::None),
+    (".csv.gz", DataFormat::Csv, Compression::Gzip),
+    (".csv", DataFormat::Csv, Compression::None),
+    (".ndjson", DataFormat::Ndjson, Compression::None),
+    (".jsonl", DataFormat::Ndjson, Compression::None),
+    (".parquet", DataFormat::Parquet, Compression::None),
+    (".itch", DataFormat::Itch, Compression::None),
+    (".nq", DataFormat::Itch, Compression::None),
+];
+
+/// Determines the format of the file at `path`.
+///
+/// Known suffixes (`.dbn`, `.dbn.zst`, `.csv`, `.csv.gz`, `.ndjson`, `.jsonl`,
+/// `.parquet`, `.itch`, `.nq`) are trusted without opening the file. Otherwise the
+/// leading bytes are sniffed: zstd and gzip streams are decompressed, then DBN and
+/// parquet are recognized by their magic bytes and NDJSON by a leading `{`. CSV and
+/// ITCH have no signature and must use one of their suffixes.
+pub fn detect_format(path: impl AsRef<Path>) -> Result<InputFormat, FormatError> {
+    let path = path.as_ref();
+    if let Some(format) = format_from_suffix(path) {
+        return Ok(format);
+    }
+    sniff(path)
+        .map_err(|source| FormatError::Io {
+            path: path.to_owned(),
+            source,
+        })?
+        .ok_or_else(|| FormatError::Unrecognized(path.to_owned()))
+}
+
+/// The format named by the suffix of `path`, one of those `detect_format` trusts, without
+/// opening the file; e.g. to choose how to write an output file.
+pub fn format_from_suffix(path: impl AsRef<Path>) -> Option<InputFormat> {
+    let path = path.as_ref();
+    let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+    SUFFIXES
+        .iter()
+        .find(|(suffix, ..)| name.ends_with(suffix))
+        .map(|&(_, format, compression)| InputFormat::new(format, compression))
+}
+
+/// The format to read the file at `path` as: `format` if given, otherwise the one
+/// `detect_format` finds.
+///
+/// A given format without compression is taken at its word about the data, not about
+/// how it is packed: its compression is sniffed from the file, so `dbn` also reads a
+/// zstd-compressed DBN file.
+pub fn resolve_format(
+    path: impl AsRef<Path>,
+    format: Option<InputFormat>,
+) -> Result<InputFormat, FormatError> {
+    let path = path.as_ref();
+    match format {
+        Some(format) if format.compression == Compression::None => {
+            let compression = File::open(path)
+                .and_then(|file| sniff_compression(&mut BufReader::new(file)))
+                .map_err(|source| FormatError::Io {
+                    path: path.to_owned(),
+                    source,
+                })?;
+            Ok(InputFormat::new(format.format, compression))
+        }
+        Some(format) => Ok(format),
+        None => detect_format(path),
+    }
+}
+
+fn sniff(path: &Path) -> io::Result<Option<InputFormat>> {
+    let head = read_head(BufReader::new(File::open(path)?))?;
+    let (compression, content) = if head.starts_with(ZSTD_MAGIC) {
+        let decoder = zstd::stream::read::Decoder::new(BufReader::new(File::open(path)?))?;
+        (Compression::Zstd, read_head(decoder)?)
+    } else if head.starts_with(GZIP_MAGIC) {
+        let decoder = MultiGzDecoder::new(BufReader::new(File::open(path)?));
+        (Compression::Gzip, read_head(decoder)?)
+    } else {
+        (Compression::None, head)
+    };
+    Ok(sniff_content(&content).map(|format| InputFormat::new(format, compression)))
+}
+
+pub(crate) fn sniff_content(head: &[u8]) -> Option<DataFormat> {
+    if head.starts_with(DBN_MAGIC) {
+        Some(DataFormat::Dbn)
+    } else if head.starts_with(PARQUET_MAGIC) {
+        Some(DataFormat::Parquet)
+    } else if head.trim_ascii_start().starts_with(b"{") {
+        Some(DataFormat::Ndjson)
+    } else {
+        None
+    }
+}
+
+/// Determines the compression of a stream from its leading bytes, which are left
+/// unconsumed so the stream can still be read from the start.
+pub fn sniff_compression(reader: &mut impl BufRead) -> io::Result<Compression> {
+    let head = reader.fill_buf()?;
+    Ok(if head.starts_with(ZSTD_MAGIC) {
+        Compression::Zstd
+    } else if head.starts_with(GZIP_MAGIC) {
+        Compression::Gzip
+    } else {
+        Compression::None
+    })
+}
+
+/// Wraps `reader` in a decoder for `compression`.
+pub fn decompress<'a>(
+    reader: impl BufRead + 'a,
+    compression: Compression,
+) -> io::Result<Box<dyn BufRead + 'a>> {
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+        Compression::Zstd => Box::new(BufReader::new(zstd::stream::read::Decoder::with_buffer(
+            reader,
+        )?)),
+    })
+}
+
+/// Reads up to `SNIFF_LEN` bytes; a truncated compressed stream yields what was read.
+fn read_head(reader: impl Read) -> io::Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    match reader.take(SNIFF_LEN as u64).read_to_end(&mut head) {
+        Ok(_) => Ok(head),
+        Err(_) if !head.is_empty() => Ok(head),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::{env, fs, process};
+
+    use flate2::write::GzEncoder;
+
+    fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+        let path = env::temp_dir().join(format!("rainybook-{}-{name}", process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn detect_contents(name: &str, contents: &[u8]) -> Result<InputFormat, FormatError> {
+        let path = temp_file(name, contents);
+        let format = detect_format(&path);
+        fs::remove_file(&path).unwrap();
+        format
+    }
+
+    #[test]
+    fn test_suffixes() {
+        let detect = |name: &str| detect_format(name).unwrap();
+        assert_eq!(
+            detect("data/glbx.dbn.zst"),
+            InputFormat::new(DataFormat::Dbn, Compression::Zstd)
+        );
+        assert_eq!(
+            detect("GLBX.DBN"),
+            InputFormat::new(DataFormat::Dbn, Compression::None)
+        );
+        assert_eq!(
+            detect("capture.csv.gz"),
+            InputFormat::new(DataFormat::Csv, Compression::Gzip)
+        );
+        assert_eq!(
+            detect("capture.jsonl"),
+            InputFormat::new(DataFormat::Ndjson, Compression::None)
+        );
+        assert_eq!(
+            detect("mbo.parquet"),
+            InputFormat::new(DataFormat::Parquet, Compression::None)
+        );
+        assert_eq!(
+            detect("20190130.itch"),
+            InputFormat::new(DataFormat::Itch, Compression::None)
+        );
+        assert_eq!(
+            detect("totalview.NQ"),
+            InputFormat::new(DataFormat::Itch, Compression::None)
+        );
+    }
+
+    #[test]
+    fn test_zstd_parquet_is_not_dbn() {
+        let compressed = zstd::encode_all(&b"PAR1\x15\x04\x15\x10"[..], 0).unwrap();
+        assert_eq!(
+            detect_contents("mbo.zst", &compressed).unwrap(),
+            InputFormat::new(DataFormat::Parquet, Compression::Zstd)
+        );
+
+        let compressed = zstd::encode_all(&b"DBN\x03\x00\x00\x00\x00"[..], 0).unwrap();
+        assert_eq!(
+            detect_contents("mbo-dbn.zst", &compressed).unwrap(),
+            InputFormat::new(DataFormat::Dbn, Compression::Zstd)
+        );
+    }
+
+    #[test]
+    fn test_extensionless_sniffed() {
+        assert_eq!(
+            detect_contents("extensionless", b"DBN\x03\x00\x00\x00\x00").unwrap(),
+            InputFormat::new(DataFormat::Dbn, Compression::None)
+        );
+        assert_eq!(
+            detect_contents("extensionless-json", b"\n  {\"action\":\"A\"}").unwrap(),
+            InputFormat::new(DataFormat::Ndjson, Compression::None)
+        );
+
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"{\"action\":\"A\"}\n").unwrap();
+        assert_eq!(
+            detect_contents("capture.gz", &encoder.finish().unwrap()).unwrap(),
+            InputFormat::new(DataFormat::Ndjson, Compression::Gzip)
+        );
+    }
+
+    #[test]
+    fn test_stream_compression() {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"action,side\n").unwrap();
+        let gzip = encoder.finish().unwrap();
+        let zstd = zstd::encode_all(&b"action,side\n"[..], 0).unwrap();
+        for (bytes, expected) in [
+            (&b"action,side\n"[..], Compression::None),
+            (&gzip[..], Compression::Gzip),
+            (&zstd[..], Compression::Zstd),
+        ] {
+            let mut reader = BufReader::new(bytes);
+            let compression = sniff_compression(&mut reader).unwrap();
+            assert_eq!(compression, expected);
+            let mut text = String::new();
+            decompress(reader, compression)
+                .unwrap()
+                .read_to_string(&mut text)
+                .unwrap();
+            assert_eq!(text, "action,side\n");
+        }
+    }
+
+    #[test]
+    fn test_format_names() {
+        assert_eq!("DBN".parse::<DataFormat>().unwrap(), DataFormat::Dbn);
+        assert_eq!("jsonl".parse::<DataFormat>().unwrap(), DataFormat::Ndjson);
+        assert!(matches!(
+            "xlsx".parse::<DataFormat>(),
+            Err(FormatError::UnknownName(_))
+        ));
+    }
+
+    #[test]
+    fn test_input_format_names() {
+        let parse = |name: &str| name.parse::<InputFormat>().unwrap();
+        assert_eq!(
+            parse("dbn"),
+            InputFormat::new(DataFormat::Dbn, Compression::None)
+        );
+        assert_eq!(
+            parse("dbn-zst"),
+            InputFormat::new(DataFormat::Dbn, Compression::Zstd)
+        );
+        assert_eq!(
+            parse("CSV.gz"),
+            InputFormat::new(DataFormat::Csv, Compression::Gzip)
+        );
+        for invalid in ["dbn-bz2", "xlsx", "zst", "-zst"] {
+            assert!(
+                matches!(
+                    invalid.parse::<InputFormat>(),
+                    Err(FormatError::UnknownName(_))
+                ),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_resolve_given_format() {
+        let compressed = zstd::encode_all(&b"DBN\x03\x00\x00\x00\x00"[..], 0).unwrap();
+        let path = temp_file("blob", &compressed);
+        let resolve = |format: &str| resolve_format(&path, Some(format.parse().unwrap()));
+        assert_eq!(
+            resolve("dbn").unwrap(),
+            InputFormat::new(DataFormat::Dbn, Compression::Zstd)
+        );
+        // A given format wins over the content
+        assert_eq!(
+            resolve("csv-gz").unwrap(),
+            InputFormat::new(DataFormat::Csv, Compression::Gzip)
+        );
+        assert_eq!(
+            resolve_format(&path, None).unwrap(),
+            InputFormat::new(DataFormat::Dbn, Compression::Zstd)
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_unrecognized() {
+        assert!(matches!(
+            detect_contents("notes.txt", b"action,side,price"),
+            Err(FormatError::Unrecognized(_))
+        ));
+        assert!(matches!(
+            detect_format("/nonexistent/rainybook-input"),
+            Err(FormatError::Io { .. })
+        ));
+    }
+}
@@ -0,0 +1,554 @@
+//! The book operations `MboProcessor` drives, so its message routing can run over any
+//! book.
+//!
+//! `MboProcessor<O, B>` keeps one `B: BookHandler` per instrument and does everything
+//! else itself: action dispatch, validation, the error policy, statistics, snapshot
+//! and Clear handling, the undo journal and observer callbacks. `B` defaults to
+//! `OrderBook`; `DenseOrderBook` is the other book of the crate implementing it.
+//!
+//! The first group of methods is what every book must offer. The rest have defaults
+//! for books without the corresponding feature of `OrderBook`: without an override a
+//! book does not match crossing orders, never purges expired orders, refuses no Add,
+//! counts no anomalies and ignores the processor's event journal and depth limit
+//! settings.
+
+use std::ops::Bound;
+
+use crate::orderbook::audit::EventRetention;
+use crate::orderbook::book::{self, OrderBook};
+use crate::orderbook::{
+    AddOrderInfo, Anomalies, Bbo, DenseOrderBook, DepthLimitPolicy, MarketByPrice, MatchOutcome,
+    ModifyOrderInfo, ModifyPriorityPolicy, Order, OrderBookError, RemoveOrderInfo, SelfMatchPolicy,
+    Side,
+};
+
+/// A book `MboProcessor` can apply messages to.
+///
+/// New instruments get a clone of the processor's template book, see
+/// `MboProcessor::with_observer_and_book`.
+pub trait BookHandler: Clone {
+    /// Adds an order, replacing any order with the same id.
+    fn add_order(&mut self, order: Order) -> AddOrderInfo;
+
+    /// Removes an order, or returns `None` if it is not in the book.
+    fn remove_order(&mut self, order_id: u64) -> Option<RemoveOrderInfo>;
+
+    /// Changes an order's price and size, keeping its queue position as `policy`
+    /// says, or returns `None` if it is not in the book.
+    fn modify_order(
+        &mut self,
+        order: Order,
+        policy: ModifyPriorityPolicy,
+    ) -> Option<ModifyOrderInfo>;
+
+    /// Removes every order and returns them as a book, which the processor passes to
+    /// `restore` if the clear is rolled back.
+    fn clear(&mut self) -> Self;
+
+    /// Removes every order on `side`, best level first and in queue order within a
+    /// level, returning what each removal left as `remove_order` does.
+    fn clear_side(&mut self, side: Side) -> Vec<RemoveOrderInfo>;
+
+    fn get_order(&self, order_id: u64) -> Option<&Order>;
+
+    /// Price and total quantity of the best bid level.
+    fn best_bid(&self) -> Option<(i64, u64)>;
+
+    /// Price and total quantity of the best ask level.
+    fn best_ask(&self) -> Option<(i64, u64)>;
+
+    /// Every level of the book.
+    fn snapshot(&self) -> MarketByPrice;
+
+    /// Deterministic hash of the resting orders, as `OrderBook::state_hash`.
+    fn state_hash(&self) -> u64;
+
+    /// Told of a Fill of `size` against a resting order. The processor leaves the
+    /// order as it is, since feeds send its Modify or Cancel next, and so does the
+    /// default. Not journaled.
+    fn fill_order(&mut self, _order_id: u64, _size: u32) {}
+
+    fn bbo(&self) -> Bbo {
+        Bbo {
+            bid: self.best_bid(),
+            ask: self.best_ask(),
+        }
+    }
+
+    /// Midpoint of the best bid and ask, or `None` if either side is empty.
+    fn mid(&self) -> Option<f64> {
+        book::mid(self.best_bid(), self.best_ask())
+    }
+
+    /// Price and total quantity of the levels on `side` with prices in `range`, for
+    /// `TradeQuality`. The default reads them from `snapshot`.
+    fn levels_in(&self, side: Side, range: (Bound<i64>, Bound<i64>)) -> Vec<(i64, u64)> {
+        let snapshot = self.snapshot();
+        let levels = match side {
+            Side::Bid => snapshot.bids,
+            Side::Ask => snapshot.asks,
+        };
+        levels
+            .range(range)
+            .map(|(&price, level)| (price, level.total_quantity))
+            .collect()
+    }
+
+    /// Trades `order` against the resting orders it crosses, under
+    /// `CrossingPolicy::Match`. The default matches nothing, so the order rests.
+    fn match_order(&mut self, _order: &Order, _policy: SelfMatchPolicy) -> MatchOutcome {
+        MatchOutcome::default()
+    }
+
+    /// Fails if the book refuses `order` before it is matched, as a depth-limited
+    /// `OrderBook` under `DepthLimitPolicy::Reject` does. The default accepts all.
+    fn check_add(&self, _order: &Order) -> Result<(), OrderBookError> {
+        Ok(())
+    }
+
+    /// Removes the orders whose `Order::expires_at` is at or before `now_ns`. The
+    /// default keeps every order.
+    fn purge_expired(&mut self, _now_ns: u64) -> Vec<RemoveOrderInfo> {
+        Vec::new()
+    }
+
+    /// Puts back `old`, the book `clear` returned.
+    fn restore(&mut self, old: Self) {
+        *self = old;
+    }
+
+    /// Counters of the irregular operations the book ignored. The default counts none.
+    fn anomalies(&self) -> Anomalies {
+        Anomalies::default()
+    }
+
+    /// Sets the anomaly counters back, on rollback.
+    fn restore_anomalies(&mut self, _anomalies: Anomalies) {}
+
+    /// Applies `MboProcessor::with_book_events` and `with_book_depth_limit` to the book
+    /// of a new instrument. The default ignores them.
+    fn configure(
+        self,
+        _events: EventRetention,
+        _depth_limit: Option<(usize, DepthLimitPolicy)>,
+    ) -> Self {
+        self
+    }
+}
+
+impl BookHandler for OrderBook {
+    fn add_order(&mut self, order: Order) -> AddOrderInfo {
+        OrderBook::add_order(self, order)
+    }
+
+    fn remove_order(&mut self, order_id: u64) -> Option<RemoveOrderInfo> {
+        OrderBook::remove_order(self, order_id)
+    }
+
+    fn modify_order(
+        &mut self,
+        order: Order,
+        policy: ModifyPriorityPolicy,
+    ) -> Option<ModifyOrderInfo> {
+        self.modify_order_with_policy(order, policy)
+    }
+
+    fn clear(&mut self) -> Self {
+        self.take_orders()
+    }
+
+    fn clear_side(&mut self, side: Side) -> Vec<RemoveOrderInfo> {
+        OrderBook::clear_side(self, side)
+    }
+
+    fn get_order(&self, order_id: u64) -> Option<&Order> {
+        OrderBook::get_order(self, order_id)
+    }
+
+    fn best_bid(&self) -> Option<(i64, u64)> {
+        OrderBook::best_bid(self)
+    }
+
+    fn best_ask(&self) -> Option<(i64, u64)> {
+        OrderBook::best_ask(self)
+    }
+
+    fn snapshot(&self) -> MarketByPrice {
+        MarketByPrice::from(self)
+    }
+
+    fn state_hash(&self) -> u64 {
+        OrderBook::state_hash(self)
+    }
+
+    fn bbo(&self) -> Bbo {
+        OrderBook::bbo(self)
+    }
+
+    fn levels_in(&self, side: Side, range: (Bound<i64>, Bound<i64>)) -> Vec<(i64, u64)> {
+        self.levels(side)
+            .range(range)
+            .map(|(&price, level)| (price, level.total_qty()))
+            .collect()
+    }
+
+    fn match_order(&mut self, order: &Order, policy: SelfMatchPolicy) -> MatchOutcome {
+        self.match_order_with_policy(order, policy)
+    }
+
+    fn check_add(&self, order: &Order) -> Result<(), OrderBookError> {
+        match self.depth_limit_policy() {
+            DepthLimitPolicy::Reject => self.check_depth_limit(order),
+            _ => Ok(()),
+        }
+    }
+
+    fn purge_expired(&mut self, now_ns: u64) -> Vec<RemoveOrderInfo> {
+        self.purge_expired_infos(now_ns)
+    }
+
+    fn restore(&mut self, old: Self) {
+        self.restore_orders(old);
+    }
+
+    fn anomalies(&self) -> Anomalies {
+        OrderBook::anomalies(self)
+    }
+
+    fn restore_anomalies(&mut self, anomalies: Anomalies) {
+        OrderBook::restore_anomalies(self, anomalies);
+    }
+
+    fn configure(
+        self,
+        events: EventRetention,
+        depth_limit: Option<(usize, DepthLimitPolicy)>,
+    ) -> Self {
+        let book = self.with_events(events);
+        match depth_limit {
+            Some((max_levels, policy)) => book
+                .with_max_levels_per_side(max_levels)
+                .with_depth_limit_policy(policy),
+            None => book,
+        }
+    }
+}
+
+impl BookHandler for DenseOrderBook {
+    fn add_order(&mut self, order: Order) -> AddOrderInfo {
+        DenseOrderBook::add_order(self, order)
+    }
+
+    fn remove_order(&mut self, order_id: u64) -> Option<RemoveOrderInfo> {
+        DenseOrderBook::remove_order(self, order_id)
+    }
+
+    fn modify_order(
+        &mut self,
+        order: Order,
+        policy: ModifyPriorityPolicy,
+    ) -> Option<ModifyOrderInfo> {
+        self.modify_order_with_policy(order, policy)
+    }
+
+    fn clear(&mut self) -> Self {
+        self.take_orders()
+    }
+
+    fn clear_side(&mut self, side: Side) -> Vec<RemoveOrderInfo> {
+        DenseOrderBook::clear_side(self, side)
+    }
+
+    fn get_order(&self, order_id: u64) -> Option<&Order> {
+        DenseOrderBook::get_order(self, order_id)
+    }
+
+    fn best_bid(&self) -> Option<(i64, u64)> {
+        DenseOrderBook::best_bid(self)
+    }
+
+    fn best_ask(&self) -> Option<(i64, u64)> {
+        DenseOrderBook::best_ask(self)
+    }
+
+    fn snapshot(&self) -> MarketByPrice {
+        MarketByPrice::from(self)
+    }
+
+    fn state_hash(&self) -> u64 {
+        DenseOrderBook::state_hash(self)
+    }
+
+    fn match_order(&mut self, order: &Order, policy: SelfMatchPolicy) -> MatchOutcome {
+        self.match_order_with_policy(order, policy)
+    }
+
+    fn check_add(&self, order: &Order) -> Result<(), OrderBookError> {
+        match self.depth_limit_policy() {
+            DepthLimitPolicy::Reject => self.check_depth_limit(order),
+            _ => Ok(()),
+        }
+    }
+
+    fn purge_expired(&mut self, now_ns: u64) -> Vec<RemoveOrderInfo> {
+        self.purge_expired_infos(now_ns)
+    }
+
+    fn anomalies(&self) -> Anomalies {
+        DenseOrderBook::anomalies(self)
+    }
+
+    fn restore_anomalies(&mut self, anomalies: Anomalies) {
+        DenseOrderBook::restore_anomalies(self, anomalies);
+    }
+
+    /// The dense book keeps no audit journal, so `events` is not used.
+    fn configure(
+        self,
+        _events: EventRetention,
+        depth_limit: Option<(usize, DepthLimitPolicy)>,
+    ) -> Self {
+        match depth_limit {
+            Some((max_levels, policy)) => self
+                .with_max_levels_per_side(max_levels)
+                .with_depth_limit_policy(policy),
+            None => self,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use time::{Duration, OffsetDateTime};
+
+    use super::*;
+    use crate::generators::OrderGenerator;
+    use crate::orderbook::{
+        Action, CrossingPolicy, ErrorPolicy, MarketByOrderMessage, MboProcessor,
+    };
+
+    /// A book call, as recorded by `MockBook`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Call {
+        Add(u64),
+        Remove(u64),
+        Modify(u64, u64),
+        Fill(u64, u32),
+        Clear,
+        ClearSide(Side),
+        GetOrder(u64),
+        BestBid,
+        BestAsk,
+        Snapshot,
+    }
+
+    /// An `OrderBook` that records every call the processor makes to it. Only the
+    /// required methods and `fill_order` are recorded; the other defaults call them.
+    #[derive(Debug, Default, Clone)]
+    struct MockBook {
+        book: OrderBook,
+        calls: RefCell<Vec<Call>>,
+    }
+
+    impl MockBook {
+        fn record(&self, call: Call) {
+            self.calls.borrow_mut().push(call);
+        }
+    }
+
+    impl BookHandler for MockBook {
+        fn add_order(&mut self, order: Order) -> AddOrderInfo {
+            self.record(Call::Add(order.order_id));
+            self.book.add_order(order)
+        }
+
+        fn remove_order(&mut self, order_id: u64) -> Option<RemoveOrderInfo> {
+            self.record(Call::Remove(order_id));
+            self.book.remove_order(order_id)
+        }
+
+        fn modify_order(
+            &mut self,
+            order: Order,
+            policy: ModifyPriorityPolicy,
+        ) -> Option<ModifyOrderInfo> {
+            self.record(Call::Modify(order.order_id, order.size));
+            BookHandler::modify_order(&mut self.book, order, policy)
+        }
+
+        fn clear(&mut self) -> Self {
+            self.record(Call::Clear);
+            Self {
+                book: self.book.take_orders(),
+                calls: RefCell::default(),
+            }
+        }
+
+        fn clear_side(&mut self, side: Side) -> Vec<RemoveOrderInfo> {
+            self.record(Call::ClearSide(side));
+            self.book.clear_side(side)
+        }
+
+        fn get_order(&self, order_id: u64) -> Option<&Order> {
+            self.record(Call::GetOrder(order_id));
+            self.book.get_order(order_id)
+        }
+
+        fn best_bid(&self) -> Option<(i64, u64)> {
+            self.record(Call::BestBid);
+            self.book.best_bid()
+        }
+
+        fn best_ask(&self) -> Option<(i64, u64)> {
+            self.record(Call::BestAsk);
+            self.book.best_ask()
+        }
+
+        fn snapshot(&self) -> MarketByPrice {
+            self.record(Call::Snapshot);
+            MarketByPrice::from(&self.book)
+        }
+
+        fn state_hash(&self) -> u64 {
+            self.book.state_hash()
+        }
+
+        fn fill_order(&mut self, order_id: u64, size: u32) {
+            self.record(Call::Fill(order_id, size));
+        }
+    }
+
+    fn message(
+        action: Action,
+        order_id: u64,
+        side: Option<Side>,
+        price: i64,
+        size: u32,
+    ) -> MarketByOrderMessage {
+        let event_time = OffsetDateTime::UNIX_EPOCH + Duration::seconds(order_id as i64);
+        MarketByOrderMessage {
+            no_side: side.is_none(),
+            ..MarketByOrderMessage::test(action, side.unwrap_or(Side::Bid), price, order_id, size)
+                .at(event_time)
+        }
+    }
+
+    #[test]
+    fn test_processor_book_calls() {
+        let mut processor = MboProcessor::with_observer_and_book((), MockBook::default())
+            .with_error_policy(ErrorPolicy::Collect);
+        let messages = [
+            message(Action::Add, 1, Some(Side::Bid), 100, 10),
+            message(Action::Add, 2, Some(Side::Ask), 102, 5),
+            message(Action::Modify, 1, Some(Side::Bid), 100, 8),
+            // A Trade without a side asks for the mid to classify its aggressor
+            message(Action::Trade, 0, None, 102, 3),
+            // A Fill without an owner looks up its order's
+            message(Action::Fill, 2, Some(Side::Ask), 102, 3),
+            message(Action::Cancel, 2, Some(Side::Ask), 102, 2),
+            // Unknown orders still reach the book, which decides they are unknown
+            message(Action::Cancel, 9, Some(Side::Ask), 102, 1),
+            message(Action::Modify, 9, Some(Side::Ask), 103, 1),
+            // An invalid message never does
+            message(Action::Add, 3, Some(Side::Bid), 100, 0),
+            // A Clear with a side only clears that side
+            message(Action::Clear, 0, Some(Side::Ask), 0, 0),
+            message(Action::Clear, 0, None, 0, 0),
+        ];
+        let summary = processor.process_messages(messages);
+        assert_eq!(summary.processed, 9);
+        assert_eq!(
+            summary.failures.iter().map(|f| f.index).collect::<Vec<_>>(),
+            vec![7, 8]
+        );
+        assert_eq!(processor.best_bid(), None);
+        processor.mbp(1).unwrap();
+
+        assert_eq!(
+            processor.book(1).unwrap().calls.take(),
+            vec![
+                Call::Add(1),
+                Call::Add(2),
+                Call::Modify(1, 8),
+                Call::BestBid,
+                Call::BestAsk,
+                Call::GetOrder(2),
+                Call::Fill(2, 3),
+                Call::Remove(2),
+                Call::Remove(9),
+                Call::Modify(9, 1),
+                Call::ClearSide(Side::Ask),
+                Call::Clear,
+                Call::BestBid,
+                Call::Snapshot,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dense_processor_matches_order_book() {
+        let messages = OrderGenerator::default_seeded(3).make_mbo_messages(20_000);
+        for policy in [CrossingPolicy::Rest, CrossingPolicy::Match] {
+            let mut processor = MboProcessor::new().with_crossing_policy(policy);
+            let dense_book = DenseOrderBook::new(9_900, 10_100, 1).unwrap();
+            let mut dense =
+                MboProcessor::with_observer_and_book((), dense_book).with_crossing_policy(policy);
+            let summary = processor.process_messages(&messages);
+            let dense_summary = dense.process_messages(&messages);
+            assert_eq!(dense_summary.processed, summary.processed);
+            assert_eq!(dense_summary.failures.len(), summary.failures.len());
+
+            assert_eq!(dense.instruments(), processor.instruments());
+            for id in processor.instruments() {
+                assert_eq!(dense.mbp(id), processor.mbp(id));
+            }
+            assert_eq!(dense.state_hash(), processor.state_hash());
+            assert_eq!(dense.stats(), processor.stats());
+            assert_eq!(dense.flow_stats(), processor.flow_stats());
+        }
+    }
+
+    #[test]
+    fn test_dense_processor_matches_order_book_policies() {
+        let mut messages = OrderGenerator::default_seeded(5).make_mbo_messages(20_000);
+        for message in &mut messages {
+            message.owner = Some((message.order_id % 3) as u32);
+        }
+        fn configure<B: BookHandler>(
+            processor: MboProcessor<(), B>,
+            self_match: SelfMatchPolicy,
+            depth_policy: DepthLimitPolicy,
+        ) -> MboProcessor<(), B> {
+            processor
+                .with_crossing_policy(CrossingPolicy::Match)
+                .with_error_policy(ErrorPolicy::Collect)
+                .with_self_match_policy(self_match)
+                .with_book_depth_limit(5, depth_policy)
+                .with_order_ttl(Duration::microseconds(500))
+                .with_anomaly_log(EventRetention::All)
+        }
+        for (self_match, depth_policy) in [
+            (SelfMatchPolicy::CancelResting, DepthLimitPolicy::Drop),
+            (SelfMatchPolicy::CancelIncoming, DepthLimitPolicy::Reject),
+        ] {
+            let processor = MboProcessor::with_observer(());
+            let mut processor = configure(processor, self_match, depth_policy);
+            let dense_book = DenseOrderBook::new(9_900, 10_100, 1).unwrap();
+            let dense = MboProcessor::with_observer_and_book((), dense_book);
+            let mut dense = configure(dense, self_match, depth_policy);
+            let summary = processor.process_messages(&messages);
+            let dense_summary = dense.process_messages(&messages);
+            assert_eq!(dense_summary.processed, summary.processed);
+            assert_eq!(dense_summary.failures.len(), summary.failures.len());
+
+            for id in processor.instruments() {
+                assert_eq!(dense.mbp(id), processor.mbp(id));
+            }
+            assert_eq!(dense.state_hash(), processor.state_hash());
+            assert_eq!(dense.stats(), processor.stats());
+            assert!(dense.anomaly_log().eq(processor.anomaly_log()));
+        }
+    }
+}
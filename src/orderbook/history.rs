@@ -0,0 +1,343 @@
+//! An in-memory history of the best bid and offer, queryable by time after a replay.
+//!
+//! `BboHistory` keeps one `BboSample` per change of the quote, optionally only the
+//! newest ones or one in every few changes, so the spread and touch can be looked up
+//! at any event time without writing a top-of-book file.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+
+#[cfg(feature = "polars")]
+use polars::prelude::{DataFrame, PolarsResult, df};
+
+use crate::orderbook::Bbo;
+use crate::orderbook::book::{mid, spread};
+
+/// A quote and the time it took effect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BboSample {
+    pub ts_event: OffsetDateTime,
+    pub bbo: Bbo,
+}
+
+impl BboSample {
+    /// Ask minus bid, or `None` if either side is empty.
+    pub fn spread(&self) -> Option<i64> {
+        spread(self.bbo.bid, self.bbo.ask)
+    }
+
+    /// Midpoint of the bid and ask, or `None` if either side is empty.
+    pub fn mid(&self) -> Option<f64> {
+        mid(self.bbo.bid, self.bbo.ask)
+    }
+}
+
+/// The quotes of a book in time order, one per change.
+///
+/// A quote equal to the previous one is not a change and is not recorded. With
+/// `with_downsample(n)` only the first of every `n` changes is kept, and with
+/// `with_max_len` the oldest samples are evicted once the limit is reached; either way
+/// lookups see only the samples kept.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BboHistory {
+    max_len: Option<usize>,
+    /// Keep one change in this many, at least 1.
+    downsample: u64,
+    /// Changes seen, kept or not.
+    changes: u64,
+    /// The last quote seen, initially empty.
+    last: Bbo,
+    samples: VecDeque<BboSample>,
+}
+
+/// Spread statistics of a `BboHistory` over a time range. Time with either side empty
+/// is not weighed, so `mean_spread` is `None` without two-sided time.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SpreadSummary {
+    /// Samples taking effect within the range.
+    pub changes: usize,
+    /// Time within the range with both sides quoted.
+    pub two_sided: Duration,
+    /// Time-weighted average spread over two-sided time, in price units.
+    pub mean_spread: Option<f64>,
+    /// Narrowest spread of any two-sided quote in force during the range.
+    pub min_spread: Option<i64>,
+    /// Widest spread of any two-sided quote in force during the range.
+    pub max_spread: Option<i64>,
+}
+
+impl BboHistory {
+    /// A history keeping every change.
+    pub fn new() -> Self {
+        Self {
+            downsample: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Keeps at most `max_len` samples, at least one, evicting the oldest.
+    pub fn with_max_len(mut self, max_len: usize) -> Self {
+        self.max_len = Some(max_len.max(1));
+        self
+    }
+
+    /// Keeps the first of every `factor` changes, so 1 keeps them all.
+    pub fn with_downsample(mut self, factor: u64) -> Self {
+        self.downsample = factor.max(1);
+        self
+    }
+
+    pub fn max_len(&self) -> Option<usize> {
+        self.max_len
+    }
+
+    pub fn downsample(&self) -> u64 {
+        self.downsample.max(1)
+    }
+
+    /// Records `bbo` as the quote from `ts_event` on if it differs from the last one.
+    /// A timestamp before the newest sample's is taken as that sample's, keeping the
+    /// samples in time order.
+    pub fn record(&mut self, ts_event: OffsetDateTime, bbo: Bbo) {
+        if bbo == self.last {
+            return;
+        }
+        self.last = bbo;
+        self.changes += 1;
+        if !(self.changes - 1).is_multiple_of(self.downsample()) {
+            return;
+        }
+        let ts_event = match self.samples.back() {
+            Some(newest) => ts_event.max(newest.ts_event),
+            None => ts_event,
+        };
+        if self
+            .max_len
+            .is_some_and(|max_len| self.samples.len() >= max_len)
+        {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(BboSample { ts_event, bbo });
+    }
+
+    /// Changes seen, including those downsampled away or evicted.
+    pub fn changes(&self) -> u64 {
+        self.changes
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// The samples kept, oldest first.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &BboSample> + ExactSizeIterator {
+        self.samples.iter()
+    }
+
+    pub fn latest(&self) -> Option<&BboSample> {
+        self.samples.back()
+    }
+
+    /// The quote in force at `ts`: that of the last sample at or before it. `None`
+    /// before the oldest sample kept.
+    pub fn at(&self, ts: OffsetDateTime) -> Option<Bbo> {
+        let index = self.samples.partition_point(|sample| sample.ts_event <= ts);
+        index.checked_sub(1).map(|index| self.samples[index].bbo)
+    }
+
+    /// The samples taking effect from `start` up to but excluding `end`.
+    pub fn between(
+        &self,
+        start: OffsetDateTime,
+        end: OffsetDateTime,
+    ) -> impl DoubleEndedIterator<Item = &BboSample> + ExactSizeIterator {
+        let first = self
+            .samples
+            .partition_point(|sample| sample.ts_event < start);
+        let last = self
+            .samples
+            .partition_point(|sample| sample.ts_event < end)
+            .max(first);
+        self.samples.range(first..last)
+    }
+
+    /// Spread statistics from `start` up to `end`, counting the quote in force at
+    /// `start` from `start` on and each sample until the next or `end`.
+    pub fn spread_summary(&self, start: OffsetDateTime, end: OffsetDateTime) -> SpreadSummary {
+        let prevailing = self.at(start).map(|bbo| BboSample {
+            ts_event: start,
+            bbo,
+        });
+        let changes = self.between(start, end);
+        let mut summary = SpreadSummary {
+            changes: changes.len(),
+            ..SpreadSummary::default()
+        };
+        let mut quotes = prevailing.into_iter().chain(changes.copied()).peekable();
+        let (mut two_sided, mut weighted) = (0i128, 0i128);
+        while let Some(quote) = quotes.next() {
+            let Some(spread) = quote.spread() else {
+                continue;
+            };
+            summary.min_spread = Some(summary.min_spread.map_or(spread, |min| min.min(spread)));
+            summary.max_spread = Some(summary.max_spread.map_or(spread, |max| max.max(spread)));
+            let until = quotes.peek().map_or(end, |next| next.ts_event);
+            let nanos = (until - quote.ts_event).whole_nanoseconds().max(0);
+            two_sided += nanos;
+            weighted += i128::from(spread) * nanos;
+        }
+        summary.two_sided = Duration::nanoseconds(two_sided as i64);
+        summary.mean_spread = (two_sided > 0).then(|| weighted as f64 / two_sided as f64);
+        summary
+    }
+
+    /// The samples as a DataFrame: `ts_event` (nanoseconds), `bid_px`, `bid_sz`,
+    /// `ask_px` and `ask_sz`, null for an empty side.
+    #[cfg(feature = "polars")]
+    pub fn to_dataframe(&self) -> PolarsResult<DataFrame> {
+        let samples = &self.samples;
+        df!(
+            "ts_event" => samples.iter().map(|s| s.ts_event.unix_timestamp_nanos() as i64).collect::<Vec<_>>(),
+            "bid_px" => samples.iter().map(|s| s.bbo.bid.map(|(p, _)| p)).collect::<Vec<_>>(),
+            "bid_sz" => samples.iter().map(|s| s.bbo.bid.map(|(_, q)| q)).collect::<Vec<_>>(),
+            "ask_px" => samples.iter().map(|s| s.bbo.ask.map(|(p, _)| p)).collect::<Vec<_>>(),
+            "ask_sz" => samples.iter().map(|s| s.bbo.ask.map(|(_, q)| q)).collect::<Vec<_>>(),
+        )
+    }
+}
+
+impl SpreadSummary {
+    /// The summary as a one-row DataFrame, with `two_sided` in nanoseconds and missing
+    /// statistics null.
+    #[cfg(feature = "polars")]
+    pub fn to_dataframe(&self) -> PolarsResult<DataFrame> {
+        df!(
+            "changes" => vec![self.changes as u64],
+            "two_sided_ns" => vec![self.two_sided.whole_nanoseconds() as i64],
+            "mean_spread" => vec![self.mean_spread],
+            "min_spread" => vec![self.min_spread],
+            "max_spread" => vec![self.max_spread],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: i64) -> OffsetDateTime {
+        OffsetDateTime::UNIX_EPOCH + Duration::seconds(seconds)
+    }
+
+    fn quote(bid: i64, ask: i64) -> Bbo {
+        Bbo {
+            bid: Some((bid, 10)),
+            ask: Some((ask, 10)),
+        }
+    }
+
+    #[test]
+    fn test_at_boundaries() {
+        let mut history = BboHistory::new();
+        assert_eq!(history.at(at(0)), None);
+        history.record(at(10), quote(100, 102));
+        history.record(at(20), quote(100, 104));
+        // Unchanged, not recorded
+        history.record(at(25), quote(100, 104));
+        assert_eq!(history.len(), 2);
+
+        // Before the first quote
+        assert_eq!(history.at(at(9)), None);
+        // Exactly at a timestamp, the quote taking effect then
+        assert_eq!(history.at(at(10)), Some(quote(100, 102)));
+        assert_eq!(history.at(at(20)), Some(quote(100, 104)));
+        // Between and after samples, the one in force
+        assert_eq!(history.at(at(19)), Some(quote(100, 102)));
+        assert_eq!(history.at(at(1_000)), Some(quote(100, 104)));
+    }
+
+    #[test]
+    fn test_same_timestamp_and_out_of_order() {
+        let mut history = BboHistory::new();
+        history.record(at(10), quote(100, 102));
+        history.record(at(10), quote(100, 103));
+        // Earlier than the newest sample, taken as at 10 s
+        history.record(at(5), quote(100, 101));
+        assert_eq!(history.at(at(10)), Some(quote(100, 101)));
+        assert_eq!(history.at(at(9)), None);
+        assert!(history.iter().all(|sample| sample.ts_event == at(10)));
+    }
+
+    #[test]
+    fn test_ring_buffer_eviction() {
+        let mut history = BboHistory::new().with_max_len(3);
+        for second in 0..5 {
+            history.record(at(second), quote(100, 101 + second));
+        }
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.changes(), 5);
+        let times: Vec<_> = history.iter().map(|sample| sample.ts_event).collect();
+        assert_eq!(times, [at(2), at(3), at(4)]);
+        // Evicted quotes are no longer known
+        assert_eq!(history.at(at(1)), None);
+        assert_eq!(history.at(at(2)), Some(quote(100, 103)));
+        assert_eq!(history.latest().map(BboSample::spread), Some(Some(5)));
+    }
+
+    #[test]
+    fn test_downsample() {
+        let mut history = BboHistory::new().with_downsample(2);
+        for second in 0..5 {
+            history.record(at(second), quote(100, 101 + second));
+            // Repeats do not count as changes
+            history.record(at(second), quote(100, 101 + second));
+        }
+        let spreads: Vec<_> = history.iter().filter_map(BboSample::spread).collect();
+        assert_eq!(spreads, [1, 3, 5]);
+        assert_eq!(history.at(at(1)), Some(quote(100, 101)));
+    }
+
+    #[test]
+    fn test_between_and_spread_summary() {
+        let mut history = BboHistory::new();
+        history.record(at(0), quote(100, 102));
+        history.record(at(10), quote(100, 104));
+        history.record(
+            at(20),
+            Bbo {
+                bid: None,
+                ask: Some((104, 10)),
+            },
+        );
+        history.record(at(25), quote(100, 101));
+
+        let times: Vec<_> = history
+            .between(at(10), at(25))
+            .map(|sample| sample.ts_event)
+            .collect();
+        assert_eq!(times, [at(10), at(20)]);
+        assert_eq!(history.between(at(30), at(10)).len(), 0);
+
+        // Spread 2 for 5 s from the quote in force, 4 for 10 s, one-sided for 5 s, 1
+        // for 5 s
+        let summary = history.spread_summary(at(5), at(30));
+        assert_eq!(summary.changes, 3);
+        assert_eq!(summary.two_sided, Duration::seconds(20));
+        assert_eq!(
+            summary.mean_spread,
+            Some((2.0 * 5.0 + 4.0 * 10.0 + 5.0) / 20.0)
+        );
+        assert_eq!((summary.min_spread, summary.max_spread), (Some(1), Some(4)));
+
+        assert_eq!(
+            history.spread_summary(at(-5), at(0)),
+            SpreadSummary::default()
+        );
+    }
+}
@@ -0,0 +1,423 @@
+//! Detecting hidden size from prints that outlast the displayed quantity.
+//!
+//! A level that keeps trading after its displayed quantity should have run out is
+//! being refilled from somewhere the book does not show: an iceberg order, or hidden
+//! liquidity resting at the price. `IcebergDetector` watches the prints at each level
+//! against the quantity the book displayed there before they traded, and flags a
+//! level once what traded there within a rolling window of event time exceeds the
+//! most it ever displayed in that window. This is a heuristic: fresh orders joining a
+//! level as fast as it trades look the same.
+
+use std::collections::{HashMap, VecDeque};
+
+#[cfg(feature = "polars")]
+use polars::prelude::{DataFrame, PolarsResult, df};
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+
+use crate::orderbook::book::OrderLevel;
+use crate::orderbook::{Action, BookKey, MarketByOrderMessage, MboObserver, OrderBook, Side};
+
+/// A level that traded more than it displayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IcebergSignal {
+    pub instrument_id: u32,
+    /// Publisher of the book, zero unless the processor routes by publisher.
+    pub publisher_id: u16,
+    /// Side of the resting liquidity that traded.
+    pub side: Side,
+    pub price: i64,
+    /// Largest quantity the level displayed before any of the signal's prints.
+    pub displayed_max: u64,
+    /// Quantity traded at the level from `first_ts` to `last_ts`.
+    pub executed: u64,
+    pub first_ts: OffsetDateTime,
+    pub last_ts: OffsetDateTime,
+}
+
+impl IcebergSignal {
+    /// Quantity traded beyond the most the level displayed.
+    pub fn excess(&self) -> u64 {
+        self.executed.saturating_sub(self.displayed_max)
+    }
+}
+
+/// A print at a watched level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Print {
+    ts: OffsetDateTime,
+    /// Quantity the level displayed before the print's event reduced it.
+    displayed: u64,
+    size: u64,
+}
+
+/// The prints of one level within the window, and the signal they raised, if any.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct LevelWatch {
+    prints: VecDeque<Print>,
+    /// Index in `IcebergDetector::signals` of the signal still growing.
+    signal: Option<usize>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct BookState {
+    levels: HashMap<(Side, i64), LevelWatch>,
+    /// Whether the current event printed a Trade, making its Fills the resting halves
+    /// of prints already counted.
+    traded: bool,
+}
+
+/// Observer flagging levels whose prints within `window` of event time exceed the
+/// largest quantity they displayed by more than `min_excess`.
+///
+/// Each Trade counts against the side opposite its own; each Fill of an event without
+/// a Trade counts against the side of the order it filled. A Trade or Fill does not
+/// change the book, so the quantity displayed is read as the print's event found it.
+/// Once a level is flagged, its later prints extend the same signal until `window`
+/// passes without a print there. Executions of a crossing Add under
+/// `CrossingPolicy::Match` are not seen, and a Clear ends every signal of its book.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IcebergDetector {
+    window: Duration,
+    min_excess: u64,
+    books: HashMap<BookKey, BookState>,
+    signals: Vec<IcebergSignal>,
+}
+
+impl IcebergDetector {
+    /// A detector comparing the prints and displayed quantity of each level over the
+    /// last `window` of event time.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            min_excess: 0,
+            books: HashMap::new(),
+            signals: Vec::new(),
+        }
+    }
+
+    /// Flags a level only once its prints exceed the most it displayed by more than
+    /// `min_excess`, rather than by any quantity.
+    pub fn with_min_excess(mut self, min_excess: u64) -> Self {
+        self.min_excess = min_excess;
+        self
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    pub fn min_excess(&self) -> u64 {
+        self.min_excess
+    }
+
+    /// Every signal raised, in the order levels were flagged.
+    pub fn signals(&self) -> &[IcebergSignal] {
+        &self.signals
+    }
+
+    pub fn into_signals(self) -> Vec<IcebergSignal> {
+        self.signals
+    }
+
+    /// Counts a print of `size` at `price` against the resting `side`.
+    fn record(
+        &mut self,
+        key: BookKey,
+        side: Side,
+        price: i64,
+        size: u64,
+        ts: OffsetDateTime,
+        book: &OrderBook,
+    ) {
+        let displayed = book
+            .levels(side)
+            .get(&price)
+            .map_or(0, OrderLevel::total_qty);
+        let window = self.window;
+        let watch = self
+            .books
+            .entry(key)
+            .or_default()
+            .levels
+            .entry((side, price))
+            .or_default();
+        while watch
+            .prints
+            .front()
+            .is_some_and(|print| ts - print.ts > window)
+        {
+            watch.prints.pop_front();
+        }
+        if watch.prints.is_empty() {
+            watch.signal = None;
+        }
+        watch.prints.push_back(Print {
+            ts,
+            displayed,
+            size,
+        });
+
+        if let Some(index) = watch.signal {
+            let signal = &mut self.signals[index];
+            signal.displayed_max = signal.displayed_max.max(displayed);
+            signal.executed += size;
+            signal.last_ts = ts;
+            return;
+        }
+        let executed: u64 = watch.prints.iter().map(|print| print.size).sum();
+        let displayed_max = watch
+            .prints
+            .iter()
+            .map(|print| print.displayed)
+            .max()
+            .unwrap_or(0);
+        if executed > displayed_max.saturating_add(self.min_excess) {
+            watch.signal = Some(self.signals.len());
+            self.signals.push(IcebergSignal {
+                instrument_id: key.instrument_id,
+                publisher_id: key.publisher_id,
+                side,
+                price,
+                displayed_max,
+                executed,
+                first_ts: watch.prints.front().map_or(ts, |print| print.ts),
+                last_ts: ts,
+            });
+        }
+    }
+
+    /// The signals as a DataFrame: `instrument_id`, `publisher_id`, `side` (`"B"` or
+    /// `"A"`), `price`, `displayed_max`, `executed`, `excess`, and `first_ts` and
+    /// `last_ts` (nanoseconds).
+    #[cfg(feature = "polars")]
+    pub fn to_dataframe(&self) -> PolarsResult<DataFrame> {
+        let signals = &self.signals;
+        df!(
+            "instrument_id" => signals.iter().map(|s| s.instrument_id).collect::<Vec<_>>(),
+            "publisher_id" => signals.iter().map(|s| s.publisher_id).collect::<Vec<_>>(),
+            "side" => signals.iter().map(|s| match s.side {
+                Side::Bid => "B",
+                Side::Ask => "A",
+            }).collect::<Vec<_>>(),
+            "price" => signals.iter().map(|s| s.price).collect::<Vec<_>>(),
+            "displayed_max" => signals.iter().map(|s| s.displayed_max).collect::<Vec<_>>(),
+            "executed" => signals.iter().map(|s| s.executed).collect::<Vec<_>>(),
+            "excess" => signals.iter().map(IcebergSignal::excess).collect::<Vec<_>>(),
+            "first_ts" => signals.iter().map(|s| s.first_ts.unix_timestamp_nanos() as i64).collect::<Vec<_>>(),
+            "last_ts" => signals.iter().map(|s| s.last_ts.unix_timestamp_nanos() as i64).collect::<Vec<_>>(),
+        )
+    }
+}
+
+impl MboObserver for IcebergDetector {
+    fn on_message_applied(
+        &mut self,
+        message: &MarketByOrderMessage,
+        key: BookKey,
+        book: &OrderBook,
+    ) {
+        let print = match message.action {
+            Action::Clear => {
+                self.books.remove(&key);
+                None
+            }
+            Action::Trade => {
+                self.books.entry(key).or_default().traded = true;
+                let resting = match message.side {
+                    Side::Bid => Side::Ask,
+                    Side::Ask => Side::Bid,
+                };
+                message.defined_price().map(|price| (resting, price))
+            }
+            Action::Fill if !self.books.get(&key).is_some_and(|state| state.traded) => {
+                let resting = book
+                    .get_order(message.order_id)
+                    .map_or(message.side, |order| order.side);
+                message.defined_price().map(|price| (resting, price))
+            }
+            _ => None,
+        };
+        if let Some((side, price)) = print {
+            self.record(
+                key,
+                side,
+                price,
+                message.size.into(),
+                message.event_time,
+                book,
+            );
+        }
+        if let Some(state) = self.books.get_mut(&key).filter(|_| message.is_last) {
+            state.traded = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::orderbook::MboProcessor;
+
+    /// A message `seconds` into the session, ending its event if `is_last`.
+    fn msg(
+        seconds: i64,
+        action: Action,
+        order_id: u64,
+        side: Side,
+        price: i64,
+        size: u32,
+        is_last: bool,
+    ) -> MarketByOrderMessage {
+        MarketByOrderMessage {
+            sequence: seconds as u32,
+            ..MarketByOrderMessage::test(action, side, price, order_id, size)
+                .at(OffsetDateTime::UNIX_EPOCH + Duration::seconds(seconds))
+                .with_last(is_last)
+        }
+    }
+
+    /// A buy of `size` at 101 taking `size` from ask `order_id`, which is left with
+    /// `left`.
+    fn lift(seconds: i64, order_id: u64, size: u32, left: u32) -> [MarketByOrderMessage; 3] {
+        let reduce = match left {
+            0 => msg(
+                seconds,
+                Action::Cancel,
+                order_id,
+                Side::Ask,
+                101,
+                size,
+                true,
+            ),
+            _ => msg(
+                seconds,
+                Action::Modify,
+                order_id,
+                Side::Ask,
+                101,
+                left,
+                true,
+            ),
+        };
+        [
+            msg(seconds, Action::Trade, 0, Side::Bid, 101, size, false),
+            msg(seconds, Action::Fill, order_id, Side::Ask, 101, size, false),
+            reduce,
+        ]
+    }
+
+    fn detector(window_seconds: i64) -> MboProcessor<IcebergDetector> {
+        let mut processor =
+            MboProcessor::with_observer(IcebergDetector::new(Duration::seconds(window_seconds)));
+        processor.process_messages([msg(0, Action::Add, 100, Side::Bid, 99, 50, true)]);
+        processor
+    }
+
+    #[test]
+    fn test_refilled_level_raises_one_signal() {
+        let mut processor = detector(60);
+        // An ask showing 10 at a time, refilled under a new id each time it trades out
+        for (refill, seconds) in [(1, 1), (2, 10), (3, 20), (4, 30)] {
+            processor.process_messages([msg(
+                seconds,
+                Action::Add,
+                refill,
+                Side::Ask,
+                101,
+                10,
+                true,
+            )]);
+            processor.process_messages(lift(seconds + 1, refill, 10, 0));
+        }
+
+        let signals = processor.observer().signals();
+        assert_eq!(
+            signals,
+            [IcebergSignal {
+                instrument_id: 1,
+                publisher_id: 0,
+                side: Side::Ask,
+                price: 101,
+                displayed_max: 10,
+                executed: 40,
+                first_ts: OffsetDateTime::UNIX_EPOCH + Duration::seconds(2),
+                last_ts: OffsetDateTime::UNIX_EPOCH + Duration::seconds(31),
+            }]
+        );
+        assert_eq!(signals[0].excess(), 30);
+    }
+
+    #[test]
+    fn test_benign_patterns_raise_nothing() {
+        // A deep ask traded down in pieces, never beyond what it displayed
+        let mut processor = detector(60);
+        processor.process_messages([msg(1, Action::Add, 1, Side::Ask, 101, 50, true)]);
+        processor.process_messages(lift(2, 1, 20, 30));
+        processor.process_messages(lift(3, 1, 20, 10));
+        processor.process_messages(lift(4, 1, 10, 0));
+        assert!(processor.observer().signals().is_empty());
+
+        // Refills further apart than the window
+        let mut processor = detector(5);
+        for (refill, seconds) in [(1, 1), (2, 10), (3, 20)] {
+            processor.process_messages([msg(
+                seconds,
+                Action::Add,
+                refill,
+                Side::Ask,
+                101,
+                10,
+                true,
+            )]);
+            processor.process_messages(lift(seconds + 1, refill, 10, 0));
+        }
+        assert!(processor.observer().signals().is_empty());
+
+        // Within the window, but short of the required excess
+        let mut processor = MboProcessor::with_observer(
+            IcebergDetector::new(Duration::seconds(60)).with_min_excess(10),
+        );
+        for (refill, seconds) in [(1, 1), (2, 10)] {
+            processor.process_messages([msg(
+                seconds,
+                Action::Add,
+                refill,
+                Side::Ask,
+                101,
+                10,
+                true,
+            )]);
+            processor.process_messages(lift(seconds + 1, refill, 10, 0));
+        }
+        assert!(processor.observer().signals().is_empty());
+        processor.process_messages([msg(20, Action::Add, 3, Side::Ask, 101, 10, true)]);
+        processor.process_messages(lift(21, 3, 10, 0));
+        assert_eq!(processor.observer().signals()[0].excess(), 20);
+    }
+
+    #[cfg(feature = "polars")]
+    #[test]
+    fn test_to_dataframe() {
+        let mut processor = detector(60);
+        for (refill, seconds) in [(1, 1), (2, 10)] {
+            processor.process_messages([msg(
+                seconds,
+                Action::Add,
+                refill,
+                Side::Ask,
+                101,
+                10,
+                true,
+            )]);
+            processor.process_messages(lift(seconds + 1, refill, 10, 0));
+        }
+        let df = processor.observer().to_dataframe().unwrap();
+        assert_eq!(df.shape(), (1, 9));
+        let side = df.column("side").unwrap().str().unwrap();
+        assert_eq!(side.get(0), Some("A"));
+        let executed = df.column("executed").unwrap().u64().unwrap();
+        assert_eq!(executed.get(0), Some(20));
+    }
+}
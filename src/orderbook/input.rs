@@ -0,0 +1,308 @@
+//! Processing MBO data from any `Read`, such as standard input.
+//!
+//! A stream has no file name to detect the format from and cannot be rewound, so the
+//! format is given by the caller and every format is read front to back in one pass.
+//! Parquet keeps its metadata at the end of the file and cannot be read this way.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use dbn::decode::{DynReader, dbn::Decoder};
+#[cfg(feature = "polars")]
+use polars::prelude::PolarsError;
+use thiserror::Error;
+
+use crate::orderbook::format::sniff_content;
+use crate::orderbook::{
+    Compression, ConversionError, ConversionReport, CsvOptions, CsvReadError, DataFormat,
+    DbnStreamError, FormatError, InputFormat, MboObserver, MboProcessor, ProcessSummary,
+    ProgressSink, decompress, mbo_messages, read_mbo_csv_from, read_mbo_ndjson_from,
+    resolve_format, sniff_compression,
+};
+#[cfg(feature = "itch")]
+use crate::orderbook::{ItchError, itch_messages};
+
+#[derive(Debug, Error)]
+pub enum InputError {
+    #[error("{0} input cannot be read from a stream")]
+    NotStreamable(InputFormat),
+
+    #[error("{0} input is not supported")]
+    Unsupported(InputFormat),
+
+    #[error("Could not determine the format of the stream, it needs to be given")]
+    UnrecognizedStream,
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Dbn(#[from] dbn::Error),
+
+    #[error(transparent)]
+    DbnStream(#[from] DbnStreamError),
+
+    #[error(transparent)]
+    Csv(#[from] CsvReadError),
+
+    #[error(transparent)]
+    Format(#[from] FormatError),
+
+    #[cfg(feature = "polars")]
+    #[error(transparent)]
+    Polars(#[from] PolarsError),
+
+    #[cfg(feature = "itch")]
+    #[error(transparent)]
+    Itch(#[from] ItchError),
+}
+
+/// Where `open_input` reads from.
+pub enum InputSource<'a> {
+    File(&'a Path),
+    /// A stream such as standard input, read front to back once.
+    Reader(Box<dyn BufRead + 'a>),
+}
+
+/// Opens `source` as a stream of `format` with its compression removed, returning the
+/// format read with the compression found.
+///
+/// A file without `format` is read as `resolve_format` finds. A stream without one is
+/// sniffed from its leading bytes, which are left unconsumed, and fails with
+/// `InputError::UnrecognizedStream` unless it holds DBN or NDJSON. A format without
+/// compression has its compression sniffed too. Parquet cannot be streamed and fails
+/// with `InputError::NotStreamable`.
+pub fn open_input<'a>(
+    source: InputSource<'a>,
+    format: Option<InputFormat>,
+) -> Result<(InputFormat, Box<dyn BufRead + 'a>), InputError> {
+    let (mut reader, format): (Box<dyn BufRead + 'a>, _) = match source {
+        InputSource::File(path) => (
+            Box::new(BufReader::new(File::open(path)?)),
+            Some(resolve_format(path, format)?),
+        ),
+        InputSource::Reader(reader) => (reader, format),
+    };
+    let compression = match format {
+        Some(format) if format.compression != Compression::None => format.compression,
+        _ => sniff_compression(&mut reader)?,
+    };
+    let mut reader = decompress(reader, compression)?;
+    let format = match format {
+        Some(format) => format.format,
+        None => sniff_content(reader.fill_buf()?).ok_or(InputError::UnrecognizedStream)?,
+    };
+    let input = InputFormat::new(format, compression);
+    match format {
+        DataFormat::Parquet => Err(InputError::NotStreamable(input)),
+        _ => Ok((input, reader)),
+    }
+}
+
+/// Outcome of `process_reader`.
+#[derive(Debug, Default, Clone)]
+pub struct ReaderProcessSummary {
+    /// Processing outcome, with failure indices counted over the converted messages.
+    pub summary: ProcessSummary,
+    /// 0-based row index and reason of every CSV or NDJSON row that could not be
+    /// converted.
+    pub rejected: Vec<(usize, ConversionError)>,
+}
+
+/// Decompresses `reader` according to `format` and applies its MBO messages to
+/// `processor`.
+///
+/// DBN and ITCH are converted and applied a record at a time; the first record that
+/// cannot be decoded or converted ends processing with an error, leaving the messages
+/// before it applied. CSV, read with the default `CsvOptions`, and NDJSON are read in
+/// full first, with unconvertible rows reported in `rejected`. Parquet fails with
+/// `InputError::NotStreamable`.
+pub fn process_reader<O: MboObserver>(
+    reader: impl Read,
+    format: InputFormat,
+    processor: &mut MboProcessor<O>,
+) -> Result<ReaderProcessSummary, InputError> {
+    process_reader_with_progress(reader, format, processor, ())
+}
+
+/// Processes the input of `reader` like `process_reader`, reporting the messages
+/// processed to `sink` as it goes. CSV and NDJSON are read in full before the first
+/// report.
+pub fn process_reader_with_progress<O: MboObserver>(
+    reader: impl Read,
+    format: InputFormat,
+    processor: &mut MboProcessor<O>,
+    mut sink: impl ProgressSink,
+) -> Result<ReaderProcessSummary, InputError> {
+    let reader = decompress(BufReader::new(reader), format.compression)?;
+    let mut read_error = None;
+    let mut result = ReaderProcessSummary::default();
+    match format.format {
+        DataFormat::Dbn => {
+            let decoder = Decoder::new(DynReader::new_inferred(reader)?)?;
+            let messages = mbo_messages(decoder)
+                .map_while(|message| message.map_err(|e| read_error = Some(e.into())).ok());
+            result.summary = processor.process_messages_with_progress(messages, &mut sink);
+        }
+        DataFormat::Csv => {
+            let report = read_mbo_csv_from(reader, CsvOptions::new())?;
+            result = process_report(report, processor, &mut sink)
+        }
+        DataFormat::Ndjson => {
+            result = process_report(read_mbo_ndjson_from(reader)?, processor, &mut sink)
+        }
+        DataFormat::Parquet => return Err(InputError::NotStreamable(format)),
+        #[cfg(feature = "itch")]
+        DataFormat::Itch => {
+            let messages = itch_messages(reader)
+                .map_while(|message| message.map_err(|e| read_error = Some(e.into())).ok());
+            result.summary = processor.process_messages_with_progress(messages, &mut sink);
+        }
+        #[cfg(not(feature = "itch"))]
+        DataFormat::Itch => return Err(InputError::Unsupported(format)),
+    }
+    match read_error {
+        Some(e) => Err(e),
+        None => Ok(result),
+    }
+}
+
+fn process_report<O: MboObserver>(
+    report: ConversionReport,
+    processor: &mut MboProcessor<O>,
+    sink: impl ProgressSink,
+) -> ReaderProcessSummary {
+    ReaderProcessSummary {
+        summary: processor.process_messages_with_progress(&report.messages, sink),
+        rejected: report.rejected,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::c_char;
+    use std::io::Cursor;
+
+    use dbn::encode::{DynWriter, EncodeRecord, dbn::Encoder};
+    use dbn::{MboMsg, MetadataBuilder, SType, Schema};
+
+    fn record(sequence: u32, action: u8, side: u8, order_id: u64, price: i64) -> MboMsg {
+        let ts_event = 1_000 + u64::from(sequence);
+        MboMsg {
+            hd: dbn::RecordHeader::new::<MboMsg>(dbn::enums::rtype::MBO, 1, 7, ts_event),
+            order_id,
+            price,
+            size: 10,
+            flags: dbn::FlagSet::new(dbn::flags::LAST),
+            channel_id: 0,
+            action: action as c_char,
+            side: side as c_char,
+            ts_recv: ts_event + 50,
+            ts_in_delta: 0,
+            sequence,
+        }
+    }
+
+    fn dbn_bytes() -> Vec<u8> {
+        let metadata = MetadataBuilder::new()
+            .dataset("TEST".to_owned())
+            .schema(Some(Schema::Mbo))
+            .start(0)
+            .stype_in(Some(SType::InstrumentId))
+            .stype_out(SType::InstrumentId)
+            .build();
+        let mut buf = Vec::new();
+        {
+            let writer = DynWriter::new(&mut buf, dbn::Compression::None).unwrap();
+            let mut encoder = Encoder::new(writer, &metadata).unwrap();
+            [
+                record(1, b'A', b'B', 1, 100),
+                record(2, b'A', b'A', 2, 101),
+                record(3, b'A', b'B', 3, 99),
+                record(4, b'C', b'B', 1, 100),
+            ]
+            .iter()
+            .for_each(|record| encoder.encode_record(record).unwrap());
+            encoder.flush().unwrap();
+        }
+        buf
+    }
+
+    #[test]
+    fn test_dbn_from_cursor() {
+        for (bytes, compression) in [
+            (dbn_bytes(), Compression::None),
+            (
+                zstd::encode_all(&dbn_bytes()[..], 0).unwrap(),
+                Compression::Zstd,
+            ),
+        ] {
+            let mut proc = MboProcessor::new();
+            let format = InputFormat::new(DataFormat::Dbn, compression);
+            let result = process_reader(Cursor::new(bytes), format, &mut proc).unwrap();
+            assert_eq!(result.summary.processed, 4);
+            assert!(result.summary.is_success());
+            assert_eq!(proc.book(7).unwrap().best_bid(), Some((99, 10)));
+            assert_eq!(proc.book(7).unwrap().best_ask(), Some((101, 10)));
+        }
+    }
+
+    #[test]
+    fn test_ndjson_reports_rejected_lines() {
+        let ndjson = concat!(
+            r#"{"action":"A","side":"B","price":100,"order_id":1,"size":5}"#,
+            "\nnot json\n",
+            r#"{"action":"A","side":"A","price":101,"order_id":2,"size":5}"#,
+            "\n",
+        );
+        let mut proc = MboProcessor::new();
+        let format = InputFormat::new(DataFormat::Ndjson, Compression::None);
+        let result = process_reader(ndjson.as_bytes(), format, &mut proc).unwrap();
+        assert_eq!(result.summary.processed, 2);
+        assert_eq!(
+            result
+                .rejected
+                .iter()
+                .map(|(row, _)| *row)
+                .collect::<Vec<_>>(),
+            vec![1]
+        );
+        assert_eq!(proc.best_bid(), Some((100, 5)));
+    }
+
+    #[test]
+    fn test_parquet_is_not_streamable() {
+        let format = InputFormat::new(DataFormat::Parquet, Compression::None);
+        assert!(matches!(
+            process_reader(&b"PAR1"[..], format, &mut MboProcessor::new()),
+            Err(InputError::NotStreamable(_))
+        ));
+    }
+
+    #[test]
+    fn test_open_input_sniffs_stream() {
+        let ndjson = r#"{"action":"A","side":"B","price":100,"order_id":1,"size":5}"#;
+        let compressed = zstd::encode_all(ndjson.as_bytes(), 0).unwrap();
+        let source = InputSource::Reader(Box::new(&compressed[..]));
+        let (format, mut reader) = open_input(source, None).unwrap();
+        assert_eq!(
+            format,
+            InputFormat::new(DataFormat::Ndjson, Compression::Zstd)
+        );
+        let mut text = String::new();
+        reader.read_to_string(&mut text).unwrap();
+        assert_eq!(text, ndjson);
+
+        let csv = b"action,side,price,order_id,size\n";
+        let source = InputSource::Reader(Box::new(&csv[..]));
+        assert!(matches!(
+            open_input(source, None),
+            Err(InputError::UnrecognizedStream)
+        ));
+        let source = InputSource::Reader(Box::new(&csv[..]));
+        let (format, _) = open_input(source, Some("csv".parse().unwrap())).unwrap();
+        assert_eq!(format, InputFormat::new(DataFormat::Csv, Compression::None));
+    }
+}
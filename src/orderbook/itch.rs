@@ -0,0 +1,670 @@
+//! Parsing NASDAQ TotalView-ITCH 5.0 into `MarketByOrderMessage`s.
+//!
+//! ITCH files are a sequence of messages, each preceded by its length as a big-endian
+//! `u16`. Every message starts with a type byte, the stock locate code, a tracking
+//! number and a 6-byte timestamp in nanoseconds since midnight; all integer fields are
+//! big-endian. Only the messages that change the book are converted, plus Stock
+//! Directory messages, which map locate codes to symbols. Other message types are
+//! skipped.
+//!
+//! ITCH reports executions and cancels as quantity deltas against an order reference,
+//! while `MboProcessor` works with the resulting order state, so `ItchMessages` tracks
+//! every open order:
+//!
+//! - Add Order (`A`, and `F` with MPID) becomes an Add. The MPID, read as a big-endian
+//!   `u32` of its four ASCII bytes, is the order's owner in every message about it.
+//! - Order Executed (`E`, and `C` with price) becomes a Fill followed by a Modify to
+//!   the remaining size, or a Cancel once nothing remains.
+//! - Order Cancel (`X`) becomes a Modify to the remaining size, or a Cancel.
+//! - Order Delete (`D`) becomes a Cancel.
+//! - Order Replace (`U`) becomes a Cancel of the original order followed by an Add of
+//!   the new one, which goes to the back of the queue.
+//! - Trade (`P`), a match against a non-displayed order, becomes a Trade.
+//!
+//! An ITCH message converted into several messages is one event: only the last of them
+//! carries the LAST flag. The locate code is used as the instrument id, and prices
+//! (4 implied decimals) are scaled to dbn fixed-point units.
+
+use std::collections::VecDeque;
+use std::io::{self, Read};
+
+use dbn::{FIXED_PRICE_SCALE, flags};
+use thiserror::Error;
+use time::{Duration, OffsetDateTime};
+
+use crate::orderbook::book::OrderMap;
+use crate::orderbook::{Action, MarketByOrderMessage, Side, SymbolMap};
+
+/// Multiplier from ITCH prices, in units of 1/10,000, to dbn fixed-point prices.
+const PRICE_SCALE: i64 = FIXED_PRICE_SCALE / 10_000;
+
+/// Length of the type, stock locate, tracking number and timestamp fields that start
+/// every message.
+const HEADER_LEN: usize = 11;
+
+#[derive(Debug, Error)]
+pub enum ItchError {
+    #[error("Failed to read ITCH message {index}: {source}")]
+    Io { index: usize, source: io::Error },
+
+    #[error("ITCH message {index} of type '{kind}' is {len} bytes, expected {expected}")]
+    Truncated {
+        index: usize,
+        kind: char,
+        len: usize,
+        expected: usize,
+    },
+
+    #[error("ITCH message {index} has invalid side '{side}'")]
+    InvalidSide { index: usize, side: char },
+
+    #[error("ITCH message {index} refers to unknown order {order_ref}")]
+    UnknownOrder { index: usize, order_ref: u64 },
+}
+
+impl ItchError {
+    /// 0-based index of the ITCH message that failed.
+    pub fn index(&self) -> usize {
+        match self {
+            ItchError::Io { index, .. }
+            | ItchError::Truncated { index, .. }
+            | ItchError::InvalidSide { index, .. }
+            | ItchError::UnknownOrder { index, .. } => *index,
+        }
+    }
+}
+
+/// An order that is still on the book, as far as the ITCH stream is concerned.
+#[derive(Debug, Clone, Copy)]
+struct OpenOrder {
+    locate: u16,
+    side: Side,
+    price: i64,
+    shares: u32,
+    /// MPID of an `F` Add as a big-endian `u32`.
+    owner: Option<u32>,
+}
+
+/// Fields common to every ITCH message.
+#[derive(Debug, Clone, Copy)]
+struct Header {
+    locate: u16,
+    event_time: OffsetDateTime,
+}
+
+/// Iterator over the book-building messages of an ITCH 5.0 stream, created by
+/// `itch_messages`.
+///
+/// A message that cannot be converted is yielded as an error and iteration continues
+/// with the next one. A read error, including a stream that ends mid-message, is
+/// yielded once and ends the iteration.
+pub struct ItchMessages<R> {
+    reader: R,
+    session_start: OffsetDateTime,
+    index: usize,
+    done: bool,
+    orders: OrderMap<OpenOrder>,
+    symbols: SymbolMap,
+    pending: VecDeque<MarketByOrderMessage>,
+}
+
+/// Streams the book-building messages of the length-prefixed ITCH 5.0 data in `reader`.
+pub fn itch_messages<R: Read>(reader: R) -> ItchMessages<R> {
+    ItchMessages {
+        reader,
+        session_start: OffsetDateTime::UNIX_EPOCH,
+        index: 0,
+        done: false,
+        orders: OrderMap::default(),
+        symbols: SymbolMap::new(),
+        pending: VecDeque::new(),
+    }
+}
+
+impl<R> ItchMessages<R> {
+    /// Midnight of the trading session, to which ITCH timestamps are relative. Defaults
+    /// to the UNIX epoch; pass midnight US/Eastern of the session date for wall-clock
+    /// event times.
+    pub fn with_session_start(mut self, session_start: OffsetDateTime) -> Self {
+        self.session_start = session_start;
+        self
+    }
+
+    /// Number of ITCH messages read so far, including skipped ones.
+    pub fn messages_read(&self) -> usize {
+        self.index
+    }
+
+    /// Symbols of the Stock Directory messages read so far, keyed by locate code and
+    /// valid on the session date.
+    pub fn symbols(&self) -> &SymbolMap {
+        &self.symbols
+    }
+
+    /// Number of orders added and not yet fully executed or deleted.
+    pub fn open_orders(&self) -> usize {
+        self.orders.len()
+    }
+}
+
+impl<R: Read> ItchMessages<R> {
+    /// Reads the next length-prefixed message; `None` at a clean end of the stream.
+    fn read_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut len = [0u8; 2];
+        match self.reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let mut frame = vec![0u8; u16::from_be_bytes(len).into()];
+        self.reader.read_exact(&mut frame)?;
+        Ok(Some(frame))
+    }
+
+    /// Converts one ITCH message, queueing the resulting messages in `pending`.
+    fn convert(&mut self, frame: &[u8]) -> Result<(), ItchError> {
+        let index = self.index;
+        let Some(&kind) = frame.first() else {
+            return Ok(());
+        };
+        let expected = match kind {
+            b'R' => 39,
+            b'A' => 36,
+            b'F' => 40,
+            b'E' => 31,
+            b'C' => 36,
+            b'X' => 23,
+            b'D' => 19,
+            b'U' => 35,
+            b'P' => 44,
+            // Not relevant to the book
+            _ => return Ok(()),
+        };
+        if frame.len() < expected {
+            return Err(ItchError::Truncated {
+                index,
+                kind: kind as char,
+                len: frame.len(),
+                expected,
+            });
+        }
+        let header = Header {
+            locate: be_u16(frame, 1),
+            event_time: self.session_start + Duration::nanoseconds(be_u48(frame, 5) as i64),
+        };
+        let body = &frame[HEADER_LEN..];
+        match kind {
+            b'R' => {
+                let symbol = String::from_utf8_lossy(&body[..8]);
+                let date = self.session_start.date();
+                self.symbols.insert(
+                    symbol.trim_end(),
+                    header.locate.into(),
+                    date,
+                    date.next_day().unwrap_or(date),
+                );
+            }
+            b'A' | b'F' => {
+                let order_ref = be_u64(body, 0);
+                let order = OpenOrder {
+                    locate: header.locate,
+                    side: side(body[8], index)?,
+                    shares: be_u32(body, 9),
+                    price: price(be_u32(body, 21)),
+                    owner: (kind == b'F').then(|| be_u32(body, 25)),
+                };
+                self.orders.insert(order_ref, order);
+                self.push(&header, Action::Add, order_ref, &order, order.shares);
+            }
+            b'E' | b'C' => {
+                let order_ref = be_u64(body, 0);
+                let executed = be_u32(body, 8);
+                let mut order = self.open_order(order_ref, index)?;
+                let fill = OpenOrder {
+                    price: match kind {
+                        b'C' => price(be_u32(body, 21)),
+                        _ => order.price,
+                    },
+                    ..order
+                };
+                self.push(&header, Action::Fill, order_ref, &fill, executed);
+                order.shares = order.shares.saturating_sub(executed);
+                self.reduce(&header, order_ref, order);
+            }
+            b'X' => {
+                let order_ref = be_u64(body, 0);
+                let mut order = self.open_order(order_ref, index)?;
+                order.shares = order.shares.saturating_sub(be_u32(body, 8));
+                self.reduce(&header, order_ref, order);
+            }
+            b'D' => {
+                let order_ref = be_u64(body, 0);
+                let order = self.open_order(order_ref, index)?;
+                self.reduce(&header, order_ref, OpenOrder { shares: 0, ..order });
+            }
+            b'U' => {
+                let (order_ref, new_ref) = (be_u64(body, 0), be_u64(body, 8));
+                let old = self.open_order(order_ref, index)?;
+                self.orders.remove(&order_ref);
+                self.push(&header, Action::Cancel, order_ref, &old, old.shares);
+                let order = OpenOrder {
+                    shares: be_u32(body, 16),
+                    price: price(be_u32(body, 20)),
+                    ..old
+                };
+                self.orders.insert(new_ref, order);
+                self.push(&header, Action::Add, new_ref, &order, order.shares);
+            }
+            b'P' => {
+                let trade = OpenOrder {
+                    locate: header.locate,
+                    side: side(body[8], index)?,
+                    shares: be_u32(body, 9),
+                    price: price(be_u32(body, 21)),
+                    owner: None,
+                };
+                self.push(
+                    &header,
+                    Action::Trade,
+                    be_u64(body, 0),
+                    &trade,
+                    trade.shares,
+                );
+            }
+            _ => unreachable!("message type checked above"),
+        }
+        // Only the last message of the event carries the LAST flag
+        if let Some(last) = self.pending.back_mut() {
+            last.is_last = true;
+            last.flags |= flags::LAST;
+        }
+        Ok(())
+    }
+
+    fn open_order(&self, order_ref: u64, index: usize) -> Result<OpenOrder, ItchError> {
+        self.orders
+            .get(&order_ref)
+            .copied()
+            .ok_or(ItchError::UnknownOrder { index, order_ref })
+    }
+
+    /// Queues a Modify to the order's remaining shares, or a Cancel if none remain.
+    fn reduce(&mut self, header: &Header, order_ref: u64, order: OpenOrder) {
+        if order.shares == 0 {
+            self.orders.remove(&order_ref);
+            self.push(header, Action::Cancel, order_ref, &order, 0);
+        } else {
+            self.orders.insert(order_ref, order);
+            self.push(header, Action::Modify, order_ref, &order, order.shares);
+        }
+    }
+
+    fn push(
+        &mut self,
+        header: &Header,
+        action: Action,
+        order_id: u64,
+        order: &OpenOrder,
+        size: u32,
+    ) {
+        self.pending.push_back(MarketByOrderMessage {
+            instrument_id: order.locate.into(),
+            action,
+            side: order.side,
+            no_side: false,
+            price: order.price,
+            order_id,
+            size,
+            is_last: false,
+            flags: 0,
+            // ITCH messages have no sequence number; their position in the stream is
+            // used instead, so queue priority follows arrival order
+            sequence: self.index as u32,
+            event_time: header.event_time,
+            recv_time: header.event_time,
+            ts_in_delta: Duration::ZERO,
+            owner: order.owner,
+            publisher_id: 0,
+            channel_id: 0,
+        });
+    }
+}
+
+impl<R: Read> Iterator for ItchMessages<R> {
+    type Item = Result<MarketByOrderMessage, ItchError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(message) = self.pending.pop_front() {
+                return Some(Ok(message));
+            }
+            if self.done {
+                return None;
+            }
+            let index = self.index;
+            let frame = match self.read_frame() {
+                Ok(Some(frame)) => frame,
+                Ok(None) => {
+                    self.done = true;
+                    return None;
+                }
+                Err(source) => {
+                    self.done = true;
+                    return Some(Err(ItchError::Io { index, source }));
+                }
+            };
+            let result = self.convert(&frame);
+            self.index += 1;
+            if let Err(e) = result {
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+fn side(byte: u8, index: usize) -> Result<Side, ItchError> {
+    match byte {
+        b'B' => Ok(Side::Bid),
+        b'S' => Ok(Side::Ask),
+        _ => Err(ItchError::InvalidSide {
+            index,
+            side: byte as char,
+        }),
+    }
+}
+
+fn price(itch_price: u32) -> i64 {
+    i64::from(itch_price) * PRICE_SCALE
+}
+
+fn be_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn be_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(bytes[at..at + 4].try_into().expect("4-byte slice"))
+}
+
+/// The 6-byte timestamp field.
+fn be_u48(bytes: &[u8], at: usize) -> u64 {
+    bytes[at..at + 6]
+        .iter()
+        .fold(0, |value, &byte| value << 8 | u64::from(byte))
+}
+
+fn be_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_be_bytes(bytes[at..at + 8].try_into().expect("8-byte slice"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::orderbook::MboProcessor;
+
+    /// 09:30 in nanoseconds since midnight, which needs all six timestamp bytes.
+    const OPEN: u64 = 34_200_000_000_000;
+
+    /// A length-prefixed message with the common header.
+    fn frame(kind: u8, locate: u16, timestamp: u64, body: &[u8]) -> Vec<u8> {
+        let mut message = vec![kind];
+        message.extend(locate.to_be_bytes());
+        message.extend(0u16.to_be_bytes());
+        message.extend(&timestamp.to_be_bytes()[2..]);
+        message.extend(body);
+        let mut bytes = (message.len() as u16).to_be_bytes().to_vec();
+        bytes.extend(message);
+        bytes
+    }
+
+    fn stock(symbol: &str) -> [u8; 8] {
+        let mut stock = [b' '; 8];
+        stock[..symbol.len()].copy_from_slice(symbol.as_bytes());
+        stock
+    }
+
+    fn add(order_ref: u64, side: u8, shares: u32, price: u32, mpid: Option<&[u8; 4]>) -> Vec<u8> {
+        let mut body = order_ref.to_be_bytes().to_vec();
+        body.push(side);
+        body.extend(shares.to_be_bytes());
+        body.extend(stock("AAPL"));
+        body.extend(price.to_be_bytes());
+        match mpid {
+            Some(mpid) => {
+                body.extend(mpid);
+                frame(b'F', 7, OPEN, &body)
+            }
+            None => frame(b'A', 7, OPEN, &body),
+        }
+    }
+
+    fn executed(order_ref: u64, shares: u32, price: Option<u32>) -> Vec<u8> {
+        let mut body = order_ref.to_be_bytes().to_vec();
+        body.extend(shares.to_be_bytes());
+        body.extend(99u64.to_be_bytes());
+        match price {
+            Some(price) => {
+                body.push(b'Y');
+                body.extend(price.to_be_bytes());
+                frame(b'C', 7, OPEN + 1, &body)
+            }
+            None => frame(b'E', 7, OPEN + 1, &body),
+        }
+    }
+
+    fn cancel(order_ref: u64, shares: u32) -> Vec<u8> {
+        let mut body = order_ref.to_be_bytes().to_vec();
+        body.extend(shares.to_be_bytes());
+        frame(b'X', 7, OPEN + 2, &body)
+    }
+
+    fn delete(order_ref: u64) -> Vec<u8> {
+        frame(b'D', 7, OPEN + 3, &order_ref.to_be_bytes())
+    }
+
+    fn replace(order_ref: u64, new_ref: u64, shares: u32, price: u32) -> Vec<u8> {
+        let mut body = order_ref.to_be_bytes().to_vec();
+        body.extend(new_ref.to_be_bytes());
+        body.extend(shares.to_be_bytes());
+        body.extend(price.to_be_bytes());
+        frame(b'U', 7, OPEN + 4, &body)
+    }
+
+    fn parse(bytes: &[u8]) -> Vec<MarketByOrderMessage> {
+        itch_messages(bytes).collect::<Result<_, _>>().unwrap()
+    }
+
+    fn summary(messages: &[MarketByOrderMessage]) -> Vec<(Action, u64, u32, bool)> {
+        messages
+            .iter()
+            .map(|m| (m.action, m.order_id, m.size, m.is_last))
+            .collect()
+    }
+
+    #[test]
+    fn test_add_order_with_and_without_mpid() {
+        let bytes = [
+            add(1, b'B', 100, 1_500_000, None),
+            add(2, b'S', 50, 1_500_100, Some(b"GSCO")),
+        ]
+        .concat();
+        let messages = parse(&bytes);
+        assert_eq!(messages.len(), 2);
+
+        let bid = &messages[0];
+        assert_eq!(bid.action, Action::Add);
+        assert_eq!(bid.instrument_id, 7);
+        assert_eq!((bid.side, bid.order_id, bid.size), (Side::Bid, 1, 100));
+        // $150.0000 in dbn fixed-point
+        assert_eq!(bid.price, 150_000_000_000);
+        assert_eq!(
+            bid.event_time,
+            OffsetDateTime::UNIX_EPOCH + Duration::hours(9) + Duration::minutes(30)
+        );
+        assert!(bid.is_last);
+
+        assert_eq!(bid.owner, None);
+
+        let ask = &messages[1];
+        assert_eq!((ask.side, ask.order_id, ask.size), (Side::Ask, 2, 50));
+        assert_eq!(ask.price, 150_010_000_000);
+        assert_eq!(ask.owner, Some(u32::from_be_bytes(*b"GSCO")));
+    }
+
+    #[test]
+    fn test_executions_become_fills() {
+        let bytes = [
+            add(1, b'B', 100, 1_500_000, None),
+            executed(1, 30, None),
+            executed(1, 70, Some(1_499_900)),
+        ]
+        .concat();
+        let mut stream = itch_messages(&bytes[..]);
+        let messages: Vec<_> = stream.by_ref().collect::<Result<_, _>>().unwrap();
+        assert_eq!(
+            summary(&messages),
+            vec![
+                (Action::Add, 1, 100, true),
+                (Action::Fill, 1, 30, false),
+                (Action::Modify, 1, 70, true),
+                (Action::Fill, 1, 70, false),
+                (Action::Cancel, 1, 0, true),
+            ]
+        );
+        // Executed With Price reports the execution price, not the order's
+        assert_eq!(messages[2].price, 150_000_000_000);
+        assert_eq!(messages[3].price, 149_990_000_000);
+        assert_eq!(stream.messages_read(), 3);
+        assert_eq!(stream.open_orders(), 0);
+    }
+
+    #[test]
+    fn test_cancel_and_delete() {
+        let bytes = [
+            add(1, b'S', 100, 1_500_000, None),
+            add(2, b'S', 40, 1_500_000, None),
+            cancel(1, 25),
+            cancel(2, 40),
+            delete(1),
+        ]
+        .concat();
+        let messages = parse(&bytes);
+        assert_eq!(
+            summary(&messages[2..]),
+            vec![
+                (Action::Modify, 1, 75, true),
+                (Action::Cancel, 2, 0, true),
+                (Action::Cancel, 1, 0, true),
+            ]
+        );
+
+        let mut proc = MboProcessor::new();
+        assert!(proc.process_messages(&messages[..3]).is_success());
+        assert_eq!(proc.best_ask(), Some((150_000_000_000, 115)));
+        assert!(proc.process_messages(&messages[3..]).is_success());
+        assert_eq!(proc.best_ask(), None);
+    }
+
+    #[test]
+    fn test_replace_chain() {
+        let bytes = [
+            add(1, b'B', 100, 1_500_000, None),
+            add(2, b'B', 10, 1_499_000, None),
+            replace(1, 3, 80, 1_499_000),
+            replace(3, 4, 60, 1_499_000),
+        ]
+        .concat();
+        let messages = parse(&bytes);
+        assert_eq!(
+            summary(&messages[2..]),
+            vec![
+                (Action::Cancel, 1, 100, false),
+                (Action::Add, 3, 80, true),
+                (Action::Cancel, 3, 80, false),
+                (Action::Add, 4, 60, true),
+            ]
+        );
+        // The replacement keeps the original side
+        assert!(messages.iter().all(|m| m.side == Side::Bid));
+
+        let mut proc = MboProcessor::new();
+        assert!(proc.process_messages(&messages).is_success());
+        let book = proc.book(7).unwrap();
+        assert_eq!(book.top_n_bids(5), vec![(149_900_000_000, 70)]);
+        assert!(book.get_order(1).is_none() && book.get_order(3).is_none());
+        // A replaced order goes behind the order already resting at its new price
+        assert_eq!(book.queue_position(2), Some(0));
+        assert_eq!(book.queue_position(4), Some(1));
+    }
+
+    #[test]
+    fn test_stock_directory_and_trade() {
+        let mut directory = stock("MSFT").to_vec();
+        directory.extend([0u8; 20]);
+        let mut trade = 0u64.to_be_bytes().to_vec();
+        trade.push(b'B');
+        trade.extend(200u32.to_be_bytes());
+        trade.extend(stock("MSFT"));
+        trade.extend(4_100_000u32.to_be_bytes());
+        trade.extend(5u64.to_be_bytes());
+        let bytes = [
+            // System Event: start of messages, skipped
+            frame(b'S', 0, 0, b"O"),
+            frame(b'R', 12, 0, &directory),
+            frame(b'P', 12, OPEN, &trade),
+        ]
+        .concat();
+
+        let mut stream = itch_messages(&bytes[..]);
+        let messages: Vec<_> = stream.by_ref().collect::<Result<_, _>>().unwrap();
+        assert_eq!(stream.symbols().latest_symbol(12), Some("MSFT"));
+        assert_eq!(messages.len(), 1);
+        let trade = &messages[0];
+        assert_eq!(trade.action, Action::Trade);
+        assert_eq!(trade.instrument_id, 12);
+        assert_eq!((trade.side, trade.size), (Side::Bid, 200));
+        assert_eq!(trade.price, 410_000_000_000);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut bytes = [
+            delete(9),
+            add(1, b'?', 100, 1_500_000, None),
+            add(2, b'B', 100, 1_500_000, None),
+        ]
+        .concat();
+        // A message cut off by the end of the stream
+        bytes.extend(&add(3, b'B', 100, 1_500_000, None)[..10]);
+
+        let results: Vec<_> = itch_messages(&bytes[..]).collect();
+        assert_eq!(results.len(), 4);
+        assert!(matches!(
+            results[0],
+            Err(ItchError::UnknownOrder {
+                index: 0,
+                order_ref: 9
+            })
+        ));
+        assert!(matches!(
+            results[1],
+            Err(ItchError::InvalidSide {
+                index: 1,
+                side: '?'
+            })
+        ));
+        assert_eq!(results[2].as_ref().unwrap().order_id, 2);
+        assert!(matches!(results[3], Err(ItchError::Io { index: 3, .. })));
+
+        let short = frame(b'D', 7, 0, &[0; 4]);
+        assert!(matches!(
+            itch_messages(&short[..]).next(),
+            Some(Err(ItchError::Truncated {
+                kind: 'D',
+                len: 15,
+                expected: 19,
+                ..
+            }))
+        ));
+    }
+}
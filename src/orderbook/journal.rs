@@ -0,0 +1,146 @@
+//! Undo journal backing `MboProcessor::rollback`.
+//!
+//! Each processed message records the inverse of its effect on the book together
+//! with the processor state it overwrote. The journal is bounded; once full, the
+//! oldest entry is dropped for every new one.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use time::{Duration, OffsetDateTime};
+
+use crate::orderbook::flow::FlowMark;
+use crate::orderbook::{Anomalies, BookHandler, BookKey, MboStats, Order};
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum RollbackError {
+    #[error("Cannot roll back {requested} messages, only {available} are journaled")]
+    InsufficientHistory { requested: usize, available: usize },
+}
+
+/// Inverse of a message's effect on its instrument's book.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum Undo<B> {
+    /// The message did not change the book (Fill, Trade, unknown Cancel, failed Modify).
+    Nothing,
+    /// Remove an added order, restoring the order it replaced, if any.
+    RemoveAdded {
+        order_id: u64,
+        replaced: Option<Order>,
+    },
+    /// Re-insert a cancelled order at its original queue position.
+    Restore(Order),
+    /// Replace a modified order with its prior state.
+    ReplaceWith(Order),
+    /// Put back the whole book discarded by a Clear or the start of a snapshot.
+    RestoreBook(Box<B>),
+    /// Several changes made by one message, undone in reverse order.
+    Sequence(Vec<Undo<B>>),
+}
+
+impl<B: BookHandler> Undo<B> {
+    pub fn apply(self, book: &mut B) {
+        match self {
+            Undo::Nothing => {}
+            Undo::RemoveAdded { order_id, replaced } => {
+                book.remove_order(order_id);
+                if let Some(order) = replaced {
+                    book.add_order(order);
+                }
+            }
+            Undo::Restore(order) => {
+                book.add_order(order);
+            }
+            Undo::ReplaceWith(order) => {
+                book.remove_order(order.order_id);
+                book.add_order(order);
+            }
+            Undo::RestoreBook(old_book) => book.restore(*old_book),
+            Undo::Sequence(undos) => undos.into_iter().rev().for_each(|undo| undo.apply(book)),
+        }
+    }
+}
+
+/// Processor state overwritten by a message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ProcessorMark {
+    pub key: BookKey,
+    /// True if the message created the instrument's book.
+    pub new_instrument: bool,
+    pub stats: MboStats,
+    /// Quantity the message added to each owner's executed quantity.
+    pub executed: Vec<(u32, u64)>,
+    /// Orders purged as expired before the message applied.
+    #[serde(default)]
+    pub expired: Vec<Order>,
+    pub anomalies: Anomalies,
+    #[serde(default)]
+    pub flow: FlowMark,
+    pub in_snapshot: bool,
+    pub last_key: BookKey,
+    pub event_complete: bool,
+    pub sequence_number: u32,
+    pub last_event_time: OffsetDateTime,
+    pub last_recv_time: OffsetDateTime,
+    pub last_ts_in_delta: Duration,
+    #[serde(default)]
+    pub last_flags: u8,
+    #[serde(default)]
+    pub last_publisher_id: u16,
+    #[serde(default)]
+    pub last_channel_id: u8,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct JournalEntry<B> {
+    pub mark: ProcessorMark,
+    pub undo: Undo<B>,
+}
+
+/// Bounded journal of the most recent messages. A depth of zero disables journaling.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub(crate) struct Journal<B> {
+    depth: usize,
+    entries: VecDeque<JournalEntry<B>>,
+}
+
+impl<B> Journal<B> {
+    pub fn with_depth(depth: usize) -> Self {
+        Self {
+            depth,
+            entries: VecDeque::with_capacity(depth),
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.depth > 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn push(&mut self, entry: JournalEntry<B>) {
+        if self.entries.len() == self.depth {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Removes the `n` most recent entries, newest first.
+    pub fn take_last(&mut self, n: usize) -> Result<Vec<JournalEntry<B>>, RollbackError> {
+        let available = self.entries.len();
+        if n > available {
+            return Err(RollbackError::InsufficientHistory {
+                requested: n,
+                available,
+            });
+        }
+        Ok(self.entries.drain(available - n..).rev().collect())
+    }
+}
@@ -0,0 +1,506 @@
+//! Level-based (L2) incremental updates derived from MBO processing.
+//!
+//! `L2Publisher` observes an `MboProcessor` and turns each applied message into the
+//! changes it made to price levels, for consumers that keep a depth book rather than
+//! individual orders.
+
+use std::collections::HashMap;
+use std::mem;
+#[cfg(feature = "polars")]
+use std::path::Path;
+
+#[cfg(feature = "polars")]
+use polars::prelude::{DataFrame, IpcWriter, ParquetWriter, PolarsResult, SerWriter, df};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+#[cfg(feature = "polars")]
+use crate::orderbook::create_output;
+use crate::orderbook::events::{
+    OrderAddedEvent, OrderCancelledEvent, OrderModifiedEvent, TradeEvent,
+};
+use crate::orderbook::{
+    BookKey, MarketByOrderMessage, MarketByPrice, MboObserver, OrderBook, OrderLevelSummary, Side,
+};
+
+/// What an `L2Update` does to its level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum L2Action {
+    /// The level now holds `new_qty` in `new_count` orders, whether new or changed.
+    Set,
+    /// The level is gone; `new_qty` and `new_count` are 0.
+    Delete,
+}
+
+/// A change to one price level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct L2Update {
+    pub side: Side,
+    pub price: i64,
+    pub new_qty: u64,
+    pub new_count: usize,
+    pub action: L2Action,
+}
+
+/// The level changes made by one MBO message, to be applied together.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct L2Batch {
+    pub instrument_id: u32,
+    /// Publisher of the book, zero unless the processor routes by publisher.
+    pub publisher_id: u16,
+    /// Venue sequence number of the source message.
+    pub sequence: u32,
+    /// Exchange event timestamp of the source message.
+    pub event_time: OffsetDateTime,
+    /// Server receive timestamp of the source message.
+    pub recv_time: OffsetDateTime,
+    /// True if the source message ended its event, leaving the book consistent.
+    pub is_last: bool,
+    /// By side, then price.
+    pub updates: Vec<L2Update>,
+}
+
+impl L2Batch {
+    /// Applies the updates to `mbp`, a depth view of the batch's book.
+    pub fn apply_to(&self, mbp: &mut MarketByPrice) {
+        for update in &self.updates {
+            let levels = match update.side {
+                Side::Bid => &mut mbp.bids,
+                Side::Ask => &mut mbp.asks,
+            };
+            match update.action {
+                L2Action::Set => levels.insert(
+                    update.price,
+                    OrderLevelSummary {
+                        price: update.price,
+                        total_quantity: update.new_qty,
+                        order_count: update.new_count,
+                    },
+                ),
+                L2Action::Delete => levels.remove(&update.price),
+            };
+        }
+    }
+}
+
+/// Receives the batches of an `L2Publisher` as they are made.
+pub trait L2Sink {
+    fn publish(&mut self, batch: &L2Batch);
+}
+
+/// Accumulates the batches, to be drained with `L2Publisher::drain`.
+impl L2Sink for Vec<L2Batch> {
+    fn publish(&mut self, batch: &L2Batch) {
+        self.push(batch.clone());
+    }
+}
+
+/// Calls back with each batch.
+impl<F: FnMut(&L2Batch)> L2Sink for F {
+    fn publish(&mut self, batch: &L2Batch) {
+        self(batch)
+    }
+}
+
+/// Observer turning each applied MBO message into an `L2Batch` of the levels it changed,
+/// skipping messages that changed none.
+///
+/// Like `IncrementalMbp`, it notes the levels a message's callbacks touch and compares
+/// them with the book in `on_message_applied`, against the levels it last published for
+/// that book: a Modify that moves an order publishes its old level (Set, or Delete if
+/// emptied) and its new one in the same batch, and a Clear or snapshot start Deletes
+/// every level the book no longer has. Applying every batch to an empty
+/// `MarketByPrice` gives `MarketByPrice::from` of the book after each message. Books
+/// changed without callbacks, by `MboProcessor::seed_book` or `MboProcessor::rollback`,
+/// are not published.
+///
+/// `L2Publisher::new()` accumulates the batches for `drain` and `to_dataframe`;
+/// `with_sink` hands them to any `L2Sink`, such as a closure.
+#[derive(Debug, Default)]
+pub struct L2Publisher<S: L2Sink = Vec<L2Batch>> {
+    sink: S,
+    /// The levels as last published, per book.
+    published: HashMap<BookKey, MarketByPrice>,
+    /// Levels touched by the message being applied.
+    touched: Vec<(Side, i64)>,
+    /// True if the message being applied cleared the book.
+    cleared: bool,
+}
+
+impl L2Publisher {
+    /// A publisher accumulating its batches.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The batches accumulated since the last `drain`.
+    pub fn batches(&self) -> &[L2Batch] {
+        &self.sink
+    }
+
+    /// Takes the accumulated batches, oldest first.
+    pub fn drain(&mut self) -> Vec<L2Batch> {
+        mem::take(&mut self.sink)
+    }
+
+    /// Converts the accumulated batches into a DataFrame with one row per update.
+    ///
+    /// `batch` numbers the batches from 0, so rows to apply together share it;
+    /// timestamps are nanoseconds since the UNIX epoch, `side` is `"B"` or `"A"` and
+    /// `action` is `"set"` or `"delete"`.
+    #[cfg(feature = "polars")]
+    pub fn to_dataframe(&self) -> PolarsResult<DataFrame> {
+        let rows: Vec<(u32, &L2Batch, &L2Update)> = (0..)
+            .zip(&self.sink)
+            .flat_map(|(index, batch)| batch.updates.iter().map(move |u| (index, batch, u)))
+            .collect();
+        df!(
+            "batch" => rows.iter().map(|&(index, _, _)| index).collect::<Vec<_>>(),
+            "instrument_id" => rows.iter().map(|(_, b, _)| b.instrument_id).collect::<Vec<_>>(),
+            "publisher_id" => rows.iter().map(|(_, b, _)| b.publisher_id).collect::<Vec<_>>(),
+            "ts_event" => rows.iter().map(|(_, b, _)| b.event_time.unix_timestamp_nanos() as i64).collect::<Vec<_>>(),
+            "ts_recv" => rows.iter().map(|(_, b, _)| b.recv_time.unix_timestamp_nanos() as i64).collect::<Vec<_>>(),
+            "sequence" => rows.iter().map(|(_, b, _)| b.sequence).collect::<Vec<_>>(),
+            "side" => rows.iter().map(|(_, _, u)| match u.side { Side::Bid => "B", Side::Ask => "A" }).collect::<Vec<_>>(),
+            "price" => rows.iter().map(|(_, _, u)| u.price).collect::<Vec<_>>(),
+            "qty" => rows.iter().map(|(_, _, u)| u.new_qty).collect::<Vec<_>>(),
+            "count" => rows.iter().map(|(_, _, u)| u.new_count as u64).collect::<Vec<_>>(),
+            "action" => rows.iter().map(|(_, _, u)| match u.action { L2Action::Set => "set", L2Action::Delete => "delete" }).collect::<Vec<_>>(),
+        )
+    }
+
+    /// Writes the DataFrame from `to_dataframe` to a parquet file.
+    #[cfg(feature = "polars")]
+    pub fn write_parquet(&self, path: impl AsRef<Path>) -> PolarsResult<()> {
+        let mut df = self.to_dataframe()?;
+        ParquetWriter::new(create_output(path, true)?).finish(&mut df)?;
+        Ok(())
+    }
+
+    /// Writes the same DataFrame to an Arrow IPC (Feather v2) file.
+    #[cfg(feature = "polars")]
+    pub fn write_ipc(&self, path: impl AsRef<Path>) -> PolarsResult<()> {
+        let mut df = self.to_dataframe()?;
+        IpcWriter::new(create_output(path, true)?).finish(&mut df)?;
+        Ok(())
+    }
+}
+
+impl<S: L2Sink> L2Publisher<S> {
+    /// A publisher handing each batch to `sink`.
+    pub fn with_sink(sink: S) -> Self {
+        Self {
+            sink,
+            published: HashMap::new(),
+            touched: Vec::new(),
+            cleared: false,
+        }
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn into_sink(self) -> S {
+        self.sink
+    }
+
+    fn touch(&mut self, side: Side, price: i64) {
+        self.touched.push((side, price));
+    }
+}
+
+impl<S: L2Sink> MboObserver for L2Publisher<S> {
+    fn on_order_added(&mut self, event: &OrderAddedEvent) {
+        self.touch(event.order.side, event.order.price);
+        if let Some((side, price)) = event.replaced_level {
+            self.touch(side, price);
+        }
+    }
+
+    fn on_order_cancelled(&mut self, event: &OrderCancelledEvent) {
+        self.touch(event.order.side, event.order.price);
+    }
+
+    fn on_order_modified(&mut self, event: &OrderModifiedEvent) {
+        self.touch(event.order.side, event.order.price);
+        // A Modify can move the order to the other side
+        self.touch(Side::Bid, event.old_price);
+        self.touch(Side::Ask, event.old_price);
+    }
+
+    fn on_trade(&mut self, event: &TradeEvent) {
+        // Resting orders executed by a crossing Add
+        if !event.aggressor {
+            self.touch(event.side, event.price);
+        }
+    }
+
+    fn on_clear(&mut self) {
+        self.cleared = true;
+    }
+
+    fn on_message_applied(
+        &mut self,
+        message: &MarketByOrderMessage,
+        key: BookKey,
+        book: &OrderBook,
+    ) {
+        let published = self.published.entry(key).or_default();
+        if mem::take(&mut self.cleared) {
+            let bids = published.bids.keys().map(|&price| (Side::Bid, price));
+            let asks = published.asks.keys().map(|&price| (Side::Ask, price));
+            self.touched.extend(bids.chain(asks));
+        }
+        self.touched
+            .sort_unstable_by_key(|&(side, price)| (side == Side::Ask, price));
+        self.touched.dedup();
+
+        let mut updates = Vec::new();
+        for (side, price) in self.touched.drain(..) {
+            let (levels, summaries) = match side {
+                Side::Bid => (book.bids(), &mut published.bids),
+                Side::Ask => (book.asks(), &mut published.asks),
+            };
+            match levels.get(&price).map(OrderLevelSummary::from) {
+                Some(level) if summaries.get(&price) != Some(&level) => {
+                    summaries.insert(price, level);
+                    updates.push(L2Update {
+                        side,
+                        price,
+                        new_qty: level.total_quantity,
+                        new_count: level.order_count,
+                        action: L2Action::Set,
+                    });
+                }
+                Some(_) => {}
+                None => {
+                    if summaries.remove(&price).is_some() {
+                        updates.push(L2Update {
+                            side,
+                            price,
+                            new_qty: 0,
+                            new_count: 0,
+                            action: L2Action::Delete,
+                        });
+                    }
+                }
+            }
+        }
+        if !updates.is_empty() {
+            self.sink.publish(&L2Batch {
+                instrument_id: key.instrument_id,
+                publisher_id: key.publisher_id,
+                sequence: message.sequence,
+                event_time: message.event_time,
+                recv_time: message.recv_time,
+                is_last: message.is_last,
+                updates,
+            });
+        }
+    }
+
+    /// Publishes the levels of orders purged before the message failed, under its
+    /// sequence.
+    fn on_message_rejected(
+        &mut self,
+        message: &MarketByOrderMessage,
+        key: BookKey,
+        book: &OrderBook,
+    ) {
+        self.on_message_applied(message, key, book);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Duration;
+
+    use crate::generators::{MessageMix, OrderGenerator};
+    use crate::orderbook::{
+        Action, CrossingPolicy, ErrorPolicy, IncrementalMbp, MboProcessor, ValidationPolicy,
+    };
+
+    fn msg(
+        sequence: u32,
+        action: Action,
+        order_id: u64,
+        side: Side,
+        price: i64,
+        size: u32,
+    ) -> MarketByOrderMessage {
+        MarketByOrderMessage {
+            sequence,
+            ..MarketByOrderMessage::test(action, side, price, order_id, size)
+                .at(OffsetDateTime::UNIX_EPOCH + Duration::seconds(i64::from(sequence)))
+        }
+    }
+
+    fn set(side: Side, price: i64, new_qty: u64, new_count: usize) -> L2Update {
+        L2Update {
+            side,
+            price,
+            new_qty,
+            new_count,
+            action: L2Action::Set,
+        }
+    }
+
+    fn delete(side: Side, price: i64) -> L2Update {
+        L2Update {
+            side,
+            price,
+            new_qty: 0,
+            new_count: 0,
+            action: L2Action::Delete,
+        }
+    }
+
+    #[test]
+    fn test_l2_stream_reproduces_mbp_at_every_message() {
+        let messages = OrderGenerator::default_seeded(11)
+            .make_lifecycle_messages(20_000, MessageMix::default());
+        let mut processor = MboProcessor::with_observer(L2Publisher::new())
+            .with_crossing_policy(CrossingPolicy::Match)
+            .with_validation_policy(ValidationPolicy::PassThrough)
+            .with_error_policy(ErrorPolicy::Collect);
+        let mut mbp = MarketByPrice::new();
+        let mut published = 0;
+        // A Clear half way through deletes every level
+        let clear = msg(u32::MAX, Action::Clear, 0, Side::Bid, 0, 0);
+        let half = messages.len() / 2;
+        for (index, message) in messages[..half]
+            .iter()
+            .chain([&clear])
+            .chain(&messages[half..])
+            .enumerate()
+        {
+            // Failed messages change nothing, so publish nothing
+            let _ = processor.process_message(message);
+            for batch in processor.observer_mut().drain() {
+                assert_eq!(batch.sequence, message.sequence);
+                batch.apply_to(&mut mbp);
+                published += 1;
+            }
+            let expected = processor
+                .book(message.instrument_id)
+                .map(MarketByPrice::from)
+                .unwrap_or_default();
+            assert_eq!(
+                (&mbp.bids, &mbp.asks),
+                (&expected.bids, &expected.asks),
+                "after message {index}"
+            );
+        }
+        assert!(published > messages.len() / 2);
+    }
+
+    #[test]
+    fn test_modify_moving_price_publishes_one_batch() {
+        let mut batches = Vec::new();
+        let mut processor =
+            MboProcessor::with_observer(L2Publisher::with_sink(|batch: &L2Batch| {
+                batches.push(batch.updates.clone())
+            }));
+        processor.process_messages([
+            msg(1, Action::Add, 1, Side::Bid, 100, 10),
+            msg(2, Action::Add, 2, Side::Bid, 100, 5),
+            msg(3, Action::Add, 3, Side::Bid, 99, 7),
+            // To a new level, then onto another order's
+            msg(4, Action::Modify, 1, Side::Bid, 101, 10),
+            msg(5, Action::Modify, 2, Side::Bid, 99, 5),
+            // Nothing changes at any level
+            msg(6, Action::Fill, 3, Side::Bid, 99, 1),
+            msg(7, Action::Cancel, 42, Side::Bid, 99, 1),
+        ]);
+        drop(processor);
+
+        assert_eq!(
+            batches,
+            vec![
+                vec![set(Side::Bid, 100, 10, 1)],
+                vec![set(Side::Bid, 100, 15, 2)],
+                vec![set(Side::Bid, 99, 7, 1)],
+                vec![set(Side::Bid, 100, 5, 1), set(Side::Bid, 101, 10, 1)],
+                vec![set(Side::Bid, 99, 12, 2), delete(Side::Bid, 100)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rejected_message_publishes_its_purge() {
+        let mut processor =
+            MboProcessor::with_observer((L2Publisher::new(), IncrementalMbp::new()))
+                .with_order_ttl(Duration::seconds(2))
+                .with_error_policy(ErrorPolicy::Collect);
+        let mut batches = Vec::new();
+        for message in [
+            msg(1, Action::Add, 1, Side::Bid, 100, 10),
+            msg(2, Action::Add, 2, Side::Bid, 99, 5),
+            // Both orders expire first, then the Modify fails
+            msg(5, Action::Modify, 42, Side::Bid, 99, 1),
+            msg(6, Action::Add, 3, Side::Ask, 105, 4),
+        ] {
+            let result = processor.process_message(&message);
+            assert_eq!(result.is_err(), message.action == Action::Modify);
+            let (publisher, _) = processor.observer_mut();
+            batches.extend(
+                publisher
+                    .drain()
+                    .into_iter()
+                    .map(|batch| (batch.sequence, batch.updates)),
+            );
+            let expected = MarketByPrice::from(processor.book(1).unwrap());
+            let view = processor.observer().1.mbp(1).unwrap();
+            assert_eq!((&view.bids, &view.asks), (&expected.bids, &expected.asks));
+        }
+
+        assert_eq!(
+            batches,
+            vec![
+                (1, vec![set(Side::Bid, 100, 10, 1)]),
+                (2, vec![set(Side::Bid, 99, 5, 1)]),
+                (5, vec![delete(Side::Bid, 99), delete(Side::Bid, 100)]),
+                (6, vec![set(Side::Ask, 105, 4, 1)]),
+            ]
+        );
+    }
+
+    #[cfg(feature = "polars")]
+    #[test]
+    fn test_to_dataframe_rows() {
+        let mut processor = MboProcessor::with_observer(L2Publisher::new());
+        processor.process_messages([
+            msg(1, Action::Add, 1, Side::Bid, 100, 10),
+            msg(2, Action::Add, 2, Side::Ask, 101, 4),
+            msg(3, Action::Modify, 1, Side::Bid, 99, 10),
+        ]);
+        let df = processor.observer().to_dataframe().unwrap();
+        assert_eq!(df.shape(), (4, 11));
+
+        let batch = df.column("batch").unwrap().u32().unwrap();
+        let side = df.column("side").unwrap().str().unwrap();
+        let price = df.column("price").unwrap().i64().unwrap();
+        let action = df.column("action").unwrap().str().unwrap();
+        let rows: Vec<_> = (0..df.height())
+            .map(|i| {
+                (
+                    batch.get(i).unwrap(),
+                    side.get(i).unwrap(),
+                    price.get(i).unwrap(),
+                    action.get(i).unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (0, "B", 100, "set"),
+                (1, "A", 101, "set"),
+                (2, "B", 99, "set"),
+                (2, "B", 100, "delete"),
+            ]
+        );
+    }
+}
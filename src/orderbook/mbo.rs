@@ -1,16 +1,38 @@
-use dbn::MboMsg;
+use std::any;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::ffi::c_char;
+use std::iter::{self, Peekable};
+use std::sync::LazyLock;
+
 use dbn::enums::Action as DbnAction;
-use dbn::enums::Side as DbnSide;
+use dbn::flags;
+use dbn::{MboMsg, UNDEF_PRICE};
 use num_enum::{IntoPrimitive, TryFromPrimitive};
+use serde::{Deserialize, Serialize};
 use strum::Display;
 use thiserror::Error;
 use time::{Duration, OffsetDateTime};
-use tracing::debug;
+use tracing::{debug, warn};
+
+#[cfg(feature = "polars")]
+use polars::prelude::{DataFrame, PolarsResult};
 
+use crate::orderbook::RollbackError;
+use crate::orderbook::anomaly::{AnomalyLog, AnomalyRecord};
+use crate::orderbook::book::StateHasher;
 use crate::orderbook::events::{
     OrderAddedEvent, OrderCancelledEvent, OrderModifiedEvent, TradeEvent,
 };
-use crate::orderbook::{Order, OrderBook, OrderBookError, Side};
+use crate::orderbook::flow::FlowTracker;
+use crate::orderbook::journal::{Journal, JournalEntry, ProcessorMark, Undo};
+use crate::orderbook::{
+    Anomalies, BboHistory, BookHandler, ConversionError, DepthLimitPolicy, EventRetention,
+    FlowStats, MarketByPrice, MatchOutcome, MboStats, ModifyPriorityPolicy, Order, OrderBook,
+    OrderBookError, ParticipantStats, ProcessFailure, ProcessSummary, QuoteStats, SeedMode,
+    SelfMatchPolicy, Side, TradeQuality, ValidationError, ValidationPolicy, validate,
+};
 
 /// Observer trait for reacting to MBO message processing events.
 ///
@@ -19,8 +41,9 @@ use crate::orderbook::{Order, OrderBook, OrderBookError, Side};
 ///
 /// The processor is generic over this trait: `MboProcessor<O: MboObserver>`.
 /// Multiple observers can be composed via tuples: `(A, B)` where both implement
-/// `MboObserver`.
-pub trait MboObserver {
+/// `MboObserver`. `B` is the book type of the processor, `OrderBook` unless it was
+/// created with another `BookHandler`.
+pub trait MboObserver<B = OrderBook> {
     /// Called after an Add action places an order in the book.
     fn on_order_added(&mut self, _event: &OrderAddedEvent) {}
 
@@ -34,15 +57,34 @@ pub trait MboObserver {
     /// The `aggressor` field distinguishes Trade (true) from Fill (false).
     fn on_trade(&mut self, _event: &TradeEvent) {}
 
-    /// Called after a Clear action resets the book.
+    /// Called after a Clear action resets the book. A Clear of one side reports its
+    /// orders through `on_order_cancelled` instead.
     fn on_clear(&mut self) {}
 
+    /// Called before a Clear, if enabled with `MboProcessor::with_session_snapshots`,
+    /// with the Clear and a snapshot of the book as the session left it, stamped with
+    /// the Clear's timestamps and sequence.
+    fn on_session_snapshot(&mut self, _message: &MarketByOrderMessage, _snapshot: &MarketByPrice) {}
+
+    /// Called after every applied message with the book it was routed to, under `key`
+    /// (see `MboProcessor::book_key`), which may be mid-event. Observers keeping state
+    /// per book key it by `key` rather than the instrument, as two publishers of one
+    /// instrument have a book each under publisher routing. Messages skipped by
+    /// validation or that fail are not reported.
+    fn on_message_applied(&mut self, _message: &MarketByOrderMessage, _key: BookKey, _book: &B) {}
+
+    /// Called instead of `on_message_applied` when a message fails, with the book it
+    /// was routed to. Orders purged by `MboProcessor::with_order_ttl` before the
+    /// failure stay removed and have been reported through `on_order_cancelled`, so
+    /// observers that buffer a message's callbacks settle them here.
+    fn on_message_rejected(&mut self, _message: &MarketByOrderMessage, _key: BookKey, _book: &B) {}
+
     /// Called after any message where `is_last` is true.
     /// The book is in a consistent state at this point, suitable for
     /// snapshot extraction or top-of-book sampling.
     fn on_event_complete(
         &mut self,
-        _book: &OrderBook,
+        _book: &B,
         _event_time: OffsetDateTime,
         _recv_time: OffsetDateTime,
     ) {
@@ -50,11 +92,74 @@ pub trait MboObserver {
 }
 
 /// Zero-cost no-op observer. All methods are optimized away by the compiler.
-impl MboObserver for () {}
+impl<B> MboObserver<B> for () {}
+
+/// Optional observer: forwards every event when `Some`, does nothing when `None`.
+/// Useful when an observer is enabled by runtime configuration.
+impl<B, O: MboObserver<B>> MboObserver<B> for Option<O> {
+    fn on_order_added(&mut self, event: &OrderAddedEvent) {
+        if let Some(observer) = self {
+            observer.on_order_added(event);
+        }
+    }
+
+    fn on_order_cancelled(&mut self, event: &OrderCancelledEvent) {
+        if let Some(observer) = self {
+            observer.on_order_cancelled(event);
+        }
+    }
+
+    fn on_order_modified(&mut self, event: &OrderModifiedEvent) {
+        if let Some(observer) = self {
+            observer.on_order_modified(event);
+        }
+    }
+
+    fn on_trade(&mut self, event: &TradeEvent) {
+        if let Some(observer) = self {
+            observer.on_trade(event);
+        }
+    }
+
+    fn on_clear(&mut self) {
+        if let Some(observer) = self {
+            observer.on_clear();
+        }
+    }
+
+    fn on_session_snapshot(&mut self, message: &MarketByOrderMessage, snapshot: &MarketByPrice) {
+        if let Some(observer) = self {
+            observer.on_session_snapshot(message, snapshot);
+        }
+    }
+
+    fn on_message_applied(&mut self, message: &MarketByOrderMessage, key: BookKey, book: &B) {
+        if let Some(observer) = self {
+            observer.on_message_applied(message, key, book);
+        }
+    }
+
+    fn on_message_rejected(&mut self, message: &MarketByOrderMessage, key: BookKey, book: &B) {
+        if let Some(observer) = self {
+            observer.on_message_rejected(message, key, book);
+        }
+    }
+
+    fn on_event_complete(
+        &mut self,
+        book: &B,
+        event_time: OffsetDateTime,
+        recv_time: OffsetDateTime,
+    ) {
+        if let Some(observer) = self {
+            observer.on_event_complete(book, event_time, recv_time);
+        }
+    }
+}
 
 /// Compose two observers. Both receive every event.
 /// Usage: `MboProcessor::with_observer((observer_a, observer_b))`
-impl<A: MboObserver, B: MboObserver> MboObserver for (A, B) {
+impl<B, X: MboObserver<B>, Y: MboObserver<B>> MboObserver<B> for (X, Y) {
     fn on_order_added(&mut self, event: &OrderAddedEvent) {
         self.0.on_order_added(event);
         self.1.on_order_added(event);
@@ -80,9 +185,24 @@ impl<A: MboObserver, B: MboObserver> MboObserver for (A, B) {
         self.1.on_clear();
     }
 
+    fn on_session_snapshot(&mut self, message: &MarketByOrderMessage, snapshot: &MarketByPrice) {
+        self.0.on_session_snapshot(message, snapshot);
+        self.1.on_session_snapshot(message, snapshot);
+    }
+
+    fn on_message_applied(&mut self, message: &MarketByOrderMessage, key: BookKey, book: &B) {
+        self.0.on_message_applied(message, key, book);
+        self.1.on_message_applied(message, key, book);
+    }
+
+    fn on_message_rejected(&mut self, message: &MarketByOrderMessage, key: BookKey, book: &B) {
+        self.0.on_message_rejected(message, key, book);
+        self.1.on_message_rejected(message, key, book);
+    }
+
     fn on_event_complete(
         &mut self,
-        book: &OrderBook,
+        book: &B,
         event_time: OffsetDateTime,
         recv_time: OffsetDateTime,
     ) {
@@ -104,6 +224,34 @@ pub enum MboProcessError {
 
     #[error("Record type from flag bits {0} is not supported. Only MBO records are supported.")]
     UnsupportedRecordType(u8),
+
+    #[error("{action} for order {order_id} has an undefined price")]
+    UndefinedPrice { action: Action, order_id: u64 },
+
+    #[error("Message failed validation: {0}")]
+    ValidationFailed(#[from] ValidationError),
+}
+
+/// How an Add whose price crosses the opposite side of the book is handled.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CrossingPolicy {
+    /// Rest the order as-is, leaving the book crossed (the feed is expected to send
+    /// the resulting Fills and Cancels).
+    #[default]
+    Rest,
+    /// Match the order against resting liquidity best-first, emitting synthetic
+    /// Trade/Fill events, and rest only the residual.
+    Match,
+}
+
+/// How `MboProcessor::process_messages` reacts to a message that fails to process.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ErrorPolicy {
+    /// Stop at the first failing message.
+    #[default]
+    FailFast,
+    /// Record the failure and continue with the next message.
+    Collect,
 }
 
 /// Action for an market-by-order record.
@@ -120,24 +268,145 @@ pub enum Action {
 }
 
 /// A market-by-order message that is either an order, a trade or a system event.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+///
+/// Serde writes timestamps and `ts_in_delta` as integer nanoseconds.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct MarketByOrderMessage {
+    /// Venue-assigned id of the instrument this message belongs to.
+    pub instrument_id: u32,
     pub action: Action,
     pub side: Side,
+    /// True when the record gave no side (dbn side `N`), as Trades, Fills, Cancels
+    /// and Clears may; `side` then holds `Side::Bid` as a placeholder.
+    #[serde(default)]
+    pub no_side: bool,
     pub price: i64,
     pub order_id: u64,
     pub size: u32,
     /// True when the dbn LAST flag (`F_LAST`) is set, marking the end of an event.
     /// The order book is only in a consistent state after processing a LAST-flagged message.
     pub is_last: bool,
+    /// Raw dbn flag bits (`dbn::flags`), e.g. `F_LAST` and `F_SNAPSHOT`.
+    pub flags: u8,
     /// The sequence number (assigned by the venue) of the message.
     pub sequence: u32,
     /// Exchange event timestamp.
+    #[serde(with = "time::serde::timestamp::nanoseconds")]
     pub event_time: OffsetDateTime,
     /// Server receive timestamp.
+    #[serde(with = "time::serde::timestamp::nanoseconds")]
     pub recv_time: OffsetDateTime,
     /// Duration delta before `recv_time`.
+    #[serde(with = "duration_nanoseconds")]
     pub ts_in_delta: Duration,
+    /// Participant the order is attributed to, if the feed says; never set from DBN.
+    #[serde(default)]
+    pub owner: Option<u32>,
+    /// Dataset publisher (venue and feed) of the record, zero when unknown. Consolidated
+    /// feeds carry the same `instrument_id` from several publishers.
+    #[serde(default)]
+    pub publisher_id: u16,
+    /// Channel of the venue the record arrived on, zero when unknown.
+    #[serde(default)]
+    pub channel_id: u8,
+}
+
+/// Serde of a `Duration` as integer nanoseconds, like the timestamps beside it.
+mod duration_nanoseconds {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(duration.whole_nanoseconds() as i64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        i64::deserialize(deserializer).map(Duration::nanoseconds)
+    }
+}
+
+impl MarketByOrderMessage {
+    /// True when the dbn SNAPSHOT flag (`F_SNAPSHOT`) is set, i.e. the message is part of
+    /// a book snapshot rather than an incremental update.
+    pub fn is_snapshot(&self) -> bool {
+        self.flags & flags::SNAPSHOT != 0
+    }
+
+    /// The dbn character of the action, e.g. `'A'` for Add.
+    pub fn action_char(&self) -> char {
+        match self.action {
+            Action::Add => 'A',
+            Action::Cancel => 'C',
+            Action::Modify => 'M',
+            Action::Fill => 'F',
+            Action::Clear => 'R',
+            Action::Trade => 'T',
+        }
+    }
+
+    /// The dbn character of the side: `'B'`, `'A'`, or `'N'` if the record gave none.
+    pub fn side_char(&self) -> char {
+        match self.known_side() {
+            Some(Side::Bid) => 'B',
+            Some(Side::Ask) => 'A',
+            None => 'N',
+        }
+    }
+
+    /// The side, or `None` if the record gave none.
+    pub fn known_side(&self) -> Option<Side> {
+        (!self.no_side).then_some(self.side)
+    }
+
+    /// The price, or `None` if it is the dbn "no price" sentinel (`UNDEF_PRICE`), which
+    /// Databento uses on some Trade and Clear records.
+    pub fn defined_price(&self) -> Option<i64> {
+        (self.price != UNDEF_PRICE).then_some(self.price)
+    }
+}
+
+/// Message factories for tests; other fields are set with struct update syntax.
+#[cfg(test)]
+impl MarketByOrderMessage {
+    /// A LAST-flagged message of instrument 1 at the UNIX epoch, with sequence 0.
+    pub(crate) fn test(action: Action, side: Side, price: i64, order_id: u64, size: u32) -> Self {
+        Self {
+            instrument_id: 1,
+            action,
+            side,
+            no_side: false,
+            price,
+            order_id,
+            size,
+            is_last: true,
+            flags: flags::LAST,
+            sequence: 0,
+            event_time: OffsetDateTime::UNIX_EPOCH,
+            recv_time: OffsetDateTime::UNIX_EPOCH,
+            ts_in_delta: Duration::ZERO,
+            owner: None,
+            publisher_id: 0,
+            channel_id: 0,
+        }
+    }
+
+    /// The message with event and receive time `time`.
+    pub(crate) fn at(self, time: OffsetDateTime) -> Self {
+        Self {
+            event_time: time,
+            recv_time: time,
+            ..self
+        }
+    }
+
+    /// The message ending its event or not, with the LAST flag to match.
+    pub(crate) fn with_last(self, is_last: bool) -> Self {
+        Self {
+            is_last,
+            flags: if is_last { flags::LAST } else { 0 },
+            ..self
+        }
+    }
 }
 
 fn convert_action(dbn_action: DbnAction) -> Result<Action, MboProcessError> {
@@ -152,16 +421,14 @@ fn convert_action(dbn_action: DbnAction) -> Result<Action, MboProcessError> {
     }
 }
 
-fn convert_side(dbn_side: DbnSide, action: Action) -> Result<Side, MboProcessError> {
-    match dbn_side {
-        DbnSide::Bid => Ok(Side::Bid),
-        DbnSide::Ask => Ok(Side::Ask),
-        DbnSide::None => match action {
-            // These actions don't use side in processing; use dummy value.
-            // Cancel/Fill look up by order_id only. Clear resets book. Trade is ignored.
-            Action::Clear | Action::Trade | Action::Cancel | Action::Fill => Ok(Side::Bid),
-            _ => Err(MboProcessError::SideConversionError(b'N' as i8)),
-        },
+/// The side of a dbn side character: `B` bid, `A` ask and `N` none. Every reader
+/// decodes sides through this, and `MarketByOrderMessage::side_char` is its inverse.
+pub fn side_from_char(side: c_char) -> Result<Option<Side>, MboProcessError> {
+    match side as u8 {
+        b'B' => Ok(Some(Side::Bid)),
+        b'A' => Ok(Some(Side::Ask)),
+        b'N' => Ok(None),
+        _ => Err(MboProcessError::SideConversionError(side)),
     }
 }
 
@@ -177,25 +444,80 @@ impl TryFrom<&MboMsg> for MarketByOrderMessage {
             .action()
             .map_err(|_| MboProcessError::UnknownAction(msg.action))?;
         let action = convert_action(dbn_action)?;
+        // An Add or Modify without a price would rest at i64::MAX and poison the book.
+        if msg.price == UNDEF_PRICE && matches!(action, Action::Add | Action::Modify) {
+            return Err(MboProcessError::UndefinedPrice {
+                action,
+                order_id: msg.order_id,
+            });
+        }
 
-        let dbn_side = msg
-            .side()
-            .map_err(|_| MboProcessError::SideConversionError(msg.side))?;
-        let side = convert_side(dbn_side, action)?;
+        // Cancel, Fill, Clear and Trade may come without a side: Cancel and Fill look the
+        // order up by id, and Clear and Trade don't need one. Add and Modify do.
+        let side = side_from_char(msg.side)?;
+        if side.is_none() && matches!(action, Action::Add | Action::Modify) {
+            return Err(MboProcessError::SideConversionError(b'N' as i8));
+        }
 
         Ok(MarketByOrderMessage {
+            instrument_id: msg.hd.instrument_id,
             action,
-            side,
+            side: side.unwrap_or(Side::Bid),
+            no_side: side.is_none(),
             price: msg.price,
             order_id: msg.order_id,
             size: msg.size,
             is_last: msg.flags.is_last(),
+            flags: msg.flags.raw(),
             sequence: msg.sequence,
             event_time: OffsetDateTime::from_unix_timestamp_nanos(msg.hd.ts_event as i128)
                 .expect("dbn ts_event is within supported range"),
             recv_time: OffsetDateTime::from_unix_timestamp_nanos(msg.ts_recv as i128)
                 .expect("dbn ts_recv is within supported range"),
             ts_in_delta: Duration::nanoseconds(msg.ts_in_delta as i64),
+            owner: None,
+            publisher_id: msg.hd.publisher_id,
+            channel_id: msg.channel_id,
+        })
+    }
+}
+
+/// The inverse of the `TryFrom<&MboMsg>` conversion. The LAST flag follows `is_last`.
+/// Fails on a timestamp before the UNIX epoch or a `ts_in_delta` outside `i32`
+/// nanoseconds, which a DBN record cannot hold.
+impl TryFrom<&MarketByOrderMessage> for MboMsg {
+    type Error = ConversionError;
+
+    fn try_from(message: &MarketByOrderMessage) -> Result<Self, Self::Error> {
+        fn nanos<T: TryFrom<i128>>(
+            column: &'static str,
+            value: i128,
+        ) -> Result<T, ConversionError> {
+            T::try_from(value).map_err(|_| ConversionError::InvalidValue {
+                column,
+                value: value.to_string(),
+                expected: any::type_name::<T>(),
+            })
+        }
+
+        let last = if message.is_last { flags::LAST } else { 0 };
+        Ok(MboMsg {
+            hd: dbn::RecordHeader::new::<MboMsg>(
+                dbn::enums::rtype::MBO,
+                message.publisher_id,
+                message.instrument_id,
+                nanos("ts_event", message.event_time.unix_timestamp_nanos())?,
+            ),
+            order_id: message.order_id,
+            price: message.price,
+            size: message.size,
+            flags: dbn::FlagSet::new((message.flags & !flags::LAST) | last),
+            channel_id: message.channel_id,
+            action: message.action_char() as c_char,
+            side: message.side_char() as c_char,
+            ts_recv: nanos("ts_recv", message.recv_time.unix_timestamp_nanos())?,
+            ts_in_delta: nanos("ts_in_delta", message.ts_in_delta.whole_nanoseconds())?,
+            sequence: message.sequence,
         })
     }
 }
@@ -208,13 +530,144 @@ impl From<&MarketByOrderMessage> for Order {
             price: msg.price,
             size: msg.size.into(),
             sequence: msg.sequence,
+            owner: msg.owner,
+            expires_at: None,
+        }
+    }
+}
+
+/// The book a message is routed to: its instrument and, under publisher routing, its
+/// publisher. Ordered by instrument id, then publisher id.
+#[derive(
+    Debug, Default, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub struct BookKey {
+    pub instrument_id: u32,
+    /// Zero unless the processor routes by publisher.
+    pub publisher_id: u16,
+}
+
+impl BookKey {
+    pub fn new(publisher_id: u16, instrument_id: u32) -> Self {
+        Self {
+            instrument_id,
+            publisher_id,
+        }
+    }
+}
+
+/// The key of `instrument_id` without publisher routing.
+impl From<u32> for BookKey {
+    fn from(instrument_id: u32) -> Self {
+        Self::new(0, instrument_id)
+    }
+}
+
+/// Order book and counters for a single instrument.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub(crate) struct InstrumentState<B = OrderBook> {
+    book: B,
+    stats: MboStats,
+    /// Whether the last message for this instrument was snapshot-flagged.
+    in_snapshot: bool,
+    /// Quantity filled per order owner, cumulative across clears.
+    executed: HashMap<u32, u64>,
+    /// Time-weighted BBO statistics, from the first completed event once enabled.
+    quotes: Option<QuoteStats>,
+    /// BBO changes, from the first completed event once enabled.
+    #[serde(default)]
+    history: Option<BboHistory>,
+    /// Classified prints, from the first print once enabled.
+    trades: Option<TradeQuality>,
+    /// Executed volume by aggressor side.
+    #[serde(default)]
+    flow: FlowTracker,
+}
+
+impl<B: BookHandler> InstrumentState<B> {
+    /// State of an instrument seen for the first time: a clone of the processor's book
+    /// template, configured with its event retention and depth limit.
+    fn from_template(
+        template: &B,
+        events: EventRetention,
+        depth_limit: Option<(usize, DepthLimitPolicy)>,
+        flow_window: Option<Duration>,
+    ) -> Self {
+        Self::new(
+            template.clone().configure(events, depth_limit),
+            FlowTracker::new(flow_window),
+        )
+    }
+
+    fn new(book: B, flow: FlowTracker) -> Self {
+        Self {
+            book,
+            stats: MboStats::default(),
+            in_snapshot: false,
+            executed: HashMap::new(),
+            quotes: None,
+            history: None,
+            trades: None,
+            flow,
+        }
+    }
+
+    /// Message counters combined with the book's anomaly counters.
+    fn stats(&self) -> MboStats {
+        let anomalies = self.book.anomalies();
+        MboStats {
+            duplicate_adds: anomalies.duplicate_add,
+            unknown_cancels: anomalies.unknown_cancel,
+            unknown_modifies: anomalies.unknown_modify,
+            missing_levels: anomalies.level_missing,
+            ..self.stats
         }
     }
 }
 
-/// Market-By-Order processor that maintains an in-memory order book,
+/// Nanoseconds since the UNIX epoch of `time`, as order expiries are kept; 0 before it.
+fn unix_nanos(time: OffsetDateTime) -> u64 {
+    time.unix_timestamp_nanos().max(0) as u64
+}
+
+/// Sets the undo for the current message unless journaling is disabled (`None`) or an
+/// earlier step, such as the start of a snapshot, already recorded a whole-book undo.
+fn record_undo<B>(undo: &mut Option<Undo<B>>, make: impl FnOnce() -> Undo<B>) {
+    if matches!(undo, Some(Undo::Nothing)) {
+        *undo = Some(make());
+    }
+}
+
+/// Adds `qty` filled to the executed quantity of `owner`, noting it in `mark` so a
+/// rollback can take it back. Fills without an owner cost nothing.
+fn credit_executed(
+    executed: &mut HashMap<u32, u64>,
+    mark: &mut Option<ProcessorMark>,
+    owner: Option<u32>,
+    qty: u64,
+) {
+    let Some(owner) = owner.filter(|_| qty > 0) else {
+        return;
+    };
+    *executed.entry(owner).or_default() += qty;
+    if let Some(mark) = mark {
+        mark.executed.push((owner, qty));
+    }
+}
+
+/// Returned by `MboProcessor::order_book` before any message has been processed.
+static EMPTY_BOOK: LazyLock<OrderBook> = LazyLock::new(OrderBook::new);
+
+/// Market-By-Order processor that maintains an in-memory order book per instrument,
 /// and emits desired market-by-price or other views.
 ///
+/// Messages are routed by `instrument_id`; each instrument's book is created lazily on its
+/// first message, and a Clear only resets the book of the instrument it belongs to.
+/// Consolidated feeds, where several publishers quote the same instrument id, can be
+/// routed by `(publisher_id, instrument_id)` with `with_publisher_routing` instead. The
+/// methods taking an instrument id then address the book of publisher 0; `book_keys`
+/// and `book_for` reach every publisher's book.
+///
 /// The order book is only in a consistent, queryable state after a message
 /// with `is_last == true` has been processed (the dbn `F_LAST` flag marks
 /// the end of an exchange event).
@@ -222,10 +675,28 @@ impl From<&MarketByOrderMessage> for Order {
 /// Generic over an observer `O` that receives events during message processing.
 /// Defaults to `()` (zero-cost no-op). Use `with_observer` to supply a custom
 /// observer, or compose multiple via tuples: `MboProcessor::with_observer((a, b))`.
-#[derive(Debug)]
-pub struct MboProcessor<O: MboObserver = ()> {
-    order_book: OrderBook,
+///
+/// Also generic over the book `B`, `OrderBook` by default. `with_observer_and_book`
+/// takes any `BookHandler`, such as a `DenseOrderBook`; what needs an `OrderBook`
+/// (`order_book`, `market_by_price`, `participant_summary`, `seed_book`, checkpoints)
+/// is only offered for the default.
+///
+/// Processors whose observer is `Clone` are `Clone`, copying the books, counters,
+/// statistics, undo journal and state hashes along with the observer and whatever it
+/// has accumulated. `fork` copies the same state for any observer but attaches a fresh
+/// one, so a branch explored from the current state starts with empty outputs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MboProcessor<O: MboObserver<B> = (), B: BookHandler = OrderBook> {
+    /// Book and counters per instrument id, and publisher id if routed by publisher.
+    instruments: HashMap<BookKey, InstrumentState<B>>,
+    /// Book key of the last processed message.
+    last_key: BookKey,
+    /// Whether books are keyed by publisher as well as instrument.
+    #[serde(default)]
+    publisher_routing: bool,
     observer: O,
+    /// Behavior of `process_messages` on failure.
+    error_policy: ErrorPolicy,
     /// Whether the last processed message had the LAST flag set.
     event_complete: bool,
     /// The sequence number (assigned by the venue) of the last record processed.
@@ -236,20 +707,64 @@ pub struct MboProcessor<O: MboObserver = ()> {
     last_recv_time: OffsetDateTime,
     /// Duration delta of the last processed message.
     last_ts_in_delta: Duration,
+    /// Raw dbn flags of the last processed message.
+    #[serde(default)]
+    last_flags: u8,
+    /// Publisher id of the last processed message, whether or not routed by it.
+    #[serde(default)]
+    last_publisher_id: u16,
+    /// Channel id of the last processed message.
+    #[serde(default)]
+    last_channel_id: u8,
+    /// Handling of Adds that cross the book.
+    crossing_policy: CrossingPolicy,
+    /// Queue-position rule applied to Modifies.
+    modify_priority_policy: ModifyPriorityPolicy,
+    /// Handling of crossing Adds that reach an order of their own owner.
+    self_match_policy: SelfMatchPolicy,
+    /// Handling of messages that fail `validate`.
+    validation_policy: ValidationPolicy,
+    /// Audit journal retention of each instrument's book.
+    book_events: EventRetention,
+    /// Depth limit of each instrument's book, unlimited if `None`.
+    #[serde(default)]
+    book_depth_limit: Option<(usize, DepthLimitPolicy)>,
+    /// Time to live of added orders, after which they are purged.
+    #[serde(default)]
+    order_ttl: Option<Duration>,
+    /// Whether observers get a snapshot of each book before a Clear.
+    #[serde(default)]
+    session_snapshots: bool,
+    /// Whether each instrument keeps `QuoteStats`.
+    quote_stats: bool,
+    /// Empty history cloned for each instrument, if instruments keep one.
+    #[serde(default)]
+    bbo_history: Option<BboHistory>,
+    /// Whether each instrument keeps a `TradeQuality`.
+    trade_quality: bool,
+    /// Undo history for `rollback`; disabled (depth 0) by default.
+    journal: Journal<B>,
+    /// Messages between recorded state hashes; 0 (the default) records none.
+    #[serde(default)]
+    state_hash_interval: usize,
+    #[serde(default)]
+    messages_since_hash: usize,
+    #[serde(default)]
+    state_hashes: Vec<u64>,
+    /// Anomalies with their messages, if enabled.
+    #[serde(default)]
+    anomaly_log: Option<AnomalyLog>,
+    /// Event time over which `recent_flow_stats` sums, if enabled.
+    #[serde(default)]
+    flow_window: Option<Duration>,
+    /// Empty book each new instrument starts from a clone of.
+    #[serde(default)]
+    book_template: B,
 }
 
 impl Default for MboProcessor {
     fn default() -> Self {
-        Self {
-            order_book: OrderBook::default(),
-            observer: (),
-            // Start as true so the initial (empty) state is considered consistent.
-            event_complete: true,
-            sequence_number: 0,
-            last_event_time: OffsetDateTime::UNIX_EPOCH,
-            last_recv_time: OffsetDateTime::UNIX_EPOCH,
-            last_ts_in_delta: Duration::ZERO,
-        }
+        Self::with_observer(())
     }
 }
 
@@ -262,684 +777,3291 @@ impl MboProcessor {
 impl<O: MboObserver> MboProcessor<O> {
     /// Creates a new processor with the given observer.
     pub fn with_observer(observer: O) -> Self {
+        Self::with_observer_and_book(observer, OrderBook::new())
+    }
+
+    /// Returns the book of the instrument of the last processed message.
+    /// For single-instrument feeds this is the only book; it is empty if no
+    /// message has been processed yet.
+    pub fn order_book(&self) -> &OrderBook {
+        self.book_for(self.last_key).unwrap_or(&EMPTY_BOOK)
+    }
+
+    /// Consumes the processor and returns the book of the instrument of the last
+    /// processed message. Books of other instruments are dropped; use `into_books`
+    /// for multi-instrument feeds.
+    pub fn into_inner(mut self) -> OrderBook {
+        self.instruments
+            .remove(&self.last_key)
+            .map(|state| state.book)
+            .unwrap_or_default()
+    }
+
+    /// Market-by-price snapshot of the book returned by `order_book`, stamped with the
+    /// timestamps and sequence of the last processed message. `depth` limits the number
+    /// of levels per side; `None` includes every level.
+    pub fn market_by_price(&self, depth: Option<usize>) -> MarketByPrice {
+        match depth {
+            Some(n) => MarketByPrice::from_top_n_with_metadata(self, n),
+            None => MarketByPrice::from_book_with_metadata(self),
+        }
+    }
+
+    /// Resting orders per owner in the book of `instrument_id`, as in
+    /// `OrderBook::participant_summary`, with each owner's executed quantity: the size
+    /// of Fill messages for its orders and, under `CrossingPolicy::Match`, of
+    /// executions against them. Owners with fills but nothing resting are included.
+    pub fn participant_summary(
+        &self,
+        instrument_id: u32,
+    ) -> Option<HashMap<u32, ParticipantStats>> {
+        let state = self.instruments.get(&instrument_id.into())?;
+        let mut summary = state.book.participant_summary();
+        for (&owner, &qty) in &state.executed {
+            summary.entry(owner).or_default().executed_qty = qty;
+        }
+        Some(summary)
+    }
+
+    /// Seeds the book of `instrument_id` with aggregated depth, as described in
+    /// `OrderBook::seed_from_depth`, and returns the number of synthetic orders added.
+    ///
+    /// Later messages for the instrument apply on top of the seeded liquidity; a Clear
+    /// removes it together with the real orders. Seeding is not journaled.
+    pub fn seed_book(
+        &mut self,
+        instrument_id: u32,
+        levels: &[(Side, i64, u64, u32)],
+        mode: SeedMode,
+    ) -> usize {
+        let (events, depth_limit, flow_window) =
+            (self.book_events, self.book_depth_limit, self.flow_window);
+        let book_template = &self.book_template;
+        self.instruments
+            .entry(instrument_id.into())
+            .or_insert_with(|| {
+                InstrumentState::from_template(book_template, events, depth_limit, flow_window)
+            })
+            .book
+            .seed_from_depth(levels, mode)
+    }
+}
+
+impl<O: MboObserver<B>, B: BookHandler> MboProcessor<O, B> {
+    /// Creates a processor with the given observer that gives every instrument a clone
+    /// of `book`, e.g. an empty `DenseOrderBook` over the instrument's price range.
+    pub fn with_observer_and_book(observer: O, book: B) -> Self {
         Self {
-            order_book: OrderBook::default(),
+            instruments: HashMap::new(),
+            last_key: BookKey::default(),
+            publisher_routing: false,
             observer,
+            error_policy: ErrorPolicy::default(),
+            // Start as true so the initial (empty) state is considered consistent.
             event_complete: true,
             sequence_number: 0,
             last_event_time: OffsetDateTime::UNIX_EPOCH,
             last_recv_time: OffsetDateTime::UNIX_EPOCH,
             last_ts_in_delta: Duration::ZERO,
+            last_flags: 0,
+            last_publisher_id: 0,
+            last_channel_id: 0,
+            crossing_policy: CrossingPolicy::default(),
+            modify_priority_policy: ModifyPriorityPolicy::default(),
+            self_match_policy: SelfMatchPolicy::default(),
+            validation_policy: ValidationPolicy::default(),
+            book_events: EventRetention::default(),
+            book_depth_limit: None,
+            order_ttl: None,
+            session_snapshots: false,
+            quote_stats: false,
+            bbo_history: None,
+            trade_quality: false,
+            journal: Journal::with_depth(0),
+            state_hash_interval: 0,
+            messages_since_hash: 0,
+            state_hashes: Vec::new(),
+            anomaly_log: None,
+            flow_window: None,
+            book_template: book,
         }
     }
 
-    /// Returns a reference to the observer.
-    pub fn observer(&self) -> &O {
-        &self.observer
+    /// Sets the error policy used by `process_messages`.
+    pub fn with_error_policy(mut self, error_policy: ErrorPolicy) -> Self {
+        self.error_policy = error_policy;
+        self
     }
 
-    /// Returns a mutable reference to the observer.
-    pub fn observer_mut(&mut self) -> &mut O {
-        &mut self.observer
+    /// Keys books by `(publisher_id, instrument_id)` rather than by instrument alone, so
+    /// consolidated feeds keep each venue's book of an instrument apart. Only messages
+    /// processed from now on are routed by publisher.
+    pub fn with_publisher_routing(mut self, enabled: bool) -> Self {
+        self.publisher_routing = enabled;
+        self
     }
 
-    /// Consumes the processor and returns the observer.
-    /// Consumer gains ownership of the observer.
-    pub fn into_observer(self) -> O {
-        self.observer
+    pub fn publisher_routing(&self) -> bool {
+        self.publisher_routing
     }
 
-    pub fn order_book(&self) -> &OrderBook {
-        &self.order_book
+    /// The key of the book `message` is routed to.
+    pub fn book_key(&self, message: &MarketByOrderMessage) -> BookKey {
+        match self.publisher_routing {
+            true => BookKey::new(message.publisher_id, message.instrument_id),
+            false => BookKey::from(message.instrument_id),
+        }
     }
 
-    /// Returns true if the last processed message had the LAST flag set,
-    /// meaning the order book is in a consistent state suitable for
-    /// MBP snapshot extraction.
-    pub fn is_event_complete(&self) -> bool {
-        self.event_complete
+    /// Sets how Adds that cross the book are handled.
+    pub fn with_crossing_policy(mut self, crossing_policy: CrossingPolicy) -> Self {
+        self.crossing_policy = crossing_policy;
+        self
     }
 
-    pub fn last_sequence_number(&self) -> u32 {
-        self.sequence_number
+    /// Returns the configured crossing policy.
+    pub fn crossing_policy(&self) -> CrossingPolicy {
+        self.crossing_policy
     }
 
-    /// Returns the event timestamp (exchange timestamp) of the last processed message.
-    /// Returns `OffsetDateTime::UNIX_EPOCH` if no messages have been processed.
-    pub fn last_event_time(&self) -> OffsetDateTime {
-        self.last_event_time
+    /// Sets whether Modifies keep queue position on a size increase.
+    pub fn with_modify_priority_policy(mut self, policy: ModifyPriorityPolicy) -> Self {
+        self.modify_priority_policy = policy;
+        self
     }
 
-    /// Returns the receive timestamp (server timestamp) of the last processed message.
-    /// Returns `OffsetDateTime::UNIX_EPOCH` if no messages have been processed.
-    pub fn last_recv_time(&self) -> OffsetDateTime {
-        self.last_recv_time
+    /// Returns the configured modify priority policy.
+    pub fn modify_priority_policy(&self) -> ModifyPriorityPolicy {
+        self.modify_priority_policy
     }
 
-    /// Returns the duration delta of the last processed message.
-    pub fn last_ts_in_delta(&self) -> Duration {
-        self.last_ts_in_delta
+    /// Sets what a crossing Add does on reaching a resting order of its own owner.
+    /// Only applies under `CrossingPolicy::Match`.
+    pub fn with_self_match_policy(mut self, policy: SelfMatchPolicy) -> Self {
+        self.self_match_policy = policy;
+        self
     }
 
-    /// Returns all timestamp information as a tuple: (event_time, recv_time, ts_in_delta).
-    pub fn last_timestamps(&self) -> (OffsetDateTime, OffsetDateTime, Duration) {
-        (
-            self.last_event_time,
-            self.last_recv_time,
-            self.last_ts_in_delta,
-        )
+    pub fn self_match_policy(&self) -> SelfMatchPolicy {
+        self.self_match_policy
     }
 
-    /// Processes an incoming MBO message and updates the order book accordingly.
-    ///
-    /// Only Add, Cancel, Modify, and Clear actions modify the order book.
-    /// Fill and Trade are informational and do not change order sizes —
-    /// actual size changes arrive as separate Modify or Cancel messages.
+    /// Journals the changes to each instrument's book as set by `retention`, readable
+    /// through `book(id)`. Only books created from now on journal; journals are not
+    /// checkpointed.
+    pub fn with_book_events(mut self, retention: EventRetention) -> Self {
+        self.book_events = retention;
+        self
+    }
+
+    pub fn book_events(&self) -> EventRetention {
+        self.book_events
+    }
+
+    /// Keeps at most `max_levels` price levels per side in each instrument's book, as
+    /// `OrderBook::with_max_levels_per_side` does. Under `DepthLimitPolicy::Reject` an
+    /// Add beyond the limit fails with `OrderBookError::BeyondDepthLimit`. Only books
+    /// created from now on are limited, and `rollback` does not bring back the orders
+    /// that an Add or Modify trimmed away.
+    pub fn with_book_depth_limit(mut self, max_levels: usize, policy: DepthLimitPolicy) -> Self {
+        self.book_depth_limit = Some((max_levels, policy));
+        self
+    }
+
+    pub fn book_depth_limit(&self) -> Option<(usize, DepthLimitPolicy)> {
+        self.book_depth_limit
+    }
+
+    /// Gives every added order an expiry `ttl` after its Add's event time, and purges
+    /// each instrument's expired orders before applying its next message, reporting
+    /// them as cancelled, so orders age out as event time advances during replay. The
+    /// expiry is kept by Modifies; orders seeded into a book never expire.
     ///
-    /// Observer callbacks are fired after the book mutation completes.
-    /// If `is_last` is set, `on_event_complete` is called with the consistent book state.
-    pub fn process_message(
-        &mut self,
-        message: &MarketByOrderMessage,
-    ) -> Result<(), MboProcessError> {
-        self.event_complete = message.is_last;
-        self.sequence_number = message.sequence;
-        self.last_event_time = message.event_time;
-        self.last_recv_time = message.recv_time;
-        self.last_ts_in_delta = message.ts_in_delta;
+    /// The purge runs once the message has passed `validate`, but before the book can
+    /// refuse it: a Modify of an unknown (or just expired) order or an Add rejected by
+    /// the depth limit still leaves the expired orders purged. Observers then get
+    /// `MboObserver::on_message_rejected` for the message, and `rollback` of it puts
+    /// the purged orders back.
+    pub fn with_order_ttl(mut self, ttl: Duration) -> Self {
+        self.order_ttl = Some(ttl);
+        self
+    }
 
-        match message.action {
-            Action::Add => {
-                debug!(
-                    "Adding order ID {}: side {:?}, price {}, size {}",
-                    message.order_id, message.side, message.price, message.size
-                );
-                let info = self.order_book.add_order(Order::from(message));
-                self.observer.on_order_added(&OrderAddedEvent {
-                    order: info.order,
-                    level_qty: info.level_qty,
-                    level_order_count: info.level_order_count,
-                    new_level: info.new_level,
-                    event_time: message.event_time,
-                    recv_time: message.recv_time,
-                    sequence: message.sequence,
-                });
-            }
-            Action::Cancel => {
-                debug!("Cancelling order ID {}", message.order_id);
-                if let Some(info) = self.order_book.remove_order(message.order_id) {
-                    self.observer.on_order_cancelled(&OrderCancelledEvent {
-                        order: info.order,
-                        remaining_level_qty: info.remaining_level_qty,
-                        remaining_level_count: info.remaining_level_count,
-                        level_removed: info.level_removed,
-                        event_time: message.event_time,
-                        recv_time: message.recv_time,
-                        sequence: message.sequence,
-                    });
-                }
-            }
-            Action::Modify => {
-                debug!(
-                    "Modifying order ID {} to price {}, size {}",
-                    message.order_id, message.price, message.size
-                );
-                if let Some(info) = self.order_book.modify_order(Order::from(message)) {
-                    self.observer.on_order_modified(&OrderModifiedEvent {
-                        order: info.order,
-                        old_price: info.old_price,
-                        old_size: info.old_size,
-                        level_qty: info.level_qty,
-                        level_order_count: info.level_order_count,
-                        retained_queue_position: info.retained_queue_position,
+    pub fn order_ttl(&self) -> Option<Duration> {
+        self.order_ttl
+    }
+
+    /// Passes observers a snapshot of the book a Clear is about to clear, through
+    /// `MboObserver::on_session_snapshot`, so the state at the end of a session is not
+    /// lost. Off by default.
+    pub fn with_session_snapshots(mut self, enabled: bool) -> Self {
+        self.session_snapshots = enabled;
+        self
+    }
+
+    pub fn session_snapshots(&self) -> bool {
+        self.session_snapshots
+    }
+
+    /// Keeps `QuoteStats` per instrument, fed the book's BBO at the end of every event
+    /// with the event's timestamp.
+    pub fn with_quote_stats(mut self, enabled: bool) -> Self {
+        self.quote_stats = enabled;
+        self
+    }
+
+    /// The quote statistics of an instrument, if enabled and it has completed an event.
+    pub fn quote_stats(&self, instrument_id: u32) -> Option<&QuoteStats> {
+        self.instruments.get(&instrument_id.into())?.quotes.as_ref()
+    }
+
+    /// Keeps a clone of `history`, typically empty, per instrument, recording the book's
+    /// BBO at the end of every event with the event's timestamp. Its `with_max_len` and
+    /// `with_downsample` settings apply to every instrument.
+    pub fn with_bbo_history(mut self, history: BboHistory) -> Self {
+        self.bbo_history = Some(history);
+        self
+    }
+
+    /// The BBO history of an instrument, if enabled and it has completed an event.
+    pub fn bbo_history(&self, instrument_id: u32) -> Option<&BboHistory> {
+        self.instruments
+            .get(&instrument_id.into())?
+            .history
+            .as_ref()
+    }
+
+    /// Also sums the executed volume by aggressor side over the last `window` of event
+    /// time, as `recent_flow_stats` returns. Applies to books created after the call.
+    pub fn with_flow_window(mut self, window: Duration) -> Self {
+        self.flow_window = Some(window);
+        self
+    }
+
+    pub fn flow_window(&self) -> Option<Duration> {
+        self.flow_window
+    }
+
+    /// Executed volume and trades by aggressor side, rolled up across instruments;
+    /// see the `flow` module for how prints are attributed to a side.
+    pub fn flow_stats(&self) -> FlowStats {
+        self.instruments
+            .values()
+            .map(|state| state.flow.totals())
+            .sum()
+    }
+
+    /// Executed volume and trades by aggressor side of one instrument, if it has been
+    /// seen.
+    pub fn instrument_flow_stats(&self, instrument_id: u32) -> Option<FlowStats> {
+        Some(self.instruments.get(&instrument_id.into())?.flow.totals())
+    }
+
+    /// Executed volume and trades by aggressor side across instruments over the flow
+    /// window ending at the last event time, or `None` without `with_flow_window`.
+    pub fn recent_flow_stats(&self) -> Option<FlowStats> {
+        let window = self.flow_window.map(|_| FlowStats::default());
+        self.instruments
+            .values()
+            .filter_map(|state| state.flow.recent(self.last_event_time))
+            .fold(window, |sum, stats| sum.map(|sum| sum + stats))
+    }
+
+    /// Keeps a `TradeQuality` per instrument, classifying every Trade and Fill, and
+    /// every execution of a crossing Add under `CrossingPolicy::Match`, by the book
+    /// before the event's first print.
+    pub fn with_trade_quality(mut self, enabled: bool) -> Self {
+        self.trade_quality = enabled;
+        self
+    }
+
+    /// The classified prints of an instrument, once enabled and it has processed a
+    /// Trade, a Fill or, under `CrossingPolicy::Match`, an Add.
+    pub fn trade_quality(&self, instrument_id: u32) -> Option<&TradeQuality> {
+        self.instruments.get(&instrument_id.into())?.trades.as_ref()
+    }
+
+    /// Size-weighted mean realized spread of an instrument's prints, against the
+    /// midpoint its BBO history shows `horizon` after each. `None` unless both
+    /// `with_trade_quality` and `with_bbo_history` are on and some print has a
+    /// two-sided quote `horizon` later.
+    pub fn realized_spread(&self, instrument_id: u32, horizon: Duration) -> Option<f64> {
+        let state = self.instruments.get(&instrument_id.into())?;
+        state
+            .trades
+            .as_ref()?
+            .mean_realized_spread(state.history.as_ref()?, horizon)
+    }
+
+    /// Sets how messages that fail validation are handled.
+    pub fn with_validation_policy(mut self, validation_policy: ValidationPolicy) -> Self {
+        self.validation_policy = validation_policy;
+        self
+    }
+
+    /// Returns the configured validation policy.
+    pub fn validation_policy(&self) -> ValidationPolicy {
+        self.validation_policy
+    }
+
+    /// Keeps an undo journal of the last `depth` messages so they can be reverted with
+    /// `rollback`. A depth of zero (the default) disables journaling.
+    pub fn with_journal_depth(mut self, depth: usize) -> Self {
+        self.journal = Journal::with_depth(depth);
+        self
+    }
+
+    /// Returns the configured journal depth.
+    pub fn journal_depth(&self) -> usize {
+        self.journal.depth()
+    }
+
+    /// Returns the number of messages that can currently be rolled back.
+    pub fn journal_len(&self) -> usize {
+        self.journal.len()
+    }
+
+    /// Records `state_hash` after every `interval` messages, for `state_hashes`. An
+    /// interval of zero (the default) records none.
+    pub fn with_state_hash_interval(mut self, interval: usize) -> Self {
+        self.state_hash_interval = interval;
+        self
+    }
+
+    /// Returns the configured state hash interval.
+    pub fn state_hash_interval(&self) -> usize {
+        self.state_hash_interval
+    }
+
+    /// The state hashes recorded so far, oldest first. A rollback does not remove them.
+    pub fn state_hashes(&self) -> &[u64] {
+        &self.state_hashes
+    }
+
+    /// Deterministic hash of every instrument's book, combining each `OrderBook::state_hash`
+    /// with its instrument id (and publisher id, if routed by publisher) in ascending
+    /// `BookKey` order. Two replays of the same messages with the same settings give the
+    /// same hash.
+    pub fn state_hash(&self) -> u64 {
+        let mut keys: Vec<_> = self.instruments.keys().copied().collect();
+        keys.sort_unstable();
+        let mut hasher = StateHasher::new();
+        for key in keys {
+            hasher.write_u64(u64::from(key.publisher_id) << 32 | u64::from(key.instrument_id));
+            hasher.write_u64(self.instruments[&key].book.state_hash());
+        }
+        hasher.finish()
+    }
+
+    /// Logs every anomaly the books count (see `Anomalies`) with the message that caused
+    /// it: all of them, the last N, or none (`EventRetention::Off`, the default).
+    pub fn with_anomaly_log(mut self, retention: EventRetention) -> Self {
+        self.anomaly_log = (retention != EventRetention::Off).then(|| AnomalyLog::new(retention));
+        self
+    }
+
+    /// The logged anomalies, oldest first; none unless enabled with `with_anomaly_log`.
+    /// A rollback does not remove them. Collected into `Anomalies` from the start, they
+    /// give the counters of `stats`.
+    pub fn anomaly_log(&self) -> impl Iterator<Item = &AnomalyRecord> {
+        self.anomaly_log.iter().flat_map(AnomalyLog::records)
+    }
+
+    /// The logged anomalies as a DataFrame, with the columns of an `AnomalyRecord`.
+    #[cfg(feature = "polars")]
+    pub fn anomalies_to_dataframe(&self) -> PolarsResult<DataFrame> {
+        match &self.anomaly_log {
+            Some(log) => log.to_dataframe(),
+            None => AnomalyLog::default().to_dataframe(),
+        }
+    }
+
+    /// The anomaly counters of a book, zero before its first message.
+    fn book_anomalies(&self, key: BookKey) -> Anomalies {
+        self.instruments
+            .get(&key)
+            .map(|state| state.book.anomalies())
+            .unwrap_or_default()
+    }
+
+    /// Returns the error policy used by `process_messages`.
+    pub fn error_policy(&self) -> ErrorPolicy {
+        self.error_policy
+    }
+
+    /// Returns the counters accumulated over all processed messages, rolled up
+    /// across instruments.
+    pub fn stats(&self) -> MboStats {
+        self.instruments.values().map(InstrumentState::stats).sum()
+    }
+
+    /// Returns the counters for a single instrument, if it has been seen.
+    pub fn instrument_stats(&self, instrument_id: u32) -> Option<MboStats> {
+        self.instruments
+            .get(&instrument_id.into())
+            .map(InstrumentState::stats)
+    }
+
+    /// A copy of the processor with `O::default()` attached as its observer: the books,
+    /// counters, statistics, undo journal, state hashes and anomaly log carry over,
+    /// what the observer recorded (BBO rows, snapshots, trades) does not.
+    pub fn fork(&self) -> Self
+    where
+        O: Default,
+    {
+        self.fork_with_observer(O::default())
+    }
+
+    /// Like `fork`, but attaches `observer`, of any type, to the copy.
+    pub fn fork_with_observer<P: MboObserver<B>>(&self, observer: P) -> MboProcessor<P, B> {
+        MboProcessor {
+            instruments: self.instruments.clone(),
+            last_key: self.last_key,
+            publisher_routing: self.publisher_routing,
+            observer,
+            error_policy: self.error_policy,
+            event_complete: self.event_complete,
+            sequence_number: self.sequence_number,
+            last_event_time: self.last_event_time,
+            last_recv_time: self.last_recv_time,
+            last_ts_in_delta: self.last_ts_in_delta,
+            last_flags: self.last_flags,
+            last_publisher_id: self.last_publisher_id,
+            last_channel_id: self.last_channel_id,
+            crossing_policy: self.crossing_policy,
+            modify_priority_policy: self.modify_priority_policy,
+            self_match_policy: self.self_match_policy,
+            validation_policy: self.validation_policy,
+            book_events: self.book_events,
+            book_depth_limit: self.book_depth_limit,
+            order_ttl: self.order_ttl,
+            session_snapshots: self.session_snapshots,
+            quote_stats: self.quote_stats,
+            bbo_history: self.bbo_history.clone(),
+            trade_quality: self.trade_quality,
+            journal: self.journal.clone(),
+            state_hash_interval: self.state_hash_interval,
+            messages_since_hash: self.messages_since_hash,
+            state_hashes: self.state_hashes.clone(),
+            anomaly_log: self.anomaly_log.clone(),
+            flow_window: self.flow_window,
+            book_template: self.book_template.clone(),
+        }
+    }
+
+    /// Returns a reference to the observer.
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    /// Returns a mutable reference to the observer.
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
+
+    /// Consumes the processor and returns the observer.
+    /// Consumer gains ownership of the observer.
+    pub fn into_observer(self) -> O {
+        self.observer
+    }
+
+    /// Consumes the processor and returns all books by their keys.
+    pub fn into_books(self) -> HashMap<BookKey, B> {
+        self.instruments
+            .into_iter()
+            .map(|(key, state)| (key, state.book))
+            .collect()
+    }
+
+    /// Best bid `(price, total_qty)` of the book of the instrument of the last
+    /// processed message.
+    pub fn best_bid(&self) -> Option<(i64, u64)> {
+        self.book_for(self.last_key)?.best_bid()
+    }
+
+    /// Best ask `(price, total_qty)` of the book of the instrument of the last
+    /// processed message.
+    pub fn best_ask(&self) -> Option<(i64, u64)> {
+        self.book_for(self.last_key)?.best_ask()
+    }
+
+    /// Returns the book for the given instrument, if it has been seen.
+    pub fn book(&self, instrument_id: u32) -> Option<&B> {
+        self.book_for(instrument_id.into())
+    }
+
+    /// Returns the book under `key`, if a message has been routed to it.
+    pub fn book_for(&self, key: BookKey) -> Option<&B> {
+        self.instruments.get(&key).map(|state| &state.book)
+    }
+
+    /// Returns the ids of all instruments seen so far, in ascending order.
+    pub fn instruments(&self) -> Vec<u32> {
+        let mut ids: Vec<u32> = self
+            .instruments
+            .keys()
+            .map(|key| key.instrument_id)
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+
+    /// Returns the keys of all books, in ascending order. Under publisher routing an
+    /// instrument quoted by several publishers has a key for each.
+    pub fn book_keys(&self) -> Vec<BookKey> {
+        let mut keys: Vec<BookKey> = self.instruments.keys().copied().collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    /// Returns a full market-by-price view of the given instrument's book.
+    pub fn mbp(&self, instrument_id: u32) -> Option<MarketByPrice> {
+        self.book(instrument_id).map(B::snapshot)
+    }
+
+    /// Returns true if the last processed message was part of a book snapshot
+    /// (dbn `F_SNAPSHOT` flag). The book is only complete once the snapshot ends.
+    pub fn is_in_snapshot(&self) -> bool {
+        self.instruments
+            .get(&self.last_key)
+            .is_some_and(|state| state.in_snapshot)
+    }
+
+    /// Returns true if the last processed message had the LAST flag set,
+    /// meaning the order book is in a consistent state suitable for
+    /// MBP snapshot extraction.
+    pub fn is_event_complete(&self) -> bool {
+        self.event_complete
+    }
+
+    pub fn last_sequence_number(&self) -> u32 {
+        self.sequence_number
+    }
+
+    /// Returns the event timestamp (exchange timestamp) of the last processed message.
+    /// Returns `OffsetDateTime::UNIX_EPOCH` if no messages have been processed.
+    pub fn last_event_time(&self) -> OffsetDateTime {
+        self.last_event_time
+    }
+
+    /// Returns the receive timestamp (server timestamp) of the last processed message.
+    /// Returns `OffsetDateTime::UNIX_EPOCH` if no messages have been processed.
+    pub fn last_recv_time(&self) -> OffsetDateTime {
+        self.last_recv_time
+    }
+
+    /// Returns the duration delta of the last processed message.
+    pub fn last_ts_in_delta(&self) -> Duration {
+        self.last_ts_in_delta
+    }
+
+    /// Returns the raw dbn flags of the last processed message, 0 before the first.
+    pub fn last_flags(&self) -> u8 {
+        self.last_flags
+    }
+
+    /// Returns the publisher id of the last processed message, 0 before the first.
+    pub fn last_publisher_id(&self) -> u16 {
+        self.last_publisher_id
+    }
+
+    /// Returns the channel id of the last processed message, 0 before the first.
+    pub fn last_channel_id(&self) -> u8 {
+        self.last_channel_id
+    }
+
+    /// Returns all timestamp information as a tuple: (event_time, recv_time, ts_in_delta).
+    pub fn last_timestamps(&self) -> (OffsetDateTime, OffsetDateTime, Duration) {
+        (
+            self.last_event_time,
+            self.last_recv_time,
+            self.last_ts_in_delta,
+        )
+    }
+
+    /// Processes an incoming MBO message and updates the order book accordingly.
+    ///
+    /// Only Add, Cancel, Modify, and Clear actions modify the order book.
+    /// Fill and Trade are informational and do not change order sizes —
+    /// actual size changes arrive as separate Modify or Cancel messages.
+    ///
+    /// Observer callbacks are fired after the book mutation completes.
+    /// If `is_last` is set, `on_event_complete` is called with the consistent book state.
+    /// A message that fails is reported with `on_message_rejected` instead.
+    ///
+    /// A Modify for an order that is not in the book fails with
+    /// `OrderBookError::OrderNotFound` and leaves the book untouched, apart from the
+    /// orders expired by `with_order_ttl`.
+    ///
+    /// Messages are checked with `validate` first unless the validation policy is
+    /// `PassThrough`. An invalid message never changes the book: under `Reject` it fails
+    /// with `MboProcessError::ValidationFailed`, under `Skip` it is counted and ignored
+    /// (still completing the event if it carries the LAST flag).
+    ///
+    /// With `with_state_hash_interval`, every Nth call records `state_hash` afterwards,
+    /// and with `with_anomaly_log` every anomaly the message caused is logged.
+    pub fn process_message(
+        &mut self,
+        message: &MarketByOrderMessage,
+    ) -> Result<(), MboProcessError> {
+        let key = self.book_key(message);
+        let before = self.anomaly_log.is_some().then(|| self.book_anomalies(key));
+        let result = self.apply_message(message);
+        if result.is_err() {
+            let state = &self.instruments[&key];
+            self.observer.on_message_rejected(message, key, &state.book);
+        }
+        if let Some(before) = before {
+            let after = self.book_anomalies(key);
+            if let Some(log) = &mut self.anomaly_log {
+                log.record(message, before, after);
+            }
+        }
+        if self.state_hash_interval > 0 {
+            self.messages_since_hash += 1;
+            if self.messages_since_hash == self.state_hash_interval {
+                self.messages_since_hash = 0;
+                let hash = self.state_hash();
+                self.state_hashes.push(hash);
+            }
+        }
+        result
+    }
+
+    /// Applies one message to its instrument's book; see `process_message`.
+    fn apply_message(&mut self, message: &MarketByOrderMessage) -> Result<(), MboProcessError> {
+        let key = self.book_key(message);
+        let mut mark = self.journal.is_enabled().then(|| self.mark(key));
+        // `None` when journaling is disabled, so no undo information is gathered.
+        let mut undo = mark.as_ref().map(|_| Undo::Nothing);
+
+        self.record_last(message);
+
+        let (events, depth_limit, flow_window) =
+            (self.book_events, self.book_depth_limit, self.flow_window);
+        let book_template = &self.book_template;
+        let InstrumentState {
+            book,
+            stats,
+            in_snapshot,
+            executed,
+            quotes,
+            history,
+            trades,
+            flow,
+        } = self.instruments.entry(key).or_insert_with(|| {
+            InstrumentState::from_template(book_template, events, depth_limit, flow_window)
+        });
+        stats.messages += 1;
+        stats.action_counts.record(message.action);
+
+        if self.validation_policy != ValidationPolicy::PassThrough
+            && let Err(error) = validate(message)
+        {
+            if self.validation_policy == ValidationPolicy::Reject {
+                stats.rejected_messages += 1;
+                self.journal_push(mark, undo);
+                return Err(error.into());
+            }
+            warn!("Skipping invalid message: {error}");
+            stats.skipped_messages += 1;
+            if message.is_last {
+                if let Some(trades) = trades {
+                    trades.end_event();
+                }
+                self.observer
+                    .on_event_complete(book, self.last_event_time, self.last_recv_time);
+            }
+            self.journal_push(mark, undo);
+            return Ok(());
+        }
+
+        // A snapshot is authoritative: the book is rebuilt from its records alone.
+        let snapshot_begins = message.is_snapshot() && !*in_snapshot;
+        // No order survives the clear, so a Modify beginning a snapshot fails before it,
+        // and before anything expires.
+        if snapshot_begins && message.action == Action::Modify {
+            self.journal_push(mark, undo);
+            return Err(OrderBookError::OrderNotFound(message.order_id).into());
+        }
+
+        // Orders expire with event time whether or not the message then applies, so a
+        // Modify or depth-limited Add that fails below leaves them purged
+        if self.order_ttl.is_some() {
+            for info in book.purge_expired(unix_nanos(message.event_time)) {
+                debug!(order_id = info.order.order_id, "Purging expired order");
+                if let Some(mark) = &mut mark {
+                    mark.expired.push(info.order);
+                }
+                self.observer.on_order_cancelled(&OrderCancelledEvent {
+                    order: info.order,
+                    remaining_level_qty: info.remaining_level_qty,
+                    remaining_level_count: info.remaining_level_count,
+                    level_removed: info.level_removed,
+                    event_time: message.event_time,
+                    recv_time: message.recv_time,
+                    sequence: message.sequence,
+                });
+            }
+        }
+
+        *in_snapshot = message.is_snapshot();
+        if snapshot_begins && message.action != Action::Clear {
+            debug!(
+                instrument_id = message.instrument_id,
+                "Snapshot begins, clearing book"
+            );
+            let old_book = book.clear();
+            record_undo(&mut undo, || Undo::RestoreBook(Box::new(old_book)));
+            self.observer.on_clear();
+        }
+
+        match message.action {
+            Action::Add => {
+                debug!(
+                    order_id = message.order_id,
+                    action = %message.action,
+                    side = ?message.side,
+                    price = message.price,
+                    size = message.size,
+                    "Adding order"
+                );
+                let replaced = undo
+                    .as_ref()
+                    .and_then(|_| book.get_order(message.order_id).copied());
+                let order = Order {
+                    expires_at: self
+                        .order_ttl
+                        .map(|ttl| unix_nanos(message.event_time + ttl)),
+                    ..Order::from(message)
+                };
+                // An order beyond a full side's worst level cannot cross, so it is
+                // rejected before matching
+                if let Err(error) = book.check_add(&order) {
+                    self.journal_push(mark, undo);
+                    return Err(error.into());
+                }
+                // Executions are classified after matching, by the book before it
+                if self.trade_quality && self.crossing_policy == CrossingPolicy::Match {
+                    trades.get_or_insert_with(TradeQuality::new).before_match(
+                        book,
+                        order.side,
+                        order.price,
+                    );
+                }
+                let MatchOutcome {
+                    executions,
+                    cancelled,
+                    incoming_cancelled,
+                } = match self.crossing_policy {
+                    CrossingPolicy::Rest => MatchOutcome::default(),
+                    CrossingPolicy::Match => book.match_order(&order, self.self_match_policy),
+                };
+                // Resting orders of the same owner cancelled rather than traded against
+                for info in &cancelled {
+                    debug!(
+                        order_id = info.order.order_id,
+                        "Cancelling resting order to prevent a self-match"
+                    );
+                    self.observer.on_order_cancelled(&OrderCancelledEvent {
+                        order: info.order,
+                        remaining_level_qty: info.remaining_level_qty,
+                        remaining_level_count: info.remaining_level_count,
+                        level_removed: info.level_removed,
+                        event_time: message.event_time,
+                        recv_time: message.recv_time,
+                        sequence: message.sequence,
+                    });
+                }
+                // Each execution is reported like a feed would: the aggressor's Trade
+                // followed by the resting order's Fill.
+                executions.iter().for_each(|execution| {
+                    if let Some(quality) = trades {
+                        quality.record(
+                            book,
+                            order.side,
+                            execution.resting.price,
+                            execution.size,
+                            message.event_time,
+                            message.sequence,
+                        );
+                    }
+                    stats.traded_volume += execution.size;
+                    flow.record(
+                        message.event_time,
+                        Some(order.side),
+                        Some(execution.resting.price),
+                        execution.size,
+                        None,
+                    );
+                    credit_executed(executed, &mut mark, execution.resting.owner, execution.size);
+                    let trade = TradeEvent {
+                        price: execution.resting.price,
+                        // Bounded by the incoming message's u32 size.
+                        size: execution.size as u32,
+                        side: order.side,
+                        order_id: order.order_id,
+                        aggressor: true,
+                        event_time: message.event_time,
+                        recv_time: message.recv_time,
+                        sequence: message.sequence,
+                    };
+                    self.observer.on_trade(&trade);
+                    self.observer.on_trade(&TradeEvent {
+                        side: execution.resting.side,
+                        order_id: execution.resting.order_id,
+                        aggressor: false,
+                        ..trade
+                    });
+                });
+
+                let executed: u64 = executions.iter().map(|execution| execution.size).sum();
+                let residual = Order {
+                    size: match incoming_cancelled {
+                        true => 0,
+                        false => order.size - executed,
+                    },
+                    ..order
+                };
+                let info = (residual.size > 0).then(|| book.add_order(residual));
+                record_undo(&mut undo, || {
+                    let remove_added = info.map(|_| Undo::RemoveAdded {
+                        order_id: message.order_id,
+                        replaced,
+                    });
+                    match (executions.is_empty() && cancelled.is_empty(), remove_added) {
+                        (true, Some(remove_added)) => remove_added,
+                        (_, remove_added) => Undo::Sequence(
+                            cancelled
+                                .iter()
+                                .map(|info| Undo::Restore(info.order))
+                                .chain(executions.iter().map(|execution| {
+                                    if execution.size == execution.resting.size {
+                                        Undo::Restore(execution.resting)
+                                    } else {
+                                        Undo::ReplaceWith(execution.resting)
+                                    }
+                                }))
+                                .chain(remove_added)
+                                .collect(),
+                        ),
+                    }
+                });
+
+                if let Some(info) = info.filter(|info| !info.dropped) {
+                    self.observer.on_order_added(&OrderAddedEvent {
+                        order: info.order,
+                        level_qty: info.level_qty,
+                        level_order_count: info.level_order_count,
+                        new_level: info.new_level,
+                        replaced_level: info.replaced_level,
                         event_time: message.event_time,
                         recv_time: message.recv_time,
                         sequence: message.sequence,
                     });
                 }
             }
-            Action::Fill | Action::Trade => {
-                // Fill and Trade do NOT modify the order book.
-                // If a trade affects a resting order's size, Databento sends
-                // a separate Modify or Cancel message for that change.
-                self.observer.on_trade(&TradeEvent {
-                    price: message.price,
-                    size: message.size,
-                    side: message.side,
-                    aggressor: message.action == Action::Trade,
+            Action::Cancel => {
+                debug!(
+                    order_id = message.order_id,
+                    action = %message.action,
+                    price = message.price,
+                    size = message.size,
+                    "Cancelling order"
+                );
+                // An unknown order is ignored; the book counts it as an anomaly.
+                if let Some(info) = book.remove_order(message.order_id) {
+                    record_undo(&mut undo, || Undo::Restore(info.order));
+                    self.observer.on_order_cancelled(&OrderCancelledEvent {
+                        order: info.order,
+                        remaining_level_qty: info.remaining_level_qty,
+                        remaining_level_count: info.remaining_level_count,
+                        level_removed: info.level_removed,
+                        event_time: message.event_time,
+                        recv_time: message.recv_time,
+                        sequence: message.sequence,
+                    })
+                }
+            }
+            Action::Modify => {
+                debug!(
+                    order_id = message.order_id,
+                    action = %message.action,
+                    price = message.price,
+                    size = message.size,
+                    "Modifying order"
+                );
+                let prior = undo
+                    .as_ref()
+                    .and_then(|_| book.get_order(message.order_id).copied());
+                let Some(info) =
+                    book.modify_order(Order::from(message), self.modify_priority_policy)
+                else {
+                    self.journal_push(mark, undo);
+                    return Err(OrderBookError::OrderNotFound(message.order_id).into());
+                };
+                if let Some(prior) = prior {
+                    record_undo(&mut undo, || Undo::ReplaceWith(prior));
+                }
+                self.observer.on_order_modified(&OrderModifiedEvent {
+                    order: info.order,
+                    old_price: info.old_price,
+                    old_size: info.old_size,
+                    level_qty: info.level_qty,
+                    level_order_count: info.level_order_count,
+                    retained_queue_position: info.retained_queue_position,
                     event_time: message.event_time,
                     recv_time: message.recv_time,
                     sequence: message.sequence,
                 });
             }
+            Action::Fill | Action::Trade => {
+                // Fill and Trade do NOT modify the order book.
+                // If a trade affects a resting order's size, Databento sends
+                // a separate Modify or Cancel message for that change.
+                if self.trade_quality {
+                    trades
+                        .get_or_insert_with(TradeQuality::new)
+                        .record_message(message, book);
+                }
+                if message.action == Action::Trade {
+                    stats.traded_volume += u64::from(message.size);
+                    flow.record(
+                        message.event_time,
+                        message.known_side(),
+                        message.defined_price(),
+                        message.size.into(),
+                        book.mid(),
+                    );
+                } else {
+                    // The filled order is still resting; its Modify or Cancel follows
+                    let owner = message.owner.or_else(|| {
+                        book.get_order(message.order_id)
+                            .and_then(|order| order.owner)
+                    });
+                    credit_executed(executed, &mut mark, owner, message.size.into());
+                    book.fill_order(message.order_id, message.size);
+                }
+                // A print without a price carries nothing observers can use.
+                match message.defined_price() {
+                    Some(price) => self.observer.on_trade(&TradeEvent {
+                        price,
+                        size: message.size,
+                        side: message.side,
+                        order_id: message.order_id,
+                        aggressor: message.action == Action::Trade,
+                        event_time: message.event_time,
+                        recv_time: message.recv_time,
+                        sequence: message.sequence,
+                    }),
+                    None => debug!("{} without a price, not reported", message.action),
+                }
+            }
             Action::Clear => {
-                // Order book will be rebuilt using subsequent messages.
-                debug!("Clearing order book");
-                self.order_book = OrderBook::new();
-                self.observer.on_clear();
+                if self.session_snapshots {
+                    let mut snapshot = book.snapshot();
+                    snapshot.event_time = Some(message.event_time);
+                    snapshot.recv_time = Some(message.recv_time);
+                    snapshot.sequence = Some(message.sequence);
+                    self.observer.on_session_snapshot(message, &snapshot);
+                }
+                match message.known_side() {
+                    Some(side) => {
+                        debug!(instrument_id = message.instrument_id, %side, "Clearing side");
+                        let removed = book.clear_side(side);
+                        record_undo(&mut undo, || {
+                            Undo::Sequence(
+                                removed
+                                    .iter()
+                                    .map(|info| Undo::Restore(info.order))
+                                    .collect(),
+                            )
+                        });
+                        for info in removed {
+                            self.observer.on_order_cancelled(&OrderCancelledEvent {
+                                order: info.order,
+                                remaining_level_qty: info.remaining_level_qty,
+                                remaining_level_count: info.remaining_level_count,
+                                level_removed: info.level_removed,
+                                event_time: message.event_time,
+                                recv_time: message.recv_time,
+                                sequence: message.sequence,
+                            });
+                        }
+                    }
+                    None => {
+                        // Order book will be rebuilt using subsequent messages.
+                        debug!(instrument_id = message.instrument_id, "Clearing order book");
+                        let old_book = book.clear();
+                        record_undo(&mut undo, || Undo::RestoreBook(Box::new(old_book)));
+                        self.observer.on_clear();
+                    }
+                }
             }
         }
 
-        if message.is_last {
-            self.observer.on_event_complete(
-                &self.order_book,
-                self.last_event_time,
-                self.last_recv_time,
-            );
-        }
+        self.observer.on_message_applied(message, key, book);
+        if message.is_last {
+            if self.quote_stats {
+                quotes
+                    .get_or_insert_with(QuoteStats::new)
+                    .update(message.event_time, book.bbo());
+            }
+            if let Some(template) = &self.bbo_history {
+                history
+                    .get_or_insert_with(|| template.clone())
+                    .record(message.event_time, book.bbo());
+            }
+            if let Some(trades) = trades {
+                trades.end_event();
+            }
+            self.observer
+                .on_event_complete(book, self.last_event_time, self.last_recv_time);
+        }
+
+        self.journal_push(mark, undo);
+        Ok(())
+    }
+
+    /// Takes the metadata of `message` as that of the last processed message.
+    pub(crate) fn record_last(&mut self, message: &MarketByOrderMessage) {
+        self.event_complete = message.is_last;
+        self.sequence_number = message.sequence;
+        self.last_event_time = message.event_time;
+        self.last_recv_time = message.recv_time;
+        self.last_ts_in_delta = message.ts_in_delta;
+        self.last_flags = message.flags;
+        self.last_publisher_id = message.publisher_id;
+        self.last_channel_id = message.channel_id;
+        self.last_key = self.book_key(message);
+    }
+
+    /// A processor without books or observer that applies messages under the same
+    /// policies as this one, for processing part of the input elsewhere. It journals
+    /// to the same depth and logs anomalies with the same retention, from an empty log;
+    /// state hashes are left off, as they cover every book at once.
+    #[cfg(feature = "parallel")]
+    pub(crate) fn with_same_policies(&self) -> MboProcessor<(), B> {
+        let processor = MboProcessor::with_observer_and_book((), self.book_template.clone())
+            .with_error_policy(self.error_policy)
+            .with_crossing_policy(self.crossing_policy)
+            .with_modify_priority_policy(self.modify_priority_policy)
+            .with_self_match_policy(self.self_match_policy)
+            .with_validation_policy(self.validation_policy)
+            .with_book_events(self.book_events)
+            .with_publisher_routing(self.publisher_routing);
+        let processor = match self.book_depth_limit {
+            Some((max_levels, policy)) => processor.with_book_depth_limit(max_levels, policy),
+            None => processor,
+        };
+        let mut processor = processor
+            .with_quote_stats(self.quote_stats)
+            .with_trade_quality(self.trade_quality);
+        processor.bbo_history = self.bbo_history.clone();
+        processor.order_ttl = self.order_ttl;
+        processor.session_snapshots = self.session_snapshots;
+        processor.flow_window = self.flow_window;
+        processor.journal = Journal::with_depth(self.journal.depth());
+        processor.anomaly_log = self.anomaly_log.as_ref().map(AnomalyLog::emptied);
+        processor
+    }
+
+    /// The anomaly log, if enabled.
+    #[cfg(feature = "parallel")]
+    pub(crate) fn anomaly_log_mut(&mut self) -> Option<&mut AnomalyLog> {
+        self.anomaly_log.as_mut()
+    }
+
+    /// Removes the anomaly log, if enabled.
+    #[cfg(feature = "parallel")]
+    pub(crate) fn take_anomaly_log(&mut self) -> Option<AnomalyLog> {
+        self.anomaly_log.take()
+    }
+
+    /// Removes the books and counters of every instrument.
+    #[cfg(feature = "parallel")]
+    pub(crate) fn take_instruments(&mut self) -> HashMap<BookKey, InstrumentState<B>> {
+        std::mem::take(&mut self.instruments)
+    }
+
+    /// Adds the books and counters of instruments taken from another processor.
+    #[cfg(feature = "parallel")]
+    pub(crate) fn insert_instruments(
+        &mut self,
+        instruments: impl IntoIterator<Item = (BookKey, InstrumentState<B>)>,
+    ) {
+        self.instruments.extend(instruments);
+    }
+
+    /// Drops the undo history, which no longer matches the books once they were
+    /// changed behind the journal's back.
+    #[cfg(feature = "parallel")]
+    pub(crate) fn clear_journal(&mut self) {
+        self.journal = Journal::with_depth(self.journal.depth());
+    }
+
+    /// Reverts the last `n` processed messages, newest first, restoring the books,
+    /// statistics and last-message metadata to their state before those messages.
+    ///
+    /// Requires journaling (`with_journal_depth`); fails without changing anything if
+    /// fewer than `n` messages are journaled. Observers are not notified.
+    pub fn rollback(&mut self, n: usize) -> Result<(), RollbackError> {
+        self.journal
+            .take_last(n)?
+            .into_iter()
+            .for_each(|entry| self.revert(entry));
+        Ok(())
+    }
+
+    /// Captures the state a message for the book under `key` is about to overwrite.
+    fn mark(&self, key: BookKey) -> ProcessorMark {
+        let state = self.instruments.get(&key);
+        ProcessorMark {
+            key,
+            new_instrument: state.is_none(),
+            stats: state.map(|s| s.stats).unwrap_or_default(),
+            executed: Vec::new(),
+            expired: Vec::new(),
+            anomalies: state.map(|s| s.book.anomalies()).unwrap_or_default(),
+            flow: state.map(|s| s.flow.mark()).unwrap_or_default(),
+            in_snapshot: state.is_some_and(|s| s.in_snapshot),
+            last_key: self.last_key,
+            event_complete: self.event_complete,
+            sequence_number: self.sequence_number,
+            last_event_time: self.last_event_time,
+            last_recv_time: self.last_recv_time,
+            last_ts_in_delta: self.last_ts_in_delta,
+            last_flags: self.last_flags,
+            last_publisher_id: self.last_publisher_id,
+            last_channel_id: self.last_channel_id,
+        }
+    }
+
+    fn journal_push(&mut self, mark: Option<ProcessorMark>, undo: Option<Undo<B>>) {
+        if let (Some(mark), Some(undo)) = (mark, undo) {
+            self.journal.push(JournalEntry { mark, undo });
+        }
+    }
+
+    fn revert(&mut self, JournalEntry { mark, undo }: JournalEntry<B>) {
+        if mark.new_instrument {
+            self.instruments.remove(&mark.key);
+        } else if let Some(state) = self.instruments.get_mut(&mark.key) {
+            undo.apply(&mut state.book);
+            // Purged before the message applied, so put back after undoing it
+            for &order in &mark.expired {
+                state.book.add_order(order);
+            }
+            state.book.restore_anomalies(mark.anomalies);
+            state.stats = mark.stats;
+            state.flow.restore(mark.flow);
+            state.in_snapshot = mark.in_snapshot;
+            for &(owner, qty) in &mark.executed {
+                if let Entry::Occupied(mut entry) = state.executed.entry(owner) {
+                    *entry.get_mut() -= qty;
+                    if *entry.get() == 0 {
+                        entry.remove();
+                    }
+                }
+            }
+        }
+        self.last_key = mark.last_key;
+        self.event_complete = mark.event_complete;
+        self.sequence_number = mark.sequence_number;
+        self.last_event_time = mark.last_event_time;
+        self.last_recv_time = mark.last_recv_time;
+        self.last_ts_in_delta = mark.last_ts_in_delta;
+        self.last_flags = mark.last_flags;
+        self.last_publisher_id = mark.last_publisher_id;
+        self.last_channel_id = mark.last_channel_id;
+    }
+
+    /// Processes a stream of messages in order and summarizes the outcome.
+    ///
+    /// Accepts anything that yields messages by value or by reference, so a lazy
+    /// adapter over decoded DBN records can be passed without collecting into a `Vec`.
+    /// Failures are reported with their 0-based index in the input; whether processing
+    /// continues after a failure is governed by the processor's `ErrorPolicy`.
+    pub fn process_messages<I>(&mut self, messages: I) -> ProcessSummary
+    where
+        I: IntoIterator,
+        I::Item: Borrow<MarketByOrderMessage>,
+    {
+        let mut summary = ProcessSummary::default();
+        for (index, message) in messages.into_iter().enumerate() {
+            let message = message.borrow();
+            match self.process_message(message) {
+                Ok(()) => {
+                    summary.processed += 1;
+                    summary.action_counts.record(message.action);
+                }
+                Err(error) => {
+                    summary.failures.push(ProcessFailure { index, error });
+                    if self.error_policy == ErrorPolicy::FailFast {
+                        break;
+                    }
+                }
+            }
+        }
+        summary
+    }
+
+    /// Processes messages whose `event_time` is at or before `ts_event` (nanoseconds since
+    /// the UNIX epoch) and stops, leaving `messages` positioned at the first later message
+    /// so processing can resume from there.
+    ///
+    /// All messages sharing the target timestamp are applied. Failure indices in the
+    /// summary are relative to the start of this call.
+    pub fn process_until<I>(&mut self, messages: &mut Peekable<I>, ts_event: u64) -> ProcessSummary
+    where
+        I: Iterator,
+        I::Item: Borrow<MarketByOrderMessage>,
+    {
+        let target = i128::from(ts_event);
+        self.process_messages(iter::from_fn(|| {
+            messages.next_if(|message| message.borrow().event_time.unix_timestamp_nanos() <= target)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+    use std::ffi::c_char;
+    use std::{env, fs, process};
+
+    use time::{Duration, OffsetDateTime};
+
+    use crate::generators::{MessageMix, OrderGenerator};
+    use crate::orderbook::{
+        AnomalyKind, BboRecorder, BookChange, BookEvent, FlowStats, IncrementalMbp,
+        OrderLevelSummary, TradeCollector,
+    };
+
+    fn ts(s: &str) -> OffsetDateTime {
+        use time::format_description::well_known::Rfc3339;
+        OffsetDateTime::parse(s, &Rfc3339).unwrap()
+    }
+
+    /// Helper for creating test messages with auto-incrementing sequence numbers and timestamps.
+    /// Each test should create its own instance to get monotonic sequences and timestamps.
+    struct TestMessageBuilder {
+        next_sequence: u32,
+        next_event_time: OffsetDateTime,
+        time_increment: Duration,
+    }
+
+    impl TestMessageBuilder {
+        fn new() -> Self {
+            Self {
+                next_sequence: 1,
+                next_event_time: ts("2024-01-01T00:00:00Z"),
+                time_increment: Duration::milliseconds(1),
+            }
+        }
+
+        fn msg(
+            &mut self,
+            action: Action,
+            order_id: u64,
+            side: Side,
+            price: i64,
+            size: u32,
+            is_last: bool,
+        ) -> MarketByOrderMessage {
+            let sequence = self.next_sequence;
+            self.next_sequence += 1;
+
+            let event_time = self.next_event_time;
+            self.next_event_time += self.time_increment;
+
+            MarketByOrderMessage {
+                sequence,
+                recv_time: event_time + Duration::microseconds(50),
+                ts_in_delta: Duration::microseconds(-10),
+                ..MarketByOrderMessage::test(action, side, price, order_id, size)
+                    .at(event_time)
+                    .with_last(is_last)
+            }
+        }
+
+        /// A Clear of the whole book, without a side.
+        fn clear(&mut self) -> MarketByOrderMessage {
+            MarketByOrderMessage {
+                no_side: true,
+                ..self.msg(Action::Clear, 0, Side::Bid, 0, 0, true)
+            }
+        }
+    }
+
+    #[test]
+    fn test_fill_does_not_modify_book() {
+        let mut proc = MboProcessor::new();
+        let mut seq = TestMessageBuilder::new();
+
+        // Add an order
+        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, true))
+            .unwrap();
+        assert_eq!(proc.order_book().best_bid(), Some((100, 50)));
+
+        // Fill should NOT change the order
+        proc.process_message(&seq.msg(Action::Fill, 1, Side::Bid, 100, 20, false))
+            .unwrap();
+        assert_eq!(proc.order_book().best_bid(), Some((100, 50)));
+    }
+
+    #[test]
+    fn test_trade_does_not_modify_book() {
+        let mut proc = MboProcessor::new();
+        let mut seq = TestMessageBuilder::new();
+
+        proc.process_message(&seq.msg(Action::Add, 1, Side::Ask, 200, 30, true))
+            .unwrap();
+        assert_eq!(proc.order_book().best_ask(), Some((200, 30)));
+
+        // Trade should NOT change the order
+        proc.process_message(&seq.msg(Action::Trade, 1, Side::Ask, 200, 10, true))
+            .unwrap();
+        assert_eq!(proc.order_book().best_ask(), Some((200, 30)));
+    }
+
+    #[test]
+    fn test_event_complete_tracks_last_flag() {
+        let mut proc = MboProcessor::new();
+        let mut seq = TestMessageBuilder::new();
+
+        // Initially event_complete is true
+        assert!(proc.is_event_complete());
+
+        // Non-LAST message sets event_complete to false
+        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, false))
+            .unwrap();
+        assert!(!proc.is_event_complete());
+
+        // LAST message sets event_complete to true
+        proc.process_message(&seq.msg(Action::Add, 2, Side::Bid, 100, 30, true))
+            .unwrap();
+        assert!(proc.is_event_complete());
+    }
+
+    #[test]
+    fn test_add_cancel_modify_still_work() {
+        let mut proc = MboProcessor::new();
+        let mut seq = TestMessageBuilder::new();
+
+        // Add
+        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, true))
+            .unwrap();
+        assert_eq!(proc.order_book().best_bid(), Some((100, 50)));
+
+        // Modify (changes price and size)
+        proc.process_message(&seq.msg(Action::Modify, 1, Side::Bid, 110, 60, true))
+            .unwrap();
+        assert_eq!(proc.order_book().best_bid(), Some((110, 60)));
+
+        // Cancel
+        proc.process_message(&seq.msg(Action::Cancel, 1, Side::Bid, 0, 0, true))
+            .unwrap();
+        assert_eq!(proc.order_book().best_bid(), None);
+    }
+
+    #[test]
+    fn test_clear_resets_book() {
+        let mut proc = MboProcessor::new();
+        let mut seq = TestMessageBuilder::new();
+
+        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, true))
+            .unwrap();
+        proc.process_message(&seq.msg(Action::Add, 2, Side::Ask, 200, 30, true))
+            .unwrap();
+        assert!(proc.order_book().best_bid().is_some());
+        assert!(proc.order_book().best_ask().is_some());
+
+        proc.process_message(&seq.clear()).unwrap();
+        assert_eq!(proc.order_book().best_bid(), None);
+        assert_eq!(proc.order_book().best_ask(), None);
+    }
+
+    #[test]
+    fn test_clear_removes_seeded_orders() {
+        let mut proc = MboProcessor::new();
+        let mut seq = TestMessageBuilder::new();
+        let levels = [(Side::Bid, 100, 40, 4), (Side::Ask, 101, 30, 3)];
+        assert_eq!(proc.seed_book(1, &levels, SeedMode::PerOrder), 7);
+
+        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 5, true))
+            .unwrap();
+        proc.process_message(&seq.msg(Action::Cancel, 1, Side::Bid, 100, 5, true))
+            .unwrap();
+        assert_eq!(proc.best_bid(), Some((100, 40)));
+        assert_eq!(proc.best_ask(), Some((101, 30)));
+
+        proc.process_message(&seq.clear()).unwrap();
+        assert_eq!(proc.best_bid(), None);
+        assert_eq!(proc.best_ask(), None);
+    }
+
+    #[test]
+    fn test_seeded_book_is_configured() {
+        let mut proc = MboProcessor::new()
+            .with_book_depth_limit(2, DepthLimitPolicy::Drop)
+            .with_book_events(EventRetention::All);
+        let levels = [
+            (Side::Bid, 100, 10, 1),
+            (Side::Bid, 99, 10, 1),
+            (Side::Bid, 98, 10, 1),
+        ];
+        proc.seed_book(1, &levels, SeedMode::Aggregate);
+
+        let book = proc.book(1).unwrap();
+        assert_eq!(book.level_count(Side::Bid), 2);
+        assert_eq!(book.anomalies().depth_dropped, 1);
+        assert_eq!(book.events().count(), 2);
+    }
+
+    #[test]
+    fn test_clear_with_side_keeps_other_side() {
+        #[derive(Debug, Default)]
+        struct Cancels {
+            cancelled: Vec<u64>,
+            clears: usize,
+        }
+
+        impl MboObserver for Cancels {
+            fn on_order_cancelled(&mut self, event: &OrderCancelledEvent) {
+                self.cancelled.push(event.order.order_id);
+            }
+
+            fn on_clear(&mut self) {
+                self.clears += 1;
+            }
+        }
+
+        let mut proc = MboProcessor::with_observer(Cancels::default()).with_journal_depth(1);
+        let mut seq = TestMessageBuilder::new();
+        let messages = [
+            seq.msg(Action::Add, 1, Side::Bid, 100, 50, true),
+            seq.msg(Action::Add, 2, Side::Bid, 101, 20, true),
+            seq.msg(Action::Add, 3, Side::Bid, 101, 10, true),
+            seq.msg(Action::Add, 4, Side::Ask, 105, 30, true),
+        ];
+        assert!(proc.process_messages(messages).is_success());
+        let before = proc.state_hash();
+
+        proc.process_message(&seq.msg(Action::Clear, 0, Side::Bid, 0, 0, true))
+            .unwrap();
+        assert_eq!(proc.best_bid(), None);
+        assert_eq!(proc.best_ask(), Some((105, 30)));
+        // Reported as cancels, best level first and in queue order
+        assert_eq!(proc.observer().cancelled, vec![2, 3, 1]);
+        assert_eq!(proc.observer().clears, 0);
+
+        proc.rollback(1).unwrap();
+        assert_eq!(proc.state_hash(), before);
+        assert_eq!(proc.order_book().queue_position(3), Some(1));
+    }
+
+    #[test]
+    fn test_session_snapshot_before_clear() {
+        type Levels = Vec<(i64, u64)>;
+
+        /// Instrument, sequence, and bid and ask levels of each session snapshot.
+        #[derive(Debug, Default)]
+        struct Sessions(Vec<(u32, Option<u32>, Levels, Levels)>);
+
+        impl MboObserver for Sessions {
+            fn on_session_snapshot(
+                &mut self,
+                message: &MarketByOrderMessage,
+                snapshot: &MarketByPrice,
+            ) {
+                assert_eq!(snapshot.event_time, Some(message.event_time));
+                let levels = |side: &BTreeMap<i64, OrderLevelSummary>| {
+                    side.iter()
+                        .map(|(&price, level)| (price, level.total_quantity))
+                        .collect()
+                };
+                self.0.push((
+                    message.instrument_id,
+                    snapshot.sequence,
+                    levels(&snapshot.bids),
+                    levels(&snapshot.asks),
+                ));
+            }
+        }
+
+        let mut seq = TestMessageBuilder::new();
+        let messages = [
+            for_instrument(10, seq.msg(Action::Add, 1, Side::Bid, 100, 50, true)),
+            for_instrument(20, seq.msg(Action::Add, 1, Side::Ask, 200, 30, true)),
+            for_instrument(10, seq.msg(Action::Add, 2, Side::Ask, 105, 5, true)),
+            for_instrument(10, seq.msg(Action::Clear, 0, Side::Ask, 0, 0, true)),
+            for_instrument(10, seq.clear()),
+        ];
+        let clear = messages[3];
+
+        let mut proc = MboProcessor::with_observer(Sessions::default());
+        assert!(proc.process_messages(messages).is_success());
+        assert!(proc.observer().0.is_empty());
+
+        let mut proc =
+            MboProcessor::with_observer(Sessions::default()).with_session_snapshots(true);
+        assert!(proc.process_messages(messages).is_success());
+        // Each shows the book its Clear found, stamped with the Clear
+        assert_eq!(
+            proc.observer().0,
+            vec![
+                (10, Some(clear.sequence), vec![(100, 50)], vec![(105, 5)]),
+                (10, Some(clear.sequence + 1), vec![(100, 50)], vec![]),
+            ]
+        );
+        // The other instrument's book was neither snapshotted nor cleared
+        assert_eq!(proc.book(20).unwrap().best_ask(), Some((200, 30)));
+        assert!(proc.mbp(10).unwrap().bids.is_empty());
+    }
+
+    #[test]
+    fn test_processor_tracks_timestamps() {
+        let mut proc = MboProcessor::new();
+        let mut seq = TestMessageBuilder::new();
+
+        // Initially timestamps are at UNIX epoch
+        assert_eq!(proc.last_event_time(), OffsetDateTime::UNIX_EPOCH);
+        assert_eq!(proc.last_recv_time(), OffsetDateTime::UNIX_EPOCH);
+
+        // Process first message
+        let msg1 = seq.msg(Action::Add, 1, Side::Bid, 100, 50, true);
+        proc.process_message(&msg1).unwrap();
+
+        assert_eq!(proc.last_event_time(), msg1.event_time);
+        assert_eq!(proc.last_recv_time(), msg1.recv_time);
+        assert_eq!(proc.last_ts_in_delta(), msg1.ts_in_delta);
+
+        // Process second message - timestamps should update
+        let msg2 = seq.msg(Action::Add, 2, Side::Bid, 99, 30, true);
+        proc.process_message(&msg2).unwrap();
+
+        assert_eq!(proc.last_event_time(), msg2.event_time);
+        assert!(proc.last_event_time() > msg1.event_time);
+    }
+
+    // --- Observer tests ---
+
+    /// Test observer that counts how many times each callback is invoked.
+    #[derive(Debug, Default)]
+    struct CountingObserver {
+        adds: u32,
+        cancels: u32,
+        modifies: u32,
+        trades: u32,
+        clears: u32,
+        event_completes: u32,
+    }
+
+    impl MboObserver for CountingObserver {
+        fn on_order_added(&mut self, _event: &OrderAddedEvent) {
+            self.adds += 1;
+        }
+        fn on_order_cancelled(&mut self, _event: &OrderCancelledEvent) {
+            self.cancels += 1;
+        }
+        fn on_order_modified(&mut self, _event: &OrderModifiedEvent) {
+            self.modifies += 1;
+        }
+        fn on_trade(&mut self, _event: &TradeEvent) {
+            self.trades += 1;
+        }
+        fn on_clear(&mut self) {
+            self.clears += 1;
+        }
+        fn on_event_complete(
+            &mut self,
+            _book: &OrderBook,
+            _event_time: OffsetDateTime,
+            _recv_time: OffsetDateTime,
+        ) {
+            self.event_completes += 1;
+        }
+    }
+
+    #[test]
+    fn test_observer_called_for_each_action() {
+        let mut proc = MboProcessor::with_observer(CountingObserver::default());
+        let mut seq = TestMessageBuilder::new();
+
+        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, true))
+            .unwrap();
+        proc.process_message(&seq.msg(Action::Modify, 1, Side::Bid, 110, 60, true))
+            .unwrap();
+        proc.process_message(&seq.msg(Action::Cancel, 1, Side::Bid, 0, 0, true))
+            .unwrap();
+        proc.process_message(&seq.msg(Action::Trade, 99, Side::Bid, 100, 10, true))
+            .unwrap();
+        proc.process_message(&seq.msg(Action::Fill, 99, Side::Bid, 100, 10, true))
+            .unwrap();
+        proc.process_message(&seq.clear()).unwrap();
+
+        let obs = proc.observer();
+        assert_eq!(obs.adds, 1);
+        assert_eq!(obs.modifies, 1);
+        assert_eq!(obs.cancels, 1);
+        assert_eq!(obs.trades, 2); // Trade + Fill both fire on_trade
+        assert_eq!(obs.clears, 1);
+    }
+
+    #[test]
+    fn test_event_complete_fires_only_on_is_last() {
+        let mut proc = MboProcessor::with_observer(CountingObserver::default());
+        let mut seq = TestMessageBuilder::new();
+
+        // Non-LAST message should not fire event_complete
+        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, false))
+            .unwrap();
+        assert_eq!(proc.observer().event_completes, 0);
+
+        // LAST message should fire event_complete
+        proc.process_message(&seq.msg(Action::Add, 2, Side::Bid, 99, 30, true))
+            .unwrap();
+        assert_eq!(proc.observer().event_completes, 1);
+
+        // Another non-LAST
+        proc.process_message(&seq.msg(Action::Trade, 99, Side::Bid, 100, 10, false))
+            .unwrap();
+        assert_eq!(proc.observer().event_completes, 1);
+
+        // LAST again
+        proc.process_message(&seq.msg(Action::Fill, 1, Side::Bid, 100, 10, true))
+            .unwrap();
+        assert_eq!(proc.observer().event_completes, 2);
+    }
+
+    #[test]
+    fn test_event_complete_receives_correct_book_and_timestamps() {
+        /// Observer that captures the book state at event completion.
+        #[derive(Debug)]
+        struct SnapshotObserver {
+            best_bid_at_complete: Option<(i64, u64)>,
+            captured_event_time: OffsetDateTime,
+            captured_recv_time: OffsetDateTime,
+        }
+
+        impl Default for SnapshotObserver {
+            fn default() -> Self {
+                Self {
+                    best_bid_at_complete: None,
+                    captured_event_time: OffsetDateTime::UNIX_EPOCH,
+                    captured_recv_time: OffsetDateTime::UNIX_EPOCH,
+                }
+            }
+        }
+
+        impl MboObserver for SnapshotObserver {
+            fn on_event_complete(
+                &mut self,
+                book: &OrderBook,
+                event_time: OffsetDateTime,
+                recv_time: OffsetDateTime,
+            ) {
+                self.best_bid_at_complete = book.best_bid();
+                self.captured_event_time = event_time;
+                self.captured_recv_time = recv_time;
+            }
+        }
+
+        let mut proc = MboProcessor::with_observer(SnapshotObserver::default());
+        let mut seq = TestMessageBuilder::new();
+
+        let msg = seq.msg(Action::Add, 1, Side::Bid, 100, 50, true);
+        let expected_event_time = msg.event_time;
+        let expected_recv_time = msg.recv_time;
+        proc.process_message(&msg).unwrap();
+
+        let obs = proc.observer();
+        assert_eq!(obs.best_bid_at_complete, Some((100, 50)));
+        assert_eq!(obs.captured_event_time, expected_event_time);
+        assert_eq!(obs.captured_recv_time, expected_recv_time);
+    }
+
+    #[test]
+    fn test_tuple_observer_both_called() {
+        let obs_a = CountingObserver::default();
+        let obs_b = CountingObserver::default();
+        let mut proc = MboProcessor::with_observer((obs_a, obs_b));
+        let mut seq = TestMessageBuilder::new();
+
+        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, true))
+            .unwrap();
+        proc.process_message(&seq.msg(Action::Trade, 99, Side::Bid, 100, 10, true))
+            .unwrap();
+
+        let (a, b) = proc.observer();
+        assert_eq!(a.adds, 1);
+        assert_eq!(a.trades, 1);
+        assert_eq!(a.event_completes, 2);
+        assert_eq!(b.adds, 1);
+        assert_eq!(b.trades, 1);
+        assert_eq!(b.event_completes, 2);
+    }
+
+    #[test]
+    fn test_into_observer_extracts_state() {
+        let mut proc = MboProcessor::with_observer(CountingObserver::default());
+        let mut seq = TestMessageBuilder::new();
+
+        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, true))
+            .unwrap();
+        proc.process_message(&seq.msg(Action::Add, 2, Side::Bid, 99, 30, true))
+            .unwrap();
+
+        let obs = proc.into_observer();
+        assert_eq!(obs.adds, 2);
+        assert_eq!(obs.event_completes, 2);
+    }
+
+    #[test]
+    fn test_order_added_event_contains_level_info() {
+        #[derive(Debug, Default)]
+        struct AddObserver {
+            last_event: Option<OrderAddedEvent>,
+        }
+        impl MboObserver for AddObserver {
+            fn on_order_added(&mut self, event: &OrderAddedEvent) {
+                self.last_event = Some(*event);
+            }
+        }
+
+        let mut proc = MboProcessor::with_observer(AddObserver::default());
+        let mut seq = TestMessageBuilder::new();
+
+        // First order at price 100 — new level
+        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, true))
+            .unwrap();
+        let evt = proc.observer().last_event.unwrap();
+        assert_eq!(evt.order.price, 100);
+        assert_eq!(evt.order.size, 50);
+        assert_eq!(evt.level_qty, 50);
+        assert_eq!(evt.level_order_count, 1);
+        assert!(evt.new_level);
+
+        // Second order at same price — existing level
+        proc.process_message(&seq.msg(Action::Add, 2, Side::Bid, 100, 30, true))
+            .unwrap();
+        let evt = proc.observer().last_event.unwrap();
+        assert_eq!(evt.level_qty, 80); // 50 + 30
+        assert_eq!(evt.level_order_count, 2);
+        assert!(!evt.new_level);
+    }
+
+    #[test]
+    fn test_order_cancelled_event_contains_level_info() {
+        #[derive(Debug, Default)]
+        struct CancelObserver {
+            last_event: Option<OrderCancelledEvent>,
+        }
+        impl MboObserver for CancelObserver {
+            fn on_order_cancelled(&mut self, event: &OrderCancelledEvent) {
+                self.last_event = Some(*event);
+            }
+        }
+
+        let mut proc = MboProcessor::with_observer(CancelObserver::default());
+        let mut seq = TestMessageBuilder::new();
+
+        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, true))
+            .unwrap();
+        proc.process_message(&seq.msg(Action::Add, 2, Side::Bid, 100, 30, true))
+            .unwrap();
+
+        // Cancel one of two orders at this level
+        proc.process_message(&seq.msg(Action::Cancel, 1, Side::Bid, 0, 0, true))
+            .unwrap();
+        let evt = proc.observer().last_event.unwrap();
+        assert_eq!(evt.order.order_id, 1);
+        assert_eq!(evt.order.size, 50);
+        assert_eq!(evt.remaining_level_qty, 30);
+        assert_eq!(evt.remaining_level_count, 1);
+        assert!(!evt.level_removed);
+
+        // Cancel the last order — level should be removed
+        proc.process_message(&seq.msg(Action::Cancel, 2, Side::Bid, 0, 0, true))
+            .unwrap();
+        let evt = proc.observer().last_event.unwrap();
+        assert_eq!(evt.order.order_id, 2);
+        assert_eq!(evt.remaining_level_qty, 0);
+        assert_eq!(evt.remaining_level_count, 0);
+        assert!(evt.level_removed);
+    }
+
+    #[test]
+    fn test_order_modified_event_captures_old_state() {
+        #[derive(Debug, Default)]
+        struct ModifyObserver {
+            last_event: Option<OrderModifiedEvent>,
+        }
+        impl MboObserver for ModifyObserver {
+            fn on_order_modified(&mut self, event: &OrderModifiedEvent) {
+                self.last_event = Some(*event);
+            }
+        }
+
+        let mut proc = MboProcessor::with_observer(ModifyObserver::default());
+        let mut seq = TestMessageBuilder::new();
+
+        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, true))
+            .unwrap();
+
+        // Modify price and size
+        proc.process_message(&seq.msg(Action::Modify, 1, Side::Bid, 110, 60, true))
+            .unwrap();
+        let evt = proc.observer().last_event.unwrap();
+        assert_eq!(evt.order.price, 110);
+        assert_eq!(evt.order.size, 60);
+        assert_eq!(evt.old_price, 100);
+        assert_eq!(evt.old_size, 50);
+        assert_eq!(evt.level_qty, 60);
+        assert_eq!(evt.level_order_count, 1);
+    }
+
+    #[test]
+    fn test_trade_event_aggressor_flag() {
+        #[derive(Debug, Default)]
+        struct TradeObserver {
+            events: Vec<TradeEvent>,
+        }
+        impl MboObserver for TradeObserver {
+            fn on_trade(&mut self, event: &TradeEvent) {
+                self.events.push(*event);
+            }
+        }
+
+        let mut proc = MboProcessor::with_observer(TradeObserver::default());
+        let mut seq = TestMessageBuilder::new();
+
+        // Trade action → aggressor = true
+        proc.process_message(&seq.msg(Action::Trade, 99, Side::Ask, 100, 10, false))
+            .unwrap();
+        // Fill action → aggressor = false
+        proc.process_message(&seq.msg(Action::Fill, 1, Side::Bid, 100, 10, true))
+            .unwrap();
+
+        let events = &proc.observer().events;
+        assert_eq!(events.len(), 2);
+        assert!(events[0].aggressor);
+        assert!(!events[1].aggressor);
+    }
+
+    #[test]
+    fn test_trade_collector_observer() {
+        use crate::orderbook::tradestream::TradeCollector;
+
+        let mut proc = MboProcessor::with_observer(TradeCollector::new());
+        let mut seq = TestMessageBuilder::new();
+
+        // Add an order (should not produce trades)
+        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, true))
+            .unwrap();
+        assert_eq!(proc.observer().trades().len(), 0);
+
+        // Trade message should be collected (aggressor)
+        proc.process_message(&seq.msg(Action::Trade, 99, Side::Ask, 100, 10, false))
+            .unwrap();
+        assert_eq!(proc.observer().trades().len(), 1);
+
+        // Fill message should also be collected (passive)
+        proc.process_message(&seq.msg(Action::Fill, 1, Side::Bid, 100, 20, true))
+            .unwrap();
+        assert_eq!(proc.observer().trades().len(), 2);
+
+        // Verify trade data and aggressor flag
+        let trades = proc.into_observer().into_trades();
+        assert_eq!(trades[0].price, 100);
+        assert_eq!(trades[0].size, 10);
+        assert!(trades[0].aggressor);
+        assert_eq!(trades[1].price, 100);
+        assert_eq!(trades[1].size, 20);
+        assert!(!trades[1].aggressor);
+    }
+
+    #[test]
+    fn test_flow_stats_classify_prints() {
+        let mut proc = MboProcessor::new()
+            .with_crossing_policy(CrossingPolicy::Match)
+            .with_flow_window(Duration::milliseconds(3))
+            .with_journal_depth(4);
+        let mut seq = TestMessageBuilder::new();
+        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, true))
+            .unwrap();
+        proc.process_message(&seq.msg(Action::Add, 2, Side::Ask, 104, 50, true))
+            .unwrap();
+        let unsided = |message: MarketByOrderMessage| MarketByOrderMessage {
+            no_side: true,
+            ..message
+        };
+        // Above the mid of 102, a buy
+        proc.process_message(&unsided(seq.msg(Action::Trade, 0, Side::Bid, 104, 5, true)))
+            .unwrap();
+        // An explicit sell, though above the mid and at the last price
+        proc.process_message(&seq.msg(Action::Trade, 0, Side::Ask, 104, 3, true))
+            .unwrap();
+        // At the mid, a downtick from 104: a sell
+        proc.process_message(&unsided(seq.msg(Action::Trade, 0, Side::Bid, 102, 2, true)))
+            .unwrap();
+        // Fills are not counted; a crossing Add under Match is a buy of 7
+        proc.process_message(&seq.msg(Action::Fill, 2, Side::Ask, 104, 5, true))
+            .unwrap();
+        proc.process_message(&seq.msg(Action::Add, 3, Side::Bid, 104, 7, true))
+            .unwrap();
 
-        Ok(())
+        let flow = proc.flow_stats();
+        assert_eq!(
+            flow,
+            FlowStats {
+                buy_volume: 12,
+                sell_volume: 5,
+                buy_trades: 2,
+                sell_trades: 2,
+                unclassified: 0,
+            }
+        );
+        assert_eq!(
+            flow.buy_volume + flow.sell_volume,
+            proc.stats().traded_volume
+        );
+        assert_eq!(proc.instrument_flow_stats(1), Some(flow));
+        // The window of 3 ms ending at the Add holds the sell of 2 and the buy of 7
+        let recent = proc.recent_flow_stats().unwrap();
+        assert_eq!((recent.buy_volume, recent.sell_volume), (7, 2));
+
+        proc.rollback(3).unwrap();
+        let flow = proc.flow_stats();
+        assert_eq!((flow.buy_volume, flow.sell_volume), (5, 3));
+        assert_eq!(MboProcessor::new().recent_flow_stats(), None);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_bbo_history_records_completed_events() {
+        let mut proc = MboProcessor::new().with_bbo_history(BboHistory::new().with_max_len(2));
+        let mut seq = TestMessageBuilder::new();
+        let start = seq.next_event_time;
+        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, true))
+            .unwrap();
+        // Mid-event quotes are not recorded
+        proc.process_message(&seq.msg(Action::Add, 2, Side::Ask, 105, 50, false))
+            .unwrap();
+        proc.process_message(&seq.msg(Action::Add, 3, Side::Ask, 104, 50, true))
+            .unwrap();
+        proc.process_message(&seq.msg(Action::Cancel, 3, Side::Ask, 104, 50, true))
+            .unwrap();
 
-    use time::{Duration, OffsetDateTime};
+        let history = proc.bbo_history(1).unwrap();
+        assert_eq!(history.changes(), 3);
+        let spreads: Vec<_> = history.iter().map(|sample| sample.spread()).collect();
+        assert_eq!(spreads, [Some(4), Some(5)]);
+        assert_eq!(history.max_len(), Some(2));
+        // The bid-only quote at the start was evicted
+        assert_eq!(history.at(start), None);
+        assert_eq!(
+            history
+                .at(start + Duration::milliseconds(2))
+                .map(|bbo| bbo.ask),
+            Some(Some((104, 50)))
+        );
+        assert_eq!(MboProcessor::new().bbo_history(1), None);
+    }
 
-    fn ts(s: &str) -> OffsetDateTime {
-        use time::format_description::well_known::Rfc3339;
-        OffsetDateTime::parse(s, &Rfc3339).unwrap()
+    // --- Batch processing tests ---
+
+    /// Ten messages where the 7th (index 6) modifies an order that was never added.
+    fn batch_with_bad_seventh(seq: &mut TestMessageBuilder) -> Vec<MarketByOrderMessage> {
+        vec![
+            seq.msg(Action::Add, 1, Side::Bid, 100, 50, true),
+            seq.msg(Action::Add, 2, Side::Bid, 99, 30, true),
+            seq.msg(Action::Add, 3, Side::Ask, 101, 40, true),
+            seq.msg(Action::Modify, 1, Side::Bid, 100, 45, true),
+            seq.msg(Action::Trade, 0, Side::Ask, 100, 5, false),
+            seq.msg(Action::Fill, 1, Side::Bid, 100, 5, true),
+            seq.msg(Action::Modify, 42, Side::Bid, 98, 10, true),
+            seq.msg(Action::Cancel, 2, Side::Bid, 0, 0, true),
+            seq.msg(Action::Add, 4, Side::Ask, 102, 20, true),
+            seq.msg(Action::Cancel, 3, Side::Ask, 0, 0, true),
+        ]
     }
 
-    /// Helper for creating test messages with auto-incrementing sequence numbers and timestamps.
-    /// Each test should create its own instance to get monotonic sequences and timestamps.
-    struct TestMessageBuilder {
-        next_sequence: u32,
-        next_event_time: OffsetDateTime,
-        time_increment: Duration,
+    #[test]
+    fn test_process_messages_fail_fast_reports_failure_index() {
+        let mut proc = MboProcessor::new();
+        let mut seq = TestMessageBuilder::new();
+        let messages = batch_with_bad_seventh(&mut seq);
+
+        let summary = proc.process_messages(messages);
+
+        assert_eq!(summary.processed, 6);
+        assert_eq!(summary.failures.len(), 1);
+        let failure = summary.first_failure().unwrap();
+        assert_eq!(failure.index, 6);
+        assert!(matches!(
+            failure.error,
+            MboProcessError::OrderBookError(OrderBookError::OrderNotFound(42))
+        ));
+
+        // Processing stopped at the failure: order 2 was never cancelled, order 4 never added.
+        assert_eq!(proc.order_book().top_n_bids(2), vec![(100, 45), (99, 30)]);
+        assert_eq!(proc.order_book().top_n_asks(2), vec![(101, 40)]);
     }
 
-    impl TestMessageBuilder {
-        fn new() -> Self {
-            Self {
-                next_sequence: 1,
-                next_event_time: ts("2024-01-01T00:00:00Z"),
-                time_increment: Duration::milliseconds(1),
-            }
-        }
+    #[test]
+    fn test_process_messages_collect_continues_past_failure() {
+        let mut proc = MboProcessor::new().with_error_policy(ErrorPolicy::Collect);
+        let mut seq = TestMessageBuilder::new();
+        let messages = batch_with_bad_seventh(&mut seq);
+
+        let summary = proc.process_messages(messages);
+
+        assert_eq!(summary.processed, 9);
+        assert_eq!(summary.failures.len(), 1);
+        assert_eq!(summary.first_failure().unwrap().index, 6);
+        assert_eq!(summary.action_counts.add, 4);
+        assert_eq!(summary.action_counts.modify, 1);
+        assert_eq!(summary.action_counts.cancel, 2);
+        assert_eq!(summary.action_counts.trade, 1);
+        assert_eq!(summary.action_counts.fill, 1);
+        assert_eq!(summary.action_counts.total(), summary.processed);
+
+        assert_eq!(proc.order_book().top_n_bids(2), vec![(100, 45)]);
+        assert_eq!(proc.order_book().top_n_asks(2), vec![(102, 20)]);
+    }
 
-        fn msg(
-            &mut self,
-            action: Action,
-            order_id: u64,
-            side: Side,
-            price: i64,
-            size: u32,
-            is_last: bool,
-        ) -> MarketByOrderMessage {
-            let sequence = self.next_sequence;
-            self.next_sequence += 1;
+    #[test]
+    fn test_process_messages_accepts_lazy_owned_iterator() {
+        let mut proc = MboProcessor::new();
+        let mut seq = TestMessageBuilder::new();
 
-            let event_time = self.next_event_time;
-            self.next_event_time += self.time_increment;
-            let recv_time = event_time + Duration::microseconds(50);
+        let summary = proc.process_messages(
+            (1..=5).map(|id| seq.msg(Action::Add, id, Side::Bid, 100 - id as i64, 10, true)),
+        );
 
-            MarketByOrderMessage {
-                action,
-                side,
-                price,
-                order_id,
-                size,
-                is_last,
-                sequence,
-                event_time,
-                recv_time,
-                ts_in_delta: Duration::microseconds(-10),
-            }
-        }
+        assert!(summary.is_success());
+        assert_eq!(summary.processed, 5);
+        assert_eq!(proc.order_book().best_bid(), Some((99, 10)));
     }
 
+    // --- Statistics tests ---
+
     #[test]
-    fn test_fill_does_not_modify_book() {
+    fn test_stats_counts_known_sequence() {
         let mut proc = MboProcessor::new();
         let mut seq = TestMessageBuilder::new();
 
-        // Add an order
-        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, true))
+        let messages = [
+            seq.msg(Action::Add, 1, Side::Bid, 100, 50, true),
+            seq.msg(Action::Add, 2, Side::Ask, 101, 40, true),
+            // Duplicate add: replaces order 1
+            seq.msg(Action::Add, 1, Side::Bid, 99, 20, true),
+            seq.msg(Action::Trade, 0, Side::Ask, 101, 15, false),
+            seq.msg(Action::Fill, 2, Side::Ask, 101, 15, false),
+            seq.msg(Action::Modify, 2, Side::Ask, 101, 25, true),
+            // Cancel for an order that never existed
+            seq.msg(Action::Cancel, 77, Side::Bid, 0, 0, true),
+            seq.msg(Action::Trade, 0, Side::Bid, 99, 5, true),
+            seq.msg(Action::Cancel, 1, Side::Bid, 0, 0, true),
+            seq.clear(),
+        ];
+        messages
+            .iter()
+            .try_for_each(|m| proc.process_message(m))
             .unwrap();
-        assert_eq!(proc.order_book().best_bid(), Some((100, 50)));
+        // Modify for an unknown order fails but is still counted
+        assert!(
+            proc.process_message(&seq.msg(Action::Modify, 88, Side::Bid, 98, 10, true))
+                .is_err()
+        );
+
+        let stats = proc.stats();
+        assert_eq!(stats.messages, 11);
+        assert_eq!(stats.action_counts.add, 3);
+        assert_eq!(stats.action_counts.cancel, 2);
+        assert_eq!(stats.action_counts.modify, 2);
+        assert_eq!(stats.action_counts.fill, 1);
+        assert_eq!(stats.action_counts.trade, 2);
+        assert_eq!(stats.action_counts.clear, 1);
+        assert_eq!(stats.duplicate_adds, 1);
+        assert_eq!(stats.unknown_cancels, 1);
+        assert_eq!(stats.unknown_modifies, 1);
+        assert_eq!(stats.warnings(), 3);
+        assert_eq!(stats.traded_volume, 20);
+    }
 
-        // Fill should NOT change the order
-        proc.process_message(&seq.msg(Action::Fill, 1, Side::Bid, 100, 20, false))
-            .unwrap();
-        assert_eq!(proc.order_book().best_bid(), Some((100, 50)));
+    // --- Multi-instrument tests ---
+
+    fn for_instrument(instrument_id: u32, msg: MarketByOrderMessage) -> MarketByOrderMessage {
+        MarketByOrderMessage {
+            instrument_id,
+            ..msg
+        }
     }
 
     #[test]
-    fn test_trade_does_not_modify_book() {
+    fn test_instruments_have_independent_books() {
         let mut proc = MboProcessor::new();
         let mut seq = TestMessageBuilder::new();
 
-        proc.process_message(&seq.msg(Action::Add, 1, Side::Ask, 200, 30, true))
-            .unwrap();
-        assert_eq!(proc.order_book().best_ask(), Some((200, 30)));
-
-        // Trade should NOT change the order
-        proc.process_message(&seq.msg(Action::Trade, 1, Side::Ask, 200, 10, true))
-            .unwrap();
-        assert_eq!(proc.order_book().best_ask(), Some((200, 30)));
+        // Same order id on two instruments, interleaved
+        let messages = [
+            for_instrument(10, seq.msg(Action::Add, 1, Side::Bid, 100, 50, true)),
+            for_instrument(20, seq.msg(Action::Add, 1, Side::Bid, 100, 50, true)),
+            for_instrument(20, seq.msg(Action::Add, 2, Side::Ask, 105, 10, true)),
+            for_instrument(10, seq.msg(Action::Cancel, 1, Side::Bid, 0, 0, true)),
+        ];
+        let summary = proc.process_messages(messages);
+        assert!(summary.is_success());
+
+        assert_eq!(proc.instruments(), vec![10, 20]);
+        assert_eq!(proc.book(10).unwrap().best_bid(), None);
+        assert_eq!(proc.book(20).unwrap().best_bid(), Some((100, 50)));
+        assert_eq!(proc.book(20).unwrap().best_ask(), Some((105, 10)));
+        assert!(proc.book(30).is_none());
+
+        // No warnings: the cancel found its order on instrument 10
+        assert_eq!(proc.stats().unknown_cancels, 0);
+        assert_eq!(proc.stats().duplicate_adds, 0);
     }
 
     #[test]
-    fn test_event_complete_tracks_last_flag() {
+    fn test_clear_only_resets_own_instrument() {
         let mut proc = MboProcessor::new();
         let mut seq = TestMessageBuilder::new();
 
-        // Initially event_complete is true
-        assert!(proc.is_event_complete());
-
-        // Non-LAST message sets event_complete to false
-        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, false))
-            .unwrap();
-        assert!(!proc.is_event_complete());
+        let messages = [
+            for_instrument(10, seq.msg(Action::Add, 1, Side::Bid, 100, 50, true)),
+            for_instrument(20, seq.msg(Action::Add, 1, Side::Ask, 200, 30, true)),
+            for_instrument(10, seq.clear()),
+        ];
+        assert!(proc.process_messages(messages).is_success());
+
+        assert!(proc.mbp(10).unwrap().bids.is_empty());
+        let mbp = proc.mbp(20).unwrap();
+        assert_eq!(mbp.asks.get(&200).unwrap().total_quantity, 30);
+
+        // A one-sided Clear is scoped to its instrument too
+        let clear = seq.msg(Action::Clear, 0, Side::Ask, 0, 0, true);
+        proc.process_message(&for_instrument(10, clear)).unwrap();
+        assert_eq!(proc.book(20).unwrap().best_ask(), Some((200, 30)));
+    }
 
-        // LAST message sets event_complete to true
-        proc.process_message(&seq.msg(Action::Add, 2, Side::Bid, 100, 30, true))
-            .unwrap();
-        assert!(proc.is_event_complete());
+    fn for_publisher(publisher_id: u16, msg: MarketByOrderMessage) -> MarketByOrderMessage {
+        MarketByOrderMessage {
+            publisher_id,
+            ..msg
+        }
     }
 
     #[test]
-    fn test_add_cancel_modify_still_work() {
-        let mut proc = MboProcessor::new();
+    fn test_publisher_routing_keeps_venues_apart() {
         let mut seq = TestMessageBuilder::new();
+        // Two venues quote instrument 10 with overlapping order ids
+        let messages = [
+            for_publisher(1, seq.msg(Action::Add, 1, Side::Bid, 100, 50, true)),
+            for_publisher(2, seq.msg(Action::Add, 1, Side::Bid, 101, 20, true)),
+            for_publisher(2, seq.msg(Action::Add, 2, Side::Ask, 105, 10, true)),
+            for_publisher(1, seq.msg(Action::Cancel, 1, Side::Bid, 0, 0, true)),
+        ]
+        .map(|msg| for_instrument(10, msg));
+
+        let mut routed = MboProcessor::new().with_publisher_routing(true);
+        assert!(routed.process_messages(messages).is_success());
+        assert_eq!(routed.instruments(), vec![10]);
+        assert_eq!(
+            routed.book_keys(),
+            vec![BookKey::new(1, 10), BookKey::new(2, 10)]
+        );
+        assert_eq!(
+            routed.book_for(BookKey::new(1, 10)).unwrap().best_bid(),
+            None
+        );
+        let venue = routed.book_for(BookKey::new(2, 10)).unwrap();
+        assert_eq!(venue.best_bid(), Some((101, 20)));
+        assert_eq!(venue.best_ask(), Some((105, 10)));
+        // The last message was publisher 1's Cancel
+        assert_eq!(routed.best_ask(), None);
+        assert_eq!(routed.last_publisher_id(), 1);
+        assert_eq!(routed.stats().duplicate_adds, 0);
+
+        // Without routing by publisher the venues merge into one book
+        let mut merged = MboProcessor::new();
+        assert!(merged.process_messages(messages).is_success());
+        assert_eq!(merged.book_keys(), vec![BookKey::from(10)]);
+        assert_eq!(merged.stats().duplicate_adds, 1);
+        assert_ne!(merged.state_hash(), routed.state_hash());
+    }
 
-        // Add
-        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, true))
-            .unwrap();
-        assert_eq!(proc.order_book().best_bid(), Some((100, 50)));
+    #[test]
+    fn test_publisher_routing_keys_observers_by_book() {
+        use std::convert::Infallible;
 
-        // Modify (changes price and size)
-        proc.process_message(&seq.msg(Action::Modify, 1, Side::Bid, 110, 60, true))
-            .unwrap();
-        assert_eq!(proc.order_book().best_bid(), Some((110, 60)));
+        use crate::orderbook::{Bbo, L2Action, L2Publisher, L2Update};
 
-        // Cancel
-        proc.process_message(&seq.msg(Action::Cancel, 1, Side::Bid, 0, 0, true))
-            .unwrap();
-        assert_eq!(proc.order_book().best_bid(), None);
+        let mut seq = TestMessageBuilder::new();
+        let messages = [
+            for_publisher(1, seq.msg(Action::Add, 1, Side::Bid, 100, 50, true)),
+            for_publisher(2, seq.msg(Action::Add, 1, Side::Bid, 101, 20, true)),
+            for_publisher(2, seq.msg(Action::Add, 2, Side::Ask, 105, 10, true)),
+            for_publisher(1, seq.msg(Action::Add, 3, Side::Bid, 100, 5, true)),
+            for_publisher(2, seq.msg(Action::Cancel, 1, Side::Bid, 0, 0, true)),
+        ]
+        .map(|msg| for_instrument(10, msg));
+
+        let mut routed = MboProcessor::with_observer((L2Publisher::new(), IncrementalMbp::new()))
+            .with_publisher_routing(true);
+        let bbos: Vec<(Bbo, bool)> = routed
+            .drive(messages.map(Ok::<_, Infallible>))
+            .map(|event| event.unwrap())
+            .map(|event| (event.bbo, event.bbo_changed))
+            .collect();
+        // Each message changes the top of its own venue's book
+        let bbo = |bid, ask| Bbo { bid, ask };
+        assert_eq!(
+            bbos,
+            vec![
+                (bbo(Some((100, 50)), None), true),
+                (bbo(Some((101, 20)), None), true),
+                (bbo(Some((101, 20)), Some((105, 10))), true),
+                (bbo(Some((100, 55)), None), true),
+                (bbo(None, Some((105, 10))), true),
+            ]
+        );
+
+        let (publisher, incremental) = routed.observer();
+        let set = |side, price, new_qty, new_count| L2Update {
+            side,
+            price,
+            new_qty,
+            new_count,
+            action: L2Action::Set,
+        };
+        let batches: Vec<(u16, u32, Vec<L2Update>)> = publisher
+            .batches()
+            .iter()
+            .map(|batch| (batch.publisher_id, batch.sequence, batch.updates.clone()))
+            .collect();
+        assert_eq!(
+            batches,
+            vec![
+                (1, 1, vec![set(Side::Bid, 100, 50, 1)]),
+                (2, 2, vec![set(Side::Bid, 101, 20, 1)]),
+                (2, 3, vec![set(Side::Ask, 105, 10, 1)]),
+                (1, 4, vec![set(Side::Bid, 100, 55, 2)]),
+                (
+                    2,
+                    5,
+                    vec![L2Update {
+                        action: L2Action::Delete,
+                        ..set(Side::Bid, 101, 0, 0)
+                    }]
+                ),
+            ]
+        );
+        for key in routed.book_keys() {
+            let expected = MarketByPrice::from(routed.book_for(key).unwrap());
+            assert_eq!(incremental.mbp_for(key), Some(&expected));
+        }
+        assert_eq!(incremental.mbp(10), None);
     }
 
     #[test]
-    fn test_clear_resets_book() {
-        let mut proc = MboProcessor::new();
+    fn test_last_message_ids() {
+        let mut proc = MboProcessor::new().with_journal_depth(1);
+        assert_eq!(
+            (
+                proc.last_flags(),
+                proc.last_publisher_id(),
+                proc.last_channel_id()
+            ),
+            (0, 0, 0)
+        );
         let mut seq = TestMessageBuilder::new();
+        let first = MarketByOrderMessage {
+            channel_id: 3,
+            ..for_publisher(7, seq.msg(Action::Add, 1, Side::Bid, 100, 50, true))
+        };
+        let second = MarketByOrderMessage {
+            flags: flags::SNAPSHOT,
+            channel_id: 4,
+            ..for_publisher(8, seq.msg(Action::Add, 2, Side::Ask, 105, 10, false))
+        };
+        proc.process_message(&first).unwrap();
+        proc.process_message(&second).unwrap();
+        assert_eq!(
+            (
+                proc.last_flags(),
+                proc.last_publisher_id(),
+                proc.last_channel_id()
+            ),
+            (flags::SNAPSHOT, 8, 4)
+        );
+        proc.rollback(1).unwrap();
+        assert_eq!(
+            (
+                proc.last_flags(),
+                proc.last_publisher_id(),
+                proc.last_channel_id()
+            ),
+            (flags::LAST, 7, 3)
+        );
+    }
 
-        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, true))
-            .unwrap();
-        proc.process_message(&seq.msg(Action::Add, 2, Side::Ask, 200, 30, true))
-            .unwrap();
-        assert!(proc.order_book().best_bid().is_some());
-        assert!(proc.order_book().best_ask().is_some());
+    #[test]
+    fn test_stats_roll_up_across_instruments() {
+        let mut proc = MboProcessor::new();
+        let mut seq = TestMessageBuilder::new();
 
-        proc.process_message(&seq.msg(Action::Clear, 0, Side::Bid, 0, 0, true))
-            .unwrap();
-        assert_eq!(proc.order_book().best_bid(), None);
-        assert_eq!(proc.order_book().best_ask(), None);
+        let messages = [
+            for_instrument(10, seq.msg(Action::Add, 1, Side::Bid, 100, 50, true)),
+            for_instrument(20, seq.msg(Action::Add, 1, Side::Bid, 100, 50, true)),
+            for_instrument(20, seq.msg(Action::Trade, 0, Side::Ask, 100, 7, true)),
+            for_instrument(10, seq.msg(Action::Cancel, 9, Side::Bid, 0, 0, true)),
+        ];
+        assert!(proc.process_messages(messages).is_success());
+
+        let a = proc.instrument_stats(10).unwrap();
+        let b = proc.instrument_stats(20).unwrap();
+        assert_eq!(a.messages, 2);
+        assert_eq!(a.unknown_cancels, 1);
+        assert_eq!(b.messages, 2);
+        assert_eq!(b.traded_volume, 7);
+        assert_eq!(proc.stats(), a + b);
+        assert_eq!(proc.stats().action_counts.add, 2);
     }
 
+    // --- Book accessor tests ---
+
     #[test]
-    fn test_processor_tracks_timestamps() {
+    fn test_bbo_and_mbp_through_processor() {
         let mut proc = MboProcessor::new();
         let mut seq = TestMessageBuilder::new();
 
-        // Initially timestamps are at UNIX epoch
-        assert_eq!(proc.last_event_time(), OffsetDateTime::UNIX_EPOCH);
-        assert_eq!(proc.last_recv_time(), OffsetDateTime::UNIX_EPOCH);
+        assert_eq!(proc.best_bid(), None);
+        assert_eq!(proc.best_ask(), None);
 
-        // Process first message
-        let msg1 = seq.msg(Action::Add, 1, Side::Bid, 100, 50, true);
-        proc.process_message(&msg1).unwrap();
+        let messages = [
+            seq.msg(Action::Add, 1, Side::Bid, 100, 50, true),
+            seq.msg(Action::Add, 2, Side::Bid, 101, 20, true),
+            seq.msg(Action::Add, 3, Side::Ask, 103, 40, true),
+            seq.msg(Action::Add, 4, Side::Ask, 104, 10, true),
+        ];
+        assert!(proc.process_messages(messages).is_success());
 
-        assert_eq!(proc.last_event_time(), msg1.event_time);
-        assert_eq!(proc.last_recv_time(), msg1.recv_time);
-        assert_eq!(proc.last_ts_in_delta(), msg1.ts_in_delta);
+        assert_eq!(proc.best_bid(), Some((101, 20)));
+        assert_eq!(proc.best_ask(), Some((103, 40)));
 
-        // Process second message - timestamps should update
-        let msg2 = seq.msg(Action::Add, 2, Side::Bid, 99, 30, true);
-        proc.process_message(&msg2).unwrap();
+        let mbp = proc.market_by_price(Some(1));
+        assert_eq!(mbp.bids.len(), 1);
+        assert_eq!(mbp.asks.len(), 1);
+        assert_eq!(mbp.sequence, Some(4));
+        assert_eq!(proc.market_by_price(None).asks.len(), 2);
 
-        assert_eq!(proc.last_event_time(), msg2.event_time);
-        assert!(proc.last_event_time() > msg1.event_time);
+        let book = proc.into_inner();
+        assert_eq!(book.top_n_bids(2), vec![(101, 20), (100, 50)]);
     }
 
-    // --- Observer tests ---
-
-    /// Test observer that counts how many times each callback is invoked.
-    #[derive(Debug, Default)]
-    struct CountingObserver {
-        adds: u32,
-        cancels: u32,
-        modifies: u32,
-        trades: u32,
-        clears: u32,
-        event_completes: u32,
-    }
+    // --- Snapshot tests ---
 
-    impl MboObserver for CountingObserver {
-        fn on_order_added(&mut self, _event: &OrderAddedEvent) {
-            self.adds += 1;
-        }
-        fn on_order_cancelled(&mut self, _event: &OrderCancelledEvent) {
-            self.cancels += 1;
-        }
-        fn on_order_modified(&mut self, _event: &OrderModifiedEvent) {
-            self.modifies += 1;
-        }
-        fn on_trade(&mut self, _event: &TradeEvent) {
-            self.trades += 1;
-        }
-        fn on_clear(&mut self) {
-            self.clears += 1;
-        }
-        fn on_event_complete(
-            &mut self,
-            _book: &OrderBook,
-            _event_time: OffsetDateTime,
-            _recv_time: OffsetDateTime,
-        ) {
-            self.event_completes += 1;
+    fn snapshot(msg: MarketByOrderMessage) -> MarketByOrderMessage {
+        MarketByOrderMessage {
+            flags: msg.flags | flags::SNAPSHOT,
+            ..msg
         }
     }
 
     #[test]
-    fn test_observer_called_for_each_action() {
-        let mut proc = MboProcessor::with_observer(CountingObserver::default());
+    fn test_snapshot_replaces_existing_book() {
+        let mut proc = MboProcessor::new();
         let mut seq = TestMessageBuilder::new();
 
-        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, true))
-            .unwrap();
-        proc.process_message(&seq.msg(Action::Modify, 1, Side::Bid, 110, 60, true))
+        // Stale state from before the snapshot
+        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 90, 10, true))
             .unwrap();
-        proc.process_message(&seq.msg(Action::Cancel, 1, Side::Bid, 0, 0, true))
-            .unwrap();
-        proc.process_message(&seq.msg(Action::Trade, 99, Side::Bid, 100, 10, true))
+        assert!(!proc.is_in_snapshot());
+
+        // Snapshot batch: only the final record carries LAST
+        proc.process_message(&snapshot(seq.msg(
+            Action::Add,
+            2,
+            Side::Bid,
+            100,
+            50,
+            false,
+        )))
+        .unwrap();
+        assert!(proc.is_in_snapshot());
+        assert!(!proc.is_event_complete());
+        assert_eq!(proc.best_bid(), Some((100, 50)));
+        assert_eq!(proc.best_ask(), None);
+
+        proc.process_message(&snapshot(seq.msg(Action::Add, 3, Side::Ask, 101, 40, true)))
             .unwrap();
-        proc.process_message(&seq.msg(Action::Fill, 99, Side::Bid, 100, 10, true))
+        assert!(proc.is_event_complete());
+        assert_eq!(proc.order_book().top_n_bids(5), vec![(100, 50)]);
+        assert_eq!(proc.best_ask(), Some((101, 40)));
+
+        // Incremental updates after the snapshot do not clear the book
+        proc.process_message(&seq.msg(Action::Add, 4, Side::Bid, 99, 20, false))
             .unwrap();
-        proc.process_message(&seq.msg(Action::Clear, 0, Side::Bid, 0, 0, true))
+        assert!(!proc.is_in_snapshot());
+        assert!(!proc.is_event_complete());
+        proc.process_message(&seq.msg(Action::Cancel, 3, Side::Ask, 0, 0, true))
             .unwrap();
-
-        let obs = proc.observer();
-        assert_eq!(obs.adds, 1);
-        assert_eq!(obs.modifies, 1);
-        assert_eq!(obs.cancels, 1);
-        assert_eq!(obs.trades, 2); // Trade + Fill both fire on_trade
-        assert_eq!(obs.clears, 1);
+        assert!(proc.is_event_complete());
+        assert_eq!(proc.order_book().top_n_bids(5), vec![(100, 50), (99, 20)]);
+        assert_eq!(proc.best_ask(), None);
     }
 
     #[test]
-    fn test_event_complete_fires_only_on_is_last() {
-        let mut proc = MboProcessor::with_observer(CountingObserver::default());
+    fn test_snapshot_modify_fails_without_clearing() {
+        let mut proc = MboProcessor::with_observer(IncrementalMbp::new());
         let mut seq = TestMessageBuilder::new();
-
-        // Non-LAST message should not fire event_complete
-        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, false))
-            .unwrap();
-        assert_eq!(proc.observer().event_completes, 0);
-
-        // LAST message should fire event_complete
-        proc.process_message(&seq.msg(Action::Add, 2, Side::Bid, 99, 30, true))
-            .unwrap();
-        assert_eq!(proc.observer().event_completes, 1);
-
-        // Another non-LAST
-        proc.process_message(&seq.msg(Action::Trade, 99, Side::Bid, 100, 10, false))
+        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 90, 10, true))
             .unwrap();
-        assert_eq!(proc.observer().event_completes, 1);
 
-        // LAST again
-        proc.process_message(&seq.msg(Action::Fill, 1, Side::Bid, 100, 10, true))
-            .unwrap();
-        assert_eq!(proc.observer().event_completes, 2);
+        // The order is gone once the snapshot clears the book, so the Modify fails first
+        let result = proc.process_message(&snapshot(seq.msg(
+            Action::Modify,
+            1,
+            Side::Bid,
+            95,
+            10,
+            true,
+        )));
+        assert!(matches!(
+            result,
+            Err(MboProcessError::OrderBookError(
+                OrderBookError::OrderNotFound(1)
+            ))
+        ));
+        assert!(!proc.is_in_snapshot());
+        assert_eq!(proc.best_bid(), Some((90, 10)));
+        assert_eq!(
+            proc.observer().mbp(1).unwrap().bids,
+            MarketByPrice::from(proc.order_book()).bids
+        );
     }
 
     #[test]
-    fn test_event_complete_receives_correct_book_and_timestamps() {
-        /// Observer that captures the book state at event completion.
-        #[derive(Debug)]
-        struct SnapshotObserver {
-            best_bid_at_complete: Option<(i64, u64)>,
-            captured_event_time: OffsetDateTime,
-            captured_recv_time: OffsetDateTime,
-        }
+    fn test_snapshot_flag_from_dbn() {
+        let msg = snapshot(TestMessageBuilder::new().msg(Action::Add, 1, Side::Bid, 1, 1, true));
+        assert!(msg.is_snapshot());
+        assert!(
+            !TestMessageBuilder::new()
+                .msg(Action::Add, 1, Side::Bid, 1, 1, true)
+                .is_snapshot()
+        );
+    }
 
-        impl Default for SnapshotObserver {
-            fn default() -> Self {
-                Self {
-                    best_bid_at_complete: None,
-                    captured_event_time: OffsetDateTime::UNIX_EPOCH,
-                    captured_recv_time: OffsetDateTime::UNIX_EPOCH,
-                }
-            }
-        }
+    // --- Point-in-time replay tests ---
 
-        impl MboObserver for SnapshotObserver {
-            fn on_event_complete(
-                &mut self,
-                book: &OrderBook,
-                event_time: OffsetDateTime,
-                recv_time: OffsetDateTime,
-            ) {
-                self.best_bid_at_complete = book.best_bid();
-                self.captured_event_time = event_time;
-                self.captured_recv_time = recv_time;
-            }
-        }
+    fn nanos(time: OffsetDateTime) -> u64 {
+        time.unix_timestamp_nanos() as u64
+    }
 
-        let mut proc = MboProcessor::with_observer(SnapshotObserver::default());
+    #[test]
+    fn test_process_until_stops_after_target_and_resumes() {
+        let mut proc = MboProcessor::new();
         let mut seq = TestMessageBuilder::new();
+        let first = seq.msg(Action::Add, 1, Side::Bid, 100, 50, true);
+        // Same timestamp as the first message
+        let same_ts = MarketByOrderMessage {
+            event_time: first.event_time,
+            ..seq.msg(Action::Add, 2, Side::Bid, 99, 30, true)
+        };
+        let later = seq.msg(Action::Add, 3, Side::Ask, 101, 40, true);
+        let latest = seq.msg(Action::Cancel, 1, Side::Bid, 0, 0, true);
+
+        let mut messages = [first, same_ts, later, latest].into_iter().peekable();
+        let summary = proc.process_until(&mut messages, nanos(first.event_time));
+
+        assert_eq!(summary.processed, 2);
+        assert_eq!(proc.order_book().top_n_bids(5), vec![(100, 50), (99, 30)]);
+        assert_eq!(proc.best_ask(), None);
+        assert_eq!(messages.peek(), Some(&later));
+
+        let summary = proc.process_until(&mut messages, nanos(later.event_time));
+        assert_eq!(summary.processed, 1);
+        assert_eq!(proc.best_ask(), Some((101, 40)));
+
+        // The remainder can be processed normally
+        assert_eq!(proc.process_messages(messages).processed, 1);
+        assert_eq!(proc.best_bid(), Some((99, 30)));
+    }
 
-        let msg = seq.msg(Action::Add, 1, Side::Bid, 100, 50, true);
-        let expected_event_time = msg.event_time;
-        let expected_recv_time = msg.recv_time;
-        proc.process_message(&msg).unwrap();
+    #[test]
+    fn test_process_until_before_first_message_is_noop() {
+        let mut proc = MboProcessor::new();
+        let mut seq = TestMessageBuilder::new();
+        let first = seq.msg(Action::Add, 1, Side::Bid, 100, 50, true);
 
-        let obs = proc.observer();
-        assert_eq!(obs.best_bid_at_complete, Some((100, 50)));
-        assert_eq!(obs.captured_event_time, expected_event_time);
-        assert_eq!(obs.captured_recv_time, expected_recv_time);
+        let mut messages = [first].into_iter().peekable();
+        let summary = proc.process_until(&mut messages, nanos(first.event_time) - 1);
+        assert_eq!(summary.processed, 0);
+        assert_eq!(proc.best_bid(), None);
+        assert_eq!(messages.peek(), Some(&first));
+
+        let mut empty = iter::empty::<MarketByOrderMessage>().peekable();
+        assert_eq!(proc.process_until(&mut empty, u64::MAX).processed, 0);
+        assert!(proc.instruments().is_empty());
+    }
+
+    // --- Rollback tests ---
+
+    fn journaled() -> MboProcessor {
+        MboProcessor::new().with_journal_depth(8)
     }
 
     #[test]
-    fn test_tuple_observer_both_called() {
-        let obs_a = CountingObserver::default();
-        let obs_b = CountingObserver::default();
-        let mut proc = MboProcessor::with_observer((obs_a, obs_b));
+    fn test_rollback_three_messages_matches_earlier_checkpoint() {
+        let path = env::temp_dir().join(format!("rainybook-{}-rollback.ckpt", process::id()));
+        let mut proc = journaled();
+        let mut seq = TestMessageBuilder::new();
+
+        [
+            seq.msg(Action::Add, 1, Side::Bid, 100, 50, true),
+            seq.msg(Action::Add, 2, Side::Bid, 100, 30, true),
+            seq.msg(Action::Add, 3, Side::Ask, 101, 40, true),
+        ]
+        .iter()
+        .try_for_each(|m| proc.process_message(m))
+        .unwrap();
+        proc.save_checkpoint(&path).unwrap();
+
+        // Cancel the queue head, shrink order 2, then clear the book
+        [
+            seq.msg(Action::Cancel, 1, Side::Bid, 0, 0, true),
+            seq.msg(Action::Modify, 2, Side::Bid, 100, 10, true),
+            seq.clear(),
+        ]
+        .iter()
+        .try_for_each(|m| proc.process_message(m))
+        .unwrap();
+        assert_eq!(proc.best_bid(), None);
+
+        proc.rollback(3).unwrap();
+
+        let checkpoint = MboProcessor::<()>::load_checkpoint(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(proc.order_book(), checkpoint.order_book());
+        assert_eq!(proc.stats(), checkpoint.stats());
+        assert_eq!(proc.last_sequence_number(), 3);
+        assert_eq!(proc.last_timestamps(), checkpoint.last_timestamps());
+        // Order 1 is back at the head of the queue
+        assert_eq!(proc.order_book().queue_position(1), Some(0));
+        assert_eq!(proc.order_book().queue_position(2), Some(1));
+    }
+
+    #[test]
+    fn test_rollback_duplicate_add_and_failed_modify() {
+        let mut proc = journaled();
         let mut seq = TestMessageBuilder::new();
 
         proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, true))
             .unwrap();
-        proc.process_message(&seq.msg(Action::Trade, 99, Side::Bid, 100, 10, true))
+        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 99, 20, true))
+            .unwrap();
+        assert!(
+            proc.process_message(&seq.msg(Action::Modify, 9, Side::Bid, 98, 5, true))
+                .is_err()
+        );
+        proc.process_message(&seq.msg(Action::Add, 2, Side::Ask, 105, 5, true))
             .unwrap();
 
-        let (a, b) = proc.observer();
-        assert_eq!(a.adds, 1);
-        assert_eq!(a.trades, 1);
-        assert_eq!(a.event_completes, 2);
-        assert_eq!(b.adds, 1);
-        assert_eq!(b.trades, 1);
-        assert_eq!(b.event_completes, 2);
+        proc.rollback(3).unwrap();
+        assert_eq!(proc.order_book().top_n_bids(5), vec![(100, 50)]);
+        assert_eq!(proc.best_ask(), None);
+        assert_eq!(proc.stats().messages, 1);
+        assert_eq!(proc.stats().duplicate_adds, 0);
+        assert_eq!(proc.stats().unknown_modifies, 0);
+
+        proc.rollback(1).unwrap();
+        assert!(proc.instruments().is_empty());
+        assert_eq!(proc.last_sequence_number(), 0);
     }
 
     #[test]
-    fn test_into_observer_extracts_state() {
-        let mut proc = MboProcessor::with_observer(CountingObserver::default());
+    fn test_rollback_across_snapshot() {
+        let mut proc = journaled();
         let mut seq = TestMessageBuilder::new();
 
-        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, true))
+        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 90, 10, true))
             .unwrap();
-        proc.process_message(&seq.msg(Action::Add, 2, Side::Bid, 99, 30, true))
+        proc.process_message(&snapshot(seq.msg(Action::Add, 2, Side::Bid, 100, 50, true)))
             .unwrap();
+        assert_eq!(proc.order_book().top_n_bids(5), vec![(100, 50)]);
 
-        let obs = proc.into_observer();
-        assert_eq!(obs.adds, 2);
-        assert_eq!(obs.event_completes, 2);
+        proc.rollback(1).unwrap();
+        assert_eq!(proc.order_book().top_n_bids(5), vec![(90, 10)]);
+        assert!(!proc.is_in_snapshot());
     }
 
     #[test]
-    fn test_order_added_event_contains_level_info() {
-        #[derive(Debug, Default)]
-        struct AddObserver {
-            last_event: Option<OrderAddedEvent>,
-        }
-        impl MboObserver for AddObserver {
-            fn on_order_added(&mut self, event: &OrderAddedEvent) {
-                self.last_event = Some(*event);
-            }
-        }
-
-        let mut proc = MboProcessor::with_observer(AddObserver::default());
+    fn test_rollback_bounded_by_journal_depth() {
+        let mut proc = MboProcessor::new().with_journal_depth(2);
         let mut seq = TestMessageBuilder::new();
 
-        // First order at price 100 — new level
-        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, true))
-            .unwrap();
-        let evt = proc.observer().last_event.unwrap();
-        assert_eq!(evt.order.price, 100);
-        assert_eq!(evt.order.size, 50);
-        assert_eq!(evt.level_qty, 50);
-        assert_eq!(evt.level_order_count, 1);
-        assert!(evt.new_level);
+        (1..=3).for_each(|id| {
+            proc.process_message(&seq.msg(Action::Add, id, Side::Bid, 100, 10, true))
+                .unwrap()
+        });
+        assert_eq!(proc.journal_len(), 2);
+        assert_eq!(
+            proc.rollback(3),
+            Err(RollbackError::InsufficientHistory {
+                requested: 3,
+                available: 2
+            })
+        );
+        // Nothing was rolled back on error
+        assert_eq!(proc.best_bid(), Some((100, 30)));
+
+        proc.rollback(2).unwrap();
+        assert_eq!(proc.best_bid(), Some((100, 10)));
+    }
 
-        // Second order at same price — existing level
-        proc.process_message(&seq.msg(Action::Add, 2, Side::Bid, 100, 30, true))
+    #[test]
+    fn test_journal_disabled_by_default() {
+        let mut proc = MboProcessor::new();
+        let mut seq = TestMessageBuilder::new();
+        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 10, true))
             .unwrap();
-        let evt = proc.observer().last_event.unwrap();
-        assert_eq!(evt.level_qty, 80); // 50 + 30
-        assert_eq!(evt.level_order_count, 2);
-        assert!(!evt.new_level);
+        assert_eq!(proc.journal_depth(), 0);
+        assert_eq!(proc.journal_len(), 0);
+        assert!(proc.rollback(1).is_err());
     }
 
-    #[test]
-    fn test_order_cancelled_event_contains_level_info() {
-        #[derive(Debug, Default)]
-        struct CancelObserver {
-            last_event: Option<OrderCancelledEvent>,
-        }
-        impl MboObserver for CancelObserver {
-            fn on_order_cancelled(&mut self, event: &OrderCancelledEvent) {
-                self.last_event = Some(*event);
-            }
-        }
+    // --- Crossing policy tests ---
 
-        let mut proc = MboProcessor::with_observer(CancelObserver::default());
+    fn crossing_book(policy: CrossingPolicy) -> (MboProcessor<TradeCollector>, TestMessageBuilder) {
+        let mut proc = MboProcessor::with_observer(TradeCollector::new())
+            .with_crossing_policy(policy)
+            .with_journal_depth(4);
         let mut seq = TestMessageBuilder::new();
+        [
+            seq.msg(Action::Add, 1, Side::Ask, 101, 10, true),
+            seq.msg(Action::Add, 2, Side::Ask, 102, 10, true),
+            seq.msg(Action::Add, 3, Side::Ask, 103, 10, true),
+            seq.msg(Action::Add, 4, Side::Bid, 99, 10, true),
+        ]
+        .iter()
+        .try_for_each(|m| proc.process_message(m))
+        .unwrap();
+        (proc, seq)
+    }
 
-        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, true))
+    #[test]
+    fn test_match_sweeps_two_levels_and_rests_remainder() {
+        let (mut proc, mut seq) = crossing_book(CrossingPolicy::Match);
+
+        proc.process_message(&seq.msg(Action::Add, 10, Side::Bid, 102, 25, true))
             .unwrap();
-        proc.process_message(&seq.msg(Action::Add, 2, Side::Bid, 100, 30, true))
+
+        // Both ask levels at or below 102 are consumed; 5 rest at the limit price
+        assert_eq!(proc.order_book().top_n_asks(5), vec![(103, 10)]);
+        assert_eq!(proc.order_book().top_n_bids(5), vec![(102, 5), (99, 10)]);
+        assert_eq!(proc.order_book().get_order(10).unwrap().size, 5);
+
+        let trades = proc.observer().trades();
+        let aggressor: Vec<_> = trades
+            .iter()
+            .filter(|t| t.aggressor)
+            .map(|t| (t.price, t.size, t.side))
+            .collect();
+        assert_eq!(aggressor, vec![(101, 10, Side::Bid), (102, 10, Side::Bid)]);
+        assert!(
+            trades
+                .iter()
+                .filter(|t| !t.aggressor)
+                .all(|t| t.side == Side::Ask)
+        );
+        assert_eq!(trades.len(), 4);
+        assert_eq!(proc.stats().traded_volume, 20);
+
+        // The sweep rolls back as a single message
+        proc.rollback(1).unwrap();
+        assert_eq!(
+            proc.order_book().top_n_asks(5),
+            vec![(101, 10), (102, 10), (103, 10)]
+        );
+        assert_eq!(proc.best_bid(), Some((99, 10)));
+    }
+
+    #[test]
+    fn test_match_fully_filled_order_never_rests() {
+        let (mut proc, mut seq) = crossing_book(CrossingPolicy::Match);
+
+        proc.process_message(&seq.msg(Action::Add, 10, Side::Ask, 95, 4, true))
             .unwrap();
 
-        // Cancel one of two orders at this level
-        proc.process_message(&seq.msg(Action::Cancel, 1, Side::Bid, 0, 0, true))
+        assert_eq!(proc.best_bid(), Some((99, 6)));
+        assert!(proc.order_book().get_order(10).is_none());
+        assert_eq!(proc.best_ask(), Some((101, 10)));
+        assert_eq!(proc.observer().trades().len(), 2);
+
+        // Partial execution keeps the resting bid's queue position, and rolls back
+        proc.rollback(1).unwrap();
+        assert_eq!(proc.best_bid(), Some((99, 10)));
+    }
+
+    #[test]
+    fn test_rest_policy_leaves_crossed_book() {
+        let (mut proc, mut seq) = crossing_book(CrossingPolicy::default());
+
+        proc.process_message(&seq.msg(Action::Add, 10, Side::Bid, 102, 25, true))
             .unwrap();
-        let evt = proc.observer().last_event.unwrap();
-        assert_eq!(evt.order.order_id, 1);
-        assert_eq!(evt.order.size, 50);
-        assert_eq!(evt.remaining_level_qty, 30);
-        assert_eq!(evt.remaining_level_count, 1);
-        assert!(!evt.level_removed);
 
-        // Cancel the last order — level should be removed
-        proc.process_message(&seq.msg(Action::Cancel, 2, Side::Bid, 0, 0, true))
+        assert_eq!(proc.best_bid(), Some((102, 25)));
+        assert_eq!(proc.best_ask(), Some((101, 10)));
+        assert!(proc.observer().trades().is_empty());
+    }
+
+    #[test]
+    fn test_self_match_policies() {
+        let owned = |message, owner| MarketByOrderMessage {
+            owner: Some(owner),
+            ..message
+        };
+        let run = |policy| {
+            let mut proc = MboProcessor::new()
+                .with_crossing_policy(CrossingPolicy::Match)
+                .with_self_match_policy(policy)
+                .with_journal_depth(4);
+            let mut seq = TestMessageBuilder::new();
+            [
+                owned(seq.msg(Action::Add, 1, Side::Ask, 101, 10, true), 3),
+                owned(seq.msg(Action::Add, 2, Side::Ask, 102, 10, true), 7),
+                owned(seq.msg(Action::Add, 3, Side::Ask, 102, 10, true), 3),
+                owned(seq.msg(Action::Add, 10, Side::Bid, 102, 25, true), 7),
+            ]
+            .iter()
+            .try_for_each(|m| proc.process_message(m))
             .unwrap();
-        let evt = proc.observer().last_event.unwrap();
-        assert_eq!(evt.order.order_id, 2);
-        assert_eq!(evt.remaining_level_qty, 0);
-        assert_eq!(evt.remaining_level_count, 0);
-        assert!(evt.level_removed);
+            proc
+        };
+
+        let proc = run(SelfMatchPolicy::Allow);
+        assert_eq!(proc.order_book().top_n_asks(5), vec![(102, 5)]);
+        // Resting orders are credited, the owner's own included
+        let summary = proc.participant_summary(1).unwrap();
+        assert_eq!(
+            (summary[&3].executed_qty, summary[&7].executed_qty),
+            (15, 10)
+        );
+
+        let mut proc = run(SelfMatchPolicy::CancelResting);
+        assert!(proc.order_book().get_order(2).is_none());
+        assert_eq!(proc.order_book().top_n_asks(5), vec![]);
+        assert_eq!(proc.best_bid(), Some((102, 5)));
+        // The cancel rolls back with the sweep, back into its queue position
+        proc.rollback(1).unwrap();
+        assert_eq!(proc.order_book().top_n_asks(5), vec![(101, 10), (102, 20)]);
+        assert_eq!(proc.order_book().queue_position(2), Some(0));
+        assert_eq!(proc.best_bid(), None);
+
+        // Nothing of the incoming order rests
+        let proc = run(SelfMatchPolicy::CancelIncoming);
+        assert_eq!(proc.order_book().top_n_asks(5), vec![(102, 20)]);
+        assert_eq!(proc.best_bid(), None);
+        assert!(proc.order_book().get_order(10).is_none());
+    }
+
+    // --- Modify priority policy tests ---
+
+    fn queue_after_size_up(policy: ModifyPriorityPolicy) -> Vec<Option<usize>> {
+        let mut proc = MboProcessor::new().with_modify_priority_policy(policy);
+        let mut seq = TestMessageBuilder::new();
+        proc.process_messages([
+            seq.msg(Action::Add, 1, Side::Bid, 100, 10, true),
+            seq.msg(Action::Add, 2, Side::Bid, 100, 10, true),
+            seq.msg(Action::Add, 3, Side::Bid, 100, 10, true),
+            seq.msg(Action::Modify, 2, Side::Bid, 100, 15, true),
+        ]);
+        [1, 2, 3]
+            .iter()
+            .map(|&id| proc.order_book().queue_position(id))
+            .collect()
     }
 
     #[test]
-    fn test_order_modified_event_captures_old_state() {
-        #[derive(Debug, Default)]
-        struct ModifyObserver {
-            last_event: Option<OrderModifiedEvent>,
-        }
-        impl MboObserver for ModifyObserver {
-            fn on_order_modified(&mut self, event: &OrderModifiedEvent) {
-                self.last_event = Some(*event);
-            }
-        }
+    fn test_modify_priority_policy_applied_by_processor() {
+        assert_eq!(
+            queue_after_size_up(ModifyPriorityPolicy::ExchangeStyle),
+            vec![Some(0), Some(2), Some(1)]
+        );
+        assert_eq!(
+            queue_after_size_up(ModifyPriorityPolicy::AlwaysPreserve),
+            vec![Some(0), Some(1), Some(2)]
+        );
+    }
 
-        let mut proc = MboProcessor::with_observer(ModifyObserver::default());
+    #[test]
+    fn test_book_depth_limit_applied_by_processor() {
         let mut seq = TestMessageBuilder::new();
+        let mut proc = MboProcessor::new().with_book_depth_limit(2, DepthLimitPolicy::Reject);
+        for (order_id, price) in [(1, 100), (2, 99)] {
+            proc.process_message(&seq.msg(Action::Add, order_id, Side::Bid, price, 10, true))
+                .unwrap();
+        }
+        let result = proc.process_message(&seq.msg(Action::Add, 3, Side::Bid, 98, 10, true));
+        assert!(matches!(
+            result,
+            Err(MboProcessError::OrderBookError(
+                OrderBookError::BeyondDepthLimit { order_id: 3, .. }
+            ))
+        ));
+
+        // A new best bid still trims the worst level
+        proc.process_message(&seq.msg(Action::Add, 4, Side::Bid, 101, 10, true))
+            .unwrap();
+        assert_eq!(proc.order_book().top_n_bids(5), vec![(101, 10), (100, 10)]);
+        assert_eq!(proc.order_book().max_levels_per_side(), Some(2));
+    }
 
-        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, true))
+    #[test]
+    fn test_order_ttl_purges_with_event_time() {
+        let mut seq = TestMessageBuilder::new();
+        let mut proc = MboProcessor::new()
+            .with_order_ttl(Duration::milliseconds(2))
+            .with_journal_depth(10);
+        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 10, true))
             .unwrap();
+        proc.process_message(&seq.msg(Action::Add, 2, Side::Bid, 99, 20, true))
+            .unwrap();
+        assert_eq!(proc.best_bid(), Some((100, 10)));
 
-        // Modify price and size
-        proc.process_message(&seq.msg(Action::Modify, 1, Side::Bid, 110, 60, true))
+        // Two milliseconds on, the first order expires exactly as the message arrives
+        proc.process_message(&seq.msg(Action::Add, 3, Side::Ask, 101, 5, true))
             .unwrap();
-        let evt = proc.observer().last_event.unwrap();
-        assert_eq!(evt.order.price, 110);
-        assert_eq!(evt.order.size, 60);
-        assert_eq!(evt.old_price, 100);
-        assert_eq!(evt.old_size, 50);
-        assert_eq!(evt.level_qty, 60);
-        assert_eq!(evt.level_order_count, 1);
+        assert_eq!(proc.order_book().top_n_bids(5), vec![(99, 20)]);
+        proc.process_message(&seq.msg(Action::Add, 4, Side::Ask, 102, 5, true))
+            .unwrap();
+        assert_eq!(proc.best_bid(), None);
+        assert_eq!(proc.order_book().level_count(Side::Ask), 2);
+
+        // Rolling back the message puts back the order its event time expired
+        proc.rollback(1).unwrap();
+        assert_eq!(proc.order_book().top_n_bids(5), vec![(99, 20)]);
+        assert_eq!(proc.order_book().top_n_asks(5), vec![(101, 5)]);
     }
 
     #[test]
-    fn test_trade_event_aggressor_flag() {
-        #[derive(Debug, Default)]
-        struct TradeObserver {
-            events: Vec<TradeEvent>,
-        }
-        impl MboObserver for TradeObserver {
-            fn on_trade(&mut self, event: &TradeEvent) {
-                self.events.push(*event);
-            }
-        }
-
-        let mut proc = MboProcessor::with_observer(TradeObserver::default());
+    fn test_order_ttl_purge_of_failing_messages() {
         let mut seq = TestMessageBuilder::new();
-
-        // Trade action → aggressor = true
-        proc.process_message(&seq.msg(Action::Trade, 99, Side::Ask, 100, 10, false))
+        let mut proc = MboProcessor::new()
+            .with_order_ttl(Duration::milliseconds(2))
+            .with_journal_depth(10);
+        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 10, true))
             .unwrap();
-        // Fill action → aggressor = false
-        proc.process_message(&seq.msg(Action::Fill, 1, Side::Bid, 100, 10, true))
+        proc.process_message(&seq.msg(Action::Add, 2, Side::Bid, 99, 20, true))
             .unwrap();
 
-        let events = &proc.observer().events;
-        assert_eq!(events.len(), 2);
-        assert!(events[0].aggressor);
-        assert!(!events[1].aggressor);
+        // A Modify beginning a snapshot fails before the first order expires
+        let snapshot_modify = MarketByOrderMessage {
+            flags: flags::SNAPSHOT,
+            ..seq.msg(Action::Modify, 2, Side::Bid, 99, 5, true)
+        };
+        assert!(proc.process_message(&snapshot_modify).is_err());
+        assert_eq!(proc.order_book().top_n_bids(5), vec![(100, 10), (99, 20)]);
+
+        // One for an order its event time expired fails after the purge, which stays
+        let modify = seq.msg(Action::Modify, 1, Side::Bid, 100, 5, true);
+        assert!(matches!(
+            proc.process_message(&modify),
+            Err(MboProcessError::OrderBookError(
+                OrderBookError::OrderNotFound(1)
+            ))
+        ));
+        assert_eq!(proc.best_bid(), None);
+
+        proc.rollback(1).unwrap();
+        assert_eq!(proc.order_book().top_n_bids(5), vec![(100, 10), (99, 20)]);
+    }
+
+    // --- Undefined price tests ---
+
+    fn dbn_mbo(action: u8, side: u8, price: i64) -> MboMsg {
+        MboMsg {
+            hd: dbn::RecordHeader::new::<MboMsg>(dbn::enums::rtype::MBO, 1, 1, 1_000),
+            order_id: 42,
+            price,
+            size: 10,
+            flags: dbn::FlagSet::new(flags::LAST),
+            channel_id: 0,
+            action: action as c_char,
+            side: side as c_char,
+            ts_recv: 1_050,
+            ts_in_delta: 0,
+            sequence: 1,
+        }
     }
 
     #[test]
-    fn test_trade_collector_observer() {
-        use crate::orderbook::tradestream::TradeCollector;
+    fn test_undefined_price_add_rejected_at_conversion() {
+        let result = MarketByOrderMessage::try_from(&dbn_mbo(b'A', b'A', UNDEF_PRICE));
+        assert!(matches!(
+            result,
+            Err(MboProcessError::UndefinedPrice {
+                action: Action::Add,
+                order_id: 42
+            })
+        ));
+        assert!(MarketByOrderMessage::try_from(&dbn_mbo(b'M', b'A', UNDEF_PRICE)).is_err());
+
+        let trade = MarketByOrderMessage::try_from(&dbn_mbo(b'T', b'N', UNDEF_PRICE)).unwrap();
+        assert_eq!(trade.defined_price(), None);
+        // The missing side is kept, and written back as such
+        assert_eq!(trade.known_side(), None);
+        assert_eq!(MboMsg::try_from(&trade).unwrap().side, b'N' as c_char);
+        let ask = MarketByOrderMessage::try_from(&dbn_mbo(b'T', b'A', 100)).unwrap();
+        assert_eq!(ask.known_side(), Some(Side::Ask));
+    }
+
+    #[test]
+    fn test_side_characters() {
+        assert_eq!(side_from_char(b'B' as c_char).unwrap(), Some(Side::Bid));
+        assert_eq!(side_from_char(b'N' as c_char).unwrap(), None);
+        assert!(matches!(
+            side_from_char(b'S' as c_char),
+            Err(MboProcessError::SideConversionError(side)) if side == b'S' as i8
+        ));
+        // Only orders that rest need a side
+        assert!(matches!(
+            MarketByOrderMessage::try_from(&dbn_mbo(b'A', b'N', 100)),
+            Err(MboProcessError::SideConversionError(_))
+        ));
+        for (action, side) in [(b'A', b'B'), (b'M', b'A'), (b'C', b'N'), (b'F', b'N')] {
+            let message = MarketByOrderMessage::try_from(&dbn_mbo(action, side, 100)).unwrap();
+            assert_eq!(message.side_char(), side as char);
+            assert_eq!(MboMsg::try_from(&message).unwrap().side, side as c_char);
+        }
+    }
+
+    #[test]
+    fn test_publisher_and_channel_ids_round_trip() {
+        let record = MboMsg {
+            channel_id: 6,
+            ..dbn_mbo(b'A', b'B', 100)
+        };
+        let message = MarketByOrderMessage::try_from(&record).unwrap();
+        assert_eq!((message.publisher_id, message.channel_id), (1, 6));
+        let written = MboMsg::try_from(&message).unwrap();
+        assert_eq!((written.hd.publisher_id, written.channel_id), (1, 6));
+    }
 
+    #[test]
+    fn test_message_serde_round_trip() {
+        let mut message = MarketByOrderMessage::try_from(&dbn_mbo(b'T', b'N', 100)).unwrap();
+        message.ts_in_delta = Duration::nanoseconds(-250);
+        message.owner = Some(3);
+        let json = serde_json::to_value(message).unwrap();
+        assert_eq!(json["action"], "Trade");
+        assert_eq!(json["side"], "Bid");
+        assert_eq!(json["no_side"], true);
+        assert_eq!(json["ts_in_delta"], -250);
+        assert_eq!(
+            json["event_time"],
+            message.event_time.unix_timestamp_nanos() as i64
+        );
+        assert_eq!(
+            serde_json::from_value::<MarketByOrderMessage>(json).unwrap(),
+            message
+        );
+        let binary = bincode::serialize(&message).unwrap();
+        assert_eq!(
+            bincode::deserialize::<MarketByOrderMessage>(&binary).unwrap(),
+            message
+        );
+
+        // Numeric discriminants are accepted, and optional fields may be left out
+        let mut json = serde_json::to_value(message).unwrap();
+        let object = json.as_object_mut().unwrap();
+        object.insert("action".to_owned(), 1.into());
+        object.insert("side".to_owned(), 2.into());
+        object.remove("no_side");
+        object.remove("owner");
+        let added = serde_json::from_value::<MarketByOrderMessage>(json).unwrap();
+        assert_eq!(
+            (added.action, added.known_side()),
+            (Action::Add, Some(Side::Ask))
+        );
+        assert_eq!(added.owner, None);
+    }
+
+    #[test]
+    fn test_undefined_price_add_never_becomes_best_ask() {
         let mut proc = MboProcessor::with_observer(TradeCollector::new());
         let mut seq = TestMessageBuilder::new();
+        proc.process_message(&seq.msg(Action::Add, 1, Side::Ask, 101, 10, true))
+            .unwrap();
 
-        // Add an order (should not produce trades)
-        proc.process_message(&seq.msg(Action::Add, 1, Side::Bid, 100, 50, true))
+        let result =
+            proc.process_message(&seq.msg(Action::Add, 2, Side::Ask, UNDEF_PRICE, 10, true));
+        assert!(matches!(result, Err(MboProcessError::ValidationFailed(_))));
+        assert_eq!(proc.best_ask(), Some((101, 10)));
+
+        // A Trade without a price is accepted but not reported
+        proc.process_message(&seq.msg(Action::Trade, 0, Side::Ask, UNDEF_PRICE, 5, true))
             .unwrap();
-        assert_eq!(proc.observer().trades().len(), 0);
+        assert!(proc.observer().trades().is_empty());
+        assert_eq!(proc.best_ask(), Some((101, 10)));
+    }
 
-        // Trade message should be collected (aggressor)
-        proc.process_message(&seq.msg(Action::Trade, 99, Side::Ask, 100, 10, false))
+    #[test]
+    fn test_participant_summary_counts_fills() {
+        let mut proc = MboProcessor::new().with_journal_depth(4);
+        let mut seq = TestMessageBuilder::new();
+        let owned = |message, owner| MarketByOrderMessage {
+            owner: Some(owner),
+            ..message
+        };
+        proc.process_message(&owned(seq.msg(Action::Add, 1, Side::Bid, 100, 30, true), 7))
+            .unwrap();
+        proc.process_message(&owned(seq.msg(Action::Add, 2, Side::Bid, 100, 10, true), 8))
+            .unwrap();
+        // The Fill and Modify carry no owner; the resting order's is used
+        proc.process_message(&seq.msg(Action::Fill, 1, Side::Bid, 100, 12, false))
+            .unwrap();
+        proc.process_message(&seq.msg(Action::Modify, 1, Side::Bid, 100, 18, true))
             .unwrap();
-        assert_eq!(proc.observer().trades().len(), 1);
 
-        // Fill message should also be collected (passive)
-        proc.process_message(&seq.msg(Action::Fill, 1, Side::Bid, 100, 20, true))
+        let summary = proc.participant_summary(1).unwrap();
+        assert_eq!((summary[&7].bid.qty, summary[&7].executed_qty), (18, 12));
+        assert_eq!((summary[&8].bid.qty, summary[&8].executed_qty), (10, 0));
+        assert_eq!(proc.participant_summary(2), None);
+
+        // Rolling back the fill takes its quantity back
+        proc.rollback(2).unwrap();
+        let summary = proc.participant_summary(1).unwrap();
+        assert_eq!((summary[&7].bid.qty, summary[&7].executed_qty), (30, 0));
+    }
+
+    #[test]
+    fn test_book_events_replay_processed_stream() {
+        let messages =
+            OrderGenerator::default_seeded(8).make_lifecycle_messages(5_000, MessageMix::default());
+        let mut proc = MboProcessor::new()
+            .with_crossing_policy(CrossingPolicy::Match)
+            .with_error_policy(ErrorPolicy::Collect)
+            .with_book_events(EventRetention::All)
+            .with_journal_depth(8);
+        let (head, tail) = messages.split_at(messages.len() - 5);
+        proc.process_messages(head);
+        let (best_ask, _) = proc.best_ask().unwrap();
+        let last = head[head.len() - 1];
+        proc.process_message(&MarketByOrderMessage {
+            action: Action::Add,
+            side: Side::Bid,
+            price: best_ask,
+            size: 1_000,
+            order_id: 1 << 40,
+            ..last
+        })
+        .unwrap();
+        proc.process_messages(tail);
+        // Undone changes are journaled as changes too
+        proc.rollback(3).unwrap();
+
+        let book = proc.order_book();
+        let events: Vec<BookEvent> = book.events().copied().collect();
+        let mut replayed = OrderBook::replay_events(&events);
+        assert!(events.len() > messages.len() / 2);
+        assert!(
+            events
+                .iter()
+                .any(|event| matches!(event.change, BookChange::Filled { .. }))
+        );
+        // Anomalies of operations that failed are not journaled
+        replayed.restore_anomalies(book.anomalies());
+        assert_eq!(&replayed, book);
+    }
+
+    #[test]
+    fn test_participant_summary_empty_without_owners() {
+        let messages =
+            OrderGenerator::default_seeded(3).make_lifecycle_messages(5_000, MessageMix::default());
+        let mut proc = MboProcessor::new();
+        assert!(proc.process_messages(&messages).is_success());
+        assert!(proc.order_book().level_count(Side::Bid) > 0);
+        assert!(proc.participant_summary(1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_state_hashes_match_across_replays() {
+        let messages =
+            OrderGenerator::default_seeded(4).make_lifecycle_messages(5_000, MessageMix::default());
+        let replay = |messages: &[MarketByOrderMessage]| {
+            let mut proc = MboProcessor::new()
+                .with_crossing_policy(CrossingPolicy::Match)
+                .with_error_policy(ErrorPolicy::Collect)
+                .with_state_hash_interval(100);
+            proc.process_messages(messages);
+            (proc.state_hashes().to_vec(), proc.state_hash())
+        };
+        let (hashes, last) = replay(&messages);
+        assert_eq!(hashes.len(), messages.len() / 100);
+        assert_eq!(*hashes.last().unwrap(), last);
+        assert_eq!(replay(&messages), (hashes.clone(), last));
+
+        // A different size on an order that stays in the book changes every hash from
+        // its Add on
+        let mut changed = messages.clone();
+        let index = (0..changed.len())
+            .rev()
+            .find(|&i| {
+                let add = &changed[i];
+                add.action == Action::Add
+                    && changed[i + 1..]
+                        .iter()
+                        .all(|message| message.order_id != add.order_id)
+            })
             .unwrap();
-        assert_eq!(proc.observer().trades().len(), 2);
+        changed[index].size += 1;
+        let (changed_hashes, changed_last) = replay(&changed);
+        assert_eq!(changed_hashes[..index / 100], hashes[..index / 100]);
+        assert!((index / 100..hashes.len()).all(|i| changed_hashes[i] != hashes[i]));
+        assert_ne!(changed_last, last);
+    }
 
-        // Verify trade data and aggressor flag
-        let trades = proc.into_observer().into_trades();
-        assert_eq!(trades[0].price, 100);
-        assert_eq!(trades[0].size, 10);
-        assert!(trades[0].aggressor);
-        assert_eq!(trades[1].price, 100);
-        assert_eq!(trades[1].size, 20);
-        assert!(!trades[1].aggressor);
+    #[test]
+    fn test_anomaly_log_records_messages() {
+        let mut seq = TestMessageBuilder::new();
+        let messages = [
+            seq.msg(Action::Add, 1, Side::Bid, 100, 10, true),
+            seq.msg(Action::Add, 1, Side::Bid, 100, 20, true),
+            seq.msg(Action::Cancel, 9, Side::Ask, 105, 5, true),
+            seq.msg(Action::Add, 2, Side::Bid, 99, 30, true),
+            seq.msg(Action::Cancel, 1, Side::Bid, 100, 20, true),
+        ];
+        let run = |retention| {
+            let mut proc = MboProcessor::new()
+                .with_error_policy(ErrorPolicy::Collect)
+                .with_book_depth_limit(1, DepthLimitPolicy::Drop)
+                .with_anomaly_log(retention);
+            proc.process_messages(messages);
+            proc
+        };
+
+        let proc = run(EventRetention::All);
+        let record = |kind, message_index: usize| {
+            let message = &messages[message_index];
+            AnomalyRecord {
+                kind,
+                message_index: message_index as u64,
+                instrument_id: 1,
+                ts_event: message.event_time.unix_timestamp_nanos() as i64,
+                sequence: message.sequence,
+                action: message.action_char(),
+                side: message.side,
+                order_id: message.order_id,
+                price: message.price,
+                size: message.size,
+            }
+        };
+        let expected = [
+            record(AnomalyKind::DuplicateAdd, 1),
+            record(AnomalyKind::UnknownCancel, 2),
+            record(AnomalyKind::DepthDropped, 3),
+        ];
+        let log: Vec<AnomalyRecord> = proc.anomaly_log().copied().collect();
+        assert_eq!(log, expected);
+
+        // The counters follow from the log
+        let counted: Anomalies = proc.anomaly_log().collect();
+        let stats = proc.stats();
+        assert_eq!(
+            (
+                counted.duplicate_add,
+                counted.unknown_cancel,
+                counted.depth_dropped
+            ),
+            (stats.duplicate_adds, stats.unknown_cancels, 1)
+        );
+        assert_eq!(counted, proc.order_book().anomalies());
+
+        let bounded = run(EventRetention::Last(2));
+        assert!(
+            bounded
+                .anomaly_log()
+                .copied()
+                .eq(expected[1..].iter().copied())
+        );
+        assert_eq!(run(EventRetention::Off).anomaly_log().count(), 0);
+
+        #[cfg(feature = "polars")]
+        {
+            let df = proc.anomalies_to_dataframe().unwrap();
+            assert_eq!(df.height(), 3);
+            let kinds: Vec<_> = df.column("kind").unwrap().str().unwrap().iter().collect();
+            assert_eq!(
+                kinds,
+                [
+                    Some("duplicate_add"),
+                    Some("unknown_cancel"),
+                    Some("depth_dropped")
+                ]
+            );
+            assert_eq!(
+                MboProcessor::new()
+                    .anomalies_to_dataframe()
+                    .unwrap()
+                    .height(),
+                0
+            );
+        }
+    }
+
+    #[test]
+    fn test_fork_branches_diverge_from_shared_state() {
+        let messages =
+            OrderGenerator::default_seeded(6).make_lifecycle_messages(4_000, MessageMix::default());
+        let (head, tail) = messages.split_at(2_000);
+        let mut proc = MboProcessor::with_observer(BboRecorder::new())
+            .with_crossing_policy(CrossingPolicy::Match)
+            .with_error_policy(ErrorPolicy::Collect)
+            .with_state_hash_interval(100);
+        proc.process_messages(head);
+        let forked_at = proc.state_hash();
+        let history = proc.state_hashes().to_vec();
+        assert!(!proc.observer().is_empty());
+
+        // A clone copies the observer's rows, a fork starts it empty
+        let copy = proc.clone();
+        assert_eq!(copy.observer().len(), proc.observer().len());
+        assert_eq!(copy.state_hash(), forked_at);
+        let mut branch = proc.fork();
+        assert!(branch.observer().is_empty());
+        assert_eq!(branch.stats(), proc.stats());
+
+        // The branch takes an aggressive buy first
+        let (best_ask, _) = branch.best_ask().unwrap();
+        branch
+            .process_message(&MarketByOrderMessage {
+                action: Action::Add,
+                side: Side::Bid,
+                price: best_ask,
+                size: 1_000,
+                order_id: 1 << 40,
+                ..head[head.len() - 1]
+            })
+            .unwrap();
+        branch.process_messages(tail);
+        assert_eq!(proc.state_hash(), forked_at);
+        proc.process_messages(tail);
+
+        assert_ne!(branch.state_hash(), proc.state_hash());
+        assert_eq!(proc.state_hashes()[..history.len()], history);
+        assert_eq!(branch.state_hashes()[..history.len()], history);
+        assert_ne!(
+            branch.state_hashes()[history.len()..],
+            proc.state_hashes()[history.len()..]
+        );
+        assert_eq!(copy.state_hash(), forked_at);
     }
 }
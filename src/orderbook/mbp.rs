@@ -1,9 +1,23 @@
+use dbn::{BidAskPair, UNDEF_PRICE};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap};
+use std::{array, iter, mem};
+use thiserror::Error;
 use time::OffsetDateTime;
 
-use crate::orderbook::book::OrderLevel;
-use crate::orderbook::{MboObserver, MboProcessor, OrderBook};
+#[cfg(feature = "polars")]
+use polars::prelude::{
+    Column, DataFrame, DataType, PolarsResult, Series, df, polars_bail, polars_err,
+};
+
+use crate::orderbook::book::{self, OrderLevel};
+#[cfg(feature = "polars")]
+use crate::orderbook::dataframe::integer_column;
+use crate::orderbook::{
+    BookKey, L2Action, L2Update, MarketByOrderMessage, MboObserver, MboProcessor, OrderAddedEvent,
+    OrderBook, OrderCancelledEvent, OrderModifiedEvent, PriceScale, Side, TradeEvent,
+};
 
 /// An order level summary gives aggregate information about a price level.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -13,6 +27,15 @@ pub struct OrderLevelSummary {
     pub order_count: usize,
 }
 
+impl OrderLevelSummary {
+    /// Price times quantity in currency, for prices in fixed-point units of
+    /// `1 / price_scale`, e.g. `dbn::FIXED_PRICE_SCALE`. Multiplied exactly in `i128`
+    /// before scaling.
+    pub fn notional(&self, price_scale: i64) -> f64 {
+        (i128::from(self.price) * i128::from(self.total_quantity)) as f64 / price_scale as f64
+    }
+}
+
 impl From<&OrderLevel> for OrderLevelSummary {
     fn from(level: &OrderLevel) -> Self {
         Self {
@@ -23,9 +46,18 @@ impl From<&OrderLevel> for OrderLevelSummary {
     }
 }
 
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum DepthJsonError {
+    #[error("Depth JSON has no '{0}' array")]
+    MissingSide(&'static str),
+
+    #[error("Invalid {side} level {level} in depth JSON, expected [price, quantity]")]
+    InvalidLevel { side: &'static str, level: usize },
+}
+
 /// Market-By-Price view of the order book.
 /// Aggregates each price level into an `OrderLevelSummary`.
-#[derive(Default, Debug, Serialize, Deserialize)]
+#[derive(Default, Debug, PartialEq, Serialize, Deserialize)]
 pub struct MarketByPrice {
     pub bids: BTreeMap<i64, OrderLevelSummary>,
     pub asks: BTreeMap<i64, OrderLevelSummary>,
@@ -49,7 +81,7 @@ impl MarketByPrice {
     /// Bids are the `n` highest-priced levels; asks are the `n` lowest-priced levels.
     pub fn from_top_n(book: &OrderBook, n: usize) -> Self {
         let bids = book
-            .bids
+            .bids()
             .iter()
             .rev()
             .take(n)
@@ -57,7 +89,7 @@ impl MarketByPrice {
             .collect();
 
         let asks = book
-            .asks
+            .asks()
             .iter()
             .take(n)
             .map(|(&price, level)| (price, OrderLevelSummary::from(level)))
@@ -72,14 +104,511 @@ impl MarketByPrice {
         }
     }
 
+    pub fn best_bid(&self) -> Option<(i64, u64)> {
+        self.bids
+            .last_key_value()
+            .map(|(&price, level)| (price, level.total_quantity))
+    }
+
+    pub fn best_ask(&self) -> Option<(i64, u64)> {
+        self.asks
+            .first_key_value()
+            .map(|(&price, level)| (price, level.total_quantity))
+    }
+
+    /// Best ask minus best bid, as `OrderBook::spread`.
+    pub fn spread(&self) -> Option<i64> {
+        book::spread(self.best_bid(), self.best_ask())
+    }
+
+    /// Midpoint of the best bid and ask, as `OrderBook::mid`.
+    pub fn mid(&self) -> Option<f64> {
+        book::mid(self.best_bid(), self.best_ask())
+    }
+
+    /// Sum of `OrderLevelSummary::notional` over the best `depth` levels of `side`.
+    pub fn total_notional(&self, side: Side, depth: usize, price_scale: i64) -> f64 {
+        let levels = match side {
+            Side::Bid => self.top_n_bids(depth),
+            Side::Ask => self.top_n_asks(depth),
+        };
+        levels.iter().map(|level| level.notional(price_scale)).sum()
+    }
+
+    /// Quantity imbalance over the best `depth` levels per side, as
+    /// `OrderBook::imbalance`.
+    pub fn imbalance(&self, depth: usize) -> Option<f64> {
+        book::imbalance(self.depth_bids(depth), self.depth_asks(depth))
+    }
+
+    /// Mid weighted by the quantity of the best `depth` levels per side, as
+    /// `OrderBook::weighted_mid`.
+    pub fn weighted_mid(&self, depth: usize) -> Option<f64> {
+        book::weighted_mid(self.depth_bids(depth), self.depth_asks(depth))
+    }
+
+    fn depth_bids(&self, depth: usize) -> impl Iterator<Item = (i64, u64)> {
+        self.bids
+            .values()
+            .rev()
+            .take(depth)
+            .map(|level| (level.price, level.total_quantity))
+    }
+
+    fn depth_asks(&self, depth: usize) -> impl Iterator<Item = (i64, u64)> {
+        self.asks
+            .values()
+            .take(depth)
+            .map(|level| (level.price, level.total_quantity))
+    }
+
     /// Top-N bid levels, ordered best (highest price) to worst.
     pub fn top_n_bids(&self, n: usize) -> Vec<OrderLevelSummary> {
-        self.bids.values().rev().take(n).copied().collect()
+        let mut levels = Vec::with_capacity(n.min(self.bids.len()));
+        self.top_n_bids_into(n, &mut levels);
+        levels
     }
 
     /// Top-N ask levels, ordered best (lowest price) to worst.
     pub fn top_n_asks(&self, n: usize) -> Vec<OrderLevelSummary> {
-        self.asks.values().take(n).copied().collect()
+        let mut levels = Vec::with_capacity(n.min(self.asks.len()));
+        self.top_n_asks_into(n, &mut levels);
+        levels
+    }
+
+    /// Like `top_n_bids`, but clears `out` and writes into it, reusing its allocation.
+    pub fn top_n_bids_into(&self, n: usize, out: &mut Vec<OrderLevelSummary>) {
+        out.clear();
+        out.extend(self.bids.values().rev().take(n).copied());
+    }
+
+    /// Like `top_n_asks`, but clears `out` and writes into it, reusing its allocation.
+    pub fn top_n_asks_into(&self, n: usize, out: &mut Vec<OrderLevelSummary>) {
+        out.clear();
+        out.extend(self.asks.values().take(n).copied());
+    }
+
+    /// The best `max_levels` levels of `side`, best first, each with the total quantity
+    /// of that level and all better ones: the x and y of a depth chart.
+    pub fn cumulative_depth(&self, side: Side, max_levels: usize) -> Vec<(i64, u64)> {
+        let levels = match side {
+            Side::Bid => self.top_n_bids(max_levels),
+            Side::Ask => self.top_n_asks(max_levels),
+        };
+        levels
+            .into_iter()
+            .scan(0u64, |total, level| {
+                *total = total.saturating_add(level.total_quantity);
+                Some((level.price, *total))
+            })
+            .collect()
+    }
+
+    /// Cumulative liquidity curve of `side`, thinned to at most `max_points` points, as
+    /// `OrderBook::depth_curve`.
+    pub fn depth_curve(&self, side: Side, max_points: usize) -> Vec<(i64, u64)> {
+        match side {
+            Side::Bid => book::depth_curve(self.depth_bids(usize::MAX), max_points),
+            Side::Ask => book::depth_curve(self.depth_asks(usize::MAX), max_points),
+        }
+    }
+
+    /// Cumulative notional curve of `side`, as `OrderBook::notional_curve`.
+    pub fn notional_curve(
+        &self,
+        side: Side,
+        max_points: usize,
+        price_scale: i64,
+    ) -> Vec<(i64, f64)> {
+        match side {
+            Side::Bid => book::notional_curve(self.depth_bids(usize::MAX), max_points, price_scale),
+            Side::Ask => book::notional_curve(self.depth_asks(usize::MAX), max_points, price_scale),
+        }
+    }
+
+    /// Both `depth_curve`s as a tidy DataFrame for plotting, with columns `side`
+    /// (`"B"` or `"A"`), `price` and `cum_qty`, bids first.
+    #[cfg(feature = "polars")]
+    pub fn depth_curves_to_dataframe(&self, max_points: usize) -> PolarsResult<DataFrame> {
+        let bids = self.depth_curve(Side::Bid, max_points);
+        let asks = self.depth_curve(Side::Ask, max_points);
+        let side: Vec<&str> = iter::repeat_n("B", bids.len())
+            .chain(iter::repeat_n("A", asks.len()))
+            .collect();
+        let points: Vec<(i64, u64)> = bids.into_iter().chain(asks).collect();
+        df!(
+            "side" => side,
+            "price" => points.iter().map(|&(price, _)| price).collect::<Vec<_>>(),
+            "cum_qty" => points.iter().map(|&(_, qty)| qty).collect::<Vec<_>>(),
+        )
+    }
+
+    /// This snapshot with its levels merged into buckets `bucket_ticks` price units
+    /// wide, keeping its metadata. Bids are rounded down and asks up to a multiple of
+    /// `bucket_ticks`, so the buckets never cross; each bucket sums the quantities and
+    /// order counts of its levels. A width of 0 or 1 leaves the levels as they are.
+    pub fn bucketize(&self, bucket_ticks: u32) -> Self {
+        let width = i64::from(bucket_ticks.max(1));
+        let merge = |levels: &BTreeMap<i64, OrderLevelSummary>, round_up: bool| {
+            let mut buckets = BTreeMap::new();
+            for level in levels.values() {
+                let mut price = level.price.div_euclid(width) * width;
+                if round_up && price != level.price {
+                    price += width;
+                }
+                let bucket = buckets.entry(price).or_insert(OrderLevelSummary {
+                    price,
+                    total_quantity: 0,
+                    order_count: 0,
+                });
+                bucket.total_quantity += level.total_quantity;
+                bucket.order_count += level.order_count;
+            }
+            buckets
+        };
+        Self {
+            bids: merge(&self.bids, false),
+            asks: merge(&self.asks, true),
+            event_time: self.event_time,
+            recv_time: self.recv_time,
+            sequence: self.sequence,
+        }
+    }
+
+    /// A price ladder of the best `depth` levels per side, for printing: asks above
+    /// bids, both from the highest price down, each level with its quantity and order
+    /// count. Prices are formatted with `PriceScale::format`, e.g. with two decimals
+    /// for a scale of 100 and nine for DBN's.
+    pub fn render(&self, depth: usize, price_scale: PriceScale) -> String {
+        let price = |price: i64| price_scale.format(price);
+        let header = ["Orders", "Bid Qty", "Price", "Ask Qty", "Orders"].map(String::from);
+        let asks = self.top_n_asks(depth).into_iter().rev().map(|level| {
+            [
+                String::new(),
+                String::new(),
+                price(level.price),
+                level.total_quantity.to_string(),
+                level.order_count.to_string(),
+            ]
+        });
+        let bids = self.top_n_bids(depth).into_iter().map(|level| {
+            [
+                level.order_count.to_string(),
+                level.total_quantity.to_string(),
+                price(level.price),
+                String::new(),
+                String::new(),
+            ]
+        });
+        let rows: Vec<[String; 5]> = iter::once(header).chain(asks).chain(bids).collect();
+        let widths: [usize; 5] =
+            array::from_fn(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0));
+
+        let mut ladder = String::new();
+        for (index, row) in rows.iter().enumerate() {
+            let cells: Vec<String> = row
+                .iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{cell:>width$}"))
+                .collect();
+            ladder.push_str(cells.join("  ").trim_end());
+            ladder.push('\n');
+            if index == 0 {
+                let rule = widths.iter().sum::<usize>() + 2 * (widths.len() - 1);
+                ladder.push_str(&"-".repeat(rule));
+                ladder.push('\n');
+            }
+        }
+        ladder
+    }
+
+    /// This snapshot truncated to at most `n` levels per side, keeping its metadata.
+    pub fn top_n(&self, n: usize) -> Self {
+        let levels = |levels: Vec<OrderLevelSummary>| {
+            levels
+                .into_iter()
+                .map(|level| (level.price, level))
+                .collect()
+        };
+        Self {
+            bids: levels(self.top_n_bids(n)),
+            asks: levels(self.top_n_asks(n)),
+            event_time: self.event_time,
+            recv_time: self.recv_time,
+            sequence: self.sequence,
+        }
+    }
+
+    /// The level updates that turn this view into `other`, by side then price: a `Set`
+    /// for each level of `other` that is new or differs in quantity or order count, a
+    /// `Delete` for each level `other` lacks. Empty if the levels are equal; metadata is
+    /// not compared.
+    pub fn diff(&self, other: &MarketByPrice) -> Vec<L2Update> {
+        let mut updates = Vec::new();
+        for (side, levels, others) in [
+            (Side::Bid, &self.bids, &other.bids),
+            (Side::Ask, &self.asks, &other.asks),
+        ] {
+            let mut changes: Vec<L2Update> = others
+                .values()
+                .filter(|level| levels.get(&level.price) != Some(level))
+                .map(|level| L2Update {
+                    side,
+                    price: level.price,
+                    new_qty: level.total_quantity,
+                    new_count: level.order_count,
+                    action: L2Action::Set,
+                })
+                .collect();
+            changes.extend(
+                levels
+                    .keys()
+                    .filter(|price| !others.contains_key(price))
+                    .map(|&price| L2Update {
+                        side,
+                        price,
+                        new_qty: 0,
+                        new_count: 0,
+                        action: L2Action::Delete,
+                    }),
+            );
+            changes.sort_unstable_by_key(|update| update.price);
+            updates.extend(changes);
+        }
+        updates
+    }
+
+    /// Converts the levels into a DataFrame with one row per level and columns `side`
+    /// (`"B"` or `"A"`), `level` (0 for the best price), `price`, `size` and
+    /// `order_count`. Bids come first, each side ordered best to worst.
+    #[cfg(feature = "polars")]
+    pub fn to_dataframe(&self) -> PolarsResult<DataFrame> {
+        self.to_dataframe_with(&MbpFrameOptions::default())
+    }
+
+    /// Like `to_dataframe`, limited to the depth in `options` and preceded by the
+    /// constant tag columns it sets, so snapshots can be stacked into one table.
+    #[cfg(feature = "polars")]
+    pub fn to_dataframe_with(&self, options: &MbpFrameOptions) -> PolarsResult<DataFrame> {
+        let depth = options.depth.unwrap_or(usize::MAX);
+        let bids = self.bids.values().rev().take(depth);
+        let asks = self.asks.values().take(depth);
+        let side: Vec<&str> = bids
+            .clone()
+            .map(|_| "B")
+            .chain(asks.clone().map(|_| "A"))
+            .collect();
+        let level: Vec<u32> = (0..bids.len() as u32).chain(0..asks.len() as u32).collect();
+        let levels: Vec<&OrderLevelSummary> = bids.chain(asks).collect();
+        let mut df = df!(
+            "side" => side,
+            "level" => level,
+            "price" => levels.iter().map(|level| level.price).collect::<Vec<_>>(),
+            "size" => levels.iter().map(|level| level.total_quantity).collect::<Vec<_>>(),
+            "order_count" => levels.iter().map(|level| level.order_count as u32).collect::<Vec<_>>(),
+        )?;
+        if let Some(price_scale) = options.price_scale {
+            let price_display: Vec<f64> = levels
+                .iter()
+                .map(|level| price_scale.to_f64(level.price))
+                .collect();
+            let notional: Vec<f64> = levels
+                .iter()
+                .map(|level| level.notional(price_scale.divisor()))
+                .collect();
+            df.with_column(Column::new("price_display".into(), price_display))?;
+            df.with_column(Column::new("notional".into(), notional))?;
+        }
+
+        let rows = df.height();
+        let mut tags = Vec::new();
+        if let Some(ts_event) = options.ts_event {
+            let ns = ts_event.unix_timestamp_nanos() as i64;
+            tags.push(Column::new("ts_event".into(), vec![ns; rows]));
+        }
+        if let Some(instrument_id) = options.instrument_id {
+            tags.push(Column::new(
+                "instrument_id".into(),
+                vec![instrument_id; rows],
+            ));
+        }
+        if tags.is_empty() {
+            return Ok(df);
+        }
+        DataFrame::new(tags.into_iter().chain(df.take_columns()).collect())
+    }
+
+    /// The best `depth` levels per side in the exchange-style JSON shape
+    /// `{"bids": [[price, qty], ...], "asks": [[price, qty], ...]}`, best first. A side
+    /// with fewer levels gives a shorter array.
+    ///
+    /// Prices are the fixed-point integers of the book under `PriceScale::RAW`, and
+    /// numbers in units under any other scale. Order counts and metadata are left out.
+    pub fn to_json_depth(&self, depth: usize, price_scale: PriceScale) -> Value {
+        let price = |price: i64| match price_scale.is_raw() {
+            true => json!(price),
+            false => json!(price_scale.to_f64(price)),
+        };
+        let levels = |levels: Vec<OrderLevelSummary>| {
+            levels
+                .into_iter()
+                .map(|level| json!([price(level.price), level.total_quantity]))
+                .collect::<Vec<_>>()
+        };
+        json!({
+            "bids": levels(self.top_n_bids(depth)),
+            "asks": levels(self.top_n_asks(depth)),
+        })
+    }
+
+    /// Reads the shape written by `to_json_depth` with the same `price_scale`, rounding
+    /// scaled prices to the nearest fixed-point integer. The order count of every level
+    /// is 0, as the shape does not carry it.
+    pub fn from_json_depth(value: &Value, price_scale: PriceScale) -> Result<Self, DepthJsonError> {
+        let side =
+            |side: &'static str| -> Result<BTreeMap<i64, OrderLevelSummary>, DepthJsonError> {
+                let levels = value[side]
+                    .as_array()
+                    .ok_or(DepthJsonError::MissingSide(side))?;
+                levels
+                    .iter()
+                    .enumerate()
+                    .map(|(level, pair)| {
+                        let invalid = DepthJsonError::InvalidLevel { side, level };
+                        let (price, quantity) = match pair.as_array().map(Vec::as_slice) {
+                            Some([price, quantity]) => (price, quantity),
+                            _ => return Err(invalid),
+                        };
+                        let price = match price_scale.is_raw() {
+                            true => price.as_i64(),
+                            false => price.as_f64().map(|price| price_scale.to_price(price)),
+                        };
+                        let (Some(price), Some(total_quantity)) = (price, quantity.as_u64()) else {
+                            return Err(invalid);
+                        };
+                        Ok((
+                            price,
+                            OrderLevelSummary {
+                                price,
+                                total_quantity,
+                                order_count: 0,
+                            },
+                        ))
+                    })
+                    .collect()
+            };
+        Ok(Self {
+            bids: side("bids")?,
+            asks: side("asks")?,
+            ..Self::default()
+        })
+    }
+
+    /// Reads back the levels of a frame in the schema of `to_dataframe`: `side` (`"B"`
+    /// or `"A"`, also `"Bid"` or `"Ask"`, or their `i8` character codes), `price`,
+    /// `size` and `order_count`. Other columns, such as `level`, are ignored, and the
+    /// metadata is left unset.
+    ///
+    /// A missing column, a null, an unknown side or two rows for the same level is an
+    /// error.
+    #[cfg(feature = "polars")]
+    pub fn from_dataframe(df: &DataFrame) -> PolarsResult<Self> {
+        let mut mbp = Self::new();
+        for (side, level) in frame_levels(df)? {
+            mbp.insert_level(side, level)?;
+        }
+        Ok(mbp)
+    }
+
+    /// Reads back a table of stacked snapshots, such as `to_dataframe_with` frames
+    /// tagged with `ts_event`, as one `MarketByPrice` per `ts_event` in time order, each
+    /// with its `event_time` set. The levels are read as in `from_dataframe`.
+    ///
+    /// A snapshot cannot hold several instruments, so if the table has an
+    /// `instrument_id` column, filter it to one instrument first, e.g. with
+    /// `filter_instrument`.
+    #[cfg(feature = "polars")]
+    pub fn from_dataframe_by_time(df: &DataFrame) -> PolarsResult<Vec<(u64, Self)>> {
+        let ts_event = frame_column(df, "ts_event", &DataType::UInt64)?;
+        let ts_event = ts_event.u64()?;
+        if let Some(instrument_id) = df.column("instrument_id").ok()
+            && instrument_id.n_unique()? > 1
+        {
+            polars_bail!(
+                InvalidOperation: "MBP frame mixes instruments, filter it to one instrument first"
+            );
+        }
+        let mut snapshots: BTreeMap<u64, Self> = BTreeMap::new();
+        for (row, (side, level)) in frame_levels(df)?.into_iter().enumerate() {
+            let ns = ts_event
+                .get(row)
+                .ok_or_else(|| polars_err!(ComputeError: "null ts_event in row {}", row))?;
+            let mbp = snapshots.entry(ns).or_insert_with(|| Self {
+                event_time: OffsetDateTime::from_unix_timestamp_nanos(i128::from(ns)).ok(),
+                ..Self::default()
+            });
+            mbp.insert_level(side, level)?;
+        }
+        Ok(snapshots.into_iter().collect())
+    }
+
+    #[cfg(feature = "polars")]
+    fn insert_level(&mut self, side: Side, level: OrderLevelSummary) -> PolarsResult<()> {
+        let levels = match side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        };
+        if levels.insert(level.price, level).is_some() {
+            polars_bail!(Duplicate: "MBP frame has two {:?} levels at price {}", side, level.price);
+        }
+        Ok(())
+    }
+
+    /// The best `N` levels per side as dbn depth, best first, e.g.
+    /// `to_bid_ask_pairs::<10>()` for the levels of an MBP-10 record. Slots past the
+    /// last level of a side have `UNDEF_PRICE` and a zero size and count; sizes and
+    /// counts beyond `u32` saturate.
+    pub fn to_bid_ask_pairs<const N: usize>(&self) -> [BidAskPair; N] {
+        let mut bids = self.bids.values().rev();
+        let mut asks = self.asks.values();
+        let saturate = |value: u64| u32::try_from(value).unwrap_or(u32::MAX);
+        let price = |level: Option<&OrderLevelSummary>| level.map_or(UNDEF_PRICE, |l| l.price);
+        let size = |level: Option<&OrderLevelSummary>| {
+            level.map_or(0, |level| saturate(level.total_quantity))
+        };
+        let count = |level: Option<&OrderLevelSummary>| {
+            level.map_or(0, |level| saturate(level.order_count as u64))
+        };
+        array::from_fn(|_| {
+            let (bid, ask) = (bids.next(), asks.next());
+            BidAskPair {
+                bid_px: price(bid),
+                ask_px: price(ask),
+                bid_sz: size(bid),
+                ask_sz: size(ask),
+                bid_ct: count(bid),
+                ask_ct: count(ask),
+            }
+        })
+    }
+
+    /// The levels of a dbn MBP-1 or MBP-10 record, leaving out the sides without a
+    /// price or size as `depth_levels` does. The metadata is left unset.
+    pub fn from_bid_ask_pairs(levels: &[BidAskPair]) -> Self {
+        let mut mbp = Self::new();
+        for (side, price, total_quantity, order_count) in depth_levels(levels) {
+            let levels = match side {
+                Side::Bid => &mut mbp.bids,
+                Side::Ask => &mut mbp.asks,
+            };
+            let level = OrderLevelSummary {
+                price,
+                total_quantity,
+                order_count: order_count as usize,
+            };
+            levels.insert(price, level);
+        }
+        mbp
     }
 
     /// Create an MBP-N snapshot with timestamp metadata from the processor.
@@ -107,16 +636,160 @@ impl MarketByPrice {
     }
 }
 
+/// The column `name` of an MBP frame, cast to `target`.
+#[cfg(feature = "polars")]
+fn frame_column(df: &DataFrame, name: &str, target: &DataType) -> PolarsResult<Series> {
+    let column = df
+        .column(name)
+        .map_err(|_| polars_err!(ColumnNotFound: "MBP frame has no '{}' column", name))?;
+    integer_column(column.as_materialized_series(), target)
+}
+
+/// The side and level of every row of an MBP frame, in row order.
+#[cfg(feature = "polars")]
+fn frame_levels(df: &DataFrame) -> PolarsResult<Vec<(Side, OrderLevelSummary)>> {
+    let side = df
+        .column("side")
+        .map_err(|_| polars_err!(ColumnNotFound: "MBP frame has no 'side' column"))?
+        .as_materialized_series();
+    let sides: Vec<Option<Side>> = match side.dtype() {
+        DataType::String => side
+            .str()?
+            .into_iter()
+            .map(|side| match side? {
+                "B" | "Bid" => Some(Side::Bid),
+                "A" | "Ask" => Some(Side::Ask),
+                _ => None,
+            })
+            .collect(),
+        DataType::Int8 => side
+            .i8()?
+            .into_iter()
+            .map(|side| match side? as u8 {
+                b'B' => Some(Side::Bid),
+                b'A' => Some(Side::Ask),
+                _ => None,
+            })
+            .collect(),
+        dtype => polars_bail!(
+            SchemaMismatch: "MBP frame column 'side' has type {}, expected strings or i8 codes",
+            dtype
+        ),
+    };
+    let price = frame_column(df, "price", &DataType::Int64)?;
+    let size = frame_column(df, "size", &DataType::UInt64)?;
+    let order_count = frame_column(df, "order_count", &DataType::UInt32)?;
+    let (price, size, order_count) = (price.i64()?, size.u64()?, order_count.u32()?);
+    (0..df.height())
+        .map(|row| {
+            let (Some(side), Some(price), Some(total_quantity), Some(order_count)) = (
+                sides[row],
+                price.get(row),
+                size.get(row),
+                order_count.get(row),
+            ) else {
+                polars_bail!(ComputeError: "MBP frame row {} has a null or an unknown side", row);
+            };
+            let level = OrderLevelSummary {
+                price,
+                total_quantity,
+                order_count: order_count as usize,
+            };
+            Ok((side, level))
+        })
+        .collect()
+}
+
+/// Options for `MarketByPrice::to_dataframe_with`. The defaults keep every level and
+/// add no columns, matching `MarketByPrice::to_dataframe`.
+#[cfg(feature = "polars")]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct MbpFrameOptions {
+    depth: Option<usize>,
+    ts_event: Option<OffsetDateTime>,
+    instrument_id: Option<u32>,
+    price_scale: Option<PriceScale>,
+}
+
+#[cfg(feature = "polars")]
+impl MbpFrameOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep at most `depth` levels per side; `None` keeps every level.
+    pub fn with_depth(mut self, depth: Option<usize>) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    /// Add a `ts_event` column (`i64` nanoseconds since the UNIX epoch) holding
+    /// `ts_event` on every row.
+    pub fn with_ts_event(mut self, ts_event: Option<OffsetDateTime>) -> Self {
+        self.ts_event = ts_event;
+        self
+    }
+
+    /// Add an `instrument_id` column (`u32`) holding `instrument_id` on every row.
+    pub fn with_instrument_id(mut self, instrument_id: Option<u32>) -> Self {
+        self.instrument_id = instrument_id;
+        self
+    }
+
+    /// Add a `price_display` column (`f64`) with each level's price in units of
+    /// `price_scale`, and a `notional` column (`f64`) with its
+    /// `OrderLevelSummary::notional` at that scale.
+    pub fn with_price_scale(mut self, price_scale: Option<PriceScale>) -> Self {
+        self.price_scale = price_scale;
+        self
+    }
+
+    pub fn depth(&self) -> Option<usize> {
+        self.depth
+    }
+
+    pub fn price_scale(&self) -> Option<PriceScale> {
+        self.price_scale
+    }
+
+    pub fn ts_event(&self) -> Option<OffsetDateTime> {
+        self.ts_event
+    }
+
+    pub fn instrument_id(&self) -> Option<u32> {
+        self.instrument_id
+    }
+}
+
+/// Converts the `levels` of a DBN MBP-1 or MBP-10 record into the
+/// `(side, price, size, count)` levels taken by `OrderBook::seed_from_depth`.
+///
+/// Sides without a price (`UNDEF_PRICE`) or size are left out, so a record with
+/// fewer than ten levels on a side seeds only the levels it has.
+pub fn depth_levels(levels: &[BidAskPair]) -> Vec<(Side, i64, u64, u32)> {
+    levels
+        .iter()
+        .flat_map(|level| {
+            [
+                (Side::Bid, level.bid_px, level.bid_sz, level.bid_ct),
+                (Side::Ask, level.ask_px, level.ask_sz, level.ask_ct),
+            ]
+        })
+        .filter(|&(_, price, size, _)| price != UNDEF_PRICE && size > 0)
+        .map(|(side, price, size, count)| (side, price, u64::from(size), count))
+        .collect()
+}
+
 impl From<&OrderBook> for MarketByPrice {
     fn from(book: &OrderBook) -> Self {
         let bids = book
-            .bids
+            .bids()
             .iter()
             .map(|(&price, level)| (price, OrderLevelSummary::from(level)))
             .collect();
 
         let asks = book
-            .asks
+            .asks()
             .iter()
             .map(|(&price, level)| (price, OrderLevelSummary::from(level)))
             .collect();
@@ -131,13 +804,128 @@ impl From<&OrderBook> for MarketByPrice {
     }
 }
 
+/// Market-by-price views of every book, kept up to date as an observer of
+/// `MboProcessor` instead of being rebuilt with `MarketByPrice::from` after each message.
+///
+/// The callbacks of a message record the levels it touched, at most a few apart from
+/// the levels swept by a crossing Add, and `on_message_applied` re-reads just those
+/// levels from the book, as does `on_message_rejected` for orders purged before a
+/// message failed. The views equal `MarketByPrice::from` of each book, without
+/// metadata. Books changed without callbacks, by `MboProcessor::seed_book` or
+/// `MboProcessor::rollback`, have to be brought back in line with `sync`.
+#[derive(Debug, Default)]
+pub struct IncrementalMbp {
+    books: HashMap<BookKey, MarketByPrice>,
+    /// Levels touched by the message being applied.
+    touched: Vec<(Side, i64)>,
+    /// True if the message being applied cleared the book.
+    cleared: bool,
+}
+
+impl IncrementalMbp {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The view of `instrument_id`'s book, if a message for it has been applied.
+    pub fn mbp(&self, instrument_id: u32) -> Option<&MarketByPrice> {
+        self.mbp_for(instrument_id.into())
+    }
+
+    /// The view of the book under `key`, if a message has been routed to it.
+    pub fn mbp_for(&self, key: BookKey) -> Option<&MarketByPrice> {
+        self.books.get(&key)
+    }
+
+    /// Rebuilds the view of `instrument_id` from `book`.
+    pub fn sync(&mut self, instrument_id: u32, book: &OrderBook) {
+        self.sync_for(instrument_id.into(), book);
+    }
+
+    /// Rebuilds the view of the book under `key` from `book`.
+    pub fn sync_for(&mut self, key: BookKey, book: &OrderBook) {
+        self.books.insert(key, MarketByPrice::from(book));
+    }
+
+    fn touch(&mut self, side: Side, price: i64) {
+        self.touched.push((side, price));
+    }
+}
+
+impl MboObserver for IncrementalMbp {
+    fn on_order_added(&mut self, event: &OrderAddedEvent) {
+        self.touch(event.order.side, event.order.price);
+        if let Some((side, price)) = event.replaced_level {
+            self.touch(side, price);
+        }
+    }
+
+    fn on_order_cancelled(&mut self, event: &OrderCancelledEvent) {
+        self.touch(event.order.side, event.order.price);
+    }
+
+    fn on_order_modified(&mut self, event: &OrderModifiedEvent) {
+        self.touch(event.order.side, event.order.price);
+        // A Modify can move the order to the other side
+        self.touch(Side::Bid, event.old_price);
+        self.touch(Side::Ask, event.old_price);
+    }
+
+    fn on_trade(&mut self, event: &TradeEvent) {
+        // Resting orders executed by a crossing Add
+        if !event.aggressor {
+            self.touch(event.side, event.price);
+        }
+    }
+
+    fn on_clear(&mut self) {
+        self.cleared = true;
+        self.touched.clear();
+    }
+
+    fn on_message_applied(
+        &mut self,
+        _message: &MarketByOrderMessage,
+        key: BookKey,
+        book: &OrderBook,
+    ) {
+        let mbp = self.books.entry(key).or_default();
+        if mem::take(&mut self.cleared) {
+            mbp.bids.clear();
+            mbp.asks.clear();
+        }
+        for (side, price) in self.touched.drain(..) {
+            let (levels, summaries) = match side {
+                Side::Bid => (book.bids(), &mut mbp.bids),
+                Side::Ask => (book.asks(), &mut mbp.asks),
+            };
+            match levels.get(&price) {
+                Some(level) => summaries.insert(price, OrderLevelSummary::from(level)),
+                None => summaries.remove(&price),
+            };
+        }
+    }
+
+    fn on_message_rejected(
+        &mut self,
+        message: &MarketByOrderMessage,
+        key: BookKey,
+        book: &OrderBook,
+    ) {
+        self.on_message_applied(message, key, book);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::orderbook::mbo::{Action, MarketByOrderMessage, MboProcessor};
-    use crate::orderbook::{Order, Side};
+    use crate::orderbook::{Order, SeedMode, Side};
     use time::{Duration, OffsetDateTime};
 
+    #[cfg(feature = "polars")]
+    use polars::prelude::NamedFrom;
+
     fn ts(s: &str) -> OffsetDateTime {
         use time::format_description::well_known::Rfc3339;
         OffsetDateTime::parse(s, &Rfc3339).unwrap()
@@ -151,6 +939,8 @@ mod tests {
             price,
             size,
             sequence: order_id as u32,
+            owner: None,
+            expires_at: None,
         }
     }
 
@@ -182,6 +972,48 @@ mod tests {
         assert_eq!(ask_summary.order_count, 2);
     }
 
+    #[test]
+    fn test_diff_turns_one_view_into_the_other() {
+        let mut a = OrderBook::new();
+        a.add_orders([
+            order(1, Side::Bid, 100, 10),
+            order(2, Side::Bid, 99, 5),
+            order(3, Side::Ask, 101, 7),
+        ]);
+        let mut b = OrderBook::new();
+        b.add_orders([
+            order(1, Side::Bid, 100, 10),
+            order(2, Side::Bid, 100, 5),
+            order(3, Side::Ask, 101, 7),
+            order(4, Side::Ask, 102, 1),
+        ]);
+        let (from, to) = (MarketByPrice::from(&a), MarketByPrice::from(&b));
+        assert!(from.diff(&MarketByPrice::from(&a)).is_empty());
+
+        let updates = from.diff(&to);
+        let summary = |update: &L2Update| (update.side, update.price, update.action);
+        assert_eq!(
+            updates.iter().map(summary).collect::<Vec<_>>(),
+            vec![
+                (Side::Bid, 99, L2Action::Delete),
+                (Side::Bid, 100, L2Action::Set),
+                (Side::Ask, 102, L2Action::Set),
+            ]
+        );
+        let mut patched = MarketByPrice::from(&a);
+        crate::orderbook::L2Batch {
+            instrument_id: 1,
+            publisher_id: 0,
+            sequence: 0,
+            event_time: OffsetDateTime::UNIX_EPOCH,
+            recv_time: OffsetDateTime::UNIX_EPOCH,
+            is_last: true,
+            updates,
+        }
+        .apply_to(&mut patched);
+        assert_eq!(patched, to);
+    }
+
     #[test]
     fn test_market_by_price_multiple_levels() {
         let mut book = OrderBook::new();
@@ -332,6 +1164,29 @@ mod tests {
         assert_eq!(top2[1].price, 9900);
     }
 
+    #[test]
+    fn test_top_n_into_matches_top_n() {
+        let mut book = OrderBook::new();
+        book.add_order(order(1, Side::Bid, 10000, 100));
+        book.add_order(order(2, Side::Bid, 9900, 200));
+        book.add_order(order(3, Side::Ask, 10100, 50));
+        let mbp = MarketByPrice::from(&book);
+
+        let mut out = mbp.top_n_bids(2);
+        for n in [0, 1, 2, 5] {
+            mbp.top_n_bids_into(n, &mut out);
+            assert_eq!(out, mbp.top_n_bids(n), "bids, n = {n}");
+            mbp.top_n_asks_into(n, &mut out);
+            assert_eq!(out, mbp.top_n_asks(n), "asks, n = {n}");
+        }
+        let bids: Vec<_> = mbp
+            .top_n_bids(5)
+            .iter()
+            .map(|l| (l.price, l.total_quantity))
+            .collect();
+        assert_eq!(bids, book.top_n_bids(5));
+    }
+
     #[test]
     fn test_top_n_asks_ordering() {
         let mut book = OrderBook::new();
@@ -429,22 +1284,265 @@ mod tests {
         assert_eq!(mbp.sequence, None);
     }
 
+    fn three_level_book() -> OrderBook {
+        let mut book = OrderBook::new();
+        book.add_order(order(1, Side::Bid, 99, 10));
+        book.add_order(order(2, Side::Bid, 100, 20));
+        book.add_order(order(3, Side::Bid, 100, 5));
+        book.add_order(order(4, Side::Bid, 98, 30));
+        book.add_order(order(5, Side::Ask, 101, 7));
+        book
+    }
+
+    #[test]
+    fn test_top_n_keeps_best_levels() {
+        let mut mbp = MarketByPrice::from(&three_level_book());
+        mbp.sequence = Some(7);
+
+        let top = mbp.top_n(2);
+        assert_eq!(top.bids.keys().copied().collect::<Vec<_>>(), vec![99, 100]);
+        assert_eq!(top.asks.len(), 1);
+        assert_eq!(top.sequence, Some(7));
+    }
+
+    #[cfg(feature = "polars")]
+    #[test]
+    fn test_to_dataframe() {
+        let df = MarketByPrice::from(&three_level_book())
+            .to_dataframe()
+            .unwrap();
+        assert_eq!(df.shape(), (4, 5));
+
+        let side: Vec<Option<&str>> = df
+            .column("side")
+            .unwrap()
+            .str()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(side, vec![Some("B"), Some("B"), Some("B"), Some("A")]);
+        let level: Vec<Option<u32>> = df
+            .column("level")
+            .unwrap()
+            .u32()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(level, vec![Some(0), Some(1), Some(2), Some(0)]);
+        let price: Vec<Option<i64>> = df
+            .column("price")
+            .unwrap()
+            .i64()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(price, vec![Some(100), Some(99), Some(98), Some(101)]);
+        let size: Vec<Option<u64>> = df
+            .column("size")
+            .unwrap()
+            .u64()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(size, vec![Some(25), Some(10), Some(30), Some(7)]);
+        let order_count: Vec<Option<u32>> = df
+            .column("order_count")
+            .unwrap()
+            .u32()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(order_count, vec![Some(2), Some(1), Some(1), Some(1)]);
+    }
+
+    #[cfg(feature = "polars")]
+    #[test]
+    fn test_dataframe_with_options() {
+        let mut book = OrderBook::new();
+        book.add_order(order(1, Side::Bid, 98, 30));
+        book.add_order(order(2, Side::Bid, 100, 10));
+        book.add_order(order(3, Side::Bid, 99, 20));
+        book.add_order(order(4, Side::Ask, 102, 5));
+        book.add_order(order(5, Side::Ask, 101, 7));
+        book.add_order(order(6, Side::Ask, 101, 3));
+        let mbp = MarketByPrice::from(&book);
+
+        // The defaults keep the schema of `to_dataframe`
+        let plain = mbp.to_dataframe_with(&MbpFrameOptions::new()).unwrap();
+        assert_eq!(plain, mbp.to_dataframe().unwrap());
+        assert_eq!(
+            plain.get_column_names(),
+            ["side", "level", "price", "size", "order_count"]
+        );
+
+        let options = MbpFrameOptions::new()
+            .with_ts_event(Some(ts("2024-03-01T14:30:00Z")))
+            .with_instrument_id(Some(7));
+        let df = mbp.to_dataframe_with(&options).unwrap();
+        assert_eq!(
+            df.get_column_names(),
+            [
+                "ts_event",
+                "instrument_id",
+                "side",
+                "level",
+                "price",
+                "size",
+                "order_count"
+            ]
+        );
+        let column = |name: &str| -> Vec<i64> {
+            let column = df
+                .column(name)
+                .unwrap()
+                .cast(&polars::prelude::DataType::Int64);
+            column.unwrap().i64().unwrap().into_no_null_iter().collect()
+        };
+        assert_eq!(column("level"), [0, 1, 2, 0, 1]);
+        assert_eq!(column("price"), [100, 99, 98, 101, 102]);
+        assert_eq!(column("size"), [10, 20, 30, 10, 5]);
+        assert_eq!(column("instrument_id"), [7; 5]);
+        assert_eq!(column("ts_event"), [1_709_303_400_000_000_000; 5]);
+
+        let shallow = mbp.to_dataframe_with(&options.with_depth(Some(1))).unwrap();
+        assert_eq!(shallow.height(), 2);
+        assert_eq!(
+            shallow.column("price").unwrap().i64().unwrap().get(1),
+            Some(101)
+        );
+    }
+
+    #[cfg(feature = "polars")]
+    #[test]
+    fn test_dataframe_round_trip() {
+        let mbp = depth_chart_book();
+        let df = mbp.to_dataframe().unwrap();
+        let read = MarketByPrice::from_dataframe(&df).unwrap();
+        assert_eq!(read.bids, mbp.bids);
+        assert_eq!(read.asks, mbp.asks);
+        assert_eq!(read, mbp);
+
+        // Long side names and i8 codes
+        let long = df!(
+            "side" => ["Bid", "Ask"],
+            "price" => [100i64, 101],
+            "size" => [10u64, 7],
+            "order_count" => [1u32, 2],
+        )
+        .unwrap();
+        let mut codes = long.clone();
+        codes
+            .replace("side", Series::new("side".into(), [b'B' as i8, b'A' as i8]))
+            .unwrap();
+        for df in [long, codes] {
+            let read = MarketByPrice::from_dataframe(&df).unwrap();
+            assert_eq!(read.best_bid(), Some((100, 10)));
+            assert_eq!(read.asks[&101].order_count, 2);
+        }
+
+        let missing = df.drop("order_count").unwrap();
+        let message = MarketByPrice::from_dataframe(&missing)
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("no 'order_count' column"), "{message}");
+    }
+
+    #[cfg(feature = "polars")]
+    #[test]
+    fn test_dataframe_by_time() {
+        let first = depth_chart_book();
+        let second = MarketByPrice::from(&three_level_book());
+        let frame = |mbp: &MarketByPrice, ns: i64| {
+            let ts_event = OffsetDateTime::from_unix_timestamp_nanos(i128::from(ns)).unwrap();
+            let options = MbpFrameOptions::new()
+                .with_ts_event(Some(ts_event))
+                .with_instrument_id(Some(1));
+            mbp.to_dataframe_with(&options).unwrap()
+        };
+        // Stacked out of time order
+        let mut df = frame(&second, 2_000);
+        df.vstack_mut(&frame(&first, 1_000)).unwrap();
+
+        let snapshots = MarketByPrice::from_dataframe_by_time(&df).unwrap();
+        assert_eq!(
+            snapshots.iter().map(|(ns, _)| *ns).collect::<Vec<_>>(),
+            [1_000, 2_000]
+        );
+        assert_eq!(snapshots[0].1.bids, first.bids);
+        assert_eq!(snapshots[1].1.asks, second.asks);
+        assert_eq!(
+            snapshots[1].1.event_time.unwrap().unix_timestamp_nanos(),
+            2_000
+        );
+
+        let mut mixed = df.clone();
+        mixed
+            .replace(
+                "instrument_id",
+                Series::new(
+                    "instrument_id".into(),
+                    vec![1u32, 1, 1, 1, 2, 2, 2, 2, 2, 2],
+                ),
+            )
+            .unwrap();
+        assert!(MarketByPrice::from_dataframe_by_time(&mixed).is_err());
+        assert!(MarketByPrice::from_dataframe_by_time(&first.to_dataframe().unwrap()).is_err());
+    }
+
+    #[cfg(feature = "polars")]
+    #[test]
+    fn test_truncated_dataframe_is_bounded() {
+        // 100 levels per side, two orders each
+        let mut book = OrderBook::new();
+        for level in 0..100 {
+            for (side, price) in [(Side::Bid, 1_000 - level), (Side::Ask, 1_001 + level)] {
+                book.add_order(order(price as u64 * 2, side, price, 10));
+                book.add_order(order(price as u64 * 2 + 1, side, price, 5));
+            }
+        }
+
+        let truncated = MarketByPrice::from_top_n(&book, 10);
+        assert_eq!(truncated, MarketByPrice::from(&book).top_n(10));
+        let df = truncated.to_dataframe().unwrap();
+        assert_eq!(df.height(), 20);
+
+        let price: Vec<Option<i64>> = df
+            .column("price")
+            .unwrap()
+            .i64()
+            .unwrap()
+            .into_iter()
+            .collect();
+        let expected_bids = (991..=1_000).rev();
+        let expected_asks = 1_001..=1_010;
+        assert_eq!(
+            price,
+            expected_bids
+                .chain(expected_asks)
+                .map(Some)
+                .collect::<Vec<_>>()
+        );
+        let size: Vec<Option<u64>> = df
+            .column("size")
+            .unwrap()
+            .u64()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert!(size.iter().all(|&size| size == Some(15)));
+    }
+
     #[test]
     fn test_mbp_snapshot_with_metadata() {
         let mut processor = MboProcessor::new();
 
         // Create message with known timestamps
         let msg = MarketByOrderMessage {
-            action: Action::Add,
-            side: Side::Bid,
-            price: 10000,
-            order_id: 1,
-            size: 100,
-            is_last: true,
             sequence: 42,
-            event_time: ts("2009-02-13T23:31:30Z"),
             recv_time: ts("2009-02-13T23:31:30.000050Z"), // +50µs latency
             ts_in_delta: Duration::microseconds(-10),
+            ..MarketByOrderMessage::test(Action::Add, Side::Bid, 10000, 1, 100)
+                .at(ts("2009-02-13T23:31:30Z"))
         };
         processor.process_message(&msg).unwrap();
 
@@ -469,31 +1567,21 @@ mod tests {
         // Process multiple messages
         processor
             .process_message(&MarketByOrderMessage {
-                action: Action::Add,
-                side: Side::Bid,
-                price: 100,
-                order_id: 1,
-                size: 50,
-                is_last: true,
                 sequence: 1,
-                event_time: OffsetDateTime::UNIX_EPOCH + Duration::nanoseconds(1000),
                 recv_time: OffsetDateTime::UNIX_EPOCH + Duration::nanoseconds(1050),
                 ts_in_delta: Duration::nanoseconds(-10),
+                ..MarketByOrderMessage::test(Action::Add, Side::Bid, 100, 1, 50)
+                    .at(OffsetDateTime::UNIX_EPOCH + Duration::nanoseconds(1000))
             })
             .unwrap();
 
         processor
             .process_message(&MarketByOrderMessage {
-                action: Action::Add,
-                side: Side::Bid,
-                price: 99,
-                order_id: 2,
-                size: 30,
-                is_last: true,
                 sequence: 2,
-                event_time: OffsetDateTime::UNIX_EPOCH + Duration::nanoseconds(2000),
                 recv_time: OffsetDateTime::UNIX_EPOCH + Duration::nanoseconds(2050),
                 ts_in_delta: Duration::nanoseconds(-10),
+                ..MarketByOrderMessage::test(Action::Add, Side::Bid, 99, 2, 30)
+                    .at(OffsetDateTime::UNIX_EPOCH + Duration::nanoseconds(2000))
             })
             .unwrap();
 
@@ -524,16 +1612,11 @@ mod tests {
         let mut msg =
             |action, order_id, side, price: i64, size: u32, is_last| -> MarketByOrderMessage {
                 let m = MarketByOrderMessage {
-                    action,
-                    side,
-                    price,
-                    order_id,
-                    size,
-                    is_last,
                     sequence: next_seq,
-                    event_time: t0,
                     recv_time: recv,
-                    ts_in_delta: Duration::ZERO,
+                    ..MarketByOrderMessage::test(action, side, price, order_id, size)
+                        .at(t0)
+                        .with_last(is_last)
                 };
                 next_seq += 1;
                 m
@@ -597,4 +1680,496 @@ mod tests {
         assert_eq!(asks[1].total_quantity, 60);
         assert_eq!(asks[1].order_count, 1);
     }
+
+    #[test]
+    fn test_depth_levels_skips_undefined_sides() {
+        let levels = [
+            BidAskPair {
+                bid_px: 100,
+                ask_px: 101,
+                bid_sz: 10,
+                ask_sz: 20,
+                bid_ct: 2,
+                ask_ct: 3,
+            },
+            BidAskPair {
+                bid_px: 99,
+                ask_px: UNDEF_PRICE,
+                bid_sz: 5,
+                ..Default::default()
+            },
+            BidAskPair {
+                bid_px: UNDEF_PRICE,
+                ask_px: UNDEF_PRICE,
+                ..Default::default()
+            },
+        ];
+
+        assert_eq!(
+            depth_levels(&levels),
+            vec![
+                (Side::Bid, 100, 10, 2),
+                (Side::Ask, 101, 20, 3),
+                (Side::Bid, 99, 5, 0),
+            ]
+        );
+    }
+
+    /// Replays generated messages for two instruments, with crossing Adds matched, ids
+    /// reused and the occasional Clear, comparing the incremental views to full rebuilds.
+    #[test]
+    fn test_incremental_mbp_matches_rebuild() {
+        use rand::{Rng, SeedableRng};
+        use rand_chacha::ChaCha8Rng;
+
+        use crate::orderbook::CrossingPolicy;
+
+        let mut rng = ChaCha8Rng::seed_from_u64(7);
+        let mut processor = MboProcessor::with_observer(IncrementalMbp::new())
+            .with_crossing_policy(CrossingPolicy::Match);
+        for sequence in 0..5_000u32 {
+            let side = if rng.random_bool(0.5) {
+                Side::Bid
+            } else {
+                Side::Ask
+            };
+            let action = match rng.random_range(0..100) {
+                0 => Action::Clear,
+                1..45 => Action::Add,
+                45..70 => Action::Cancel,
+                70..90 => Action::Modify,
+                90..95 => Action::Fill,
+                _ => Action::Trade,
+            };
+            let instrument_id = rng.random_range(1..=2);
+            let message = MarketByOrderMessage {
+                instrument_id,
+                sequence,
+                ..MarketByOrderMessage::test(
+                    action,
+                    side,
+                    rng.random_range(990..1010),
+                    rng.random_range(1..300),
+                    rng.random_range(1..50),
+                )
+            };
+            // Modifies of orders not in the book fail and change nothing
+            let _ = processor.process_message(&message);
+
+            if sequence % 50 == 0 {
+                for id in processor.instruments() {
+                    let expected = MarketByPrice::from(processor.book(id).unwrap());
+                    assert_eq!(
+                        processor.observer().mbp(id),
+                        Some(&expected),
+                        "instrument {id} after message {sequence}"
+                    );
+                }
+            }
+        }
+        for id in [1, 2] {
+            let expected = processor.mbp(id).unwrap();
+            assert!(expected.bids.len() + expected.asks.len() > 5);
+            assert_eq!(processor.observer().mbp(id), Some(&expected));
+        }
+        assert!(processor.stats().traded_volume > 0);
+    }
+
+    #[test]
+    fn test_incremental_mbp_sync_after_seed() {
+        let mut processor = MboProcessor::with_observer(IncrementalMbp::new());
+        processor.seed_book(
+            1,
+            &[(Side::Bid, 100, 50, 2), (Side::Ask, 101, 30, 1)],
+            SeedMode::Aggregate,
+        );
+        let book = processor.book(1).unwrap();
+        let mut observer = IncrementalMbp::new();
+        observer.sync(1, book);
+        *processor.observer_mut() = observer;
+
+        let message = MarketByOrderMessage {
+            sequence: 1,
+            ..MarketByOrderMessage::test(Action::Add, Side::Bid, 100, 1, 10)
+        };
+        processor.process_message(&message).unwrap();
+        let mbp = processor.observer().mbp(1).unwrap();
+        assert_eq!(mbp.bids[&100].total_quantity, 60);
+        assert_eq!(mbp.asks[&101].total_quantity, 30);
+        assert_eq!(mbp, &processor.mbp(1).unwrap());
+    }
+
+    #[test]
+    fn test_json_depth_golden() {
+        let mbp = MarketByPrice::from(&three_level_book());
+        let expected: Value =
+            serde_json::from_str(r#"{"bids": [[100, 25], [99, 10]], "asks": [[101, 7]]}"#).unwrap();
+        assert_eq!(mbp.to_json_depth(2, PriceScale::RAW), expected);
+
+        let scaled: Value = serde_json::from_str(
+            r#"{"bids": [[1.0, 25], [0.99, 10], [0.98, 30]], "asks": [[1.01, 7]]}"#,
+        )
+        .unwrap();
+        let cents = PriceScale::from_decimals(2).unwrap();
+        assert_eq!(mbp.to_json_depth(10, cents), scaled);
+
+        let empty: Value = serde_json::from_str(r#"{"bids": [], "asks": []}"#).unwrap();
+        assert_eq!(MarketByPrice::new().to_json_depth(5, cents), empty);
+    }
+
+    #[test]
+    fn test_json_depth_round_trip() {
+        let mbp = MarketByPrice::from(&three_level_book());
+        for price_scale in [
+            PriceScale::RAW,
+            PriceScale::DBN,
+            PriceScale::from_divisor(100).unwrap(),
+        ] {
+            let json = mbp.to_json_depth(10, price_scale);
+            let read = MarketByPrice::from_json_depth(&json, price_scale).unwrap();
+            let levels = |mbp: &MarketByPrice| -> Vec<(i64, u64)> {
+                mbp.bids
+                    .values()
+                    .chain(mbp.asks.values())
+                    .map(|level| (level.price, level.total_quantity))
+                    .collect()
+            };
+            assert_eq!(levels(&read), levels(&mbp));
+            assert!(read.bids.values().all(|level| level.order_count == 0));
+        }
+
+        let truncated =
+            MarketByPrice::from_json_depth(&mbp.to_json_depth(1, PriceScale::RAW), PriceScale::RAW)
+                .unwrap();
+        assert_eq!(truncated.bids.keys().collect::<Vec<_>>(), [&100]);
+
+        assert_eq!(
+            MarketByPrice::from_json_depth(&json!({"bids": []}), PriceScale::RAW),
+            Err(DepthJsonError::MissingSide("asks"))
+        );
+        assert_eq!(
+            MarketByPrice::from_json_depth(
+                &json!({"bids": [[100, 5], [99]], "asks": []}),
+                PriceScale::RAW
+            ),
+            Err(DepthJsonError::InvalidLevel {
+                side: "bids",
+                level: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_metrics_match_book() {
+        let book = three_level_book();
+        let mbp = MarketByPrice::from(&book);
+        assert_eq!(mbp.best_bid(), book.best_bid());
+        assert_eq!(mbp.best_ask(), book.best_ask());
+        assert_eq!(mbp.spread(), Some(1));
+        assert_eq!(mbp.mid(), Some(100.5));
+        // 25 bid against 7 ask at the top
+        assert_eq!(mbp.imbalance(1), Some(18.0 / 32.0));
+        assert_eq!(
+            mbp.weighted_mid(1),
+            Some((100.0 * 7.0 + 101.0 * 25.0) / 32.0)
+        );
+        for depth in 0..5 {
+            assert_eq!(mbp.imbalance(depth), book.imbalance(depth));
+            assert_eq!(mbp.weighted_mid(depth), book.weighted_mid(depth));
+        }
+        assert_eq!(mbp.imbalance(0), None);
+
+        // A snapshot read back without the book gives the same numbers
+        let read =
+            MarketByPrice::from_json_depth(&mbp.to_json_depth(3, PriceScale::RAW), PriceScale::RAW)
+                .unwrap();
+        assert_eq!(read.weighted_mid(3), book.weighted_mid(3));
+
+        let mut generated = OrderBook::new();
+        for order in crate::generators::OrderGenerator::default_seeded(3).make_orders(1_000) {
+            generated.add_order(order);
+        }
+        let mbp = MarketByPrice::from(&generated);
+        for depth in [1, 5, 10, 1_000] {
+            assert_eq!(mbp.imbalance(depth), generated.imbalance(depth));
+            assert_eq!(mbp.weighted_mid(depth), generated.weighted_mid(depth));
+        }
+        assert_eq!(mbp.mid(), generated.mid());
+
+        let mut one_sided = OrderBook::new();
+        one_sided.add_order(order(1, Side::Bid, 100, 10));
+        let mbp = MarketByPrice::from(&one_sided);
+        assert_eq!(mbp.best_ask(), None);
+        assert_eq!(mbp.spread(), None);
+        assert_eq!(mbp.mid(), None);
+        assert_eq!(mbp.imbalance(5), None);
+        assert_eq!(mbp.weighted_mid(5), one_sided.weighted_mid(5));
+
+        // Prices too far apart for their difference to fit
+        let mut extreme = OrderBook::new();
+        extreme.add_order(order(1, Side::Bid, i64::MIN, u64::from(u32::MAX)));
+        extreme.add_order(order(2, Side::Ask, i64::MAX - 1, 1));
+        assert_eq!(extreme.spread(), Some(i64::MAX));
+        assert_eq!(MarketByPrice::from(&extreme).spread(), Some(i64::MAX));
+        assert_eq!(extreme.mid(), Some(0.0));
+    }
+
+    /// Three levels per side: bids 100 x 10, 99 x 20, 97 x 5 and asks 101 x 7,
+    /// 102 x 3, 105 x 40.
+    fn depth_chart_book() -> MarketByPrice {
+        let mut book = OrderBook::new();
+        let levels = [
+            (Side::Bid, 100, 10),
+            (Side::Bid, 99, 20),
+            (Side::Bid, 97, 5),
+            (Side::Ask, 101, 7),
+            (Side::Ask, 102, 3),
+            (Side::Ask, 105, 40),
+        ];
+        for (order_id, (side, price, size)) in levels.into_iter().enumerate() {
+            book.add_order(order(order_id as u64 + 1, side, price, size));
+        }
+        MarketByPrice::from(&book)
+    }
+
+    #[test]
+    fn test_cumulative_depth() {
+        let mbp = depth_chart_book();
+        assert_eq!(
+            mbp.cumulative_depth(Side::Bid, 10),
+            vec![(100, 10), (99, 30), (97, 35)]
+        );
+        assert_eq!(
+            mbp.cumulative_depth(Side::Ask, 2),
+            vec![(101, 7), (102, 10)]
+        );
+        assert!(
+            MarketByPrice::new()
+                .cumulative_depth(Side::Ask, 10)
+                .is_empty()
+        );
+
+        let mut huge = MarketByPrice::new();
+        for price in [1, 2] {
+            huge.asks.insert(
+                price,
+                OrderLevelSummary {
+                    price,
+                    total_quantity: u64::MAX / 2 + 1,
+                    order_count: 1,
+                },
+            );
+        }
+        assert_eq!(huge.cumulative_depth(Side::Ask, 2)[1], (2, u64::MAX));
+    }
+
+    #[test]
+    fn test_depth_curve_matches_book() {
+        let mut book = OrderBook::new();
+        for (order_id, price) in (0..5).map(|i| (i + 1, 101 + i as i64)) {
+            book.add_order(order(order_id, Side::Ask, price, order_id * 10));
+        }
+        let mbp = MarketByPrice::from(&book);
+        assert_eq!(
+            mbp.depth_curve(Side::Ask, 5),
+            vec![(101, 10), (102, 30), (103, 60), (104, 100), (105, 150)]
+        );
+        assert_eq!(
+            mbp.depth_curve(Side::Ask, 3),
+            vec![(101, 10), (103, 60), (105, 150)]
+        );
+        for max_points in 0..7 {
+            assert_eq!(
+                mbp.depth_curve(Side::Ask, max_points),
+                book.depth_curve(Side::Ask, max_points)
+            );
+        }
+        assert_eq!(
+            mbp.notional_curve(Side::Ask, 3, 1),
+            book.notional_curve(Side::Ask, 3, 1)
+        );
+    }
+
+    #[cfg(feature = "polars")]
+    #[test]
+    fn test_depth_curves_to_dataframe() {
+        let df = depth_chart_book().depth_curves_to_dataframe(2).unwrap();
+        let side = df.column("side").unwrap().str().unwrap();
+        let price = df.column("price").unwrap().i64().unwrap();
+        let cum_qty = df.column("cum_qty").unwrap().u64().unwrap();
+        let rows: Vec<_> = (0..df.height())
+            .map(|i| {
+                (
+                    side.get(i).unwrap(),
+                    price.get(i).unwrap(),
+                    cum_qty.get(i).unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            vec![("B", 100, 10), ("B", 97, 35), ("A", 101, 7), ("A", 105, 50)]
+        );
+    }
+
+    #[test]
+    fn test_bucketize() {
+        let mut mbp = depth_chart_book();
+        mbp.sequence = Some(3);
+        let buckets = mbp.bucketize(5);
+        // Bids 100 | 99, 97 -> 100 | 95; asks 101, 102 | 105 -> 105 | 105
+        assert_eq!(
+            buckets.cumulative_depth(Side::Bid, 10),
+            vec![(100, 10), (95, 35)]
+        );
+        assert_eq!(buckets.cumulative_depth(Side::Ask, 10), vec![(105, 50)]);
+        assert_eq!(buckets.asks[&105].order_count, 3);
+        assert_eq!(buckets.sequence, Some(3));
+        assert_eq!(
+            mbp.bucketize(1),
+            MarketByPrice {
+                ..mbp.top_n(usize::MAX)
+            }
+        );
+    }
+
+    #[test]
+    fn test_bid_ask_pairs_match_mbp10_levels() {
+        // Three bid and three ask levels, so seven slots of each side are empty
+        let mut record = dbn::Mbp10Msg {
+            levels: array::from_fn(|_| BidAskPair {
+                bid_px: UNDEF_PRICE,
+                ask_px: UNDEF_PRICE,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        for (slot, (bid_px, bid_sz, ask_px, ask_sz)) in
+            [(100, 10, 101, 7), (99, 20, 102, 3), (97, 5, 105, 40)]
+                .into_iter()
+                .enumerate()
+        {
+            record.levels[slot] = BidAskPair {
+                bid_px,
+                ask_px,
+                bid_sz,
+                ask_sz,
+                bid_ct: 1,
+                ask_ct: 1,
+            };
+        }
+
+        let mbp = depth_chart_book();
+        assert_eq!(mbp.to_bid_ask_pairs::<10>(), record.levels);
+        assert_eq!(MarketByPrice::from_bid_ask_pairs(&record.levels), mbp);
+
+        let [top] = mbp.to_bid_ask_pairs::<1>();
+        assert_eq!((top.bid_px, top.ask_px, top.ask_sz), (100, 101, 7));
+        let [empty] = MarketByPrice::new().to_bid_ask_pairs::<1>();
+        assert_eq!(
+            (empty.bid_px, empty.bid_sz, empty.ask_ct),
+            (UNDEF_PRICE, 0, 0)
+        );
+    }
+
+    #[test]
+    fn test_render_golden() {
+        let mut mbp = depth_chart_book();
+        mbp.bids.get_mut(&99).unwrap().order_count = 12;
+        let expected = "\
+Orders  Bid Qty  Price  Ask Qty  Orders
+---------------------------------------
+                  1.05       40       1
+                  1.02        3       1
+                  1.01        7       1
+     1       10   1.00
+    12       20   0.99
+     1        5   0.97
+";
+        assert_eq!(
+            mbp.render(10, PriceScale::from_decimals(2).unwrap()),
+            expected
+        );
+
+        // Uneven depth: one ask, two bids
+        let expected = "\
+Orders  Bid Qty        Price  Ask Qty  Orders
+---------------------------------------------
+                 0.000000101        7       1
+     1       10  0.000000100
+    12       20  0.000000099
+";
+        let mut uneven = mbp.top_n(2);
+        uneven.asks.pop_last();
+        assert_eq!(uneven.render(2, PriceScale::DBN), expected);
+    }
+
+    /// Bids 4550.25 x 3 and 4550.00 x 10, ask 4550.50 x 1_000_000, in dbn prices.
+    fn dbn_scale_book() -> MarketByPrice {
+        let mut book = OrderBook::new();
+        book.add_order(order(1, Side::Bid, 4_550_250_000_000, 3));
+        book.add_order(order(2, Side::Bid, 4_550_000_000_000, 10));
+        book.add_order(order(3, Side::Ask, 4_550_500_000_000, 1_000_000));
+        MarketByPrice::from(&book)
+    }
+
+    #[test]
+    fn test_render_dbn_prices() {
+        let expected = "\
+Orders  Bid Qty           Price  Ask Qty  Orders
+------------------------------------------------
+                 4550.500000000  1000000       1
+     1        3  4550.250000000
+     1       10  4550.000000000
+";
+        assert_eq!(dbn_scale_book().render(5, PriceScale::DBN), expected);
+        // Without a known scale the integers are shown as they are
+        let raw = dbn_scale_book().render(1, PriceScale::RAW);
+        assert!(raw.contains("  4550500000000  "), "{raw}");
+    }
+
+    #[test]
+    fn test_notional() {
+        let mbp = dbn_scale_book();
+        let scale = dbn::FIXED_PRICE_SCALE;
+        assert!((mbp.bids[&4_550_250_000_000].notional(scale) - 13_650.75).abs() < 1e-6);
+        assert!((mbp.total_notional(Side::Bid, 10, scale) - 59_150.75).abs() < 1e-6);
+        assert!((mbp.total_notional(Side::Bid, 1, scale) - 13_650.75).abs() < 1e-6);
+        // 4.55e18 fixed-point units would overflow i64
+        assert!((mbp.total_notional(Side::Ask, 10, scale) - 4_550_500_000.0).abs() < 1e-3);
+        assert_eq!(
+            MarketByPrice::new().total_notional(Side::Ask, 10, scale),
+            0.0
+        );
+    }
+
+    #[cfg(feature = "polars")]
+    #[test]
+    fn test_notional_column() {
+        let mbp = dbn_scale_book();
+        assert!(mbp.to_dataframe().unwrap().column("notional").is_err());
+
+        let options = MbpFrameOptions::new().with_price_scale(Some(PriceScale::DBN));
+        let df = mbp.to_dataframe_with(&options).unwrap();
+        let notional: Vec<f64> = df
+            .column("notional")
+            .unwrap()
+            .f64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        let expected = [13_650.75, 45_500.0, 4_550_500_000.0];
+        let price_display = df.column("price_display").unwrap().f64().unwrap();
+        assert_eq!(
+            price_display.into_no_null_iter().collect::<Vec<_>>(),
+            [4_550.25, 4_550.0, 4_550.5]
+        );
+        assert!(
+            notional
+                .iter()
+                .zip(expected)
+                .all(|(notional, expected)| (notional - expected).abs() < 1e-3),
+            "{notional:?}"
+        );
+    }
 }
@@ -0,0 +1,231 @@
+//! Liquidity metrics of a book: how much quantity rests near the touch and how far
+//! executing against it moves the price.
+//!
+//! Prices are in the book's integer units, so "per tick" means per price unit for a
+//! book kept in ticks. Each metric is a free function over an `OrderBook` and a method
+//! of the same name on `MarketByPrice`, giving identical results. Metrics that a book
+//! too thin to measure cannot define are `None`, never NaN.
+
+use crate::orderbook::book::OrderLevel;
+use crate::orderbook::{MarketByPrice, OrderBook, OrderLevelSummary, Side};
+
+/// Slope of the cumulative depth curve over the first `levels` levels of `side`, in
+/// quantity per tick: with `C_k` the quantity of level `k` and all better ones and
+/// `p_k` its price,
+///
+/// `(C_{n-1} - C_0) / |p_{n-1} - p_0|`
+///
+/// where `n` is `levels`, or the number of levels on `side` if fewer. `None` unless
+/// `n` is at least 2.
+pub fn book_slope(book: &OrderBook, side: Side, levels: usize) -> Option<f64> {
+    slope(book_levels(book, side), levels)
+}
+
+/// Quantity on both sides within `ticks` of the mid, inclusive: the sum of the levels
+/// with `|p - (best_bid + best_ask) / 2| <= ticks`. `None` without a mid.
+pub fn depth_within(book: &OrderBook, ticks: u32) -> Option<u64> {
+    within(
+        book_levels(book, Side::Bid),
+        book_levels(book, Side::Ask),
+        ticks,
+    )
+}
+
+/// Price impact coefficient `λ` of executing `qty` against `side`, such that executing
+/// `Q` units moves the price by about `λ·Q` ticks. `side` is the side consumed, so
+/// `Side::Ask` for a buy.
+///
+/// The sweep of `qty` through `side` from the touch gives a point per level it reaches:
+/// `c_k`, the quantity executed up to and including level `k` (`qty` at the last), and
+/// `d_k = |p_k - p_0|`, how far that level is from the touch. `λ` is their least
+/// squares fit through the origin,
+///
+/// `λ = Σ c_k·d_k / Σ c_k²`
+///
+/// so 0 if `qty` fills at the touch. `None` if `qty` is 0 or more than `side` holds.
+pub fn price_impact(book: &OrderBook, side: Side, qty: u64) -> Option<f64> {
+    impact(book_levels(book, side), qty)
+}
+
+impl MarketByPrice {
+    /// Slope of the cumulative depth curve near the touch, as `metrics::book_slope`.
+    pub fn book_slope(&self, side: Side, levels: usize) -> Option<f64> {
+        slope(mbp_levels(self, side), levels)
+    }
+
+    /// Quantity within `ticks` of the mid, as `metrics::depth_within`.
+    pub fn depth_within(&self, ticks: u32) -> Option<u64> {
+        within(
+            mbp_levels(self, Side::Bid),
+            mbp_levels(self, Side::Ask),
+            ticks,
+        )
+    }
+
+    /// Price impact coefficient of executing `qty` against `side`, as
+    /// `metrics::price_impact`.
+    pub fn price_impact(&self, side: Side, qty: u64) -> Option<f64> {
+        impact(mbp_levels(self, side), qty)
+    }
+}
+
+/// `(price, quantity)` of the levels of `side`, best first.
+fn book_levels(book: &OrderBook, side: Side) -> Box<dyn Iterator<Item = (i64, u64)> + '_> {
+    let level = |(&price, level): (&i64, &OrderLevel)| (price, level.total_qty());
+    match side {
+        Side::Bid => Box::new(book.bids().iter().rev().map(level)),
+        Side::Ask => Box::new(book.asks().iter().map(level)),
+    }
+}
+
+fn mbp_levels(mbp: &MarketByPrice, side: Side) -> Box<dyn Iterator<Item = (i64, u64)> + '_> {
+    let level = |level: &OrderLevelSummary| (level.price, level.total_quantity);
+    match side {
+        Side::Bid => Box::new(mbp.bids.values().rev().map(level)),
+        Side::Ask => Box::new(mbp.asks.values().map(level)),
+    }
+}
+
+fn slope(levels: impl Iterator<Item = (i64, u64)>, max_levels: usize) -> Option<f64> {
+    let mut levels = levels.take(max_levels);
+    let (touch, _) = levels.next()?;
+    let (last, beyond_touch) = levels.fold((None, 0u64), |(_, qty), (price, level_qty)| {
+        (Some(price), qty.saturating_add(level_qty))
+    });
+    let distance = last?.abs_diff(touch);
+    (distance > 0).then(|| beyond_touch as f64 / distance as f64)
+}
+
+fn within(
+    bids: impl Iterator<Item = (i64, u64)>,
+    asks: impl Iterator<Item = (i64, u64)>,
+    ticks: u32,
+) -> Option<u64> {
+    let (mut bids, mut asks) = (bids.peekable(), asks.peekable());
+    let twice_mid = i128::from(bids.peek()?.0) + i128::from(asks.peek()?.0);
+    let reach = 2 * i128::from(ticks);
+    // Exact in twice the price, as the mid may fall between two prices
+    let near = |&(price, _): &(i64, u64)| (2 * i128::from(price) - twice_mid).abs() <= reach;
+    Some(
+        bids.take_while(near)
+            .chain(asks.take_while(near))
+            .fold(0u64, |total, (_, qty)| total.saturating_add(qty)),
+    )
+}
+
+fn impact(levels: impl Iterator<Item = (i64, u64)>, qty: u64) -> Option<f64> {
+    if qty == 0 {
+        return None;
+    }
+    let mut levels = levels.peekable();
+    let (touch, _) = *levels.peek()?;
+    let (mut executed, mut cross, mut square) = (0u64, 0.0, 0.0);
+    for (price, level_qty) in levels {
+        executed += level_qty.min(qty - executed);
+        let (c, d) = (executed as f64, price.abs_diff(touch) as f64);
+        cross += c * d;
+        square += c * c;
+        if executed == qty {
+            return Some(cross / square);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::Order;
+
+    /// Bids of 5 at 99, 15 at 98 and 40 at 95; asks of 10 at 101, 20 at 102 and 30
+    /// at 104. The mid is 100.
+    fn book() -> OrderBook {
+        let mut book = OrderBook::new();
+        let levels = [
+            (Side::Bid, 99, 5),
+            (Side::Bid, 98, 15),
+            (Side::Bid, 95, 40),
+            (Side::Ask, 101, 10),
+            (Side::Ask, 102, 20),
+            (Side::Ask, 104, 30),
+        ];
+        for (order_id, (side, price, size)) in (1..).zip(levels) {
+            book.add_order(Order {
+                order_id,
+                side,
+                price,
+                size,
+                sequence: order_id as u32,
+                owner: None,
+                expires_at: None,
+            });
+        }
+        book
+    }
+
+    #[test]
+    fn test_book_slope() {
+        let book = book();
+        // (60 - 10) / (104 - 101) and (30 - 10) / (102 - 101)
+        assert_eq!(book_slope(&book, Side::Ask, 3), Some(50.0 / 3.0));
+        assert_eq!(book_slope(&book, Side::Ask, 2), Some(20.0));
+        assert_eq!(book_slope(&book, Side::Ask, 10), Some(50.0 / 3.0));
+        // (60 - 5) / (99 - 95)
+        assert_eq!(book_slope(&book, Side::Bid, 3), Some(13.75));
+        assert_eq!(book_slope(&book, Side::Bid, 1), None);
+        assert_eq!(book_slope(&OrderBook::new(), Side::Bid, 3), None);
+    }
+
+    #[test]
+    fn test_depth_within() {
+        let mut book = book();
+        assert_eq!(depth_within(&book, 0), Some(0));
+        // 99 and 101, then also 98 and 102
+        assert_eq!(depth_within(&book, 1), Some(15));
+        assert_eq!(depth_within(&book, 2), Some(50));
+        assert_eq!(depth_within(&book, 5), Some(120));
+
+        // A mid of 100.5: 99 and 102 are 1.5 away, 98 is 2.5
+        book.remove_order(4);
+        assert_eq!(depth_within(&book, 1), Some(0));
+        assert_eq!(depth_within(&book, 2), Some(25));
+
+        book.remove_order(1);
+        book.remove_order(2);
+        book.remove_order(3);
+        assert_eq!(depth_within(&book, 5), None);
+    }
+
+    #[test]
+    fn test_price_impact() {
+        let book = book();
+        // Fills at the touch
+        assert_eq!(price_impact(&book, Side::Ask, 10), Some(0.0));
+        // Points (10, 0) and (25, 1): 25 / (100 + 625)
+        assert_eq!(price_impact(&book, Side::Ask, 25), Some(25.0 / 725.0));
+        // Points (10, 0), (30, 1) and (60, 3): 210 / 4600
+        assert_eq!(price_impact(&book, Side::Ask, 60), Some(210.0 / 4600.0));
+        // Points (5, 0) and (20, 1): 20 / 425
+        assert_eq!(price_impact(&book, Side::Bid, 20), Some(20.0 / 425.0));
+        assert_eq!(price_impact(&book, Side::Ask, 61), None);
+        assert_eq!(price_impact(&book, Side::Ask, 0), None);
+        assert_eq!(price_impact(&OrderBook::new(), Side::Ask, 1), None);
+    }
+
+    #[test]
+    fn test_market_by_price_matches_book() {
+        let book = book();
+        let mbp = MarketByPrice::from(&book);
+        for side in [Side::Bid, Side::Ask] {
+            for n in 0..5 {
+                assert_eq!(mbp.book_slope(side, n), book_slope(&book, side, n));
+            }
+            for qty in [0, 1, 10, 30, 60, 61] {
+                assert_eq!(mbp.price_impact(side, qty), price_impact(&book, side, qty));
+            }
+        }
+        for ticks in 0..6 {
+            assert_eq!(mbp.depth_within(ticks), depth_within(&book, ticks));
+        }
+    }
+}
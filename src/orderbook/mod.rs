@@ -1,13 +1,156 @@
+pub mod anomaly;
+pub mod audit;
+pub mod bars;
+pub mod bbo;
 pub mod book;
+#[cfg(feature = "async")]
+pub mod channel;
+pub mod checkpoint;
+pub mod consistency;
+pub mod conversion;
+pub mod csvread;
+#[cfg(feature = "polars")]
+pub mod dataframe;
+pub mod dbnstream;
+pub mod dbnwrite;
+pub mod dense;
+pub mod depth;
+pub mod drive;
+pub mod engine;
 pub mod events;
+pub mod expiry;
+#[cfg(feature = "databento")]
+pub mod fetch;
+pub mod files;
+pub mod flow;
+pub mod format;
+pub mod handler;
+pub mod history;
+pub mod iceberg;
+pub mod input;
+#[cfg(feature = "itch")]
+pub mod itch;
+pub mod journal;
+pub mod l2;
 pub mod mbo;
 pub mod mbp;
+pub mod metrics;
+pub mod names;
+pub mod ndjson;
+#[cfg(feature = "parallel")]
+pub mod parallel;
+#[cfg(feature = "polars")]
+pub mod parquet;
+pub mod passive;
+pub mod periodic;
+pub mod pipeline;
+pub mod playback;
+pub mod pricescale;
+pub mod progress;
+pub mod queue;
+pub mod quotes;
+pub mod remap;
+pub mod replay;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "polars")]
+pub mod snapshot;
+pub mod source;
+pub mod stats;
+pub mod summary;
+pub mod symbology;
+pub mod timerange;
+pub mod tob;
+pub mod tradequality;
 pub mod tradestream;
+pub mod validation;
 
+pub use anomaly::{AnomalyKind, AnomalyRecord};
+pub use audit::{BookChange, BookEvent, EventRetention};
+pub use bars::{Bar, BarBuilder, BarError};
+pub use bbo::{BboRecorder, Mbp1Row, Mbp1Tracker};
 pub use book::{
-    AddOrderInfo, ModifyOrderInfo, Order, OrderBook, OrderBookError, RemoveOrderInfo, Side,
+    AddOrderInfo, Anomalies, Bbo, DepthLimitPolicy, Execution, MatchOutcome, ModifyOrderInfo,
+    ModifyPriorityPolicy, Order, OrderBook, OrderBookError, ParticipantSide, ParticipantStats,
+    RemoveOrderInfo, SYNTHETIC_ORDER_ID_MIN, SeedMode, SelfMatchPolicy, SelfMatchReport, Side,
+    is_synthetic_order_id,
 };
+#[cfg(feature = "async")]
+pub use channel::BboUpdate;
+pub use checkpoint::CheckpointError;
+pub use consistency::ConsistencyError;
+pub use conversion::{ConversionError, ConversionReport};
+pub use csvread::{
+    CharEncoding, CsvOptions, CsvReadError, CsvRows, iter_mbo_csv_from, read_mbo_csv,
+    read_mbo_csv_from, write_mbo_csv,
+};
+#[cfg(feature = "polars")]
+pub use dataframe::{
+    ColumnMapping, ColumnMappingError, MboRows, filter_instrument, into_mbo_messages,
+    into_mbo_messages_with, iter_mbo_messages, iter_mbo_messages_with, mbo_messages_to_dataframe,
+};
+pub use dbnstream::{BytesRead, CountingReader, DbnStreamError, MboMessages, mbo_messages};
+pub use dbnwrite::{DbnWriteError, DbnWriter, mbo_metadata, write_dbn};
+pub use dense::{DenseBookError, DenseOrderBook, MAX_DENSE_LEVELS};
+pub use depth::{DEPTH_BUCKETS, DepthBucketSummary, DepthStats, DepthSummary, DepthTracker};
+pub use drive::ProcessEvent;
+pub use engine::{ExecutionReport, NewOrder, TimeInForce};
 pub use events::{OrderAddedEvent, OrderCancelledEvent, OrderModifiedEvent, TradeEvent};
-pub use mbo::{Action, MarketByOrderMessage, MboObserver, MboProcessError, MboProcessor};
-pub use mbp::{MarketByPrice, OrderLevelSummary};
+#[cfg(feature = "databento")]
+pub use fetch::{API_KEY_ENV, FetchError, FetchRequest};
+pub use files::{create_output, expand_paths, first_event_time, sort_by_first_event};
+pub use flow::FlowStats;
+pub use format::{
+    Compression, DataFormat, FormatError, InputFormat, decompress, detect_format,
+    format_from_suffix, resolve_format, sniff_compression,
+};
+pub use handler::BookHandler;
+pub use history::{BboHistory, BboSample, SpreadSummary};
+pub use iceberg::{IcebergDetector, IcebergSignal};
+pub use input::{
+    InputError, InputSource, ReaderProcessSummary, open_input, process_reader,
+    process_reader_with_progress,
+};
+#[cfg(feature = "itch")]
+pub use itch::{ItchError, ItchMessages, itch_messages};
+pub use journal::RollbackError;
+pub use l2::{L2Action, L2Batch, L2Publisher, L2Sink, L2Update};
+pub use mbo::{
+    Action, BookKey, CrossingPolicy, ErrorPolicy, MarketByOrderMessage, MboObserver,
+    MboProcessError, MboProcessor, side_from_char,
+};
+#[cfg(feature = "polars")]
+pub use mbp::MbpFrameOptions;
+pub use mbp::{DepthJsonError, IncrementalMbp, MarketByPrice, OrderLevelSummary, depth_levels};
+pub use names::ParseNameError;
+pub use ndjson::{
+    NdjsonLines, iter_mbo_ndjson_from, read_mbo_ndjson, read_mbo_ndjson_from, write_mbo_ndjson,
+};
+#[cfg(feature = "parallel")]
+pub use parallel::ParallelError;
+#[cfg(feature = "polars")]
+pub use parquet::{ParquetBatches, ParquetProcessSummary, process_parquet_streaming};
+pub use passive::{PassiveFillSimulator, PassiveOrder, PassiveOrderState, QueueModel};
+pub use periodic::{IntervalError, SnapshotInterval};
+pub use pipeline::{PipelineError, PipelineOptions};
+pub use playback::{
+    MAX_PLAYBACK_SPEED, MIN_PLAYBACK_SPEED, Playback, PlaybackCommand, PlaybackStep,
+};
+pub use pricescale::{PriceScale, PriceScaleError};
+pub use progress::{DEFAULT_PROGRESS_INTERVAL, ProgressSink, WithProgress};
+pub use quotes::{QuoteStats, QuoteSummary};
+pub use remap::IdRemapper;
+pub use replay::{ReplayError, Replayer};
+#[cfg(feature = "server")]
+pub use server::{BookFeed, BookServer, ServerOptions};
+#[cfg(feature = "polars")]
+pub use snapshot::{SnapshotWriter, TimeSampler};
+pub use source::RecordSourceError;
+pub use stats::MboStats;
+pub use summary::{ActionCounts, ProcessFailure, ProcessSummary};
+pub use symbology::{SymbolMap, SymbolMapError};
+pub use timerange::{TimeRange, TimeRangeError, WindowCounts, filter_time_range, parse_time_ns};
+pub use tob::{TopOfBookError, TopOfBookRecorder, TopOfBookWriter};
+pub use tradequality::{TradeClass, TradeQuality, TradeQualityCounts, TradeRecord};
 pub use tradestream::TradeCollector;
+pub use validation::{ValidationError, ValidationPolicy, validate};
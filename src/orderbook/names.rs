@@ -0,0 +1,210 @@
+//! Names of `Action` and `Side` for text and serde formats.
+//!
+//! Both parse from their names (`"Add"`, `"bid"`, in any case) or dbn characters
+//! (`"A"` for Add, `"B"` for Bid) and display as their names. With serde they are
+//! written as names to human-readable formats such as JSON and YAML, which also accept
+//! the numeric discriminants, and as their `i8` discriminants to binary formats such
+//! as the bincode of checkpoints.
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+use num_enum::TryFromPrimitive;
+use serde::de::{self, Unexpected, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+use crate::orderbook::{Action, Side};
+
+/// A name that is not an `Action` or `Side`.
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ParseNameError {
+    #[error("Unknown action '{0}', expected Add, Cancel, Modify, Fill, Clear or Trade")]
+    Action(String),
+
+    #[error("Unknown side '{0}', expected Bid or Ask")]
+    Side(String),
+}
+
+impl FromStr for Action {
+    type Err = ParseNameError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "add" | "a" => Ok(Action::Add),
+            "cancel" | "c" => Ok(Action::Cancel),
+            "modify" | "m" => Ok(Action::Modify),
+            "fill" | "f" => Ok(Action::Fill),
+            "clear" | "r" => Ok(Action::Clear),
+            "trade" | "t" => Ok(Action::Trade),
+            _ => Err(ParseNameError::Action(name.to_owned())),
+        }
+    }
+}
+
+impl FromStr for Side {
+    type Err = ParseNameError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "bid" | "b" => Ok(Side::Bid),
+            "ask" | "a" => Ok(Side::Ask),
+            _ => Err(ParseNameError::Side(name.to_owned())),
+        }
+    }
+}
+
+/// Writes `value` as its name to human-readable formats and as its discriminant to
+/// others.
+fn serialize_named<T, S>(value: T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: fmt::Display + Into<i8>,
+    S: Serializer,
+{
+    match serializer.is_human_readable() {
+        true => serializer.collect_str(&value),
+        false => serializer.serialize_i8(value.into()),
+    }
+}
+
+/// Reads a name or a discriminant, as `serialize_named` writes them.
+fn deserialize_named<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: FromStr<Err = ParseNameError> + TryFromPrimitive<Primitive = i8>,
+    D: Deserializer<'de>,
+{
+    match deserializer.is_human_readable() {
+        true => deserializer.deserialize_any(NamedVisitor(PhantomData)),
+        false => deserializer.deserialize_i8(NamedVisitor(PhantomData)),
+    }
+}
+
+struct NamedVisitor<T>(PhantomData<T>);
+
+impl<T> Visitor<'_> for NamedVisitor<T>
+where
+    T: FromStr<Err = ParseNameError> + TryFromPrimitive<Primitive = i8>,
+{
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a name or a numeric discriminant")
+    }
+
+    fn visit_str<E: de::Error>(self, name: &str) -> Result<T, E> {
+        name.parse().map_err(E::custom)
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<T, E> {
+        i8::try_from(value)
+            .ok()
+            .and_then(|value| T::try_from_primitive(value).ok())
+            .ok_or_else(|| E::invalid_value(Unexpected::Signed(value), &self))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<T, E> {
+        i8::try_from(value)
+            .ok()
+            .and_then(|value| T::try_from_primitive(value).ok())
+            .ok_or_else(|| E::invalid_value(Unexpected::Unsigned(value), &self))
+    }
+}
+
+impl Serialize for Action {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_named(*self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Action {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_named(deserializer)
+    }
+}
+
+impl Serialize for Side {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_named(*self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Side {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_named(deserializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ACTIONS: [(Action, &str); 6] = [
+        (Action::Add, "A"),
+        (Action::Cancel, "C"),
+        (Action::Modify, "M"),
+        (Action::Fill, "F"),
+        (Action::Clear, "R"),
+        (Action::Trade, "T"),
+    ];
+
+    #[test]
+    fn test_actions_round_trip() {
+        for (action, character) in ACTIONS {
+            let json = serde_json::to_string(&action).unwrap();
+            assert_eq!(json, format!("\"{action}\""));
+            assert_eq!(serde_json::from_str::<Action>(&json).unwrap(), action);
+            let discriminant = i8::from(action).to_string();
+            assert_eq!(
+                serde_json::from_str::<Action>(&discriminant).unwrap(),
+                action
+            );
+            let binary = bincode::serialize(&action).unwrap();
+            assert_eq!(binary, [i8::from(action) as u8]);
+            assert_eq!(bincode::deserialize::<Action>(&binary).unwrap(), action);
+
+            let name = action.to_string();
+            assert_eq!(name.parse::<Action>().unwrap(), action);
+            assert_eq!(name.to_uppercase().parse::<Action>().unwrap(), action);
+            assert_eq!(character.parse::<Action>().unwrap(), action);
+        }
+    }
+
+    #[test]
+    fn test_sides_round_trip() {
+        for (side, name, character) in [(Side::Bid, "Bid", "B"), (Side::Ask, "Ask", "A")] {
+            assert_eq!(side.to_string(), name);
+            let json = serde_json::to_string(&side).unwrap();
+            assert_eq!(json, format!("\"{name}\""));
+            assert_eq!(serde_json::from_str::<Side>(&json).unwrap(), side);
+            let discriminant = i8::from(side).to_string();
+            assert_eq!(serde_json::from_str::<Side>(&discriminant).unwrap(), side);
+            let binary = bincode::serialize(&side).unwrap();
+            assert_eq!(bincode::deserialize::<Side>(&binary).unwrap(), side);
+
+            assert_eq!(name.to_lowercase().parse::<Side>().unwrap(), side);
+            assert_eq!(character.parse::<Side>().unwrap(), side);
+        }
+    }
+
+    #[test]
+    fn test_unknown_names_are_named() {
+        assert_eq!(
+            "Buy".parse::<Side>().unwrap_err().to_string(),
+            "Unknown side 'Buy', expected Bid or Ask"
+        );
+        assert_eq!(
+            "Replace".parse::<Action>().unwrap_err(),
+            ParseNameError::Action("Replace".to_owned())
+        );
+        let error = serde_json::from_str::<Action>("\"Replace\"").unwrap_err();
+        assert!(error.to_string().contains("'Replace'"), "{error}");
+        let error = serde_json::from_str::<Side>("\"N\"").unwrap_err();
+        assert!(error.to_string().contains("'N'"), "{error}");
+        let error = serde_json::from_str::<Side>("3").unwrap_err();
+        assert!(
+            error.to_string().contains("invalid value: integer `3`"),
+            "{error}"
+        );
+    }
+}
@@ -0,0 +1,281 @@
+//! Reading and writing MBO messages as newline-delimited JSON, one object per line:
+//!
+//! ```text
+//! {"action":"A","side":"B","price":100,"order_id":1,"size":10,"ts_event":1000}
+//! ```
+//!
+//! Fields follow `into_mbo_messages`. `action` and `side` may be dbn characters
+//! (`"A"`) or character codes (`65`).
+
+use std::borrow::Borrow;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Lines, Write};
+use std::iter::Enumerate;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::orderbook::conversion::MboRow;
+use crate::orderbook::{ConversionError, ConversionReport, MarketByOrderMessage};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum CharField {
+    Code(i8),
+    Text(String),
+}
+
+impl CharField {
+    fn code(self, column: &'static str) -> Result<i8, ConversionError> {
+        match self {
+            CharField::Code(code) => Ok(code),
+            CharField::Text(text) => match text.as_bytes() {
+                &[c] if c.is_ascii() => Ok(c as i8),
+                _ => Err(ConversionError::InvalidCharacter {
+                    column,
+                    value: text,
+                }),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Line {
+    action: CharField,
+    side: CharField,
+    price: i64,
+    order_id: u64,
+    size: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instrument_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ts_event: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ts_recv: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ts_in_delta: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sequence: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    flags: Option<u8>,
+}
+
+impl Line {
+    fn into_message(self) -> Result<MarketByOrderMessage, ConversionError> {
+        let ts_event = self.ts_event.unwrap_or_default();
+        MboRow {
+            action: self.action.code("action")?,
+            side: self.side.code("side")?,
+            price: self.price,
+            order_id: self.order_id,
+            size: self.size,
+            instrument_id: self.instrument_id.unwrap_or_default(),
+            ts_event,
+            ts_recv: self.ts_recv.unwrap_or(ts_event),
+            ts_in_delta: self.ts_in_delta.unwrap_or_default(),
+            sequence: self.sequence.unwrap_or_default(),
+            flags: self.flags.unwrap_or(dbn::flags::LAST),
+            publisher_id: 0,
+            channel_id: 0,
+        }
+        .into_message()
+    }
+}
+
+impl From<&MarketByOrderMessage> for Line {
+    fn from(message: &MarketByOrderMessage) -> Self {
+        Self {
+            action: CharField::Text(message.action_char().to_string()),
+            side: CharField::Text(message.side_char().to_string()),
+            price: message.price,
+            order_id: message.order_id,
+            size: message.size,
+            instrument_id: Some(message.instrument_id),
+            ts_event: Some(message.event_time.unix_timestamp_nanos() as u64),
+            ts_recv: Some(message.recv_time.unix_timestamp_nanos() as u64),
+            ts_in_delta: Some(message.ts_in_delta.whole_nanoseconds() as i32),
+            sequence: Some(message.sequence),
+            flags: Some(message.flags),
+        }
+    }
+}
+
+/// Reads an NDJSON file of MBO messages.
+///
+/// Lines that do not parse or fail conversion are reported in
+/// `ConversionReport::rejected` with their 0-based line index. Blank lines are ignored.
+pub fn read_mbo_ndjson(path: impl AsRef<Path>) -> io::Result<ConversionReport> {
+    read_mbo_ndjson_from(BufReader::new(File::open(path)?))
+}
+
+/// Like `read_mbo_ndjson`, reading from `reader`.
+pub fn read_mbo_ndjson_from<R: BufRead>(reader: R) -> io::Result<ConversionReport> {
+    let mut report = ConversionReport::default();
+    for line in iter_mbo_ndjson_from(reader) {
+        let (index, converted) = line?;
+        report.push(index, converted);
+    }
+    Ok(report)
+}
+
+/// Like `read_mbo_ndjson_from`, converting one line at a time as the returned iterator
+/// is advanced instead of collecting the messages.
+///
+/// Each item is a line's 0-based index with its message, or why it was rejected; an
+/// error reading `reader` is yielded as an `Err` item.
+pub fn iter_mbo_ndjson_from<R: BufRead>(reader: R) -> NdjsonLines<R> {
+    NdjsonLines {
+        lines: reader.lines().enumerate(),
+    }
+}
+
+/// Lines of NDJSON input converted as they are read, returned by
+/// `iter_mbo_ndjson_from`.
+pub struct NdjsonLines<R> {
+    lines: Enumerate<Lines<R>>,
+}
+
+impl<R: BufRead> Iterator for NdjsonLines<R> {
+    type Item = io::Result<(usize, Result<MarketByOrderMessage, ConversionError>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (index, line) = self.lines.next()?;
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e)),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let converted = serde_json::from_str::<Line>(&line)
+                .map_err(|e| ConversionError::Malformed(e.to_string()))
+                .and_then(Line::into_message);
+            return Some(Ok((index, converted)));
+        }
+    }
+}
+
+/// Writes `messages` as NDJSON with every field, action and side as characters.
+pub fn write_mbo_ndjson<W, I>(mut writer: W, messages: I) -> io::Result<()>
+where
+    W: Write,
+    I: IntoIterator,
+    I::Item: Borrow<MarketByOrderMessage>,
+{
+    for message in messages {
+        serde_json::to_writer(&mut writer, &Line::from(message.borrow()))?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use time::{Duration, OffsetDateTime};
+
+    use crate::orderbook::{Action, Side};
+
+    fn message(action: Action, side: Side, order_id: u64, sequence: u32) -> MarketByOrderMessage {
+        let event_time = OffsetDateTime::UNIX_EPOCH + Duration::microseconds(sequence.into());
+        let is_last = sequence.is_multiple_of(2);
+        MarketByOrderMessage {
+            instrument_id: 42,
+            sequence,
+            recv_time: event_time + Duration::nanoseconds(250),
+            ts_in_delta: Duration::nanoseconds(120),
+            ..MarketByOrderMessage::test(action, side, 100 + order_id as i64, order_id, 10)
+                .at(event_time)
+                .with_last(is_last)
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let messages = vec![
+            message(Action::Add, Side::Bid, 1, 1),
+            message(Action::Add, Side::Ask, 2, 2),
+            message(Action::Modify, Side::Bid, 1, 3),
+            message(Action::Trade, Side::Ask, 0, 4),
+            message(Action::Fill, Side::Bid, 1, 5),
+            message(Action::Cancel, Side::Ask, 2, 6),
+            message(Action::Clear, Side::Bid, 0, 7),
+        ];
+        let mut buf = Vec::new();
+        write_mbo_ndjson(&mut buf, &messages).unwrap();
+        assert_eq!(buf.iter().filter(|&&b| b == b'\n').count(), 7);
+
+        let report = read_mbo_ndjson_from(&buf[..]).unwrap();
+        assert_eq!(report.skipped(), 0);
+        assert_eq!(report.messages, messages);
+    }
+
+    #[test]
+    fn test_numeric_and_string_chars() {
+        let ndjson = r#"{"action":"A","side":"B","price":100,"order_id":1,"size":10,"ts_event":1000}
+{"action":65,"side":65,"price":101,"order_id":2,"size":5}
+"#;
+        let report = read_mbo_ndjson_from(ndjson.as_bytes()).unwrap();
+        assert_eq!(report.skipped(), 0);
+        assert_eq!(report.messages[0].side, Side::Bid);
+        assert_eq!(report.messages[0].event_time.unix_timestamp_nanos(), 1000);
+        assert_eq!(report.messages[1].action, Action::Add);
+        assert_eq!(report.messages[1].side, Side::Ask);
+        assert!(report.messages[1].is_last);
+    }
+
+    #[test]
+    fn test_bad_lines_reported_with_index() {
+        let ndjson = r#"{"action":"A","side":"B","price":100,"order_id":1,"size":10}
+
+{"action":"A","side":"B","price":null,"order_id":2,"size":10}
+not json
+{"action":"Add","side":"B","price":100,"order_id":3,"size":10}
+{"action":"A","side":"B","price":100,"order_id":4,"size":10}
+"#;
+        let report = read_mbo_ndjson_from(ndjson.as_bytes()).unwrap();
+        assert_eq!(report.converted(), 2);
+        let rejected: Vec<usize> = report.rejected.iter().map(|(index, _)| *index).collect();
+        assert_eq!(rejected, vec![2, 3, 4]);
+        assert!(matches!(
+            report.rejected[0].1,
+            ConversionError::Malformed(_)
+        ));
+        assert!(matches!(
+            report.rejected[2].1,
+            ConversionError::InvalidCharacter {
+                column: "action",
+                ..
+            }
+        ));
+    }
+
+    /// Reads its bytes, then fails.
+    struct Truncated(&'static [u8]);
+
+    impl io::Read for Truncated {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(io::Error::from(io::ErrorKind::ConnectionReset));
+            }
+            self.0.read(buf)
+        }
+    }
+
+    #[test]
+    fn test_iter_yields_lines_before_read_error() {
+        let ndjson =
+            b"{\"action\":\"A\",\"side\":\"B\",\"price\":100,\"order_id\":1,\"size\":10}\n\n";
+        let mut lines = iter_mbo_ndjson_from(BufReader::new(Truncated(ndjson)));
+        let (index, converted) = lines.next().unwrap().unwrap();
+        assert_eq!(index, 0);
+        assert_eq!(converted.unwrap().order_id, 1);
+        assert_eq!(
+            lines.next().unwrap().unwrap_err().kind(),
+            io::ErrorKind::ConnectionReset
+        );
+    }
+}
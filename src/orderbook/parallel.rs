@@ -0,0 +1,388 @@
+//! Processing a multi-instrument stream on several threads.
+//!
+//! Instruments never share state, so the stream is split by `instrument_id` into shards,
+//! each applied by its own processor on its own thread. Messages of one instrument go to
+//! the same shard in input order, so each book sees exactly the sequence it would in a
+//! single-threaded run, Clears and snapshots included.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use thiserror::Error;
+
+use crate::orderbook::anomaly::AnomalyLog;
+use crate::orderbook::{
+    BookKey, ErrorPolicy, MarketByOrderMessage, MboObserver, MboProcessor, ProcessFailure,
+    ProcessSummary,
+};
+
+/// Messages handed to a shard at a time, to keep channel overhead off the hot path.
+const BATCH_MESSAGES: usize = 1024;
+
+/// Batches queued per shard before the reader waits for it, bounding memory when the
+/// input is read faster than a shard applies it.
+const QUEUED_BATCHES: usize = 8;
+
+/// Messages with their index in the input.
+type Batch = Vec<(usize, MarketByOrderMessage)>;
+
+#[derive(Debug, Error)]
+pub enum ParallelError {
+    #[error(
+        "State hashes cover every book at once and cannot be recorded on several threads; \
+         process the messages sequentially"
+    )]
+    StateHashes,
+}
+
+impl<O: MboObserver> MboProcessor<O> {
+    /// Processes a stream of messages like `process_messages`, but on `threads` threads
+    /// with the instruments spread across them.
+    ///
+    /// The messages are read on the calling thread and dispatched as they arrive, so the
+    /// input is streamed rather than collected. The books and counters end up in this
+    /// processor, including those of instruments seen before, and the last message of
+    /// the input becomes the last processed one. Failure indices are relative to the
+    /// start of this call, in input order.
+    ///
+    /// The observer is not notified, and the rollback journal is cleared. Under
+    /// `ErrorPolicy::FailFast` the failure reported is the first in input order, as
+    /// sequentially, but other instruments may have applied messages after it by the
+    /// time every thread stops. Anomalies are logged as sequentially, numbered in
+    /// input order and only up to that failure.
+    ///
+    /// Fails with `ParallelError::StateHashes` without processing anything if state
+    /// hashes are recorded (`with_state_hash_interval`).
+    pub fn process_parallel<I>(
+        &mut self,
+        messages: I,
+        threads: usize,
+    ) -> Result<ProcessSummary, ParallelError>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<MarketByOrderMessage>,
+    {
+        if self.state_hash_interval() > 0 {
+            return Err(ParallelError::StateHashes);
+        }
+        let threads = threads.max(1);
+        let first_index = self.anomaly_log_mut().map_or(0, |log| log.next_index());
+        let mut shards: Vec<MboProcessor> =
+            (0..threads).map(|_| self.with_same_policies()).collect();
+        // Instruments keep their shard, so books from earlier processing carry on there
+        let mut assigned: HashMap<BookKey, usize> = HashMap::new();
+        let mut instruments: Vec<_> = self.take_instruments().into_iter().collect();
+        instruments.sort_unstable_by_key(|&(key, _)| key);
+        for (key, state) in instruments {
+            let shard = assigned.len() % threads;
+            assigned.insert(key, shard);
+            shards[shard].insert_instruments([(key, state)]);
+        }
+
+        // Under fail-fast, the index of the earliest failure found so far
+        let stop = AtomicUsize::new(usize::MAX);
+        let mut last: Option<MarketByOrderMessage> = None;
+        let mut given = 0;
+        let outcomes = thread::scope(|scope| {
+            let (senders, workers): (Vec<_>, Vec<_>) = shards
+                .into_iter()
+                .map(|mut shard| {
+                    let (sender, receiver) = mpsc::sync_channel::<Batch>(QUEUED_BATCHES);
+                    let stop = &stop;
+                    let worker = scope.spawn(move || {
+                        let summary = apply_batches(&mut shard, receiver, stop, first_index);
+                        (shard, summary)
+                    });
+                    (sender, worker)
+                })
+                .unzip();
+
+            let mut batches: Vec<Batch> = vec![Vec::new(); threads];
+            for (index, message) in messages.into_iter().enumerate() {
+                if index > stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                let message = message.borrow();
+                let next = assigned.len() % threads;
+                let shard = *assigned.entry(self.book_key(message)).or_insert(next);
+                batches[shard].push((index, *message));
+                if batches[shard].len() == BATCH_MESSAGES {
+                    let batch =
+                        std::mem::replace(&mut batches[shard], Vec::with_capacity(BATCH_MESSAGES));
+                    // A shard that stopped on a failure no longer receives
+                    let _ = senders[shard].send(batch);
+                }
+                last = Some(*message);
+                given = index + 1;
+            }
+            for (sender, batch) in senders.into_iter().zip(batches) {
+                if !batch.is_empty() {
+                    let _ = sender.send(batch);
+                }
+            }
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("shard thread panicked"))
+                .collect::<Vec<_>>()
+        });
+
+        let mut summary = ProcessSummary::default();
+        let mut anomalies = Vec::new();
+        for (mut shard, outcome) in outcomes {
+            self.insert_instruments(shard.take_instruments());
+            anomalies.extend(shard.take_anomaly_log().map(AnomalyLog::into_records));
+            summary.append(outcome, 0);
+        }
+        summary.failures.sort_by_key(|failure| failure.index);
+        if self.error_policy() == ErrorPolicy::FailFast {
+            summary.failures.truncate(1);
+            // Sequentially the messages after the failure are not given at all
+            if let Some(failure) = summary.failures.first() {
+                given = failure.index + 1;
+            }
+        }
+        if let Some(log) = self.anomaly_log_mut() {
+            let next_index = first_index + given as u64;
+            let mut records: Vec<_> = anomalies
+                .into_iter()
+                .flatten()
+                .filter(|record| record.message_index < next_index)
+                .collect();
+            // Stable, so the records of one message keep their order
+            records.sort_by_key(|record| record.message_index);
+            log.append(records, next_index);
+        }
+        if let Some(last) = &last {
+            self.record_last(last);
+        }
+        self.clear_journal();
+        Ok(summary)
+    }
+}
+
+/// Applies the batches of one shard until the input ends or, under fail-fast, it
+/// reaches a message after the earliest failure of any shard. Anomalies are logged at
+/// `first_index` plus the message's index in the input.
+fn apply_batches(
+    shard: &mut MboProcessor,
+    batches: Receiver<Batch>,
+    stop: &AtomicUsize,
+    first_index: u64,
+) -> ProcessSummary {
+    let mut summary = ProcessSummary::default();
+    let fail_fast = shard.error_policy() == ErrorPolicy::FailFast;
+    for batch in batches {
+        for (index, message) in batch {
+            if index > stop.load(Ordering::Relaxed) {
+                return summary;
+            }
+            if let Some(log) = shard.anomaly_log_mut() {
+                log.seek(first_index + index as u64);
+            }
+            match shard.process_message(&message) {
+                Ok(()) => {
+                    summary.processed += 1;
+                    summary.action_counts.record(message.action);
+                }
+                Err(error) => {
+                    summary.failures.push(ProcessFailure { index, error });
+                    if fail_fast {
+                        stop.fetch_min(index, Ordering::Relaxed);
+                        return summary;
+                    }
+                }
+            }
+        }
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::generators::{MessageMix, make_multi_instrument_messages};
+    use crate::orderbook::{
+        Action, EventRetention, MarketByPrice, MboProcessError, Side, ValidationPolicy,
+    };
+
+    /// Two-instrument stream with a Clear of instrument 2 and a snapshot of instrument 1
+    /// part way through.
+    fn two_instrument_messages() -> Vec<MarketByOrderMessage> {
+        let mut messages = make_multi_instrument_messages(7, 2, 20_000, MessageMix::default());
+        let clear_at = messages
+            .iter()
+            .position(|message| message.instrument_id == 2 && message.sequence > 5_000)
+            .unwrap();
+        messages[clear_at] = MarketByOrderMessage {
+            action: Action::Clear,
+            order_id: 0,
+            price: dbn::UNDEF_PRICE,
+            size: 0,
+            ..messages[clear_at]
+        };
+        // Re-adds the orders of instrument 1 as a snapshot of its book
+        let snapshot_at = 12_000;
+        let mut book = MboProcessor::new();
+        book.process_messages(
+            messages[..snapshot_at]
+                .iter()
+                .filter(|m| m.instrument_id == 1),
+        );
+        let snapshot: Vec<MarketByOrderMessage> = MarketByPrice::from(book.book(1).unwrap())
+            .bids
+            .values()
+            .enumerate()
+            .map(|(i, level)| MarketByOrderMessage {
+                instrument_id: 1,
+                action: Action::Add,
+                side: Side::Bid,
+                price: level.price,
+                order_id: 1_000_000 + i as u64,
+                size: level.total_quantity as u32,
+                flags: dbn::flags::SNAPSHOT,
+                is_last: false,
+                ..messages[snapshot_at]
+            })
+            .collect();
+        messages.splice(snapshot_at..snapshot_at, snapshot);
+        messages
+    }
+
+    fn sequential(messages: &[MarketByOrderMessage]) -> (MboProcessor, ProcessSummary) {
+        let mut processor = MboProcessor::new().with_error_policy(ErrorPolicy::Collect);
+        let summary = processor.process_messages(messages);
+        (processor, summary)
+    }
+
+    fn failure_indices(summary: &ProcessSummary) -> Vec<usize> {
+        summary
+            .failures
+            .iter()
+            .map(|failure| failure.index)
+            .collect()
+    }
+
+    #[test]
+    fn test_parallel_matches_sequential() {
+        let messages = two_instrument_messages();
+        let (expected, expected_summary) = sequential(&messages);
+        // Orders removed by the Clear and the snapshot are still referred to
+        assert!(!expected_summary.is_success());
+        for threads in [1, 2, 3] {
+            let mut processor = MboProcessor::new().with_error_policy(ErrorPolicy::Collect);
+            let summary = processor.process_parallel(&messages, threads).unwrap();
+            assert_eq!(summary.processed, expected_summary.processed);
+            assert_eq!(summary.action_counts, expected_summary.action_counts);
+            assert_eq!(
+                failure_indices(&summary),
+                failure_indices(&expected_summary)
+            );
+            assert_eq!(processor.instruments(), vec![1, 2]);
+            for instrument_id in [1, 2] {
+                assert_eq!(
+                    processor.mbp(instrument_id),
+                    expected.mbp(instrument_id),
+                    "instrument {instrument_id} with {threads} threads"
+                );
+                assert_eq!(
+                    processor.instrument_stats(instrument_id),
+                    expected.instrument_stats(instrument_id)
+                );
+            }
+            assert_eq!(processor.stats(), expected.stats());
+            assert_eq!(
+                processor.last_sequence_number(),
+                expected.last_sequence_number()
+            );
+            assert_eq!(processor.last_event_time(), expected.last_event_time());
+        }
+    }
+
+    #[test]
+    fn test_parallel_continues_earlier_books() {
+        let messages = two_instrument_messages();
+        let (expected, _) = sequential(&messages);
+        let (head, tail) = messages.split_at(messages.len() / 2);
+        let (mut processor, _) = sequential(head);
+        processor.process_parallel(tail, 2).unwrap();
+        for instrument_id in [1, 2] {
+            assert_eq!(processor.mbp(instrument_id), expected.mbp(instrument_id));
+        }
+    }
+
+    #[test]
+    fn test_parallel_logs_anomalies_as_sequentially() {
+        let messages = two_instrument_messages();
+        let (head, tail) = messages.split_at(1_000);
+        for retention in [EventRetention::All, EventRetention::Last(5)] {
+            let logged = |parallel: bool| {
+                let mut processor = MboProcessor::new()
+                    .with_error_policy(ErrorPolicy::Collect)
+                    .with_anomaly_log(retention);
+                processor.process_messages(head);
+                if parallel {
+                    processor.process_parallel(tail, 3).unwrap();
+                } else {
+                    processor.process_messages(tail);
+                }
+                // Numbered on after the parallel part: the head again adds duplicates
+                processor.process_messages(head);
+                processor.anomaly_log().copied().collect::<Vec<_>>()
+            };
+            let expected = logged(false);
+            assert!(
+                expected
+                    .iter()
+                    .any(|record| record.message_index as usize >= messages.len())
+            );
+            assert_eq!(logged(true), expected, "{retention:?}");
+        }
+    }
+
+    #[test]
+    fn test_parallel_rejects_state_hashes() {
+        let mut processor = MboProcessor::new().with_state_hash_interval(100);
+        let result = processor.process_parallel(two_instrument_messages(), 2);
+        assert!(matches!(result, Err(ParallelError::StateHashes)));
+        assert!(processor.instruments().is_empty());
+    }
+
+    #[test]
+    fn test_parallel_failures_in_input_order() {
+        let mut messages = make_multi_instrument_messages(3, 2, 20_000, MessageMix::default());
+        // Invalid Trades, which fail without changing the books, one per instrument
+        let mut invalid = [1, 2].map(|instrument_id| {
+            messages
+                .iter()
+                .position(|m| m.instrument_id == instrument_id && m.action == Action::Trade)
+                .unwrap()
+        });
+        invalid.sort_unstable();
+        for index in invalid {
+            messages[index].size = u32::MAX;
+        }
+
+        let mut processor = MboProcessor::new()
+            .with_validation_policy(ValidationPolicy::Reject)
+            .with_error_policy(ErrorPolicy::Collect);
+        let summary = processor.process_parallel(&messages, 2).unwrap();
+        assert_eq!(failure_indices(&summary), invalid);
+        assert_eq!(summary.processed, messages.len() as u64 - 2);
+
+        for _ in 0..20 {
+            let mut processor =
+                MboProcessor::new().with_validation_policy(ValidationPolicy::Reject);
+            let summary = processor.process_parallel(&messages, 2).unwrap();
+            assert_eq!(summary.failures.len(), 1);
+            assert_eq!(summary.failures[0].index, invalid[0]);
+            assert!(matches!(
+                summary.failures[0].error,
+                MboProcessError::ValidationFailed(_)
+            ));
+        }
+    }
+}
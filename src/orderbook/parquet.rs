@@ -0,0 +1,277 @@
+//! Reading MBO parquet files a batch of rows at a time.
+//!
+//! Loading a capture of tens of millions of rows into one DataFrame, and then into a
+//! `Vec` of messages, needs memory for both at once. Reading a slice of rows at a time
+//! only decodes the row groups overlapping that slice, so peak memory is bounded by
+//! the batch size instead of the file size.
+
+use std::fs::File;
+use std::path::Path;
+
+use polars::prelude::{ParquetReader, PolarsResult, SerReader, polars_bail};
+
+use crate::orderbook::{
+    ColumnMapping, ConversionError, ConversionReport, ErrorPolicy, MboObserver, MboProcessor,
+    MboRows, ProcessSummary, filter_instrument, iter_mbo_messages_with,
+};
+
+/// Converted batches of a parquet file of MBO rows, in file order.
+///
+/// Each item is the `ConversionReport` of up to `batch_size` rows. Rejected row indices
+/// are relative to the start of the file, not the batch; with an instrument filter they
+/// count only the rows of that instrument.
+pub struct ParquetBatches {
+    file: File,
+    rows: usize,
+    offset: usize,
+    batch_size: usize,
+    mapping: ColumnMapping,
+    instrument_id: Option<u32>,
+    kept: usize,
+}
+
+impl ParquetBatches {
+    pub fn open(path: impl AsRef<Path>, batch_size: usize) -> PolarsResult<Self> {
+        if batch_size == 0 {
+            polars_bail!(InvalidOperation: "parquet batch size must be positive");
+        }
+        let mut file = File::open(path)?;
+        let rows = ParquetReader::new(&mut file).num_rows()?;
+        Ok(Self {
+            file,
+            rows,
+            offset: 0,
+            batch_size,
+            mapping: ColumnMapping::default(),
+            instrument_id: None,
+            kept: 0,
+        })
+    }
+
+    /// Read the MBO fields from the columns named by `mapping`.
+    pub fn with_column_mapping(mut self, mapping: ColumnMapping) -> Self {
+        self.mapping = mapping;
+        self
+    }
+
+    /// Only convert the rows of `instrument_id`, filtering each batch with
+    /// `filter_instrument` first. Batches still hold up to `batch_size` file rows, so
+    /// they may be smaller or empty.
+    pub fn with_instrument_id(mut self, instrument_id: Option<u32>) -> Self {
+        self.instrument_id = instrument_id;
+        self
+    }
+
+    /// Total number of rows in the file.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Reads the next batch as rows converted while they are iterated, instead of as a
+    /// `ConversionReport`, or `None` after the last batch. Only this batch's columns
+    /// are held, by the returned `MboRows`.
+    pub fn next_rows(&mut self) -> Option<PolarsResult<MboRows>> {
+        if self.offset >= self.rows {
+            return None;
+        }
+        let rows = self.read_rows();
+        if rows.is_err() {
+            // A batch that cannot be read is not retried
+            self.offset = self.rows;
+        }
+        Some(rows)
+    }
+
+    fn read_rows(&mut self) -> PolarsResult<MboRows> {
+        let len = self.batch_size.min(self.rows - self.offset);
+        let df = ParquetReader::new(&mut self.file)
+            .with_slice(Some((self.offset, len)))
+            .finish()?;
+        let df = match self.instrument_id {
+            Some(instrument_id) => filter_instrument(&df, &self.mapping, instrument_id)?,
+            None => df,
+        };
+        let rows = iter_mbo_messages_with(&df, &self.mapping)?;
+        self.offset += len;
+        self.kept += rows.len();
+        Ok(rows)
+    }
+}
+
+impl Iterator for ParquetBatches {
+    type Item = PolarsResult<ConversionReport>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.kept;
+        self.next_rows().map(|rows| {
+            let mut report = ConversionReport::default();
+            for (row, converted) in rows?.enumerate() {
+                report.push(offset + row, converted);
+            }
+            Ok(report)
+        })
+    }
+}
+
+/// Outcome of `process_parquet_streaming`.
+#[derive(Debug, Default, Clone)]
+pub struct ParquetProcessSummary {
+    /// Processing outcome, with failure indices counted over the converted messages.
+    pub summary: ProcessSummary,
+    /// 0-based file row index and reason of every row that could not be converted.
+    pub rejected: Vec<(usize, ConversionError)>,
+}
+
+/// Processes the MBO rows of the parquet file at `path`, reading and converting
+/// `batch_size` rows at a time as described in `into_mbo_messages`.
+///
+/// Each batch is applied to `processor` before the next is read, so messages are
+/// processed in file order. Under `ErrorPolicy::FailFast` reading stops at the first
+/// message that fails to process.
+pub fn process_parquet_streaming<O: MboObserver>(
+    path: impl AsRef<Path>,
+    processor: &mut MboProcessor<O>,
+    batch_size: usize,
+) -> PolarsResult<ParquetProcessSummary> {
+    let mut result = ParquetProcessSummary::default();
+    let mut offset = 0;
+    for report in ParquetBatches::open(path, batch_size)? {
+        let report = report?;
+        result.rejected.extend(report.rejected);
+        let batch = processor.process_messages(&report.messages);
+        let failed = !batch.is_success();
+        result.summary.append(batch, offset);
+        offset += report.messages.len();
+        if failed && processor.error_policy() == ErrorPolicy::FailFast {
+            break;
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::{env, fs, process};
+
+    use polars::prelude::{DataFrame, ParquetWriter, df};
+
+    use crate::orderbook::{MboProcessError, OrderBookError, into_mbo_messages};
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("rainybook-{}-{name}", process::id()))
+    }
+
+    /// 100 rows adding and cancelling orders on both sides, with one unknown action.
+    fn frame() -> DataFrame {
+        let rows = 0..100u64;
+        let action: Vec<i8> = rows
+            .clone()
+            .map(|i| match i {
+                42 => b'?',
+                _ if i % 3 == 2 => b'C',
+                _ => b'A',
+            } as i8)
+            .collect();
+        let side: Vec<i8> = rows
+            .clone()
+            .map(|i| if (i / 3) % 2 == 0 { b'B' } else { b'A' } as i8)
+            .collect();
+        // Cancels refer to the order added just before them
+        let order_id: Vec<u64> = rows
+            .clone()
+            .map(|i| if i % 3 == 2 { i } else { i + 1 })
+            .collect();
+        let price: Vec<i64> = rows
+            .clone()
+            .map(|i| match (i / 3) % 2 {
+                0 => 100 - (i / 3 % 7) as i64,
+                _ => 110 + (i / 3 % 5) as i64,
+            })
+            .collect();
+        let ts_event: Vec<u64> = rows.map(|i| 1_000 + i).collect();
+        df!(
+            "action" => action,
+            "side" => side,
+            "price" => price,
+            "order_id" => order_id,
+            "size" => vec![10u32; 100],
+            "ts_event" => ts_event,
+        )
+        .unwrap()
+    }
+
+    fn write_parquet(name: &str, df: &mut DataFrame, row_group_size: usize) -> PathBuf {
+        let path = temp_path(name);
+        ParquetWriter::new(File::create(&path).unwrap())
+            .with_row_group_size(Some(row_group_size))
+            .finish(df)
+            .unwrap();
+        path
+    }
+
+    #[test]
+    fn test_streaming_matches_all_at_once() {
+        let path = write_parquet("streaming.parquet", &mut frame(), 16);
+        let row_groups = ParquetReader::new(File::open(&path).unwrap())
+            .get_metadata()
+            .unwrap()
+            .row_groups
+            .len();
+        assert!(row_groups > 1);
+
+        let report = into_mbo_messages(&frame()).unwrap();
+        let mut expected = MboProcessor::new().with_error_policy(ErrorPolicy::Collect);
+        let expected_summary = expected.process_messages(&report.messages);
+        assert!(expected_summary.is_success());
+
+        // Batches that do not line up with the row groups
+        for batch_size in [1, 7, 16, 30, 1_000] {
+            let mut proc = MboProcessor::new().with_error_policy(ErrorPolicy::Collect);
+            let result = process_parquet_streaming(&path, &mut proc, batch_size).unwrap();
+            assert_eq!(proc.book(0), expected.book(0));
+            assert_eq!(result.summary.processed, expected_summary.processed);
+            assert_eq!(
+                result
+                    .rejected
+                    .iter()
+                    .map(|(row, _)| *row)
+                    .collect::<Vec<_>>(),
+                vec![42]
+            );
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_fail_fast_stops_reading() {
+        // Row 15 modifies an order that was never added
+        let mut df = df!(
+            "action" => (0..30).map(|i| if i == 15 { b'M' } else { b'A' } as i8).collect::<Vec<_>>(),
+            "side" => vec![b'B' as i8; 30],
+            "price" => vec![100i64; 30],
+            "order_id" => (0..30u64).map(|i| if i == 15 { 999 } else { i }).collect::<Vec<_>>(),
+            "size" => vec![10u32; 30],
+        )
+        .unwrap();
+        let path = write_parquet("streaming-fail-fast.parquet", &mut df, 10);
+
+        let mut proc = MboProcessor::new();
+        let result = process_parquet_streaming(&path, &mut proc, 10).unwrap();
+        assert_eq!(result.summary.processed, 15);
+        let failure = result.summary.first_failure().unwrap();
+        assert_eq!(failure.index, 15);
+        assert!(matches!(
+            failure.error,
+            MboProcessError::OrderBookError(OrderBookError::OrderNotFound(999))
+        ));
+        assert_eq!(proc.best_bid(), Some((100, 150)));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_zero_batch_size() {
+        assert!(ParquetBatches::open("/nonexistent/mbo.parquet", 0).is_err());
+    }
+}
@@ -0,0 +1,477 @@
+//! Estimating whether hypothetical passive orders would have filled.
+//!
+//! A `PassiveOrder` joins a level of the recorded book with some quantity queued ahead
+//! of it. As the processor replays the session, `PassiveFillSimulator` depletes that
+//! queue with the Fills at the order's price and fills the order with whatever trades
+//! beyond it. Cancellations ahead are the uncertain part, since the feed does not say
+//! whether a cancelled order was ahead of or behind the hypothetical one; `QueueModel`
+//! picks how they count.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::orderbook::book::OrderLevel;
+use crate::orderbook::events::{OrderCancelledEvent, OrderModifiedEvent, TradeEvent};
+use crate::orderbook::{BookKey, MarketByOrderMessage, MboObserver, OrderBook, Side};
+
+/// How cancellations at a hypothetical order's level change the quantity ahead of it.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QueueModel {
+    /// Cancellations never shorten the queue ahead: only trades do.
+    #[default]
+    Conservative,
+    /// A cancellation shortens the queue ahead by its share of the level: cancelling
+    /// `q` of a level of `L` with `A` ahead leaves `A - q * A / L` ahead.
+    ProRata,
+}
+
+/// A hypothetical resting order, never sent to the book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PassiveOrder {
+    pub instrument_id: u32,
+    pub side: Side,
+    pub price: i64,
+    pub size: u64,
+    pub placed_at: OffsetDateTime,
+    /// Quantity queued ahead of the order when placed.
+    pub queue_ahead: u64,
+    /// Quantity resting at the level when placed, ahead or not.
+    pub level_qty: u64,
+}
+
+impl PassiveOrder {
+    /// An order joining the back of the level at `price` on `side` of `book`, behind
+    /// everything resting there.
+    pub fn joining(
+        book: &OrderBook,
+        instrument_id: u32,
+        side: Side,
+        price: i64,
+        size: u64,
+        placed_at: OffsetDateTime,
+    ) -> Self {
+        let level_qty = book
+            .levels(side)
+            .get(&price)
+            .map_or(0, OrderLevel::total_qty);
+        Self {
+            instrument_id,
+            side,
+            price,
+            size,
+            placed_at,
+            queue_ahead: level_qty,
+            level_qty,
+        }
+    }
+
+    /// An order queued right behind the resting order `order_id` of `book`, at its side
+    /// and price, or `None` if it is not in the book.
+    pub fn behind(
+        book: &OrderBook,
+        instrument_id: u32,
+        order_id: u64,
+        size: u64,
+        placed_at: OffsetDateTime,
+    ) -> Option<Self> {
+        let order = book.get_order(order_id)?;
+        Some(Self {
+            queue_ahead: book.queue_depth_ahead(order_id)? + order.size,
+            ..Self::joining(
+                book,
+                instrument_id,
+                order.side,
+                order.price,
+                size,
+                placed_at,
+            )
+        })
+    }
+}
+
+/// Where a hypothetical order stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PassiveOrderState {
+    pub order: PassiveOrder,
+    /// Quantity still queued ahead of the order.
+    pub queue_ahead: u64,
+    pub filled_qty: u64,
+    /// Event time of the trade that completed the fill.
+    pub filled_at: Option<OffsetDateTime>,
+}
+
+impl PassiveOrderState {
+    pub fn is_filled(&self) -> bool {
+        self.filled_at.is_some()
+    }
+
+    /// Fills `qty` more, at most the unfilled size, at `time`.
+    fn fill(&mut self, qty: u64, time: OffsetDateTime) {
+        self.filled_qty += qty.min(self.order.size - self.filled_qty);
+        if self.filled_qty == self.order.size {
+            self.filled_at = Some(time);
+        }
+    }
+}
+
+/// A change at a level observed while a message is applied, kept until the message's
+/// instrument is known.
+#[derive(Debug, Clone, Copy)]
+enum LevelChange {
+    /// A resting order traded `size`.
+    Fill {
+        side: Side,
+        price: i64,
+        size: u64,
+        time: OffsetDateTime,
+    },
+    /// Resting quantity left the level other than by trading here.
+    Reduced { side: Side, price: i64, qty: u64 },
+}
+
+/// Observer estimating the fills of hypothetical passive orders from the Fills and
+/// cancellations of the replayed session.
+///
+/// A Fill at an order's side and price first depletes the queue ahead, then fills the
+/// order with the rest; a Fill at a worse price on its side means the level traded
+/// through, filling it completely. Cancels, and Modifies that lower a size or send an
+/// order to the back of the queue or away, shorten the queue ahead as the
+/// `QueueModel` says. The size reductions a venue reports after a Fill, within the same
+/// event, are trades rather than cancellations and are not counted again.
+///
+/// Orders only see messages processed after they are placed, so process up to their
+/// placement time before `place`.
+#[derive(Debug, Default)]
+pub struct PassiveFillSimulator {
+    model: QueueModel,
+    orders: Vec<PassiveOrderState>,
+    /// Changes made by the message being applied.
+    changes: Vec<LevelChange>,
+    /// Quantity filled per resting order id in the current event, not yet reduced.
+    filled_this_event: HashMap<u64, u64>,
+    /// Resting quantity at each open order's level after the last message, before any
+    /// change of the current one.
+    level_qty: Vec<u64>,
+}
+
+impl PassiveFillSimulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_queue_model(mut self, model: QueueModel) -> Self {
+        self.model = model;
+        self
+    }
+
+    pub fn queue_model(&self) -> QueueModel {
+        self.model
+    }
+
+    /// Starts tracking `order`, returning its index in `orders`.
+    pub fn place(&mut self, order: PassiveOrder) -> usize {
+        self.orders.push(PassiveOrderState {
+            order,
+            queue_ahead: order.queue_ahead,
+            filled_qty: 0,
+            filled_at: None,
+        });
+        self.level_qty.push(order.level_qty);
+        self.orders.len() - 1
+    }
+
+    /// Every order placed, by index.
+    pub fn orders(&self) -> &[PassiveOrderState] {
+        &self.orders
+    }
+
+    pub fn order(&self, index: usize) -> Option<&PassiveOrderState> {
+        self.orders.get(index)
+    }
+
+    /// Subtracts the part of `qty` left the order `order_id` by its own fills this
+    /// event, returning the rest.
+    fn beyond_fills(&mut self, order_id: u64, qty: u64) -> u64 {
+        match self.filled_this_event.get_mut(&order_id) {
+            Some(filled) => {
+                let traded = qty.min(*filled);
+                *filled -= traded;
+                qty - traded
+            }
+            None => qty,
+        }
+    }
+}
+
+impl MboObserver for PassiveFillSimulator {
+    fn on_order_cancelled(&mut self, event: &OrderCancelledEvent) {
+        let order = event.order;
+        let qty = self.beyond_fills(order.order_id, order.size);
+        self.changes.push(LevelChange::Reduced {
+            side: order.side,
+            price: order.price,
+            qty,
+        });
+    }
+
+    fn on_order_modified(&mut self, event: &OrderModifiedEvent) {
+        let order = event.order;
+        // What is left at the old place in the queue, if anything
+        let kept = match event.retained_queue_position {
+            true => order.size.min(event.old_size),
+            false => 0,
+        };
+        let qty = self.beyond_fills(order.order_id, event.old_size - kept);
+        // Taken to be on the same side as before; Modifies that change side are rare
+        self.changes.push(LevelChange::Reduced {
+            side: order.side,
+            price: event.old_price,
+            qty,
+        });
+    }
+
+    fn on_trade(&mut self, event: &TradeEvent) {
+        if event.aggressor {
+            return;
+        }
+        *self.filled_this_event.entry(event.order_id).or_default() += u64::from(event.size);
+        self.changes.push(LevelChange::Fill {
+            side: event.side,
+            price: event.price,
+            size: event.size.into(),
+            time: event.event_time,
+        });
+    }
+
+    fn on_message_applied(
+        &mut self,
+        message: &MarketByOrderMessage,
+        _key: BookKey,
+        book: &OrderBook,
+    ) {
+        let model = self.model;
+        let states = self.orders.iter_mut().zip(&mut self.level_qty);
+        for (state, level_qty) in states.filter(|(state, _)| {
+            state.order.instrument_id == message.instrument_id && !state.is_filled()
+        }) {
+            let PassiveOrder { side, price, .. } = state.order;
+            for &change in &self.changes {
+                match change {
+                    LevelChange::Fill {
+                        side: fill_side,
+                        price: fill_price,
+                        size,
+                        time,
+                    } if fill_side == side => {
+                        let through = match side {
+                            Side::Bid => fill_price < price,
+                            Side::Ask => fill_price > price,
+                        };
+                        if through {
+                            state.queue_ahead = 0;
+                            state.fill(state.order.size, time);
+                        } else if fill_price == price {
+                            let depleted = size.min(state.queue_ahead);
+                            state.queue_ahead -= depleted;
+                            state.fill(size - depleted, time);
+                        }
+                    }
+                    LevelChange::Reduced {
+                        side: reduced_side,
+                        price: reduced_price,
+                        qty,
+                    } if reduced_side == side
+                        && reduced_price == price
+                        && model == QueueModel::ProRata
+                        && *level_qty > 0 =>
+                    {
+                        let decay = u128::from(qty.min(*level_qty)) * u128::from(state.queue_ahead)
+                            / u128::from(*level_qty);
+                        // Bounded by queue_ahead, as qty is at most the level's
+                        state.queue_ahead -= decay as u64;
+                    }
+                    _ => {}
+                }
+                if state.is_filled() {
+                    break;
+                }
+            }
+            *level_qty = book
+                .levels(side)
+                .get(&price)
+                .map_or(0, OrderLevel::total_qty);
+        }
+        self.changes.clear();
+    }
+
+    /// Orders purged before the message failed leave their queues as if cancelled.
+    fn on_message_rejected(
+        &mut self,
+        message: &MarketByOrderMessage,
+        key: BookKey,
+        book: &OrderBook,
+    ) {
+        self.on_message_applied(message, key, book);
+    }
+
+    fn on_event_complete(
+        &mut self,
+        _book: &OrderBook,
+        _event_time: OffsetDateTime,
+        _recv_time: OffsetDateTime,
+    ) {
+        self.filled_this_event.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Duration;
+
+    use crate::orderbook::{Action, MboProcessor};
+
+    fn at(seconds: i64) -> OffsetDateTime {
+        OffsetDateTime::UNIX_EPOCH + Duration::seconds(seconds)
+    }
+
+    /// A message `seconds` into the session, ending its event unless it is a Trade or
+    /// Fill, which the venue follows with the resting order's reduction.
+    fn msg(
+        seconds: i64,
+        action: Action,
+        order_id: u64,
+        side: Side,
+        price: i64,
+        size: u32,
+    ) -> MarketByOrderMessage {
+        let is_last = !matches!(action, Action::Trade | Action::Fill);
+        MarketByOrderMessage {
+            sequence: seconds as u32,
+            ..MarketByOrderMessage::test(action, side, price, order_id, size)
+                .at(at(seconds))
+                .with_last(is_last)
+        }
+    }
+
+    /// Sells `size` into the resting bid `order_id` of `resting` at 100: the Trade, the
+    /// Fill and the Cancel or Modify that takes the quantity off the order.
+    fn sell_into(
+        seconds: i64,
+        order_id: u64,
+        resting: u32,
+        size: u32,
+    ) -> [MarketByOrderMessage; 3] {
+        let reduce = match size == resting {
+            true => msg(seconds, Action::Cancel, order_id, Side::Bid, 100, size),
+            false => msg(
+                seconds,
+                Action::Modify,
+                order_id,
+                Side::Bid,
+                100,
+                resting - size,
+            ),
+        };
+        [
+            msg(seconds, Action::Trade, 0, Side::Ask, 100, size),
+            msg(seconds, Action::Fill, order_id, Side::Bid, 100, size),
+            reduce,
+        ]
+    }
+
+    /// Bids of 10 (order 1) and 20 (order 2) at 100, and the hypothetical order's
+    /// index under `model`, joining behind both with 5.
+    fn simulate(model: QueueModel) -> (MboProcessor<PassiveFillSimulator>, usize) {
+        let mut processor =
+            MboProcessor::with_observer(PassiveFillSimulator::new().with_queue_model(model));
+        processor.process_messages([
+            msg(1, Action::Add, 1, Side::Bid, 100, 10),
+            msg(2, Action::Add, 2, Side::Bid, 100, 20),
+        ]);
+        let order = PassiveOrder::joining(processor.order_book(), 1, Side::Bid, 100, 5, at(3));
+        assert_eq!(order.queue_ahead, 30);
+        let index = processor.observer_mut().place(order);
+        (processor, index)
+    }
+
+    #[test]
+    fn test_trades_deplete_queue_then_fill() {
+        let (mut processor, index) = simulate(QueueModel::Conservative);
+        processor.process_messages(sell_into(4, 1, 10, 10));
+        let state = processor.observer().orders()[index];
+        assert_eq!((state.queue_ahead, state.filled_qty), (20, 0));
+
+        // 18 of order 2 trade, then 4 more: 2 ahead and 2 of ours
+        processor.process_messages(sell_into(5, 2, 20, 18));
+        processor.process_messages(sell_into(6, 2, 2, 2));
+        let state = processor.observer().orders()[index];
+        assert_eq!((state.queue_ahead, state.filled_qty), (0, 0));
+        // Orders behind ours only trade after it
+        processor
+            .process_message(&msg(7, Action::Add, 3, Side::Bid, 100, 10))
+            .unwrap();
+        processor.process_messages(sell_into(8, 3, 10, 3));
+        let state = processor.observer().orders()[index];
+        assert_eq!((state.filled_qty, state.filled_at), (3, None));
+        processor.process_messages(sell_into(9, 3, 7, 2));
+        let state = processor.observer().orders()[index];
+        assert_eq!((state.filled_qty, state.filled_at), (5, Some(at(9))));
+        assert!(state.is_filled());
+    }
+
+    #[test]
+    fn test_cancels_ahead_by_queue_model() {
+        let cancel = msg(4, Action::Cancel, 1, Side::Bid, 100, 10);
+        // Order 2 halves its size, keeping its place
+        let reduce = msg(5, Action::Modify, 2, Side::Bid, 100, 10);
+
+        let (mut processor, index) = simulate(QueueModel::Conservative);
+        processor.process_messages([cancel, reduce]);
+        assert_eq!(processor.observer().orders()[index].queue_ahead, 30);
+
+        // 10 of 30 cancelled leave 20 of 30 ahead, then 10 of 20 leave 10 of 20
+        let (mut processor, index) = simulate(QueueModel::ProRata);
+        // Behind order 1, only its 10 are ahead of a second order
+        let behind = PassiveOrder::behind(processor.order_book(), 1, 1, 5, at(3)).unwrap();
+        assert_eq!((behind.queue_ahead, behind.level_qty), (10, 30));
+        let second = processor.observer_mut().place(behind);
+        processor.process_message(&cancel).unwrap();
+        // 10 of 30 cancelled take a third of its queue ahead
+        assert_eq!(processor.observer().orders()[second].queue_ahead, 7);
+        assert_eq!(processor.observer().orders()[index].queue_ahead, 20);
+        processor.process_message(&reduce).unwrap();
+        assert_eq!(processor.observer().orders()[index].queue_ahead, 10);
+
+        // Reductions after a Fill are trades, not cancellations
+        processor.process_messages(sell_into(6, 2, 10, 4));
+        let state = processor.observer().orders()[index];
+        assert_eq!((state.queue_ahead, state.filled_qty), (6, 0));
+    }
+
+    #[test]
+    fn test_trade_through_fills_completely() {
+        let (mut processor, index) = simulate(QueueModel::Conservative);
+        processor.process_messages([
+            msg(4, Action::Add, 3, Side::Bid, 99, 10),
+            msg(5, Action::Trade, 0, Side::Ask, 99, 1),
+            msg(5, Action::Fill, 3, Side::Bid, 99, 1),
+            msg(5, Action::Modify, 3, Side::Bid, 99, 9),
+        ]);
+        let state = processor.observer().orders()[index];
+        assert_eq!((state.filled_qty, state.filled_at), (5, Some(at(5))));
+
+        // Other instruments and the other side leave it alone
+        let (mut processor, index) = simulate(QueueModel::Conservative);
+        processor.process_messages([
+            MarketByOrderMessage {
+                instrument_id: 2,
+                ..msg(4, Action::Fill, 9, Side::Bid, 100, 50)
+            },
+            msg(5, Action::Fill, 9, Side::Ask, 100, 50),
+        ]);
+        assert_eq!(processor.observer().orders()[index].queue_ahead, 30);
+    }
+}
@@ -0,0 +1,186 @@
+//! Acting on the books at regular points of a replay, e.g. to take or print snapshots.
+
+use std::borrow::Borrow;
+use std::str::FromStr;
+
+use thiserror::Error;
+use time::{Duration, OffsetDateTime};
+
+use crate::orderbook::{
+    ErrorPolicy, MarketByOrderMessage, MboObserver, MboProcessor, ProcessFailure, ProcessSummary,
+};
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[error(
+    "Invalid interval '{0}', expected a positive message count such as 1000 or seconds of \
+     event time such as 5s"
+)]
+pub struct IntervalError(String);
+
+/// How often `MboProcessor::process_with_interval` stops to look at the books.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotInterval {
+    /// After every this many messages. Zero never stops.
+    Messages(usize),
+    /// After the first message, then after the first message at least this much event
+    /// time after the last stop.
+    EventTime(Duration),
+}
+
+/// Parses a message count, e.g. `1000`, or seconds of event time, e.g. `5s` or `0.5s`.
+impl FromStr for SnapshotInterval {
+    type Err = IntervalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || IntervalError(s.to_owned());
+        let interval = match s.strip_suffix('s') {
+            Some(seconds) => {
+                let seconds: f64 = seconds.parse().map_err(|_| invalid())?;
+                if !(seconds.is_finite() && seconds > 0.0) {
+                    return Err(invalid());
+                }
+                Self::EventTime(Duration::seconds_f64(seconds))
+            }
+            None => match s.parse().map_err(|_| invalid())? {
+                0 => return Err(invalid()),
+                messages => Self::Messages(messages),
+            },
+        };
+        Ok(interval)
+    }
+}
+
+impl<O: MboObserver> MboProcessor<O> {
+    /// Processes `messages` like `process_messages`, calling `on_tick` with the processor
+    /// and the 0-based index of the last message applied at every `interval`.
+    ///
+    /// Ticks fall on message counts or event times, so one may fall in the middle of an
+    /// event. The first error from `on_tick` ends processing and is returned.
+    pub fn process_with_interval<I, E>(
+        &mut self,
+        messages: I,
+        interval: SnapshotInterval,
+        mut on_tick: impl FnMut(&Self, u64) -> Result<(), E>,
+    ) -> Result<ProcessSummary, E>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<MarketByOrderMessage>,
+    {
+        let mut summary = ProcessSummary::default();
+        let mut next_tick: Option<OffsetDateTime> = None;
+        for (index, message) in messages.into_iter().enumerate() {
+            let message = message.borrow();
+            match self.process_message(message) {
+                Ok(()) => {
+                    summary.processed += 1;
+                    summary.action_counts.record(message.action);
+                }
+                Err(error) => {
+                    summary.failures.push(ProcessFailure { index, error });
+                    if self.error_policy() == ErrorPolicy::FailFast {
+                        break;
+                    }
+                }
+            }
+            let due = match interval {
+                SnapshotInterval::Messages(every) => (index + 1).is_multiple_of(every),
+                SnapshotInterval::EventTime(period) => {
+                    let due = next_tick.is_none_or(|next| message.event_time >= next);
+                    if due {
+                        next_tick = Some(message.event_time + period);
+                    }
+                    due
+                }
+            };
+            if due {
+                on_tick(self, index as u64)?;
+            }
+        }
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+
+    use crate::orderbook::{Action, Side};
+
+    fn add(order_id: u64, ts_event_ms: i64) -> MarketByOrderMessage {
+        let event_time = OffsetDateTime::UNIX_EPOCH + Duration::milliseconds(ts_event_ms);
+        MarketByOrderMessage {
+            sequence: order_id as u32,
+            ..MarketByOrderMessage::test(
+                Action::Add,
+                Side::Bid,
+                100 + order_id as i64,
+                order_id,
+                10,
+            )
+            .at(event_time)
+        }
+    }
+
+    /// Ticks as `(message index, best bid)`.
+    fn ticks(messages: &[MarketByOrderMessage], interval: SnapshotInterval) -> Vec<(u64, i64)> {
+        let mut ticks = Vec::new();
+        let mut proc = MboProcessor::new();
+        let summary = proc
+            .process_with_interval(messages, interval, |proc, index| {
+                ticks.push((index, proc.best_bid().unwrap().0));
+                Ok::<_, Infallible>(())
+            })
+            .unwrap();
+        assert_eq!(summary.processed, messages.len() as u64);
+        ticks
+    }
+
+    #[test]
+    fn test_ticks_every_n_messages() {
+        let messages: Vec<_> = (1..=7).map(|id| add(id, id as i64)).collect();
+        assert_eq!(
+            ticks(&messages, SnapshotInterval::Messages(3)),
+            vec![(2, 103), (5, 106)]
+        );
+        assert_eq!(ticks(&messages, SnapshotInterval::Messages(0)), vec![]);
+    }
+
+    #[test]
+    fn test_ticks_on_event_time() {
+        let messages: Vec<_> = [0, 400, 1000, 1100, 3500]
+            .into_iter()
+            .enumerate()
+            .map(|(index, ms)| add(index as u64 + 1, ms))
+            .collect();
+        let interval = SnapshotInterval::EventTime(Duration::SECOND);
+        assert_eq!(
+            ticks(&messages, interval),
+            vec![(0, 101), (2, 103), (4, 105)]
+        );
+    }
+
+    #[test]
+    fn test_tick_error_stops_processing() {
+        let messages: Vec<_> = (1..=5).map(|id| add(id, 0)).collect();
+        let mut proc = MboProcessor::new();
+        let result =
+            proc.process_with_interval(&messages, SnapshotInterval::Messages(2), |_, index| {
+                Err(index)
+            });
+        assert_eq!(result.unwrap_err(), 1);
+        assert_eq!(proc.best_bid(), Some((102, 10)));
+    }
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!("250".parse(), Ok(SnapshotInterval::Messages(250)));
+        assert_eq!(
+            "1.5s".parse(),
+            Ok(SnapshotInterval::EventTime(Duration::milliseconds(1500)))
+        );
+        for invalid in ["0", "0s", "-1s", "s", "fast"] {
+            assert!(invalid.parse::<SnapshotInterval>().is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,299 @@
+//! Decoding a stream on one thread while applying it on another.
+//!
+//! Reading records, decompressing and converting them is CPU work that does not touch
+//! the books, so `MboProcessor::process_pipelined` overlaps it with processing: the
+//! calling thread pulls messages from the input and hands them in batches over a bounded
+//! channel to a thread that applies them in input order. The books, counters, observer
+//! calls and summary are exactly those of `process_messages`; only the wall time differs.
+
+use std::borrow::Borrow;
+use std::convert::Infallible;
+use std::mem;
+use std::panic;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use thiserror::Error;
+
+use crate::orderbook::{
+    ErrorPolicy, MarketByOrderMessage, MboObserver, MboProcessor, ProcessSummary,
+};
+
+/// Batch size and channel depth of `MboProcessor::process_pipelined`. Defaults to batches
+/// of 1024 messages with 8 batches queued.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PipelineOptions {
+    batch_size: usize,
+    channel_depth: usize,
+}
+
+impl Default for PipelineOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: 1024,
+            channel_depth: 8,
+        }
+    }
+}
+
+impl PipelineOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Messages handed to the processing thread at a time, at least 1. Larger batches
+    /// keep channel overhead off the hot path, smaller ones start processing sooner.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Batches decoded ahead of processing before the decoding thread waits, bounding
+    /// memory when the input is read faster than it is applied. With 0 each batch is
+    /// handed over directly.
+    pub fn with_channel_depth(mut self, channel_depth: usize) -> Self {
+        self.channel_depth = channel_depth;
+        self
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    pub fn channel_depth(&self) -> usize {
+        self.channel_depth
+    }
+}
+
+/// Reading the input of `MboProcessor::try_process_pipelined` failed.
+#[derive(Debug, Error)]
+#[error("Reading message {index} failed: {source}")]
+pub struct PipelineError<E> {
+    /// 0-based index in the input of the message that could not be read.
+    pub index: usize,
+    #[source]
+    pub source: E,
+    /// Outcome of the messages before it, which were all applied.
+    pub summary: ProcessSummary,
+}
+
+impl<O: MboObserver + Send> MboProcessor<O> {
+    /// Processes a stream of messages like `process_messages`, reading it on the calling
+    /// thread while another thread applies it.
+    ///
+    /// Pays off when producing the messages is expensive, e.g. a lazy adapter over
+    /// compressed DBN records; over messages already in memory it only adds the cost
+    /// of handing them over. Failure indices are relative to the start of this call.
+    pub fn process_pipelined<I>(&mut self, messages: I, options: PipelineOptions) -> ProcessSummary
+    where
+        I: IntoIterator,
+        I::Item: Borrow<MarketByOrderMessage>,
+    {
+        let messages = messages
+            .into_iter()
+            .map(|message| Ok::<_, Infallible>(*message.borrow()));
+        match self.try_process_pipelined(messages, options) {
+            Ok(summary) => summary,
+            Err(error) => match error.source {},
+        }
+    }
+
+    /// `process_pipelined` over an input whose reads can fail. The first failed read
+    /// ends the input: the messages before it are applied and returned with the error
+    /// in `PipelineError`.
+    ///
+    /// Under `ErrorPolicy::FailFast` a failed message stops both threads, and the
+    /// summary is returned as from `process_messages` even if a later read would have
+    /// failed.
+    pub fn try_process_pipelined<I, E>(
+        &mut self,
+        messages: I,
+        options: PipelineOptions,
+    ) -> Result<ProcessSummary, PipelineError<E>>
+    where
+        I: IntoIterator<Item = Result<MarketByOrderMessage, E>>,
+    {
+        let fail_fast = self.error_policy() == ErrorPolicy::FailFast;
+        let (sender, receiver) = mpsc::sync_channel(options.channel_depth);
+        let (summary, failed) = thread::scope(|scope| {
+            let worker = scope.spawn(|| apply_batches(self, receiver));
+
+            let mut failed = None;
+            let mut batch = Vec::with_capacity(options.batch_size);
+            for (index, message) in messages.into_iter().enumerate() {
+                match message {
+                    Ok(message) => batch.push(message),
+                    Err(source) => {
+                        failed = Some((index, source));
+                        break;
+                    }
+                }
+                if batch.len() == options.batch_size {
+                    let full = mem::replace(&mut batch, Vec::with_capacity(options.batch_size));
+                    // The processing thread hangs up once it stops on a failure
+                    if sender.send(full).is_err() {
+                        break;
+                    }
+                }
+            }
+            if !batch.is_empty() {
+                let _ = sender.send(batch);
+            }
+            drop(sender);
+            let summary = worker
+                .join()
+                .unwrap_or_else(|payload| panic::resume_unwind(payload));
+            (summary, failed)
+        });
+
+        match failed {
+            // Processing stopped before reaching the failed read
+            Some(_) if fail_fast && !summary.is_success() => Ok(summary),
+            Some((index, source)) => Err(PipelineError {
+                index,
+                source,
+                summary,
+            }),
+            None => Ok(summary),
+        }
+    }
+}
+
+/// Applies batches in order until the channel closes or, under fail-fast, a message
+/// fails.
+fn apply_batches<O: MboObserver>(
+    processor: &mut MboProcessor<O>,
+    batches: Receiver<Vec<MarketByOrderMessage>>,
+) -> ProcessSummary {
+    let fail_fast = processor.error_policy() == ErrorPolicy::FailFast;
+    let mut summary = ProcessSummary::default();
+    let mut offset = 0;
+    for batch in batches {
+        summary.append(processor.process_messages(&batch), offset);
+        if fail_fast && !summary.is_success() {
+            break;
+        }
+        offset += batch.len();
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::generators::{MessageMix, make_multi_instrument_messages};
+    use crate::orderbook::{Action, ValidationPolicy};
+
+    fn failure_indices(summary: &ProcessSummary) -> Vec<usize> {
+        summary
+            .failures
+            .iter()
+            .map(|failure| failure.index)
+            .collect()
+    }
+
+    fn assert_same(
+        (processor, summary): (&MboProcessor, &ProcessSummary),
+        (expected, expected_summary): (&MboProcessor, &ProcessSummary),
+    ) {
+        assert_eq!(summary.processed, expected_summary.processed);
+        assert_eq!(summary.action_counts, expected_summary.action_counts);
+        assert_eq!(failure_indices(summary), failure_indices(expected_summary));
+        assert_eq!(processor.instruments(), expected.instruments());
+        for instrument_id in expected.instruments() {
+            assert_eq!(processor.book(instrument_id), expected.book(instrument_id));
+            assert_eq!(
+                processor.instrument_stats(instrument_id),
+                expected.instrument_stats(instrument_id)
+            );
+        }
+        assert_eq!(processor.stats(), expected.stats());
+        assert_eq!(
+            processor.last_sequence_number(),
+            expected.last_sequence_number()
+        );
+        assert_eq!(processor.last_event_time(), expected.last_event_time());
+    }
+
+    #[test]
+    fn test_pipelined_matches_sequential() {
+        let mut messages = make_multi_instrument_messages(11, 3, 20_000, MessageMix::default());
+        // Invalid Trades, which fail without changing the books
+        let trades = messages
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| m.action == Action::Trade);
+        let invalid: Vec<usize> = trades.map(|(index, _)| index).step_by(50).collect();
+        for &index in &invalid {
+            messages[index].size = u32::MAX;
+        }
+        let processor = || {
+            MboProcessor::new()
+                .with_validation_policy(ValidationPolicy::Reject)
+                .with_error_policy(ErrorPolicy::Collect)
+        };
+        let mut expected = processor();
+        let expected_summary = expected.process_messages(&messages);
+        assert_eq!(failure_indices(&expected_summary), invalid);
+
+        for (batch_size, channel_depth) in [(1, 0), (7, 1), (1024, 8), (50_000, 2)] {
+            let options = PipelineOptions::new()
+                .with_batch_size(batch_size)
+                .with_channel_depth(channel_depth);
+            let mut pipelined = processor();
+            let summary = pipelined.process_pipelined(&messages, options);
+            assert_same((&pipelined, &summary), (&expected, &expected_summary));
+        }
+    }
+
+    #[test]
+    fn test_read_failure_reported_with_index() {
+        let messages = make_multi_instrument_messages(5, 2, 1_000, MessageMix::default());
+        let input = messages
+            .iter()
+            .enumerate()
+            .map(|(index, &message)| match index {
+                600 => Err("truncated record"),
+                _ => Ok(message),
+            });
+        let options = PipelineOptions::new().with_batch_size(64);
+        let mut processor = MboProcessor::new();
+        let error = processor.try_process_pipelined(input, options).unwrap_err();
+        assert_eq!(error.index, 600);
+        assert_eq!(error.source, "truncated record");
+        assert!(error.to_string().contains("message 600"));
+
+        let mut expected = MboProcessor::new();
+        let expected_summary = expected.process_messages(&messages[..600]);
+        assert_same((&processor, &error.summary), (&expected, &expected_summary));
+    }
+
+    #[test]
+    fn test_fail_fast_stops_both_stages() {
+        let mut messages = make_multi_instrument_messages(3, 2, 20_000, MessageMix::default());
+        let invalid = messages
+            .iter()
+            .position(|m| m.action == Action::Trade)
+            .unwrap();
+        messages[invalid].size = u32::MAX;
+        let processor = || MboProcessor::new().with_validation_policy(ValidationPolicy::Reject);
+        let mut expected = processor();
+        let expected_summary = expected.process_messages(&messages);
+
+        // A read failure after the failed message is never reached sequentially
+        let input = messages
+            .iter()
+            .enumerate()
+            .map(|(index, &message)| match index {
+                19_000 => Err("unreachable"),
+                _ => Ok(message),
+            });
+        let mut pipelined = processor();
+        let summary = pipelined
+            .try_process_pipelined(input, PipelineOptions::new().with_batch_size(16))
+            .unwrap();
+        assert_eq!(failure_indices(&summary), vec![invalid]);
+        assert_same((&pipelined, &summary), (&expected, &expected_summary));
+    }
+}
@@ -0,0 +1,280 @@
+//! Interactive control of a paced replay: pausing, stepping, changing speed and jumping
+//! ahead in event time.
+//!
+//! `Playback` only decides when the next message should be applied; reading input,
+//! applying messages and drawing are up to the caller, which keeps the logic testable
+//! without a terminal.
+
+use std::time::{Duration, Instant};
+
+use time::OffsetDateTime;
+
+use crate::orderbook::{ReplayError, Replayer};
+
+/// Slowest speed factor `PlaybackCommand::Slower` goes down to.
+pub const MIN_PLAYBACK_SPEED: f64 = 1.0 / 64.0;
+
+/// Fastest paced speed factor; `PlaybackCommand::Faster` goes from here to unpaced.
+pub const MAX_PLAYBACK_SPEED: f64 = 1024.0;
+
+/// A request from the viewer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackCommand {
+    TogglePause,
+    /// Apply this many messages at once, then stay paused.
+    Step(u64),
+    /// Double the speed, or stop pacing above `MAX_PLAYBACK_SPEED`.
+    Faster,
+    /// Halve the speed, down to `MIN_PLAYBACK_SPEED`.
+    Slower,
+    /// Apply every message before this event time at once. Times already passed are
+    /// ignored, as playback only moves forward.
+    JumpTo(OffsetDateTime),
+}
+
+/// What to do about the next message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackStep {
+    /// Apply it now, then call `Playback::applied`.
+    Apply,
+    /// It is due after this long; wait, or handle input in the meantime.
+    Wait(Duration),
+    /// Playback is paused; wait for a command.
+    Paused,
+}
+
+/// Playback position and controls over a `Replayer` schedule.
+///
+/// Call `next` with the event time of the next message to learn whether it is due,
+/// `applied` once it is applied, and `handle` for every command.
+pub struct Playback<N = fn() -> Instant, S = fn(Duration)> {
+    replayer: Replayer<N, S>,
+    paused: bool,
+    /// Messages left to apply from `PlaybackCommand::Step`.
+    steps: u64,
+    jump_to: Option<OffsetDateTime>,
+    position: u64,
+}
+
+impl Playback {
+    /// Plays at `speed` times real time, or unpaced at 0.0, starting unpaused.
+    pub fn new(speed: f64) -> Result<Self, ReplayError> {
+        Ok(Self::with_replayer(Replayer::new(speed)?))
+    }
+}
+
+impl<N, S> Playback<N, S>
+where
+    N: FnMut() -> Instant,
+    S: FnMut(Duration),
+{
+    /// Plays on the schedule of `replayer`, e.g. one with a virtual clock for testing.
+    pub fn with_replayer(replayer: Replayer<N, S>) -> Self {
+        Self {
+            replayer,
+            paused: false,
+            steps: 0,
+            jump_to: None,
+            position: 0,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.replayer.speed()
+    }
+
+    /// Messages applied so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// The event time being jumped to, while a jump is under way.
+    pub fn jump_target(&self) -> Option<OffsetDateTime> {
+        self.jump_to
+    }
+
+    pub fn handle(&mut self, command: PlaybackCommand) {
+        match command {
+            PlaybackCommand::TogglePause => {
+                self.paused = !self.paused;
+                self.steps = 0;
+                // Resume from the next message rather than catching up on the pause
+                self.replayer.reset();
+            }
+            PlaybackCommand::Step(messages) => {
+                self.paused = true;
+                self.steps += messages;
+            }
+            PlaybackCommand::Faster => {
+                let speed = match self.speed() {
+                    0.0 => 0.0,
+                    speed if speed >= MAX_PLAYBACK_SPEED => 0.0,
+                    speed => speed * 2.0,
+                };
+                self.set_speed(speed);
+            }
+            PlaybackCommand::Slower => {
+                let speed = match self.speed() {
+                    0.0 => MAX_PLAYBACK_SPEED,
+                    speed => (speed / 2.0).max(MIN_PLAYBACK_SPEED),
+                };
+                self.set_speed(speed);
+            }
+            PlaybackCommand::JumpTo(event_time) => self.jump_to = Some(event_time),
+        }
+    }
+
+    fn set_speed(&mut self, speed: f64) {
+        self.replayer
+            .set_speed(speed)
+            .expect("playback speeds are finite and non-negative");
+    }
+
+    /// Whether the next message, at `event_time`, is due.
+    pub fn next(&mut self, event_time: OffsetDateTime) -> PlaybackStep {
+        if let Some(target) = self.jump_to {
+            if event_time < target {
+                return PlaybackStep::Apply;
+            }
+            self.jump_to = None;
+            self.replayer.reset();
+        }
+        if self.steps > 0 {
+            return PlaybackStep::Apply;
+        }
+        if self.paused {
+            return PlaybackStep::Paused;
+        }
+        match self.replayer.delay(event_time) {
+            Duration::ZERO => PlaybackStep::Apply,
+            wait => PlaybackStep::Wait(wait),
+        }
+    }
+
+    /// Records that the next message was applied.
+    pub fn applied(&mut self) {
+        self.position += 1;
+        if self.jump_to.is_none() {
+            self.steps = self.steps.saturating_sub(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    type Clock = Rc<Cell<Instant>>;
+    type VirtualPlayback = Playback<Box<dyn FnMut() -> Instant>, fn(Duration)>;
+
+    /// A playback on a virtual clock that only moves when the test advances it.
+    fn virtual_playback(speed: f64) -> (VirtualPlayback, Clock) {
+        let clock = Rc::new(Cell::new(Instant::now()));
+        let now: Box<dyn FnMut() -> Instant> = {
+            let clock = Rc::clone(&clock);
+            Box::new(move || clock.get())
+        };
+        let noop: fn(Duration) = |_| {};
+        let replayer = Replayer::with_clock(speed, now, noop).unwrap();
+        (Playback::with_replayer(replayer), clock)
+    }
+
+    fn at_millis(millis: i64) -> OffsetDateTime {
+        OffsetDateTime::UNIX_EPOCH + time::Duration::milliseconds(millis)
+    }
+
+    /// Applies every message that is due at the current time, returning how many.
+    fn drain<N: FnMut() -> Instant, S: FnMut(Duration)>(
+        playback: &mut Playback<N, S>,
+        event_millis: &[i64],
+    ) -> usize {
+        let mut applied = 0;
+        for &ms in &event_millis[playback.position() as usize..] {
+            if playback.next(at_millis(ms)) != PlaybackStep::Apply {
+                break;
+            }
+            playback.applied();
+            applied += 1;
+        }
+        applied
+    }
+
+    #[test]
+    fn test_paced_playback() {
+        let (mut playback, clock) = virtual_playback(1.0);
+        let events = [0, 0, 100, 300];
+        assert_eq!(drain(&mut playback, &events), 2);
+        assert_eq!(
+            playback.next(at_millis(100)),
+            PlaybackStep::Wait(Duration::from_millis(100))
+        );
+        clock.set(clock.get() + Duration::from_millis(100));
+        assert_eq!(drain(&mut playback, &events), 1);
+        assert_eq!(playback.position(), 3);
+    }
+
+    #[test]
+    fn test_pause_and_step() {
+        let (mut playback, clock) = virtual_playback(1.0);
+        let events = [0, 1000, 2000, 3000, 4000];
+        assert_eq!(drain(&mut playback, &events), 1);
+
+        playback.handle(PlaybackCommand::TogglePause);
+        clock.set(clock.get() + Duration::from_secs(10));
+        assert_eq!(playback.next(at_millis(1000)), PlaybackStep::Paused);
+
+        // Stepping applies regardless of the schedule and stays paused
+        playback.handle(PlaybackCommand::Step(2));
+        assert_eq!(drain(&mut playback, &events), 2);
+        assert!(playback.is_paused());
+        assert_eq!(playback.next(at_millis(3000)), PlaybackStep::Paused);
+
+        // Resuming restarts the schedule instead of catching up on the pause
+        playback.handle(PlaybackCommand::TogglePause);
+        assert_eq!(drain(&mut playback, &events), 1);
+        assert_eq!(
+            playback.next(at_millis(4000)),
+            PlaybackStep::Wait(Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn test_jump_ahead() {
+        let (mut playback, _clock) = virtual_playback(1.0);
+        let events = [0, 1000, 2000, 3000, 4000];
+        playback.handle(PlaybackCommand::TogglePause);
+        playback.handle(PlaybackCommand::JumpTo(at_millis(2500)));
+        assert_eq!(playback.jump_target(), Some(at_millis(2500)));
+        assert_eq!(drain(&mut playback, &events), 3);
+        assert_eq!(playback.jump_target(), None);
+        assert!(playback.is_paused());
+
+        // A time already passed moves nothing
+        playback.handle(PlaybackCommand::JumpTo(at_millis(1000)));
+        assert_eq!(drain(&mut playback, &events), 0);
+        assert_eq!(playback.position(), 3);
+    }
+
+    #[test]
+    fn test_speed_limits() {
+        let (mut playback, _clock) = virtual_playback(MAX_PLAYBACK_SPEED / 2.0);
+        playback.handle(PlaybackCommand::Faster);
+        assert_eq!(playback.speed(), MAX_PLAYBACK_SPEED);
+        playback.handle(PlaybackCommand::Faster);
+        assert_eq!(playback.speed(), 0.0);
+        playback.handle(PlaybackCommand::Faster);
+        assert_eq!(playback.speed(), 0.0);
+        playback.handle(PlaybackCommand::Slower);
+        assert_eq!(playback.speed(), MAX_PLAYBACK_SPEED);
+
+        let (mut playback, _clock) = virtual_playback(MIN_PLAYBACK_SPEED);
+        playback.handle(PlaybackCommand::Slower);
+        assert_eq!(playback.speed(), MIN_PLAYBACK_SPEED);
+    }
+}
@@ -0,0 +1,184 @@
+//! How the integer prices of a book are presented.
+//!
+//! Books keep prices as the feed's fixed-point integers and never convert them. A
+//! `PriceScale` only says how many of those units make 1 when a price is shown or
+//! exported, so a DBN price of `101250000000` reads `101.250000000` rather than a
+//! number no one can make sense of.
+
+use std::str::FromStr;
+
+use dbn::FIXED_PRICE_SCALE;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum PriceScaleError {
+    #[error("Price scale divisor must be positive, got {0}")]
+    NonPositiveDivisor(i64),
+
+    #[error("A price scale of {0} decimals does not fit in an i64 divisor")]
+    TooManyDecimals(u32),
+
+    #[error(
+        "Invalid price scale '{0}', expected raw, a tick size such as 1e-9 or 0.01, or a \
+         divisor such as 100"
+    )]
+    Invalid(String),
+}
+
+/// Fixed-point units per 1 of a book's integer prices, for display and export only.
+/// The default, `RAW`, shows the integers as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PriceScale {
+    divisor: i64,
+}
+
+impl Default for PriceScale {
+    fn default() -> Self {
+        Self::RAW
+    }
+}
+
+impl PriceScale {
+    /// Prices shown as the integers they are.
+    pub const RAW: Self = Self { divisor: 1 };
+
+    /// DBN prices, in units of 1e-9 (`dbn::FIXED_PRICE_SCALE`).
+    pub const DBN: Self = Self {
+        divisor: FIXED_PRICE_SCALE,
+    };
+
+    /// `divisor` units per 1, e.g. 100 for prices in cents.
+    pub fn from_divisor(divisor: i64) -> Result<Self, PriceScaleError> {
+        match divisor > 0 {
+            true => Ok(Self { divisor }),
+            false => Err(PriceScaleError::NonPositiveDivisor(divisor)),
+        }
+    }
+
+    /// Prices with `decimals` implied decimals, e.g. 9 for DBN and 2 for cents.
+    pub fn from_decimals(decimals: u32) -> Result<Self, PriceScaleError> {
+        10i64
+            .checked_pow(decimals)
+            .map(|divisor| Self { divisor })
+            .ok_or(PriceScaleError::TooManyDecimals(decimals))
+    }
+
+    pub fn divisor(&self) -> i64 {
+        self.divisor
+    }
+
+    pub fn is_raw(&self) -> bool {
+        self.divisor == 1
+    }
+
+    /// Decimals a price is shown with: the powers of ten below the divisor, so two for
+    /// 100 and nine for DBN's 1_000_000_000.
+    pub fn decimals(&self) -> usize {
+        powers_of_ten()
+            .take_while(|&unit| unit < self.divisor)
+            .count()
+    }
+
+    /// `price` in units, as a float.
+    pub fn to_f64(&self, price: i64) -> f64 {
+        price as f64 / self.divisor as f64
+    }
+
+    /// The fixed-point price nearest `value` units.
+    pub fn to_price(&self, value: f64) -> i64 {
+        (value * self.divisor as f64).round() as i64
+    }
+
+    /// `price` in units with `decimals` decimals, exact for power-of-ten divisors.
+    pub fn format(&self, price: i64) -> String {
+        let decimals = self.decimals();
+        if powers_of_ten().nth(decimals) != Some(self.divisor) {
+            return format!("{:.*}", decimals, self.to_f64(price));
+        }
+        let (units, fraction) = (price / self.divisor, (price % self.divisor).unsigned_abs());
+        let sign = if price < 0 && units == 0 { "-" } else { "" };
+        match decimals {
+            0 => price.to_string(),
+            _ => format!("{sign}{units}.{fraction:0decimals$}"),
+        }
+    }
+}
+
+fn powers_of_ten() -> impl Iterator<Item = i64> {
+    std::iter::successors(Some(1i64), |unit| unit.checked_mul(10))
+}
+
+impl FromStr for PriceScale {
+    type Err = PriceScaleError;
+
+    /// Parses `raw`, a tick size of at most 1 such as `1e-9` or `0.01`, or a divisor
+    /// such as `100`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || PriceScaleError::Invalid(s.to_owned());
+        if s.eq_ignore_ascii_case("raw") {
+            return Ok(Self::RAW);
+        }
+        if let Ok(divisor) = s.parse::<i64>() {
+            return Self::from_divisor(divisor);
+        }
+        let tick: f64 = s.parse().map_err(|_| invalid())?;
+        if !(tick > 0.0 && tick <= 1.0) {
+            return Err(invalid());
+        }
+        let divisor = (1.0 / tick).round();
+        match divisor < i64::MAX as f64 {
+            true => Self::from_divisor(divisor as i64),
+            false => Err(invalid()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formats_dbn_and_tick_prices() {
+        assert_eq!(PriceScale::DBN.decimals(), 9);
+        assert_eq!(PriceScale::DBN.format(101_250_000_000), "101.250000000");
+        assert_eq!(PriceScale::DBN.format(-500_000_000), "-0.500000000");
+        assert_eq!(PriceScale::DBN.to_f64(99_500_000_000), 99.5);
+        assert_eq!(PriceScale::DBN.to_price(99.5), 99_500_000_000);
+
+        let cents = PriceScale::from_decimals(2).unwrap();
+        assert_eq!(cents.format(10_125), "101.25");
+        assert_eq!(cents.format(-1_205), "-12.05");
+        assert_eq!(cents.to_f64(10_125), 101.25);
+
+        assert_eq!(PriceScale::RAW.format(10_125), "10125");
+        assert_eq!(PriceScale::default(), PriceScale::RAW);
+        // Quarters, rounded to one decimal
+        let quarters = PriceScale::from_divisor(4).unwrap();
+        assert_eq!(
+            (quarters.decimals(), quarters.format(10)),
+            (1, "2.5".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("1e-9".parse(), Ok(PriceScale::DBN));
+        assert_eq!("1000000000".parse(), Ok(PriceScale::DBN));
+        assert_eq!("0.01".parse(), PriceScale::from_decimals(2));
+        assert_eq!("raw".parse(), Ok(PriceScale::RAW));
+        assert_eq!(
+            "0".parse::<PriceScale>(),
+            Err(PriceScaleError::NonPositiveDivisor(0))
+        );
+        assert!(matches!(
+            "10.5".parse::<PriceScale>(),
+            Err(PriceScaleError::Invalid(_))
+        ));
+        assert!("cents".parse::<PriceScale>().is_err());
+        assert_eq!(
+            PriceScale::from_decimals(19),
+            Err(PriceScaleError::TooManyDecimals(19))
+        );
+    }
+}
@@ -0,0 +1,173 @@
+//! Reporting progress through long streams of messages.
+//!
+//! The library only counts messages; how progress is shown, if at all, is up to the
+//! `ProgressSink` passed in, so processing stays free of any UI.
+
+use std::borrow::Borrow;
+
+use crate::orderbook::{MarketByOrderMessage, MboObserver, MboProcessor, ProcessSummary};
+
+/// Messages between calls to `ProgressSink::progress`, unless the sink asks otherwise.
+pub const DEFAULT_PROGRESS_INTERVAL: u64 = 100_000;
+
+/// Receives the number of messages read so far as a stream is processed.
+pub trait ProgressSink {
+    /// Messages between calls to `progress`; zero is taken as 1.
+    fn interval(&self) -> u64 {
+        DEFAULT_PROGRESS_INTERVAL
+    }
+
+    /// Called after every `interval` messages with the count so far.
+    fn progress(&mut self, messages: u64);
+
+    /// Called once when processing ends, with the final count.
+    fn finish(&mut self, messages: u64) {
+        let _ = messages;
+    }
+}
+
+impl ProgressSink for () {
+    fn progress(&mut self, _messages: u64) {}
+}
+
+impl<S: ProgressSink + ?Sized> ProgressSink for &mut S {
+    fn interval(&self) -> u64 {
+        (**self).interval()
+    }
+
+    fn progress(&mut self, messages: u64) {
+        (**self).progress(messages)
+    }
+
+    fn finish(&mut self, messages: u64) {
+        (**self).finish(messages)
+    }
+}
+
+/// Counts the items pulled through it, reporting to `sink` every `sink.interval()`.
+///
+/// Iterate by reference with `by_ref` to keep the adapter, then call `finish` once the
+/// stream is done with, whether or not it was read to the end.
+pub struct WithProgress<I, S: ProgressSink> {
+    inner: I,
+    sink: S,
+    interval: u64,
+    count: u64,
+}
+
+impl<I: Iterator, S: ProgressSink> WithProgress<I, S> {
+    pub fn new(inner: impl IntoIterator<IntoIter = I>, sink: S) -> Self {
+        let interval = sink.interval().max(1);
+        Self {
+            inner: inner.into_iter(),
+            sink,
+            interval,
+            count: 0,
+        }
+    }
+
+    /// Items pulled so far.
+    pub fn pulled(&self) -> u64 {
+        self.count
+    }
+
+    /// Reports the final count to the sink and returns it.
+    pub fn finish(mut self) -> S {
+        self.sink.finish(self.count);
+        self.sink
+    }
+}
+
+impl<I: Iterator, S: ProgressSink> Iterator for WithProgress<I, S> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.inner.next()?;
+        self.count += 1;
+        if self.count.is_multiple_of(self.interval) {
+            self.sink.progress(self.count);
+        }
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<O: MboObserver> MboProcessor<O> {
+    /// Processes `messages` like `process_messages`, reporting the messages read to
+    /// `sink` as it goes.
+    pub fn process_messages_with_progress<I, S>(&mut self, messages: I, sink: S) -> ProcessSummary
+    where
+        I: IntoIterator,
+        I::Item: Borrow<MarketByOrderMessage>,
+        S: ProgressSink,
+    {
+        let mut messages = WithProgress::new(messages, sink);
+        let summary = self.process_messages(messages.by_ref());
+        messages.finish();
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use time::{Duration, OffsetDateTime};
+
+    use crate::orderbook::{Action, Side};
+
+    /// Records every call it receives.
+    #[derive(Debug, Default)]
+    struct RecordingSink {
+        progress: Vec<u64>,
+        finished: Vec<u64>,
+    }
+
+    impl ProgressSink for RecordingSink {
+        fn interval(&self) -> u64 {
+            2
+        }
+
+        fn progress(&mut self, messages: u64) {
+            self.progress.push(messages);
+        }
+
+        fn finish(&mut self, messages: u64) {
+            self.finished.push(messages);
+        }
+    }
+
+    fn add(order_id: u64) -> MarketByOrderMessage {
+        let event_time = OffsetDateTime::UNIX_EPOCH + Duration::seconds(order_id as i64);
+        MarketByOrderMessage {
+            sequence: order_id as u32,
+            ..MarketByOrderMessage::test(Action::Add, Side::Bid, 100, order_id, 10).at(event_time)
+        }
+    }
+
+    #[test]
+    fn test_reports_every_interval_and_on_finish() {
+        let messages: Vec<_> = (1..=5).map(add).collect();
+        let mut sink = RecordingSink::default();
+        let mut proc = MboProcessor::new();
+        let summary = proc.process_messages_with_progress(&messages, &mut sink);
+        assert_eq!(summary.processed, 5);
+        assert_eq!(sink.progress, vec![2, 4]);
+        assert_eq!(sink.finished, vec![5]);
+    }
+
+    #[test]
+    fn test_finish_after_stopping_early() {
+        let mut tracked = WithProgress::new((1..=10).map(add), RecordingSink::default());
+        let mut proc = MboProcessor::new();
+        proc.process_messages(tracked.by_ref().take(3));
+        assert_eq!(tracked.pulled(), 3);
+        let sink = tracked.finish();
+        assert_eq!(sink.progress, vec![2]);
+        assert_eq!(sink.finished, vec![3]);
+        assert_eq!(proc.best_bid(), Some((100, 30)));
+    }
+}
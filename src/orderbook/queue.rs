@@ -0,0 +1,471 @@
+//! Storage for the orders of a price level, and reuse of emptied levels.
+//!
+//! A level's queue is a slab of slots holding its orders, linked front to back in
+//! `(sequence, order_id)` order. The level indexes each order's slot by order id, so
+//! an order leaving from anywhere in the queue is unlinked in constant time, and its
+//! slot goes on a free list for the next order to join; the `cancel_in_queue` bench
+//! compares cancels at the front and in the middle of deep queues. New orders almost
+//! always join at the back; one that belongs further forward is placed by walking back
+//! from the tail. Levels that empty are kept in the book's `LevelPool` with their slabs,
+//! so levels that come and go near the touch do not allocate each time they reappear.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::orderbook::Order;
+use crate::orderbook::book::OrderLevel;
+
+/// Most emptied levels a book keeps for reuse.
+const MAX_SPARE_LEVELS: usize = 64;
+
+/// Largest queue capacity, in orders, of an emptied level worth keeping; a level that
+/// once held more gives its memory back instead.
+const MAX_SPARE_CAPACITY: usize = 256;
+
+/// The end of a list of slots.
+const NIL: u32 = u32::MAX;
+
+/// Queue position key: sequence first, then order id for orders sharing a sequence
+/// (e.g. SNAPSHOT-phase Add records).
+fn key(order: &Order) -> (u32, u64) {
+    (order.sequence, order.order_id)
+}
+
+/// A slot of the slab: a queued order and its neighbours, or a free slot whose `next`
+/// is the next free one.
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    order: Option<Order>,
+    prev: u32,
+    next: u32,
+}
+
+/// The orders of one price level in queue order.
+///
+/// Two queues are equal if they hold the same orders in the same order, whatever
+/// slots they occupy. They serialize as their orders front to back.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "QueueRecord", into = "QueueRecord")]
+pub(crate) struct OrderQueue {
+    slots: Vec<Slot>,
+    head: u32,
+    tail: u32,
+    /// First free slot, the rest linked through `next`.
+    free: u32,
+    len: usize,
+    /// Total size of the orders.
+    qty: u64,
+}
+
+impl Default for OrderQueue {
+    fn default() -> Self {
+        Self {
+            slots: Vec::new(),
+            head: NIL,
+            tail: NIL,
+            free: NIL,
+            len: 0,
+            qty: 0,
+        }
+    }
+}
+
+impl OrderQueue {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.capacity()
+    }
+
+    /// Total size of the orders, kept up to date by every change.
+    pub fn qty(&self) -> u64 {
+        self.qty
+    }
+
+    fn order(&self, slot: u32) -> &Order {
+        self.slots[slot as usize]
+            .order
+            .as_ref()
+            .expect("linked slots hold an order")
+    }
+
+    /// The order in `slot` if it is `order_id`'s.
+    pub fn get(&self, slot: u32, order_id: u64) -> Option<&Order> {
+        self.slots
+            .get(slot as usize)?
+            .order
+            .as_ref()
+            .filter(|order| order.order_id == order_id)
+    }
+
+    /// Sets the size of `order_id`'s order in `slot`, keeping its place.
+    pub fn set_size(&mut self, slot: u32, order_id: u64, size: u64) -> Option<&Order> {
+        let order = self
+            .slots
+            .get_mut(slot as usize)?
+            .order
+            .as_mut()
+            .filter(|order| order.order_id == order_id)?;
+        self.qty = self.qty - order.size + size;
+        order.size = size;
+        Some(order)
+    }
+
+    /// Index in the queue of `order_id`'s order in `slot`, counted from the front. O(n).
+    pub fn position(&self, slot: u32, order_id: u64) -> Option<usize> {
+        self.get(slot, order_id)?;
+        self.entries().position(|(queued, _)| queued == slot)
+    }
+
+    /// Queues `order` at its place, replacing an order with the same key, and returns
+    /// its slot.
+    pub fn insert(&mut self, order: Order) -> u32 {
+        // Orders nearly always arrive in sequence, so look for the place from the back
+        let mut before = self.tail;
+        while before != NIL {
+            let queued = self.order(before);
+            if key(queued) < key(&order) {
+                break;
+            }
+            if key(queued) == key(&order) {
+                self.qty = self.qty - queued.size + order.size;
+                self.slots[before as usize].order = Some(order);
+                return before;
+            }
+            before = self.slots[before as usize].prev;
+        }
+        let after = match before {
+            NIL => self.head,
+            before => self.slots[before as usize].next,
+        };
+        let slot = self.allocate(Slot {
+            order: Some(order),
+            prev: before,
+            next: after,
+        });
+        match before {
+            NIL => self.head = slot,
+            before => self.slots[before as usize].next = slot,
+        }
+        match after {
+            NIL => self.tail = slot,
+            after => self.slots[after as usize].prev = slot,
+        }
+        self.len += 1;
+        self.qty += order.size;
+        slot
+    }
+
+    fn allocate(&mut self, slot: Slot) -> u32 {
+        match self.free {
+            NIL => {
+                self.slots.push(slot);
+                (self.slots.len() - 1) as u32
+            }
+            free => {
+                self.free = self.slots[free as usize].next;
+                self.slots[free as usize] = slot;
+                free
+            }
+        }
+    }
+
+    /// Unlinks `order_id`'s order from `slot` and frees the slot.
+    pub fn remove(&mut self, slot: u32, order_id: u64) -> Option<Order> {
+        self.get(slot, order_id)?;
+        let Slot { order, prev, next } = self.slots[slot as usize];
+        match prev {
+            NIL => self.head = next,
+            prev => self.slots[prev as usize].next = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            next => self.slots[next as usize].prev = prev,
+        }
+        self.len -= 1;
+        self.qty -= order.map_or(0, |order| order.size);
+        if self.len == 0 {
+            // Start the next orders from the first slot, keeping the slab's memory
+            self.slots.clear();
+            self.free = NIL;
+        } else {
+            self.slots[slot as usize] = Slot {
+                order: None,
+                prev: NIL,
+                next: self.free,
+            };
+            self.free = slot;
+        }
+        order
+    }
+
+    pub fn front(&self) -> Option<&Order> {
+        (self.head != NIL).then(|| self.order(self.head))
+    }
+
+    pub fn back(&self) -> Option<&Order> {
+        (self.tail != NIL).then(|| self.order(self.tail))
+    }
+
+    /// The orders front to back with their slots.
+    pub fn entries(&self) -> Entries<'_> {
+        Entries {
+            queue: self,
+            front: self.head,
+            back: self.tail,
+            remaining: self.len,
+        }
+    }
+
+    /// The orders front to back.
+    pub fn values(&self) -> impl DoubleEndedIterator<Item = &Order> {
+        self.entries().map(|(_, order)| order)
+    }
+
+    /// The orders ahead of the one at `index`, front first.
+    pub fn ahead(&self, index: usize) -> impl Iterator<Item = &Order> {
+        self.values().take(index)
+    }
+
+    /// The orders front to back with their keys, for corrupting a book in tests. Size
+    /// changes made through it leave the queue's `qty` as it was.
+    #[cfg(test)]
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = ((u32, u64), &mut Order)> {
+        let order: Vec<u32> = self.entries().map(|(slot, _)| slot).collect();
+        let mut slots: Vec<Option<&mut Slot>> = self.slots.iter_mut().map(Some).collect();
+        order.into_iter().map(move |slot| {
+            let order = slots[slot as usize]
+                .take()
+                .and_then(|slot| slot.order.as_mut())
+                .expect("linked slots hold an order");
+            (key(order), order)
+        })
+    }
+}
+
+/// Iterator over the orders of an `OrderQueue` and their slots, front to back.
+pub(crate) struct Entries<'a> {
+    queue: &'a OrderQueue,
+    front: u32,
+    back: u32,
+    remaining: usize,
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = (u32, &'a Order);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let slot = self.front;
+        self.front = self.queue.slots[slot as usize].next;
+        Some((slot, self.queue.order(slot)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl DoubleEndedIterator for Entries<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let slot = self.back;
+        self.back = self.queue.slots[slot as usize].prev;
+        Some((slot, self.queue.order(slot)))
+    }
+}
+
+impl ExactSizeIterator for Entries<'_> {}
+
+impl PartialEq for OrderQueue {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.values().eq(other.values())
+    }
+}
+
+impl Eq for OrderQueue {}
+
+impl fmt::Debug for OrderQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.values()).finish()
+    }
+}
+
+/// The serialized form of an `OrderQueue`: its orders front to back.
+#[derive(Serialize, Deserialize)]
+struct QueueRecord {
+    orders: Vec<Order>,
+}
+
+impl From<QueueRecord> for OrderQueue {
+    fn from(record: QueueRecord) -> Self {
+        let mut queue = Self::default();
+        for order in record.orders {
+            queue.insert(order);
+        }
+        queue
+    }
+}
+
+impl From<OrderQueue> for QueueRecord {
+    fn from(queue: OrderQueue) -> Self {
+        Self {
+            orders: queue.values().copied().collect(),
+        }
+    }
+}
+
+/// Emptied price levels kept for reuse, with the buffers of their queue and index.
+#[derive(Debug, Default)]
+pub(crate) struct LevelPool {
+    levels: Vec<OrderLevel>,
+}
+
+impl LevelPool {
+    /// An empty level at `price`, reusing a spare one if there is any.
+    pub fn take(&mut self, price: i64) -> OrderLevel {
+        match self.levels.pop() {
+            Some(mut level) => {
+                level.price = price;
+                level
+            }
+            None => OrderLevel::new(price),
+        }
+    }
+
+    /// Keeps `level` for reuse if it is empty, small and the pool has room.
+    pub fn give(&mut self, level: OrderLevel) {
+        if level.is_empty()
+            && level.queue.capacity() <= MAX_SPARE_CAPACITY
+            && self.levels.len() < MAX_SPARE_LEVELS
+        {
+            self.levels.push(level);
+        }
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.levels.len()
+    }
+}
+
+/// Spare levels hold no orders, so two books compare equal whatever their pools.
+impl PartialEq for LevelPool {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for LevelPool {}
+
+/// Spare levels are memory, not state, so a clone starts with an empty pool.
+impl Clone for LevelPool {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::{OrderBook, Side};
+
+    fn order(order_id: u64, sequence: u32) -> Order {
+        Order {
+            order_id,
+            side: Side::Bid,
+            price: 100,
+            size: 10,
+            sequence,
+            owner: None,
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn test_queue_keeps_sequence_order() {
+        let mut queue = OrderQueue::default();
+        let slots: Vec<u32> = [(1, 5), (2, 9), (3, 7), (4, 7), (5, 1)]
+            .into_iter()
+            .map(|(order_id, sequence)| queue.insert(order(order_id, sequence)))
+            .collect();
+        let ids = |queue: &OrderQueue| queue.values().map(|o| o.order_id).collect::<Vec<_>>();
+        assert_eq!(ids(&queue), vec![5, 1, 3, 4, 2]);
+        assert_eq!(slots, vec![0, 1, 2, 3, 4]);
+        assert_eq!(queue.position(slots[3], 4), Some(3));
+        // A slot only finds its own order
+        assert_eq!(queue.position(slots[3], 2), None);
+        assert_eq!(queue.get(9, 2), None);
+
+        // Same key replaces, and removal from the middle keeps the order
+        let replaced = queue.insert(Order {
+            size: 3,
+            ..order(3, 7)
+        });
+        assert_eq!(replaced, slots[2]);
+        assert_eq!((queue.len(), queue.qty()), (5, 43));
+        assert_eq!(queue.get(slots[2], 3).map(|o| o.size), Some(3));
+        assert_eq!(queue.remove(slots[2], 3).map(|o| o.order_id), Some(3));
+        assert_eq!(queue.remove(slots[2], 3), None);
+        assert_eq!(ids(&queue), vec![5, 1, 4, 2]);
+        assert_eq!(
+            queue.ahead(2).map(|o| o.order_id).collect::<Vec<_>>(),
+            vec![5, 1]
+        );
+        assert_eq!(queue.values().next_back().map(|o| o.order_id), Some(2));
+
+        // The freed slot is reused, and the ends unlink too
+        assert_eq!(queue.insert(order(6, 8)), slots[2]);
+        assert_eq!(ids(&queue), vec![5, 1, 4, 6, 2]);
+        queue.remove(slots[4], 5);
+        queue.remove(slots[1], 2);
+        assert_eq!(ids(&queue), vec![1, 4, 6]);
+        assert_eq!(queue.set_size(slots[3], 4, 4).map(|o| o.size), Some(4));
+        assert_eq!(queue.set_size(slots[3], 6, 4), None);
+        assert_eq!(queue.qty(), 24);
+        assert_eq!(queue.front().map(|o| o.order_id), Some(1));
+        assert_eq!(queue.back().map(|o| o.order_id), Some(6));
+
+        // Equality and serialization see the orders, not the slots
+        let mut compact = OrderQueue::default();
+        for (order_id, sequence) in [(1, 5), (4, 7), (6, 8)] {
+            compact.insert(order(order_id, sequence));
+        }
+        compact.set_size(1, 4, 4);
+        assert_eq!(queue, compact);
+        let json = serde_json::to_string(&queue).unwrap();
+        assert_eq!(json, serde_json::to_string(&compact).unwrap());
+        assert_eq!(serde_json::from_str::<OrderQueue>(&json).unwrap(), queue);
+    }
+
+    #[test]
+    fn test_emptied_levels_reused() {
+        let mut book = OrderBook::new();
+        book.add_order(order(1, 1));
+        book.remove_order(1);
+        assert_eq!(book.spare_levels.len(), 1);
+
+        // The spare level comes back at another price, empty
+        book.add_order(Order {
+            price: 99,
+            ..order(2, 2)
+        });
+        assert_eq!(book.spare_levels.len(), 0);
+        assert_eq!(book.top_n_bids(5), vec![(99, 10)]);
+        assert_eq!(book.bids()[&99].price, 99);
+        assert_eq!(book.validate(), Ok(()));
+    }
+}
@@ -0,0 +1,228 @@
+//! Time-weighted statistics of the best bid and offer over a session.
+//!
+//! Averaging the spread per message lets bursts of quote flickering dominate, so
+//! `QuoteStats` weights each quote by how long it was in force instead.
+
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+
+#[cfg(feature = "polars")]
+use polars::prelude::{DataFrame, PolarsResult, df};
+
+use crate::orderbook::Bbo;
+
+/// Accumulates time-weighted spread and quote-state statistics from BBO changes.
+///
+/// Each quote passed to `update` is in force from its timestamp until the next one,
+/// and the last until the session end given to `summary`. Time before the first
+/// two-sided quote is not observed; after it, time with either side empty counts as
+/// one-sided. Spread and mid are averaged over two-sided time, locked and crossed
+/// quotes included.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuoteStats {
+    /// The last quote recorded, initially empty.
+    quote: Bbo,
+    /// When `quote` took effect, from the first two-sided quote on.
+    since: Option<OffsetDateTime>,
+    totals: QuoteTotals,
+}
+
+/// Sums over the intervals that have ended, in nanoseconds.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct QuoteTotals {
+    updates: u64,
+    observed: i128,
+    two_sided: i128,
+    locked_or_crossed: i128,
+    /// Spread times duration.
+    spread: i128,
+    /// Bid plus ask times duration, so twice the mid.
+    twice_mid: i128,
+    min_spread: Option<i64>,
+    max_spread: Option<i64>,
+}
+
+impl QuoteTotals {
+    fn add_interval(&mut self, bbo: Bbo, nanos: i128) {
+        self.observed += nanos;
+        if let (Some((bid, _)), Some((ask, _))) = (bbo.bid, bbo.ask) {
+            self.two_sided += nanos;
+            if ask <= bid {
+                self.locked_or_crossed += nanos;
+            }
+            self.spread += (i128::from(ask) - i128::from(bid)) * nanos;
+            self.twice_mid += (i128::from(bid) + i128::from(ask)) * nanos;
+        }
+    }
+}
+
+/// Statistics of a `QuoteStats` up to a session end. Averages and shares are `None`
+/// without time to weigh them by, so a session of zero length never divides by zero.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct QuoteSummary {
+    /// BBO changes recorded, including those before the first two-sided quote.
+    pub updates: u64,
+    /// Time from the first two-sided quote to the session end.
+    pub observed: Duration,
+    /// Time-weighted average spread over two-sided time, in price units.
+    pub avg_spread: Option<f64>,
+    /// Time-weighted average mid over two-sided time.
+    pub avg_mid: Option<f64>,
+    /// Percentage of observed time the book was locked or crossed.
+    pub locked_or_crossed_pct: Option<f64>,
+    /// Percentage of observed time either side was empty.
+    pub one_sided_pct: Option<f64>,
+    /// Narrowest spread of any two-sided quote, however briefly in force.
+    pub min_spread: Option<i64>,
+    /// Widest spread of any two-sided quote.
+    pub max_spread: Option<i64>,
+}
+
+impl QuoteStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records `bbo` as the quote from `ts_event` on, ending the previous one. A quote
+    /// equal to the one in force changes nothing, and a timestamp before the previous
+    /// one gives it no time.
+    pub fn update(&mut self, ts_event: OffsetDateTime, bbo: Bbo) {
+        if bbo == self.quote {
+            return;
+        }
+        match self.since {
+            Some(since) => {
+                let nanos = (ts_event - since).whole_nanoseconds().max(0);
+                self.totals.add_interval(self.quote, nanos);
+                self.since = Some(since.max(ts_event));
+            }
+            None if bbo.bid.is_some() && bbo.ask.is_some() => self.since = Some(ts_event),
+            None => {}
+        }
+        self.quote = bbo;
+        self.totals.updates += 1;
+        if let (Some((bid, _)), Some((ask, _))) = (bbo.bid, bbo.ask) {
+            let spread = ask.saturating_sub(bid);
+            let totals = &mut self.totals;
+            totals.min_spread = Some(totals.min_spread.map_or(spread, |min| min.min(spread)));
+            totals.max_spread = Some(totals.max_spread.map_or(spread, |max| max.max(spread)));
+        }
+    }
+
+    /// The statistics with the quote in force lasting until `session_end`.
+    pub fn summary(&self, session_end: OffsetDateTime) -> QuoteSummary {
+        let mut totals = self.totals;
+        if let Some(since) = self.since {
+            let nanos = (session_end - since).whole_nanoseconds().max(0);
+            totals.add_interval(self.quote, nanos);
+        }
+        let ratio = |sum: i128, nanos: i128| (nanos > 0).then(|| sum as f64 / nanos as f64);
+        QuoteSummary {
+            updates: totals.updates,
+            observed: Duration::nanoseconds(totals.observed as i64),
+            avg_spread: ratio(totals.spread, totals.two_sided),
+            avg_mid: ratio(totals.twice_mid, totals.two_sided).map(|twice| twice / 2.0),
+            locked_or_crossed_pct: ratio(100 * totals.locked_or_crossed, totals.observed),
+            one_sided_pct: ratio(100 * (totals.observed - totals.two_sided), totals.observed),
+            min_spread: totals.min_spread,
+            max_spread: totals.max_spread,
+        }
+    }
+}
+
+impl QuoteSummary {
+    /// The summary as a one-row DataFrame, with `observed` in nanoseconds and missing
+    /// statistics null.
+    #[cfg(feature = "polars")]
+    pub fn to_dataframe(&self) -> PolarsResult<DataFrame> {
+        df!(
+            "updates" => vec![self.updates],
+            "observed_ns" => vec![self.observed.whole_nanoseconds() as i64],
+            "avg_spread" => vec![self.avg_spread],
+            "avg_mid" => vec![self.avg_mid],
+            "locked_or_crossed_pct" => vec![self.locked_or_crossed_pct],
+            "one_sided_pct" => vec![self.one_sided_pct],
+            "min_spread" => vec![self.min_spread],
+            "max_spread" => vec![self.max_spread],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: i64) -> OffsetDateTime {
+        OffsetDateTime::UNIX_EPOCH + Duration::seconds(seconds)
+    }
+
+    fn quote(bid: Option<i64>, ask: Option<i64>) -> Bbo {
+        Bbo {
+            bid: bid.map(|price| (price, 10)),
+            ask: ask.map(|price| (price, 10)),
+        }
+    }
+
+    #[test]
+    fn test_scripted_quotes() {
+        let mut stats = QuoteStats::new();
+        // Before the first two-sided quote, not observed
+        stats.update(at(0), quote(Some(100), None));
+        stats.update(at(5), quote(Some(100), None));
+        // Spread 2 for 10 s, flickering to 4 and back at 20 s
+        stats.update(at(10), quote(Some(100), Some(102)));
+        stats.update(at(20), quote(Some(100), Some(104)));
+        stats.update(at(20), quote(Some(100), Some(102)));
+        // Unchanged
+        stats.update(at(25), quote(Some(100), Some(102)));
+        // Spread 4 for 5 s, locked for 3 s, one-sided for 2 s, spread 1 for 10 s
+        stats.update(at(30), quote(Some(100), Some(104)));
+        stats.update(at(35), quote(Some(102), Some(102)));
+        stats.update(at(38), quote(None, Some(102)));
+        stats.update(at(40), quote(Some(101), Some(102)));
+
+        let summary = stats.summary(at(50));
+        assert_eq!(summary.updates, 8);
+        assert_eq!(summary.observed, Duration::seconds(40));
+        // (2 * 20 + 4 * 5 + 0 * 3 + 1 * 10) / 38
+        assert_eq!(summary.avg_spread, Some(70.0 / 38.0));
+        // (101 * 20 + 102 * 5 + 102 * 3 + 101.5 * 10) / 38
+        assert_eq!(summary.avg_mid, Some(3851.0 / 38.0));
+        assert_eq!(summary.locked_or_crossed_pct, Some(7.5));
+        assert_eq!(summary.one_sided_pct, Some(5.0));
+        assert_eq!((summary.min_spread, summary.max_spread), (Some(0), Some(4)));
+    }
+
+    #[test]
+    fn test_zero_duration_session() {
+        let mut stats = QuoteStats::new();
+        assert_eq!(
+            stats.summary(at(5)),
+            QuoteSummary {
+                updates: 0,
+                ..QuoteSummary::default()
+            }
+        );
+
+        stats.update(at(5), quote(Some(100), Some(101)));
+        stats.update(at(5), quote(Some(100), Some(103)));
+        let summary = stats.summary(at(5));
+        assert_eq!(summary.observed, Duration::ZERO);
+        assert_eq!(summary.avg_spread, None);
+        assert_eq!(summary.one_sided_pct, None);
+        assert_eq!((summary.min_spread, summary.max_spread), (Some(1), Some(3)));
+    }
+
+    #[cfg(feature = "polars")]
+    #[test]
+    fn test_summary_dataframe() {
+        let mut stats = QuoteStats::new();
+        stats.update(at(0), quote(Some(100), Some(102)));
+        let df = stats.summary(at(10)).to_dataframe().unwrap();
+        assert_eq!(df.shape(), (1, 8));
+        let avg_spread = df.column("avg_spread").unwrap().f64().unwrap();
+        assert_eq!(avg_spread.get(0), Some(2.0));
+        let observed = df.column("observed_ns").unwrap().i64().unwrap();
+        assert_eq!(observed.get(0), Some(10_000_000_000));
+    }
+}
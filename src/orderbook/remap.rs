@@ -0,0 +1,181 @@
+//! Replacing venue order ids, e.g. to share captured data without them.
+//!
+//! A venue's order ids can reveal how it sequences orders or which id ranges belong to
+//! which participant. `IdRemapper` gives every id a new one the first time it is seen,
+//! whatever the message, so a stream that starts mid-session is remapped as
+//! consistently as a whole one and every Cancel, Modify and Fill keeps referring to the
+//! same order. Written out with `DbnWriter`, the result is a sanitized copy of the data
+//! that builds the same books.
+
+use std::borrow::Borrow;
+use std::collections::HashSet;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::orderbook::book::OrderMap;
+use crate::orderbook::{MarketByOrderMessage, SYNTHETIC_ORDER_ID_MIN};
+
+/// Deterministic mapping of original order ids to new ones.
+///
+/// By default the new ids are dense: 1, 2, 3, ... in order of first sight, which keeps
+/// only the order in which ids appeared. With a seed they are drawn at random instead,
+/// so not even that can be read off them, while the same seed and stream still give the
+/// same ids. Either way new ids stay below the synthetic range (`SYNTHETIC_ORDER_ID_MIN`),
+/// and id 0, which Trades and Clears carry for no order, is kept.
+///
+/// Orders with the same sequence, such as a snapshot's Adds, queue by order id, so their
+/// order within a level can change; levels and their totals cannot.
+#[derive(Debug, Default)]
+pub struct IdRemapper {
+    ids: OrderMap<u64>,
+    /// Source of seeded ids; ids are sequential without one.
+    rng: Option<ChaCha8Rng>,
+    /// Seeded ids given out so far, so that none is given twice.
+    issued: HashSet<u64>,
+}
+
+impl IdRemapper {
+    /// A remapper handing out sequential ids from 1.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A remapper handing out random ids drawn from `seed`.
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            rng: Some(ChaCha8Rng::seed_from_u64(seed)),
+            ..Self::default()
+        }
+    }
+
+    /// The new id of `order_id`, allocated on its first sight.
+    pub fn remap_id(&mut self, order_id: u64) -> u64 {
+        if order_id == 0 {
+            return 0;
+        }
+        let next = self.ids.len() as u64 + 1;
+        let (rng, issued) = (&mut self.rng, &mut self.issued);
+        *self.ids.entry(order_id).or_insert_with(|| match rng {
+            None => next,
+            Some(rng) => loop {
+                let id = rng.random_range(1..SYNTHETIC_ORDER_ID_MIN);
+                if issued.insert(id) {
+                    break id;
+                }
+            },
+        })
+    }
+
+    /// `message` with its order id remapped.
+    pub fn remap_message(&mut self, message: &MarketByOrderMessage) -> MarketByOrderMessage {
+        MarketByOrderMessage {
+            order_id: self.remap_id(message.order_id),
+            ..*message
+        }
+    }
+
+    /// Remaps the order id of each message as it is yielded. Mappings carry over between
+    /// calls, so the files of one session can be remapped one after another.
+    pub fn remap<I>(&mut self, messages: I) -> impl Iterator<Item = MarketByOrderMessage>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<MarketByOrderMessage>,
+    {
+        messages
+            .into_iter()
+            .map(|message| self.remap_message(message.borrow()))
+    }
+
+    /// Number of original ids mapped so far.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generators::{MessageMix, OrderGenerator};
+    use crate::orderbook::{
+        Action, ErrorPolicy, MarketByPrice, MboProcessor, ProcessSummary, ValidationPolicy,
+    };
+
+    fn replay(messages: &[MarketByOrderMessage]) -> (MboProcessor, ProcessSummary) {
+        let mut processor = MboProcessor::new()
+            .with_validation_policy(ValidationPolicy::PassThrough)
+            .with_error_policy(ErrorPolicy::Collect);
+        let summary = processor.process_messages(messages);
+        (processor, summary)
+    }
+
+    fn assert_same_books(original: &[MarketByOrderMessage], remapped: &[MarketByOrderMessage]) {
+        let (original, original_summary) = replay(original);
+        let (remapped, remapped_summary) = replay(remapped);
+        assert_eq!(original_summary.processed, remapped_summary.processed);
+        assert_eq!(
+            original_summary.failures.len(),
+            remapped_summary.failures.len()
+        );
+        let (original, remapped) = (
+            MarketByPrice::from(original.order_book()),
+            MarketByPrice::from(remapped.order_book()),
+        );
+        assert!(!original.bids.is_empty() && !original.asks.is_empty());
+        assert_eq!(
+            (original.bids, original.asks),
+            (remapped.bids, remapped.asks)
+        );
+    }
+
+    #[test]
+    fn test_remapped_stream_builds_the_same_book() {
+        let messages = OrderGenerator::default_seeded(5)
+            .make_lifecycle_messages(20_000, MessageMix::default());
+        // Starting mid-session, orders first appear in Cancels, Modifies and Fills
+        let tail = &messages[messages.len() / 2..];
+        let mut seen = HashSet::new();
+        assert!(
+            tail.iter()
+                .any(|m| seen.insert(m.order_id) && m.action != Action::Add)
+        );
+
+        let remapper = |seed: Option<u64>| seed.map_or_else(IdRemapper::new, IdRemapper::with_seed);
+        for seed in [None, Some(9)] {
+            let remapped: Vec<_> = remapper(seed).remap(&messages).collect();
+            assert_same_books(&messages, &remapped);
+            let remapped: Vec<_> = remapper(seed).remap(tail).collect();
+            assert_same_books(tail, &remapped);
+        }
+    }
+
+    #[test]
+    fn test_ids_allocated_on_first_sight() {
+        let mut remapper = IdRemapper::new();
+        assert_eq!(remapper.remap_id(9_000_123), 1);
+        assert_eq!(remapper.remap_id(42), 2);
+        assert_eq!(remapper.remap_id(9_000_123), 1);
+        assert_eq!(remapper.remap_id(0), 0);
+        assert_eq!(remapper.len(), 2);
+
+        // Seeded ids are repeatable but not sequential
+        let ids = |seed| {
+            let mut remapper = IdRemapper::with_seed(seed);
+            [7, 8, 7].map(|id| remapper.remap_id(id))
+        };
+        let seeded = ids(1);
+        assert_eq!(seeded, ids(1));
+        assert_ne!(seeded, ids(2));
+        assert_eq!(seeded[0], seeded[2]);
+        assert_ne!(seeded[0], seeded[1]);
+        assert!(
+            seeded
+                .iter()
+                .all(|&id| id > 2 && id < SYNTHETIC_ORDER_ID_MIN)
+        );
+    }
+}
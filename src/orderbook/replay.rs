@@ -0,0 +1,224 @@
+//! Paced replay of recorded messages at (a multiple of) real-time speed.
+//!
+//! Delivery times are scheduled against the first message on a monotonic clock rather
+//! than by sleeping each inter-message delta, so time spent in the consumer does not
+//! accumulate as drift: a replay that falls behind delivers without sleeping until it
+//! has caught up with the schedule.
+
+use std::borrow::Borrow;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+use time::OffsetDateTime;
+
+use crate::orderbook::MarketByOrderMessage;
+
+#[derive(Debug, Error, Clone)]
+pub enum ReplayError {
+    #[error("Replay speed must be a finite, non-negative factor, got {0}")]
+    InvalidSpeed(f64),
+}
+
+/// Delivers messages spaced by their `event_time` deltas divided by a speed factor.
+///
+/// A speed of 1.0 replays in real time, 2.0 twice as fast, and 0.0 as fast as possible.
+/// Messages whose `event_time` precedes the first message's are delivered immediately.
+///
+/// The clock is injectable via `with_clock` for testing; `new` uses `Instant::now` and
+/// `thread::sleep`.
+pub struct Replayer<N = fn() -> Instant, S = fn(Duration)> {
+    speed: f64,
+    now: N,
+    sleep: S,
+    /// Event time of the first message and the instant it was delivered.
+    anchor: Option<(OffsetDateTime, Instant)>,
+}
+
+impl Replayer {
+    pub fn new(speed: f64) -> Result<Self, ReplayError> {
+        Self::with_clock(speed, Instant::now, thread::sleep)
+    }
+}
+
+impl<N, S> Replayer<N, S>
+where
+    N: FnMut() -> Instant,
+    S: FnMut(Duration),
+{
+    /// Creates a replayer reading the time from `now` and waiting with `sleep`.
+    pub fn with_clock(speed: f64, now: N, sleep: S) -> Result<Self, ReplayError> {
+        if !speed.is_finite() || speed < 0.0 {
+            return Err(ReplayError::InvalidSpeed(speed));
+        }
+        Ok(Self {
+            speed,
+            now,
+            sleep,
+            anchor: None,
+        })
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Changes the speed factor, restarting the schedule from the next message.
+    pub fn set_speed(&mut self, speed: f64) -> Result<(), ReplayError> {
+        if !speed.is_finite() || speed < 0.0 {
+            return Err(ReplayError::InvalidSpeed(speed));
+        }
+        self.speed = speed;
+        self.reset();
+        Ok(())
+    }
+
+    /// Restarts the schedule, so that the next message is due at once and later ones
+    /// are spaced from it, e.g. after a pause.
+    pub fn reset(&mut self) {
+        self.anchor = None;
+    }
+
+    /// How long until a message at `event_time` is due, without waiting for it. Zero
+    /// means it is due now; the first message after a reset always is.
+    pub fn delay(&mut self, event_time: OffsetDateTime) -> Duration {
+        if self.speed == 0.0 {
+            return Duration::ZERO;
+        }
+        let (first_event_time, start) = match self.anchor {
+            Some(anchor) => anchor,
+            None => *self.anchor.insert((event_time, (self.now)())),
+        };
+        let offset = (event_time - first_event_time).whole_nanoseconds();
+        if offset <= 0 {
+            return Duration::ZERO;
+        }
+        let deadline = start + Duration::from_nanos((offset as f64 / self.speed) as u64);
+        deadline.saturating_duration_since((self.now)())
+    }
+
+    /// Wraps `messages` so that each one is yielded no earlier than its scheduled time.
+    pub fn pace<I>(mut self, messages: I) -> impl Iterator<Item = I::Item>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<MarketByOrderMessage>,
+    {
+        messages
+            .into_iter()
+            .inspect(move |message| self.wait_for(message.borrow().event_time))
+    }
+
+    /// Passes each message to `sink` at its scheduled time.
+    pub fn replay<I, F>(self, messages: I, sink: F)
+    where
+        I: IntoIterator,
+        I::Item: Borrow<MarketByOrderMessage>,
+        F: FnMut(I::Item),
+    {
+        self.pace(messages).for_each(sink)
+    }
+
+    fn wait_for(&mut self, event_time: OffsetDateTime) {
+        let wait = self.delay(event_time);
+        if !wait.is_zero() {
+            (self.sleep)(wait);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use crate::orderbook::{Action, Side};
+
+    fn at_millis(millis: i64) -> MarketByOrderMessage {
+        let event_time = OffsetDateTime::UNIX_EPOCH + time::Duration::milliseconds(millis);
+        MarketByOrderMessage::test(Action::Trade, Side::Bid, 100, 0, 1).at(event_time)
+    }
+
+    /// Replays messages at the given event times (ms) on a virtual clock, with the sink
+    /// taking `work` of clock time per message. Returns delivery times in ms.
+    fn delivery_times(speed: f64, event_millis: &[i64], work: Duration) -> Vec<u128> {
+        let base = Instant::now();
+        let clock = Rc::new(Cell::new(base));
+        let replayer = Replayer::with_clock(
+            speed,
+            {
+                let clock = Rc::clone(&clock);
+                move || clock.get()
+            },
+            {
+                let clock = Rc::clone(&clock);
+                move |wait| clock.set(clock.get() + wait)
+            },
+        )
+        .unwrap();
+
+        let mut delivered = Vec::new();
+        replayer.replay(event_millis.iter().map(|&ms| at_millis(ms)), |_| {
+            delivered.push((clock.get() - base).as_millis());
+            clock.set(clock.get() + work);
+        });
+        delivered
+    }
+
+    #[test]
+    fn test_double_speed_schedule() {
+        let delivered = delivery_times(2.0, &[1000, 2000, 4000, 4000, 7000], Duration::ZERO);
+        assert_eq!(delivered, vec![0, 500, 1500, 1500, 3000]);
+    }
+
+    #[test]
+    fn test_backwards_timestamps_delivered_immediately() {
+        let delivered = delivery_times(1.0, &[1000, 1500, 500, 1200, 2000], Duration::ZERO);
+        assert_eq!(delivered, vec![0, 500, 500, 500, 1000]);
+    }
+
+    #[test]
+    fn test_slow_consumer_catches_up() {
+        // Each delivery takes 400ms, longer than the 250ms schedule spacing at 2x
+        let delivered =
+            delivery_times(2.0, &[0, 500, 1000, 1500, 5000], Duration::from_millis(400));
+        assert_eq!(delivered, vec![0, 400, 800, 1200, 2500]);
+    }
+
+    #[test]
+    fn test_zero_speed_never_sleeps() {
+        let delivered = delivery_times(0.0, &[0, 60_000, 120_000], Duration::ZERO);
+        assert_eq!(delivered, vec![0, 0, 0]);
+    }
+
+    #[test]
+    fn test_delay_after_reset_and_speed_change() {
+        let clock = Rc::new(Cell::new(Instant::now()));
+        let now = {
+            let clock = Rc::clone(&clock);
+            move || clock.get()
+        };
+        let mut replayer = Replayer::with_clock(1.0, now, |_| {}).unwrap();
+        let delay = |replayer: &mut Replayer<_, _>, ms| replayer.delay(at_millis(ms).event_time);
+        assert_eq!(delay(&mut replayer, 1000), Duration::ZERO);
+        assert_eq!(delay(&mut replayer, 1500), Duration::from_millis(500));
+        clock.set(clock.get() + Duration::from_millis(200));
+        assert_eq!(delay(&mut replayer, 1500), Duration::from_millis(300));
+
+        // The schedule restarts from the next message
+        replayer.set_speed(2.0).unwrap();
+        assert_eq!(delay(&mut replayer, 1500), Duration::ZERO);
+        assert_eq!(delay(&mut replayer, 2500), Duration::from_millis(500));
+        assert!(replayer.set_speed(f64::INFINITY).is_err());
+        assert_eq!(replayer.speed(), 2.0);
+    }
+
+    #[test]
+    fn test_invalid_speed() {
+        assert!(matches!(
+            Replayer::new(-1.0),
+            Err(ReplayError::InvalidSpeed(_))
+        ));
+        assert!(Replayer::new(f64::NAN).is_err());
+    }
+}
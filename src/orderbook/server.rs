@@ -0,0 +1,505 @@
+//! Serving live market-by-price books to WebSocket clients.
+//!
+//! A `BookFeed` observes the processor and a `BookServer` serves what it publishes, so
+//! the replay stays synchronous and never waits on a client. Every message is a JSON
+//! text frame:
+//!
+//! - `{"type": "snapshot", "instrument_id", "publisher_id", "ts_event", "sequence",
+//!   "bids", "asks"}`: the best `depth` levels of one book in the shape of
+//!   `MarketByPrice::to_json_depth`, sent for every book on connect and, with a
+//!   snapshot cadence, at every tick. `publisher_id` is zero unless the processor
+//!   routes by publisher.
+//! - `{"type": "delta", ...}`: the same keys, with only the levels of the depth view
+//!   that changed; a quantity of 0 removes the level. Applying the deltas to the last
+//!   snapshot gives the current depth view.
+//! - `{"type": "end"}`: the feed is finished, and the server closes the connection.
+//!
+//! `ts_event` is in nanoseconds since the UNIX epoch and `sequence` is the venue
+//! sequence number of the message that last changed the book. A client that falls
+//! further behind than the server's buffer skips the frames it missed and is sent a
+//! fresh snapshot of every book, after which its deltas resume.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{Value, json};
+use time::OffsetDateTime;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_tungstenite::accept_async;
+use tokio_tungstenite::tungstenite::{self, Message, Utf8Bytes};
+use tracing::{debug, info};
+
+use crate::orderbook::events::{
+    OrderAddedEvent, OrderCancelledEvent, OrderModifiedEvent, TradeEvent,
+};
+use crate::orderbook::{
+    BookKey, L2Batch, L2Publisher, MarketByOrderMessage, MarketByPrice, MboObserver, OrderBook,
+    OrderLevelSummary, PriceScale, SnapshotInterval,
+};
+
+/// What a `BookServer` sends and how far clients may fall behind. Defaults to deltas of
+/// the best 10 levels at raw prices, with 1024 frames buffered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ServerOptions {
+    depth: usize,
+    snapshot_every: Option<SnapshotInterval>,
+    price_scale: PriceScale,
+    buffer: usize,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            depth: 10,
+            snapshot_every: None,
+            price_scale: PriceScale::RAW,
+            buffer: 1024,
+        }
+    }
+}
+
+impl ServerOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Levels per side of the snapshots and of the view the deltas keep.
+    pub fn with_depth(mut self, depth: usize) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    /// Send a fresh snapshot of every book at this cadence of applied messages or event
+    /// time instead of deltas; `None` sends deltas.
+    pub fn with_snapshot_every(mut self, snapshot_every: Option<SnapshotInterval>) -> Self {
+        self.snapshot_every = snapshot_every;
+        self
+    }
+
+    /// Scale of the prices sent, as in `MarketByPrice::to_json_depth`.
+    pub fn with_price_scale(mut self, price_scale: PriceScale) -> Self {
+        self.price_scale = price_scale;
+        self
+    }
+
+    /// Frames a client may fall behind before it is skipped to a fresh snapshot, at
+    /// least 1.
+    pub fn with_buffer(mut self, buffer: usize) -> Self {
+        self.buffer = buffer.max(1);
+        self
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn snapshot_every(&self) -> Option<SnapshotInterval> {
+        self.snapshot_every
+    }
+
+    pub fn price_scale(&self) -> PriceScale {
+        self.price_scale
+    }
+
+    pub fn buffer(&self) -> usize {
+        self.buffer
+    }
+}
+
+/// A broadcast frame, numbered so a client can tell which ones a snapshot covers.
+#[derive(Debug, Clone)]
+struct Frame {
+    serial: u64,
+    text: Utf8Bytes,
+    end: bool,
+}
+
+/// The frames a client starts (or resumes) from, covering every frame up to `serial`.
+struct Snapshot {
+    serial: u64,
+    frames: Vec<Frame>,
+}
+
+/// The depth views as sent so far, guarded so that taking a snapshot and subscribing
+/// to the frames after it cannot interleave with publishing.
+#[derive(Debug, Default)]
+struct FeedState {
+    /// Serial of the last frame sent.
+    serial: u64,
+    views: BTreeMap<BookKey, MarketByPrice>,
+    finished: bool,
+}
+
+#[derive(Debug)]
+struct Shared {
+    options: ServerOptions,
+    state: Mutex<FeedState>,
+    tx: broadcast::Sender<Frame>,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, FeedState> {
+        // The state stays consistent even if a holder panicked
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Sends `text` to every client, failing only when none is connected.
+    fn broadcast(&self, state: &mut FeedState, text: Utf8Bytes, end: bool) {
+        state.serial += 1;
+        let _ = self.tx.send(Frame {
+            serial: state.serial,
+            text,
+            end,
+        });
+    }
+
+    fn snapshot(&self, state: &FeedState) -> Snapshot {
+        let mut frames: Vec<Frame> = state
+            .views
+            .iter()
+            .map(|(&key, view)| Frame {
+                serial: state.serial,
+                text: self.frame("snapshot", key, view),
+                end: false,
+            })
+            .collect();
+        if state.finished {
+            frames.push(end_frame(state.serial));
+        }
+        Snapshot {
+            serial: state.serial,
+            frames,
+        }
+    }
+
+    /// `levels` as a JSON frame of `kind`, tagged with their book, event time and
+    /// sequence.
+    fn frame(&self, kind: &str, key: BookKey, levels: &MarketByPrice) -> Utf8Bytes {
+        let mut frame = levels.to_json_depth(usize::MAX, self.options.price_scale);
+        frame["type"] = kind.into();
+        frame["instrument_id"] = key.instrument_id.into();
+        frame["publisher_id"] = key.publisher_id.into();
+        frame["ts_event"] = match levels.event_time {
+            Some(event_time) => (event_time.unix_timestamp_nanos() as i64).into(),
+            None => Value::Null,
+        };
+        frame["sequence"] = levels.sequence.into();
+        frame.to_string().into()
+    }
+}
+
+fn end_frame(serial: u64) -> Frame {
+    Frame {
+        serial,
+        text: end_text(),
+        end: true,
+    }
+}
+
+fn end_text() -> Utf8Bytes {
+    json!({"type": "end"}).to_string().into()
+}
+
+/// Serves the books published by its `BookFeed` to WebSocket clients.
+///
+/// Cheap to clone; clones serve the same books.
+#[derive(Debug, Clone)]
+pub struct BookServer {
+    shared: Arc<Shared>,
+}
+
+impl BookServer {
+    pub fn new(options: ServerOptions) -> Self {
+        let (tx, _) = broadcast::channel(options.buffer);
+        Self {
+            shared: Arc::new(Shared {
+                options,
+                state: Mutex::new(FeedState::default()),
+                tx,
+            }),
+        }
+    }
+
+    pub fn options(&self) -> &ServerOptions {
+        &self.shared.options
+    }
+
+    /// The observer publishing to this server's clients. Make one per server: the
+    /// clients are told the feed ended once it is dropped.
+    pub fn feed(&self) -> BookFeed {
+        BookFeed {
+            shared: Arc::clone(&self.shared),
+            publisher: L2Publisher::new(),
+            books: HashMap::new(),
+            applied: 0,
+            next_tick: None,
+        }
+    }
+
+    /// Accepts clients on `listener` until accepting fails, serving each on its own
+    /// task. A client that fails the handshake or disconnects only ends its own task.
+    pub async fn serve(self, listener: TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let shared = Arc::clone(&self.shared);
+            tokio::spawn(async move {
+                info!("Client {peer} connected");
+                match serve_client(&shared, stream).await {
+                    Ok(()) => info!("Client {peer} disconnected"),
+                    Err(e) => info!("Client {peer} dropped: {e}"),
+                }
+            });
+        }
+    }
+}
+
+/// Sends `stream` a snapshot of every book, then the frames after it until the feed
+/// ends or the client goes away.
+async fn serve_client(shared: &Shared, stream: TcpStream) -> Result<(), tungstenite::Error> {
+    let mut ws = accept_async(stream).await?;
+    let (mut rx, snapshot) = {
+        let state = shared.lock();
+        (shared.tx.subscribe(), shared.snapshot(&state))
+    };
+    let mut serial = snapshot.serial;
+    let mut ended = false;
+    for frame in snapshot.frames {
+        ended |= frame.end;
+        ws.feed(Message::Text(frame.text)).await?;
+    }
+    ws.flush().await?;
+
+    while !ended {
+        tokio::select! {
+            received = rx.recv() => match received {
+                Ok(frame) if frame.serial <= serial => {}
+                Ok(frame) => {
+                    serial = frame.serial;
+                    ended = frame.end;
+                    ws.send(Message::Text(frame.text)).await?;
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    debug!("Client fell {skipped} frames behind, resending snapshots");
+                    let snapshot = shared.snapshot(&shared.lock());
+                    serial = snapshot.serial;
+                    for frame in snapshot.frames {
+                        ended |= frame.end;
+                        ws.feed(Message::Text(frame.text)).await?;
+                    }
+                    ws.flush().await?;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            // Anything the client sends is ignored, but reading answers pings and
+            // notices a close
+            incoming = ws.next() => match incoming {
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e),
+                None => return Ok(()),
+            },
+        }
+    }
+    ws.close(None).await
+}
+
+/// Observer publishing the books of a `BookServer`: the levels each message changed,
+/// from an `L2Publisher`, are applied to a copy of every book, and the changes to its
+/// best `depth` levels are sent as a delta, or the books as snapshots at the configured
+/// cadence. Messages that leave the depth view as it was send nothing.
+///
+/// Like `L2Publisher`, it only sees books changed through callbacks, so it must observe
+/// them from empty: books seeded by `MboProcessor::seed_book`, or warmed up before it
+/// was attached, are not served as they are. Dropping it tells the clients the feed
+/// ended.
+#[derive(Debug)]
+pub struct BookFeed {
+    shared: Arc<Shared>,
+    publisher: L2Publisher,
+    /// Every level of each book, as published.
+    books: HashMap<BookKey, MarketByPrice>,
+    /// Messages applied, for a snapshot cadence of messages.
+    applied: usize,
+    /// Event time of the next snapshot, for a snapshot cadence of event time.
+    next_tick: Option<OffsetDateTime>,
+}
+
+impl BookFeed {
+    /// Applies `batch` to its book and to the depth view, returning the view's changed
+    /// levels, if any, with a quantity of 0 for removed levels.
+    fn apply(&mut self, state: &mut FeedState, batch: &L2Batch) -> Option<MarketByPrice> {
+        let depth = self.shared.options.depth;
+        let key = BookKey::new(batch.publisher_id, batch.instrument_id);
+        let book = self.books.entry(key).or_default();
+        batch.apply_to(book);
+        let view = state.views.entry(key).or_default();
+        view.event_time = Some(batch.event_time);
+        view.recv_time = Some(batch.recv_time);
+        view.sequence = Some(batch.sequence);
+        let changes = MarketByPrice {
+            bids: replace_levels(&mut view.bids, book.top_n_bids(depth)),
+            asks: replace_levels(&mut view.asks, book.top_n_asks(depth)),
+            event_time: view.event_time,
+            recv_time: view.recv_time,
+            sequence: view.sequence,
+        };
+        (!changes.bids.is_empty() || !changes.asks.is_empty()).then_some(changes)
+    }
+
+    fn tick_due(&mut self, message: &MarketByOrderMessage) -> bool {
+        self.applied += 1;
+        match self.shared.options.snapshot_every {
+            None => false,
+            Some(SnapshotInterval::Messages(every)) => {
+                every > 0 && self.applied.is_multiple_of(every)
+            }
+            Some(SnapshotInterval::EventTime(period)) => {
+                let due = self.next_tick.is_none_or(|next| message.event_time >= next);
+                if due {
+                    self.next_tick = Some(message.event_time + period);
+                }
+                due
+            }
+        }
+    }
+}
+
+/// Replaces the levels of `view` with `top`, returning those whose quantity changed,
+/// with a quantity of 0 for levels no longer in `top`.
+fn replace_levels(
+    view: &mut BTreeMap<i64, OrderLevelSummary>,
+    top: Vec<OrderLevelSummary>,
+) -> BTreeMap<i64, OrderLevelSummary> {
+    let mut changes: BTreeMap<i64, OrderLevelSummary> = top
+        .iter()
+        .filter(|level| {
+            view.get(&level.price)
+                .is_none_or(|old| old.total_quantity != level.total_quantity)
+        })
+        .map(|level| (level.price, *level))
+        .collect();
+    let top: BTreeMap<i64, OrderLevelSummary> =
+        top.into_iter().map(|level| (level.price, level)).collect();
+    for &price in view.keys().filter(|price| !top.contains_key(price)) {
+        changes.insert(
+            price,
+            OrderLevelSummary {
+                price,
+                total_quantity: 0,
+                order_count: 0,
+            },
+        );
+    }
+    *view = top;
+    changes
+}
+
+impl MboObserver for BookFeed {
+    fn on_order_added(&mut self, event: &OrderAddedEvent) {
+        self.publisher.on_order_added(event);
+    }
+
+    fn on_order_cancelled(&mut self, event: &OrderCancelledEvent) {
+        self.publisher.on_order_cancelled(event);
+    }
+
+    fn on_order_modified(&mut self, event: &OrderModifiedEvent) {
+        self.publisher.on_order_modified(event);
+    }
+
+    fn on_trade(&mut self, event: &TradeEvent) {
+        self.publisher.on_trade(event);
+    }
+
+    fn on_clear(&mut self) {
+        self.publisher.on_clear();
+    }
+
+    fn on_message_applied(
+        &mut self,
+        message: &MarketByOrderMessage,
+        key: BookKey,
+        book: &OrderBook,
+    ) {
+        self.publisher.on_message_applied(message, key, book);
+        let batches = self.publisher.drain();
+        let tick = self.tick_due(message);
+        if batches.is_empty() && !tick {
+            return;
+        }
+
+        let shared = Arc::clone(&self.shared);
+        let mut state = shared.lock();
+        for batch in &batches {
+            let Some(changes) = self.apply(&mut state, batch) else {
+                continue;
+            };
+            if shared.options.snapshot_every.is_none() {
+                let key = BookKey::new(batch.publisher_id, batch.instrument_id);
+                let text = shared.frame("delta", key, &changes);
+                shared.broadcast(&mut state, text, false);
+            }
+        }
+        if tick {
+            let texts: Vec<Utf8Bytes> = state
+                .views
+                .iter()
+                .map(|(&key, view)| shared.frame("snapshot", key, view))
+                .collect();
+            for text in texts {
+                shared.broadcast(&mut state, text, false);
+            }
+        }
+    }
+
+    /// Broadcasts the levels of orders purged before the message failed.
+    fn on_message_rejected(
+        &mut self,
+        message: &MarketByOrderMessage,
+        key: BookKey,
+        book: &OrderBook,
+    ) {
+        self.on_message_applied(message, key, book);
+    }
+}
+
+impl Drop for BookFeed {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.finished = true;
+        self.shared.broadcast(&mut state, end_text(), true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(price: i64, total_quantity: u64) -> OrderLevelSummary {
+        OrderLevelSummary {
+            price,
+            total_quantity,
+            order_count: 1,
+        }
+    }
+
+    #[test]
+    fn test_replace_levels_reports_changes_and_removals() {
+        let mut view = BTreeMap::from([(100, level(100, 5)), (99, level(99, 3))]);
+        // 101 is new, 100 changed size and 99 fell out of the view
+        let changes = replace_levels(&mut view, vec![level(101, 1), level(100, 6)]);
+        let changed: Vec<_> = changes
+            .values()
+            .map(|level| (level.price, level.total_quantity))
+            .collect();
+        assert_eq!(changed, vec![(99, 0), (100, 6), (101, 1)]);
+        assert_eq!(view.keys().copied().collect::<Vec<_>>(), vec![100, 101]);
+
+        let changes = replace_levels(&mut view, vec![level(101, 1), level(100, 6)]);
+        assert!(changes.is_empty());
+    }
+}
@@ -0,0 +1,618 @@
+//! Periodic market-by-price snapshots collected into one parquet or Arrow IPC file.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use polars::io::ipc::BatchedWriter as IpcBatchedWriter;
+use polars::prelude::{
+    DataFrame, IpcWriter, ParquetWriter, PolarsResult, SerWriter, df, polars_bail,
+};
+use time::OffsetDateTime;
+
+use crate::orderbook::{
+    ErrorPolicy, MarketByOrderMessage, MarketByPrice, MboObserver, MboProcessor, MbpFrameOptions,
+    ProcessFailure, ProcessSummary, SnapshotInterval, create_output,
+};
+
+/// Collects market-by-price snapshots and writes them to a single parquet or Arrow IPC
+/// file.
+///
+/// Every snapshot becomes one row per price level, as in `MarketByPrice::to_dataframe`,
+/// tagged with `ts_event` (nanoseconds since the UNIX epoch, null if unknown),
+/// `sample_ts` (the grid point of a `TimeSampler` snapshot in nanoseconds, otherwise
+/// null), `message_index` (0-based index of the last message applied before the
+/// snapshot) and `instrument_id` columns. Snapshots are buffered in memory and written at the end,
+/// so limit the depth to keep both memory and file size in check, or write them to an
+/// IPC file as they are taken with `stream_ipc`.
+#[derive(Debug)]
+pub struct SnapshotWriter {
+    depth: Option<usize>,
+    overwrite: bool,
+    frames: Vec<DataFrame>,
+    /// IPC file the snapshots go to instead of `frames`, once `stream_ipc` opened it.
+    ipc_stream: Option<IpcStream>,
+    /// Snapshots written to `ipc_stream`.
+    streamed: usize,
+}
+
+impl Default for SnapshotWriter {
+    fn default() -> Self {
+        Self {
+            depth: None,
+            overwrite: true,
+            frames: Vec::new(),
+            ipc_stream: None,
+            streamed: 0,
+        }
+    }
+}
+
+/// An Arrow IPC file written a record batch per snapshot.
+struct IpcStream {
+    writer: IpcBatchedWriter<File>,
+    rows: usize,
+}
+
+impl fmt::Debug for IpcStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IpcStream")
+            .field("rows", &self.rows)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SnapshotWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep at most `depth` levels per side of each snapshot; `None` keeps every level.
+    pub fn with_depth(mut self, depth: Option<usize>) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    pub fn depth(&self) -> Option<usize> {
+        self.depth
+    }
+
+    /// Whether `write_parquet` replaces an existing file rather than failing; on by
+    /// default.
+    pub fn with_overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Number of snapshots collected, including those streamed to an IPC file.
+    pub fn len(&self) -> usize {
+        self.frames.len() + self.streamed
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Writes each snapshot to an Arrow IPC file at `path` as it is added, one record
+    /// batch per snapshot, instead of buffering it. Parent directories and an existing
+    /// file are handled as by `write_parquet`.
+    ///
+    /// The file is only complete once `finish` writes its footer. Streamed snapshots are
+    /// not kept, so `to_dataframe` and the `write_*` methods leave them out.
+    pub fn stream_ipc(mut self, path: impl AsRef<Path>) -> PolarsResult<Self> {
+        let schema = Self::new().to_dataframe()?.schema().clone();
+        let writer = IpcWriter::new(self.create(path.as_ref())?).batched(&schema)?;
+        self.ipc_stream = Some(IpcStream { writer, rows: 0 });
+        Ok(self)
+    }
+
+    /// Ends the IPC file of `stream_ipc`, writing its footer, and returns the number of
+    /// rows written to it; 0 if there is none.
+    pub fn finish(&mut self) -> PolarsResult<usize> {
+        let Some(mut stream) = self.ipc_stream.take() else {
+            return Ok(0);
+        };
+        stream.writer.finish()?;
+        Ok(stream.rows)
+    }
+
+    /// Adds `snapshot` of `instrument_id`'s book, truncated to the configured depth.
+    pub fn push(
+        &mut self,
+        snapshot: &MarketByPrice,
+        instrument_id: u32,
+        message_index: u64,
+    ) -> PolarsResult<()> {
+        self.push_sample(snapshot, instrument_id, message_index, None)
+    }
+
+    fn push_sample(
+        &mut self,
+        snapshot: &MarketByPrice,
+        instrument_id: u32,
+        message_index: u64,
+        sample_time: Option<OffsetDateTime>,
+    ) -> PolarsResult<()> {
+        let levels = snapshot.to_dataframe_with(&MbpFrameOptions::new().with_depth(self.depth))?;
+        let rows = levels.height();
+        let ts_event = snapshot
+            .event_time
+            .map(|time| time.unix_timestamp_nanos() as i64);
+        let sample_ts = sample_time.map(|time| time.unix_timestamp_nanos() as i64);
+        let tags = df!(
+            "ts_event" => vec![ts_event; rows],
+            "sample_ts" => vec![sample_ts; rows],
+            "message_index" => vec![message_index; rows],
+            "instrument_id" => vec![instrument_id; rows],
+        )?;
+        let frame = tags.hstack(levels.get_columns())?;
+        match &mut self.ipc_stream {
+            Some(stream) => {
+                stream.writer.write_batch(&frame)?;
+                stream.rows += frame.height();
+                self.streamed += 1;
+            }
+            None => self.frames.push(frame),
+        }
+        Ok(())
+    }
+
+    /// Adds a snapshot of every instrument's book in `processor`, stamped with the
+    /// timestamps of the last processed message.
+    pub fn record<O: MboObserver>(
+        &mut self,
+        processor: &MboProcessor<O>,
+        message_index: u64,
+    ) -> PolarsResult<()> {
+        self.record_sample(processor, message_index, None, None)
+    }
+
+    /// Like `record`, taking at most `depth` levels per side from each book and tagging
+    /// the snapshots with `sample_time`.
+    fn record_sample<O: MboObserver>(
+        &mut self,
+        processor: &MboProcessor<O>,
+        message_index: u64,
+        sample_time: Option<OffsetDateTime>,
+        depth: Option<usize>,
+    ) -> PolarsResult<()> {
+        let (event_time, recv_time, _) = processor.last_timestamps();
+        for instrument_id in processor.instruments() {
+            let Some(book) = processor.book(instrument_id) else {
+                continue;
+            };
+            let mut snapshot = match depth {
+                Some(depth) => MarketByPrice::from_top_n(book, depth),
+                None => MarketByPrice::from(book),
+            };
+            snapshot.event_time = Some(event_time);
+            snapshot.recv_time = Some(recv_time);
+            snapshot.sequence = Some(processor.last_sequence_number());
+            self.push_sample(&snapshot, instrument_id, message_index, sample_time)?;
+        }
+        Ok(())
+    }
+
+    /// All collected snapshots, in the order they were added.
+    pub fn to_dataframe(&self) -> PolarsResult<DataFrame> {
+        let mut frames = self.frames.iter();
+        let Some(first) = frames.next() else {
+            // No snapshots: an empty frame with the same columns
+            let mut empty = Self::new();
+            empty.push(&MarketByPrice::new(), 0, 0)?;
+            return Ok(empty.frames.remove(0));
+        };
+        let mut df = first.clone();
+        for frame in frames {
+            df.vstack_mut(frame)?;
+        }
+        df.align_chunks_par();
+        Ok(df)
+    }
+
+    /// Writes the DataFrame from `to_dataframe` to a parquet file, creating any missing
+    /// parent directories, and returns the number of rows written.
+    pub fn write_parquet(&self, path: impl AsRef<Path>) -> PolarsResult<usize> {
+        let file = self.create(path.as_ref())?;
+        let mut df = self.to_dataframe()?;
+        ParquetWriter::new(file).finish(&mut df)?;
+        Ok(df.height())
+    }
+
+    /// Like `write_parquet`, writing an Arrow IPC (Feather v2) file with the same
+    /// schema.
+    pub fn write_ipc(&self, path: impl AsRef<Path>) -> PolarsResult<usize> {
+        let file = self.create(path.as_ref())?;
+        let mut df = self.to_dataframe()?;
+        IpcWriter::new(file).finish(&mut df)?;
+        Ok(df.height())
+    }
+
+    fn create(&self, path: &Path) -> io::Result<File> {
+        create_output(path, self.overwrite)
+    }
+}
+
+impl<O: MboObserver> MboProcessor<O> {
+    /// Processes `messages` like `process_messages`, adding a snapshot of every book to
+    /// `writer` after each `every` messages, as `process_with_interval` does.
+    ///
+    /// Snapshots are taken on message counts, so one may fall in the middle of an event.
+    /// Fails if `every` is zero or a snapshot cannot be converted.
+    pub fn process_with_snapshots<I>(
+        &mut self,
+        messages: I,
+        every: usize,
+        writer: &mut SnapshotWriter,
+    ) -> PolarsResult<ProcessSummary>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<MarketByOrderMessage>,
+    {
+        if every == 0 {
+            polars_bail!(InvalidOperation: "snapshot interval must be positive");
+        }
+        self.process_with_interval(
+            messages,
+            SnapshotInterval::Messages(every),
+            |processor, index| writer.record(processor, index),
+        )
+    }
+}
+
+/// Sampling of the books on a regular grid of event time, every `interval_ns`
+/// nanoseconds from the UNIX epoch, for `MboProcessor::process_with_time_samples`.
+///
+/// Each grid point is sampled as of the last message at or before it, so when a
+/// message's timestamp crosses one or more grid points, the books are sampled once per
+/// point before it is applied; points with no message between them give identical
+/// snapshots. Sampling starts at the first grid point at or after the first message and
+/// ends at the last at or before the last message, so it never reports a book before
+/// the first message or beyond the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSampler {
+    interval_ns: u64,
+    depth: Option<usize>,
+    /// The next grid point to sample, in nanoseconds, once a message was seen.
+    next: Option<i128>,
+}
+
+impl TimeSampler {
+    pub fn new(interval_ns: u64) -> Self {
+        Self {
+            interval_ns,
+            depth: None,
+            next: None,
+        }
+    }
+
+    /// Take at most `depth` levels per side from each book; `None` takes every level.
+    /// Cheaper than truncating with `SnapshotWriter::with_depth` for deep books.
+    pub fn with_depth(mut self, depth: Option<usize>) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    pub fn interval_ns(&self) -> u64 {
+        self.interval_ns
+    }
+
+    pub fn depth(&self) -> Option<usize> {
+        self.depth
+    }
+
+    /// Starts the grid at the first point at or after `event_ns`, unless started.
+    fn start(&mut self, event_ns: i128) {
+        let interval = i128::from(self.interval_ns);
+        self.next
+            .get_or_insert((event_ns + interval - 1).div_euclid(interval) * interval);
+    }
+
+    /// The next grid point before `event_ns`, moving past it.
+    fn take_due(&mut self, event_ns: i128) -> Option<OffsetDateTime> {
+        let next = self.next.filter(|&next| next < event_ns)?;
+        self.next = Some(next + i128::from(self.interval_ns));
+        OffsetDateTime::from_unix_timestamp_nanos(next).ok()
+    }
+}
+
+impl<O: MboObserver> MboProcessor<O> {
+    /// Processes `messages` like `process_messages`, adding a snapshot of every book to
+    /// `writer` at each grid point of `sampler`, with the grid point in `sample_ts`.
+    ///
+    /// The input is taken as the whole session: the grid points up to its last message
+    /// are sampled before returning. Fails if the interval is zero or a snapshot cannot
+    /// be converted.
+    pub fn process_with_time_samples<I>(
+        &mut self,
+        messages: I,
+        sampler: &mut TimeSampler,
+        writer: &mut SnapshotWriter,
+    ) -> PolarsResult<ProcessSummary>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<MarketByOrderMessage>,
+    {
+        if sampler.interval_ns == 0 {
+            polars_bail!(InvalidOperation: "sample interval must be positive");
+        }
+        let mut summary = ProcessSummary::default();
+        let mut last: Option<(u64, i128)> = None;
+        for (index, message) in messages.into_iter().enumerate() {
+            let message = message.borrow();
+            let event_ns = message.event_time.unix_timestamp_nanos();
+            sampler.start(event_ns);
+            if let Some((last_index, _)) = last {
+                while let Some(sample_time) = sampler.take_due(event_ns) {
+                    writer.record_sample(self, last_index, Some(sample_time), sampler.depth)?;
+                }
+            }
+            match self.process_message(message) {
+                Ok(()) => {
+                    summary.processed += 1;
+                    summary.action_counts.record(message.action);
+                }
+                Err(error) => {
+                    summary.failures.push(ProcessFailure { index, error });
+                    if self.error_policy() == ErrorPolicy::FailFast {
+                        break;
+                    }
+                }
+            }
+            last = Some((index as u64, event_ns));
+        }
+        // A grid point at the last message's time
+        if let Some((last_index, event_ns)) = last {
+            while let Some(sample_time) = sampler.take_due(event_ns + 1) {
+                writer.record_sample(self, last_index, Some(sample_time), sampler.depth)?;
+            }
+        }
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::{env, fs, process};
+
+    use polars::prelude::{IpcReader, ParquetReader, SerReader};
+    use time::{Duration, OffsetDateTime};
+
+    use crate::orderbook::{Action, Side};
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("rainybook-{}-{name}", process::id()))
+    }
+
+    fn add(instrument_id: u32, order_id: u64, side: Side, price: i64) -> MarketByOrderMessage {
+        let event_time = OffsetDateTime::UNIX_EPOCH + Duration::nanoseconds(order_id as i64);
+        MarketByOrderMessage {
+            instrument_id,
+            sequence: order_id as u32,
+            ..MarketByOrderMessage::test(Action::Add, side, price, order_id, 10).at(event_time)
+        }
+    }
+
+    /// Ten adds, one level each, on two instruments.
+    fn messages() -> Vec<MarketByOrderMessage> {
+        (1..=10u64)
+            .map(|i| match i % 2 {
+                0 => add(1, i, Side::Bid, 100 - i as i64),
+                _ => add(2, i, Side::Ask, 200 + i as i64),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_three_snapshots_round_trip() {
+        let mut proc = MboProcessor::new();
+        let mut writer = SnapshotWriter::new();
+        // Snapshots after messages 3, 6 and 9; the tenth message ends the input
+        let summary = proc
+            .process_with_snapshots(messages(), 3, &mut writer)
+            .unwrap();
+        assert_eq!(summary.processed, 10);
+        // Instrument 1 and 2 in each of the three snapshots
+        assert_eq!(writer.len(), 6);
+
+        let path = temp_path("snapshots.parquet");
+        assert_eq!(writer.write_parquet(&path).unwrap(), 18);
+        let df = ParquetReader::new(File::open(&path).unwrap())
+            .finish()
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // Levels after 3 messages: 1 + 2, after 6: 3 + 3, after 9: 4 + 5
+        assert_eq!(df.height(), 18);
+        let column_u64 = |name: &str| -> Vec<u64> {
+            df.column(name)
+                .unwrap()
+                .cast(&polars::prelude::DataType::UInt64)
+                .unwrap()
+                .u64()
+                .unwrap()
+                .into_no_null_iter()
+                .collect()
+        };
+        let message_index = column_u64("message_index");
+        assert_eq!(message_index.iter().filter(|&&i| i == 2).count(), 3);
+        assert_eq!(message_index.iter().filter(|&&i| i == 5).count(), 6);
+        assert_eq!(message_index.iter().filter(|&&i| i == 8).count(), 9);
+        let instrument_id = column_u64("instrument_id");
+        assert_eq!(instrument_id[..3], [1, 2, 2]);
+        let ts_event = column_u64("ts_event");
+        assert_eq!(ts_event[0], 3);
+        assert_eq!(ts_event[17], 9);
+    }
+
+    /// Rows of `df` as `(sample_ts, message_index, price)`.
+    fn sample_rows(df: &DataFrame) -> Vec<(Option<i64>, u64, i64)> {
+        let sample_ts = df.column("sample_ts").unwrap().i64().unwrap();
+        let message_index = df.column("message_index").unwrap().u64().unwrap();
+        let price = df.column("price").unwrap().i64().unwrap();
+        (0..df.height())
+            .map(|i| {
+                (
+                    sample_ts.get(i),
+                    message_index.get(i).unwrap(),
+                    price.get(i).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    /// A bid add at `price` on instrument 1 at `ms` milliseconds.
+    fn add_at(ms: i64, order_id: u64, price: i64) -> MarketByOrderMessage {
+        let event_time = OffsetDateTime::UNIX_EPOCH + Duration::milliseconds(ms);
+        MarketByOrderMessage {
+            event_time,
+            recv_time: event_time,
+            ..add(1, order_id, Side::Bid, price)
+        }
+    }
+
+    #[test]
+    fn test_time_samples_on_grid() {
+        let second = 1_000_000_000;
+        let mut sampler = TimeSampler::new(second as u64);
+        let mut writer = SnapshotWriter::new();
+        let messages = [
+            add_at(100, 1, 101),
+            add_at(900, 2, 102),
+            add_at(2300, 3, 103),
+        ];
+        let summary = MboProcessor::new()
+            .process_with_time_samples(messages, &mut sampler, &mut writer)
+            .unwrap();
+        assert_eq!(summary.processed, 3);
+
+        // 1 s and 2 s as of the message at 0.9 s, before the one at 2.3 s; no point at
+        // 0 s before the first message or at 3 s after the last
+        let df = writer.to_dataframe().unwrap();
+        assert_eq!(
+            sample_rows(&df),
+            vec![
+                (Some(second), 1, 102),
+                (Some(second), 1, 101),
+                (Some(2 * second), 1, 102),
+                (Some(2 * second), 1, 101),
+            ]
+        );
+        let ts_event = df.column("ts_event").unwrap().i64().unwrap();
+        assert_eq!(ts_event.get(3), Some(900_000_000));
+    }
+
+    #[test]
+    fn test_time_samples_include_messages_on_grid_point() {
+        let mut writer = SnapshotWriter::new();
+        let mut sampler = TimeSampler::new(500_000_000).with_depth(Some(1));
+        let messages = [
+            add_at(500, 1, 101),
+            add_at(1000, 2, 102),
+            add_at(1000, 3, 103),
+        ];
+        MboProcessor::new()
+            .process_with_time_samples(messages, &mut sampler, &mut writer)
+            .unwrap();
+        // 0.5 s with its own message, 1 s at the end of the input with both of its
+        // messages, at a depth of 1
+        assert_eq!(
+            sample_rows(&writer.to_dataframe().unwrap()),
+            vec![(Some(500_000_000), 0, 101), (Some(1_000_000_000), 2, 103)]
+        );
+
+        let zero = MboProcessor::new().process_with_time_samples(
+            messages,
+            &mut TimeSampler::new(0),
+            &mut writer,
+        );
+        assert!(zero.is_err());
+    }
+
+    #[test]
+    fn test_depth_truncation() {
+        let mut proc = MboProcessor::new();
+        proc.process_messages(messages());
+
+        let mut writer = SnapshotWriter::new().with_depth(Some(2));
+        writer.record(&proc, 9).unwrap();
+        let df = writer.to_dataframe().unwrap();
+        // Two bid levels for instrument 1, two ask levels for instrument 2
+        assert_eq!(df.height(), 4);
+        let price: Vec<i64> = df
+            .column("price")
+            .unwrap()
+            .i64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert_eq!(price, vec![98, 96, 201, 203]);
+    }
+
+    #[test]
+    fn test_no_snapshots() {
+        let mut writer = SnapshotWriter::new();
+        let summary = MboProcessor::new()
+            .process_with_snapshots(&messages()[..2], 3, &mut writer)
+            .unwrap();
+        assert_eq!(summary.processed, 2);
+        assert!(writer.is_empty());
+
+        let df = writer.to_dataframe().unwrap();
+        assert_eq!(df.height(), 0);
+        assert_eq!(df.width(), 9);
+    }
+
+    #[test]
+    fn test_write_creates_parent_and_keeps_existing() {
+        let mut proc = MboProcessor::new();
+        proc.process_messages(messages());
+        let mut writer = SnapshotWriter::new().with_overwrite(false);
+        writer.record(&proc, 9).unwrap();
+
+        let dir = temp_path("nested");
+        let path = dir.join("deeper").join("final.parquet");
+        assert_eq!(writer.write_parquet(&path).unwrap(), 10);
+        assert!(writer.write_parquet(&path).is_err());
+        assert_eq!(
+            writer.with_overwrite(true).write_parquet(&path).unwrap(),
+            10
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_ipc_round_trip_matches_parquet() {
+        let stream_path = temp_path("streamed.arrow");
+        let mut streamed = SnapshotWriter::new().stream_ipc(&stream_path).unwrap();
+        let mut buffered = SnapshotWriter::new();
+        for writer in [&mut streamed, &mut buffered] {
+            MboProcessor::new()
+                .process_with_snapshots(messages(), 3, writer)
+                .unwrap();
+        }
+        assert_eq!(streamed.len(), 6);
+        assert_eq!(streamed.to_dataframe().unwrap().height(), 0);
+        assert_eq!(streamed.finish().unwrap(), 18);
+
+        let parquet_path = temp_path("snapshots.parquet");
+        let ipc_path = temp_path("snapshots.feather");
+        assert_eq!(buffered.write_parquet(&parquet_path).unwrap(), 18);
+        assert_eq!(buffered.write_ipc(&ipc_path).unwrap(), 18);
+        let parquet = ParquetReader::new(File::open(&parquet_path).unwrap())
+            .finish()
+            .unwrap();
+        fs::remove_file(&parquet_path).unwrap();
+        // Both IPC files read back as the parquet file does, schema included
+        for path in [ipc_path, stream_path] {
+            let ipc = IpcReader::new(File::open(&path).unwrap()).finish().unwrap();
+            fs::remove_file(&path).unwrap();
+            assert_eq!(ipc.schema(), parquet.schema());
+            assert!(ipc.equals_missing(&parquet));
+        }
+    }
+}
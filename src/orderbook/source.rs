@@ -0,0 +1,160 @@
+//! Processing raw `MboMsg` records pulled from a fallible source, such as a network
+//! client streaming records from a historical or live gateway.
+//!
+//! Unlike a file, such a source can fail part-way through for reasons unrelated to the
+//! data. Those failures are kept apart from conversion and processing failures, which
+//! are reported per record in the `ProcessSummary`.
+
+use std::borrow::Borrow;
+
+use dbn::MboMsg;
+use thiserror::Error;
+
+use crate::orderbook::{
+    ErrorPolicy, MarketByOrderMessage, MboObserver, MboProcessor, ProcessFailure, ProcessSummary,
+};
+
+/// The record source failed after `processed` records had been received from it.
+#[derive(Debug, Error)]
+#[error("Record source failed after {processed} records: {source}")]
+pub struct RecordSourceError<E> {
+    pub processed: usize,
+    #[source]
+    pub source: E,
+}
+
+impl<O: MboObserver> MboProcessor<O> {
+    /// Converts and applies records from `records` until it is exhausted or fails.
+    ///
+    /// Records are pulled one at a time, so the source may be unbounded. A record that
+    /// fails conversion or processing is recorded in the summary with its 0-based index
+    /// and handled according to the `ErrorPolicy`. An error from the source itself ends
+    /// processing with a `RecordSourceError`; the book keeps every record applied
+    /// before it.
+    pub fn process_records<I, R, E>(
+        &mut self,
+        records: I,
+    ) -> Result<ProcessSummary, RecordSourceError<E>>
+    where
+        I: IntoIterator<Item = Result<R, E>>,
+        R: Borrow<MboMsg>,
+    {
+        let mut summary = ProcessSummary::default();
+        for (index, record) in records.into_iter().enumerate() {
+            let record = record.map_err(|source| RecordSourceError {
+                processed: index,
+                source,
+            })?;
+            let outcome = MarketByOrderMessage::try_from(record.borrow())
+                .and_then(|message| self.process_message(&message).map(|()| message.action));
+            match outcome {
+                Ok(action) => {
+                    summary.processed += 1;
+                    summary.action_counts.record(action);
+                }
+                Err(error) => {
+                    summary.failures.push(ProcessFailure { index, error });
+                    if self.error_policy() == ErrorPolicy::FailFast {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::c_char;
+    use std::io;
+
+    use crate::orderbook::MboProcessError;
+
+    fn record(action: u8, side: u8, order_id: u64, price: i64) -> MboMsg {
+        MboMsg {
+            hd: dbn::RecordHeader::new::<MboMsg>(dbn::enums::rtype::MBO, 1, 1, 1_000),
+            order_id,
+            price,
+            size: 10,
+            flags: dbn::FlagSet::new(dbn::flags::LAST),
+            channel_id: 0,
+            action: action as c_char,
+            side: side as c_char,
+            ts_recv: 1_050,
+            ts_in_delta: 0,
+            sequence: 1,
+        }
+    }
+
+    /// A mock source that yields `records` and then fails with a reset connection.
+    fn failing_source(records: Vec<MboMsg>) -> impl Iterator<Item = io::Result<MboMsg>> {
+        records
+            .into_iter()
+            .map(Ok)
+            .chain([Err(io::Error::from(io::ErrorKind::ConnectionReset))])
+    }
+
+    #[test]
+    fn test_complete_source() {
+        let records = [
+            record(b'A', b'B', 1, 100),
+            record(b'A', b'A', 2, 101),
+            record(b'C', b'B', 1, 100),
+        ];
+        let mut proc = MboProcessor::new();
+        let summary = proc
+            .process_records(records.iter().map(Ok::<_, io::Error>))
+            .unwrap();
+        assert!(summary.is_success());
+        assert_eq!(summary.processed, 3);
+        assert_eq!(proc.best_bid(), None);
+        assert_eq!(proc.best_ask(), Some((101, 10)));
+    }
+
+    #[test]
+    fn test_source_error_reports_processed_count() {
+        let mut proc = MboProcessor::new();
+        let error = proc
+            .process_records(failing_source(vec![
+                record(b'A', b'B', 1, 100),
+                record(b'A', b'B', 2, 101),
+            ]))
+            .unwrap_err();
+        assert_eq!(error.processed, 2);
+        assert_eq!(error.source.kind(), io::ErrorKind::ConnectionReset);
+        // Records received before the failure stay applied
+        assert_eq!(proc.best_bid(), Some((101, 10)));
+    }
+
+    #[test]
+    fn test_bad_record_follows_error_policy() {
+        let records = [
+            record(b'A', b'B', 1, 100),
+            record(b'?', b'B', 2, 101),
+            record(b'A', b'B', 3, 102),
+        ];
+
+        let mut proc = MboProcessor::new();
+        let summary = proc
+            .process_records(records.iter().map(Ok::<_, io::Error>))
+            .unwrap();
+        assert_eq!(summary.processed, 1);
+        assert!(matches!(
+            summary.failures[..],
+            [ProcessFailure {
+                index: 1,
+                error: MboProcessError::UnknownAction(_)
+            }]
+        ));
+
+        let mut proc = MboProcessor::new().with_error_policy(ErrorPolicy::Collect);
+        let summary = proc
+            .process_records(records.iter().map(Ok::<_, io::Error>))
+            .unwrap();
+        assert_eq!(summary.processed, 2);
+        assert_eq!(summary.failures.len(), 1);
+        assert_eq!(proc.best_bid(), Some((102, 10)));
+    }
+}
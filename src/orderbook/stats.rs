@@ -0,0 +1,98 @@
+use std::fmt;
+use std::iter::Sum;
+use std::ops::Add;
+
+use serde::{Deserialize, Serialize};
+
+use crate::orderbook::{Action, ActionCounts};
+
+/// Running counters maintained by `MboProcessor` while processing messages.
+///
+/// Only counts and volumes are tracked; throughput is left to the caller, which
+/// knows the wall-clock time spent replaying. The warning counters are taken from the
+/// books' `Anomalies`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MboStats {
+    /// Messages passed to `process_message`, including those that failed.
+    pub messages: u64,
+    /// Messages per action, including those that failed.
+    pub action_counts: ActionCounts,
+    /// Adds for an order id already in the book (the old order is replaced).
+    pub duplicate_adds: u64,
+    /// Cancels for an order id not in the book (ignored).
+    pub unknown_cancels: u64,
+    /// Modifies for an order id not in the book (rejected with an error).
+    pub unknown_modifies: u64,
+    /// Cancels of an indexed order missing from its price level (ignored).
+    pub missing_levels: u64,
+    /// Sum of Trade sizes. Fills are not included, as they report the passive
+    /// side of the same executions.
+    pub traded_volume: u64,
+    /// Messages that failed validation under `ValidationPolicy::Reject`.
+    pub rejected_messages: u64,
+    /// Messages that failed validation and were ignored under `ValidationPolicy::Skip`.
+    pub skipped_messages: u64,
+}
+
+impl MboStats {
+    /// Total number of warnings raised by the book.
+    pub fn warnings(&self) -> u64 {
+        self.duplicate_adds + self.unknown_cancels + self.unknown_modifies + self.missing_levels
+    }
+}
+
+impl Add for MboStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            messages: self.messages + other.messages,
+            action_counts: self.action_counts + other.action_counts,
+            duplicate_adds: self.duplicate_adds + other.duplicate_adds,
+            unknown_cancels: self.unknown_cancels + other.unknown_cancels,
+            unknown_modifies: self.unknown_modifies + other.unknown_modifies,
+            missing_levels: self.missing_levels + other.missing_levels,
+            traded_volume: self.traded_volume + other.traded_volume,
+            rejected_messages: self.rejected_messages + other.rejected_messages,
+            skipped_messages: self.skipped_messages + other.skipped_messages,
+        }
+    }
+}
+
+impl Sum for MboStats {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), Add::add)
+    }
+}
+
+impl fmt::Display for MboStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Total MBO messages: {}", self.messages)?;
+        [
+            Action::Add,
+            Action::Cancel,
+            Action::Modify,
+            Action::Fill,
+            Action::Clear,
+            Action::Trade,
+        ]
+        .iter()
+        .try_for_each(|&action| {
+            writeln!(
+                f,
+                "  {:>8}: {:>12}",
+                action.to_string(),
+                self.action_counts.get(action)
+            )
+        })?;
+        writeln!(f, "Traded volume:      {}", self.traded_volume)?;
+        writeln!(f, "Warnings:           {}", self.warnings())?;
+        writeln!(f, "  Duplicate adds:   {}", self.duplicate_adds)?;
+        writeln!(f, "  Unknown cancels:  {}", self.unknown_cancels)?;
+        writeln!(f, "  Unknown modifies: {}", self.unknown_modifies)?;
+        writeln!(f, "  Missing levels:   {}", self.missing_levels)?;
+        writeln!(f, "Invalid messages:")?;
+        writeln!(f, "  Rejected:         {}", self.rejected_messages)?;
+        write!(f, "  Skipped:          {}", self.skipped_messages)
+    }
+}
@@ -0,0 +1,141 @@
+use std::fmt;
+use std::ops::Add;
+
+use serde::{Deserialize, Serialize};
+
+use crate::orderbook::{Action, MboProcessError};
+
+/// Per-action message counters.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActionCounts {
+    pub add: u64,
+    pub cancel: u64,
+    pub modify: u64,
+    pub fill: u64,
+    pub clear: u64,
+    pub trade: u64,
+}
+
+impl ActionCounts {
+    /// Increments the counter for the given action.
+    pub fn record(&mut self, action: Action) {
+        match action {
+            Action::Add => self.add += 1,
+            Action::Cancel => self.cancel += 1,
+            Action::Modify => self.modify += 1,
+            Action::Fill => self.fill += 1,
+            Action::Clear => self.clear += 1,
+            Action::Trade => self.trade += 1,
+        }
+    }
+
+    /// Returns the counter for the given action.
+    pub fn get(&self, action: Action) -> u64 {
+        match action {
+            Action::Add => self.add,
+            Action::Cancel => self.cancel,
+            Action::Modify => self.modify,
+            Action::Fill => self.fill,
+            Action::Clear => self.clear,
+            Action::Trade => self.trade,
+        }
+    }
+
+    /// Sum over all actions.
+    pub fn total(&self) -> u64 {
+        self.add + self.cancel + self.modify + self.fill + self.clear + self.trade
+    }
+}
+
+impl Add for ActionCounts {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            add: self.add + other.add,
+            cancel: self.cancel + other.cancel,
+            modify: self.modify + other.modify,
+            fill: self.fill + other.fill,
+            clear: self.clear + other.clear,
+            trade: self.trade + other.trade,
+        }
+    }
+}
+
+/// A message that failed to process, identified by its 0-based position in the input.
+#[derive(Debug, Clone)]
+pub struct ProcessFailure {
+    pub index: usize,
+    pub error: MboProcessError,
+}
+
+/// Outcome of processing a batch of messages via `MboProcessor::process_messages`.
+///
+/// Under `ErrorPolicy::FailFast` processing stops at the first failure, so `failures`
+/// holds at most one entry. Under `ErrorPolicy::Collect` every failure is recorded and
+/// processing continues with the next message.
+#[derive(Debug, Default, Clone)]
+pub struct ProcessSummary {
+    /// Number of messages successfully applied.
+    pub processed: u64,
+    /// Successfully applied messages broken down by action.
+    pub action_counts: ActionCounts,
+    /// Failed messages, in input order.
+    pub failures: Vec<ProcessFailure>,
+}
+
+impl ProcessSummary {
+    /// Returns the first failure, if any.
+    pub fn first_failure(&self) -> Option<&ProcessFailure> {
+        self.failures.first()
+    }
+
+    /// True if no message failed.
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Adds the outcome of a later batch whose first message was at index `offset` of
+    /// the whole input, so that failure indices stay relative to the whole input.
+    pub fn append(&mut self, batch: ProcessSummary, offset: usize) {
+        self.processed += batch.processed;
+        self.action_counts = self.action_counts + batch.action_counts;
+        self.failures
+            .extend(batch.failures.into_iter().map(|failure| ProcessFailure {
+                index: offset + failure.index,
+                ..failure
+            }));
+    }
+}
+
+impl fmt::Display for ProcessSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Processed messages: {}", self.processed)?;
+        [
+            Action::Add,
+            Action::Cancel,
+            Action::Modify,
+            Action::Fill,
+            Action::Clear,
+            Action::Trade,
+        ]
+        .iter()
+        .try_for_each(|&action| {
+            writeln!(
+                f,
+                "  {:>8}: {:>12}",
+                action.to_string(),
+                self.action_counts.get(action)
+            )
+        })?;
+        write!(f, "Failures: {}", self.failures.len())?;
+        match self.first_failure() {
+            Some(failure) => write!(
+                f,
+                " (first at message #{}: {})",
+                failure.index, failure.error
+            ),
+            None => Ok(()),
+        }
+    }
+}
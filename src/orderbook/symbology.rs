@@ -0,0 +1,358 @@
+//! Resolving instrument ids to symbols, and back, from the mappings in DBN metadata.
+//!
+//! Each mapping in a DBN file covers a date range. A symbol can move from one
+//! instrument to another mid-file (a continuous contract rolling over), and a parent
+//! symbol such as `ES.FUT` maps to several instruments at once, so every lookup takes
+//! the date it applies to.
+
+use dbn::{Metadata, SType};
+use thiserror::Error;
+use time::Date;
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum SymbolMapError {
+    #[error("Mapping for '{symbol}' has instrument id {value:?}, which is not a number")]
+    InvalidInstrumentId { symbol: String, value: String },
+
+    #[error("{}", unknown_symbol(.symbol, .available))]
+    UnknownSymbol {
+        symbol: String,
+        /// Every symbol in the map, sorted.
+        available: Vec<String>,
+    },
+}
+
+/// Most symbols an `UnknownSymbol` error lists before giving only their count.
+const MAX_LISTED_SYMBOLS: usize = 20;
+
+fn unknown_symbol(symbol: &str, available: &[String]) -> String {
+    match available.len() {
+        0 => format!("Symbol '{symbol}' not found: the input has no symbol mappings"),
+        count if count > MAX_LISTED_SYMBOLS => format!(
+            "Symbol '{symbol}' not found among the {count} mapped symbols; symbols must match \
+             exactly in the symbology the data was requested in, e.g. ESZ4 or ES.FUT"
+        ),
+        _ => format!(
+            "Symbol '{symbol}' not found, available symbols: {}",
+            available.join(", ")
+        ),
+    }
+}
+
+/// One symbol ↔ instrument id mapping, valid from `start` up to but excluding `end`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Interval {
+    symbol: String,
+    instrument_id: u32,
+    start: Date,
+    end: Date,
+}
+
+impl Interval {
+    fn contains(&self, date: Date) -> bool {
+        self.start <= date && date < self.end
+    }
+}
+
+/// Two-way lookup between instrument ids and symbols over the dates of a DBN file.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SymbolMap {
+    intervals: Vec<Interval>,
+}
+
+impl SymbolMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds the map from the `mappings` of DBN metadata.
+    ///
+    /// Mappings are usually from the requested symbols to instrument ids. When the
+    /// input symbology is itself instrument ids the roles are reversed, which is
+    /// handled as well.
+    pub fn from_metadata(metadata: &Metadata) -> Result<Self, SymbolMapError> {
+        let ids_are_input = metadata.stype_in == Some(SType::InstrumentId);
+        let mut map = Self::new();
+        for mapping in &metadata.mappings {
+            for interval in &mapping.intervals {
+                // An empty symbol marks a gap where the symbol did not resolve
+                if interval.symbol.is_empty() {
+                    continue;
+                }
+                let (symbol, id) = if ids_are_input {
+                    (&interval.symbol, &mapping.raw_symbol)
+                } else {
+                    (&mapping.raw_symbol, &interval.symbol)
+                };
+                let instrument_id =
+                    id.parse()
+                        .map_err(|_| SymbolMapError::InvalidInstrumentId {
+                            symbol: symbol.clone(),
+                            value: id.clone(),
+                        })?;
+                map.insert(
+                    symbol,
+                    instrument_id,
+                    interval.start_date,
+                    interval.end_date,
+                );
+            }
+        }
+        Ok(map)
+    }
+
+    /// Maps `symbol` to `instrument_id` from `start` up to but excluding `end`.
+    pub fn insert(&mut self, symbol: &str, instrument_id: u32, start: Date, end: Date) {
+        self.intervals.push(Interval {
+            symbol: symbol.to_owned(),
+            instrument_id,
+            start,
+            end,
+        });
+    }
+
+    /// Adds every mapping of `other`, e.g. from the next file of a multi-file input.
+    pub fn merge(&mut self, other: SymbolMap) {
+        self.intervals.extend(other.intervals);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    /// The symbol of `instrument_id` on `date`.
+    pub fn symbol(&self, instrument_id: u32, date: Date) -> Option<&str> {
+        self.intervals
+            .iter()
+            .find(|i| i.instrument_id == instrument_id && i.contains(date))
+            .map(|i| i.symbol.as_str())
+    }
+
+    /// The symbol `instrument_id` was last mapped to, for labelling output that spans
+    /// the whole file.
+    pub fn latest_symbol(&self, instrument_id: u32) -> Option<&str> {
+        self.intervals
+            .iter()
+            .filter(|i| i.instrument_id == instrument_id)
+            .max_by_key(|i| i.end)
+            .map(|i| i.symbol.as_str())
+    }
+
+    /// The instruments `symbol` maps to on `date`; more than one for a parent symbol.
+    pub fn instrument_ids<'a>(
+        &'a self,
+        symbol: &'a str,
+        date: Date,
+    ) -> impl Iterator<Item = u32> + 'a {
+        self.intervals
+            .iter()
+            .filter(move |i| i.symbol == symbol && i.contains(date))
+            .map(|i| i.instrument_id)
+    }
+
+    /// Every symbol in the map, sorted and without repeats.
+    pub fn symbols(&self) -> Vec<&str> {
+        let mut symbols: Vec<&str> = self.intervals.iter().map(|i| i.symbol.as_str()).collect();
+        symbols.sort_unstable();
+        symbols.dedup();
+        symbols
+    }
+
+    /// The instruments `symbol` maps to on any date, sorted. Fails with the symbols that
+    /// are mapped if `symbol` is not one of them.
+    pub fn resolve(&self, symbol: &str) -> Result<Vec<u32>, SymbolMapError> {
+        let mut ids: Vec<u32> = self
+            .intervals
+            .iter()
+            .filter(|i| i.symbol == symbol)
+            .map(|i| i.instrument_id)
+            .collect();
+        if ids.is_empty() {
+            return Err(SymbolMapError::UnknownSymbol {
+                symbol: symbol.to_owned(),
+                available: self.symbols().into_iter().map(str::to_owned).collect(),
+            });
+        }
+        ids.sort_unstable();
+        ids.dedup();
+        Ok(ids)
+    }
+
+    /// Whether `symbol` maps to `instrument_id` on `date`.
+    pub fn matches(&self, symbol: &str, instrument_id: u32, date: Date) -> bool {
+        self.instrument_ids(symbol, date)
+            .any(|id| id == instrument_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dbn::{MappingInterval, SymbolMapping};
+    use time::macros::date;
+
+    fn metadata(stype_in: SType, mappings: Vec<SymbolMapping>) -> Metadata {
+        let mut metadata = dbn::MetadataBuilder::new()
+            .dataset("GLBX.MDP3".to_owned())
+            .schema(Some(dbn::Schema::Mbo))
+            .start(0)
+            .stype_in(Some(stype_in))
+            .stype_out(SType::InstrumentId)
+            .build();
+        metadata.mappings = mappings;
+        metadata
+    }
+
+    fn mapping(raw_symbol: &str, intervals: &[(Date, Date, &str)]) -> SymbolMapping {
+        SymbolMapping {
+            raw_symbol: raw_symbol.to_owned(),
+            intervals: intervals
+                .iter()
+                .map(|&(start_date, end_date, symbol)| MappingInterval {
+                    start_date,
+                    end_date,
+                    symbol: symbol.to_owned(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_remap_mid_file() {
+        // ES.c.0 rolls from the December to the March contract on the 20th
+        let map = SymbolMap::from_metadata(&metadata(
+            SType::Continuous,
+            vec![mapping(
+                "ES.c.0",
+                &[
+                    (date!(2024 - 12 - 18), date!(2024 - 12 - 20), "100"),
+                    (date!(2024 - 12 - 20), date!(2024 - 12 - 23), "200"),
+                ],
+            )],
+        ))
+        .unwrap();
+
+        assert_eq!(map.symbol(100, date!(2024 - 12 - 19)), Some("ES.c.0"));
+        // End dates are exclusive
+        assert_eq!(map.symbol(100, date!(2024 - 12 - 20)), None);
+        assert_eq!(map.symbol(200, date!(2024 - 12 - 20)), Some("ES.c.0"));
+        assert_eq!(map.symbol(200, date!(2024 - 12 - 23)), None);
+
+        let ids = |date| map.instrument_ids("ES.c.0", date).collect::<Vec<u32>>();
+        assert_eq!(ids(date!(2024 - 12 - 19)), vec![100]);
+        assert_eq!(ids(date!(2024 - 12 - 20)), vec![200]);
+        assert!(ids(date!(2024 - 12 - 17)).is_empty());
+        assert!(!map.matches("ES.c.0", 100, date!(2024 - 12 - 20)));
+        assert!(map.matches("ES.c.0", 200, date!(2024 - 12 - 20)));
+    }
+
+    #[test]
+    fn test_parent_symbol_and_gaps() {
+        let map = SymbolMap::from_metadata(&metadata(
+            SType::Parent,
+            vec![
+                mapping(
+                    "ES.FUT",
+                    &[(date!(2024 - 12 - 18), date!(2024 - 12 - 20), "100")],
+                ),
+                mapping(
+                    "ES.FUT",
+                    &[
+                        (date!(2024 - 12 - 18), date!(2024 - 12 - 19), "200"),
+                        (date!(2024 - 12 - 19), date!(2024 - 12 - 20), ""),
+                    ],
+                ),
+            ],
+        ))
+        .unwrap();
+
+        let mut ids: Vec<u32> = map
+            .instrument_ids("ES.FUT", date!(2024 - 12 - 18))
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec![100, 200]);
+        // The unresolved interval is not a mapping
+        assert_eq!(map.symbol(200, date!(2024 - 12 - 19)), None);
+        assert_eq!(map.latest_symbol(200), Some("ES.FUT"));
+        assert_eq!(map.latest_symbol(300), None);
+        assert_eq!(
+            map.instrument_ids("NQ.FUT", date!(2024 - 12 - 18)).count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_instrument_id_input() {
+        let map = SymbolMap::from_metadata(&metadata(
+            SType::InstrumentId,
+            vec![mapping(
+                "100",
+                &[(date!(2024 - 12 - 18), date!(2024 - 12 - 20), "ESZ4")],
+            )],
+        ))
+        .unwrap();
+        assert_eq!(map.symbol(100, date!(2024 - 12 - 18)), Some("ESZ4"));
+        assert!(map.matches("ESZ4", 100, date!(2024 - 12 - 19)));
+    }
+
+    #[test]
+    fn test_invalid_instrument_id() {
+        let error = SymbolMap::from_metadata(&metadata(
+            SType::RawSymbol,
+            vec![mapping(
+                "ESZ4",
+                &[(date!(2024 - 12 - 18), date!(2024 - 12 - 20), "ESZ4")],
+            )],
+        ))
+        .unwrap_err();
+        assert_eq!(
+            error,
+            SymbolMapError::InvalidInstrumentId {
+                symbol: "ESZ4".to_owned(),
+                value: "ESZ4".to_owned()
+            }
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        let map = SymbolMap::from_metadata(&metadata(
+            SType::Continuous,
+            vec![
+                mapping(
+                    "ES.c.0",
+                    &[
+                        (date!(2024 - 12 - 18), date!(2024 - 12 - 20), "200"),
+                        (date!(2024 - 12 - 20), date!(2024 - 12 - 23), "100"),
+                    ],
+                ),
+                mapping(
+                    "NQ.c.0",
+                    &[(date!(2024 - 12 - 18), date!(2024 - 12 - 23), "300")],
+                ),
+            ],
+        ))
+        .unwrap();
+        assert_eq!(map.resolve("ES.c.0").unwrap(), vec![100, 200]);
+        assert_eq!(map.symbols(), vec!["ES.c.0", "NQ.c.0"]);
+
+        let error = map.resolve("ESZ4").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Symbol 'ESZ4' not found, available symbols: ES.c.0, NQ.c.0"
+        );
+
+        let mut many = SymbolMap::new();
+        for id in 0..=MAX_LISTED_SYMBOLS as u32 {
+            many.insert(
+                &format!("S{id}"),
+                id,
+                date!(2024 - 12 - 18),
+                date!(2024 - 12 - 19),
+            );
+        }
+        let message = many.resolve("ESZ4").unwrap_err().to_string();
+        assert!(message.contains("among the 21 mapped symbols"), "{message}");
+    }
+}
@@ -0,0 +1,235 @@
+//! Restricting processing to a window of event time, e.g. the continuous session.
+//!
+//! Dropping the messages before the window leaves the book without the orders they
+//! added, so the window can instead be warmed up: the earlier messages are applied to
+//! build the book, and only what follows is reported.
+
+use std::borrow::Borrow;
+use std::iter::{self, Peekable};
+
+use thiserror::Error;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+use crate::orderbook::{MarketByOrderMessage, MboObserver, MboProcessor, ProcessSummary};
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum TimeRangeError {
+    #[error(
+        "Invalid time '{0}', expected nanoseconds since the UNIX epoch or an RFC 3339 timestamp"
+    )]
+    InvalidTime(String),
+
+    #[error("Time range ends at {end_ns}, which is not after its start at {start_ns}")]
+    Reversed { start_ns: u64, end_ns: u64 },
+}
+
+/// Parses nanoseconds since the UNIX epoch, e.g. `1709303400000000000`, or an RFC 3339
+/// timestamp, e.g. `2024-03-01T14:30:00Z`.
+pub fn parse_time_ns(s: &str) -> Result<u64, TimeRangeError> {
+    let invalid = || TimeRangeError::InvalidTime(s.to_owned());
+    if let Ok(ns) = s.parse() {
+        return Ok(ns);
+    }
+    let time = OffsetDateTime::parse(s, &Rfc3339).map_err(|_| invalid())?;
+    u64::try_from(time.unix_timestamp_nanos()).map_err(|_| invalid())
+}
+
+/// Event times from `start_ns`, inclusive, to `end_ns`, exclusive, in nanoseconds since
+/// the UNIX epoch. A missing bound leaves that side open.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TimeRange {
+    pub start_ns: Option<u64>,
+    pub end_ns: Option<u64>,
+}
+
+impl TimeRange {
+    /// Fails if `end_ns` is not after `start_ns`, which would leave the range empty.
+    pub fn new(start_ns: Option<u64>, end_ns: Option<u64>) -> Result<Self, TimeRangeError> {
+        if let (Some(start_ns), Some(end_ns)) = (start_ns, end_ns)
+            && end_ns <= start_ns
+        {
+            return Err(TimeRangeError::Reversed { start_ns, end_ns });
+        }
+        Ok(Self { start_ns, end_ns })
+    }
+
+    /// True if the message's event time is before the start.
+    pub fn is_before_start(&self, message: &MarketByOrderMessage) -> bool {
+        self.start_ns
+            .is_some_and(|start| message.event_time.unix_timestamp_nanos() < i128::from(start))
+    }
+
+    /// True if the message's event time is at or after the end.
+    pub fn is_past_end(&self, message: &MarketByOrderMessage) -> bool {
+        self.end_ns
+            .is_some_and(|end| message.event_time.unix_timestamp_nanos() >= i128::from(end))
+    }
+
+    pub fn contains(&self, message: &MarketByOrderMessage) -> bool {
+        !self.is_before_start(message) && !self.is_past_end(message)
+    }
+}
+
+/// Messages counted against a `TimeRange` by their event time.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WindowCounts {
+    /// Before the start, whether dropped or applied to warm up the book.
+    pub before: u64,
+    pub inside: u64,
+    /// At or after the end.
+    pub after: u64,
+}
+
+impl WindowCounts {
+    /// Counts `message` as before, inside or after `range`.
+    pub fn record(&mut self, range: &TimeRange, message: &MarketByOrderMessage) {
+        if range.is_before_start(message) {
+            self.before += 1;
+        } else if range.is_past_end(message) {
+            self.after += 1;
+        } else {
+            self.inside += 1;
+        }
+    }
+
+    pub fn outside(&self) -> u64 {
+        self.before + self.after
+    }
+}
+
+/// Keeps the messages whose event time is within `range`.
+///
+/// Every message is checked, so messages out of event-time order are kept or dropped
+/// individually rather than ending the stream.
+pub fn filter_time_range<I>(messages: I, range: TimeRange) -> impl Iterator<Item = I::Item>
+where
+    I: IntoIterator,
+    I::Item: Borrow<MarketByOrderMessage>,
+{
+    messages
+        .into_iter()
+        .filter(move |message| range.contains(message.borrow()))
+}
+
+impl<O: MboObserver> MboProcessor<O> {
+    /// Processes the messages at the front of `messages` whose event time is before the
+    /// start of `range`, leaving the first message at or after the start in `messages`.
+    ///
+    /// Warming up with the observers' output turned off, then processing the rest with
+    /// it on, reports the window with the book built from everything before it.
+    pub fn warm_up<I>(&mut self, messages: &mut Peekable<I>, range: TimeRange) -> ProcessSummary
+    where
+        I: Iterator,
+        I::Item: Borrow<MarketByOrderMessage>,
+    {
+        self.process_messages(iter::from_fn(|| {
+            messages.next_if(|message| range.is_before_start(message.borrow()))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use time::Duration;
+
+    use crate::orderbook::{Action, Side};
+
+    fn message(action: Action, order_id: u64, price: i64, ts_event: i64) -> MarketByOrderMessage {
+        let event_time = OffsetDateTime::UNIX_EPOCH + Duration::nanoseconds(ts_event);
+        MarketByOrderMessage {
+            sequence: ts_event as u32,
+            ..MarketByOrderMessage::test(action, Side::Bid, price, order_id, 10).at(event_time)
+        }
+    }
+
+    /// Orders 1 and 2 rest before the start at 100; after it order 1 is cancelled and
+    /// order 3 added.
+    fn messages() -> Vec<MarketByOrderMessage> {
+        vec![
+            message(Action::Add, 1, 101, 10),
+            message(Action::Add, 2, 100, 20),
+            message(Action::Cancel, 1, 101, 100),
+            message(Action::Add, 3, 99, 110),
+            message(Action::Add, 4, 98, 200),
+        ]
+    }
+
+    #[test]
+    fn test_warm_up_builds_book_before_start() {
+        let range = TimeRange::new(Some(100), Some(200)).unwrap();
+
+        let mut warm = MboProcessor::new();
+        let mut stream =
+            filter_time_range(messages(), TimeRange::new(None, range.end_ns).unwrap()).peekable();
+        let warm_up = warm.warm_up(&mut stream, range);
+        assert_eq!(warm_up.processed, 2);
+        assert_eq!(stream.peek().map(|message| message.order_id), Some(1));
+        assert!(warm.process_messages(stream).is_success());
+
+        // Matches processing everything up to the end
+        let mut full = MboProcessor::new();
+        full.process_messages(&messages()[..4]);
+        assert_eq!(warm.best_bid(), full.best_bid());
+        assert_eq!(warm.best_bid(), Some((100, 10)));
+        assert_eq!(
+            warm.book(1).unwrap().top_n_bids(3),
+            vec![(100, 10), (99, 10)]
+        );
+
+        // Starting cold at the start misses order 2 and cancels an unknown order 1
+        let mut cold = MboProcessor::new();
+        assert!(
+            cold.process_messages(filter_time_range(messages(), range))
+                .is_success()
+        );
+        assert_eq!(cold.best_bid(), Some((99, 10)));
+        assert_eq!(cold.stats().unknown_cancels, 1);
+        assert_eq!(warm.stats().unknown_cancels, 0);
+    }
+
+    #[test]
+    fn test_parse_time_ns() {
+        assert_eq!(parse_time_ns("1500").unwrap(), 1_500);
+        assert_eq!(
+            parse_time_ns("2024-03-01T14:30:00.000000001Z").unwrap(),
+            1_709_303_400_000_000_001
+        );
+        assert_eq!(
+            parse_time_ns("2024-03-01T15:30:00+01:00").unwrap(),
+            1_709_303_400_000_000_000
+        );
+        assert!(matches!(
+            parse_time_ns("yesterday"),
+            Err(TimeRangeError::InvalidTime(_))
+        ));
+        assert!(matches!(
+            TimeRange::new(Some(2), Some(1)),
+            Err(TimeRangeError::Reversed { .. })
+        ));
+        assert!(matches!(
+            TimeRange::new(Some(2), Some(2)),
+            Err(TimeRangeError::Reversed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_window_counts() {
+        let range = TimeRange::new(Some(100), Some(200)).unwrap();
+        let mut counts = WindowCounts::default();
+        for message in &messages() {
+            counts.record(&range, message);
+        }
+        assert_eq!(
+            counts,
+            WindowCounts {
+                before: 2,
+                inside: 2,
+                after: 1
+            }
+        );
+        assert_eq!(counts.outside(), 3);
+    }
+}
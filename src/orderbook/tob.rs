@@ -0,0 +1,530 @@
+//! Per-message top-of-book recording: an MBP-1 feed derived from MBO.
+//!
+//! Column names and types follow Databento's MBP-1 schema (`ts_event`, `publisher_id`,
+//! `instrument_id`, `sequence`, `action`, `bid_px_00`, ...) so the output can be joined against
+//! Databento's own MBP-1 files. Prices are dbn fixed-point integers. A missing side
+//! has null price and size.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "polars")]
+use polars::io::{ipc::BatchedWriter as IpcBatchedWriter, parquet::write::BatchedWriter};
+#[cfg(feature = "polars")]
+use polars::prelude::{
+    DataFrame, Int64Chunked, IntoColumn, IpcWriter, ParquetWriter, PolarsError, PolarsResult,
+    SerWriter, TimeUnit, TimeZone, df,
+};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::orderbook::{
+    BookKey, MarketByOrderMessage, MboObserver, Mbp1Row, Mbp1Tracker, OrderBook, create_output,
+};
+
+#[derive(Debug, Error)]
+pub enum TopOfBookError {
+    #[error("Failed to write top of book: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Failed to write top of book: {0}")]
+    Csv(#[from] csv::Error),
+
+    #[cfg(feature = "polars")]
+    #[error("Failed to write top of book: {0}")]
+    Polars(#[from] PolarsError),
+
+    #[error(
+        "Cannot write top of book to {0}: expected a .csv, .parquet or Arrow IPC (.arrow, \
+         .feather, .ipc) file"
+    )]
+    UnsupportedFormat(PathBuf),
+}
+
+const CSV_HEADER: [&str; 9] = [
+    "ts_event",
+    "publisher_id",
+    "instrument_id",
+    "sequence",
+    "action",
+    "bid_px_00",
+    "ask_px_00",
+    "bid_sz_00",
+    "ask_sz_00",
+];
+
+/// One CSV output row, in `CSV_HEADER` order.
+#[derive(Serialize)]
+struct CsvRow {
+    ts_event: i64,
+    publisher_id: u16,
+    instrument_id: u32,
+    sequence: u32,
+    action: char,
+    bid_px_00: Option<i64>,
+    ask_px_00: Option<i64>,
+    bid_sz_00: Option<u32>,
+    ask_sz_00: Option<u32>,
+}
+
+/// A file that top-of-book rows are appended to, chunk by chunk.
+pub enum TopOfBookWriter {
+    Csv(Box<csv::Writer<File>>),
+    #[cfg(feature = "polars")]
+    Parquet(Box<BatchedWriter<File>>),
+    #[cfg(feature = "polars")]
+    Ipc(Box<IpcBatchedWriter<File>>),
+}
+
+impl TopOfBookWriter {
+    /// Creates the file at `path` and any missing parent directories, choosing CSV,
+    /// parquet or Arrow IPC (`.arrow`, `.feather` or `.ipc`) by its extension. Parquet
+    /// and IPC require the `polars` feature.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, TopOfBookError> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("csv") => Ok(TopOfBookWriter::Csv(Box::new(csv_writer(path)?))),
+            #[cfg(feature = "polars")]
+            Some("parquet") => {
+                let schema = TopOfBookRecorder::new().to_dataframe()?.schema().clone();
+                let writer = ParquetWriter::new(create_output(path, true)?).batched(&schema)?;
+                Ok(TopOfBookWriter::Parquet(Box::new(writer)))
+            }
+            #[cfg(feature = "polars")]
+            Some("arrow" | "feather" | "ipc") => {
+                let schema = TopOfBookRecorder::new().to_dataframe()?.schema().clone();
+                let writer = IpcWriter::new(create_output(path, true)?).batched(&schema)?;
+                Ok(TopOfBookWriter::Ipc(Box::new(writer)))
+            }
+            _ => Err(TopOfBookError::UnsupportedFormat(path.to_owned())),
+        }
+    }
+
+    /// Appends the rows buffered in `recorder`.
+    fn write(&mut self, recorder: &TopOfBookRecorder) -> Result<(), TopOfBookError> {
+        match self {
+            TopOfBookWriter::Csv(writer) => {
+                for row in recorder.csv_rows() {
+                    writer.serialize(row)?;
+                }
+                writer.flush()?;
+            }
+            #[cfg(feature = "polars")]
+            TopOfBookWriter::Parquet(writer) => writer.write_batch(&recorder.to_dataframe()?)?,
+            #[cfg(feature = "polars")]
+            TopOfBookWriter::Ipc(writer) => writer.write_batch(&recorder.to_dataframe()?)?,
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), TopOfBookError> {
+        match self {
+            TopOfBookWriter::Csv(mut writer) => writer.flush()?,
+            #[cfg(feature = "polars")]
+            TopOfBookWriter::Parquet(writer) => {
+                writer.finish()?;
+            }
+            #[cfg(feature = "polars")]
+            TopOfBookWriter::Ipc(mut writer) => writer.finish()?,
+        }
+        Ok(())
+    }
+}
+
+/// Creates a CSV file and writes the header, so that even an empty output has one.
+fn csv_writer(path: &Path) -> Result<csv::Writer<File>, TopOfBookError> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(create_output(path, true)?);
+    writer.write_record(CSV_HEADER)?;
+    Ok(writer)
+}
+
+/// Observer that records the top of book after every applied message.
+///
+/// Each row holds the message's `ts_event`, `instrument_id`, `sequence` and `action`
+/// and the best bid and ask of its book after the message, with the book's
+/// `publisher_id` (zero unless the processor routes by publisher). With deduplication
+/// enabled a row is only recorded when that book's top of book changed.
+///
+/// Rows are buffered column-wise, so memory grows with the number of recorded rows.
+/// For long sessions attach a `TopOfBookWriter` with `with_writer`, which bounds the
+/// buffer to one chunk.
+#[derive(Default)]
+pub struct TopOfBookRecorder {
+    dedup: bool,
+    trackers: HashMap<BookKey, Mbp1Tracker>,
+    writer: Option<TopOfBookWriter>,
+    chunk_rows: usize,
+    /// First write failure; recording stops once set.
+    error: Option<TopOfBookError>,
+    written: usize,
+    ts_event: Vec<i64>,
+    publisher_id: Vec<u16>,
+    instrument_id: Vec<u32>,
+    sequence: Vec<u32>,
+    action: Vec<char>,
+    bid_px: Vec<Option<i64>>,
+    ask_px: Vec<Option<i64>>,
+    bid_sz: Vec<Option<u32>>,
+    ask_sz: Vec<Option<u32>>,
+}
+
+impl TopOfBookRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only record a row when the book's best bid or ask price or size changed.
+    pub fn with_dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Append rows to `writer` whenever `chunk_rows` rows are buffered.
+    ///
+    /// A write failure stops recording; it is returned by `finish`.
+    pub fn with_writer(mut self, writer: TopOfBookWriter, chunk_rows: usize) -> Self {
+        self.writer = Some(writer);
+        self.chunk_rows = chunk_rows.max(1);
+        self
+    }
+
+    /// Number of buffered rows, not counting rows already written.
+    pub fn len(&self) -> usize {
+        self.ts_event.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ts_event.is_empty()
+    }
+
+    /// Number of rows written to the attached writer so far.
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// Writes the buffered rows to the attached writer, if any, and closes it.
+    /// Returns the total number of rows written.
+    pub fn finish(&mut self) -> Result<usize, TopOfBookError> {
+        self.flush();
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        if let Some(writer) = self.writer.take() {
+            writer.finish()?;
+        }
+        Ok(self.written)
+    }
+
+    /// Converts the buffered rows into a DataFrame. `ts_event` is a UTC nanosecond
+    /// datetime, as in Databento's MBP-1 files.
+    #[cfg(feature = "polars")]
+    pub fn to_dataframe(&self) -> PolarsResult<DataFrame> {
+        let ts_event = Int64Chunked::from_vec("ts_event".into(), self.ts_event.clone())
+            .into_datetime(TimeUnit::Nanoseconds, Some(TimeZone::UTC));
+        let action: Vec<String> = self.action.iter().map(char::to_string).collect();
+        let mut df = df!(
+            "publisher_id" => &self.publisher_id,
+            "instrument_id" => &self.instrument_id,
+            "sequence" => &self.sequence,
+            "action" => action,
+            "bid_px_00" => &self.bid_px,
+            "ask_px_00" => &self.ask_px,
+            "bid_sz_00" => &self.bid_sz,
+            "ask_sz_00" => &self.ask_sz,
+        )?;
+        df.insert_column(0, ts_event.into_column())?;
+        Ok(df)
+    }
+
+    /// Writes the buffered rows to a parquet file.
+    #[cfg(feature = "polars")]
+    pub fn write_parquet(&self, path: impl AsRef<Path>) -> PolarsResult<()> {
+        let mut df = self.to_dataframe()?;
+        ParquetWriter::new(create_output(path, true)?).finish(&mut df)?;
+        Ok(())
+    }
+
+    /// Writes the buffered rows to an Arrow IPC (Feather v2) file.
+    #[cfg(feature = "polars")]
+    pub fn write_ipc(&self, path: impl AsRef<Path>) -> PolarsResult<()> {
+        let mut df = self.to_dataframe()?;
+        IpcWriter::new(create_output(path, true)?).finish(&mut df)?;
+        Ok(())
+    }
+
+    /// Writes the buffered rows to a CSV file with a header row. `ts_event` is written
+    /// as nanoseconds since the UNIX epoch.
+    pub fn write_csv(&self, path: impl AsRef<Path>) -> Result<(), TopOfBookError> {
+        let path = path.as_ref();
+        let mut writer = csv_writer(path)?;
+        for row in self.csv_rows() {
+            writer.serialize(row)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn csv_rows(&self) -> impl Iterator<Item = CsvRow> + '_ {
+        (0..self.len()).map(|i| CsvRow {
+            ts_event: self.ts_event[i],
+            publisher_id: self.publisher_id[i],
+            instrument_id: self.instrument_id[i],
+            sequence: self.sequence[i],
+            action: self.action[i],
+            bid_px_00: self.bid_px[i],
+            ask_px_00: self.ask_px[i],
+            bid_sz_00: self.bid_sz[i],
+            ask_sz_00: self.ask_sz[i],
+        })
+    }
+
+    /// Moves the buffered rows to the attached writer.
+    fn flush(&mut self) {
+        if self.is_empty() {
+            return;
+        }
+        let Some(mut writer) = self.writer.take() else {
+            return;
+        };
+        match writer.write(self) {
+            Ok(()) => {
+                self.written += self.len();
+                self.writer = Some(writer);
+            }
+            Err(error) => self.error = Some(error),
+        }
+        self.clear();
+    }
+
+    fn clear(&mut self) {
+        self.ts_event.clear();
+        self.publisher_id.clear();
+        self.instrument_id.clear();
+        self.sequence.clear();
+        self.action.clear();
+        self.bid_px.clear();
+        self.ask_px.clear();
+        self.bid_sz.clear();
+        self.ask_sz.clear();
+    }
+}
+
+impl MboObserver for TopOfBookRecorder {
+    fn on_message_applied(
+        &mut self,
+        message: &MarketByOrderMessage,
+        key: BookKey,
+        book: &OrderBook,
+    ) {
+        if self.error.is_some() {
+            return;
+        }
+        let row = if self.dedup {
+            let tracker = self.trackers.entry(key).or_default();
+            match tracker.update(book) {
+                Some(row) => row,
+                None => return,
+            }
+        } else {
+            Mbp1Row::from(book.bbo())
+        };
+        // Databento sizes are u32; a larger aggregate saturates
+        let size = |size: Option<u64>| size.map(|size| u32::try_from(size).unwrap_or(u32::MAX));
+        self.ts_event
+            .push(message.event_time.unix_timestamp_nanos() as i64);
+        self.publisher_id.push(key.publisher_id);
+        self.instrument_id.push(key.instrument_id);
+        self.sequence.push(message.sequence);
+        self.action.push(message.action_char());
+        self.bid_px.push(row.bid_px);
+        self.ask_px.push(row.ask_px);
+        self.bid_sz.push(size(row.bid_sz));
+        self.ask_sz.push(size(row.ask_sz));
+
+        if self.writer.is_some() && self.len() >= self.chunk_rows {
+            self.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    use time::{Duration, OffsetDateTime};
+
+    use crate::orderbook::{Action, MboProcessor, Side};
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("rainybook-{}-{name}", process::id()))
+    }
+
+    fn msg(
+        seq: u32,
+        action: Action,
+        order_id: u64,
+        side: Side,
+        price: i64,
+    ) -> MarketByOrderMessage {
+        MarketByOrderMessage {
+            instrument_id: 7,
+            sequence: seq,
+            ..MarketByOrderMessage::test(action, side, price, order_id, 10)
+                .at(OffsetDateTime::UNIX_EPOCH + Duration::nanoseconds(seq.into()))
+        }
+    }
+
+    /// Five messages, of which the deep bid and the trade leave the top of book as is.
+    fn messages() -> Vec<MarketByOrderMessage> {
+        vec![
+            msg(1, Action::Add, 1, Side::Bid, 100),
+            msg(2, Action::Add, 2, Side::Ask, 105),
+            msg(3, Action::Add, 3, Side::Bid, 99),
+            msg(4, Action::Trade, 0, Side::Ask, 105),
+            msg(5, Action::Cancel, 1, Side::Bid, 100),
+        ]
+    }
+
+    fn recorded(dedup: bool) -> TopOfBookRecorder {
+        let mut proc = MboProcessor::with_observer(TopOfBookRecorder::new().with_dedup(dedup));
+        assert!(proc.process_messages(messages()).is_success());
+        proc.into_observer()
+    }
+
+    #[test]
+    fn test_row_per_message() {
+        let recorder = recorded(false);
+        assert_eq!(recorder.len(), 5);
+        assert_eq!(recorder.action, vec!['A', 'A', 'A', 'T', 'C']);
+        assert_eq!(recorder.sequence, vec![1, 2, 3, 4, 5]);
+        assert_eq!(
+            recorder.bid_px,
+            vec![Some(100), Some(100), Some(100), Some(100), Some(99)]
+        );
+        assert_eq!(
+            recorder.ask_px,
+            vec![None, Some(105), Some(105), Some(105), Some(105)]
+        );
+        assert_eq!(recorder.ask_sz[0], None);
+    }
+
+    #[test]
+    fn test_dedup() {
+        let recorder = recorded(true);
+        assert_eq!(recorder.sequence, vec![1, 2, 5]);
+        assert_eq!(recorder.bid_px, vec![Some(100), Some(100), Some(99)]);
+    }
+
+    #[test]
+    fn test_chunked_csv_writer() {
+        let path = temp_path("tob.csv");
+        let recorder =
+            TopOfBookRecorder::new().with_writer(TopOfBookWriter::create(&path).unwrap(), 2);
+        let mut proc = MboProcessor::with_observer(recorder);
+        proc.process_messages(messages());
+        // Two chunks of two rows written, one row still buffered
+        assert_eq!(proc.observer().written(), 4);
+        assert_eq!(proc.observer().len(), 1);
+        assert_eq!(proc.observer_mut().finish().unwrap(), 5);
+
+        let csv = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines[0],
+            "ts_event,publisher_id,instrument_id,sequence,action,bid_px_00,ask_px_00,bid_sz_00,\
+             ask_sz_00"
+        );
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[1], "1,0,7,1,A,100,,10,");
+        assert_eq!(lines[5], "5,0,7,5,C,99,105,10,10");
+    }
+
+    #[test]
+    fn test_unsupported_output() {
+        assert!(matches!(
+            TopOfBookWriter::create(temp_path("tob.txt")),
+            Err(TopOfBookError::UnsupportedFormat(_))
+        ));
+    }
+
+    #[cfg(feature = "polars")]
+    #[test]
+    fn test_dataframe_dtypes() {
+        use polars::prelude::DataType;
+
+        let df = recorded(false).to_dataframe().unwrap();
+        assert_eq!(df.shape(), (5, 9));
+        let dtypes: Vec<(&str, DataType)> = df
+            .get_columns()
+            .iter()
+            .map(|column| (column.name().as_str(), column.dtype().clone()))
+            .collect();
+        assert_eq!(
+            dtypes,
+            vec![
+                (
+                    "ts_event",
+                    DataType::Datetime(TimeUnit::Nanoseconds, Some(TimeZone::UTC))
+                ),
+                ("publisher_id", DataType::UInt16),
+                ("instrument_id", DataType::UInt32),
+                ("sequence", DataType::UInt32),
+                ("action", DataType::String),
+                ("bid_px_00", DataType::Int64),
+                ("ask_px_00", DataType::Int64),
+                ("bid_sz_00", DataType::UInt32),
+                ("ask_sz_00", DataType::UInt32),
+            ]
+        );
+    }
+
+    #[cfg(feature = "polars")]
+    #[test]
+    fn test_chunked_parquet_writer() {
+        use polars::prelude::{ParquetReader, SerReader};
+
+        let path = temp_path("tob.parquet");
+        let mut proc = MboProcessor::with_observer(
+            TopOfBookRecorder::new().with_writer(TopOfBookWriter::create(&path).unwrap(), 2),
+        );
+        proc.process_messages(messages());
+        assert_eq!(proc.observer_mut().finish().unwrap(), 5);
+
+        let df = ParquetReader::new(File::open(&path).unwrap())
+            .finish()
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(df.height(), 5);
+        assert_eq!(
+            df.schema(),
+            recorded(false).to_dataframe().unwrap().schema()
+        );
+    }
+
+    #[cfg(feature = "polars")]
+    #[test]
+    fn test_chunked_ipc_writer() {
+        use polars::prelude::{IpcReader, SerReader};
+
+        let path = temp_path("tob.feather");
+        let mut proc = MboProcessor::with_observer(
+            TopOfBookRecorder::new().with_writer(TopOfBookWriter::create(&path).unwrap(), 2),
+        );
+        proc.process_messages(messages());
+        assert_eq!(proc.observer_mut().finish().unwrap(), 5);
+
+        let df = IpcReader::new(File::open(&path).unwrap()).finish().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(df.equals_missing(&recorded(false).to_dataframe().unwrap()));
+    }
+}
@@ -0,0 +1,625 @@
+//! Classifying trades against the quote they printed into.
+//!
+//! A venue reports an event's prints before, or interleaved with, the Cancels and
+//! Modifies that take the traded quantity off the book, so by its second print the
+//! book no longer shows what the aggressor met. `TradeQuality` therefore judges every
+//! print of an event against the book as the event's first print found it: its BBO,
+//! and the quantity displayed at each price it printed at, net of its earlier prints.
+
+use std::ops::Bound;
+
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+
+#[cfg(feature = "polars")]
+use polars::prelude::{DataFrame, PolarsResult, df};
+
+use crate::orderbook::book::mid;
+use crate::orderbook::{Action, Bbo, BboHistory, BookHandler, MarketByOrderMessage, Side};
+
+/// Where a trade printed relative to the best price opposite its aggressor before the
+/// event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TradeClass {
+    /// At the best opposite price.
+    AtTouch,
+    /// Better than the best opposite price for the aggressor: inside the spread, or
+    /// against hidden liquidity.
+    PriceImprovement,
+    /// Worse than the best opposite price, after the event's earlier prints took every
+    /// better level.
+    Sweep,
+    /// Worse than the best opposite price while quantity displayed at a better price
+    /// was left untraded.
+    TradeThrough,
+    /// Nothing was displayed opposite the aggressor.
+    NoQuote,
+}
+
+impl TradeClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TradeClass::AtTouch => "at_touch",
+            TradeClass::PriceImprovement => "price_improvement",
+            TradeClass::Sweep => "sweep",
+            TradeClass::TradeThrough => "trade_through",
+            TradeClass::NoQuote => "no_quote",
+        }
+    }
+}
+
+/// A classified print.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TradeRecord {
+    pub event_time: OffsetDateTime,
+    pub sequence: u32,
+    /// Side of the aggressor, so `Side::Bid` for a buy.
+    pub aggressor: Side,
+    pub price: i64,
+    pub size: u64,
+    /// The quote before the event's first print.
+    pub bbo: Bbo,
+    /// Quantity displayed at `price` opposite the aggressor before the event, less
+    /// what the event's earlier prints traded there.
+    pub displayed_qty: u64,
+    pub class: TradeClass,
+}
+
+impl TradeRecord {
+    /// Whether the print traded more than was displayed at its price.
+    pub fn exceeds_displayed(&self) -> bool {
+        self.size > self.displayed_qty
+    }
+
+    /// Twice the distance of the price from the midpoint `horizon` after the print, in
+    /// the aggressor's direction: what the liquidity it took kept once the price moved.
+    /// The midpoint is that of `history` at the time, so `None` if either side is empty
+    /// then or `history` holds no quote that early.
+    pub fn realized_spread(&self, history: &BboHistory, horizon: Duration) -> Option<f64> {
+        let later = history.at(self.event_time + horizon)?;
+        let mid = mid(later.bid, later.ask)?;
+        Some(match self.aggressor {
+            Side::Bid => 2.0 * (self.price as f64 - mid),
+            Side::Ask => 2.0 * (mid - self.price as f64),
+        })
+    }
+}
+
+/// Number of prints of each class.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TradeQualityCounts {
+    pub trades: u64,
+    pub at_touch: u64,
+    pub price_improvement: u64,
+    pub sweep: u64,
+    pub trade_through: u64,
+    pub no_quote: u64,
+    /// Prints of any class that traded more than was displayed at their price.
+    pub exceeds_displayed: u64,
+}
+
+impl TradeQualityCounts {
+    fn record(&mut self, record: &TradeRecord) {
+        self.trades += 1;
+        *match record.class {
+            TradeClass::AtTouch => &mut self.at_touch,
+            TradeClass::PriceImprovement => &mut self.price_improvement,
+            TradeClass::Sweep => &mut self.sweep,
+            TradeClass::TradeThrough => &mut self.trade_through,
+            TradeClass::NoQuote => &mut self.no_quote,
+        } += 1;
+        if record.exceeds_displayed() {
+            self.exceeds_displayed += 1;
+        }
+    }
+}
+
+/// A price the current event printed at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct PrintedLevel {
+    side: Side,
+    price: i64,
+    /// Quantity at the level when the event first printed there.
+    displayed: u64,
+    /// Quantity the event has printed there since.
+    printed: u64,
+}
+
+impl PrintedLevel {
+    fn remaining(&self) -> u64 {
+        self.displayed.saturating_sub(self.printed)
+    }
+}
+
+/// The book as the current event's first print found it.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct EventState {
+    bbo: Bbo,
+    /// Whether the event printed a Trade, making its Fills the resting halves of
+    /// prints already classified.
+    traded: bool,
+    levels: Vec<PrintedLevel>,
+}
+
+impl EventState {
+    /// Index of the level at `price` on `side`, taken from `book` on first use.
+    fn level(&mut self, book: &impl BookHandler, side: Side, price: i64) -> usize {
+        if let Some(index) = self
+            .levels
+            .iter()
+            .position(|level| level.side == side && level.price == price)
+        {
+            return index;
+        }
+        self.levels.push(PrintedLevel {
+            side,
+            price,
+            displayed: book
+                .levels_in(side, (Bound::Included(price), Bound::Included(price)))
+                .first()
+                .map_or(0, |&(_, qty)| qty),
+            printed: 0,
+        });
+        self.levels.len() - 1
+    }
+
+    /// Quantity left on `side` at prices better than `price` for an aggressor trading
+    /// against it. Levels the event printed at count what its prints left of them,
+    /// whether or not their reductions have arrived; others are as `book` shows them.
+    fn remaining_better(&self, book: &impl BookHandler, side: Side, price: i64) -> u64 {
+        book.levels_in(side, reachable(side, price, Bound::Excluded))
+            .into_iter()
+            .map(|(level_price, qty)| {
+                match self
+                    .levels
+                    .iter()
+                    .find(|printed| printed.side == side && printed.price == level_price)
+                {
+                    Some(printed) => printed.remaining(),
+                    None => qty,
+                }
+            })
+            .sum()
+    }
+}
+
+/// The other side.
+fn opposite(side: Side) -> Side {
+    match side {
+        Side::Bid => Side::Ask,
+        Side::Ask => Side::Bid,
+    }
+}
+
+/// Prices on `side` that an aggressor limited to `limit` reaches, `limit` itself
+/// bounded by `bound`.
+fn reachable(side: Side, limit: i64, bound: fn(i64) -> Bound<i64>) -> (Bound<i64>, Bound<i64>) {
+    match side {
+        Side::Bid => (bound(limit), Bound::Unbounded),
+        Side::Ask => (Bound::Unbounded, bound(limit)),
+    }
+}
+
+/// Classifies each print by the quote before its event, enabled with
+/// `MboProcessor::with_trade_quality`.
+///
+/// A Trade's aggressor is its side; a Fill's is opposite the resting order it filled.
+/// A feed reporting both sends a Trade for the aggressor followed by a Fill per
+/// resting order, so once an event has printed a Trade its Fills are not counted
+/// again. Feeds that leave a Trade's side unknown (dbn side `N`) have it read as a
+/// buy.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TradeQuality {
+    records: Vec<TradeRecord>,
+    counts: TradeQualityCounts,
+    /// The book before the current event's first print, once it printed.
+    event: Option<EventState>,
+}
+
+impl TradeQuality {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every print classified, in order.
+    pub fn records(&self) -> &[TradeRecord] {
+        &self.records
+    }
+
+    pub fn counts(&self) -> TradeQualityCounts {
+        self.counts
+    }
+
+    /// Size-weighted mean `TradeRecord::realized_spread` at `horizon` of the prints it
+    /// is defined for, or `None` if it is defined for none.
+    pub fn mean_realized_spread(&self, history: &BboHistory, horizon: Duration) -> Option<f64> {
+        let (size, weighted) = self
+            .records
+            .iter()
+            .filter_map(|r| Some((r.size as f64, r.realized_spread(history, horizon)?)))
+            .fold((0.0, 0.0), |(size, weighted), (s, spread)| {
+                (size + s, weighted + s * spread)
+            });
+        (size > 0.0).then(|| weighted / size)
+    }
+
+    /// Classifies a Trade or Fill `message` against `book`, to which the event's
+    /// reductions so far have been applied. Prints without a price are ignored.
+    pub(crate) fn record_message(
+        &mut self,
+        message: &MarketByOrderMessage,
+        book: &impl BookHandler,
+    ) {
+        let Some(price) = message.defined_price() else {
+            return;
+        };
+        let aggressor = match message.action {
+            Action::Trade => message.side,
+            _ => opposite(
+                book.get_order(message.order_id)
+                    .map_or(message.side, |order| order.side),
+            ),
+        };
+        let event = self.event.get_or_insert_with(|| EventState {
+            bbo: book.bbo(),
+            ..EventState::default()
+        });
+        match message.action {
+            Action::Trade => event.traded = true,
+            _ if event.traded => return,
+            _ => {}
+        }
+        self.record(
+            book,
+            aggressor,
+            price,
+            message.size.into(),
+            message.event_time,
+            message.sequence,
+        );
+    }
+
+    /// Takes note of the levels an order from `aggressor` limited to `limit` reaches in
+    /// `book`, before the processor matches it and they change.
+    pub(crate) fn before_match(&mut self, book: &impl BookHandler, aggressor: Side, limit: i64) {
+        let side = opposite(aggressor);
+        let reached: Vec<i64> = book
+            .levels_in(side, reachable(side, limit, Bound::Included))
+            .into_iter()
+            .map(|(price, _)| price)
+            .collect();
+        if reached.is_empty() {
+            return;
+        }
+        let event = self.event.get_or_insert_with(|| EventState {
+            bbo: book.bbo(),
+            ..EventState::default()
+        });
+        event.traded = true;
+        for price in reached {
+            event.level(book, side, price);
+        }
+    }
+
+    /// Classifies a print of `size` at `price` by `aggressor`.
+    pub(crate) fn record(
+        &mut self,
+        book: &impl BookHandler,
+        aggressor: Side,
+        price: i64,
+        size: u64,
+        event_time: OffsetDateTime,
+        sequence: u32,
+    ) {
+        let resting = opposite(aggressor);
+        let event = self.event.get_or_insert_with(|| EventState {
+            bbo: book.bbo(),
+            ..EventState::default()
+        });
+        let index = event.level(book, resting, price);
+        let displayed_qty = event.levels[index].remaining();
+        let (touch, improves) = match aggressor {
+            Side::Bid => (
+                event.bbo.ask,
+                price < event.bbo.ask.map_or(i64::MIN, |(p, _)| p),
+            ),
+            Side::Ask => (
+                event.bbo.bid,
+                price > event.bbo.bid.map_or(i64::MAX, |(p, _)| p),
+            ),
+        };
+        let class = match touch {
+            None => TradeClass::NoQuote,
+            Some((touch, _)) if touch == price => TradeClass::AtTouch,
+            Some(_) if improves => TradeClass::PriceImprovement,
+            Some(_) if event.remaining_better(book, resting, price) > 0 => TradeClass::TradeThrough,
+            Some(_) => TradeClass::Sweep,
+        };
+        event.levels[index].printed += size;
+
+        let record = TradeRecord {
+            event_time,
+            sequence,
+            aggressor,
+            price,
+            size,
+            bbo: event.bbo,
+            displayed_qty,
+            class,
+        };
+        self.counts.record(&record);
+        self.records.push(record);
+    }
+
+    /// Ends the current event, so the next print sees the book afresh.
+    pub(crate) fn end_event(&mut self) {
+        self.event = None;
+    }
+
+    /// The records as a DataFrame: `ts_event` (nanoseconds), `sequence`, `aggressor`
+    /// (`"B"` or `"A"`), `price`, `size`, the pre-trade `bid_px`, `bid_sz`, `ask_px`
+    /// and `ask_sz` (null for an empty side), `displayed_qty`, `class` (as
+    /// `TradeClass::as_str`) and `exceeds_displayed`.
+    #[cfg(feature = "polars")]
+    pub fn to_dataframe(&self) -> PolarsResult<DataFrame> {
+        let records = &self.records;
+        df!(
+            "ts_event" => records.iter().map(|r| r.event_time.unix_timestamp_nanos() as i64).collect::<Vec<_>>(),
+            "sequence" => records.iter().map(|r| r.sequence).collect::<Vec<_>>(),
+            "aggressor" => records.iter().map(|r| match r.aggressor {
+                Side::Bid => "B",
+                Side::Ask => "A",
+            }).collect::<Vec<_>>(),
+            "price" => records.iter().map(|r| r.price).collect::<Vec<_>>(),
+            "size" => records.iter().map(|r| r.size).collect::<Vec<_>>(),
+            "bid_px" => records.iter().map(|r| r.bbo.bid.map(|(p, _)| p)).collect::<Vec<_>>(),
+            "bid_sz" => records.iter().map(|r| r.bbo.bid.map(|(_, q)| q)).collect::<Vec<_>>(),
+            "ask_px" => records.iter().map(|r| r.bbo.ask.map(|(p, _)| p)).collect::<Vec<_>>(),
+            "ask_sz" => records.iter().map(|r| r.bbo.ask.map(|(_, q)| q)).collect::<Vec<_>>(),
+            "displayed_qty" => records.iter().map(|r| r.displayed_qty).collect::<Vec<_>>(),
+            "class" => records.iter().map(|r| r.class.as_str()).collect::<Vec<_>>(),
+            "exceeds_displayed" => records.iter().map(TradeRecord::exceeds_displayed).collect::<Vec<_>>(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::orderbook::{CrossingPolicy, MboProcessor};
+
+    /// A message `seconds` into the session, ending its event if `is_last`.
+    fn msg(
+        seconds: i64,
+        action: Action,
+        order_id: u64,
+        side: Side,
+        price: i64,
+        size: u32,
+        is_last: bool,
+    ) -> MarketByOrderMessage {
+        MarketByOrderMessage {
+            sequence: seconds as u32,
+            ..MarketByOrderMessage::test(action, side, price, order_id, size)
+                .at(OffsetDateTime::UNIX_EPOCH + Duration::seconds(seconds))
+                .with_last(is_last)
+        }
+    }
+
+    /// Asks of 10 at 101 (order 1) and 10 at 102 (order 2), and a bid of 10 at 99
+    /// (order 3).
+    fn quoted() -> MboProcessor {
+        let mut processor = MboProcessor::new().with_trade_quality(true);
+        processor.process_messages([
+            msg(1, Action::Add, 1, Side::Ask, 101, 10, true),
+            msg(2, Action::Add, 2, Side::Ask, 102, 10, true),
+            msg(3, Action::Add, 3, Side::Bid, 99, 10, true),
+        ]);
+        processor
+    }
+
+    fn classes(processor: &MboProcessor) -> Vec<(i64, TradeClass, u64, bool)> {
+        processor
+            .trade_quality(1)
+            .unwrap()
+            .records()
+            .iter()
+            .map(|r| (r.price, r.class, r.displayed_qty, r.exceeds_displayed()))
+            .collect()
+    }
+
+    #[test]
+    fn test_sweep_judged_by_book_before_event() {
+        // Prints interleaved with their reductions
+        let mut processor = quoted();
+        processor.process_messages([
+            msg(4, Action::Trade, 0, Side::Bid, 101, 10, false),
+            msg(4, Action::Fill, 1, Side::Ask, 101, 10, false),
+            msg(4, Action::Cancel, 1, Side::Ask, 101, 10, false),
+            msg(4, Action::Trade, 0, Side::Bid, 102, 4, false),
+            msg(4, Action::Fill, 2, Side::Ask, 102, 4, false),
+            msg(4, Action::Modify, 2, Side::Ask, 102, 6, true),
+        ]);
+        let expected = vec![
+            (101, TradeClass::AtTouch, 10, false),
+            (102, TradeClass::Sweep, 10, false),
+        ];
+        assert_eq!(classes(&processor), expected);
+        let quality = processor.trade_quality(1).unwrap();
+        // Both against the quote before the event; Fills are not counted again
+        let bbo = Bbo {
+            bid: Some((99, 10)),
+            ask: Some((101, 10)),
+        };
+        assert!(quality.records().iter().all(|r| r.bbo == bbo));
+        assert!(quality.records().iter().all(|r| r.aggressor == Side::Bid));
+
+        // All prints before their reductions
+        let mut processor = quoted();
+        processor.process_messages([
+            msg(4, Action::Trade, 0, Side::Bid, 101, 10, false),
+            msg(4, Action::Trade, 0, Side::Bid, 102, 4, false),
+            msg(4, Action::Fill, 1, Side::Ask, 101, 10, false),
+            msg(4, Action::Fill, 2, Side::Ask, 102, 4, false),
+            msg(4, Action::Cancel, 1, Side::Ask, 101, 10, false),
+            msg(4, Action::Modify, 2, Side::Ask, 102, 6, true),
+        ]);
+        assert_eq!(classes(&processor), expected);
+    }
+
+    #[test]
+    fn test_trade_through() {
+        let mut processor = quoted();
+        // 102 trades with 101 still displayed, and partly traded 101 left behind
+        processor.process_messages([
+            msg(4, Action::Trade, 0, Side::Bid, 102, 4, false),
+            msg(4, Action::Fill, 2, Side::Ask, 102, 4, false),
+            msg(4, Action::Modify, 2, Side::Ask, 102, 6, true),
+            msg(5, Action::Trade, 0, Side::Bid, 101, 5, false),
+            msg(5, Action::Trade, 0, Side::Bid, 102, 1, false),
+            msg(5, Action::Modify, 1, Side::Ask, 101, 5, false),
+            msg(5, Action::Modify, 2, Side::Ask, 102, 5, true),
+        ]);
+        assert_eq!(
+            classes(&processor),
+            vec![
+                (102, TradeClass::TradeThrough, 10, false),
+                (101, TradeClass::AtTouch, 10, false),
+                (102, TradeClass::TradeThrough, 6, false),
+            ]
+        );
+        let counts = processor.trade_quality(1).unwrap().counts();
+        assert_eq!((counts.trades, counts.trade_through), (3, 2));
+    }
+
+    #[test]
+    fn test_price_improvement_and_hidden_size() {
+        let mut processor = quoted();
+        processor.process_messages([
+            // Inside the spread, against nothing displayed
+            msg(4, Action::Trade, 0, Side::Bid, 100, 3, true),
+            // More than displayed at the touch
+            msg(5, Action::Trade, 0, Side::Ask, 99, 15, false),
+            msg(5, Action::Cancel, 3, Side::Bid, 99, 10, true),
+            // Nothing left to sell into
+            msg(6, Action::Trade, 0, Side::Ask, 98, 1, true),
+        ]);
+        assert_eq!(
+            classes(&processor),
+            vec![
+                (100, TradeClass::PriceImprovement, 0, true),
+                (99, TradeClass::AtTouch, 10, true),
+                (98, TradeClass::NoQuote, 0, true),
+            ]
+        );
+        let counts = processor.trade_quality(1).unwrap().counts();
+        assert_eq!(
+            counts,
+            TradeQualityCounts {
+                trades: 3,
+                at_touch: 1,
+                price_improvement: 1,
+                no_quote: 1,
+                exceeds_displayed: 3,
+                ..TradeQualityCounts::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_fills_without_trades_and_matched_adds() {
+        // A feed sending only the resting side's Fills: the aggressor sold
+        let mut processor = quoted();
+        processor.process_messages([
+            msg(4, Action::Fill, 3, Side::Bid, 99, 4, false),
+            msg(4, Action::Modify, 3, Side::Bid, 99, 6, true),
+        ]);
+        let record = processor.trade_quality(1).unwrap().records()[0];
+        assert_eq!(
+            (record.aggressor, record.class, record.size),
+            (Side::Ask, TradeClass::AtTouch, 4)
+        );
+
+        // Executions of a crossing Add, judged by the book before matching
+        let mut processor = quoted().with_crossing_policy(CrossingPolicy::Match);
+        processor
+            .process_message(&msg(4, Action::Add, 4, Side::Bid, 102, 15, true))
+            .unwrap();
+        assert_eq!(
+            classes(&processor),
+            vec![
+                (101, TradeClass::AtTouch, 10, false),
+                (102, TradeClass::Sweep, 10, false),
+            ]
+        );
+
+        assert!(MboProcessor::new().trade_quality(1).is_none());
+    }
+
+    #[test]
+    fn test_realized_spread_at_horizon() {
+        let mut processor = quoted().with_bbo_history(BboHistory::new());
+        processor.process_messages([
+            // A buy of 10 at 101 leaves 99 / 102, mid 100.5
+            msg(4, Action::Trade, 0, Side::Bid, 101, 10, false),
+            msg(4, Action::Fill, 1, Side::Ask, 101, 10, false),
+            msg(4, Action::Cancel, 1, Side::Ask, 101, 10, true),
+            // A bid at 101 moves the mid to 101.5
+            msg(6, Action::Add, 4, Side::Bid, 101, 10, true),
+            // A sell of 5 at 101 leaves it there
+            msg(8, Action::Trade, 0, Side::Ask, 101, 5, false),
+            msg(8, Action::Modify, 4, Side::Bid, 101, 5, true),
+        ]);
+        let history = processor.bbo_history(1).unwrap();
+        let records = processor.trade_quality(1).unwrap().records();
+        let spreads = |horizon| {
+            records
+                .iter()
+                .map(|r| r.realized_spread(history, horizon))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(spreads(Duration::seconds(1)), vec![Some(1.0), Some(1.0)]);
+        // A quote taking effect exactly at the horizon counts
+        assert_eq!(spreads(Duration::seconds(2)), vec![Some(-1.0), Some(1.0)]);
+        assert_eq!(
+            processor.realized_spread(1, Duration::seconds(3)),
+            Some((-10.0 + 5.0) / 15.0)
+        );
+
+        // Before the first quote, or without a two-sided one, it is undefined
+        assert_eq!(
+            records[0].realized_spread(history, Duration::seconds(-4)),
+            None
+        );
+        let mut one_sided = BboHistory::new();
+        one_sided.record(
+            records[0].event_time,
+            Bbo {
+                bid: Some((99, 10)),
+                ask: None,
+            },
+        );
+        assert_eq!(records[0].realized_spread(&one_sided, Duration::ZERO), None);
+        assert_eq!(quoted().realized_spread(1, Duration::seconds(1)), None);
+    }
+
+    #[cfg(feature = "polars")]
+    #[test]
+    fn test_to_dataframe() {
+        let mut processor = quoted();
+        processor.process_messages([
+            msg(4, Action::Trade, 0, Side::Bid, 100, 3, true),
+            msg(5, Action::Trade, 0, Side::Ask, 99, 5, true),
+        ]);
+        let df = processor.trade_quality(1).unwrap().to_dataframe().unwrap();
+        assert_eq!(df.shape(), (2, 12));
+        let class = df.column("class").unwrap().str().unwrap();
+        assert_eq!(class.get(0), Some("price_improvement"));
+        assert_eq!(class.get(1), Some("at_touch"));
+        let aggressor = df.column("aggressor").unwrap().str().unwrap();
+        assert_eq!(aggressor.get(1), Some("A"));
+        let ask_px = df.column("ask_px").unwrap().i64().unwrap();
+        assert_eq!(ask_px.get(0), Some(101));
+    }
+}
@@ -1,5 +1,14 @@
+#[cfg(feature = "polars")]
+use std::path::Path;
+
+#[cfg(feature = "polars")]
+use polars::prelude::{DataFrame, IpcWriter, ParquetWriter, PolarsResult, SerWriter, df};
+use serde::{Deserialize, Serialize};
+
 use crate::orderbook::events::TradeEvent;
 use crate::orderbook::mbo::MboObserver;
+#[cfg(feature = "polars")]
+use crate::orderbook::{Side, create_output};
 
 /// Observer that collects trades from Trade and Fill actions.
 ///
@@ -10,7 +19,7 @@ use crate::orderbook::mbo::MboObserver;
 /// Use with `MboProcessor::with_observer(TradeCollector::new())`, then
 /// retrieve results via `processor.observer().trades()` or
 /// `processor.into_observer().into_trades()`.
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct TradeCollector {
     trades: Vec<TradeEvent>,
 }
@@ -27,6 +36,39 @@ impl TradeCollector {
     pub fn into_trades(self) -> Vec<TradeEvent> {
         self.trades
     }
+
+    /// Converts the collected trades into a DataFrame with one row per trade.
+    ///
+    /// `ts_event` is nanoseconds since the UNIX epoch, `side` is `"B"` or `"A"` as in
+    /// Databento's side codes, and `kind` is `"trade"` for aggressor prints and `"fill"` for
+    /// passive executions.
+    #[cfg(feature = "polars")]
+    pub fn to_dataframe(&self) -> PolarsResult<DataFrame> {
+        df!(
+            "ts_event" => self.trades.iter().map(|t| t.event_time.unix_timestamp_nanos() as i64).collect::<Vec<_>>(),
+            "price" => self.trades.iter().map(|t| t.price).collect::<Vec<_>>(),
+            "size" => self.trades.iter().map(|t| t.size).collect::<Vec<_>>(),
+            "side" => self.trades.iter().map(|t| match t.side { Side::Bid => "B", Side::Ask => "A" }).collect::<Vec<_>>(),
+            "order_id" => self.trades.iter().map(|t| t.order_id).collect::<Vec<_>>(),
+            "kind" => self.trades.iter().map(|t| if t.aggressor { "trade" } else { "fill" }).collect::<Vec<_>>(),
+        )
+    }
+
+    /// Writes the DataFrame from `to_dataframe` to a parquet file.
+    #[cfg(feature = "polars")]
+    pub fn write_parquet(&self, path: impl AsRef<Path>) -> PolarsResult<()> {
+        let mut df = self.to_dataframe()?;
+        ParquetWriter::new(create_output(path, true)?).finish(&mut df)?;
+        Ok(())
+    }
+
+    /// Writes the same DataFrame to an Arrow IPC (Feather v2) file.
+    #[cfg(feature = "polars")]
+    pub fn write_ipc(&self, path: impl AsRef<Path>) -> PolarsResult<()> {
+        let mut df = self.to_dataframe()?;
+        IpcWriter::new(create_output(path, true)?).finish(&mut df)?;
+        Ok(())
+    }
 }
 
 impl MboObserver for TradeCollector {
@@ -34,3 +76,84 @@ impl MboObserver for TradeCollector {
         self.trades.push(*event);
     }
 }
+
+#[cfg(all(test, feature = "polars"))]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::{env, fs, process};
+
+    use polars::prelude::{IpcReader, SerReader};
+    use time::{Duration, OffsetDateTime};
+
+    use crate::orderbook::{Action, MarketByOrderMessage, MboProcessor};
+
+    fn msg(
+        seconds: i64,
+        action: Action,
+        order_id: u64,
+        side: Side,
+        price: i64,
+        size: u32,
+    ) -> MarketByOrderMessage {
+        MarketByOrderMessage {
+            sequence: seconds as u32,
+            ..MarketByOrderMessage::test(action, side, price, order_id, size)
+                .at(OffsetDateTime::UNIX_EPOCH + Duration::seconds(seconds))
+        }
+    }
+
+    #[test]
+    fn test_trades_to_dataframe_rows() {
+        let mut proc = MboProcessor::with_observer(TradeCollector::new());
+        let summary = proc.process_messages([
+            msg(1, Action::Add, 7, Side::Ask, 105, 10),
+            msg(2, Action::Trade, 0, Side::Bid, 105, 4),
+            msg(3, Action::Fill, 7, Side::Ask, 105, 4),
+            msg(4, Action::Trade, 0, Side::Ask, 104, 2),
+        ]);
+        assert!(summary.is_success());
+
+        let df = proc.observer().to_dataframe().unwrap();
+        assert_eq!(df.shape(), (3, 6));
+
+        let ts_event = df.column("ts_event").unwrap().i64().unwrap();
+        let price = df.column("price").unwrap().i64().unwrap();
+        let size = df.column("size").unwrap().u32().unwrap();
+        let side = df.column("side").unwrap().str().unwrap();
+        let order_id = df.column("order_id").unwrap().u64().unwrap();
+        let kind = df.column("kind").unwrap().str().unwrap();
+        let rows: Vec<_> = (0..df.height())
+            .map(|i| {
+                (
+                    ts_event.get(i).unwrap() / 1_000_000_000,
+                    price.get(i).unwrap(),
+                    size.get(i).unwrap(),
+                    side.get(i).unwrap(),
+                    order_id.get(i).unwrap(),
+                    kind.get(i).unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (2, 105, 4, "B", 0, "trade"),
+                (3, 105, 4, "A", 7, "fill"),
+                (4, 104, 2, "A", 0, "trade"),
+            ]
+        );
+
+        let path = env::temp_dir().join(format!("rainybook-{}-trades.parquet", process::id()));
+        proc.observer().write_parquet(&path).unwrap();
+        assert!(fs::metadata(&path).unwrap().len() > 0);
+        fs::remove_file(&path).unwrap();
+
+        // The IPC file reads back as the same frame
+        let path = path.with_extension("arrow");
+        proc.observer().write_ipc(&path).unwrap();
+        let ipc = IpcReader::new(File::open(&path).unwrap()).finish().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(ipc.equals_missing(&df));
+    }
+}
@@ -0,0 +1,189 @@
+//! Sanity checks applied to messages before they reach the book.
+//!
+//! Exports and feeds occasionally contain garbage rows: zero-size orders, undefined or
+//! zero prices. `validate` flags those; `MboProcessor` decides what to do with them
+//! according to its `ValidationPolicy`.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::orderbook::{Action, MarketByOrderMessage};
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    #[error("{action} for order {order_id} has invalid size {size}")]
+    InvalidSize {
+        action: Action,
+        order_id: u64,
+        size: u32,
+    },
+
+    #[error("{action} for order {order_id} has invalid price {price}")]
+    InvalidPrice {
+        action: Action,
+        order_id: u64,
+        price: i64,
+    },
+}
+
+/// How `MboProcessor::process_message` treats a message that fails `validate`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ValidationPolicy {
+    /// Fail with `MboProcessError::ValidationFailed`, leaving the book untouched.
+    #[default]
+    Reject,
+    /// Ignore the message, leaving the book untouched, and count it in
+    /// `MboStats::skipped_messages`.
+    Skip,
+    /// Apply the message without validating it.
+    PassThrough,
+}
+
+/// Checks a message for values that cannot describe a real book change.
+///
+/// - Add, Modify and Fill must have a non-zero size.
+/// - No action other than Clear may carry the undefined size (`u32::MAX`).
+/// - Add and Modify must not carry an undefined (`dbn::UNDEF_PRICE`) price.
+/// - Add, Modify, Fill and Trade must not carry a zero price. Fills and Trades may
+///   carry `UNDEF_PRICE`; they never reach the book and are not reported to observers.
+///   Cancels are matched by order id alone, so their price is not checked.
+///
+/// Clear records are exempt since they carry no price or size.
+pub fn validate(message: &MarketByOrderMessage) -> Result<(), ValidationError> {
+    let MarketByOrderMessage {
+        action,
+        order_id,
+        price,
+        size,
+        ..
+    } = *message;
+    if action == Action::Clear {
+        return Ok(());
+    }
+
+    let size_required = matches!(action, Action::Add | Action::Modify | Action::Fill);
+    if (size_required && size == 0) || size == u32::MAX {
+        return Err(ValidationError::InvalidSize {
+            action,
+            order_id,
+            size,
+        });
+    }
+    let price_required = matches!(action, Action::Add | Action::Modify);
+    if (action != Action::Cancel && price == 0) || (price_required && price == dbn::UNDEF_PRICE) {
+        return Err(ValidationError::InvalidPrice {
+            action,
+            order_id,
+            price,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::orderbook::{MboProcessError, MboProcessor, Side};
+
+    fn msg(action: Action, price: i64, size: u32) -> MarketByOrderMessage {
+        MarketByOrderMessage {
+            sequence: 1,
+            ..MarketByOrderMessage::test(action, Side::Bid, price, 7, size)
+        }
+    }
+
+    #[test]
+    fn test_zero_size_rule() {
+        [Action::Add, Action::Modify, Action::Fill]
+            .iter()
+            .for_each(|&action| {
+                assert_eq!(
+                    validate(&msg(action, 100, 0)),
+                    Err(ValidationError::InvalidSize {
+                        action,
+                        order_id: 7,
+                        size: 0
+                    })
+                );
+            });
+        // Cancels and Trades may legitimately report no size
+        assert!(validate(&msg(Action::Cancel, 100, 0)).is_ok());
+        assert!(validate(&msg(Action::Trade, 100, 0)).is_ok());
+    }
+
+    #[test]
+    fn test_undefined_size_rule() {
+        assert!(matches!(
+            validate(&msg(Action::Trade, 100, u32::MAX)),
+            Err(ValidationError::InvalidSize { .. })
+        ));
+    }
+
+    #[test]
+    fn test_price_rule() {
+        [0, dbn::UNDEF_PRICE].iter().for_each(|&price| {
+            assert!(matches!(
+                validate(&msg(Action::Add, price, 10)),
+                Err(ValidationError::InvalidPrice { .. })
+            ));
+            assert!(validate(&msg(Action::Cancel, price, 0)).is_ok());
+        });
+        assert!(matches!(
+            validate(&msg(Action::Trade, 0, 10)),
+            Err(ValidationError::InvalidPrice { .. })
+        ));
+        assert!(validate(&msg(Action::Trade, dbn::UNDEF_PRICE, 10)).is_ok());
+        // Negative prices occur for spreads
+        assert!(validate(&msg(Action::Add, -25, 10)).is_ok());
+    }
+
+    #[test]
+    fn test_clear_exempt() {
+        assert!(validate(&msg(Action::Clear, dbn::UNDEF_PRICE, 0)).is_ok());
+    }
+
+    fn run(policy: ValidationPolicy) -> (MboProcessor, Vec<Result<(), MboProcessError>>) {
+        let mut proc = MboProcessor::new().with_validation_policy(policy);
+        let results = [msg(Action::Add, 100, 10), msg(Action::Modify, 100, 0)]
+            .iter()
+            .map(|m| proc.process_message(m))
+            .collect();
+        (proc, results)
+    }
+
+    #[test]
+    fn test_reject_policy() {
+        let (proc, results) = run(ValidationPolicy::Reject);
+        assert!(matches!(
+            results[1],
+            Err(MboProcessError::ValidationFailed(
+                ValidationError::InvalidSize { .. }
+            ))
+        ));
+        assert_eq!(proc.best_bid(), Some((100, 10)));
+        assert_eq!(proc.stats().rejected_messages, 1);
+        assert_eq!(proc.stats().skipped_messages, 0);
+        assert_eq!(proc.stats().messages, 2);
+    }
+
+    #[test]
+    fn test_skip_policy() {
+        let (proc, results) = run(ValidationPolicy::Skip);
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(proc.best_bid(), Some((100, 10)));
+        assert_eq!(proc.stats().skipped_messages, 1);
+        assert_eq!(proc.stats().rejected_messages, 0);
+    }
+
+    #[test]
+    fn test_pass_through_policy() {
+        let (proc, results) = run(ValidationPolicy::PassThrough);
+        assert!(results.iter().all(Result::is_ok));
+        // The zero-size Modify is applied, leaving nothing of the order to rest
+        assert_eq!(proc.best_bid(), None);
+        assert_eq!(proc.order_book().level_count(Side::Bid), 0);
+        assert_eq!(proc.stats().skipped_messages, 0);
+        assert_eq!(proc.stats().rejected_messages, 0);
+    }
+}
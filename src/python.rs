@@ -0,0 +1,471 @@
+//! Python bindings, with the `python` feature.
+//!
+//! `OrderBook` and `MboProcessor` are exposed as Python classes, and `process_file`
+//! replays a whole input file. Batches of messages come in as columns, numpy arrays or
+//! the columns of a pyarrow Table, and snapshots go out as pyarrow Tables built from
+//! numpy arrays, so no Python object is made per message. Processing releases the GIL.
+//! The crate in `python/` builds the extension module with maturin.
+
+use std::fmt::Display;
+use std::path::PathBuf;
+
+use numpy::ndarray::ArrayView1;
+use numpy::{Element, PyArray1, PyFixedUnicode, PyReadonlyArray1, PyUntypedArrayMethods};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+
+use crate::orderbook::conversion::MboRow;
+use crate::orderbook::{
+    ConversionError, ConversionReport, ErrorPolicy, InputSource, MarketByPrice, MboProcessor,
+    Order, OrderBook, ProcessSummary, Side, open_input, process_reader,
+};
+
+fn value_error(e: impl Display) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
+
+/// A `(price, quantity)` level as Python prints the tuple, or `None`.
+fn level_repr(level: Option<(i64, u64)>) -> String {
+    match level {
+        Some((price, qty)) => format!("({price}, {qty})"),
+        None => "None".to_string(),
+    }
+}
+
+/// Parses a side given as `"B"`/`"bid"` or `"A"`/`"ask"`, in any case.
+fn parse_side(side: &str) -> PyResult<Side> {
+    match side.to_ascii_lowercase().as_str() {
+        "b" | "bid" => Ok(Side::Bid),
+        "a" | "ask" => Ok(Side::Ask),
+        _ => Err(value_error(format!(
+            "Invalid side '{side}', expected 'bid' or 'ask'"
+        ))),
+    }
+}
+
+/// An L3 order book: orders rest by price level in time priority.
+#[pyclass(name = "OrderBook", module = "rainybook")]
+#[derive(Default)]
+pub struct PyOrderBook {
+    book: OrderBook,
+}
+
+#[pymethods]
+impl PyOrderBook {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a resting order. Raises ValueError if the book cannot take it.
+    #[pyo3(signature = (order_id, side, price, size, sequence = 0))]
+    fn add(
+        &mut self,
+        order_id: u64,
+        side: &str,
+        price: i64,
+        size: u64,
+        sequence: u32,
+    ) -> PyResult<()> {
+        let order = Order {
+            order_id,
+            side: parse_side(side)?,
+            price,
+            size,
+            sequence,
+            owner: None,
+            expires_at: None,
+        };
+        self.book.try_add_order(order).map_err(value_error)?;
+        Ok(())
+    }
+
+    /// Removes an order and returns its size, or None if the book does not hold it.
+    fn cancel(&mut self, order_id: u64) -> Option<u64> {
+        self.book.remove_order(order_id).map(|info| info.order.size)
+    }
+
+    /// Moves an order to `price` with `size`, keeping its side. Returns False if the
+    /// book does not hold it.
+    #[pyo3(signature = (order_id, price, size, sequence = None))]
+    fn modify(&mut self, order_id: u64, price: i64, size: u64, sequence: Option<u32>) -> bool {
+        let Some(&old) = self.book.get_order(order_id) else {
+            return false;
+        };
+        let order = Order {
+            price,
+            size,
+            sequence: sequence.unwrap_or(old.sequence),
+            ..old
+        };
+        self.book.modify_order(order).is_some()
+    }
+
+    /// Executes `size` of a resting order and returns what is left of it, removing it
+    /// when nothing is. Raises ValueError for an unknown order or a fill above its size.
+    fn fill(&mut self, order_id: u64, size: u64) -> PyResult<u64> {
+        let delta = i64::try_from(size).map_err(value_error)?;
+        self.book
+            .adjust_order_size(order_id, -delta)
+            .map_err(value_error)
+    }
+
+    /// Matches an incoming order of `side` against the other side up to `price` and
+    /// returns the executions as `(order_id, price, size)`. The residual never rests.
+    fn sweep(&mut self, side: &str, price: i64, size: u64) -> PyResult<Vec<(u64, i64, u64)>> {
+        let order = Order {
+            order_id: 0,
+            side: parse_side(side)?,
+            price,
+            size,
+            sequence: 0,
+            owner: None,
+            expires_at: None,
+        };
+        Ok(self
+            .book
+            .match_order(&order)
+            .iter()
+            .map(|execution| {
+                (
+                    execution.resting.order_id,
+                    execution.resting.price,
+                    execution.size,
+                )
+            })
+            .collect())
+    }
+
+    /// `(price, quantity)` of the best bid level, or None.
+    fn best_bid(&self) -> Option<(i64, u64)> {
+        self.book.best_bid()
+    }
+
+    /// `(price, quantity)` of the best ask level, or None.
+    fn best_ask(&self) -> Option<(i64, u64)> {
+        self.book.best_ask()
+    }
+
+    /// `(price, quantity)` of the best `n` levels of `side`, best first.
+    fn top_n(&self, side: &str, n: usize) -> PyResult<Vec<(i64, u64)>> {
+        Ok(match parse_side(side)? {
+            Side::Bid => self.book.top_n_bids(n),
+            Side::Ask => self.book.top_n_asks(n),
+        })
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "OrderBook(best_bid={}, best_ask={})",
+            level_repr(self.book.best_bid()),
+            level_repr(self.book.best_ask())
+        )
+    }
+}
+
+/// Applies market-by-order messages to one book per instrument.
+#[pyclass(name = "MboProcessor", module = "rainybook")]
+pub struct PyMboProcessor {
+    processor: MboProcessor,
+}
+
+#[pymethods]
+impl PyMboProcessor {
+    /// With `fail_fast`, processing stops at the first message that cannot be applied;
+    /// otherwise such messages are reported and skipped.
+    #[new]
+    #[pyo3(signature = (fail_fast = false))]
+    fn new(fail_fast: bool) -> Self {
+        let policy = match fail_fast {
+            true => ErrorPolicy::FailFast,
+            false => ErrorPolicy::Collect,
+        };
+        Self {
+            processor: MboProcessor::new().with_error_policy(policy),
+        }
+    }
+
+    /// Applies a batch of messages given as columns: a dict of numpy arrays or a
+    /// pyarrow Table. `action`, `side`, `price`, `order_id` and `size` are required,
+    /// `instrument_id`, `ts_event`, `ts_recv`, `ts_in_delta`, `sequence` and `flags`
+    /// optional, with the defaults of CSV input. `action` and `side` hold dbn
+    /// characters, as strings or as their codes, e.g. 65 for 'A'.
+    ///
+    /// Returns a dict of the count processed, the `(index, reason)` of every failed
+    /// message, counted over the converted rows, and the `(row, reason)` of every row
+    /// that could not be converted.
+    fn process<'py>(
+        &mut self,
+        py: Python<'py>,
+        batch: &Bound<'py, PyAny>,
+    ) -> PyResult<Bound<'py, PyDict>> {
+        let report = convert_batch(batch)?;
+        let processor = &mut self.processor;
+        let messages = report.messages;
+        let summary = py.detach(move || processor.process_messages(&messages));
+        summary_dict(py, &summary, &report.rejected)
+    }
+
+    /// Instrument ids with a book, ascending.
+    fn instruments(&self) -> Vec<u32> {
+        self.processor.instruments()
+    }
+
+    /// `(price, quantity)` of the best bid of an instrument, or None.
+    fn best_bid(&self, instrument_id: u32) -> Option<(i64, u64)> {
+        self.processor.book(instrument_id)?.best_bid()
+    }
+
+    /// `(price, quantity)` of the best ask of an instrument, or None.
+    fn best_ask(&self, instrument_id: u32) -> Option<(i64, u64)> {
+        self.processor.book(instrument_id)?.best_ask()
+    }
+
+    /// Market-by-price snapshot of the best `depth` levels per side of every book, or
+    /// of one instrument's, as a pyarrow Table with a row per level: `instrument_id`,
+    /// `side` ('B' or 'A'), `level` (0 at the best price), `price`, `size` and
+    /// `count`. `.to_pandas()` turns it into a DataFrame.
+    #[pyo3(signature = (depth = 10, instrument_id = None))]
+    fn snapshot<'py>(
+        &self,
+        py: Python<'py>,
+        depth: usize,
+        instrument_id: Option<u32>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let instruments = match instrument_id {
+            Some(instrument_id) => vec![instrument_id],
+            None => self.processor.instruments(),
+        };
+        let mut columns = SnapshotColumns::default();
+        for instrument_id in instruments {
+            if let Some(book) = self.processor.book(instrument_id) {
+                columns.push(instrument_id, &MarketByPrice::from_top_n(book, depth));
+            }
+        }
+        columns.into_table(py)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "MboProcessor(instruments={:?}, messages={})",
+            self.processor.instruments(),
+            self.processor.stats().messages
+        )
+    }
+}
+
+/// Replays the DBN, CSV or NDJSON file at `path`, optionally compressed, into a new
+/// `MboProcessor`, with the GIL released. Returns the processor and the summary dict
+/// of `MboProcessor.process`. Raises ValueError if the file cannot be read.
+#[pyfunction]
+#[pyo3(signature = (path, fail_fast = false))]
+fn process_file(
+    py: Python<'_>,
+    path: PathBuf,
+    fail_fast: bool,
+) -> PyResult<(PyMboProcessor, Bound<'_, PyDict>)> {
+    let mut processor = PyMboProcessor::new(fail_fast);
+    let inner = &mut processor.processor;
+    let result = py
+        .detach(|| {
+            let (format, reader) = open_input(InputSource::File(&path), None)?;
+            process_reader(reader, format, inner)
+        })
+        .map_err(|e| value_error(format!("{}: {e}", path.display())))?;
+    let summary = summary_dict(py, &result.summary, &result.rejected)?;
+    Ok((processor, summary))
+}
+
+/// Adds the classes and functions to the `rainybook` Python module.
+pub fn register(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyOrderBook>()?;
+    module.add_class::<PyMboProcessor>()?;
+    module.add_function(wrap_pyfunction!(process_file, module)?)?;
+    Ok(())
+}
+
+fn summary_dict<'py>(
+    py: Python<'py>,
+    summary: &ProcessSummary,
+    rejected: &[(usize, ConversionError)],
+) -> PyResult<Bound<'py, PyDict>> {
+    let failed = summary
+        .failures
+        .iter()
+        .map(|failure| (failure.index, failure.error.to_string()));
+    let rejected = rejected
+        .iter()
+        .map(|(row, error)| (*row, error.to_string()));
+    let dict = PyDict::new(py);
+    dict.set_item("processed", summary.processed)?;
+    dict.set_item("failed", PyList::new(py, failed)?)?;
+    dict.set_item("rejected", PyList::new(py, rejected)?)?;
+    Ok(dict)
+}
+
+fn has_column(batch: &Bound<'_, PyAny>, name: &str) -> PyResult<bool> {
+    match batch.hasattr("column_names")? {
+        true => batch.getattr("column_names")?.contains(name),
+        false => batch.contains(name),
+    }
+}
+
+/// A column of `batch` as a numpy array of `T`, cast by numpy if it holds another
+/// type, or `None` if `batch` has no such column.
+fn column<'py, T: Element>(
+    batch: &Bound<'py, PyAny>,
+    name: &str,
+) -> PyResult<Option<PyReadonlyArray1<'py, T>>> {
+    if !has_column(batch, name)? {
+        return Ok(None);
+    }
+    let py = batch.py();
+    let kwargs = PyDict::new(py);
+    kwargs.set_item("dtype", T::get_dtype(py))?;
+    let values =
+        py.import("numpy")?
+            .call_method("asarray", (batch.get_item(name)?,), Some(&kwargs))?;
+    Ok(Some(values.extract()?))
+}
+
+/// A column of dbn characters as their codes. Strings are encoded by numpy, without a
+/// Python object per value.
+fn char_column<'py>(
+    batch: &Bound<'py, PyAny>,
+    name: &str,
+) -> PyResult<Option<PyReadonlyArray1<'py, i8>>> {
+    if !has_column(batch, name)? {
+        return Ok(None);
+    }
+    let values = batch
+        .py()
+        .import("numpy")?
+        .call_method1("asarray", (batch.get_item(name)?,))?;
+    let kind: String = values.getattr("dtype")?.getattr("kind")?.extract()?;
+    let values = match kind.as_str() {
+        "U" | "S" | "O" => values
+            .call_method1("astype", ("S1",))?
+            .call_method1("view", ("i1",))?,
+        _ => values.call_method1("astype", ("i1",))?,
+    };
+    Ok(Some(values.extract()?))
+}
+
+fn required<T>(column: Option<T>, name: &str) -> PyResult<T> {
+    column.ok_or_else(|| value_error(format!("Missing column '{name}'")))
+}
+
+fn column_len<T: Element>(column: &Option<PyReadonlyArray1<'_, T>>) -> Option<usize> {
+    column.as_ref().map(|column| column.len())
+}
+
+fn view<'a, T: Element>(column: &'a Option<PyReadonlyArray1<'_, T>>) -> Option<ArrayView1<'a, T>> {
+    column.as_ref().map(|column| column.as_array())
+}
+
+fn value<T: Copy>(column: &Option<ArrayView1<'_, T>>, row: usize) -> Option<T> {
+    column.as_ref().map(|column| column[row])
+}
+
+/// Converts the rows of a batch with the rules of tabular input, reporting rows that
+/// cannot be converted rather than failing the batch.
+fn convert_batch(batch: &Bound<'_, PyAny>) -> PyResult<ConversionReport> {
+    let action = required(char_column(batch, "action")?, "action")?;
+    let side = required(char_column(batch, "side")?, "side")?;
+    let price = required(column::<i64>(batch, "price")?, "price")?;
+    let order_id = required(column::<u64>(batch, "order_id")?, "order_id")?;
+    let size = required(column::<u32>(batch, "size")?, "size")?;
+    let instrument_id = column::<u32>(batch, "instrument_id")?;
+    let ts_event = column::<u64>(batch, "ts_event")?;
+    let ts_recv = column::<u64>(batch, "ts_recv")?;
+    let ts_in_delta = column::<i32>(batch, "ts_in_delta")?;
+    let sequence = column::<u32>(batch, "sequence")?;
+    let flags = column::<u8>(batch, "flags")?;
+
+    let rows = order_id.len();
+    let lengths = [
+        Some(action.len()),
+        Some(side.len()),
+        Some(price.len()),
+        Some(size.len()),
+        column_len(&instrument_id),
+        column_len(&ts_event),
+        column_len(&ts_recv),
+        column_len(&ts_in_delta),
+        column_len(&sequence),
+        column_len(&flags),
+    ];
+    if lengths.into_iter().flatten().any(|len| len != rows) {
+        return Err(value_error("Columns have different lengths"));
+    }
+
+    let (action, side, price) = (action.as_array(), side.as_array(), price.as_array());
+    let (order_id, size) = (order_id.as_array(), size.as_array());
+    let (instrument_id, ts_event, ts_recv) =
+        (view(&instrument_id), view(&ts_event), view(&ts_recv));
+    let (ts_in_delta, sequence, flags) = (view(&ts_in_delta), view(&sequence), view(&flags));
+    let mut report = ConversionReport::default();
+    for row in 0..rows {
+        let ts_event = value(&ts_event, row).unwrap_or_default();
+        let message = MboRow {
+            action: action[row],
+            side: side[row],
+            price: price[row],
+            order_id: order_id[row],
+            size: size[row],
+            instrument_id: value(&instrument_id, row).unwrap_or_default(),
+            ts_event,
+            ts_recv: value(&ts_recv, row).unwrap_or(ts_event),
+            ts_in_delta: value(&ts_in_delta, row).unwrap_or_default(),
+            sequence: value(&sequence, row).unwrap_or_default(),
+            flags: value(&flags, row).unwrap_or(dbn::flags::LAST),
+            publisher_id: 0,
+            channel_id: 0,
+        }
+        .into_message();
+        report.push(row, message);
+    }
+    Ok(report)
+}
+
+/// Columns of a market-by-price snapshot table, a row per level.
+#[derive(Default)]
+struct SnapshotColumns {
+    instrument_id: Vec<u32>,
+    side: Vec<PyFixedUnicode<1>>,
+    level: Vec<u32>,
+    price: Vec<i64>,
+    size: Vec<u64>,
+    count: Vec<u64>,
+}
+
+impl SnapshotColumns {
+    fn push(&mut self, instrument_id: u32, mbp: &MarketByPrice) {
+        let sides = [
+            ('B', mbp.bids.values().rev().collect::<Vec<_>>()),
+            ('A', mbp.asks.values().collect()),
+        ];
+        for (side, levels) in sides {
+            for (level, summary) in levels.into_iter().enumerate() {
+                self.instrument_id.push(instrument_id);
+                self.side.push(PyFixedUnicode::from([side as u32]));
+                self.level.push(level as u32);
+                self.price.push(summary.price);
+                self.size.push(summary.total_quantity);
+                self.count.push(summary.order_count as u64);
+            }
+        }
+    }
+
+    fn into_table(self, py: Python<'_>) -> PyResult<Bound<'_, PyAny>> {
+        let columns = PyDict::new(py);
+        columns.set_item("instrument_id", PyArray1::from_vec(py, self.instrument_id))?;
+        columns.set_item("side", PyArray1::from_vec(py, self.side))?;
+        columns.set_item("level", PyArray1::from_vec(py, self.level))?;
+        columns.set_item("price", PyArray1::from_vec(py, self.price))?;
+        columns.set_item("size", PyArray1::from_vec(py, self.size))?;
+        columns.set_item("count", PyArray1::from_vec(py, self.count))?;
+        py.import("pyarrow")?.call_method1("table", (columns,))
+    }
+}
@@ -0,0 +1,320 @@
+//! The `view` subcommand: an interactive terminal viewer replaying the input into the
+//! book of one instrument at a time.
+//!
+//! Playback decisions are made by `Playback`; this module only reads keys, applies the
+//! messages it is told to and draws the ladder from an `IncrementalMbp` and the
+//! spread sparkline from the processor's `BboHistory`.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::iter::Peekable;
+use std::time::{Duration, Instant};
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Sparkline, Table};
+use ratatui::{DefaultTerminal, Frame};
+use time::OffsetDateTime;
+
+use rainybook::{
+    BboHistory, IncrementalMbp, MarketByOrderMessage, MboProcessor, OrderLevelSummary, Playback,
+    PlaybackCommand, PlaybackStep, PriceScale, parse_time_ns,
+};
+
+/// Longest time between redraws, and between checks for keys while messages are due.
+const FRAME: Duration = Duration::from_millis(50);
+
+/// Messages applied by the down arrow.
+const JUMP_MESSAGES: u64 = 1_000;
+
+/// BBO changes kept per instrument for the spread sparkline.
+const SPREAD_HISTORY: usize = 240;
+
+/// Replays `messages` in the terminal until the user quits, starting at `speed` times
+/// real time and showing `depth` levels per side with prices at `price_scale`.
+pub fn view(
+    messages: impl Iterator<Item = MarketByOrderMessage>,
+    speed: f64,
+    depth: usize,
+    instrument_id: Option<u32>,
+    price_scale: PriceScale,
+) -> Result<(), Box<dyn Error>> {
+    let mut viewer = Viewer {
+        processor: MboProcessor::with_observer(IncrementalMbp::new())
+            .with_bbo_history(BboHistory::new().with_max_len(SPREAD_HISTORY)),
+        playback: Playback::new(speed)?,
+        depth,
+        price_scale,
+        instrument_id,
+        rate: MessageRate::default(),
+        last_event: None,
+        prompt: None,
+        status: String::new(),
+    };
+    let mut terminal = ratatui::init();
+    let result = viewer.run(&mut terminal, messages.peekable());
+    ratatui::restore();
+    result
+}
+
+struct Viewer {
+    processor: MboProcessor<IncrementalMbp>,
+    playback: Playback,
+    depth: usize,
+    price_scale: PriceScale,
+    /// The instrument shown; the first one seen until chosen.
+    instrument_id: Option<u32>,
+    rate: MessageRate,
+    last_event: Option<OffsetDateTime>,
+    /// Timestamp being typed after `g`.
+    prompt: Option<String>,
+    /// The outcome of the last command, or of the last failed message.
+    status: String,
+}
+
+impl Viewer {
+    fn run<I>(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        mut messages: Peekable<I>,
+    ) -> Result<(), Box<dyn Error>>
+    where
+        I: Iterator<Item = MarketByOrderMessage>,
+    {
+        let mut drawn: Option<Instant> = None;
+        loop {
+            let wait = match messages.peek() {
+                None => FRAME,
+                Some(message) => match self.playback.next(message.event_time) {
+                    PlaybackStep::Apply => {
+                        if let Some(message) = messages.next() {
+                            self.apply(&message);
+                        }
+                        Duration::ZERO
+                    }
+                    PlaybackStep::Wait(wait) => wait.min(FRAME),
+                    PlaybackStep::Paused => FRAME,
+                },
+            };
+            let frame_due = drawn.is_none_or(|drawn| drawn.elapsed() >= FRAME);
+            if frame_due {
+                self.sample(messages.peek().is_none());
+                terminal.draw(|frame| self.draw(frame))?;
+                drawn = Some(Instant::now());
+            } else if wait.is_zero() {
+                // Keep applying due messages; keys are read at the next frame
+                continue;
+            }
+            if event::poll(wait)?
+                && let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+                && !self.on_key(key)
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    fn apply(&mut self, message: &MarketByOrderMessage) {
+        if let Err(e) = self.processor.process_message(message) {
+            self.status = format!("Message {} failed: {e}", self.playback.position());
+        }
+        self.playback.applied();
+        self.instrument_id.get_or_insert(message.instrument_id);
+        self.last_event = Some(message.event_time);
+    }
+
+    /// Records the message rate of this frame.
+    fn sample(&mut self, finished: bool) {
+        if finished && self.status.is_empty() {
+            self.status = "End of input".into();
+        }
+        self.rate.sample(self.playback.position());
+    }
+
+    /// Handles a key press; false means quit.
+    fn on_key(&mut self, key: KeyEvent) -> bool {
+        if let Some(prompt) = &mut self.prompt {
+            match key.code {
+                KeyCode::Enter => {
+                    match parse_time_ns(prompt) {
+                        Ok(ns) => {
+                            let target =
+                                OffsetDateTime::UNIX_EPOCH + time::Duration::nanoseconds(ns as i64);
+                            self.playback.handle(PlaybackCommand::JumpTo(target));
+                            self.status = format!("Jumping to {target}");
+                        }
+                        Err(e) => self.status = e.to_string(),
+                    }
+                    self.prompt = None;
+                }
+                KeyCode::Esc => self.prompt = None,
+                KeyCode::Backspace => {
+                    prompt.pop();
+                }
+                KeyCode::Char(c) => prompt.push(c),
+                _ => {}
+            }
+            return true;
+        }
+        let command = match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Char(' ') => PlaybackCommand::TogglePause,
+            KeyCode::Right => PlaybackCommand::Step(1),
+            KeyCode::Down => PlaybackCommand::Step(JUMP_MESSAGES),
+            KeyCode::Char('+' | '=') => PlaybackCommand::Faster,
+            KeyCode::Char('-') => PlaybackCommand::Slower,
+            KeyCode::Char('g') => {
+                self.prompt = Some(String::new());
+                return true;
+            }
+            KeyCode::Tab => {
+                self.next_instrument();
+                return true;
+            }
+            _ => return true,
+        };
+        self.status.clear();
+        self.playback.handle(command);
+        true
+    }
+
+    /// Shows the instrument after the current one, in id order.
+    fn next_instrument(&mut self) {
+        let instruments = self.processor.instruments();
+        let next = match self.instrument_id {
+            Some(current) => instruments.iter().find(|&&id| id > current),
+            None => None,
+        };
+        if let Some(&id) = next.or(instruments.first()) {
+            self.instrument_id = Some(id);
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [header, ladder, spread, footer] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(3),
+            Constraint::Length(5),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let speed = match self.playback.speed() {
+            0.0 => "max".to_string(),
+            speed => format!("{speed}x"),
+        };
+        let state = match (self.playback.jump_target(), self.playback.is_paused()) {
+            (Some(_), _) => "JUMPING",
+            (None, true) => "PAUSED",
+            (None, false) => "PLAYING",
+        };
+        let event_time = self
+            .last_event
+            .map_or("-".to_string(), |time| time.to_string());
+        let instrument = self
+            .instrument_id
+            .map_or("-".to_string(), |id| id.to_string());
+        frame.render_widget(
+            Line::from(format!(
+                " {state} | instrument {instrument} | message {} | {event_time} | {:.0} messages/s | speed {speed}",
+                self.playback.position(),
+                self.rate.per_second(),
+            ))
+            .bold(),
+            header,
+        );
+
+        let (bids, asks) = match self
+            .instrument_id
+            .and_then(|id| self.processor.observer().mbp(id))
+        {
+            Some(mbp) => (mbp.top_n_bids(self.depth), mbp.top_n_asks(self.depth)),
+            None => (Vec::new(), Vec::new()),
+        };
+        let row = |level: &OrderLevelSummary, side_color: Color, bid: bool| {
+            let (orders, size) = (
+                level.order_count.to_string(),
+                level.total_quantity.to_string(),
+            );
+            let price = self.price_scale.format(level.price);
+            let cells = match bid {
+                true => [orders, size, price, String::new(), String::new()],
+                false => [String::new(), String::new(), price, size, orders],
+            };
+            Row::new(cells).style(Style::new().fg(side_color))
+        };
+        let rows = asks
+            .iter()
+            .rev()
+            .map(|level| row(level, Color::Red, false))
+            .chain(bids.iter().map(|level| row(level, Color::Green, true)));
+        let widths = [Constraint::Fill(1); 5];
+        let table = Table::new(rows, widths)
+            .header(Row::new(["Orders", "Bid size", "Price", "Ask size", "Orders"]).bold())
+            .block(Block::bordered().title(" Book "));
+        frame.render_widget(table, ladder);
+
+        // One bar per BBO change, zero while one-sided
+        let spreads: Vec<u64> = self
+            .instrument_id
+            .and_then(|id| self.processor.bbo_history(id))
+            .map_or(Vec::new(), |history| {
+                history
+                    .iter()
+                    .map(|sample| sample.spread().map_or(0, |spread| spread.max(0) as u64))
+                    .collect()
+            });
+        let current = self
+            .instrument_id
+            .and_then(|id| self.processor.observer().mbp(id))
+            .and_then(|mbp| mbp.spread())
+            .map_or("-".to_string(), |spread| self.price_scale.format(spread));
+        frame.render_widget(
+            Sparkline::default()
+                .data(&spreads)
+                .block(Block::bordered().title(format!(" Spread {current} "))),
+            spread,
+        );
+
+        let footer_text = match (&self.prompt, self.status.is_empty()) {
+            (Some(prompt), _) => format!(" Jump to (ns or RFC 3339): {prompt}_"),
+            (None, false) => format!(" {}", self.status),
+            (None, true) => {
+                " space pause  → step  ↓ +1000  +/- speed  g jump  tab instrument  q quit"
+                    .to_string()
+            }
+        };
+        frame.render_widget(Paragraph::new(footer_text), footer);
+    }
+}
+
+/// Messages applied per second of wall time over about the last second.
+#[derive(Default)]
+struct MessageRate {
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl MessageRate {
+    fn sample(&mut self, position: u64) {
+        let now = Instant::now();
+        self.samples.push_back((now, position));
+        while let Some(&(time, _)) = self.samples.front()
+            && now.duration_since(time) > Duration::from_secs(1)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    fn per_second(&self) -> f64 {
+        match (self.samples.front(), self.samples.back()) {
+            (Some(&(start, first)), Some(&(end, last))) if end > start => {
+                (last - first) as f64 / (end - start).as_secs_f64()
+            }
+            _ => 0.0,
+        }
+    }
+}
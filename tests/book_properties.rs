@@ -0,0 +1,376 @@
+//! Property tests of the book invariants over generated operation sequences.
+//!
+//! Operations refer to resting orders by their position among the live order ids, so a
+//! shrunk counterexample reads as a short list like `[Add { side: Bid, tick: 0, size: 1
+//! }, Fill { order: 0, size: 1 }]`. Every operation goes through `MboProcessor` and is
+//! mirrored in `Reference`, a naive list of orders, which the book is checked against
+//! after each step.
+
+use std::collections::{BTreeMap, HashMap};
+
+use proptest::prelude::*;
+use rainybook::{
+    Action, CrossingPolicy, ErrorPolicy, MarketByOrderMessage, MboProcessor, Order, OrderBook,
+    Side, ValidationPolicy,
+};
+use time::{Duration, OffsetDateTime};
+
+const INSTRUMENT_ID: u32 = 1;
+
+/// Price band the ticks of an operation are mapped into.
+#[derive(Debug, Clone, Copy)]
+enum Prices {
+    /// Bids from 100 down and asks from 101 up, so the book never crosses.
+    Separated,
+    /// Both sides from 95 up, so Adds cross and are matched. Modifies are kept from
+    /// crossing by `Reference::clamp`.
+    Overlapping,
+}
+
+impl Prices {
+    fn price(self, side: Side, tick: u8) -> i64 {
+        let tick = i64::from(tick);
+        match (self, side) {
+            (Prices::Separated, Side::Bid) => 100 - tick,
+            (Prices::Separated, Side::Ask) => 101 + tick,
+            (Prices::Overlapping, _) => 95 + tick,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Op {
+    /// A new order with a fresh id.
+    Add {
+        side: Side,
+        tick: u8,
+        size: u32,
+    },
+    /// Cancel of the `order`th live order, by id, wrapping around.
+    Cancel {
+        order: usize,
+    },
+    /// New price and size for the `order`th live order, keeping its side.
+    Modify {
+        order: usize,
+        tick: u8,
+        size: u32,
+    },
+    /// An execution of up to `size` against the `order`th live order: its Fill, then
+    /// the Cancel or reducing Modify the venue sends with it.
+    Fill {
+        order: usize,
+        size: u32,
+    },
+    Clear,
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    let side = prop_oneof![Just(Side::Bid), Just(Side::Ask)];
+    let order = 0..32usize;
+    prop_oneof![
+        4 => (side, 0..10u8, 1..20u32).prop_map(|(side, tick, size)| Op::Add { side, tick, size }),
+        2 => order.clone().prop_map(|order| Op::Cancel { order }),
+        2 => (order.clone(), 0..10u8, 1..20u32)
+            .prop_map(|(order, tick, size)| Op::Modify { order, tick, size }),
+        2 => (order, 1..20u32).prop_map(|(order, size)| Op::Fill { order, size }),
+        1 => Just(Op::Clear),
+    ]
+}
+
+/// The book as a plain list of resting orders, updated by the rules the book should
+/// follow.
+#[derive(Default)]
+struct Reference {
+    orders: HashMap<u64, Order>,
+}
+
+impl Reference {
+    /// The `n`th live order id in ascending order, wrapping around.
+    fn nth(&self, n: usize) -> Option<Order> {
+        let mut ids: Vec<u64> = self.orders.keys().copied().collect();
+        ids.sort_unstable();
+        let id = *ids.get(n % ids.len().max(1))?;
+        Some(self.orders[&id])
+    }
+
+    /// Matches `order` against the other side, best price and earliest sequence first,
+    /// then rests what is left.
+    fn add(&mut self, mut order: Order, policy: CrossingPolicy) {
+        while policy == CrossingPolicy::Match && order.size > 0 {
+            let crosses = |resting: &&Order| match order.side {
+                Side::Bid => resting.side == Side::Ask && resting.price <= order.price,
+                Side::Ask => resting.side == Side::Bid && resting.price >= order.price,
+            };
+            let best = self.orders.values().filter(crosses).min_by_key(|resting| {
+                let price = match order.side {
+                    Side::Bid => resting.price,
+                    Side::Ask => -resting.price,
+                };
+                (price, resting.sequence, resting.order_id)
+            });
+            let Some(&resting) = best else { break };
+            let size = order.size.min(resting.size);
+            order.size -= size;
+            self.reduce(resting.order_id, size);
+        }
+        if order.size > 0 {
+            self.orders.insert(order.order_id, order);
+        }
+    }
+
+    /// Takes `size` off an order, removing it once nothing is left.
+    fn reduce(&mut self, order_id: u64, size: u64) {
+        let order = self.orders.get_mut(&order_id).unwrap();
+        order.size -= size;
+        if order.size == 0 {
+            self.orders.remove(&order_id);
+        }
+    }
+
+    /// `price` moved back from the other side's best, so an order there does not
+    /// cross. `CrossingPolicy` only matches Adds; a venue never reports a Modify that
+    /// would trade.
+    fn clamp(&self, price: i64, side: Side) -> i64 {
+        let other = self.orders.values().filter(|order| order.side != side);
+        match side {
+            Side::Bid => other.map(|order| order.price - 1).fold(price, i64::min),
+            Side::Ask => other.map(|order| order.price + 1).fold(price, i64::max),
+        }
+    }
+
+    /// A modify keeps the queue position only at an unchanged price and a size that
+    /// does not grow.
+    fn modify(&mut self, new: Order) {
+        let old = self.orders[&new.order_id];
+        let sequence = match old.price == new.price && new.size <= old.size {
+            true => old.sequence,
+            false => new.sequence,
+        };
+        self.orders.insert(new.order_id, Order { sequence, ..new });
+    }
+
+    /// `(price, total quantity, order count)` per level of `side`.
+    fn levels(&self, side: Side) -> Vec<(i64, u64, usize)> {
+        let mut levels: BTreeMap<i64, (u64, usize)> = BTreeMap::new();
+        for order in self.orders.values().filter(|order| order.side == side) {
+            let level = levels.entry(order.price).or_default();
+            level.0 += order.size;
+            level.1 += 1;
+        }
+        levels
+            .into_iter()
+            .map(|(price, (qty, count))| (price, qty, count))
+            .collect()
+    }
+
+    /// Position of an order in its level's queue.
+    fn queue_position(&self, order: &Order) -> usize {
+        self.orders
+            .values()
+            .filter(|other| other.side == order.side && other.price == order.price)
+            .filter(|other| (other.sequence, other.order_id) < (order.sequence, order.order_id))
+            .count()
+    }
+}
+
+/// Applies `ops` to a processor and the reference, checking the book after each.
+struct Harness {
+    processor: MboProcessor,
+    reference: Reference,
+    prices: Prices,
+    policy: CrossingPolicy,
+    next_order_id: u64,
+    sequence: u32,
+}
+
+impl Harness {
+    fn new(prices: Prices, policy: CrossingPolicy) -> Self {
+        Self {
+            processor: MboProcessor::new()
+                .with_crossing_policy(policy)
+                .with_validation_policy(ValidationPolicy::Reject)
+                .with_error_policy(ErrorPolicy::FailFast),
+            reference: Reference::default(),
+            prices,
+            policy,
+            next_order_id: 1,
+            sequence: 0,
+        }
+    }
+
+    fn send(&mut self, action: Action, order: &Order, is_last: bool) -> Order {
+        self.sequence += 1;
+        let event_time = OffsetDateTime::UNIX_EPOCH + Duration::microseconds(self.sequence.into());
+        let message = MarketByOrderMessage {
+            instrument_id: INSTRUMENT_ID,
+            action,
+            side: order.side,
+            // Clears of the whole book come without a side, as in DBN
+            no_side: action == Action::Clear,
+            price: order.price,
+            order_id: order.order_id,
+            size: order.size as u32,
+            is_last,
+            flags: if is_last { dbn::flags::LAST } else { 0 },
+            sequence: self.sequence,
+            event_time,
+            recv_time: event_time,
+            ts_in_delta: Duration::ZERO,
+            owner: None,
+            publisher_id: 0,
+            channel_id: 0,
+        };
+        self.processor.process_message(&message).unwrap();
+        Order::from(&message)
+    }
+
+    fn apply(&mut self, op: &Op) {
+        match *op {
+            Op::Add { side, tick, size } => {
+                let order = Order {
+                    order_id: self.next_order_id,
+                    side,
+                    price: self.prices.price(side, tick),
+                    size: size.into(),
+                    sequence: 0,
+                    owner: None,
+                    expires_at: None,
+                };
+                self.next_order_id += 1;
+                let order = self.send(Action::Add, &order, true);
+                self.reference.add(order, self.policy);
+            }
+            Op::Cancel { order } => {
+                if let Some(order) = self.reference.nth(order) {
+                    self.send(Action::Cancel, &order, true);
+                    self.reference.orders.remove(&order.order_id);
+                }
+            }
+            Op::Modify { order, tick, size } => {
+                if let Some(order) = self.reference.nth(order) {
+                    let modified = Order {
+                        price: self
+                            .reference
+                            .clamp(self.prices.price(order.side, tick), order.side),
+                        size: size.into(),
+                        ..order
+                    };
+                    let modified = self.send(Action::Modify, &modified, true);
+                    self.reference.modify(modified);
+                }
+            }
+            Op::Fill { order, size } => {
+                if let Some(order) = self.reference.nth(order) {
+                    let size = u64::from(size).min(order.size);
+                    self.send(Action::Fill, &Order { size, ..order }, false);
+                    match order.size - size {
+                        0 => self.send(Action::Cancel, &order, true),
+                        left => self.send(
+                            Action::Modify,
+                            &Order {
+                                size: left,
+                                ..order
+                            },
+                            true,
+                        ),
+                    };
+                    self.reference.reduce(order.order_id, size);
+                }
+            }
+            Op::Clear => {
+                let clear = Order {
+                    order_id: 0,
+                    side: Side::Bid,
+                    price: dbn::UNDEF_PRICE,
+                    size: 0,
+                    sequence: 0,
+                    owner: None,
+                    expires_at: None,
+                };
+                self.send(Action::Clear, &clear, true);
+                self.reference.orders.clear();
+            }
+        }
+    }
+
+    fn check(&self) -> Result<(), TestCaseError> {
+        let book = self.processor.order_book();
+        prop_assert_eq!(book.validate(), Ok(()));
+        check_orders(book, &self.reference, self.next_order_id)?;
+        check_levels(book, &self.reference)?;
+        let (bid, ask) = (book.best_bid(), book.best_ask());
+        if let (Some((bid, _)), Some((ask, _))) = (bid, ask) {
+            prop_assert!(bid < ask, "crossed book: bid {bid} >= ask {ask}");
+        }
+        prop_assert_eq!(self.processor.stats().warnings(), 0);
+        Ok(())
+    }
+}
+
+/// Exactly the reference's orders are indexed, each with its queue position, among
+/// the ids added so far.
+fn check_orders(
+    book: &OrderBook,
+    reference: &Reference,
+    next_order_id: u64,
+) -> Result<(), TestCaseError> {
+    for order in reference.orders.values() {
+        prop_assert_eq!(book.get_order(order.order_id), Some(order));
+        prop_assert_eq!(
+            book.queue_position(order.order_id),
+            Some(reference.queue_position(order))
+        );
+    }
+    let gone = (1..next_order_id).filter(|id| !reference.orders.contains_key(id));
+    for order_id in gone {
+        prop_assert_eq!(book.get_order(order_id), None, "order {} is gone", order_id);
+    }
+    Ok(())
+}
+
+/// Levels match the reference's aggregation, none is empty and the cached best levels
+/// and side totals agree with them.
+fn check_levels(book: &OrderBook, reference: &Reference) -> Result<(), TestCaseError> {
+    let mbp = rainybook::MarketByPrice::from(book);
+    for (side, levels) in [(Side::Bid, &mbp.bids), (Side::Ask, &mbp.asks)] {
+        let levels: Vec<(i64, u64, usize)> = levels
+            .values()
+            .map(|level| (level.price, level.total_quantity, level.order_count))
+            .collect();
+        prop_assert!(levels.iter().all(|&(_, qty, count)| qty > 0 && count > 0));
+        prop_assert_eq!(&levels, &reference.levels(side), "{:?} levels", side);
+        prop_assert_eq!(book.level_count(side), levels.len());
+        prop_assert_eq!(
+            book.total_qty(side),
+            levels.iter().map(|&(_, qty, _)| qty).sum::<u64>()
+        );
+    }
+    let top = |level: Option<&(i64, u64, usize)>| level.map(|&(price, qty, _)| (price, qty));
+    prop_assert_eq!(book.best_bid(), top(reference.levels(Side::Bid).last()));
+    prop_assert_eq!(book.best_ask(), top(reference.levels(Side::Ask).first()));
+    Ok(())
+}
+
+fn run(ops: &[Op], prices: Prices, policy: CrossingPolicy) -> Result<(), TestCaseError> {
+    let mut harness = Harness::new(prices, policy);
+    for op in ops {
+        harness.apply(op);
+        harness.check()?;
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(512))]
+
+    #[test]
+    fn resting_book_keeps_invariants(ops in prop::collection::vec(op(), 1..200)) {
+        run(&ops, Prices::Separated, CrossingPolicy::Rest)?;
+    }
+
+    #[test]
+    fn matching_book_never_crosses(ops in prop::collection::vec(op(), 1..200)) {
+        run(&ops, Prices::Overlapping, CrossingPolicy::Match)?;
+    }
+}
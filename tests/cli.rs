@@ -0,0 +1,1073 @@
+//! Runs the `rainybook` binary on small inputs.
+
+use std::path::{Path, PathBuf};
+use std::{env, fs, process};
+
+use assert_cmd::Command;
+use dbn::{MappingInterval, SType, SymbolMapping};
+use predicates::prelude::*;
+use rainybook::{CsvOptions, mbo_metadata, read_mbo_csv, write_dbn};
+use time::macros::date;
+
+/// Writes two resting orders as NDJSON to a temporary file named after `name`.
+fn ndjson_input(name: &str) -> PathBuf {
+    let path = temp_path(&format!("{name}.ndjson"));
+    fs::write(
+        &path,
+        concat!(
+            r#"{"action":"A","side":"B","price":100,"order_id":1,"size":5,"ts_event":1000}"#,
+            "\n",
+            r#"{"action":"A","side":"A","price":101,"order_id":2,"size":7,"ts_event":2000}"#,
+            "\n",
+        ),
+    )
+    .unwrap();
+    path
+}
+
+/// Instrument 7 rests bids at 100 and 99.5 and asks at 101 and 101.25, then the 99.5
+/// bid is cancelled, a trade prints and an unknown order is cancelled; instrument 8
+/// adds one bid. Prices are dbn fixed-point, event times 1 s to 4.5 s. CSV carries no
+/// price scale, so commands showing its prices pass `SCALED`.
+fn fixture() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/mbo.csv")
+}
+
+/// Writes the fixture as DBN whose metadata maps ESZ4 to instrument 7 and NQZ4 to 8.
+fn dbn_with_mappings(name: &str) -> PathBuf {
+    let messages = read_mbo_csv(fixture(), CsvOptions::default())
+        .unwrap()
+        .messages;
+    let mut metadata = mbo_metadata("GLBX.MDP3", messages[0].event_time);
+    metadata.stype_in = Some(SType::RawSymbol);
+    metadata.mappings = [("ESZ4", "7"), ("NQZ4", "8")]
+        .into_iter()
+        .map(|(raw_symbol, instrument_id)| SymbolMapping {
+            raw_symbol: raw_symbol.to_owned(),
+            intervals: vec![MappingInterval {
+                start_date: date!(1970 - 01 - 01),
+                end_date: date!(1970 - 01 - 02),
+                symbol: instrument_id.to_owned(),
+            }],
+        })
+        .collect();
+    let path = temp_path(&format!("{name}.dbn"));
+    write_dbn(&path, &metadata, &messages).unwrap();
+    path
+}
+
+/// Shows the fixture's prices in units rather than raw integers.
+const SCALED: [&str; 2] = ["--price-scale", "1e-9"];
+
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("rainybook-{}-{name}", process::id()))
+}
+
+fn rainybook() -> Command {
+    Command::cargo_bin("rainybook").unwrap()
+}
+
+fn stdout(command: &mut Command) -> String {
+    let output = command.output().unwrap();
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_quiet_by_default() {
+    let path = ndjson_input("quiet");
+    rainybook()
+        .args(["process", "--data-path"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stderr(predicate::str::is_empty());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_debug_logs_processing() {
+    let path = ndjson_input("debug");
+    rainybook()
+        .args(["process", "--data-path"])
+        .arg(&path)
+        .arg("-vv")
+        .assert()
+        .success()
+        .stderr(
+            predicate::str::contains("Using 1 data file(s)")
+                .and(predicate::str::contains("Processing MBO message"))
+                .and(predicate::str::contains("order_id=2")),
+        );
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_json_logs_with_filter() {
+    let path = ndjson_input("json");
+    let output = rainybook()
+        .args(["process", "--data-path"])
+        .arg(&path)
+        .args([
+            "--log-format",
+            "json",
+            "--log-filter",
+            "rainybook::orderbook=debug",
+        ])
+        .output()
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+
+    // Only the library's debug logs pass the filter; the binary stays at warn
+    let events: Vec<serde_json::Value> = String::from_utf8(output.stderr)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let added: Vec<u64> = events
+        .iter()
+        .filter(|event| event["fields"]["message"] == "Adding order")
+        .map(|event| event["fields"]["order_id"].as_u64().unwrap())
+        .collect();
+    assert_eq!(added, vec![1, 2]);
+    assert!(events.iter().all(|event| {
+        event["target"]
+            .as_str()
+            .unwrap()
+            .starts_with("rainybook::orderbook")
+    }));
+}
+
+#[test]
+fn test_process() {
+    rainybook()
+        .args(["process", "--data-path"])
+        .arg(fixture())
+        .args(SCALED)
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("Total MBO messages: 8")
+                .and(predicate::str::contains(
+                    "Instrument 7: bid 10 @ 100 | ask 8 @ 101",
+                ))
+                .and(predicate::str::contains(
+                    "Instrument 8: bid 1 @ 200 | ask -",
+                ))
+                .and(predicate::str::contains("Failed messages:    0"))
+                .and(predicate::str::contains("Elapsed:")),
+        );
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn test_process_threads() {
+    let json = |threads: &[&str]| {
+        let output = stdout(
+            rainybook()
+                .args(["process", "--json", "--depth", "2"])
+                .args(SCALED)
+                .args(threads)
+                .arg("--data-path")
+                .arg(fixture()),
+        );
+        let mut summary: serde_json::Value = serde_json::from_str(output.trim()).unwrap();
+        summary["elapsed_s"] = serde_json::Value::Null;
+        summary["messages_per_s"] = serde_json::Value::Null;
+        summary
+    };
+    let sequential = json(&[]);
+    assert_eq!(sequential["instruments"].as_array().unwrap().len(), 2);
+    assert_eq!(json(&["--threads", "1"]), sequential);
+    assert_eq!(json(&["--threads", "2"]), sequential);
+
+    rainybook()
+        .args([
+            "process",
+            "--threads",
+            "2",
+            "--print-every",
+            "2",
+            "--data-path",
+        ])
+        .arg(fixture())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "--threads cannot be combined with --print-every",
+        ));
+}
+
+#[test]
+fn test_process_summary() {
+    let summary = stdout(
+        rainybook()
+            .args([
+                "process",
+                "--depth",
+                "1",
+                "--instrument-id",
+                "7",
+                "--data-path",
+            ])
+            .arg(fixture())
+            .args(SCALED),
+    );
+    let expected = concat!(
+        "Instrument 7: bid 10 @ 100 | ask 8 @ 101\n",
+        "  Spread:           1\n",
+        "  Bid levels:       1\n",
+        "  Ask levels:       2\n",
+        "  Bid quantity:     10\n",
+        "  Ask quantity:     12\n",
+    );
+    assert!(summary.contains(expected), "{summary}");
+    assert!(summary.contains("  Unknown cancels:  1"), "{summary}");
+    // The fixture's one Trade is a sell of 3
+    assert!(
+        summary.contains("  Sell volume:      3 in 1 trades\n"),
+        "{summary}"
+    );
+    assert!(summary.contains("101.000000000"), "{summary}");
+    assert!(!summary.contains("101.250000000"), "{summary}");
+
+    let json = stdout(
+        rainybook()
+            .args(["process", "--json", "--depth", "2", "--data-path"])
+            .arg(fixture())
+            .args(SCALED),
+    );
+    let summary: serde_json::Value = serde_json::from_str(json.trim()).unwrap();
+    assert_eq!(summary["stats"]["messages"], 8);
+    assert_eq!(summary["stats"]["action_counts"]["cancel"], 2);
+    assert_eq!(summary["stats"]["unknown_cancels"], 1);
+    assert_eq!(summary["flow"]["sell_volume"], 3);
+    assert_eq!(summary["flow"]["buy_trades"], 0);
+    assert_eq!(summary["failed_messages"], 0);
+    assert!(summary["elapsed_s"].as_f64().unwrap() >= 0.0);
+    let instruments = summary["instruments"].as_array().unwrap();
+    assert_eq!(instruments.len(), 2);
+    let book = &instruments[0];
+    assert_eq!(book["instrument_id"], 7);
+    assert_eq!(book["best_bid"], serde_json::json!([100.0, 10]));
+    assert_eq!(book["best_ask"], serde_json::json!([101.0, 8]));
+    assert_eq!(book["spread"], 1.0);
+    assert_eq!(book["ask_levels"], 2);
+    assert_eq!(book["ask_qty"], 12);
+    assert_eq!(book["depth"]["asks"][1][0], 101.25);
+    assert_eq!(instruments[1]["best_ask"], serde_json::Value::Null);
+    assert_eq!(instruments[1]["spread"], serde_json::Value::Null);
+}
+
+#[test]
+fn test_print_every() {
+    // The six messages of instrument 7 before 4 s
+    let print = |extra: &[&str]| {
+        stdout(
+            rainybook()
+                .args(["process", "--print-every", "2", "--print-depth", "1"])
+                .args(["--instrument-id", "7", "--end", "4000000000"])
+                .args(SCALED)
+                .args(extra)
+                .arg("--data-path")
+                .arg(fixture()),
+        )
+    };
+    let output = print(&[]);
+    let headers: Vec<&str> = output
+        .lines()
+        .filter(|line| line.starts_with("Message "))
+        .collect();
+    assert_eq!(
+        headers,
+        vec![
+            "Message 1 at ts_event 1500000000",
+            "Message 3 at ts_event 2500000000",
+            "Message 5 at ts_event 3500000000",
+        ]
+    );
+    assert_eq!(output.matches("Instrument 7\n").count(), 3, "{output}");
+    assert!(!output.contains("99.500000000"), "{output}");
+    assert!(output.contains("Total MBO messages: 6"), "{output}");
+
+    let quiet = print(&["--quiet"]);
+    assert!(!quiet.contains("Message "), "{quiet}");
+    assert!(quiet.contains("Total MBO messages: 6"), "{quiet}");
+}
+
+#[cfg(feature = "polars")]
+#[test]
+fn test_process_snapshots() {
+    use polars::prelude::{DataType, ParquetReader, SerReader};
+
+    let dir = temp_path("snapshots");
+    let output = dir.join("nested").join("snapshots.parquet");
+    let read_back = |path: &Path| {
+        let df = ParquetReader::new(fs::File::open(path).unwrap())
+            .finish()
+            .unwrap();
+        let message_index: Vec<u64> = df
+            .column("message_index")
+            .unwrap()
+            .cast(&DataType::UInt64)
+            .unwrap()
+            .u64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        (df, message_index)
+    };
+
+    // After messages 3 and 6, the best bid and ask of instrument 7 each time
+    rainybook()
+        .args(["process", "--snapshot-every", "3", "--snapshot-depth", "1"])
+        .arg("--data-path")
+        .arg(fixture())
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+    let (df, message_index) = read_back(&output);
+    let columns: Vec<&str> = df.get_column_names_str();
+    assert_eq!(
+        columns[..6],
+        [
+            "ts_event",
+            "sample_ts",
+            "message_index",
+            "instrument_id",
+            "side",
+            "level"
+        ]
+    );
+    assert!(columns.contains(&"price") && columns.contains(&"size"));
+    assert_eq!(message_index, vec![2, 2, 5, 5]);
+
+    // The file is kept without --force
+    rainybook()
+        .args(["process", "--data-path"])
+        .arg(fixture())
+        .arg("--output")
+        .arg(&output)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("pass --force"));
+    assert_eq!(read_back(&output).0.height(), 4);
+
+    // Without --snapshot-every only the final books: 1 + 2 levels of 7, 1 of 8
+    rainybook()
+        .args(["process", "--force", "--data-path"])
+        .arg(fixture())
+        .arg("--output")
+        .arg(&output)
+        .assert()
+        .success();
+    let (df, message_index) = read_back(&output);
+    assert_eq!(df.height(), 4);
+    assert_eq!(message_index, vec![7; 4]);
+    fs::remove_dir_all(&dir).unwrap();
+
+    // A parent that is a file cannot be created
+    rainybook()
+        .args(["process", "--data-path"])
+        .arg(fixture())
+        .arg("--output")
+        .arg(fixture().join("snapshots.parquet"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Cannot write snapshots to"));
+}
+
+#[cfg(feature = "polars")]
+#[test]
+fn test_process_ipc_outputs() {
+    use polars::prelude::{DataFrame, IpcReader, ParquetReader, SerReader};
+
+    let dir = temp_path("ipc-outputs");
+    fs::create_dir_all(&dir).unwrap();
+    let process = |names: [&str; 3], format: &[&str]| {
+        let [snapshots, bbo, trades] = names.map(|name| dir.join(name));
+        rainybook()
+            .args(["process", "--snapshot-every", "3", "--data-path"])
+            .arg(fixture())
+            .arg("-o")
+            .arg(&snapshots)
+            .arg("--bbo-out")
+            .arg(&bbo)
+            .arg("--trades-out")
+            .arg(&trades)
+            .args(format)
+            .assert()
+            .success();
+        [snapshots, bbo, trades]
+    };
+    let read = |path: &Path, ipc: bool| -> DataFrame {
+        let file = fs::File::open(path).unwrap();
+        match ipc {
+            true => IpcReader::new(file).finish().unwrap(),
+            false => ParquetReader::new(file).finish().unwrap(),
+        }
+    };
+
+    let parquet = process(["s.parquet", "b.parquet", "t.parquet"], &[]);
+    // By extension, and by --output-format whatever the extension
+    let by_extension = process(["s.arrow", "b.feather", "t.ipc"], &[]);
+    let by_format = process(["s.out", "b.out", "t.out"], &["--output-format", "ipc"]);
+    for (index, expected) in parquet.iter().enumerate() {
+        let expected = read(expected, false);
+        assert!(expected.height() > 0);
+        for ipc in [&by_extension[index], &by_format[index]] {
+            let df = read(ipc, true);
+            assert_eq!(df.schema(), expected.schema(), "{}", ipc.display());
+            assert!(df.equals_missing(&expected), "{}", ipc.display());
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "polars")]
+#[test]
+fn test_process_anomalies_out() {
+    use polars::prelude::{ParquetReader, SerReader};
+
+    let dir = temp_path("anomalies");
+    let path = dir.join("nested").join("anomalies.parquet");
+    let process = |force: &[&str]| {
+        rainybook()
+            .args(["process", "--data-path"])
+            .arg(fixture())
+            .arg("--anomalies-out")
+            .arg(&path)
+            .args(force)
+            .assert()
+    };
+    process(&[]).success();
+    let df = ParquetReader::new(fs::File::open(&path).unwrap())
+        .finish()
+        .unwrap();
+
+    // The file is kept without --force, and replaced with it
+    fs::write(&path, "kept").unwrap();
+    process(&[])
+        .failure()
+        .stderr(predicate::str::contains("pass --force"));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "kept");
+    process(&["--force"]).success();
+    assert_ne!(fs::read(&path).unwrap(), b"kept");
+    fs::remove_dir_all(&dir).unwrap();
+
+    // The fixture's one anomaly: the Cancel of order 9, never added, at index 6
+    assert_eq!(df.height(), 1);
+    let column = |name: &str| df.column(name).unwrap().get(0).unwrap().to_string();
+    assert_eq!(column("kind"), "\"unknown_cancel\"");
+    assert_eq!(column("message_index"), "6");
+    assert_eq!(column("order_id"), "9");
+    assert_eq!(column("ts_event"), "4000000000");
+}
+
+#[test]
+fn test_existing_outputs() {
+    let dir = temp_path("existing-outputs");
+    let tob = dir.join("nested").join("tob.csv");
+    let converted = dir.join("converted.ndjson");
+    let run = |subcommand: &str, option: &str, path: &Path, force: &[&str]| {
+        rainybook()
+            .args([subcommand, "--data-path"])
+            .arg(fixture())
+            .arg(option)
+            .arg(path)
+            .args(force)
+            .assert()
+    };
+    for (subcommand, option, path) in [
+        ("process", "--tob-out", &tob),
+        ("convert", "--output", &converted),
+    ] {
+        run(subcommand, option, path, &[]).success();
+
+        // The file is kept without --force, and replaced with it
+        fs::write(path, "kept").unwrap();
+        run(subcommand, option, path, &[])
+            .failure()
+            .stderr(predicate::str::contains("pass --force"));
+        assert_eq!(fs::read_to_string(path).unwrap(), "kept", "{option}");
+        run(subcommand, option, path, &["--force"]).success();
+        assert_ne!(fs::read_to_string(path).unwrap(), "kept", "{option}");
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_quiet_stdin() {
+    // Progress through standard input, of unknown size, would be reported in lines
+    let output = rainybook()
+        .args(["process", "--json", "--quiet", "--stdin", "--format", "csv"])
+        .pipe_stdin(fixture())
+        .unwrap()
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert!(output.stderr.is_empty(), "{output:?}");
+    let summary: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(summary["stats"]["messages"], 8);
+}
+
+#[test]
+fn test_snapshot() {
+    // Log options are accepted after the subcommand too
+    let ladder = stdout(
+        rainybook()
+            .args(["snapshot", "--depth", "2", "--as-of", "2000000000", "-v"])
+            .args(SCALED)
+            .arg("--data-path")
+            .arg(fixture()),
+    );
+    assert!(ladder.starts_with("Instrument 7\n"), "{ladder}");
+    assert!(ladder.contains("99.500000000"), "{ladder}");
+    assert!(!ladder.contains("101.250000000"), "{ladder}");
+    assert!(!ladder.contains("Instrument 8"), "{ladder}");
+
+    let json = stdout(
+        rainybook()
+            .args(["snapshot", "--json", "--instrument-id", "7", "--data-path"])
+            .arg(fixture())
+            .args(SCALED),
+    );
+    let depth: serde_json::Value = serde_json::from_str(json.trim()).unwrap();
+    assert_eq!(depth["instrument_id"], 7);
+    assert_eq!(depth["bids"].as_array().unwrap().len(), 1);
+    assert_eq!(depth["asks"][1][0], 101.25);
+}
+
+#[test]
+fn test_price_scale() {
+    // Raw integers for CSV unless a scale is given
+    let raw = stdout(
+        rainybook()
+            .args(["process", "--instrument-id", "7", "--data-path"])
+            .arg(fixture()),
+    );
+    assert!(
+        raw.contains("Instrument 7: bid 10 @ 100000000000 | ask 8 @ 101000000000"),
+        "{raw}"
+    );
+    let cents = stdout(
+        rainybook()
+            .args(["snapshot", "--instrument-id", "7", "--price-scale", "0.01"])
+            .arg("--data-path")
+            .arg(fixture()),
+    );
+    assert!(cents.contains("1012500000.00"), "{cents}");
+
+    // DBN prices are always 1e-9
+    let path = dbn_with_mappings("scale");
+    let json = stdout(
+        rainybook()
+            .args(["snapshot", "--json", "--instrument-id", "7", "--data-path"])
+            .arg(&path),
+    );
+    fs::remove_file(&path).unwrap();
+    let depth: serde_json::Value = serde_json::from_str(json.trim()).unwrap();
+    assert_eq!(depth["asks"][1][0], 101.25);
+
+    rainybook()
+        .args(["process", "--price-scale", "cents", "--data-path"])
+        .arg(fixture())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid price scale 'cents'"));
+}
+
+#[test]
+fn test_symbol_filter() {
+    let path = dbn_with_mappings("symbols");
+    let output = stdout(
+        rainybook()
+            .args(["process", "--symbol", "ESZ4", "--data-path"])
+            .arg(&path),
+    );
+    assert!(output.contains("Total MBO messages: 7"), "{output}");
+    assert!(output.contains("Symbol ESZ4:\nESZ4 (7): bid"), "{output}");
+    assert!(!output.contains("NQZ4"), "{output}");
+
+    let json = stdout(
+        rainybook()
+            .args(["process", "--json", "--symbol", "NQZ4", "--symbol", "ESZ4"])
+            .arg("--data-path")
+            .arg(&path),
+    );
+    let summary: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(summary["stats"]["messages"], 8);
+    assert_eq!(
+        summary["symbols"],
+        serde_json::json!([
+            {"symbol": "NQZ4", "instrument_ids": [8]},
+            {"symbol": "ESZ4", "instrument_ids": [7]},
+        ])
+    );
+
+    rainybook()
+        .args(["process", "--symbol", "ESH5", "--data-path"])
+        .arg(&path)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Symbol 'ESH5' not found, available symbols: ESZ4, NQZ4",
+        ));
+    fs::remove_file(&path).unwrap();
+
+    // CSV has no symbology to resolve symbols with
+    rainybook()
+        .args(["process", "--symbol", "ESZ4", "--data-path"])
+        .arg(fixture())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("with --instrument-id instead"));
+}
+
+#[test]
+fn test_time_window() {
+    // 2.5 s to 4 s holds the ask at 101.25, the cancel of the 99.5 bid and the trade
+    let window = |extra: &[&str]| {
+        stdout(
+            rainybook()
+                .args(["process", "--start", "2500000000", "--end", "4000000000"])
+                .args(SCALED)
+                .args(extra)
+                .arg("--data-path")
+                .arg(fixture()),
+        )
+    };
+
+    // Cold, the window starts from empty books
+    let cold = window(&[]);
+    assert!(cold.contains("Total MBO messages: 3"), "{cold}");
+    assert!(cold.contains("Unknown cancels:  1"), "{cold}");
+    assert!(cold.contains("Inside window:      3"), "{cold}");
+    assert!(
+        cold.contains("Outside window:     5 (3 before start, 2 after end)"),
+        "{cold}"
+    );
+    assert!(
+        cold.contains("Instrument 7: bid - | ask 4 @ 101.25"),
+        "{cold}"
+    );
+    assert!(!cold.contains("Instrument 8"), "{cold}");
+
+    // Warm, the three earlier messages build the book first
+    let warm = window(&["--warmup"]);
+    assert!(warm.contains("Total MBO messages: 6"), "{warm}");
+    assert!(warm.contains("Unknown cancels:  0"), "{warm}");
+    assert!(warm.contains("Inside window:      3"), "{warm}");
+    assert!(
+        warm.contains("Instrument 7: bid 10 @ 100 | ask 8 @ 101"),
+        "{warm}"
+    );
+
+    // With --symbol, only the messages of the symbol are counted
+    let path = dbn_with_mappings("window");
+    let json = stdout(
+        rainybook()
+            .args(["process", "--json", "--symbol", "NQZ4"])
+            .args(["--start", "2024-05-01T14:30:00Z", "--warmup", "--data-path"])
+            .arg(&path),
+    );
+    fs::remove_file(&path).unwrap();
+    let summary: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(summary["window"]["inside"], 0);
+    assert_eq!(summary["window"]["before_start"], 1);
+    assert_eq!(summary["instruments"][0]["bid_qty"], 1);
+
+    rainybook()
+        .args(["process", "--start", "2500000000", "--end", "2500000000"])
+        .arg("--data-path")
+        .arg(fixture())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("not after its start"));
+}
+
+#[test]
+fn test_compare_identical() {
+    rainybook()
+        .args(["compare", "--a"])
+        .arg(fixture())
+        .arg("--b")
+        .arg(fixture())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Books:              match"));
+}
+
+#[test]
+fn test_compare_pinpoints_divergence() {
+    // Resize order 4, the fixture's fourth message, in a copy
+    let fixture_csv = fs::read_to_string(fixture()).unwrap();
+    let corrupted = fixture_csv.replace("A,A,101250000000,4,4,", "A,A,101250000000,4,6,");
+    assert_ne!(corrupted, fixture_csv);
+    let path = temp_path("compare.csv");
+    fs::write(&path, corrupted).unwrap();
+
+    let human = rainybook()
+        .args(["compare", "--hash-every", "3", "--a"])
+        .arg(fixture())
+        .arg("--b")
+        .arg(&path)
+        .args(SCALED)
+        .assert()
+        .code(8)
+        .stdout(
+            predicate::str::contains("First divergence:   message #3\n").and(
+                predicate::str::contains(
+                    "  Instrument 7 ask 101.250000000: 4 in 1 orders vs 6 in 1 orders\n",
+                ),
+            ),
+        );
+    human.stderr(predicate::str::contains("diverge from message #3"));
+
+    let output = rainybook()
+        .args(["compare", "--json", "--a"])
+        .arg(fixture())
+        .arg("--b")
+        .arg(&path)
+        .args(SCALED)
+        .output()
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(output.status.code(), Some(8));
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["identical"], false);
+    assert_eq!(report["divergence"]["index"], 3);
+    assert_eq!(report["divergence"]["a"]["ts_event"], 2_500_000_000u64);
+    assert_eq!(
+        report["levels"],
+        serde_json::json!([{
+            "instrument_id": 7, "side": "ask", "price": 101.25, "a": [4, 1], "b": [6, 1]
+        }])
+    );
+    assert_eq!(report["stats"], serde_json::json!([]));
+}
+
+#[test]
+fn test_stats() {
+    rainybook()
+        .args(["stats", "--data-path"])
+        .arg(fixture())
+        .args(SCALED)
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("Total MBO messages: 8")
+                .and(predicate::str::contains("Unknown cancels:  1"))
+                .and(predicate::str::contains("Instruments:        2"))
+                .and(predicate::str::contains("(3.500 s)"))
+                .and(predicate::str::contains(
+                    "Event rate:         2.3 messages/s",
+                ))
+                // Two-sided from 2 s to the last event at 4.5 s
+                .and(predicate::str::contains(
+                    "Instrument 7 quotes:\n  BBO updates:      2\n  Observed:         2.500 s\n  \
+                     Avg spread:       1\n  Avg mid:          100.5\n",
+                ))
+                .and(predicate::str::contains("Instrument 8 quotes:"))
+                // 0 from 1 s, 8 from 2 s and 12 from 2.5 s to 4.5 s
+                .and(predicate::str::contains(
+                    "  Ask top 5:        avg 8.0, peak 12, trough 0\n",
+                ))
+                .and(predicate::str::is_match("State hash:         [0-9a-f]{16}\n").unwrap()),
+        );
+}
+
+#[test]
+fn test_exit_status() {
+    let process = |path: &Path| {
+        let mut command = rainybook();
+        command
+            .args(["process", "--quiet", "--data-path"])
+            .arg(path);
+        command
+    };
+    process(&fixture()).assert().code(0);
+
+    process(Path::new("/nonexistent/mbo.dbn"))
+        .assert()
+        .code(3)
+        .stderr(predicate::str::starts_with(
+            "Error: Cannot open /nonexistent/mbo.dbn",
+        ));
+
+    let not_csv = temp_path("not.csv");
+    fs::write(&not_csv, "hello\n").unwrap();
+    process(&not_csv)
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains(
+            "CSV header has no 'action' column",
+        ));
+    fs::remove_file(&not_csv).unwrap();
+
+    // The last record is cut short, after seven were processed
+    let truncated = dbn_with_mappings("truncated");
+    let bytes = fs::read(&truncated).unwrap();
+    fs::write(&truncated, &bytes[..bytes.len() - 3]).unwrap();
+    process(&truncated)
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains("Cannot decode record 7 of"));
+    fs::remove_file(&truncated).unwrap();
+
+    // The second message has no size, so fails validation
+    let invalid = temp_path("invalid.ndjson");
+    fs::write(
+        &invalid,
+        concat!(
+            r#"{"action":"A","side":"B","price":100,"order_id":1,"size":5,"ts_event":1000}"#,
+            "\n",
+            r#"{"action":"A","side":"B","price":100,"order_id":2,"size":0,"ts_event":1500}"#,
+            "\n",
+            r#"{"action":"A","side":"A","price":101,"order_id":3,"size":7,"ts_event":2000}"#,
+            "\n",
+        ),
+    )
+    .unwrap();
+    process(&invalid)
+        .assert()
+        .code(6)
+        .stdout(predicate::str::contains("Total MBO messages: 2"))
+        .stderr(predicate::str::contains("Message #1 failed to process"));
+    process(&invalid)
+        .args(["--on-error", "collect"])
+        .assert()
+        .code(7)
+        .stdout(
+            predicate::str::contains("Total MBO messages: 3")
+                .and(predicate::str::contains("Failed messages:    1")),
+        )
+        .stderr(predicate::str::contains(
+            "Completed with 1 failed messages, the first was message #1",
+        ));
+    fs::remove_file(&invalid).unwrap();
+}
+
+#[test]
+fn test_format_override() {
+    let dbn = dbn_with_mappings("format");
+    let blob = temp_path("format-blob");
+    fs::rename(&dbn, &blob).unwrap();
+    rainybook()
+        .args(["process", "--format", "dbn", "--data-path"])
+        .arg(&blob)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Total MBO messages: 8"));
+    rainybook()
+        .args(["process", "--stdin", "--format", "dbn"])
+        .pipe_stdin(&blob)
+        .unwrap()
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Total MBO messages: 8"));
+    fs::remove_file(&blob).unwrap();
+
+    // A given format wins over the parquet magic bytes, and fails to decode
+    let parquet = temp_path("parquet-blob");
+    fs::write(&parquet, b"PAR1\x15\x04\x15\x10PAR1").unwrap();
+    rainybook()
+        .args(["process", "--format", "dbn", "--data-path"])
+        .arg(&parquet)
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains("Cannot read"));
+    fs::remove_file(&parquet).unwrap();
+
+    rainybook()
+        .args(["process", "--format", "dbn-bz2", "--data-path"])
+        .arg(fixture())
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("Unknown data format 'dbn-bz2'"));
+}
+
+#[test]
+fn test_process_pipeline() {
+    let summary = |args: &[&str]| {
+        stdout(
+            rainybook()
+                .args(["process", "--depth", "3", "--data-path"])
+                .arg(fixture())
+                .args(args),
+        )
+        .lines()
+        .filter(|line| !line.starts_with("Elapsed:") && !line.starts_with("Processing rate:"))
+        .collect::<Vec<_>>()
+        .join("\n")
+    };
+    let expected = summary(&[]);
+    assert_eq!(summary(&["--pipeline"]), expected);
+    assert_eq!(
+        summary(&[
+            "--pipeline",
+            "--pipeline-batch",
+            "1",
+            "--pipeline-depth",
+            "0"
+        ]),
+        expected
+    );
+
+    rainybook()
+        .args(["process", "--pipeline-batch", "10", "--data-path"])
+        .arg(fixture())
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("--pipeline"));
+
+    // Options that process the messages their own way are refused, not ignored
+    let conflicting: &[&[&str]] = &[
+        &["--print-every", "2"],
+        #[cfg(feature = "polars")]
+        &["-o", "unused.parquet", "--snapshot-every", "2"],
+        #[cfg(feature = "parallel")]
+        &["--threads", "2"],
+    ];
+    for args in conflicting {
+        rainybook()
+            .args(["process", "--pipeline", "--data-path"])
+            .arg(fixture())
+            .args(*args)
+            .assert()
+            .code(2)
+            .stderr(predicate::str::contains("cannot be used with"));
+    }
+}
+
+#[test]
+fn test_bench() {
+    let json = stdout(rainybook().args([
+        "bench",
+        "--synthetic",
+        "2_000",
+        "--iterations",
+        "2",
+        "--json",
+    ]));
+    let results: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(results["messages"], 2000);
+    assert_eq!(results["failed_messages"], 0);
+    assert_eq!(results["iterations"], 2);
+    let processing = &results["processing"];
+    assert!(processing["min"].as_f64().unwrap() <= processing["median"].as_f64().unwrap());
+    assert!(processing["median"].as_f64().unwrap() <= processing["max"].as_f64().unwrap());
+    // Synthetic messages are never decoded
+    assert!(results["with_decoding"].is_null());
+    assert!(results["pipeline_speedup"].is_null());
+    assert!(results["action_counts"]["cancel"].as_u64().unwrap() > 0);
+
+    rainybook()
+        .args(["bench", "--data-path"])
+        .arg(fixture())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("Messages:           8")
+                .and(predicate::str::contains("Processing:         median"))
+                .and(predicate::str::contains("With decoding:      median"))
+                .and(predicate::str::contains("Pipelined:          median"))
+                .and(predicate::str::contains("Pipeline speedup:"))
+                .and(predicate::str::contains("Add:            5 (62.5%)")),
+        );
+
+    rainybook()
+        .arg("bench")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("pass --data-path"));
+}
+
+#[test]
+fn test_convert() {
+    // The summary without the wall time, which differs between runs, at one scale
+    // for all formats
+    let process = |path: &Path| {
+        stdout(
+            rainybook()
+                .args(["process", "--data-path"])
+                .arg(path)
+                .args(SCALED),
+        )
+        .lines()
+        .filter(|line| !line.starts_with("Elapsed:") && !line.starts_with("Processing rate:"))
+        .collect::<Vec<_>>()
+        .join("\n")
+    };
+    let expected = process(&fixture());
+    let mut names = vec!["convert.dbn", "convert.csv", "convert.ndjson"];
+    if cfg!(feature = "polars") {
+        names.push("convert.parquet");
+    }
+    for name in names {
+        let output = temp_path(name);
+        rainybook()
+            .args(["convert", "--data-path"])
+            .arg(fixture())
+            .arg("--output")
+            .arg(&output)
+            .assert()
+            .success();
+        assert_eq!(process(&output), expected, "{name}");
+        fs::remove_file(&output).unwrap();
+    }
+
+    let output = temp_path("convert-filtered.ndjson");
+    rainybook()
+        .args(["convert", "--instrument-id", "8", "--data-path"])
+        .arg(fixture())
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(&output).unwrap().lines().count(), 1);
+    fs::remove_file(&output).unwrap();
+
+    // Remapped ids build the same books; the unknown order 9 is the fifth id seen
+    let output = temp_path("convert-remapped.ndjson");
+    rainybook()
+        .args(["convert", "--remap-ids", "--data-path"])
+        .arg(fixture())
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+    assert_eq!(process(&output), expected);
+    let remapped = fs::read_to_string(&output).unwrap();
+    assert!(
+        remapped
+            .lines()
+            .nth(6)
+            .unwrap()
+            .contains(r#""order_id":5,"#)
+    );
+    fs::remove_file(&output).unwrap();
+
+    rainybook()
+        .args(["convert", "--data-path"])
+        .arg(fixture())
+        .args(["--output", "book.txt"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Cannot tell the output format"));
+}
+
+#[cfg(feature = "databento")]
+#[test]
+fn test_fetch_without_api_key() {
+    rainybook()
+        .env_remove("DATABENTO_API_KEY")
+        .args(["fetch", "--dataset", "GLBX.MDP3", "--symbol", "ESZ4"])
+        .args(["--start", "2024-11-04T13:00:00Z"])
+        .args(["--end", "2024-11-04T13:00:01Z"])
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("DATABENTO_API_KEY"));
+}
@@ -0,0 +1,24 @@
+//! Requests to the live Databento API. Ignored by default, as they need an API key in
+//! `DATABENTO_API_KEY` and are billed to its account; run them with `--ignored`.
+
+use rainybook::{FetchRequest, MboProcessor};
+
+/// One second of E-mini S&P 500 futures order flow.
+fn request() -> FetchRequest {
+    FetchRequest {
+        dataset: "GLBX.MDP3".to_owned(),
+        symbols: vec!["ESZ4".to_owned()],
+        start: 1_730_725_200_000_000_000,
+        end: 1_730_725_201_000_000_000,
+    }
+}
+
+#[test]
+#[ignore = "requires DATABENTO_API_KEY and network access"]
+fn test_fetch_builds_books() {
+    let mut proc = MboProcessor::new();
+    let summary = proc.fetch(&request()).unwrap();
+    assert!(summary.processed > 0);
+    assert!(summary.is_success());
+    assert!(proc.best_bid().is_some());
+}
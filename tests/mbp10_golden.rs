@@ -0,0 +1,144 @@
+//! Golden test: the book rebuilt from MBO must match the MBP-10 records of the same
+//! session, level by level.
+//!
+//! The fixtures are one ESZ4 (instrument 4242) GLBX.MDP3 session in both schemas,
+//! zstd-compressed DBN: a snapshot, about 2000 events of Adds, Cancels, Modifies of
+//! price and size, executions (a Trade, then a Fill and a Cancel or reducing Modify per
+//! resting order), standalone Trades, cancel-and-replace events, and a second snapshot
+//! half way through. The session was synthesized to Databento's conventions and its
+//! MBP-10 records derived from the live orders by a plain aggregation written
+//! independently of this crate, one record per event.
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use dbn::Mbp10Msg;
+use dbn::decode::{DecodeRecord, DynReader, dbn::Decoder};
+use rainybook::{
+    MarketByOrderMessage, MarketByPrice, MboProcessor, OrderLevelSummary, PriceScale, mbo_messages,
+};
+
+/// Levels per side in an MBP-10 record.
+const DEPTH: usize = 10;
+
+/// Mismatching records reported in full when the test fails.
+const REPORTED_MISMATCHES: usize = 3;
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+fn mbo() -> Vec<MarketByOrderMessage> {
+    let decoder = Decoder::new(DynReader::from_file(fixture("esz4.mbo.dbn.zst")).unwrap()).unwrap();
+    mbo_messages(decoder)
+        .collect::<Result<_, _>>()
+        .expect("every MBO record converts")
+}
+
+fn mbp10() -> Vec<Mbp10Msg> {
+    let mut decoder =
+        Decoder::new(DynReader::from_file(fixture("esz4.mbp-10.dbn.zst")).unwrap()).unwrap();
+    let mut records = Vec::new();
+    while let Some(record) = decoder.decode_record::<Mbp10Msg>().unwrap() {
+        records.push(record.clone());
+    }
+    records
+}
+
+/// The levels that differ by rank, then both books as price ladders.
+fn describe_mismatch(
+    record: &Mbp10Msg,
+    expected: &MarketByPrice,
+    rebuilt: &MarketByPrice,
+) -> String {
+    let mut out = format!(
+        "MBP-10 record at ts_event {} (sequence {}):\n",
+        record.hd.ts_event, record.sequence
+    );
+    let sides = [
+        ("bid", expected.top_n_bids(DEPTH), rebuilt.top_n_bids(DEPTH)),
+        ("ask", expected.top_n_asks(DEPTH), rebuilt.top_n_asks(DEPTH)),
+    ];
+    for (side, expected, rebuilt) in sides {
+        for rank in 0..expected.len().max(rebuilt.len()) {
+            let level = |levels: &[OrderLevelSummary]| match levels.get(rank) {
+                Some(level) => format!(
+                    "{} x {} ({} orders)",
+                    level.price, level.total_quantity, level.order_count
+                ),
+                None => "none".to_owned(),
+            };
+            if expected.get(rank) != rebuilt.get(rank) {
+                writeln!(
+                    out,
+                    "  {side} {}: expected {}, rebuilt {}",
+                    rank + 1,
+                    level(&expected),
+                    level(&rebuilt)
+                )
+                .unwrap();
+            }
+        }
+    }
+    write!(
+        out,
+        "expected:\n{}\nrebuilt:\n{}",
+        expected.render(DEPTH, PriceScale::DBN),
+        rebuilt.render(DEPTH, PriceScale::DBN)
+    )
+    .unwrap();
+    out
+}
+
+#[test]
+fn test_rebuilt_book_matches_mbp10() {
+    let messages = mbo();
+    let records = mbp10();
+    assert!(messages.len() > 2_000 && records.len() > 1_000);
+
+    let mut processor = MboProcessor::new();
+    let mut applied = 0;
+    let mut compared = 0;
+    let mut mismatches = Vec::new();
+    for (index, record) in records.iter().enumerate() {
+        // Records sharing a ts_event are compared once, after every MBO record up to it
+        let ts_event = record.hd.ts_event;
+        if records
+            .get(index + 1)
+            .is_some_and(|next| next.hd.ts_event == ts_event)
+        {
+            continue;
+        }
+        while let Some(message) = messages
+            .get(applied)
+            .filter(|message| message.event_time.unix_timestamp_nanos() <= i128::from(ts_event))
+        {
+            processor
+                .process_message(message)
+                .unwrap_or_else(|error| panic!("MBO record {applied}: {error}"));
+            applied += 1;
+        }
+        assert!(
+            processor.is_event_complete(),
+            "ts_event {ts_event} ends mid-event"
+        );
+
+        let expected = MarketByPrice::from_bid_ask_pairs(&record.levels);
+        let rebuilt = MarketByPrice::from_top_n(processor.order_book(), DEPTH);
+        compared += 1;
+        if (&expected.bids, &expected.asks) != (&rebuilt.bids, &rebuilt.asks) {
+            mismatches.push(describe_mismatch(record, &expected, &rebuilt));
+        }
+    }
+
+    assert!(
+        mismatches.is_empty(),
+        "{} of {compared} MBP-10 records differ from the rebuilt book; the first:\n\n{}",
+        mismatches.len(),
+        mismatches[..mismatches.len().min(REPORTED_MISMATCHES)].join("\n\n")
+    );
+    assert_eq!(applied, messages.len(), "MBO records after the last MBP-10");
+    assert_eq!(processor.order_book().anomalies().total(), 0);
+}
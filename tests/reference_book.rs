@@ -0,0 +1,287 @@
+//! Differential test of `MboProcessor` and `OrderBook` against a naive reference book.
+//!
+//! `ReferenceBook` keeps its orders in a `Vec` scanned linearly for every operation and
+//! derives its levels by sorting, so it is too simple to share the optimized book's
+//! bugs. It implements the book's documented semantics, anomalies included: an Add of
+//! a resting id replaces the order, a Cancel of an unknown id is ignored, a Modify of an
+//! unknown id fails, and a Modify to a zero size removes the order, each counted as the
+//! book counts them. Long generated streams, salted with those anomalies, go through
+//! both, and the views are compared every `CHECKPOINT` messages.
+
+use std::cmp::Reverse;
+
+use rainybook::generators::{MessageMix, OrderGenerator};
+use rainybook::{
+    Action, Anomalies, ErrorPolicy, MarketByOrderMessage, MarketByPrice, MboProcessor, Order,
+    OrderBook, OrderLevelSummary, Side, ValidationPolicy,
+};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+const INSTRUMENT_ID: u32 = 1;
+
+/// Messages between comparisons of the two books.
+const CHECKPOINT: usize = 1_000;
+
+/// Generated messages per seed, before anomalies are added.
+const MESSAGES: usize = 100_000;
+
+/// Order ids from here up are never generated, so they are unknown to both books.
+const UNKNOWN_ORDER_ID: u64 = 1 << 40;
+
+/// Levels per side compared through `top_n_bids` and `top_n_asks`.
+const TOP_N: usize = 10;
+
+/// A book that is obviously right rather than fast.
+#[derive(Debug, Default)]
+struct ReferenceBook {
+    /// Resting orders in arrival order.
+    orders: Vec<Order>,
+    anomalies: Anomalies,
+    in_snapshot: bool,
+}
+
+impl ReferenceBook {
+    fn position(&self, order_id: u64) -> Option<usize> {
+        self.orders
+            .iter()
+            .position(|order| order.order_id == order_id)
+    }
+
+    fn add(&mut self, order: Order) {
+        if let Some(index) = self.position(order.order_id) {
+            self.anomalies.duplicate_add += 1;
+            self.orders.remove(index);
+        }
+        self.orders.push(order);
+    }
+
+    fn cancel(&mut self, order_id: u64) {
+        match self.position(order_id) {
+            Some(index) => {
+                self.orders.remove(index);
+            }
+            None => self.anomalies.unknown_cancel += 1,
+        }
+    }
+
+    /// False if the order is not resting.
+    fn modify(&mut self, order: Order) -> bool {
+        let Some(index) = self.position(order.order_id) else {
+            self.anomalies.unknown_modify += 1;
+            return false;
+        };
+        if order.size == 0 {
+            self.orders.remove(index);
+        } else {
+            self.orders[index] = order;
+        }
+        true
+    }
+
+    /// Applies `message` as `MboProcessor` does without validation, returning whether
+    /// it succeeds.
+    fn apply(&mut self, message: &MarketByOrderMessage) -> bool {
+        if message.is_snapshot() && !self.in_snapshot {
+            self.orders.clear();
+        }
+        self.in_snapshot = message.is_snapshot();
+        let order = Order {
+            order_id: message.order_id,
+            side: message.side,
+            price: message.price,
+            size: u64::from(message.size),
+            sequence: message.sequence,
+            owner: None,
+            expires_at: None,
+        };
+        match message.action {
+            // Nothing rests of an Add without size
+            Action::Add if order.size > 0 => self.add(order),
+            Action::Add | Action::Fill | Action::Trade => {}
+            Action::Cancel => self.cancel(order.order_id),
+            Action::Modify => return self.modify(order),
+            Action::Clear => self.orders.clear(),
+        }
+        true
+    }
+
+    /// The levels of `side`, best first.
+    fn levels(&self, side: Side) -> Vec<OrderLevelSummary> {
+        let mut orders: Vec<&Order> = self.orders.iter().filter(|o| o.side == side).collect();
+        match side {
+            Side::Bid => orders.sort_by_key(|order| Reverse(order.price)),
+            Side::Ask => orders.sort_by_key(|order| order.price),
+        }
+        let mut levels: Vec<OrderLevelSummary> = Vec::new();
+        for order in orders {
+            match levels.last_mut() {
+                Some(level) if level.price == order.price => {
+                    level.total_quantity += order.size;
+                    level.order_count += 1;
+                }
+                _ => levels.push(OrderLevelSummary {
+                    price: order.price,
+                    total_quantity: order.size,
+                    order_count: 1,
+                }),
+            }
+        }
+        levels
+    }
+
+    fn best(&self, side: Side) -> Option<(i64, u64)> {
+        let level = self.levels(side).into_iter().next()?;
+        Some((level.price, level.total_quantity))
+    }
+
+    fn total_qty(&self, side: Side) -> u64 {
+        self.orders
+            .iter()
+            .filter(|order| order.side == side)
+            .map(|order| order.size)
+            .sum()
+    }
+}
+
+/// A lifecycle stream with duplicate Adds, Cancels and Modifies of unknown ids, and
+/// Modifies to a zero size mixed in, each after about one message in 100.
+fn salted_messages(seed: u64) -> Vec<MarketByOrderMessage> {
+    let generated = OrderGenerator::default_seeded(seed)
+        .make_lifecycle_messages(MESSAGES, MessageMix::default());
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    // Ids the stream has added, some of them long gone
+    let mut added: Vec<MarketByOrderMessage> = Vec::new();
+    let mut messages = Vec::with_capacity(generated.len() * 105 / 100);
+    for message in generated {
+        messages.push(message);
+        if message.action == Action::Add {
+            added.push(message);
+        }
+        if added.is_empty() || !rng.random_bool(0.04) {
+            continue;
+        }
+        let earlier = added[rng.random_range(0..added.len())];
+        let unknown = UNKNOWN_ORDER_ID + messages.len() as u64;
+        let anomaly = match rng.random_range(0..4) {
+            0 => MarketByOrderMessage {
+                action: Action::Add,
+                price: earlier.price + rng.random_range(-3..=3),
+                size: rng.random_range(1..50),
+                ..earlier
+            },
+            1 => MarketByOrderMessage {
+                action: Action::Cancel,
+                order_id: unknown,
+                ..earlier
+            },
+            2 => MarketByOrderMessage {
+                action: Action::Modify,
+                order_id: unknown,
+                ..earlier
+            },
+            _ => MarketByOrderMessage {
+                action: Action::Modify,
+                size: 0,
+                ..earlier
+            },
+        };
+        messages.push(MarketByOrderMessage {
+            sequence: message.sequence,
+            event_time: message.event_time,
+            recv_time: message.recv_time,
+            ..anomaly
+        });
+    }
+    messages
+}
+
+/// Compares every view of `book` with `reference`, naming `index` on a mismatch.
+fn check(book: &OrderBook, reference: &ReferenceBook, index: usize) {
+    let mbp = MarketByPrice::from(book);
+    assert_eq!(
+        mbp.top_n_bids(usize::MAX),
+        reference.levels(Side::Bid),
+        "bids after message {index}"
+    );
+    assert_eq!(
+        mbp.top_n_asks(usize::MAX),
+        reference.levels(Side::Ask),
+        "asks after message {index}"
+    );
+    assert_eq!(
+        book.best_bid(),
+        reference.best(Side::Bid),
+        "best bid after message {index}"
+    );
+    assert_eq!(
+        book.best_ask(),
+        reference.best(Side::Ask),
+        "best ask after message {index}"
+    );
+    for side in [Side::Bid, Side::Ask] {
+        assert_eq!(
+            book.total_qty(side),
+            reference.total_qty(side),
+            "{side:?} quantity after message {index}"
+        );
+        assert_eq!(
+            book.level_count(side),
+            reference.levels(side).len(),
+            "{side:?} levels after message {index}"
+        );
+        let top: Vec<(i64, u64)> = reference
+            .levels(side)
+            .iter()
+            .take(TOP_N)
+            .map(|level| (level.price, level.total_quantity))
+            .collect();
+        let book_top = match side {
+            Side::Bid => book.top_n_bids(TOP_N),
+            Side::Ask => book.top_n_asks(TOP_N),
+        };
+        assert_eq!(book_top, top, "top {side:?} levels after message {index}");
+    }
+    for order in &reference.orders {
+        let resting = book.get_order(order.order_id);
+        assert_eq!(
+            resting.map(|o| (o.side, o.price, o.size)),
+            Some((order.side, order.price, order.size)),
+            "order {} after message {index}",
+            order.order_id
+        );
+    }
+    assert_eq!(
+        book.anomalies(),
+        reference.anomalies,
+        "anomalies after message {index}"
+    );
+}
+
+#[test]
+fn test_book_matches_reference() {
+    for seed in [1, 2, 3, 4] {
+        let messages = salted_messages(seed);
+        let mut processor = MboProcessor::new()
+            .with_validation_policy(ValidationPolicy::PassThrough)
+            .with_error_policy(ErrorPolicy::Collect);
+        let mut reference = ReferenceBook::default();
+        for (index, message) in messages.iter().enumerate() {
+            let applied = processor.process_message(message).is_ok();
+            assert_eq!(
+                applied,
+                reference.apply(message),
+                "seed {seed}, message {index}: {message:?}"
+            );
+            if (index + 1) % CHECKPOINT == 0 || index + 1 == messages.len() {
+                check(processor.book(INSTRUMENT_ID).unwrap(), &reference, index);
+            }
+        }
+
+        // Every kind of anomaly was exercised
+        let anomalies = reference.anomalies;
+        assert!(anomalies.duplicate_add > 0 && anomalies.unknown_cancel > 0);
+        assert!(anomalies.unknown_modify > 0);
+        assert!(reference.orders.len() > 100, "seed {seed}");
+    }
+}
@@ -0,0 +1,116 @@
+//! Protocol test of the WebSocket book server (`--features server`): an in-process
+//! client connects mid-replay, reads the snapshot and the deltas after it, and must end
+//! with the depth view of the processor's final book, also when it falls so far behind
+//! that the server skips it to a fresh snapshot.
+
+use std::collections::BTreeMap;
+
+use futures_util::StreamExt;
+use rainybook::generators::{MessageMix, OrderGenerator};
+use rainybook::{
+    BookServer, CrossingPolicy, ErrorPolicy, MarketByPrice, MboProcessor, PriceScale,
+    ServerOptions, ValidationPolicy,
+};
+use serde_json::Value;
+use tokio::net::TcpListener;
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message;
+
+const DEPTH: usize = 5;
+
+/// Replays generated messages through a server with `buffer` frames per client, with
+/// a client connected half way, and checks the books it rebuilds against the final
+/// books of the replay. Returns the number of snapshots the client was sent.
+async fn replay_to_client(buffer: usize) -> usize {
+    let server = BookServer::new(ServerOptions::new().with_depth(DEPTH).with_buffer(buffer));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(server.clone().serve(listener));
+
+    let messages =
+        OrderGenerator::default_seeded(5).make_lifecycle_messages(20_000, MessageMix::default());
+    let (first, rest) = messages.split_at(messages.len() / 2);
+    let mut processor = MboProcessor::with_observer(server.feed())
+        .with_crossing_policy(CrossingPolicy::Match)
+        .with_validation_policy(ValidationPolicy::PassThrough)
+        .with_error_policy(ErrorPolicy::Collect);
+    processor.process_messages(first);
+
+    let (mut ws, _) = connect_async(format!("ws://{addr}")).await.unwrap();
+    let rest = rest.to_vec();
+    let expected = tokio::task::spawn_blocking(move || {
+        processor.process_messages(rest);
+        let expected: BTreeMap<u32, Value> = processor
+            .instruments()
+            .into_iter()
+            .filter_map(|id| Some((id, processor.book(id)?)))
+            .map(|(id, book)| {
+                let mbp = MarketByPrice::from_top_n(book, DEPTH);
+                (id, mbp.to_json_depth(DEPTH, PriceScale::RAW))
+            })
+            .collect();
+        // Dropping the feed ends the stream
+        drop(processor);
+        expected
+    });
+
+    let mut books: BTreeMap<u32, MarketByPrice> = BTreeMap::new();
+    let mut kinds = Vec::new();
+    while let Some(message) = ws.next().await {
+        let Message::Text(text) = message.unwrap() else {
+            continue;
+        };
+        let frame: Value = serde_json::from_str(&text).unwrap();
+        let kind = frame["type"].as_str().unwrap().to_owned();
+        if kind == "end" {
+            kinds.push(kind);
+            break;
+        }
+        let id = frame["instrument_id"].as_u64().unwrap() as u32;
+        assert!(frame["ts_event"].is_i64() && frame["sequence"].is_u64());
+        let levels = MarketByPrice::from_json_depth(&frame, PriceScale::RAW).unwrap();
+        match kind.as_str() {
+            "snapshot" => {
+                books.insert(id, levels);
+            }
+            "delta" => {
+                let book = books
+                    .get_mut(&id)
+                    .expect("delta before the book's snapshot");
+                for (side, changes) in
+                    [(&mut book.bids, levels.bids), (&mut book.asks, levels.asks)]
+                {
+                    for (price, level) in changes {
+                        match level.total_quantity {
+                            0 => side.remove(&price),
+                            _ => side.insert(price, level),
+                        };
+                    }
+                }
+            }
+            kind => panic!("unexpected frame type {kind}"),
+        }
+        kinds.push(kind);
+    }
+
+    assert_eq!(kinds.first().map(String::as_str), Some("snapshot"));
+    assert_eq!(kinds.last().map(String::as_str), Some("end"));
+    let expected = expected.await.unwrap();
+    let rebuilt: BTreeMap<u32, Value> = books
+        .iter()
+        .map(|(&id, book)| (id, book.to_json_depth(DEPTH, PriceScale::RAW)))
+        .collect();
+    assert_eq!(rebuilt, expected);
+    kinds.iter().filter(|kind| *kind == "snapshot").count()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_snapshot_then_deltas_rebuild_book() {
+    assert_eq!(replay_to_client(1 << 16).await, 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_lagging_client_resumes_from_fresh_snapshot() {
+    // The replay outpaces the client by far, so it cannot keep a one-frame buffer
+    assert!(replay_to_client(1).await > 1);
+}